@@ -48,12 +48,27 @@ mod danger {
 }
 
 fn format_node(node: hid_io_core::common_capnp::destination::Reader<'_>) -> String {
-    format!(
-        "{}: {} ({})",
+    let mut line = format!(
+        "{}: {} ({}) [{}]",
         node.get_type().unwrap(),
         node.get_name().unwrap_or(""),
         node.get_serial().unwrap_or(""),
-    )
+        node.get_seat().unwrap_or(""),
+    );
+    // Tells identical devices apart
+    if let Ok(info) = node.get_device_info() {
+        if node.has_device_info() {
+            line.push_str(&format!(
+                " {:04x}:{:04x} rev:{:04x} interface:{} port:{}",
+                info.get_vendor_id(),
+                info.get_product_id(),
+                info.get_release_number(),
+                info.get_interface_number(),
+                info.get_port_path().unwrap_or(""),
+            ));
+        }
+    }
+    line
 }
 
 #[tokio::main]
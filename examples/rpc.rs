@@ -213,6 +213,7 @@ async fn try_main() -> Result<(), ::capnp::Error> {
                         .filter(|n| {
                             n.get_type().unwrap() == NodeType::UsbKeyboard
                                 || n.get_type().unwrap() == NodeType::BleKeyboard
+                                || n.get_type().unwrap() == NodeType::BtKeyboard
                         })
                         .collect();
 
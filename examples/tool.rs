@@ -130,6 +130,10 @@ async fn try_main() -> Result<(), ::capnp::Error> {
         )
         .subcommand(SubCommand::with_name("flash").about("Attempt to enable flash mode on device"))
         .subcommand(SubCommand::with_name("sleep").about("Attempt to enable sleep mode on device"))
+        .subcommand(
+            SubCommand::with_name("diagnostics")
+                .about("Write the daemon diagnostics report (for bug reports)"),
+        )
         .get_matches();
 
     let addr = LISTEN_ADDR
@@ -235,6 +239,7 @@ async fn try_main() -> Result<(), ::capnp::Error> {
                 .filter(|n| {
                     n.get_type().unwrap() == NodeType::UsbKeyboard
                         || n.get_type().unwrap() == NodeType::BleKeyboard
+                        || n.get_type().unwrap() == NodeType::BtKeyboard
                 })
                 .collect();
             println!(" * <uid> - <NodeType>: [<VID>:<PID>-<Usage Page>:<Usage>] [<Vendor>] <Name> (<Serial>)");
@@ -244,6 +249,40 @@ async fn try_main() -> Result<(), ::capnp::Error> {
             return Ok(());
         }
 
+        // Diagnostics report (daemon command, no device needed)
+        if matches.subcommand_matches("diagnostics").is_some() {
+            let daemon = nodes
+                .iter()
+                .find(|n| n.get_type().unwrap() == NodeType::HidioDaemon)
+                .and_then(|n| match n.get_node().which() {
+                    Ok(hid_io_core::common_capnp::destination::node::Which::Daemon(node)) => {
+                        node.ok()
+                    }
+                    _ => None,
+                });
+            let daemon = match daemon {
+                Some(daemon) => daemon,
+                None => {
+                    eprintln!("Could not find daemon node");
+                    std::process::exit(1);
+                }
+            };
+
+            let request = daemon.diagnostics_request();
+            match request.send().promise.await {
+                Ok(response) => {
+                    let response = response.get()?;
+                    println!("{}", response.get_report()?);
+                    println!("Report written to {}", response.get_path()?);
+                }
+                Err(e) => {
+                    eprintln!("Diagnostics request failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            return Ok(());
+        }
+
         // Serial is used to specify the device (if necessary)
         let mut serial = "".to_string();
 
@@ -283,6 +322,7 @@ async fn try_main() -> Result<(), ::capnp::Error> {
                         .filter(|n| {
                             n.get_type().unwrap() == NodeType::UsbKeyboard
                                 || n.get_type().unwrap() == NodeType::BleKeyboard
+                                || n.get_type().unwrap() == NodeType::BtKeyboard
                         })
                         .collect();
 
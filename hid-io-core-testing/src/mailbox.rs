@@ -0,0 +1,197 @@
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * This file is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This file is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this file.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// ----- Crates -----
+
+use hid_io_core::api::{common_capnp, Endpoint};
+use hid_io_core::mailbox::{Address, Mailbox, Message};
+use hid_io_protocol::{HidIoCommandId, HidIoPacketType};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+// ----- Consts -----
+
+/// Ack timeout used by the mock mailbox
+/// Much shorter than the daemon default so failing tests finish quickly
+pub const MOCK_ACK_TIMEOUT_MS: u64 = 200;
+
+/// Default time to wait for an expected message
+pub const MOCK_EXPECT_TIMEOUT_MS: u64 = 1000;
+
+// ----- Structs -----
+
+/// Deterministic Mailbox
+///
+/// Wraps a regular hid-io-core Mailbox, keeping a receiver subscribed from creation so that
+/// every message broadcast during a test can be inspected (and sends never fail due to a lack
+/// of receivers).
+/// uids are handed out sequentially starting from last_uid + 1.
+pub struct MockMailbox {
+    mailbox: Mailbox,
+    receiver: broadcast::Receiver<Message>,
+    responders: Vec<(Address, HidIoCommandId, Vec<u8>, bool)>,
+}
+
+impl MockMailbox {
+    pub fn new() -> MockMailbox {
+        MockMailbox::with_last_uid(0)
+    }
+
+    /// Start uid assignment after the given uid
+    /// Useful to make parallel tests easier to tell apart in the logs
+    pub fn with_last_uid(last_uid: u64) -> MockMailbox {
+        let mailbox = Mailbox::default();
+        *mailbox.last_uid.write().unwrap() = last_uid;
+        *mailbox.ack_timeout.write().unwrap() = Duration::from_millis(MOCK_ACK_TIMEOUT_MS);
+        let receiver = mailbox.sender.subscribe();
+        MockMailbox {
+            mailbox,
+            receiver,
+            responders: vec![],
+        }
+    }
+
+    /// Mailbox handle to give to the code under test
+    pub fn mailbox(&self) -> Mailbox {
+        self.mailbox.clone()
+    }
+
+    /// Assign a uid, panics if the key+path has already been registered
+    pub fn assign_uid(&mut self, key: String, path: String) -> u64 {
+        self.mailbox.assign_uid(key, path).unwrap()
+    }
+
+    /// Register a new endpoint of the given type and return its uid
+    pub fn register_endpoint(&mut self, type_: common_capnp::NodeType, name: &str) -> u64 {
+        let uid = self.assign_uid(format!("mock:{}", name), "".to_string());
+        let mut endpoint = Endpoint::new(type_, uid);
+        endpoint.set_hidio_params(name.to_string(), format!("mock-{}", uid));
+        self.mailbox.register_node(endpoint);
+        uid
+    }
+
+    /// List of currently registered uids
+    pub fn node_uids(&self) -> Vec<u64> {
+        self.mailbox
+            .nodes
+            .read()
+            .unwrap()
+            .iter()
+            .map(|node| node.uid)
+            .collect()
+    }
+
+    /// Automatically reply to Data packets sent to dst with the given id
+    /// Replies are sent from dst to Address::All (which is how devices respond).
+    /// Responders are only serviced while calling try_recv(), drain() or expect().
+    pub fn respond(&mut self, dst: Address, id: HidIoCommandId, data: Vec<u8>, ack: bool) {
+        self.responders.push((dst, id, data, ack));
+    }
+
+    /// Non-blocking receive of the next broadcast message
+    pub fn try_recv(&mut self) -> Option<Message> {
+        loop {
+            match self.receiver.try_recv() {
+                Ok(msg) => {
+                    self.service_responders(&msg);
+                    return Some(msg);
+                }
+                Err(broadcast::error::TryRecvError::Lagged(skipped)) => {
+                    warn!("MockMailbox lagged by {} messages", skipped);
+                }
+                Err(_) => {
+                    return None;
+                }
+            }
+        }
+    }
+
+    /// Collect all messages that have been broadcast so far
+    pub fn drain(&mut self) -> Vec<Message> {
+        let mut msgs = vec![];
+        while let Some(msg) = self.try_recv() {
+            msgs.push(msg);
+        }
+        msgs
+    }
+
+    /// Wait for a message matching the filter, discarding any others
+    /// Returns None on timeout.
+    pub fn recv_filtered<F>(&mut self, timeout: Duration, filter: F) -> Option<Message>
+    where
+        F: Fn(&Message) -> bool,
+    {
+        let start_time = Instant::now();
+        loop {
+            match self.try_recv() {
+                Some(msg) => {
+                    if filter(&msg) {
+                        return Some(msg);
+                    }
+                }
+                None => {
+                    if start_time.elapsed() >= timeout {
+                        return None;
+                    }
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+            }
+        }
+    }
+
+    /// Wait for a message matching the filter, panics on timeout
+    pub fn expect<F>(&mut self, filter: F) -> Message
+    where
+        F: Fn(&Message) -> bool,
+    {
+        match self.recv_filtered(Duration::from_millis(MOCK_EXPECT_TIMEOUT_MS), filter) {
+            Some(msg) => msg,
+            None => panic!(
+                "Timeout ({}ms) waiting for expected mailbox message",
+                MOCK_EXPECT_TIMEOUT_MS
+            ),
+        }
+    }
+
+    /// Panics if any further messages are pending
+    pub fn assert_empty(&mut self) {
+        let msgs = self.drain();
+        assert!(msgs.is_empty(), "Unexpected mailbox messages: {:?}", msgs);
+    }
+
+    fn service_responders(&self, msg: &Message) {
+        if msg.data.ptype != HidIoPacketType::Data {
+            return;
+        }
+        for (dst, id, data, ack) in &self.responders {
+            if msg.dst == *dst && msg.data.id == *id {
+                // send_ack/send_nak swap src and dst, so the reply is from dst to Address::All
+                let reply = Message::new(Address::All, *dst, msg.data.clone());
+                if *ack {
+                    reply.send_ack(self.mailbox.sender.clone(), data.clone());
+                } else {
+                    reply.send_nak(self.mailbox.sender.clone(), data.clone());
+                }
+            }
+        }
+    }
+}
+
+impl Default for MockMailbox {
+    fn default() -> Self {
+        MockMailbox::new()
+    }
+}
@@ -0,0 +1,85 @@
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * This file is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This file is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this file.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// ----- Crates -----
+
+use hid_io_core::mailbox::HidIoPacketBuffer;
+use hid_io_protocol::{HidIoCommandId, HidIoPacketType, HidIoParseError};
+
+// ----- Functions -----
+
+/// Build a complete packet buffer
+pub fn build(ptype: HidIoPacketType, id: HidIoCommandId, data: &[u8]) -> HidIoPacketBuffer {
+    HidIoPacketBuffer {
+        ptype,
+        id,
+        unknown_id: None,
+        max_len: 64, // Default
+        data: heapless::Vec::from_slice(data).unwrap(),
+        done: true,
+    }
+}
+
+/// Serialize a packet buffer into max_len sized chunks
+/// Chunks match what HidIoEndpoint would write to the device
+pub fn serialize_chunks(mut packet: HidIoPacketBuffer, max_len: u32) -> Vec<Vec<u8>> {
+    packet.max_len = max_len;
+    let mut buf: Vec<u8> = Vec::new();
+    buf.resize_with(packet.serialized_len() as usize, Default::default);
+    let buf = packet.serialize_buffer(&mut buf).unwrap().to_vec();
+    buf.chunks(max_len as usize).map(|c| c.to_vec()).collect()
+}
+
+/// Decode a list of chunks into a list of packet buffers
+/// A trailing incomplete buffer is returned as well (done will be false).
+pub fn decode_chunks(chunks: &[Vec<u8>]) -> Result<Vec<HidIoPacketBuffer>, HidIoParseError> {
+    let mut packets = vec![];
+    let mut buffer = HidIoPacketBuffer::new();
+    for chunk in chunks {
+        if chunk.is_empty() {
+            continue;
+        }
+        buffer.max_len = chunk.len() as u32;
+        buffer.decode_packet(chunk)?;
+        if buffer.done {
+            packets.push(buffer);
+            buffer = HidIoPacketBuffer::new();
+        }
+    }
+    if !buffer.data.is_empty() {
+        packets.push(buffer);
+    }
+    Ok(packets)
+}
+
+/// Assert the contents of a packet buffer
+/// max_len is ignored as it depends on the transport.
+pub fn assert_packet(
+    packet: &HidIoPacketBuffer,
+    ptype: HidIoPacketType,
+    id: HidIoCommandId,
+    data: &[u8],
+) {
+    assert!(packet.done, "Packet incomplete => {:?}", packet);
+    assert_eq!(packet.ptype, ptype, "Packet type mismatch => {:?}", packet);
+    assert_eq!(packet.id, id, "Packet id mismatch => {:?}", packet);
+    assert_eq!(
+        &packet.data[..],
+        data,
+        "Packet payload mismatch => {:?}",
+        packet
+    );
+}
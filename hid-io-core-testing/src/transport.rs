@@ -0,0 +1,181 @@
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * This file is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This file is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this file.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// ----- Crates -----
+
+use crate::packet;
+use hid_io_core::device::HidIoTransport;
+use hid_io_core::mailbox::HidIoPacketBuffer;
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+
+// ----- Types -----
+
+type Responder = Box<dyn FnMut(&HidIoPacketBuffer) -> Option<HidIoPacketBuffer> + Send>;
+
+// ----- Structs -----
+
+#[derive(Default)]
+struct ScriptState {
+    reads: VecDeque<Vec<u8>>,
+    writes: Vec<Vec<u8>>,
+    partial: Option<HidIoPacketBuffer>,
+    responder: Option<Responder>,
+    disconnected: bool,
+}
+
+/// Scripted HidIo transport
+///
+/// Stands in for a real device (e.g. hidapi) behind a HidIoEndpoint.
+/// Each read() returns the next queued chunk (or 0 bytes if nothing is queued, which is how
+/// hidapi reports a read timeout). Every write() is recorded.
+/// The matching ScriptedHandle is used by the test to queue reads and inspect writes.
+pub struct ScriptedTransport {
+    state: Arc<Mutex<ScriptState>>,
+}
+
+/// Test-side handle to a ScriptedTransport
+#[derive(Clone)]
+pub struct ScriptedHandle {
+    state: Arc<Mutex<ScriptState>>,
+}
+
+impl ScriptedTransport {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new() -> (ScriptedTransport, ScriptedHandle) {
+        let state = Arc::new(Mutex::new(ScriptState::default()));
+        (
+            ScriptedTransport {
+                state: state.clone(),
+            },
+            ScriptedHandle { state },
+        )
+    }
+}
+
+impl HidIoTransport for ScriptedTransport {}
+
+impl Read for ScriptedTransport {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        match state.reads.pop_front() {
+            Some(chunk) => {
+                let len = chunk.len().min(buf.len());
+                buf[..len].copy_from_slice(&chunk[..len]);
+                Ok(len)
+            }
+            None => {
+                if state.disconnected {
+                    Err(std::io::Error::new(
+                        std::io::ErrorKind::BrokenPipe,
+                        "Scripted device disconnected",
+                    ))
+                } else {
+                    Ok(0)
+                }
+            }
+        }
+    }
+}
+
+impl Write for ScriptedTransport {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        if state.disconnected {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                "Scripted device disconnected",
+            ));
+        }
+        state.writes.push(buf.to_vec());
+
+        // Reassemble packets for the responder
+        if state.responder.is_some() {
+            let mut partial = state.partial.take().unwrap_or_else(HidIoPacketBuffer::new);
+            if let Err(e) = partial.decode_packet(buf) {
+                warn!("ScriptedTransport could not decode write: {:?}", e);
+                return Ok(buf.len());
+            }
+            if partial.done {
+                let reply = (state.responder.as_mut().unwrap())(&partial);
+                if let Some(reply) = reply {
+                    for chunk in packet::serialize_chunks(reply, partial.max_len) {
+                        state.reads.push_back(chunk);
+                    }
+                }
+            } else {
+                state.partial = Some(partial);
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl ScriptedHandle {
+    /// Queue a raw chunk to be read by the endpoint
+    pub fn push_chunk(&self, chunk: Vec<u8>) {
+        self.state.lock().unwrap().reads.push_back(chunk);
+    }
+
+    /// Queue a packet to be read by the endpoint, split into max_len chunks
+    pub fn push_packet(&self, packet: HidIoPacketBuffer, max_len: u32) {
+        let mut state = self.state.lock().unwrap();
+        for chunk in packet::serialize_chunks(packet, max_len) {
+            state.reads.push_back(chunk);
+        }
+    }
+
+    /// Number of queued chunks that have not been read yet
+    pub fn pending_reads(&self) -> usize {
+        self.state.lock().unwrap().reads.len()
+    }
+
+    /// Raw chunks written by the endpoint (in order)
+    pub fn written_chunks(&self) -> Vec<Vec<u8>> {
+        self.state.lock().unwrap().writes.clone()
+    }
+
+    /// Packets written by the endpoint (in order)
+    /// Panics if the written data cannot be decoded.
+    pub fn written_packets(&self) -> Vec<HidIoPacketBuffer> {
+        packet::decode_chunks(&self.written_chunks()).unwrap()
+    }
+
+    /// Forget about previously written chunks
+    pub fn clear_written(&self) {
+        self.state.lock().unwrap().writes.clear();
+    }
+
+    /// Call the responder for every complete packet written to the transport
+    /// Any returned packet is queued to be read back by the endpoint.
+    pub fn respond_with<F>(&self, responder: F)
+    where
+        F: FnMut(&HidIoPacketBuffer) -> Option<HidIoPacketBuffer> + Send + 'static,
+    {
+        self.state.lock().unwrap().responder = Some(Box::new(responder));
+    }
+
+    /// Simulate a device disconnection
+    /// Queued reads are still returned, afterwards reads and writes will fail.
+    pub fn disconnect(&self) {
+        self.state.lock().unwrap().disconnected = true;
+    }
+}
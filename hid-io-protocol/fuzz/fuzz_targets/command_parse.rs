@@ -0,0 +1,63 @@
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in
+ * all copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+ * THE SOFTWARE.
+ */
+
+#![no_main]
+
+// ----- Crates -----
+
+use heapless::consts::{U500, U64};
+use hid_io_protocol::commands::*;
+use hid_io_protocol::HidIoPacketBuffer;
+use libfuzzer_sys::fuzz_target;
+
+// ----- Structs -----
+
+/// Accepts every id, responses are serialized then dropped
+struct CommandInterface {}
+
+impl Commands<U500, U64> for CommandInterface {
+    fn tx_packetbuffer_send(
+        &mut self,
+        buf: &mut HidIoPacketBuffer<U500>,
+    ) -> Result<(), CommandError> {
+        let mut chunk = [0u8; 64];
+        let mut offset = Some(0);
+        while let Some(pos) = offset {
+            let (_len, next) = match buf.serialize_chunk(pos, &mut chunk) {
+                Ok(result) => result,
+                Err(err) => {
+                    return Err(CommandError::SerializationFailed(err));
+                }
+            };
+            offset = next;
+        }
+        Ok(())
+    }
+}
+
+// ----- Fuzz Targets -----
+
+/// Parses an arbitrary command payload
+/// Malformed payloads must be rejected with an error, never a panic.
+fuzz_target!(|buffer: HidIoPacketBuffer<U500>| {
+    let mut intf = CommandInterface {};
+    let _ = intf.rx_message_handling(buffer);
+});
@@ -0,0 +1,45 @@
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in
+ * all copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+ * THE SOFTWARE.
+ */
+
+#![no_main]
+
+// ----- Crates -----
+
+use heapless::consts::U500;
+use hid_io_protocol::{HidIoPacket, HidIoPacketBuffer, PacketOptions};
+use libfuzzer_sys::fuzz_target;
+
+// ----- Fuzz Targets -----
+
+/// Decodes a single arbitrary chunk
+/// Must never panic, decoded packets must fit in the chunk.
+fuzz_target!(|input: (PacketOptions, &[u8])| {
+    let (options, data) = input;
+
+    if let Ok(packet) = HidIoPacket::decode(data) {
+        assert!(packet.len as usize <= data.len());
+    }
+
+    let mut buffer = HidIoPacketBuffer::<U500>::new();
+    if let Ok(len) = buffer.decode_packet_opts(data, options) {
+        assert!(len as usize <= data.len());
+    }
+});
@@ -0,0 +1,83 @@
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in
+ * all copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+ * THE SOFTWARE.
+ */
+
+#![no_main]
+
+// ----- Crates -----
+
+use heapless::consts::U500;
+use hid_io_protocol::{HidIoPacketBuffer, HidIoPacketType, HidIoParseError, PacketOptions};
+use libfuzzer_sys::fuzz_target;
+
+// ----- Fuzz Targets -----
+
+/// Serializes an arbitrary buffer, drops the packets selected by the mask, then reassembles
+/// Without dropped packets the buffer must be identical. With sequencing, a finished buffer
+/// must be identical regardless of which packets went missing.
+fuzz_target!(|input: (HidIoPacketBuffer<U500>, PacketOptions, u64)| {
+    let (buffer, options, drop_mask) = input;
+
+    // Continued and Sync packets can't start a buffer
+    match buffer.ptype {
+        HidIoPacketType::Data
+        | HidIoPacketType::Ack
+        | HidIoPacketType::Nak
+        | HidIoPacketType::NaData => {}
+        _ => {
+            return;
+        }
+    }
+
+    // Serialize
+    let mut packets = vec![];
+    let mut chunk = vec![0; buffer.max_len as usize];
+    let mut offset = Some(0);
+    let mut index = 0;
+    while let Some(pos) = offset {
+        let (len, next) = buffer
+            .serialize_chunk_opts(pos, &mut chunk, options)
+            .unwrap();
+        if drop_mask & (1 << (index % 64)) == 0 {
+            packets.push(chunk[..len].to_vec());
+        }
+        index += 1;
+        offset = next;
+    }
+
+    // Reassemble
+    let mut deserialized = HidIoPacketBuffer::<U500>::new();
+    for packet in packets.iter() {
+        let mut result = deserialized.decode_packet_opts(packet, options);
+        if let Err(HidIoParseError::IncompleteBuffer { .. })
+        | Err(HidIoParseError::SequenceGap { .. }) = result
+        {
+            // Packet was not used, the buffer has been cleared
+            assert!(deserialized.data.is_empty());
+            result = deserialized.decode_packet_opts(packet, options);
+        }
+        assert_eq!(result.unwrap() as usize, packet.len());
+    }
+    deserialized.max_len = buffer.max_len;
+
+    if drop_mask == 0 || (options.sequence && deserialized.done) {
+        assert_eq!(buffer, deserialized);
+    }
+});
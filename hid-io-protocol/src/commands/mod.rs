@@ -76,14 +76,27 @@ pub mod h0000 {
     use heapless::{ArrayLength, Vec};
 
     #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
     pub struct Cmd {}
 
     #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    #[cfg_attr(feature = "serde-support", serde(bound = ""))]
     pub struct Ack<ID: ArrayLength<HidIoCommandId>> {
         pub ids: Vec<HidIoCommandId, ID>,
     }
 
     #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
     pub struct Nak {}
 }
 
@@ -94,6 +107,10 @@ pub mod h0001 {
 
     #[repr(u8)]
     #[derive(PartialEq, Clone, Copy, Debug, TryFromPrimitive)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
     pub enum Property {
         Unknown = 0x00,
         MajorVersion = 0x01,
@@ -113,6 +130,10 @@ pub mod h0001 {
 
     #[repr(u8)]
     #[derive(PartialEq, Clone, Copy, Debug, TryFromPrimitive)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
     pub enum OsType {
         Unknown = 0x00,
         Windows = 0x01,
@@ -124,11 +145,20 @@ pub mod h0001 {
     }
 
     #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
     pub struct Cmd {
         pub property: Property,
     }
 
     #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    #[cfg_attr(feature = "serde-support", serde(bound = ""))]
     pub struct Ack<S: ArrayLength<u8>> {
         pub property: Property,
 
@@ -144,6 +174,10 @@ pub mod h0001 {
     }
 
     #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
     pub struct Nak {
         pub property: Property,
     }
@@ -154,28 +188,128 @@ pub mod h0002 {
     use heapless::{ArrayLength, Vec};
 
     #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    #[cfg_attr(feature = "serde-support", serde(bound = ""))]
     pub struct Cmd<D: ArrayLength<u8>> {
         pub data: Vec<u8, D>,
     }
 
     #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    #[cfg_attr(feature = "serde-support", serde(bound = ""))]
     pub struct Ack<D: ArrayLength<u8>> {
         pub data: Vec<u8, D>,
     }
 
     #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
     pub struct Nak {}
 }
 
 /// Reset HID-IO
 pub mod h0003 {
     #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
     pub struct Cmd {}
 
     #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
     pub struct Ack {}
 
     #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Nak {}
+}
+
+/// Capabilities
+pub mod h0004 {
+    /// HID-IO protocol version implemented by this crate
+    pub const VERSION: u16 = 1;
+
+    /// 32-bit ids are supported
+    pub const CAP_WIDE_ID: u32 = 0x01;
+    /// Per-packet CRC16 is supported
+    pub const CAP_CRC16: u32 = 0x02;
+    /// Compressed payloads are supported
+    pub const CAP_COMPRESSION: u32 = 0x04;
+    /// Encrypted payloads (paired key, see h0005) are supported
+    pub const CAP_ENCRYPTION: u32 = 0x08;
+    /// Continued packets carry their payload offset (see PacketOptions::sequence)
+    pub const CAP_SEQUENCE: u32 = 0x10;
+
+    #[derive(Clone, Debug, PartialEq)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Cmd {
+        pub version: u16,
+        pub capabilities: u32,
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Ack {
+        pub version: u16,
+        pub capabilities: u32,
+    }
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Nak {}
+}
+
+/// Pair
+pub mod h0005 {
+    /// Pre-shared key length
+    pub const KEY_LEN: usize = 32;
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Cmd {
+        /// Key used to encrypt payloads on untrusted transports
+        pub key: [u8; KEY_LEN],
+    }
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Ack {}
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
     pub struct Nak {}
 }
 
@@ -186,6 +320,10 @@ pub mod h0010 {
 
     #[repr(u8)]
     #[derive(PartialEq, Clone, Copy, Debug, TryFromPrimitive)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
     pub enum Command {
         ListFields = 0x00,
         GetFieldName = 0x01,
@@ -194,6 +332,10 @@ pub mod h0010 {
     }
 
     #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
     pub struct Cmd {
         pub command: Command,
 
@@ -203,6 +345,11 @@ pub mod h0010 {
     }
 
     #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    #[cfg_attr(feature = "serde-support", serde(bound = ""))]
     pub struct Ack<S: ArrayLength<u8>> {
         pub command: Command,
 
@@ -218,6 +365,10 @@ pub mod h0010 {
     }
 
     #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
     pub struct Nak {
         pub command: Command,
 
@@ -273,20 +424,36 @@ pub mod h0016 {
 
     #[repr(u8)]
     #[derive(PartialEq, Clone, Copy, Debug, TryFromPrimitive)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
     pub enum Error {
         NotSupported = 0x00,
         Disabled = 0x01,
     }
 
     #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
     pub struct Cmd {}
 
     #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
     pub struct Ack {
         pub scancode: u16,
     }
 
     #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
     pub struct Nak {
         pub error: Error,
     }
@@ -297,14 +464,27 @@ pub mod h0017 {
     use heapless::{ArrayLength, String};
 
     #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    #[cfg_attr(feature = "serde-support", serde(bound = ""))]
     pub struct Cmd<S: ArrayLength<u8>> {
         pub string: String<S>,
     }
 
     #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
     pub struct Ack {}
 
     #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
     pub struct Nak {}
 }
 
@@ -313,14 +493,27 @@ pub mod h0018 {
     use heapless::{ArrayLength, String};
 
     #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    #[cfg_attr(feature = "serde-support", serde(bound = ""))]
     pub struct Cmd<S: ArrayLength<u8>> {
         pub symbols: String<S>,
     }
 
     #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
     pub struct Ack {}
 
     #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
     pub struct Nak {}
 }
 
@@ -338,6 +531,10 @@ pub mod h001a {
 
     #[repr(u8)]
     #[derive(PartialEq, Clone, Copy, Debug, TryFromPrimitive)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
     pub enum Error {
         NotSupported = 0x00,
         Disabled = 0x01,
@@ -345,12 +542,279 @@ pub mod h001a {
     }
 
     #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
     pub struct Cmd {}
 
     #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Ack {}
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Nak {
+        pub error: Error,
+    }
+}
+
+/// Settings Read
+pub mod h001b {
+    use heapless::{ArrayLength, Vec};
+    use num_enum::TryFromPrimitive;
+
+    #[repr(u8)]
+    #[derive(PartialEq, Clone, Copy, Debug, TryFromPrimitive)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub enum Error {
+        NotSupported = 0x00,
+        InvalidRange = 0x01,
+        ReadFailure = 0x02,
+    }
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Cmd {
+        pub offset: u32,
+        pub len: u16,
+    }
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    #[cfg_attr(feature = "serde-support", serde(bound = ""))]
+    pub struct Ack<D: ArrayLength<u8>> {
+        pub data: Vec<u8, D>,
+    }
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Nak {
+        pub error: Error,
+    }
+}
+
+/// Settings Write
+pub mod h001c {
+    use heapless::{ArrayLength, Vec};
+    use num_enum::TryFromPrimitive;
+
+    #[repr(u8)]
+    #[derive(PartialEq, Clone, Copy, Debug, TryFromPrimitive)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub enum Error {
+        NotSupported = 0x00,
+        InvalidRange = 0x01,
+        Locked = 0x02,
+        WriteFailure = 0x03,
+    }
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    #[cfg_attr(feature = "serde-support", serde(bound = ""))]
+    pub struct Cmd<D: ArrayLength<u8>> {
+        pub offset: u32,
+        pub data: Vec<u8, D>,
+    }
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Ack {}
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Nak {
+        pub error: Error,
+    }
+}
+
+/// KLL Layout Read
+pub mod h001d {
+    use heapless::{ArrayLength, Vec};
+    use num_enum::TryFromPrimitive;
+
+    #[repr(u8)]
+    #[derive(PartialEq, Clone, Copy, Debug, TryFromPrimitive)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub enum Error {
+        NotSupported = 0x00,
+        InvalidOffset = 0x01,
+    }
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Cmd {
+        pub offset: u32,
+        pub len: u16,
+    }
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    #[cfg_attr(feature = "serde-support", serde(bound = ""))]
+    pub struct Ack<D: ArrayLength<u8>> {
+        /// Total size of the active layout
+        pub size: u32,
+        pub data: Vec<u8, D>,
+    }
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Nak {
+        pub error: Error,
+    }
+}
+
+/// KLL Layout Write
+pub mod h001e {
+    use heapless::{ArrayLength, Vec};
+    use num_enum::TryFromPrimitive;
+
+    #[repr(u8)]
+    #[derive(PartialEq, Clone, Copy, Debug, TryFromPrimitive)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub enum Error {
+        NotStarted = 0x00,
+        InvalidOffset = 0x01,
+        CrcMismatch = 0x02,
+        StorageFailure = 0x03,
+    }
+
+    /// crc is a CRC-16/CCITT-FALSE of data, see hid_io_protocol::crc16
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    #[cfg_attr(feature = "serde-support", serde(bound = ""))]
+    pub struct Cmd<D: ArrayLength<u8>> {
+        pub offset: u32,
+        pub crc: u16,
+        pub data: Vec<u8, D>,
+    }
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Ack {}
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Nak {
+        pub error: Error,
+    }
+}
+
+/// KLL Layout Control
+pub mod h001f {
+    use num_enum::TryFromPrimitive;
+
+    #[repr(u8)]
+    #[derive(PartialEq, Clone, Copy, Debug, TryFromPrimitive)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub enum Action {
+        /// Start an upload, arg is the total layout size
+        Begin = 0x00,
+        /// Validate and activate the uploaded layout, arg is the CRC-16 of the whole layout
+        Apply = 0x01,
+        /// Keep the applied layout and discard the previous one
+        Confirm = 0x02,
+        /// Restore the layout that was active before Apply
+        Rollback = 0x03,
+        /// Discard an upload that has not been applied
+        Abort = 0x04,
+    }
+
+    #[repr(u8)]
+    #[derive(PartialEq, Clone, Copy, Debug, TryFromPrimitive)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub enum Error {
+        NotSupported = 0x00,
+        NotStarted = 0x01,
+        TooLarge = 0x02,
+        Incomplete = 0x03,
+        InvalidLayout = 0x04,
+        NoPrevious = 0x05,
+        StorageFailure = 0x06,
+    }
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Cmd {
+        pub action: Action,
+        pub arg: u32,
+    }
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
     pub struct Ack {}
 
     #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
     pub struct Nak {
         pub error: Error,
     }
@@ -404,589 +868,2927 @@ pub mod h0025 {
     pub struct Nak {}
 }
 
-/// Open URL
-/// TODO
-pub mod h0030 {
-    pub struct Cmd {}
-    pub struct Ack {}
-    pub struct Nak {}
-}
-
-/// Terminal Command
-pub mod h0031 {
+/// Display Text
+pub mod h0029 {
     use heapless::{ArrayLength, String};
+    use num_enum::TryFromPrimitive;
+
+    #[repr(u8)]
+    #[derive(PartialEq, Clone, Copy, Debug, TryFromPrimitive)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub enum Error {
+        NotSupported = 0x00,
+        InvalidWidget = 0x01,
+        TooLong = 0x02,
+    }
 
     #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    #[cfg_attr(feature = "serde-support", serde(bound = ""))]
     pub struct Cmd<S: ArrayLength<u8>> {
-        pub command: String<S>,
+        pub widget: u16,
+        pub text: String<S>,
     }
 
     #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
     pub struct Ack {}
 
     #[derive(Clone, Debug)]
-    pub struct Nak {}
-}
-
-/// Get OS Layout
-/// TODO
-pub mod h0032 {
-    pub struct Cmd {}
-    pub struct Ack {}
-    pub struct Nak {}
-}
-
-/// Set OS Layout
-/// TODO
-pub mod h0033 {
-    pub struct Cmd {}
-    pub struct Ack {}
-    pub struct Nak {}
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Nak {
+        pub error: Error,
+    }
 }
 
-/// Terminal Output
-pub mod h0034 {
+/// Confirm Request
+pub mod h002a {
     use heapless::{ArrayLength, String};
+    use num_enum::TryFromPrimitive;
+
+    #[repr(u8)]
+    #[derive(PartialEq, Clone, Copy, Debug, TryFromPrimitive)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub enum Error {
+        NotSupported = 0x00,
+        Busy = 0x01,
+    }
 
     #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    #[cfg_attr(feature = "serde-support", serde(bound = ""))]
     pub struct Cmd<S: ArrayLength<u8>> {
-        pub output: String<S>,
+        pub request: u16,
+        pub timeout: u8,
+        pub prompt: String<S>,
     }
 
     #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
     pub struct Ack {}
 
     #[derive(Clone, Debug)]
-    pub struct Nak {}
-}
-
-/// HID Keyboard State
-/// TODO
-pub mod h0040 {
-    pub struct Cmd {}
-    pub struct Ack {}
-    pub struct Nak {}
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Nak {
+        pub error: Error,
+    }
 }
 
-/// HID Keyboard LED State
-/// TODO
-pub mod h0041 {
-    pub struct Cmd {}
-    pub struct Ack {}
-    pub struct Nak {}
-}
+/// Confirm Response
+pub mod h002b {
+    use num_enum::TryFromPrimitive;
 
-/// HID Mouse State
-/// TODO
-pub mod h0042 {
-    pub struct Cmd {}
-    pub struct Ack {}
-    pub struct Nak {}
-}
+    #[repr(u8)]
+    #[derive(PartialEq, Clone, Copy, Debug, TryFromPrimitive)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub enum Answer {
+        Confirmed = 0x00,
+        Denied = 0x01,
+        Timeout = 0x02,
+    }
 
-/// HID Joystick State
-/// TODO
-pub mod h0043 {
-    pub struct Cmd {}
-    pub struct Ack {}
-    pub struct Nak {}
-}
+    #[repr(u8)]
+    #[derive(PartialEq, Clone, Copy, Debug, TryFromPrimitive)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub enum Error {
+        UnknownRequest = 0x00,
+    }
 
-/// Manufacturing Test
-pub mod h0050 {
     #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
     pub struct Cmd {
-        pub command: u16,
-        pub argument: u16,
+        pub request: u16,
+        pub answer: Answer,
     }
 
     #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
     pub struct Ack {}
 
     #[derive(Clone, Debug)]
-    pub struct Nak {}
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Nak {
+        pub error: Error,
+    }
 }
 
-/// Manufacturing Test Result
-pub mod h0051 {
-    use heapless::{ArrayLength, Vec};
+/// Animation Upload Begin
+pub mod h002c {
+    use num_enum::TryFromPrimitive;
 
-    #[derive(Clone, Debug)]
-    pub struct Cmd<D: ArrayLength<u8>> {
-        pub command: u16,
-        pub argument: u16,
-        pub data: Vec<u8, D>,
+    #[repr(u8)]
+    #[derive(PartialEq, Clone, Copy, Debug, TryFromPrimitive)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub enum Format {
+        OneCh8b = 0x00,
+        ThreeCh8b = 0x01,
+        OneCh16b = 0x02,
+        ThreeCh16b = 0x03,
     }
 
-    #[derive(Clone, Debug)]
-    pub struct Ack {}
+    #[repr(u8)]
+    #[derive(PartialEq, Clone, Copy, Debug, TryFromPrimitive)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub enum Error {
+        NotSupported = 0x00,
+        InvalidSlot = 0x01,
+        InvalidFormat = 0x02,
+        NotEnoughStorage = 0x03,
+    }
 
-    #[derive(Clone, Debug)]
+    #[derive(Clone, Debug, PartialEq)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Cmd {
+        pub slot: u16,
+        pub format: Format,
+        pub frames: u16,
+        pub frame_len: u16,
+    }
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Ack {}
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Nak {
+        pub error: Error,
+    }
+}
+
+/// Animation Upload Data
+pub mod h002d {
+    use heapless::{ArrayLength, Vec};
+    use num_enum::TryFromPrimitive;
+
+    #[repr(u8)]
+    #[derive(PartialEq, Clone, Copy, Debug, TryFromPrimitive)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub enum Error {
+        NotStarted = 0x00,
+        InvalidOffset = 0x01,
+        CrcMismatch = 0x02,
+        StorageFailure = 0x03,
+    }
+
+    /// crc is a CRC-16/CCITT-FALSE of data, see hid_io_protocol::crc16
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    #[cfg_attr(feature = "serde-support", serde(bound = ""))]
+    pub struct Cmd<D: ArrayLength<u8>> {
+        pub offset: u32,
+        pub crc: u16,
+        pub data: Vec<u8, D>,
+    }
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Ack {}
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Nak {
+        pub error: Error,
+    }
+
+    /// Same CRC as the packet CRC16, kept for existing callers
+    pub use crate::crc16;
+}
+
+/// Animation Upload Finish
+pub mod h002e {
+    use num_enum::TryFromPrimitive;
+
+    #[repr(u8)]
+    #[derive(PartialEq, Clone, Copy, Debug, TryFromPrimitive)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub enum Action {
+        Commit = 0x00,
+        Abort = 0x01,
+    }
+
+    #[repr(u8)]
+    #[derive(PartialEq, Clone, Copy, Debug, TryFromPrimitive)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub enum Error {
+        NotStarted = 0x00,
+        Incomplete = 0x01,
+        StorageFailure = 0x02,
+    }
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Cmd {
+        pub action: Action,
+    }
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Ack {}
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Nak {
+        pub error: Error,
+    }
+}
+
+/// Open URL
+/// TODO
+pub mod h0030 {
+    pub struct Cmd {}
+    pub struct Ack {}
     pub struct Nak {}
 }
 
-// ----- Traits -----
+/// Terminal Command
+pub mod h0031 {
+    use heapless::{ArrayLength, String};
 
-/// HID-IO Command Interface
-/// H - Max data payload length (HidIoPacketBuffer)
-/// ID - Max number of HidIoCommandIds
-pub trait Commands<H: ArrayLength<u8>, ID: ArrayLength<HidIoCommandId> + ArrayLength<u8>>
-where
-    H: core::fmt::Debug + Sub<B1> + Sub<U4>,
-{
-    /// Given a HidIoPacketBuffer serialize (and resulting send bytes)
-    fn tx_packetbuffer_send(&mut self, buf: &mut HidIoPacketBuffer<H>) -> Result<(), CommandError>;
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    #[cfg_attr(feature = "serde-support", serde(bound = ""))]
+    pub struct Cmd<S: ArrayLength<u8>> {
+        pub command: String<S>,
+    }
 
-    /// Check if id is valid for this interface
-    /// (By default support all ids)
-    fn supported_id(&self, _id: HidIoCommandId) -> bool {
-        true
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Ack {}
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Nak {}
+}
+
+/// Get OS Layout
+/// TODO
+pub mod h0032 {
+    pub struct Cmd {}
+    pub struct Ack {}
+    pub struct Nak {}
+}
+
+/// Set OS Layout
+/// TODO
+pub mod h0033 {
+    pub struct Cmd {}
+    pub struct Ack {}
+    pub struct Nak {}
+}
+
+/// Terminal Output
+pub mod h0034 {
+    use heapless::{ArrayLength, String};
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    #[cfg_attr(feature = "serde-support", serde(bound = ""))]
+    pub struct Cmd<S: ArrayLength<u8>> {
+        pub output: String<S>,
+    }
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Ack {}
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Nak {}
+}
+
+/// Host State
+pub mod h0035 {
+    use num_enum::TryFromPrimitive;
+
+    #[repr(u8)]
+    #[derive(PartialEq, Clone, Copy, Debug, TryFromPrimitive)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub enum State {
+        Active = 0x00,
+        Idle = 0x01,
+        Locked = 0x02,
     }
 
-    /// Default packet chunk
-    /// (Usual chunk sizes are 63 or 64)
-    fn default_packet_chunk(&self) -> u32 {
-        64
+    #[derive(Clone, Debug, PartialEq)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Cmd {
+        pub state: State,
+        pub idle_secs: u32,
+        pub game_mode: bool,
+    }
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Ack {}
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Nak {}
+}
+
+/// Lock Host
+pub mod h0036 {
+    use num_enum::TryFromPrimitive;
+
+    #[repr(u8)]
+    #[derive(PartialEq, Clone, Copy, Debug, TryFromPrimitive)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub enum Error {
+        NotSupported = 0x00,
+        NotPermitted = 0x01,
+        RateLimited = 0x02,
+        Failed = 0x03,
+    }
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Cmd {}
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Ack {}
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Nak {
+        pub error: Error,
+    }
+}
+
+/// File Open
+pub mod h0037 {
+    use heapless::{ArrayLength, String};
+    use num_enum::TryFromPrimitive;
+
+    #[repr(u8)]
+    #[derive(PartialEq, Clone, Copy, Debug, TryFromPrimitive)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub enum Mode {
+        Read = 0x00,
+        Write = 0x01,
+        Resume = 0x02,
+    }
+
+    /// Errors shared by the file transfer commands (h0037..h003B)
+    #[repr(u8)]
+    #[derive(PartialEq, Clone, Copy, Debug, TryFromPrimitive)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub enum Error {
+        NotSupported = 0x00,
+        NotFound = 0x01,
+        InvalidHandle = 0x02,
+        InvalidOffset = 0x03,
+        NotEnoughStorage = 0x04,
+        TooManyOpen = 0x05,
+        StorageFailure = 0x06,
+    }
+
+    /// size is the total size of the file (ignored for Read)
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    #[cfg_attr(feature = "serde-support", serde(bound = ""))]
+    pub struct Cmd<S: ArrayLength<u8>> {
+        pub mode: Mode,
+        pub size: u32,
+        pub name: String<S>,
+    }
+
+    /// size is the file size for Read and the number of bytes already stored for Resume
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Ack {
+        pub handle: u8,
+        pub size: u32,
+    }
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Nak {
+        pub error: Error,
+    }
+}
+
+/// File Write
+pub mod h0038 {
+    use super::h0037::Error;
+    use heapless::{ArrayLength, Vec};
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    #[cfg_attr(feature = "serde-support", serde(bound = ""))]
+    pub struct Cmd<D: ArrayLength<u8>> {
+        pub handle: u8,
+        pub offset: u32,
+        pub data: Vec<u8, D>,
+    }
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Ack {}
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Nak {
+        pub error: Error,
+    }
+}
+
+/// File Read
+pub mod h0039 {
+    use super::h0037::Error;
+    use heapless::{ArrayLength, Vec};
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Cmd {
+        pub handle: u8,
+        pub offset: u32,
+        pub len: u16,
+    }
+
+    /// data is shorter than requested (or empty) at the end of the file
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    #[cfg_attr(feature = "serde-support", serde(bound = ""))]
+    pub struct Ack<D: ArrayLength<u8>> {
+        pub data: Vec<u8, D>,
+    }
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Nak {
+        pub error: Error,
+    }
+}
+
+/// File Checksum
+pub mod h003a {
+    use super::h0037::Error;
+
+    #[derive(Clone, Debug, PartialEq)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Cmd {
+        pub handle: u8,
+        pub offset: u32,
+        pub len: u32,
+    }
+
+    /// crc is a CRC-16/CCITT-FALSE of the range, see hid_io_protocol::crc16
+    #[derive(Clone, Debug, PartialEq)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Ack {
+        pub crc: u16,
+    }
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Nak {
+        pub error: Error,
+    }
+}
+
+/// File Close
+pub mod h003b {
+    use super::h0037::Error;
+    use num_enum::TryFromPrimitive;
+
+    #[repr(u8)]
+    #[derive(PartialEq, Clone, Copy, Debug, TryFromPrimitive)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub enum Action {
+        Commit = 0x00,
+        Abort = 0x01,
+        Suspend = 0x02,
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Cmd {
+        pub handle: u8,
+        pub action: Action,
+    }
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Ack {}
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Nak {
+        pub error: Error,
+    }
+}
+
+/// HID Keyboard State
+/// TODO
+pub mod h0040 {
+    pub struct Cmd {}
+    pub struct Ack {}
+    pub struct Nak {}
+}
+
+/// HID Keyboard LED State
+/// TODO
+pub mod h0041 {
+    pub struct Cmd {}
+    pub struct Ack {}
+    pub struct Nak {}
+}
+
+/// HID Mouse State
+/// TODO
+pub mod h0042 {
+    pub struct Cmd {}
+    pub struct Ack {}
+    pub struct Nak {}
+}
+
+/// HID Joystick State
+/// TODO
+pub mod h0043 {
+    pub struct Cmd {}
+    pub struct Ack {}
+    pub struct Nak {}
+}
+
+/// Manufacturing Test
+pub mod h0050 {
+    #[derive(Clone, Debug, PartialEq)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Cmd {
+        pub command: u16,
+        pub argument: u16,
+    }
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Ack {}
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Nak {}
+}
+
+/// Manufacturing Test Result
+pub mod h0051 {
+    use heapless::{ArrayLength, Vec};
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    #[cfg_attr(feature = "serde-support", serde(bound = ""))]
+    pub struct Cmd<D: ArrayLength<u8>> {
+        pub command: u16,
+        pub argument: u16,
+        pub data: Vec<u8, D>,
+    }
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Ack {}
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    pub struct Nak {}
+}
+
+/// Vendor specific / experimental commands (0x60..0x6F)
+/// Payloads are passed through as raw bytes
+pub mod vendor {
+    use heapless::{ArrayLength, Vec};
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    #[cfg_attr(feature = "serde-support", serde(bound = ""))]
+    pub struct Cmd<D: ArrayLength<u8>> {
+        pub data: Vec<u8, D>,
+    }
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    #[cfg_attr(feature = "serde-support", serde(bound = ""))]
+    pub struct Ack<D: ArrayLength<u8>> {
+        pub data: Vec<u8, D>,
+    }
+
+    #[derive(Clone, Debug)]
+    #[cfg_attr(
+        feature = "serde-support",
+        derive(serde::Serialize, serde::Deserialize)
+    )]
+    #[cfg_attr(feature = "serde-support", serde(bound = ""))]
+    pub struct Nak<D: ArrayLength<u8>> {
+        pub data: Vec<u8, D>,
+    }
+}
+
+// ----- Traits -----
+
+/// HID-IO Command Interface
+/// H - Max data payload length (HidIoPacketBuffer)
+/// ID - Max number of HidIoCommandIds
+pub trait Commands<H: ArrayLength<u8>, ID: ArrayLength<HidIoCommandId> + ArrayLength<u8>>
+where
+    H: core::fmt::Debug + Sub<B1> + Sub<U4>,
+{
+    /// Given a HidIoPacketBuffer serialize (and resulting send bytes)
+    fn tx_packetbuffer_send(&mut self, buf: &mut HidIoPacketBuffer<H>) -> Result<(), CommandError>;
+
+    /// Check if id is valid for this interface
+    /// (By default support all ids)
+    fn supported_id(&self, _id: HidIoCommandId) -> bool {
+        true
+    }
+
+    /// Default packet chunk
+    /// (Usual chunk sizes are 63 or 64)
+    fn default_packet_chunk(&self) -> u32 {
+        64
+    }
+
+    /// Naks a packet discarded during reassembly because Continued packets went missing
+    /// (HidIoParseError::IncompleteBuffer or SequenceGap), only Data packets are Nak'd.
+    /// Returns true if the chunk must be decoded again, false for any other error.
+    fn rx_packetbuffer_discarded(&mut self, err: &HidIoParseError) -> Result<bool, CommandError> {
+        let (id, ptype) = match *err {
+            HidIoParseError::IncompleteBuffer { id, ptype } => (id, ptype),
+            HidIoParseError::SequenceGap { id, ptype, .. } => (id, ptype),
+            _ => {
+                return Ok(false);
+            }
+        };
+        if ptype == HidIoPacketType::Data {
+            self.empty_nak(id)?;
+        }
+        Ok(true)
+    }
+
+    /// Simple empty ack
+    fn empty_ack(&mut self, id: HidIoCommandId) -> Result<(), CommandError> {
+        // Build empty Ack
+        self.tx_packetbuffer_send(&mut HidIoPacketBuffer {
+            // Data packet
+            ptype: HidIoPacketType::Ack,
+            // Packet id
+            id,
+            // Detect max size
+            max_len: self.default_packet_chunk(),
+            // Ready to go
+            done: true,
+            // Use defaults for other fields
+            ..Default::default()
+        })
+    }
+
+    /// Simple empty nak
+    fn empty_nak(&mut self, id: HidIoCommandId) -> Result<(), CommandError> {
+        // Build empty Nak
+        self.tx_packetbuffer_send(&mut HidIoPacketBuffer {
+            // Data packet
+            ptype: HidIoPacketType::Nak,
+            // Packet id
+            id,
+            // Detect max size
+            max_len: self.default_packet_chunk(),
+            // Ready to go
+            done: true,
+            // Use defaults for other fields
+            ..Default::default()
+        })
+    }
+
+    /// Simple byte ack
+    fn byte_ack(&mut self, id: HidIoCommandId, byte: u8) -> Result<(), CommandError> {
+        // Build Ack
+        self.tx_packetbuffer_send(&mut HidIoPacketBuffer {
+            // Data packet
+            ptype: HidIoPacketType::Ack,
+            // Packet id
+            id,
+            // Known id
+            unknown_id: None,
+            // Detect max size
+            max_len: self.default_packet_chunk(),
+            // Byte payload
+            data: Vec::from_slice(&[byte]).unwrap(),
+            // Ready to go
+            done: true,
+        })
+    }
+
+    /// Simple byte nak
+    fn byte_nak(&mut self, id: HidIoCommandId, byte: u8) -> Result<(), CommandError> {
+        // Build Nak
+        self.tx_packetbuffer_send(&mut HidIoPacketBuffer {
+            // Data packet
+            ptype: HidIoPacketType::Nak,
+            // Packet id
+            id,
+            // Known id
+            unknown_id: None,
+            // Detect max size
+            max_len: self.default_packet_chunk(),
+            // Byte payload
+            data: Vec::from_slice(&[byte]).unwrap(),
+            // Ready to go
+            done: true,
+        })
+    }
+
+    /// Simple short ack (16-bit)
+    fn short_ack(&mut self, id: HidIoCommandId, val: u16) -> Result<(), CommandError> {
+        // Build Ack
+        self.tx_packetbuffer_send(&mut HidIoPacketBuffer {
+            // Data packet
+            ptype: HidIoPacketType::Ack,
+            // Packet id
+            id,
+            // Known id
+            unknown_id: None,
+            // Detect max size
+            max_len: self.default_packet_chunk(),
+            // Byte payload
+            data: Vec::from_slice(&val.to_le_bytes()).unwrap(),
+            // Ready to go
+            done: true,
+        })
+    }
+
+    /// Simple short nak (16-bit)
+    fn short_nak(&mut self, id: HidIoCommandId, val: u16) -> Result<(), CommandError> {
+        // Build Nak
+        self.tx_packetbuffer_send(&mut HidIoPacketBuffer {
+            // Data packet
+            ptype: HidIoPacketType::Nak,
+            // Packet id
+            id,
+            // Known id
+            unknown_id: None,
+            // Detect max size
+            max_len: self.default_packet_chunk(),
+            // Byte payload
+            data: Vec::from_slice(&val.to_le_bytes()).unwrap(),
+            // Ready to go
+            done: true,
+        })
+    }
+
+    /// Process specific packet types
+    /// Handles matching to interface functions
+    fn rx_message_handling(&mut self, buf: HidIoPacketBuffer<H>) -> Result<(), CommandError>
+    where
+        <H as Sub<B1>>::Output: ArrayLength<u8>,
+        <H as Sub<U4>>::Output: ArrayLength<u8>,
+    {
+        // Ids unknown to this version of the protocol have no handler
+        if let Some(id) = buf.unknown_id {
+            return Err(CommandError::InvalidId(id));
+        }
+
+        // Make sure we're processing a supported id
+        if !self.supported_id(buf.id) {
+            return Err(CommandError::IdNotSupported(buf.id));
+        }
+
+        // Check for invalid packet types
+        match buf.ptype {
+            HidIoPacketType::Data | HidIoPacketType::NaData => {}
+            HidIoPacketType::Ack => {}
+            HidIoPacketType::Nak => {}
+            _ => {
+                return Err(CommandError::InvalidRxMessage(buf.ptype));
+            }
+        }
+
+        // Match id
+        match buf.id {
+            HidIoCommandId::SupportedIds => self.h0000_supported_ids_handler(buf),
+            HidIoCommandId::GetInfo => self.h0001_info_handler(buf),
+            HidIoCommandId::TestPacket => self.h0002_test_handler(buf),
+            HidIoCommandId::ResetHidIo => self.h0003_resethidio_handler(buf),
+            HidIoCommandId::Capabilities => self.h0004_capabilities_handler(buf),
+            HidIoCommandId::Pair => self.h0005_pair_handler(buf),
+            HidIoCommandId::FlashMode => self.h0016_flashmode_handler(buf),
+            HidIoCommandId::UnicodeText => self.h0017_unicodetext_handler(buf),
+            HidIoCommandId::UnicodeState => self.h0018_unicodestate_handler(buf),
+            HidIoCommandId::SleepMode => self.h001a_sleepmode_handler(buf),
+            HidIoCommandId::SettingsRead => self.h001b_settingsread_handler(buf),
+            HidIoCommandId::SettingsWrite => self.h001c_settingswrite_handler(buf),
+            HidIoCommandId::KllLayoutRead => self.h001d_klllayoutread_handler(buf),
+            HidIoCommandId::KllLayoutWrite => self.h001e_klllayoutwrite_handler(buf),
+            HidIoCommandId::KllLayoutControl => self.h001f_klllayoutcontrol_handler(buf),
+            HidIoCommandId::DisplayText => self.h0029_displaytext_handler(buf),
+            HidIoCommandId::ConfirmRequest => self.h002a_confirmrequest_handler(buf),
+            HidIoCommandId::ConfirmResponse => self.h002b_confirmresponse_handler(buf),
+            HidIoCommandId::AnimationBegin => self.h002c_animationbegin_handler(buf),
+            HidIoCommandId::AnimationData => self.h002d_animationdata_handler(buf),
+            HidIoCommandId::AnimationFinish => self.h002e_animationfinish_handler(buf),
+            HidIoCommandId::TerminalCmd => self.h0031_terminalcmd_handler(buf),
+            HidIoCommandId::TerminalOut => self.h0034_terminalout_handler(buf),
+            HidIoCommandId::HostState => self.h0035_hoststate_handler(buf),
+            HidIoCommandId::LockHost => self.h0036_lockhost_handler(buf),
+            HidIoCommandId::FileOpen => self.h0037_fileopen_handler(buf),
+            HidIoCommandId::FileWrite => self.h0038_filewrite_handler(buf),
+            HidIoCommandId::FileRead => self.h0039_fileread_handler(buf),
+            HidIoCommandId::FileChecksum => self.h003a_filechecksum_handler(buf),
+            HidIoCommandId::FileClose => self.h003b_fileclose_handler(buf),
+            HidIoCommandId::ManufacturingTest => self.h0050_manufacturing_handler(buf),
+            HidIoCommandId::ManufacturingResult => self.h0051_manufacturingres_handler(buf),
+            id if id.is_vendor() => self.vendor_handler(buf),
+            _ => Err(CommandError::IdNotMatched(buf.id)),
+        }
+    }
+
+    fn h0000_supported_ids(&mut self, _data: h0000::Cmd) -> Result<(), CommandError> {
+        self.tx_packetbuffer_send(&mut HidIoPacketBuffer {
+            // Test packet id
+            id: HidIoCommandId::SupportedIds,
+            // Detect max size
+            max_len: self.default_packet_chunk(),
+            // Ready to go
+            done: true,
+            // Use defaults for other fields
+            ..Default::default()
+        })
+    }
+    fn h0000_supported_ids_cmd(&mut self, _data: h0000::Cmd) -> Result<h0000::Ack<ID>, h0000::Nak> {
+        Err(h0000::Nak {})
+    }
+    fn h0000_supported_ids_ack(&mut self, _data: h0000::Ack<ID>) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::SupportedIds,
+            HidIoPacketType::Ack,
+        ))
+    }
+    fn h0000_supported_ids_nak(&mut self, _data: h0000::Nak) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::SupportedIds,
+            HidIoPacketType::Nak,
+        ))
+    }
+    fn h0000_supported_ids_handler(
+        &mut self,
+        buf: HidIoPacketBuffer<H>,
+    ) -> Result<(), CommandError> {
+        // Handle packet type
+        match buf.ptype {
+            HidIoPacketType::Data => {
+                match self.h0000_supported_ids_cmd(h0000::Cmd {}) {
+                    Ok(ack) => {
+                        // Build Ack
+                        let mut buf = HidIoPacketBuffer {
+                            // Data packet
+                            ptype: HidIoPacketType::Ack,
+                            // Packet id
+                            id: buf.id,
+                            // Detect max size
+                            max_len: self.default_packet_chunk(),
+                            // Ready to go
+                            done: true,
+                            // Use defaults for other fields
+                            ..Default::default()
+                        };
+
+                        // Build list of ids
+                        for id in ack.ids {
+                            if buf
+                                .data
+                                .extend_from_slice(&(id as u16).to_le_bytes())
+                                .is_err()
+                            {
+                                return Err(CommandError::IdVecTooSmall);
+                            }
+                        }
+                        self.tx_packetbuffer_send(&mut buf)
+                    }
+                    Err(_nak) => self.empty_nak(buf.id),
+                }
+            }
+            HidIoPacketType::NaData => Err(CommandError::InvalidPacketBufferType(buf.ptype)),
+            HidIoPacketType::Ack => {
+                // Retrieve list of ids
+                let mut ids: Vec<HidIoCommandId, ID> = Vec::new();
+                // Ids are always 16-bit le for this command
+                let mut pos = 0;
+                while pos <= buf.data.len() - 2 {
+                    let slice = &buf.data[pos..pos + 2];
+                    let idnum = u16::from_le_bytes(slice.try_into().unwrap()) as u32;
+                    // Skip ids unknown to this version of the protocol (newer firmware)
+                    let id = match HidIoCommandId::try_from(idnum) {
+                        Ok(id) => id,
+                        Err(_) => {
+                            pos += 2;
+                            continue;
+                        }
+                    };
+                    // Attempt to push to id list
+                    // NOTE: If the vector is not large enough
+                    //       just truncate.
+                    //       This command won't be called by devices
+                    //       often.
+                    // TODO: Add optional fields to request a range
+                    if ids.push(id).is_err() {
+                        break;
+                    }
+                    pos += 2;
+                }
+                self.h0000_supported_ids_ack(h0000::Ack { ids })
+            }
+            HidIoPacketType::Nak => self.h0000_supported_ids_nak(h0000::Nak {}),
+            _ => Ok(()),
+        }
+    }
+
+    fn h0001_info(&mut self, data: h0001::Cmd) -> Result<(), CommandError> {
+        // Create appropriately sized buffer
+        let mut buf = HidIoPacketBuffer {
+            // Test packet id
+            id: HidIoCommandId::GetInfo,
+            // Detect max size
+            max_len: self.default_packet_chunk(),
+            // Ready to go
+            done: true,
+            // Use defaults for other fields
+            ..Default::default()
+        };
+
+        // Encode property
+        if buf.data.push(data.property as u8).is_err() {
+            return Err(CommandError::DataVecTooSmall);
+        }
+
+        self.tx_packetbuffer_send(&mut buf)
+    }
+    fn h0001_info_cmd(&mut self, _data: h0001::Cmd) -> Result<h0001::Ack<Sub1<H>>, h0001::Nak>
+    where
+        <H as Sub<B1>>::Output: ArrayLength<u8>,
+    {
+        Err(h0001::Nak {
+            property: h0001::Property::Unknown,
+        })
+    }
+    fn h0001_info_ack(&mut self, _data: h0001::Ack<Sub1<H>>) -> Result<(), CommandError>
+    where
+        <H as Sub<B1>>::Output: ArrayLength<u8>,
+    {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::GetInfo,
+            HidIoPacketType::Ack,
+        ))
+    }
+    fn h0001_info_nak(&mut self, _data: h0001::Nak) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::GetInfo,
+            HidIoPacketType::Nak,
+        ))
+    }
+    fn h0001_info_handler(&mut self, buf: HidIoPacketBuffer<H>) -> Result<(), CommandError>
+    where
+        <H as Sub<B1>>::Output: ArrayLength<u8>,
+    {
+        // Handle packet type
+        match buf.ptype {
+            HidIoPacketType::Data => {
+                if buf.data.len() < 1 {
+                    return Err(CommandError::DataVecNoData);
+                }
+                // Attempt to read first byte
+                let property = match h0001::Property::try_from(buf.data[0]) {
+                    Ok(property) => property,
+                    Err(_) => {
+                        return Err(CommandError::InvalidProperty8(buf.data[0]));
+                    }
+                };
+                match self.h0001_info_cmd(h0001::Cmd { property }) {
+                    Ok(ack) => {
+                        // Build Ack
+                        let mut buf = HidIoPacketBuffer {
+                            // Data packet
+                            ptype: HidIoPacketType::Ack,
+                            // Packet id
+                            id: buf.id,
+                            // Detect max size
+                            max_len: self.default_packet_chunk(),
+                            // Ready to go
+                            done: true,
+                            // Use defaults for other fields
+                            ..Default::default()
+                        };
+
+                        // Set property
+                        if buf.data.push(ack.property as u8).is_err() {
+                            return Err(CommandError::DataVecTooSmall);
+                        }
+
+                        // Depending on the property set the rest
+                        // of the data field
+                        match property {
+                            h0001::Property::Unknown => {}
+                            // Handle 16-bit number type
+                            h0001::Property::MajorVersion
+                            | h0001::Property::MinorVersion
+                            | h0001::Property::PatchVersion => {
+                                // Convert to byte le bytes
+                                for byte in &ack.number.to_le_bytes() {
+                                    if buf.data.push(*byte).is_err() {
+                                        return Err(CommandError::DataVecTooSmall);
+                                    }
+                                }
+                            }
+                            // Handle 8-bit os type
+                            h0001::Property::OsType => {
+                                if buf.data.push(ack.os as u8).is_err() {
+                                    return Err(CommandError::DataVecTooSmall);
+                                }
+                            }
+                            // Handle ascii values
+                            _ => {
+                                for byte in ack.string.into_bytes() {
+                                    if buf.data.push(byte).is_err() {
+                                        return Err(CommandError::DataVecTooSmall);
+                                    }
+                                }
+                            }
+                        }
+
+                        self.tx_packetbuffer_send(&mut buf)
+                    }
+                    Err(_nak) => self.byte_nak(buf.id, property as u8),
+                }
+            }
+            HidIoPacketType::NaData => Err(CommandError::InvalidPacketBufferType(buf.ptype)),
+            HidIoPacketType::Ack => {
+                if buf.data.len() < 1 {
+                    return Err(CommandError::DataVecNoData);
+                }
+                // Attempt to read first byte
+                let property = match h0001::Property::try_from(buf.data[0]) {
+                    Ok(property) => property,
+                    Err(_) => {
+                        return Err(CommandError::InvalidProperty8(buf.data[0]));
+                    }
+                };
+
+                // Setup ack struct
+                let mut ack = h0001::Ack {
+                    property,
+                    os: h0001::OsType::Unknown,
+                    number: 0,
+                    string: String::new(),
+                };
+
+                // Depending on the property set the rest
+                // of the ack fields
+                match property {
+                    h0001::Property::Unknown => {}
+                    // Handle 16-bit number type
+                    h0001::Property::MajorVersion
+                    | h0001::Property::MinorVersion
+                    | h0001::Property::PatchVersion => {
+                        // Convert from le bytes
+                        ack.number = u16::from_le_bytes(buf.data[1..3].try_into().unwrap());
+                    }
+                    // Handle 8-bit os type
+                    h0001::Property::OsType => {
+                        let typenum = buf.data[1];
+                        ack.os = match h0001::OsType::try_from(typenum) {
+                            Ok(ostype) => ostype,
+                            Err(_) => {
+                                return Err(CommandError::InvalidProperty8(typenum));
+                            }
+                        };
+                    }
+                    // Handle ascii values
+                    _ => {
+                        // NOTE: This is annoyingly inefficient?
+                        let bytes: Vec<u8, Sub1<H>> = Vec::from_slice(&buf.data[1..]).unwrap();
+                        let string = match String::from_utf8(bytes) {
+                            Ok(string) => string,
+                            Err(e) => {
+                                return Err(CommandError::InvalidUtf8(e));
+                            }
+                        };
+                        ack.string = string;
+                    }
+                }
+
+                self.h0001_info_ack(ack)
+            }
+            HidIoPacketType::Nak => {
+                if buf.data.len() < 1 {
+                    return Err(CommandError::DataVecNoData);
+                }
+                // Attempt to read first byte
+                let property = match h0001::Property::try_from(buf.data[0]) {
+                    Ok(property) => property,
+                    Err(_) => {
+                        return Err(CommandError::InvalidProperty8(buf.data[0]));
+                    }
+                };
+                self.h0001_info_nak(h0001::Nak { property })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn h0002_test(&mut self, data: h0002::Cmd<H>, na: bool) -> Result<(), CommandError> {
+        // Create appropriately sized buffer
+        let mut buf = HidIoPacketBuffer {
+            // Test packet id
+            id: HidIoCommandId::TestPacket,
+            // Detect max size
+            max_len: self.default_packet_chunk(),
+            // Use defaults for other fields
+            ..Default::default()
+        };
+
+        // Set NA (no-ack)
+        if na {
+            buf.ptype = HidIoPacketType::NaData;
+        }
+
+        // Build payload
+        if !buf.append_payload(&data.data) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+        buf.done = true;
+
+        self.tx_packetbuffer_send(&mut buf)
+    }
+    fn h0002_test_cmd(&mut self, _data: h0002::Cmd<H>) -> Result<h0002::Ack<H>, h0002::Nak> {
+        Err(h0002::Nak {})
+    }
+    fn h0002_test_nacmd(&mut self, _data: h0002::Cmd<H>) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::TestPacket,
+            HidIoPacketType::NaData,
+        ))
+    }
+    fn h0002_test_ack(&mut self, _data: h0002::Ack<H>) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::TestPacket,
+            HidIoPacketType::Ack,
+        ))
+    }
+    fn h0002_test_nak(&mut self, _data: h0002::Nak) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::TestPacket,
+            HidIoPacketType::Nak,
+        ))
+    }
+    fn h0002_test_handler(&mut self, buf: HidIoPacketBuffer<H>) -> Result<(), CommandError> {
+        // Handle packet type
+        match buf.ptype {
+            HidIoPacketType::Data => {
+                // Copy data into struct
+                let cmd = h0002::Cmd::<H> {
+                    data: match Vec::from_slice(&buf.data) {
+                        Ok(data) => data,
+                        Err(_) => {
+                            return Err(CommandError::DataVecTooSmall);
+                        }
+                    },
+                };
+
+                match self.h0002_test_cmd(cmd) {
+                    Ok(ack) => {
+                        // Build Ack (max test data size)
+                        let mut buf = HidIoPacketBuffer {
+                            // Data packet
+                            ptype: HidIoPacketType::Ack,
+                            // Packet id
+                            id: buf.id,
+                            // Detect max size
+                            max_len: self.default_packet_chunk(),
+                            ..Default::default()
+                        };
+
+                        // Copy data into buffer
+                        if !buf.append_payload(&ack.data) {
+                            return Err(CommandError::DataVecTooSmall);
+                        }
+                        buf.done = true;
+                        self.tx_packetbuffer_send(&mut buf)
+                    }
+                    Err(_nak) => self.empty_nak(buf.id),
+                }
+            }
+            HidIoPacketType::NaData => {
+                // Copy data into struct
+                let cmd = h0002::Cmd::<H> {
+                    data: match Vec::from_slice(&buf.data) {
+                        Ok(data) => data,
+                        Err(_) => {
+                            return Err(CommandError::DataVecTooSmall);
+                        }
+                    },
+                };
+
+                self.h0002_test_nacmd(cmd)
+            }
+            HidIoPacketType::Ack => {
+                // Copy data into struct
+                let ack = h0002::Ack::<H> {
+                    data: match Vec::from_slice(&buf.data) {
+                        Ok(data) => data,
+                        Err(_) => {
+                            return Err(CommandError::DataVecTooSmall);
+                        }
+                    },
+                };
+
+                self.h0002_test_ack(ack)
+            }
+            HidIoPacketType::Nak => self.h0002_test_nak(h0002::Nak {}),
+            _ => Ok(()),
+        }
+    }
+
+    fn h0003_resethidio(&mut self, _data: h0003::Cmd) -> Result<(), CommandError> {
+        self.tx_packetbuffer_send(&mut HidIoPacketBuffer {
+            // Test packet id
+            id: HidIoCommandId::ResetHidIo,
+            // Detect max size
+            max_len: self.default_packet_chunk(),
+            // Ready
+            done: true,
+            // Use defaults for other fields
+            ..Default::default()
+        })
+    }
+    fn h0003_resethidio_cmd(&mut self, _data: h0003::Cmd) -> Result<h0003::Ack, h0003::Nak> {
+        Err(h0003::Nak {})
+    }
+    fn h0003_resethidio_ack(&mut self, _data: h0003::Ack) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::ResetHidIo,
+            HidIoPacketType::Ack,
+        ))
+    }
+    fn h0003_resethidio_nak(&mut self, _data: h0003::Nak) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::ResetHidIo,
+            HidIoPacketType::Nak,
+        ))
+    }
+    fn h0003_resethidio_handler(&mut self, buf: HidIoPacketBuffer<H>) -> Result<(), CommandError> {
+        // Handle packet type
+        match buf.ptype {
+            HidIoPacketType::Data => match self.h0003_resethidio_cmd(h0003::Cmd {}) {
+                Ok(_ack) => self.empty_ack(buf.id),
+                Err(_nak) => self.empty_nak(buf.id),
+            },
+            HidIoPacketType::NaData => Err(CommandError::InvalidPacketBufferType(buf.ptype)),
+            HidIoPacketType::Ack => self.h0003_resethidio_ack(h0003::Ack {}),
+            HidIoPacketType::Nak => self.h0003_resethidio_nak(h0003::Nak {}),
+            _ => Ok(()),
+        }
+    }
+
+    fn h0004_capabilities(&mut self, data: h0004::Cmd) -> Result<(), CommandError> {
+        // Create appropriately sized buffer
+        let mut buf = HidIoPacketBuffer {
+            // Test packet id
+            id: HidIoCommandId::Capabilities,
+            // Detect max size
+            max_len: self.default_packet_chunk(),
+            // Use defaults for other fields
+            ..Default::default()
+        };
+
+        // Build payload
+        if !buf.append_payload(&data.version.to_le_bytes()) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+        if !buf.append_payload(&data.capabilities.to_le_bytes()) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+        buf.done = true;
+
+        self.tx_packetbuffer_send(&mut buf)
+    }
+    fn h0004_capabilities_cmd(&mut self, _data: h0004::Cmd) -> Result<h0004::Ack, h0004::Nak> {
+        Err(h0004::Nak {})
+    }
+    fn h0004_capabilities_ack(&mut self, _data: h0004::Ack) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::Capabilities,
+            HidIoPacketType::Ack,
+        ))
+    }
+    fn h0004_capabilities_nak(&mut self, _data: h0004::Nak) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::Capabilities,
+            HidIoPacketType::Nak,
+        ))
+    }
+    fn h0004_capabilities_handler(
+        &mut self,
+        buf: HidIoPacketBuffer<H>,
+    ) -> Result<(), CommandError> {
+        // Handle packet type
+        match buf.ptype {
+            HidIoPacketType::Data => {
+                if buf.data.len() < 6 {
+                    return Err(CommandError::DataVecNoData);
+                }
+
+                // Retrieve fields
+                let version = u16::from_le_bytes(buf.data[0..2].try_into().unwrap());
+                let capabilities = u32::from_le_bytes(buf.data[2..6].try_into().unwrap());
+                match self.h0004_capabilities_cmd(h0004::Cmd {
+                    version,
+                    capabilities,
+                }) {
+                    Ok(ack) => {
+                        // Build Ack
+                        let mut buf = HidIoPacketBuffer {
+                            // Data packet
+                            ptype: HidIoPacketType::Ack,
+                            // Packet id
+                            id: buf.id,
+                            // Detect max size
+                            max_len: self.default_packet_chunk(),
+                            // Use defaults for other fields
+                            ..Default::default()
+                        };
+                        if !buf.append_payload(&ack.version.to_le_bytes()) {
+                            return Err(CommandError::DataVecTooSmall);
+                        }
+                        if !buf.append_payload(&ack.capabilities.to_le_bytes()) {
+                            return Err(CommandError::DataVecTooSmall);
+                        }
+                        buf.done = true;
+                        self.tx_packetbuffer_send(&mut buf)
+                    }
+                    Err(_nak) => self.empty_nak(buf.id),
+                }
+            }
+            HidIoPacketType::NaData => Err(CommandError::InvalidPacketBufferType(buf.ptype)),
+            HidIoPacketType::Ack => {
+                if buf.data.len() < 6 {
+                    return Err(CommandError::DataVecNoData);
+                }
+
+                let version = u16::from_le_bytes(buf.data[0..2].try_into().unwrap());
+                let capabilities = u32::from_le_bytes(buf.data[2..6].try_into().unwrap());
+                self.h0004_capabilities_ack(h0004::Ack {
+                    version,
+                    capabilities,
+                })
+            }
+            HidIoPacketType::Nak => self.h0004_capabilities_nak(h0004::Nak {}),
+            _ => Ok(()),
+        }
+    }
+
+    fn h0005_pair(&mut self, data: h0005::Cmd) -> Result<(), CommandError> {
+        // Create appropriately sized buffer
+        let mut buf = HidIoPacketBuffer {
+            // Test packet id
+            id: HidIoCommandId::Pair,
+            // Detect max size
+            max_len: self.default_packet_chunk(),
+            // Use defaults for other fields
+            ..Default::default()
+        };
+
+        // Build payload
+        if !buf.append_payload(&data.key) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+        buf.done = true;
+
+        self.tx_packetbuffer_send(&mut buf)
+    }
+    fn h0005_pair_cmd(&mut self, _data: h0005::Cmd) -> Result<h0005::Ack, h0005::Nak> {
+        Err(h0005::Nak {})
+    }
+    fn h0005_pair_ack(&mut self, _data: h0005::Ack) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::Pair,
+            HidIoPacketType::Ack,
+        ))
+    }
+    fn h0005_pair_nak(&mut self, _data: h0005::Nak) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::Pair,
+            HidIoPacketType::Nak,
+        ))
+    }
+    fn h0005_pair_handler(&mut self, buf: HidIoPacketBuffer<H>) -> Result<(), CommandError> {
+        // Handle packet type
+        match buf.ptype {
+            HidIoPacketType::Data => {
+                if buf.data.len() < h0005::KEY_LEN {
+                    return Err(CommandError::DataVecNoData);
+                }
+
+                let mut key = [0; h0005::KEY_LEN];
+                key.copy_from_slice(&buf.data[..h0005::KEY_LEN]);
+                match self.h0005_pair_cmd(h0005::Cmd { key }) {
+                    Ok(_ack) => self.empty_ack(buf.id),
+                    Err(_nak) => self.empty_nak(buf.id),
+                }
+            }
+            HidIoPacketType::NaData => Err(CommandError::InvalidPacketBufferType(buf.ptype)),
+            HidIoPacketType::Ack => self.h0005_pair_ack(h0005::Ack {}),
+            HidIoPacketType::Nak => self.h0005_pair_nak(h0005::Nak {}),
+            _ => Ok(()),
+        }
+    }
+
+    fn h0016_flashmode(&mut self, _data: h0016::Cmd) -> Result<(), CommandError> {
+        self.tx_packetbuffer_send(&mut HidIoPacketBuffer {
+            // Test packet id
+            id: HidIoCommandId::FlashMode,
+            // Detect max size
+            max_len: self.default_packet_chunk(),
+            // Ready
+            done: true,
+            // Use defaults for other fields
+            ..Default::default()
+        })
+    }
+    fn h0016_flashmode_cmd(&mut self, _data: h0016::Cmd) -> Result<h0016::Ack, h0016::Nak> {
+        Err(h0016::Nak {
+            error: h0016::Error::NotSupported,
+        })
+    }
+    fn h0016_flashmode_ack(&mut self, _data: h0016::Ack) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::FlashMode,
+            HidIoPacketType::Ack,
+        ))
+    }
+    fn h0016_flashmode_nak(&mut self, _data: h0016::Nak) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::FlashMode,
+            HidIoPacketType::Nak,
+        ))
+    }
+    fn h0016_flashmode_handler(&mut self, buf: HidIoPacketBuffer<H>) -> Result<(), CommandError> {
+        // Handle packet type
+        match buf.ptype {
+            HidIoPacketType::Data => match self.h0016_flashmode_cmd(h0016::Cmd {}) {
+                Ok(ack) => self.short_ack(buf.id, ack.scancode),
+                Err(nak) => self.byte_nak(buf.id, nak.error as u8),
+            },
+            HidIoPacketType::NaData => Err(CommandError::InvalidPacketBufferType(buf.ptype)),
+            HidIoPacketType::Ack => {
+                if buf.data.len() < 2 {
+                    return Err(CommandError::DataVecNoData);
+                }
+
+                let scancode = u16::from_le_bytes(buf.data[0..2].try_into().unwrap());
+                self.h0016_flashmode_ack(h0016::Ack { scancode })
+            }
+            HidIoPacketType::Nak => {
+                if buf.data.len() < 1 {
+                    return Err(CommandError::DataVecNoData);
+                }
+
+                let error = match h0016::Error::try_from(buf.data[0]) {
+                    Ok(error) => error,
+                    Err(_) => {
+                        return Err(CommandError::InvalidProperty8(buf.data[0]));
+                    }
+                };
+                self.h0016_flashmode_nak(h0016::Nak { error })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn h0017_unicodetext(&mut self, data: h0017::Cmd<H>, na: bool) -> Result<(), CommandError> {
+        // Create appropriately sized buffer
+        let mut buf = HidIoPacketBuffer {
+            // Test packet id
+            id: HidIoCommandId::UnicodeText,
+            // Detect max size
+            max_len: self.default_packet_chunk(),
+            // Use defaults for other fields
+            ..Default::default()
+        };
+
+        // Set NA (no-ack)
+        if na {
+            buf.ptype = HidIoPacketType::NaData;
+        }
+
+        // Build payload
+        if !buf.append_payload(data.string.as_bytes()) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+        buf.done = true;
+
+        self.tx_packetbuffer_send(&mut buf)
+    }
+    fn h0017_unicodetext_cmd(&mut self, _data: h0017::Cmd<H>) -> Result<h0017::Ack, h0017::Nak> {
+        Err(h0017::Nak {})
+    }
+    fn h0017_unicodetext_nacmd(&mut self, _data: h0017::Cmd<H>) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::UnicodeText,
+            HidIoPacketType::NaData,
+        ))
+    }
+    fn h0017_unicodetext_ack(&mut self, _data: h0017::Ack) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::UnicodeText,
+            HidIoPacketType::Ack,
+        ))
+    }
+    fn h0017_unicodetext_nak(&mut self, _data: h0017::Nak) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::UnicodeText,
+            HidIoPacketType::Nak,
+        ))
+    }
+    fn h0017_unicodetext_handler(&mut self, buf: HidIoPacketBuffer<H>) -> Result<(), CommandError> {
+        // Handle packet type
+        match buf.ptype {
+            HidIoPacketType::Data => {
+                // Copy data into struct
+                let cmd = h0017::Cmd::<H> {
+                    string: match String::from_utf8(buf.data) {
+                        Ok(string) => string,
+                        Err(e) => {
+                            return Err(CommandError::InvalidUtf8(e));
+                        }
+                    },
+                };
+
+                match self.h0017_unicodetext_cmd(cmd) {
+                    Ok(_ack) => self.empty_ack(buf.id),
+                    Err(_nak) => self.empty_nak(buf.id),
+                }
+            }
+            HidIoPacketType::NaData => {
+                // Copy data into struct
+                let cmd = h0017::Cmd::<H> {
+                    string: match String::from_utf8(buf.data) {
+                        Ok(string) => string,
+                        Err(e) => {
+                            return Err(CommandError::InvalidUtf8(e));
+                        }
+                    },
+                };
+
+                self.h0017_unicodetext_nacmd(cmd)
+            }
+            HidIoPacketType::Ack => self.h0017_unicodetext_ack(h0017::Ack {}),
+            HidIoPacketType::Nak => self.h0017_unicodetext_nak(h0017::Nak {}),
+            _ => Ok(()),
+        }
+    }
+
+    fn h0018_unicodestate(&mut self, data: h0018::Cmd<H>, na: bool) -> Result<(), CommandError> {
+        // Create appropriately sized buffer
+        let mut buf = HidIoPacketBuffer {
+            // Test packet id
+            id: HidIoCommandId::UnicodeState,
+            // Detect max size
+            max_len: self.default_packet_chunk(),
+            // Use defaults for other fields
+            ..Default::default()
+        };
+
+        // Set NA (no-ack)
+        if na {
+            buf.ptype = HidIoPacketType::NaData;
+        }
+
+        // Build payload
+        if !buf.append_payload(data.symbols.as_bytes()) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+        buf.done = true;
+
+        self.tx_packetbuffer_send(&mut buf)
+    }
+    fn h0018_unicodestate_cmd(&mut self, _data: h0018::Cmd<H>) -> Result<h0018::Ack, h0018::Nak> {
+        Err(h0018::Nak {})
+    }
+    fn h0018_unicodestate_nacmd(&mut self, _data: h0018::Cmd<H>) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::UnicodeState,
+            HidIoPacketType::NaData,
+        ))
+    }
+    fn h0018_unicodestate_ack(&mut self, _data: h0018::Ack) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::UnicodeState,
+            HidIoPacketType::Ack,
+        ))
+    }
+    fn h0018_unicodestate_nak(&mut self, _data: h0018::Nak) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::UnicodeState,
+            HidIoPacketType::Nak,
+        ))
+    }
+    fn h0018_unicodestate_handler(
+        &mut self,
+        buf: HidIoPacketBuffer<H>,
+    ) -> Result<(), CommandError> {
+        // Handle packet type
+        match buf.ptype {
+            HidIoPacketType::Data => {
+                // Copy data into struct
+                let cmd = h0018::Cmd::<H> {
+                    symbols: match String::from_utf8(buf.data) {
+                        Ok(symbols) => symbols,
+                        Err(e) => {
+                            return Err(CommandError::InvalidUtf8(e));
+                        }
+                    },
+                };
+
+                match self.h0018_unicodestate_cmd(cmd) {
+                    Ok(_ack) => self.empty_ack(buf.id),
+                    Err(_nak) => self.empty_nak(buf.id),
+                }
+            }
+            HidIoPacketType::NaData => {
+                // Copy data into struct
+                let cmd = h0018::Cmd::<H> {
+                    symbols: match String::from_utf8(buf.data) {
+                        Ok(symbols) => symbols,
+                        Err(e) => {
+                            return Err(CommandError::InvalidUtf8(e));
+                        }
+                    },
+                };
+
+                self.h0018_unicodestate_nacmd(cmd)
+            }
+            HidIoPacketType::Ack => self.h0018_unicodestate_ack(h0018::Ack {}),
+            HidIoPacketType::Nak => self.h0018_unicodestate_nak(h0018::Nak {}),
+            _ => Ok(()),
+        }
+    }
+
+    fn h001a_sleepmode(&mut self, _data: h001a::Cmd) -> Result<(), CommandError> {
+        self.tx_packetbuffer_send(&mut HidIoPacketBuffer {
+            // Test packet id
+            id: HidIoCommandId::SleepMode,
+            // Detect max size
+            max_len: self.default_packet_chunk(),
+            // Ready
+            done: true,
+            // Use defaults for other fields
+            ..Default::default()
+        })
+    }
+    fn h001a_sleepmode_cmd(&mut self, _data: h001a::Cmd) -> Result<h001a::Ack, h001a::Nak> {
+        Err(h001a::Nak {
+            error: h001a::Error::NotSupported,
+        })
+    }
+    fn h001a_sleepmode_ack(&mut self, _data: h001a::Ack) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::FlashMode,
+            HidIoPacketType::Ack,
+        ))
+    }
+    fn h001a_sleepmode_nak(&mut self, _data: h001a::Nak) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::FlashMode,
+            HidIoPacketType::Nak,
+        ))
+    }
+    fn h001a_sleepmode_handler(&mut self, buf: HidIoPacketBuffer<H>) -> Result<(), CommandError> {
+        // Handle packet type
+        match buf.ptype {
+            HidIoPacketType::Data => match self.h001a_sleepmode_cmd(h001a::Cmd {}) {
+                Ok(_ack) => self.empty_ack(buf.id),
+                Err(nak) => self.byte_nak(buf.id, nak.error as u8),
+            },
+            HidIoPacketType::NaData => Err(CommandError::InvalidPacketBufferType(buf.ptype)),
+            HidIoPacketType::Ack => self.h001a_sleepmode_ack(h001a::Ack {}),
+            HidIoPacketType::Nak => {
+                if buf.data.len() < 1 {
+                    return Err(CommandError::DataVecNoData);
+                }
+
+                let error = match h001a::Error::try_from(buf.data[0]) {
+                    Ok(error) => error,
+                    Err(_) => {
+                        return Err(CommandError::InvalidProperty8(buf.data[0]));
+                    }
+                };
+                self.h001a_sleepmode_nak(h001a::Nak { error })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn h001b_settingsread(&mut self, data: h001b::Cmd) -> Result<(), CommandError> {
+        // Create appropriately sized buffer
+        let mut buf = HidIoPacketBuffer {
+            // Test packet id
+            id: HidIoCommandId::SettingsRead,
+            // Detect max size
+            max_len: self.default_packet_chunk(),
+            // Use defaults for other fields
+            ..Default::default()
+        };
+
+        // Build payload
+        if !buf.append_payload(&data.offset.to_le_bytes()) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+        if !buf.append_payload(&data.len.to_le_bytes()) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+
+        buf.done = true;
+
+        self.tx_packetbuffer_send(&mut buf)
+    }
+    fn h001b_settingsread_cmd(&mut self, _data: h001b::Cmd) -> Result<h001b::Ack<H>, h001b::Nak> {
+        Err(h001b::Nak {
+            error: h001b::Error::NotSupported,
+        })
+    }
+    fn h001b_settingsread_ack(&mut self, _data: h001b::Ack<H>) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::SettingsRead,
+            HidIoPacketType::Ack,
+        ))
+    }
+    fn h001b_settingsread_nak(&mut self, _data: h001b::Nak) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::SettingsRead,
+            HidIoPacketType::Nak,
+        ))
+    }
+    fn h001b_settingsread_handler(
+        &mut self,
+        buf: HidIoPacketBuffer<H>,
+    ) -> Result<(), CommandError> {
+        // Handle packet type
+        match buf.ptype {
+            HidIoPacketType::Data => {
+                if buf.data.len() < 6 {
+                    return Err(CommandError::DataVecNoData);
+                }
+
+                // Retrieve fields
+                let offset = u32::from_le_bytes(buf.data[0..4].try_into().unwrap());
+                let len = u16::from_le_bytes(buf.data[4..6].try_into().unwrap());
+
+                match self.h001b_settingsread_cmd(h001b::Cmd { offset, len }) {
+                    Ok(ack) => {
+                        // Build Ack
+                        let mut buf = HidIoPacketBuffer {
+                            // Data packet
+                            ptype: HidIoPacketType::Ack,
+                            // Packet id
+                            id: buf.id,
+                            // Detect max size
+                            max_len: self.default_packet_chunk(),
+                            ..Default::default()
+                        };
+
+                        // Copy data into buffer
+                        if !buf.append_payload(&ack.data) {
+                            return Err(CommandError::DataVecTooSmall);
+                        }
+                        buf.done = true;
+                        self.tx_packetbuffer_send(&mut buf)
+                    }
+                    Err(nak) => self.byte_nak(buf.id, nak.error as u8),
+                }
+            }
+            HidIoPacketType::NaData => Err(CommandError::InvalidPacketBufferType(buf.ptype)),
+            HidIoPacketType::Ack => {
+                // Copy data into struct
+                let ack = h001b::Ack::<H> {
+                    data: match Vec::from_slice(&buf.data) {
+                        Ok(data) => data,
+                        Err(_) => {
+                            return Err(CommandError::DataVecTooSmall);
+                        }
+                    },
+                };
+
+                self.h001b_settingsread_ack(ack)
+            }
+            HidIoPacketType::Nak => {
+                if buf.data.len() < 1 {
+                    return Err(CommandError::DataVecNoData);
+                }
+
+                let error = match h001b::Error::try_from(buf.data[0]) {
+                    Ok(error) => error,
+                    Err(_) => {
+                        return Err(CommandError::InvalidProperty8(buf.data[0]));
+                    }
+                };
+                self.h001b_settingsread_nak(h001b::Nak { error })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn h001c_settingswrite(&mut self, data: h001c::Cmd<H>) -> Result<(), CommandError> {
+        // Create appropriately sized buffer
+        let mut buf = HidIoPacketBuffer {
+            // Test packet id
+            id: HidIoCommandId::SettingsWrite,
+            // Detect max size
+            max_len: self.default_packet_chunk(),
+            // Use defaults for other fields
+            ..Default::default()
+        };
+
+        // Build payload
+        if !buf.append_payload(&data.offset.to_le_bytes()) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+        if !buf.append_payload(&data.data) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+
+        buf.done = true;
+
+        self.tx_packetbuffer_send(&mut buf)
+    }
+    fn h001c_settingswrite_cmd(&mut self, _data: h001c::Cmd<H>) -> Result<h001c::Ack, h001c::Nak> {
+        Err(h001c::Nak {
+            error: h001c::Error::NotSupported,
+        })
+    }
+    fn h001c_settingswrite_ack(&mut self, _data: h001c::Ack) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::SettingsWrite,
+            HidIoPacketType::Ack,
+        ))
+    }
+    fn h001c_settingswrite_nak(&mut self, _data: h001c::Nak) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::SettingsWrite,
+            HidIoPacketType::Nak,
+        ))
+    }
+    fn h001c_settingswrite_handler(
+        &mut self,
+        buf: HidIoPacketBuffer<H>,
+    ) -> Result<(), CommandError> {
+        // Handle packet type
+        match buf.ptype {
+            HidIoPacketType::Data => {
+                if buf.data.len() < 4 {
+                    return Err(CommandError::DataVecNoData);
+                }
+
+                // Retrieve fields
+                let offset = u32::from_le_bytes(buf.data[0..4].try_into().unwrap());
+                let data: Vec<u8, H> = Vec::from_slice(&buf.data[4..]).unwrap();
+
+                match self.h001c_settingswrite_cmd(h001c::Cmd { offset, data }) {
+                    Ok(_ack) => self.empty_ack(buf.id),
+                    Err(nak) => self.byte_nak(buf.id, nak.error as u8),
+                }
+            }
+            HidIoPacketType::NaData => Err(CommandError::InvalidPacketBufferType(buf.ptype)),
+            HidIoPacketType::Ack => self.h001c_settingswrite_ack(h001c::Ack {}),
+            HidIoPacketType::Nak => {
+                if buf.data.len() < 1 {
+                    return Err(CommandError::DataVecNoData);
+                }
+
+                let error = match h001c::Error::try_from(buf.data[0]) {
+                    Ok(error) => error,
+                    Err(_) => {
+                        return Err(CommandError::InvalidProperty8(buf.data[0]));
+                    }
+                };
+                self.h001c_settingswrite_nak(h001c::Nak { error })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn h001d_klllayoutread(&mut self, data: h001d::Cmd) -> Result<(), CommandError> {
+        // Create appropriately sized buffer
+        let mut buf = HidIoPacketBuffer {
+            // Test packet id
+            id: HidIoCommandId::KllLayoutRead,
+            // Detect max size
+            max_len: self.default_packet_chunk(),
+            // Use defaults for other fields
+            ..Default::default()
+        };
+
+        // Build payload
+        if !buf.append_payload(&data.offset.to_le_bytes()) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+        if !buf.append_payload(&data.len.to_le_bytes()) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+
+        buf.done = true;
+
+        self.tx_packetbuffer_send(&mut buf)
+    }
+    fn h001d_klllayoutread_cmd(&mut self, _data: h001d::Cmd) -> Result<h001d::Ack<H>, h001d::Nak> {
+        Err(h001d::Nak {
+            error: h001d::Error::NotSupported,
+        })
+    }
+    fn h001d_klllayoutread_ack(&mut self, _data: h001d::Ack<H>) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::KllLayoutRead,
+            HidIoPacketType::Ack,
+        ))
+    }
+    fn h001d_klllayoutread_nak(&mut self, _data: h001d::Nak) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::KllLayoutRead,
+            HidIoPacketType::Nak,
+        ))
+    }
+    fn h001d_klllayoutread_handler(
+        &mut self,
+        buf: HidIoPacketBuffer<H>,
+    ) -> Result<(), CommandError> {
+        // Handle packet type
+        match buf.ptype {
+            HidIoPacketType::Data => {
+                if buf.data.len() < 6 {
+                    return Err(CommandError::DataVecNoData);
+                }
+
+                // Retrieve fields
+                let offset = u32::from_le_bytes(buf.data[0..4].try_into().unwrap());
+                let len = u16::from_le_bytes(buf.data[4..6].try_into().unwrap());
+
+                match self.h001d_klllayoutread_cmd(h001d::Cmd { offset, len }) {
+                    Ok(ack) => {
+                        // Build Ack
+                        let mut buf = HidIoPacketBuffer {
+                            // Data packet
+                            ptype: HidIoPacketType::Ack,
+                            // Packet id
+                            id: buf.id,
+                            // Detect max size
+                            max_len: self.default_packet_chunk(),
+                            ..Default::default()
+                        };
+
+                        // Copy data into buffer
+                        if !buf.append_payload(&ack.size.to_le_bytes()) {
+                            return Err(CommandError::DataVecTooSmall);
+                        }
+                        if !buf.append_payload(&ack.data) {
+                            return Err(CommandError::DataVecTooSmall);
+                        }
+                        buf.done = true;
+                        self.tx_packetbuffer_send(&mut buf)
+                    }
+                    Err(nak) => self.byte_nak(buf.id, nak.error as u8),
+                }
+            }
+            HidIoPacketType::NaData => Err(CommandError::InvalidPacketBufferType(buf.ptype)),
+            HidIoPacketType::Ack => {
+                if buf.data.len() < 4 {
+                    return Err(CommandError::DataVecNoData);
+                }
+
+                // Copy data into struct
+                let ack = h001d::Ack::<H> {
+                    size: u32::from_le_bytes(buf.data[0..4].try_into().unwrap()),
+                    data: match Vec::from_slice(&buf.data[4..]) {
+                        Ok(data) => data,
+                        Err(_) => {
+                            return Err(CommandError::DataVecTooSmall);
+                        }
+                    },
+                };
+
+                self.h001d_klllayoutread_ack(ack)
+            }
+            HidIoPacketType::Nak => {
+                if buf.data.len() < 1 {
+                    return Err(CommandError::DataVecNoData);
+                }
+
+                let error = match h001d::Error::try_from(buf.data[0]) {
+                    Ok(error) => error,
+                    Err(_) => {
+                        return Err(CommandError::InvalidProperty8(buf.data[0]));
+                    }
+                };
+                self.h001d_klllayoutread_nak(h001d::Nak { error })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn h001e_klllayoutwrite(&mut self, data: h001e::Cmd<H>) -> Result<(), CommandError> {
+        // Create appropriately sized buffer
+        let mut buf = HidIoPacketBuffer {
+            // Test packet id
+            id: HidIoCommandId::KllLayoutWrite,
+            // Detect max size
+            max_len: self.default_packet_chunk(),
+            // Use defaults for other fields
+            ..Default::default()
+        };
+
+        // Build payload
+        if !buf.append_payload(&data.offset.to_le_bytes()) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+        if !buf.append_payload(&data.crc.to_le_bytes()) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+        if !buf.append_payload(&data.data) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+
+        buf.done = true;
+
+        self.tx_packetbuffer_send(&mut buf)
+    }
+    fn h001e_klllayoutwrite_cmd(&mut self, _data: h001e::Cmd<H>) -> Result<h001e::Ack, h001e::Nak> {
+        Err(h001e::Nak {
+            error: h001e::Error::NotStarted,
+        })
+    }
+    fn h001e_klllayoutwrite_ack(&mut self, _data: h001e::Ack) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::KllLayoutWrite,
+            HidIoPacketType::Ack,
+        ))
+    }
+    fn h001e_klllayoutwrite_nak(&mut self, _data: h001e::Nak) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::KllLayoutWrite,
+            HidIoPacketType::Nak,
+        ))
+    }
+    fn h001e_klllayoutwrite_handler(
+        &mut self,
+        buf: HidIoPacketBuffer<H>,
+    ) -> Result<(), CommandError> {
+        // Handle packet type
+        match buf.ptype {
+            HidIoPacketType::Data => {
+                if buf.data.len() < 6 {
+                    return Err(CommandError::DataVecNoData);
+                }
+
+                // Retrieve fields
+                let offset = u32::from_le_bytes(buf.data[0..4].try_into().unwrap());
+                let crc = u16::from_le_bytes(buf.data[4..6].try_into().unwrap());
+                let data: Vec<u8, H> = Vec::from_slice(&buf.data[6..]).unwrap();
+
+                // Validate chunk before handing it off
+                if crate::crc16(&data) != crc {
+                    return self.byte_nak(buf.id, h001e::Error::CrcMismatch as u8);
+                }
+
+                match self.h001e_klllayoutwrite_cmd(h001e::Cmd { offset, crc, data }) {
+                    Ok(_ack) => self.empty_ack(buf.id),
+                    Err(nak) => self.byte_nak(buf.id, nak.error as u8),
+                }
+            }
+            HidIoPacketType::NaData => Err(CommandError::InvalidPacketBufferType(buf.ptype)),
+            HidIoPacketType::Ack => self.h001e_klllayoutwrite_ack(h001e::Ack {}),
+            HidIoPacketType::Nak => {
+                if buf.data.len() < 1 {
+                    return Err(CommandError::DataVecNoData);
+                }
+
+                let error = match h001e::Error::try_from(buf.data[0]) {
+                    Ok(error) => error,
+                    Err(_) => {
+                        return Err(CommandError::InvalidProperty8(buf.data[0]));
+                    }
+                };
+                self.h001e_klllayoutwrite_nak(h001e::Nak { error })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn h001f_klllayoutcontrol(&mut self, data: h001f::Cmd) -> Result<(), CommandError> {
+        // Create appropriately sized buffer
+        let mut buf = HidIoPacketBuffer {
+            // Test packet id
+            id: HidIoCommandId::KllLayoutControl,
+            // Detect max size
+            max_len: self.default_packet_chunk(),
+            // Use defaults for other fields
+            ..Default::default()
+        };
+
+        // Build payload
+        if !buf.append_payload(&[data.action as u8]) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+        if !buf.append_payload(&data.arg.to_le_bytes()) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+
+        buf.done = true;
+
+        self.tx_packetbuffer_send(&mut buf)
+    }
+    fn h001f_klllayoutcontrol_cmd(&mut self, _data: h001f::Cmd) -> Result<h001f::Ack, h001f::Nak> {
+        Err(h001f::Nak {
+            error: h001f::Error::NotSupported,
+        })
+    }
+    fn h001f_klllayoutcontrol_ack(&mut self, _data: h001f::Ack) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::KllLayoutControl,
+            HidIoPacketType::Ack,
+        ))
+    }
+    fn h001f_klllayoutcontrol_nak(&mut self, _data: h001f::Nak) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::KllLayoutControl,
+            HidIoPacketType::Nak,
+        ))
+    }
+    fn h001f_klllayoutcontrol_handler(
+        &mut self,
+        buf: HidIoPacketBuffer<H>,
+    ) -> Result<(), CommandError> {
+        // Handle packet type
+        match buf.ptype {
+            HidIoPacketType::Data => {
+                if buf.data.len() < 5 {
+                    return Err(CommandError::DataVecNoData);
+                }
+
+                // Retrieve fields
+                let action = match h001f::Action::try_from(buf.data[0]) {
+                    Ok(action) => action,
+                    Err(_) => {
+                        return Err(CommandError::InvalidProperty8(buf.data[0]));
+                    }
+                };
+                let arg = u32::from_le_bytes(buf.data[1..5].try_into().unwrap());
+
+                match self.h001f_klllayoutcontrol_cmd(h001f::Cmd { action, arg }) {
+                    Ok(_ack) => self.empty_ack(buf.id),
+                    Err(nak) => self.byte_nak(buf.id, nak.error as u8),
+                }
+            }
+            HidIoPacketType::NaData => Err(CommandError::InvalidPacketBufferType(buf.ptype)),
+            HidIoPacketType::Ack => self.h001f_klllayoutcontrol_ack(h001f::Ack {}),
+            HidIoPacketType::Nak => {
+                if buf.data.len() < 1 {
+                    return Err(CommandError::DataVecNoData);
+                }
+
+                let error = match h001f::Error::try_from(buf.data[0]) {
+                    Ok(error) => error,
+                    Err(_) => {
+                        return Err(CommandError::InvalidProperty8(buf.data[0]));
+                    }
+                };
+                self.h001f_klllayoutcontrol_nak(h001f::Nak { error })
+            }
+            _ => Ok(()),
+        }
     }
 
-    /// Simple empty ack
-    fn empty_ack(&mut self, id: HidIoCommandId) -> Result<(), CommandError> {
-        // Build empty Ack
-        self.tx_packetbuffer_send(&mut HidIoPacketBuffer {
-            // Data packet
-            ptype: HidIoPacketType::Ack,
-            // Packet id
-            id,
+    fn h0029_displaytext(&mut self, data: h0029::Cmd<H>) -> Result<(), CommandError> {
+        // Create appropriately sized buffer
+        let mut buf = HidIoPacketBuffer {
+            // Test packet id
+            id: HidIoCommandId::DisplayText,
             // Detect max size
             max_len: self.default_packet_chunk(),
-            // Ready to go
-            done: true,
             // Use defaults for other fields
             ..Default::default()
+        };
+
+        // Build payload
+        if !buf.append_payload(&data.widget.to_le_bytes()) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+        if !buf.append_payload(data.text.as_bytes()) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+
+        buf.done = true;
+
+        self.tx_packetbuffer_send(&mut buf)
+    }
+    fn h0029_displaytext_cmd(&mut self, _data: h0029::Cmd<H>) -> Result<h0029::Ack, h0029::Nak> {
+        Err(h0029::Nak {
+            error: h0029::Error::NotSupported,
         })
     }
+    fn h0029_displaytext_ack(&mut self, _data: h0029::Ack) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::DisplayText,
+            HidIoPacketType::Ack,
+        ))
+    }
+    fn h0029_displaytext_nak(&mut self, _data: h0029::Nak) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::DisplayText,
+            HidIoPacketType::Nak,
+        ))
+    }
+    fn h0029_displaytext_handler(&mut self, buf: HidIoPacketBuffer<H>) -> Result<(), CommandError> {
+        // Handle packet type
+        match buf.ptype {
+            HidIoPacketType::Data => {
+                if buf.data.len() < 2 {
+                    return Err(CommandError::DataVecNoData);
+                }
 
-    /// Simple empty nak
-    fn empty_nak(&mut self, id: HidIoCommandId) -> Result<(), CommandError> {
-        // Build empty Nak
-        self.tx_packetbuffer_send(&mut HidIoPacketBuffer {
-            // Data packet
-            ptype: HidIoPacketType::Nak,
-            // Packet id
-            id,
+                // Retrieve fields
+                let widget = u16::from_le_bytes(buf.data[0..2].try_into().unwrap());
+                let text = match String::from_utf8(Vec::from_slice(&buf.data[2..]).unwrap()) {
+                    Ok(text) => text,
+                    Err(e) => {
+                        return Err(CommandError::InvalidUtf8(e));
+                    }
+                };
+
+                match self.h0029_displaytext_cmd(h0029::Cmd { widget, text }) {
+                    Ok(_ack) => self.empty_ack(buf.id),
+                    Err(nak) => self.byte_nak(buf.id, nak.error as u8),
+                }
+            }
+            HidIoPacketType::NaData => Err(CommandError::InvalidPacketBufferType(buf.ptype)),
+            HidIoPacketType::Ack => self.h0029_displaytext_ack(h0029::Ack {}),
+            HidIoPacketType::Nak => {
+                if buf.data.len() < 1 {
+                    return Err(CommandError::DataVecNoData);
+                }
+
+                let error = match h0029::Error::try_from(buf.data[0]) {
+                    Ok(error) => error,
+                    Err(_) => {
+                        return Err(CommandError::InvalidProperty8(buf.data[0]));
+                    }
+                };
+                self.h0029_displaytext_nak(h0029::Nak { error })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn h002a_confirmrequest(&mut self, data: h002a::Cmd<H>) -> Result<(), CommandError> {
+        // Create appropriately sized buffer
+        let mut buf = HidIoPacketBuffer {
+            // Test packet id
+            id: HidIoCommandId::ConfirmRequest,
             // Detect max size
             max_len: self.default_packet_chunk(),
-            // Ready to go
-            done: true,
             // Use defaults for other fields
             ..Default::default()
-        })
-    }
+        };
 
-    /// Simple byte ack
-    fn byte_ack(&mut self, id: HidIoCommandId, byte: u8) -> Result<(), CommandError> {
-        // Build Ack
-        self.tx_packetbuffer_send(&mut HidIoPacketBuffer {
-            // Data packet
-            ptype: HidIoPacketType::Ack,
-            // Packet id
-            id,
-            // Detect max size
-            max_len: self.default_packet_chunk(),
-            // Byte payload
-            data: Vec::from_slice(&[byte]).unwrap(),
-            // Ready to go
-            done: true,
-        })
-    }
+        // Build payload
+        if !buf.append_payload(&data.request.to_le_bytes()) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+        if !buf.append_payload(&[data.timeout]) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+        if !buf.append_payload(data.prompt.as_bytes()) {
+            return Err(CommandError::DataVecTooSmall);
+        }
 
-    /// Simple byte nak
-    fn byte_nak(&mut self, id: HidIoCommandId, byte: u8) -> Result<(), CommandError> {
-        // Build Nak
-        self.tx_packetbuffer_send(&mut HidIoPacketBuffer {
-            // Data packet
-            ptype: HidIoPacketType::Nak,
-            // Packet id
-            id,
-            // Detect max size
-            max_len: self.default_packet_chunk(),
-            // Byte payload
-            data: Vec::from_slice(&[byte]).unwrap(),
-            // Ready to go
-            done: true,
+        buf.done = true;
+
+        self.tx_packetbuffer_send(&mut buf)
+    }
+    fn h002a_confirmrequest_cmd(&mut self, _data: h002a::Cmd<H>) -> Result<h002a::Ack, h002a::Nak> {
+        Err(h002a::Nak {
+            error: h002a::Error::NotSupported,
         })
     }
+    fn h002a_confirmrequest_ack(&mut self, _data: h002a::Ack) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::ConfirmRequest,
+            HidIoPacketType::Ack,
+        ))
+    }
+    fn h002a_confirmrequest_nak(&mut self, _data: h002a::Nak) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::ConfirmRequest,
+            HidIoPacketType::Nak,
+        ))
+    }
+    fn h002a_confirmrequest_handler(
+        &mut self,
+        buf: HidIoPacketBuffer<H>,
+    ) -> Result<(), CommandError> {
+        // Handle packet type
+        match buf.ptype {
+            HidIoPacketType::Data => {
+                if buf.data.len() < 3 {
+                    return Err(CommandError::DataVecNoData);
+                }
 
-    /// Simple short ack (16-bit)
-    fn short_ack(&mut self, id: HidIoCommandId, val: u16) -> Result<(), CommandError> {
-        // Build Ack
-        self.tx_packetbuffer_send(&mut HidIoPacketBuffer {
-            // Data packet
-            ptype: HidIoPacketType::Ack,
-            // Packet id
-            id,
-            // Detect max size
-            max_len: self.default_packet_chunk(),
-            // Byte payload
-            data: Vec::from_slice(&val.to_le_bytes()).unwrap(),
-            // Ready to go
-            done: true,
-        })
+                // Retrieve fields
+                let request = u16::from_le_bytes(buf.data[0..2].try_into().unwrap());
+                let timeout = buf.data[2];
+                let prompt = match String::from_utf8(Vec::from_slice(&buf.data[3..]).unwrap()) {
+                    Ok(prompt) => prompt,
+                    Err(e) => {
+                        return Err(CommandError::InvalidUtf8(e));
+                    }
+                };
+
+                match self.h002a_confirmrequest_cmd(h002a::Cmd {
+                    request,
+                    timeout,
+                    prompt,
+                }) {
+                    Ok(_ack) => self.empty_ack(buf.id),
+                    Err(nak) => self.byte_nak(buf.id, nak.error as u8),
+                }
+            }
+            HidIoPacketType::NaData => Err(CommandError::InvalidPacketBufferType(buf.ptype)),
+            HidIoPacketType::Ack => self.h002a_confirmrequest_ack(h002a::Ack {}),
+            HidIoPacketType::Nak => {
+                if buf.data.len() < 1 {
+                    return Err(CommandError::DataVecNoData);
+                }
+
+                let error = match h002a::Error::try_from(buf.data[0]) {
+                    Ok(error) => error,
+                    Err(_) => {
+                        return Err(CommandError::InvalidProperty8(buf.data[0]));
+                    }
+                };
+                self.h002a_confirmrequest_nak(h002a::Nak { error })
+            }
+            _ => Ok(()),
+        }
     }
 
-    /// Simple short nak (16-bit)
-    fn short_nak(&mut self, id: HidIoCommandId, val: u16) -> Result<(), CommandError> {
-        // Build Nak
-        self.tx_packetbuffer_send(&mut HidIoPacketBuffer {
-            // Data packet
-            ptype: HidIoPacketType::Nak,
-            // Packet id
-            id,
+    fn h002b_confirmresponse(&mut self, data: h002b::Cmd) -> Result<(), CommandError> {
+        // Create appropriately sized buffer
+        let mut buf = HidIoPacketBuffer {
+            // Test packet id
+            id: HidIoCommandId::ConfirmResponse,
             // Detect max size
             max_len: self.default_packet_chunk(),
-            // Byte payload
-            data: Vec::from_slice(&val.to_le_bytes()).unwrap(),
-            // Ready to go
-            done: true,
-        })
-    }
+            // Use defaults for other fields
+            ..Default::default()
+        };
 
-    /// Process specific packet types
-    /// Handles matching to interface functions
-    fn rx_message_handling(&mut self, buf: HidIoPacketBuffer<H>) -> Result<(), CommandError>
-    where
-        <H as Sub<B1>>::Output: ArrayLength<u8>,
-        <H as Sub<U4>>::Output: ArrayLength<u8>,
-    {
-        // Make sure we're processing a supported id
-        if !self.supported_id(buf.id) {
-            return Err(CommandError::IdNotSupported(buf.id));
+        // Build payload
+        if !buf.append_payload(&data.request.to_le_bytes()) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+        if !buf.append_payload(&[data.answer as u8]) {
+            return Err(CommandError::DataVecTooSmall);
         }
 
-        // Check for invalid packet types
+        buf.done = true;
+
+        self.tx_packetbuffer_send(&mut buf)
+    }
+    fn h002b_confirmresponse_cmd(&mut self, _data: h002b::Cmd) -> Result<h002b::Ack, h002b::Nak> {
+        Err(h002b::Nak {
+            error: h002b::Error::UnknownRequest,
+        })
+    }
+    fn h002b_confirmresponse_ack(&mut self, _data: h002b::Ack) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::ConfirmResponse,
+            HidIoPacketType::Ack,
+        ))
+    }
+    fn h002b_confirmresponse_nak(&mut self, _data: h002b::Nak) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::ConfirmResponse,
+            HidIoPacketType::Nak,
+        ))
+    }
+    fn h002b_confirmresponse_handler(
+        &mut self,
+        buf: HidIoPacketBuffer<H>,
+    ) -> Result<(), CommandError> {
+        // Handle packet type
         match buf.ptype {
-            HidIoPacketType::Data | HidIoPacketType::NaData => {}
-            HidIoPacketType::Ack => {}
-            HidIoPacketType::Nak => {}
-            _ => {
-                return Err(CommandError::InvalidRxMessage(buf.ptype));
+            HidIoPacketType::Data => {
+                if buf.data.len() < 3 {
+                    return Err(CommandError::DataVecNoData);
+                }
+
+                // Retrieve fields
+                let request = u16::from_le_bytes(buf.data[0..2].try_into().unwrap());
+                let answer = match h002b::Answer::try_from(buf.data[2]) {
+                    Ok(answer) => answer,
+                    Err(_) => {
+                        return Err(CommandError::InvalidProperty8(buf.data[2]));
+                    }
+                };
+
+                match self.h002b_confirmresponse_cmd(h002b::Cmd { request, answer }) {
+                    Ok(_ack) => self.empty_ack(buf.id),
+                    Err(nak) => self.byte_nak(buf.id, nak.error as u8),
+                }
             }
-        }
+            HidIoPacketType::NaData => Err(CommandError::InvalidPacketBufferType(buf.ptype)),
+            HidIoPacketType::Ack => self.h002b_confirmresponse_ack(h002b::Ack {}),
+            HidIoPacketType::Nak => {
+                if buf.data.len() < 1 {
+                    return Err(CommandError::DataVecNoData);
+                }
 
-        // Match id
-        match buf.id {
-            HidIoCommandId::SupportedIds => self.h0000_supported_ids_handler(buf),
-            HidIoCommandId::GetInfo => self.h0001_info_handler(buf),
-            HidIoCommandId::TestPacket => self.h0002_test_handler(buf),
-            HidIoCommandId::ResetHidIo => self.h0003_resethidio_handler(buf),
-            HidIoCommandId::FlashMode => self.h0016_flashmode_handler(buf),
-            HidIoCommandId::UnicodeText => self.h0017_unicodetext_handler(buf),
-            HidIoCommandId::UnicodeState => self.h0018_unicodestate_handler(buf),
-            HidIoCommandId::SleepMode => self.h001a_sleepmode_handler(buf),
-            HidIoCommandId::TerminalCmd => self.h0031_terminalcmd_handler(buf),
-            HidIoCommandId::TerminalOut => self.h0034_terminalout_handler(buf),
-            HidIoCommandId::ManufacturingTest => self.h0050_manufacturing_handler(buf),
-            HidIoCommandId::ManufacturingResult => self.h0051_manufacturingres_handler(buf),
-            _ => Err(CommandError::IdNotMatched(buf.id)),
+                let error = match h002b::Error::try_from(buf.data[0]) {
+                    Ok(error) => error,
+                    Err(_) => {
+                        return Err(CommandError::InvalidProperty8(buf.data[0]));
+                    }
+                };
+                self.h002b_confirmresponse_nak(h002b::Nak { error })
+            }
+            _ => Ok(()),
         }
     }
 
-    fn h0000_supported_ids(&mut self, _data: h0000::Cmd) -> Result<(), CommandError> {
-        self.tx_packetbuffer_send(&mut HidIoPacketBuffer {
+    fn h002c_animationbegin(&mut self, data: h002c::Cmd) -> Result<(), CommandError> {
+        // Create appropriately sized buffer
+        let mut buf = HidIoPacketBuffer {
             // Test packet id
-            id: HidIoCommandId::SupportedIds,
+            id: HidIoCommandId::AnimationBegin,
             // Detect max size
             max_len: self.default_packet_chunk(),
-            // Ready to go
-            done: true,
             // Use defaults for other fields
             ..Default::default()
-        })
+        };
+
+        // Build payload
+        if !buf.append_payload(&data.slot.to_le_bytes()) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+        if !buf.append_payload(&[data.format as u8]) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+        if !buf.append_payload(&data.frames.to_le_bytes()) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+        if !buf.append_payload(&data.frame_len.to_le_bytes()) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+
+        buf.done = true;
+
+        self.tx_packetbuffer_send(&mut buf)
     }
-    fn h0000_supported_ids_cmd(&mut self, _data: h0000::Cmd) -> Result<h0000::Ack<ID>, h0000::Nak> {
-        Err(h0000::Nak {})
+    fn h002c_animationbegin_cmd(&mut self, _data: h002c::Cmd) -> Result<h002c::Ack, h002c::Nak> {
+        Err(h002c::Nak {
+            error: h002c::Error::NotSupported,
+        })
     }
-    fn h0000_supported_ids_ack(&mut self, _data: h0000::Ack<ID>) -> Result<(), CommandError> {
+    fn h002c_animationbegin_ack(&mut self, _data: h002c::Ack) -> Result<(), CommandError> {
         Err(CommandError::IdNotImplemented(
-            HidIoCommandId::SupportedIds,
+            HidIoCommandId::AnimationBegin,
             HidIoPacketType::Ack,
         ))
     }
-    fn h0000_supported_ids_nak(&mut self, _data: h0000::Nak) -> Result<(), CommandError> {
+    fn h002c_animationbegin_nak(&mut self, _data: h002c::Nak) -> Result<(), CommandError> {
         Err(CommandError::IdNotImplemented(
-            HidIoCommandId::SupportedIds,
+            HidIoCommandId::AnimationBegin,
             HidIoPacketType::Nak,
         ))
     }
-    fn h0000_supported_ids_handler(
+    fn h002c_animationbegin_handler(
         &mut self,
         buf: HidIoPacketBuffer<H>,
     ) -> Result<(), CommandError> {
         // Handle packet type
         match buf.ptype {
             HidIoPacketType::Data => {
-                match self.h0000_supported_ids_cmd(h0000::Cmd {}) {
-                    Ok(ack) => {
-                        // Build Ack
-                        let mut buf = HidIoPacketBuffer {
-                            // Data packet
-                            ptype: HidIoPacketType::Ack,
-                            // Packet id
-                            id: buf.id,
-                            // Detect max size
-                            max_len: self.default_packet_chunk(),
-                            // Ready to go
-                            done: true,
-                            // Use defaults for other fields
-                            ..Default::default()
-                        };
+                if buf.data.len() < 7 {
+                    return Err(CommandError::DataVecNoData);
+                }
 
-                        // Build list of ids
-                        for id in ack.ids {
-                            if buf
-                                .data
-                                .extend_from_slice(&(id as u16).to_le_bytes())
-                                .is_err()
-                            {
-                                return Err(CommandError::IdVecTooSmall);
-                            }
-                        }
-                        self.tx_packetbuffer_send(&mut buf)
+                // Retrieve fields
+                let slot = u16::from_le_bytes(buf.data[0..2].try_into().unwrap());
+                let format = match h002c::Format::try_from(buf.data[2]) {
+                    Ok(format) => format,
+                    Err(_) => {
+                        return self.byte_nak(buf.id, h002c::Error::InvalidFormat as u8);
                     }
-                    Err(_nak) => self.empty_nak(buf.id),
+                };
+                let frames = u16::from_le_bytes(buf.data[3..5].try_into().unwrap());
+                let frame_len = u16::from_le_bytes(buf.data[5..7].try_into().unwrap());
+
+                match self.h002c_animationbegin_cmd(h002c::Cmd {
+                    slot,
+                    format,
+                    frames,
+                    frame_len,
+                }) {
+                    Ok(_ack) => self.empty_ack(buf.id),
+                    Err(nak) => self.byte_nak(buf.id, nak.error as u8),
                 }
             }
             HidIoPacketType::NaData => Err(CommandError::InvalidPacketBufferType(buf.ptype)),
-            HidIoPacketType::Ack => {
-                // Retrieve list of ids
-                let mut ids: Vec<HidIoCommandId, ID> = Vec::new();
-                // Ids are always 16-bit le for this command
-                let mut pos = 0;
-                while pos <= buf.data.len() - 2 {
-                    let slice = &buf.data[pos..pos + 2];
-                    let idnum = u16::from_le_bytes(slice.try_into().unwrap()) as u32;
-                    // Make sure this is a valid id
-                    let id = match HidIoCommandId::try_from(idnum) {
-                        Ok(id) => id,
-                        Err(_) => {
-                            return Err(CommandError::InvalidId(idnum));
-                        }
-                    };
-                    // Attempt to push to id list
-                    // NOTE: If the vector is not large enough
-                    //       just truncate.
-                    //       This command won't be called by devices
-                    //       often.
-                    // TODO: Add optional fields to request a range
-                    if ids.push(id).is_err() {
-                        break;
-                    }
-                    pos += 2;
+            HidIoPacketType::Ack => self.h002c_animationbegin_ack(h002c::Ack {}),
+            HidIoPacketType::Nak => {
+                if buf.data.len() < 1 {
+                    return Err(CommandError::DataVecNoData);
                 }
-                self.h0000_supported_ids_ack(h0000::Ack { ids })
+
+                let error = match h002c::Error::try_from(buf.data[0]) {
+                    Ok(error) => error,
+                    Err(_) => {
+                        return Err(CommandError::InvalidProperty8(buf.data[0]));
+                    }
+                };
+                self.h002c_animationbegin_nak(h002c::Nak { error })
             }
-            HidIoPacketType::Nak => self.h0000_supported_ids_nak(h0000::Nak {}),
             _ => Ok(()),
         }
     }
 
-    fn h0001_info(&mut self, data: h0001::Cmd) -> Result<(), CommandError> {
+    fn h002d_animationdata(&mut self, data: h002d::Cmd<H>) -> Result<(), CommandError> {
         // Create appropriately sized buffer
         let mut buf = HidIoPacketBuffer {
             // Test packet id
-            id: HidIoCommandId::GetInfo,
+            id: HidIoCommandId::AnimationData,
             // Detect max size
             max_len: self.default_packet_chunk(),
-            // Ready to go
-            done: true,
             // Use defaults for other fields
             ..Default::default()
         };
 
-        // Encode property
-        if buf.data.push(data.property as u8).is_err() {
+        // Build payload
+        if !buf.append_payload(&data.offset.to_le_bytes()) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+        if !buf.append_payload(&data.crc.to_le_bytes()) {
             return Err(CommandError::DataVecTooSmall);
         }
+        if !buf.append_payload(&data.data) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+
+        buf.done = true;
 
         self.tx_packetbuffer_send(&mut buf)
     }
-    fn h0001_info_cmd(&mut self, _data: h0001::Cmd) -> Result<h0001::Ack<Sub1<H>>, h0001::Nak>
-    where
-        <H as Sub<B1>>::Output: ArrayLength<u8>,
-    {
-        Err(h0001::Nak {
-            property: h0001::Property::Unknown,
+    fn h002d_animationdata_cmd(&mut self, _data: h002d::Cmd<H>) -> Result<h002d::Ack, h002d::Nak> {
+        Err(h002d::Nak {
+            error: h002d::Error::NotStarted,
         })
     }
-    fn h0001_info_ack(&mut self, _data: h0001::Ack<Sub1<H>>) -> Result<(), CommandError>
-    where
-        <H as Sub<B1>>::Output: ArrayLength<u8>,
-    {
+    fn h002d_animationdata_ack(&mut self, _data: h002d::Ack) -> Result<(), CommandError> {
         Err(CommandError::IdNotImplemented(
-            HidIoCommandId::GetInfo,
+            HidIoCommandId::AnimationData,
             HidIoPacketType::Ack,
         ))
     }
-    fn h0001_info_nak(&mut self, _data: h0001::Nak) -> Result<(), CommandError> {
+    fn h002d_animationdata_nak(&mut self, _data: h002d::Nak) -> Result<(), CommandError> {
         Err(CommandError::IdNotImplemented(
-            HidIoCommandId::GetInfo,
+            HidIoCommandId::AnimationData,
             HidIoPacketType::Nak,
         ))
     }
-    fn h0001_info_handler(&mut self, buf: HidIoPacketBuffer<H>) -> Result<(), CommandError>
-    where
-        <H as Sub<B1>>::Output: ArrayLength<u8>,
-    {
+    fn h002d_animationdata_handler(
+        &mut self,
+        buf: HidIoPacketBuffer<H>,
+    ) -> Result<(), CommandError> {
         // Handle packet type
         match buf.ptype {
             HidIoPacketType::Data => {
+                if buf.data.len() < 6 {
+                    return Err(CommandError::DataVecNoData);
+                }
+
+                // Retrieve fields
+                let offset = u32::from_le_bytes(buf.data[0..4].try_into().unwrap());
+                let crc = u16::from_le_bytes(buf.data[4..6].try_into().unwrap());
+                let data: Vec<u8, H> = Vec::from_slice(&buf.data[6..]).unwrap();
+
+                // Validate chunk before handing it off
+                if crate::crc16(&data) != crc {
+                    return self.byte_nak(buf.id, h002d::Error::CrcMismatch as u8);
+                }
+
+                match self.h002d_animationdata_cmd(h002d::Cmd { offset, crc, data }) {
+                    Ok(_ack) => self.empty_ack(buf.id),
+                    Err(nak) => self.byte_nak(buf.id, nak.error as u8),
+                }
+            }
+            HidIoPacketType::NaData => Err(CommandError::InvalidPacketBufferType(buf.ptype)),
+            HidIoPacketType::Ack => self.h002d_animationdata_ack(h002d::Ack {}),
+            HidIoPacketType::Nak => {
                 if buf.data.len() < 1 {
                     return Err(CommandError::DataVecNoData);
                 }
-                // Attempt to read first byte
-                let property = match h0001::Property::try_from(buf.data[0]) {
-                    Ok(property) => property,
+
+                let error = match h002d::Error::try_from(buf.data[0]) {
+                    Ok(error) => error,
                     Err(_) => {
                         return Err(CommandError::InvalidProperty8(buf.data[0]));
                     }
                 };
-                match self.h0001_info_cmd(h0001::Cmd { property }) {
-                    Ok(ack) => {
-                        // Build Ack
-                        let mut buf = HidIoPacketBuffer {
-                            // Data packet
-                            ptype: HidIoPacketType::Ack,
-                            // Packet id
-                            id: buf.id,
-                            // Detect max size
-                            max_len: self.default_packet_chunk(),
-                            // Ready to go
-                            done: true,
-                            // Use defaults for other fields
-                            ..Default::default()
-                        };
-
-                        // Set property
-                        if buf.data.push(ack.property as u8).is_err() {
-                            return Err(CommandError::DataVecTooSmall);
-                        }
+                self.h002d_animationdata_nak(h002d::Nak { error })
+            }
+            _ => Ok(()),
+        }
+    }
 
-                        // Depending on the property set the rest
-                        // of the data field
-                        match property {
-                            h0001::Property::Unknown => {}
-                            // Handle 16-bit number type
-                            h0001::Property::MajorVersion
-                            | h0001::Property::MinorVersion
-                            | h0001::Property::PatchVersion => {
-                                // Convert to byte le bytes
-                                for byte in &ack.number.to_le_bytes() {
-                                    if buf.data.push(*byte).is_err() {
-                                        return Err(CommandError::DataVecTooSmall);
-                                    }
-                                }
-                            }
-                            // Handle 8-bit os type
-                            h0001::Property::OsType => {
-                                if buf.data.push(ack.os as u8).is_err() {
-                                    return Err(CommandError::DataVecTooSmall);
-                                }
-                            }
-                            // Handle ascii values
-                            _ => {
-                                for byte in ack.string.into_bytes() {
-                                    if buf.data.push(byte).is_err() {
-                                        return Err(CommandError::DataVecTooSmall);
-                                    }
-                                }
-                            }
-                        }
+    fn h002e_animationfinish(&mut self, data: h002e::Cmd) -> Result<(), CommandError> {
+        // Create appropriately sized buffer
+        let mut buf = HidIoPacketBuffer {
+            // Test packet id
+            id: HidIoCommandId::AnimationFinish,
+            // Detect max size
+            max_len: self.default_packet_chunk(),
+            // Use defaults for other fields
+            ..Default::default()
+        };
 
-                        self.tx_packetbuffer_send(&mut buf)
-                    }
-                    Err(_nak) => self.byte_nak(buf.id, property as u8),
-                }
-            }
-            HidIoPacketType::NaData => Err(CommandError::InvalidPacketBufferType(buf.ptype)),
-            HidIoPacketType::Ack => {
+        // Build payload
+        if !buf.append_payload(&[data.action as u8]) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+
+        buf.done = true;
+
+        self.tx_packetbuffer_send(&mut buf)
+    }
+    fn h002e_animationfinish_cmd(&mut self, _data: h002e::Cmd) -> Result<h002e::Ack, h002e::Nak> {
+        Err(h002e::Nak {
+            error: h002e::Error::NotStarted,
+        })
+    }
+    fn h002e_animationfinish_ack(&mut self, _data: h002e::Ack) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::AnimationFinish,
+            HidIoPacketType::Ack,
+        ))
+    }
+    fn h002e_animationfinish_nak(&mut self, _data: h002e::Nak) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::AnimationFinish,
+            HidIoPacketType::Nak,
+        ))
+    }
+    fn h002e_animationfinish_handler(
+        &mut self,
+        buf: HidIoPacketBuffer<H>,
+    ) -> Result<(), CommandError> {
+        // Handle packet type
+        match buf.ptype {
+            HidIoPacketType::Data => {
                 if buf.data.len() < 1 {
                     return Err(CommandError::DataVecNoData);
                 }
-                // Attempt to read first byte
-                let property = match h0001::Property::try_from(buf.data[0]) {
-                    Ok(property) => property,
+
+                // Retrieve fields
+                let action = match h002e::Action::try_from(buf.data[0]) {
+                    Ok(action) => action,
                     Err(_) => {
                         return Err(CommandError::InvalidProperty8(buf.data[0]));
                     }
                 };
 
-                // Setup ack struct
-                let mut ack = h0001::Ack {
-                    property,
-                    os: h0001::OsType::Unknown,
-                    number: 0,
-                    string: String::new(),
-                };
-
-                // Depending on the property set the rest
-                // of the ack fields
-                match property {
-                    h0001::Property::Unknown => {}
-                    // Handle 16-bit number type
-                    h0001::Property::MajorVersion
-                    | h0001::Property::MinorVersion
-                    | h0001::Property::PatchVersion => {
-                        // Convert from le bytes
-                        ack.number = u16::from_le_bytes(buf.data[1..3].try_into().unwrap());
-                    }
-                    // Handle 8-bit os type
-                    h0001::Property::OsType => {
-                        let typenum = buf.data[1];
-                        ack.os = match h0001::OsType::try_from(typenum) {
-                            Ok(ostype) => ostype,
-                            Err(_) => {
-                                return Err(CommandError::InvalidProperty8(typenum));
-                            }
-                        };
-                    }
-                    // Handle ascii values
-                    _ => {
-                        // NOTE: This is annoyingly inefficient?
-                        let bytes: Vec<u8, Sub1<H>> = Vec::from_slice(&buf.data[1..]).unwrap();
-                        let string = match String::from_utf8(bytes) {
-                            Ok(string) => string,
-                            Err(e) => {
-                                return Err(CommandError::InvalidUtf8(e));
-                            }
-                        };
-                        ack.string = string;
-                    }
+                match self.h002e_animationfinish_cmd(h002e::Cmd { action }) {
+                    Ok(_ack) => self.empty_ack(buf.id),
+                    Err(nak) => self.byte_nak(buf.id, nak.error as u8),
                 }
-
-                self.h0001_info_ack(ack)
             }
+            HidIoPacketType::NaData => Err(CommandError::InvalidPacketBufferType(buf.ptype)),
+            HidIoPacketType::Ack => self.h002e_animationfinish_ack(h002e::Ack {}),
             HidIoPacketType::Nak => {
                 if buf.data.len() < 1 {
                     return Err(CommandError::DataVecNoData);
                 }
-                // Attempt to read first byte
-                let property = match h0001::Property::try_from(buf.data[0]) {
-                    Ok(property) => property,
+
+                let error = match h002e::Error::try_from(buf.data[0]) {
+                    Ok(error) => error,
                     Err(_) => {
                         return Err(CommandError::InvalidProperty8(buf.data[0]));
                     }
                 };
-                self.h0001_info_nak(h0001::Nak { property })
+                self.h002e_animationfinish_nak(h002e::Nak { error })
             }
             _ => Ok(()),
         }
     }
 
-    fn h0002_test(&mut self, data: h0002::Cmd<H>, na: bool) -> Result<(), CommandError> {
+    fn h0031_terminalcmd(&mut self, data: h0031::Cmd<H>, na: bool) -> Result<(), CommandError> {
         // Create appropriately sized buffer
         let mut buf = HidIoPacketBuffer {
             // Test packet id
-            id: HidIoCommandId::TestPacket,
+            id: HidIoCommandId::TerminalCmd,
             // Detect max size
             max_len: self.default_packet_chunk(),
             // Use defaults for other fields
@@ -999,147 +3801,248 @@ where
         }
 
         // Build payload
-        if !buf.append_payload(&data.data) {
+        if !buf.append_payload(&data.command.as_bytes()) {
             return Err(CommandError::DataVecTooSmall);
         }
         buf.done = true;
 
         self.tx_packetbuffer_send(&mut buf)
     }
-    fn h0002_test_cmd(&mut self, _data: h0002::Cmd<H>) -> Result<h0002::Ack<H>, h0002::Nak> {
-        Err(h0002::Nak {})
+    fn h0031_terminalcmd_cmd(&mut self, _data: h0031::Cmd<H>) -> Result<h0031::Ack, h0031::Nak> {
+        Err(h0031::Nak {})
     }
-    fn h0002_test_nacmd(&mut self, _data: h0002::Cmd<H>) -> Result<(), CommandError> {
+    fn h0031_terminalcmd_nacmd(&mut self, _data: h0031::Cmd<H>) -> Result<(), CommandError> {
         Err(CommandError::IdNotImplemented(
-            HidIoCommandId::TestPacket,
+            HidIoCommandId::TerminalCmd,
             HidIoPacketType::NaData,
         ))
     }
-    fn h0002_test_ack(&mut self, _data: h0002::Ack<H>) -> Result<(), CommandError> {
+    fn h0031_terminalcmd_ack(&mut self, _data: h0031::Ack) -> Result<(), CommandError> {
         Err(CommandError::IdNotImplemented(
-            HidIoCommandId::TestPacket,
+            HidIoCommandId::TerminalCmd,
             HidIoPacketType::Ack,
         ))
     }
-    fn h0002_test_nak(&mut self, _data: h0002::Nak) -> Result<(), CommandError> {
+    fn h0031_terminalcmd_nak(&mut self, _data: h0031::Nak) -> Result<(), CommandError> {
         Err(CommandError::IdNotImplemented(
-            HidIoCommandId::TestPacket,
+            HidIoCommandId::TerminalCmd,
             HidIoPacketType::Nak,
         ))
     }
-    fn h0002_test_handler(&mut self, buf: HidIoPacketBuffer<H>) -> Result<(), CommandError> {
+    fn h0031_terminalcmd_handler(&mut self, buf: HidIoPacketBuffer<H>) -> Result<(), CommandError> {
         // Handle packet type
         match buf.ptype {
             HidIoPacketType::Data => {
                 // Copy data into struct
-                let cmd = h0002::Cmd::<H> {
-                    data: match Vec::from_slice(&buf.data) {
-                        Ok(data) => data,
-                        Err(_) => {
-                            return Err(CommandError::DataVecTooSmall);
+                let cmd = h0031::Cmd::<H> {
+                    command: match String::from_utf8(buf.data) {
+                        Ok(string) => string,
+                        Err(e) => {
+                            return Err(CommandError::InvalidUtf8(e));
                         }
                     },
                 };
 
-                match self.h0002_test_cmd(cmd) {
-                    Ok(ack) => {
-                        // Build Ack (max test data size)
-                        let mut buf = HidIoPacketBuffer {
-                            // Data packet
-                            ptype: HidIoPacketType::Ack,
-                            // Packet id
-                            id: buf.id,
-                            // Detect max size
-                            max_len: self.default_packet_chunk(),
-                            ..Default::default()
-                        };
-
-                        // Copy data into buffer
-                        if !buf.append_payload(&ack.data) {
-                            return Err(CommandError::DataVecTooSmall);
-                        }
-                        buf.done = true;
-                        self.tx_packetbuffer_send(&mut buf)
-                    }
+                match self.h0031_terminalcmd_cmd(cmd) {
+                    Ok(_ack) => self.empty_ack(buf.id),
                     Err(_nak) => self.empty_nak(buf.id),
                 }
             }
             HidIoPacketType::NaData => {
                 // Copy data into struct
-                let cmd = h0002::Cmd::<H> {
-                    data: match Vec::from_slice(&buf.data) {
-                        Ok(data) => data,
-                        Err(_) => {
-                            return Err(CommandError::DataVecTooSmall);
+                let cmd = h0031::Cmd::<H> {
+                    command: match String::from_utf8(buf.data) {
+                        Ok(string) => string,
+                        Err(e) => {
+                            return Err(CommandError::InvalidUtf8(e));
                         }
                     },
                 };
 
-                self.h0002_test_nacmd(cmd)
+                self.h0031_terminalcmd_nacmd(cmd)
             }
-            HidIoPacketType::Ack => {
+            HidIoPacketType::Ack => self.h0031_terminalcmd_ack(h0031::Ack {}),
+            HidIoPacketType::Nak => self.h0031_terminalcmd_nak(h0031::Nak {}),
+            _ => Ok(()),
+        }
+    }
+
+    fn h0034_terminalout(&mut self, data: h0034::Cmd<H>, na: bool) -> Result<(), CommandError> {
+        // Create appropriately sized buffer
+        let mut buf = HidIoPacketBuffer {
+            // Test packet id
+            id: HidIoCommandId::TerminalOut,
+            // Detect max size
+            max_len: self.default_packet_chunk(),
+            // Use defaults for other fields
+            ..Default::default()
+        };
+
+        // Set NA (no-ack)
+        if na {
+            buf.ptype = HidIoPacketType::NaData;
+        }
+
+        // Build payload
+        if !buf.append_payload(&data.output.as_bytes()) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+        buf.done = true;
+
+        self.tx_packetbuffer_send(&mut buf)
+    }
+    fn h0034_terminalout_cmd(&mut self, _data: h0034::Cmd<H>) -> Result<h0034::Ack, h0034::Nak> {
+        Err(h0034::Nak {})
+    }
+    fn h0034_terminalout_nacmd(&mut self, _data: h0034::Cmd<H>) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::TerminalOut,
+            HidIoPacketType::NaData,
+        ))
+    }
+    fn h0034_terminalout_ack(&mut self, _data: h0034::Ack) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::TerminalOut,
+            HidIoPacketType::Ack,
+        ))
+    }
+    fn h0034_terminalout_nak(&mut self, _data: h0034::Nak) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::TerminalOut,
+            HidIoPacketType::Nak,
+        ))
+    }
+    fn h0034_terminalout_handler(&mut self, buf: HidIoPacketBuffer<H>) -> Result<(), CommandError> {
+        // Handle packet type
+        match buf.ptype {
+            HidIoPacketType::Data => {
                 // Copy data into struct
-                let ack = h0002::Ack::<H> {
-                    data: match Vec::from_slice(&buf.data) {
-                        Ok(data) => data,
-                        Err(_) => {
-                            return Err(CommandError::DataVecTooSmall);
+                let cmd = h0034::Cmd::<H> {
+                    output: match String::from_utf8(buf.data) {
+                        Ok(string) => string,
+                        Err(e) => {
+                            return Err(CommandError::InvalidUtf8(e));
                         }
                     },
                 };
 
-                self.h0002_test_ack(ack)
+                match self.h0034_terminalout_cmd(cmd) {
+                    Ok(_ack) => self.empty_ack(buf.id),
+                    Err(_nak) => self.empty_nak(buf.id),
+                }
             }
-            HidIoPacketType::Nak => self.h0002_test_nak(h0002::Nak {}),
+            HidIoPacketType::NaData => {
+                // Copy data into struct
+                let cmd = h0034::Cmd::<H> {
+                    output: match String::from_utf8(buf.data) {
+                        Ok(string) => string,
+                        Err(e) => {
+                            return Err(CommandError::InvalidUtf8(e));
+                        }
+                    },
+                };
+
+                self.h0034_terminalout_nacmd(cmd)
+            }
+            HidIoPacketType::Ack => self.h0034_terminalout_ack(h0034::Ack {}),
+            HidIoPacketType::Nak => self.h0034_terminalout_nak(h0034::Nak {}),
             _ => Ok(()),
         }
-    }
+    }
+
+    fn h0035_hoststate(&mut self, data: h0035::Cmd, na: bool) -> Result<(), CommandError> {
+        // Create appropriately sized buffer
+        let mut buf = HidIoPacketBuffer {
+            // Test packet id
+            id: HidIoCommandId::HostState,
+            // Detect max size
+            max_len: self.default_packet_chunk(),
+            // Use defaults for other fields
+            ..Default::default()
+        };
+
+        // Set NA (no-ack)
+        if na {
+            buf.ptype = HidIoPacketType::NaData;
+        }
+
+        // Build payload
+        if !buf.append_payload(&[data.state as u8]) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+        if !buf.append_payload(&data.idle_secs.to_le_bytes()) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+        if !buf.append_payload(&[data.game_mode as u8]) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+        buf.done = true;
 
-    fn h0003_resethidio(&mut self, _data: h0003::Cmd) -> Result<(), CommandError> {
-        self.tx_packetbuffer_send(&mut HidIoPacketBuffer {
-            // Test packet id
-            id: HidIoCommandId::ResetHidIo,
-            // Detect max size
-            max_len: self.default_packet_chunk(),
-            // Ready
-            done: true,
-            // Use defaults for other fields
-            ..Default::default()
-        })
+        self.tx_packetbuffer_send(&mut buf)
     }
-    fn h0003_resethidio_cmd(&mut self, _data: h0003::Cmd) -> Result<h0003::Ack, h0003::Nak> {
-        Err(h0003::Nak {})
+    fn h0035_hoststate_cmd(&mut self, _data: h0035::Cmd) -> Result<h0035::Ack, h0035::Nak> {
+        Err(h0035::Nak {})
     }
-    fn h0003_resethidio_ack(&mut self, _data: h0003::Ack) -> Result<(), CommandError> {
+    fn h0035_hoststate_nacmd(&mut self, _data: h0035::Cmd) -> Result<(), CommandError> {
         Err(CommandError::IdNotImplemented(
-            HidIoCommandId::ResetHidIo,
+            HidIoCommandId::HostState,
+            HidIoPacketType::NaData,
+        ))
+    }
+    fn h0035_hoststate_ack(&mut self, _data: h0035::Ack) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(
+            HidIoCommandId::HostState,
             HidIoPacketType::Ack,
         ))
     }
-    fn h0003_resethidio_nak(&mut self, _data: h0003::Nak) -> Result<(), CommandError> {
+    fn h0035_hoststate_nak(&mut self, _data: h0035::Nak) -> Result<(), CommandError> {
         Err(CommandError::IdNotImplemented(
-            HidIoCommandId::ResetHidIo,
+            HidIoCommandId::HostState,
             HidIoPacketType::Nak,
         ))
     }
-    fn h0003_resethidio_handler(&mut self, buf: HidIoPacketBuffer<H>) -> Result<(), CommandError> {
+    fn h0035_hoststate_handler(&mut self, buf: HidIoPacketBuffer<H>) -> Result<(), CommandError> {
         // Handle packet type
         match buf.ptype {
-            HidIoPacketType::Data => match self.h0003_resethidio_cmd(h0003::Cmd {}) {
-                Ok(_ack) => self.empty_ack(buf.id),
-                Err(_nak) => self.empty_nak(buf.id),
-            },
-            HidIoPacketType::NaData => Err(CommandError::InvalidPacketBufferType(buf.ptype)),
-            HidIoPacketType::Ack => self.h0003_resethidio_ack(h0003::Ack {}),
-            HidIoPacketType::Nak => self.h0003_resethidio_nak(h0003::Nak {}),
+            HidIoPacketType::Data | HidIoPacketType::NaData => {
+                if buf.data.len() < 5 {
+                    return Err(CommandError::DataVecNoData);
+                }
+
+                // Retrieve fields
+                let state = match h0035::State::try_from(buf.data[0]) {
+                    Ok(state) => state,
+                    Err(_) => {
+                        return Err(CommandError::InvalidProperty8(buf.data[0]));
+                    }
+                };
+                let idle_secs = u32::from_le_bytes(buf.data[1..5].try_into().unwrap());
+                // Game mode byte is optional
+                let game_mode = buf.data.get(5).map_or(false, |val| *val != 0);
+                let cmd = h0035::Cmd {
+                    state,
+                    idle_secs,
+                    game_mode,
+                };
+
+                if buf.ptype == HidIoPacketType::NaData {
+                    return self.h0035_hoststate_nacmd(cmd);
+                }
+                match self.h0035_hoststate_cmd(cmd) {
+                    Ok(_ack) => self.empty_ack(buf.id),
+                    Err(_nak) => self.empty_nak(buf.id),
+                }
+            }
+            HidIoPacketType::Ack => self.h0035_hoststate_ack(h0035::Ack {}),
+            HidIoPacketType::Nak => self.h0035_hoststate_nak(h0035::Nak {}),
             _ => Ok(()),
         }
     }
 
-    fn h0016_flashmode(&mut self, _data: h0016::Cmd) -> Result<(), CommandError> {
+    fn h0036_lockhost(&mut self, _data: h0036::Cmd) -> Result<(), CommandError> {
         self.tx_packetbuffer_send(&mut HidIoPacketBuffer {
             // Test packet id
-            id: HidIoCommandId::FlashMode,
+            id: HidIoCommandId::LockHost,
             // Detect max size
             max_len: self.default_packet_chunk(),
             // Ready
@@ -1148,442 +4051,536 @@ where
             ..Default::default()
         })
     }
-    fn h0016_flashmode_cmd(&mut self, _data: h0016::Cmd) -> Result<h0016::Ack, h0016::Nak> {
-        Err(h0016::Nak {
-            error: h0016::Error::NotSupported,
+    fn h0036_lockhost_cmd(&mut self, _data: h0036::Cmd) -> Result<h0036::Ack, h0036::Nak> {
+        Err(h0036::Nak {
+            error: h0036::Error::NotSupported,
         })
     }
-    fn h0016_flashmode_ack(&mut self, _data: h0016::Ack) -> Result<(), CommandError> {
+    fn h0036_lockhost_ack(&mut self, _data: h0036::Ack) -> Result<(), CommandError> {
         Err(CommandError::IdNotImplemented(
-            HidIoCommandId::FlashMode,
+            HidIoCommandId::LockHost,
             HidIoPacketType::Ack,
         ))
     }
-    fn h0016_flashmode_nak(&mut self, _data: h0016::Nak) -> Result<(), CommandError> {
+    fn h0036_lockhost_nak(&mut self, _data: h0036::Nak) -> Result<(), CommandError> {
         Err(CommandError::IdNotImplemented(
-            HidIoCommandId::FlashMode,
+            HidIoCommandId::LockHost,
             HidIoPacketType::Nak,
         ))
     }
-    fn h0016_flashmode_handler(&mut self, buf: HidIoPacketBuffer<H>) -> Result<(), CommandError> {
+    fn h0036_lockhost_handler(&mut self, buf: HidIoPacketBuffer<H>) -> Result<(), CommandError> {
         // Handle packet type
         match buf.ptype {
-            HidIoPacketType::Data => match self.h0016_flashmode_cmd(h0016::Cmd {}) {
-                Ok(ack) => self.short_ack(buf.id, ack.scancode),
+            HidIoPacketType::Data => match self.h0036_lockhost_cmd(h0036::Cmd {}) {
+                Ok(_ack) => self.empty_ack(buf.id),
                 Err(nak) => self.byte_nak(buf.id, nak.error as u8),
             },
             HidIoPacketType::NaData => Err(CommandError::InvalidPacketBufferType(buf.ptype)),
-            HidIoPacketType::Ack => {
-                if buf.data.len() < 2 {
-                    return Err(CommandError::DataVecNoData);
-                }
-
-                let scancode = u16::from_le_bytes(buf.data[0..2].try_into().unwrap());
-                self.h0016_flashmode_ack(h0016::Ack { scancode })
-            }
+            HidIoPacketType::Ack => self.h0036_lockhost_ack(h0036::Ack {}),
             HidIoPacketType::Nak => {
                 if buf.data.len() < 1 {
                     return Err(CommandError::DataVecNoData);
                 }
 
-                let error = match h0016::Error::try_from(buf.data[0]) {
+                let error = match h0036::Error::try_from(buf.data[0]) {
                     Ok(error) => error,
                     Err(_) => {
                         return Err(CommandError::InvalidProperty8(buf.data[0]));
                     }
                 };
-                self.h0016_flashmode_nak(h0016::Nak { error })
+                self.h0036_lockhost_nak(h0036::Nak { error })
             }
             _ => Ok(()),
         }
     }
 
-    fn h0017_unicodetext(&mut self, data: h0017::Cmd<H>, na: bool) -> Result<(), CommandError> {
+    fn h0037_fileopen(&mut self, data: h0037::Cmd<H>) -> Result<(), CommandError> {
         // Create appropriately sized buffer
         let mut buf = HidIoPacketBuffer {
             // Test packet id
-            id: HidIoCommandId::UnicodeText,
+            id: HidIoCommandId::FileOpen,
             // Detect max size
             max_len: self.default_packet_chunk(),
             // Use defaults for other fields
             ..Default::default()
         };
 
-        // Set NA (no-ack)
-        if na {
-            buf.ptype = HidIoPacketType::NaData;
-        }
-
         // Build payload
-        if !buf.append_payload(data.string.as_bytes()) {
+        if !buf.append_payload(&[data.mode as u8]) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+        if !buf.append_payload(&data.size.to_le_bytes()) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+        if !buf.append_payload(data.name.as_bytes()) {
             return Err(CommandError::DataVecTooSmall);
         }
+
         buf.done = true;
 
         self.tx_packetbuffer_send(&mut buf)
     }
-    fn h0017_unicodetext_cmd(&mut self, _data: h0017::Cmd<H>) -> Result<h0017::Ack, h0017::Nak> {
-        Err(h0017::Nak {})
-    }
-    fn h0017_unicodetext_nacmd(&mut self, _data: h0017::Cmd<H>) -> Result<(), CommandError> {
-        Err(CommandError::IdNotImplemented(
-            HidIoCommandId::UnicodeText,
-            HidIoPacketType::NaData,
-        ))
+    fn h0037_fileopen_cmd(&mut self, _data: h0037::Cmd<H>) -> Result<h0037::Ack, h0037::Nak> {
+        Err(h0037::Nak {
+            error: h0037::Error::NotSupported,
+        })
     }
-    fn h0017_unicodetext_ack(&mut self, _data: h0017::Ack) -> Result<(), CommandError> {
+    fn h0037_fileopen_ack(&mut self, _data: h0037::Ack) -> Result<(), CommandError> {
         Err(CommandError::IdNotImplemented(
-            HidIoCommandId::UnicodeText,
+            HidIoCommandId::FileOpen,
             HidIoPacketType::Ack,
         ))
     }
-    fn h0017_unicodetext_nak(&mut self, _data: h0017::Nak) -> Result<(), CommandError> {
+    fn h0037_fileopen_nak(&mut self, _data: h0037::Nak) -> Result<(), CommandError> {
         Err(CommandError::IdNotImplemented(
-            HidIoCommandId::UnicodeText,
+            HidIoCommandId::FileOpen,
             HidIoPacketType::Nak,
         ))
     }
-    fn h0017_unicodetext_handler(&mut self, buf: HidIoPacketBuffer<H>) -> Result<(), CommandError> {
+    fn h0037_fileopen_handler(&mut self, buf: HidIoPacketBuffer<H>) -> Result<(), CommandError> {
         // Handle packet type
         match buf.ptype {
             HidIoPacketType::Data => {
-                // Copy data into struct
-                let cmd = h0017::Cmd::<H> {
-                    string: match String::from_utf8(buf.data) {
-                        Ok(string) => string,
-                        Err(e) => {
-                            return Err(CommandError::InvalidUtf8(e));
-                        }
-                    },
+                if buf.data.len() < 5 {
+                    return Err(CommandError::DataVecNoData);
+                }
+
+                // Retrieve fields
+                let mode = match h0037::Mode::try_from(buf.data[0]) {
+                    Ok(mode) => mode,
+                    Err(_) => {
+                        return Err(CommandError::InvalidProperty8(buf.data[0]));
+                    }
+                };
+                let size = u32::from_le_bytes(buf.data[1..5].try_into().unwrap());
+                let name = match String::from_utf8(Vec::from_slice(&buf.data[5..]).unwrap()) {
+                    Ok(name) => name,
+                    Err(e) => {
+                        return Err(CommandError::InvalidUtf8(e));
+                    }
                 };
 
-                match self.h0017_unicodetext_cmd(cmd) {
-                    Ok(_ack) => self.empty_ack(buf.id),
-                    Err(_nak) => self.empty_nak(buf.id),
+                match self.h0037_fileopen_cmd(h0037::Cmd { mode, size, name }) {
+                    Ok(ack) => {
+                        // Build Ack
+                        let mut buf = HidIoPacketBuffer {
+                            // Data packet
+                            ptype: HidIoPacketType::Ack,
+                            // Packet id
+                            id: buf.id,
+                            // Detect max size
+                            max_len: self.default_packet_chunk(),
+                            ..Default::default()
+                        };
+
+                        // Build payload
+                        if !buf.append_payload(&[ack.handle]) {
+                            return Err(CommandError::DataVecTooSmall);
+                        }
+                        if !buf.append_payload(&ack.size.to_le_bytes()) {
+                            return Err(CommandError::DataVecTooSmall);
+                        }
+                        buf.done = true;
+                        self.tx_packetbuffer_send(&mut buf)
+                    }
+                    Err(nak) => self.byte_nak(buf.id, nak.error as u8),
                 }
             }
-            HidIoPacketType::NaData => {
-                // Copy data into struct
-                let cmd = h0017::Cmd::<H> {
-                    string: match String::from_utf8(buf.data) {
-                        Ok(string) => string,
-                        Err(e) => {
-                            return Err(CommandError::InvalidUtf8(e));
-                        }
-                    },
-                };
+            HidIoPacketType::NaData => Err(CommandError::InvalidPacketBufferType(buf.ptype)),
+            HidIoPacketType::Ack => {
+                if buf.data.len() < 5 {
+                    return Err(CommandError::DataVecNoData);
+                }
 
-                self.h0017_unicodetext_nacmd(cmd)
+                // Retrieve fields
+                let handle = buf.data[0];
+                let size = u32::from_le_bytes(buf.data[1..5].try_into().unwrap());
+
+                self.h0037_fileopen_ack(h0037::Ack { handle, size })
+            }
+            HidIoPacketType::Nak => {
+                if buf.data.len() < 1 {
+                    return Err(CommandError::DataVecNoData);
+                }
+
+                let error = match h0037::Error::try_from(buf.data[0]) {
+                    Ok(error) => error,
+                    Err(_) => {
+                        return Err(CommandError::InvalidProperty8(buf.data[0]));
+                    }
+                };
+                self.h0037_fileopen_nak(h0037::Nak { error })
             }
-            HidIoPacketType::Ack => self.h0017_unicodetext_ack(h0017::Ack {}),
-            HidIoPacketType::Nak => self.h0017_unicodetext_nak(h0017::Nak {}),
             _ => Ok(()),
         }
     }
 
-    fn h0018_unicodestate(&mut self, data: h0018::Cmd<H>, na: bool) -> Result<(), CommandError> {
+    fn h0038_filewrite(&mut self, data: h0038::Cmd<H>) -> Result<(), CommandError> {
         // Create appropriately sized buffer
         let mut buf = HidIoPacketBuffer {
             // Test packet id
-            id: HidIoCommandId::UnicodeState,
+            id: HidIoCommandId::FileWrite,
             // Detect max size
             max_len: self.default_packet_chunk(),
             // Use defaults for other fields
             ..Default::default()
         };
 
-        // Set NA (no-ack)
-        if na {
-            buf.ptype = HidIoPacketType::NaData;
-        }
-
         // Build payload
-        if !buf.append_payload(data.symbols.as_bytes()) {
+        if !buf.append_payload(&[data.handle]) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+        if !buf.append_payload(&data.offset.to_le_bytes()) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+        if !buf.append_payload(&data.data) {
             return Err(CommandError::DataVecTooSmall);
         }
+
         buf.done = true;
 
         self.tx_packetbuffer_send(&mut buf)
     }
-    fn h0018_unicodestate_cmd(&mut self, _data: h0018::Cmd<H>) -> Result<h0018::Ack, h0018::Nak> {
-        Err(h0018::Nak {})
-    }
-    fn h0018_unicodestate_nacmd(&mut self, _data: h0018::Cmd<H>) -> Result<(), CommandError> {
-        Err(CommandError::IdNotImplemented(
-            HidIoCommandId::UnicodeState,
-            HidIoPacketType::NaData,
-        ))
+    fn h0038_filewrite_cmd(&mut self, _data: h0038::Cmd<H>) -> Result<h0038::Ack, h0038::Nak> {
+        Err(h0038::Nak {
+            error: h0037::Error::NotSupported,
+        })
     }
-    fn h0018_unicodestate_ack(&mut self, _data: h0018::Ack) -> Result<(), CommandError> {
+    fn h0038_filewrite_ack(&mut self, _data: h0038::Ack) -> Result<(), CommandError> {
         Err(CommandError::IdNotImplemented(
-            HidIoCommandId::UnicodeState,
+            HidIoCommandId::FileWrite,
             HidIoPacketType::Ack,
         ))
     }
-    fn h0018_unicodestate_nak(&mut self, _data: h0018::Nak) -> Result<(), CommandError> {
+    fn h0038_filewrite_nak(&mut self, _data: h0038::Nak) -> Result<(), CommandError> {
         Err(CommandError::IdNotImplemented(
-            HidIoCommandId::UnicodeState,
+            HidIoCommandId::FileWrite,
             HidIoPacketType::Nak,
         ))
     }
-    fn h0018_unicodestate_handler(
-        &mut self,
-        buf: HidIoPacketBuffer<H>,
-    ) -> Result<(), CommandError> {
+    fn h0038_filewrite_handler(&mut self, buf: HidIoPacketBuffer<H>) -> Result<(), CommandError> {
         // Handle packet type
         match buf.ptype {
             HidIoPacketType::Data => {
-                // Copy data into struct
-                let cmd = h0018::Cmd::<H> {
-                    symbols: match String::from_utf8(buf.data) {
-                        Ok(symbols) => symbols,
-                        Err(e) => {
-                            return Err(CommandError::InvalidUtf8(e));
-                        }
-                    },
-                };
+                if buf.data.len() < 5 {
+                    return Err(CommandError::DataVecNoData);
+                }
 
-                match self.h0018_unicodestate_cmd(cmd) {
+                // Retrieve fields
+                let handle = buf.data[0];
+                let offset = u32::from_le_bytes(buf.data[1..5].try_into().unwrap());
+                let data: Vec<u8, H> = Vec::from_slice(&buf.data[5..]).unwrap();
+
+                match self.h0038_filewrite_cmd(h0038::Cmd {
+                    handle,
+                    offset,
+                    data,
+                }) {
                     Ok(_ack) => self.empty_ack(buf.id),
-                    Err(_nak) => self.empty_nak(buf.id),
+                    Err(nak) => self.byte_nak(buf.id, nak.error as u8),
                 }
             }
-            HidIoPacketType::NaData => {
-                // Copy data into struct
-                let cmd = h0018::Cmd::<H> {
-                    symbols: match String::from_utf8(buf.data) {
-                        Ok(symbols) => symbols,
-                        Err(e) => {
-                            return Err(CommandError::InvalidUtf8(e));
-                        }
-                    },
-                };
+            HidIoPacketType::NaData => Err(CommandError::InvalidPacketBufferType(buf.ptype)),
+            HidIoPacketType::Ack => self.h0038_filewrite_ack(h0038::Ack {}),
+            HidIoPacketType::Nak => {
+                if buf.data.len() < 1 {
+                    return Err(CommandError::DataVecNoData);
+                }
 
-                self.h0018_unicodestate_nacmd(cmd)
+                let error = match h0037::Error::try_from(buf.data[0]) {
+                    Ok(error) => error,
+                    Err(_) => {
+                        return Err(CommandError::InvalidProperty8(buf.data[0]));
+                    }
+                };
+                self.h0038_filewrite_nak(h0038::Nak { error })
             }
-            HidIoPacketType::Ack => self.h0018_unicodestate_ack(h0018::Ack {}),
-            HidIoPacketType::Nak => self.h0018_unicodestate_nak(h0018::Nak {}),
             _ => Ok(()),
         }
     }
 
-    fn h001a_sleepmode(&mut self, _data: h001a::Cmd) -> Result<(), CommandError> {
-        self.tx_packetbuffer_send(&mut HidIoPacketBuffer {
-            // Test packet id
-            id: HidIoCommandId::SleepMode,
-            // Detect max size
-            max_len: self.default_packet_chunk(),
-            // Ready
-            done: true,
-            // Use defaults for other fields
-            ..Default::default()
-        })
+    fn h0039_fileread(&mut self, data: h0039::Cmd) -> Result<(), CommandError> {
+        // Create appropriately sized buffer
+        let mut buf = HidIoPacketBuffer {
+            // Test packet id
+            id: HidIoCommandId::FileRead,
+            // Detect max size
+            max_len: self.default_packet_chunk(),
+            // Use defaults for other fields
+            ..Default::default()
+        };
+
+        // Build payload
+        if !buf.append_payload(&[data.handle]) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+        if !buf.append_payload(&data.offset.to_le_bytes()) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+        if !buf.append_payload(&data.len.to_le_bytes()) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+
+        buf.done = true;
+
+        self.tx_packetbuffer_send(&mut buf)
     }
-    fn h001a_sleepmode_cmd(&mut self, _data: h001a::Cmd) -> Result<h001a::Ack, h001a::Nak> {
-        Err(h001a::Nak {
-            error: h001a::Error::NotSupported,
+    fn h0039_fileread_cmd(&mut self, _data: h0039::Cmd) -> Result<h0039::Ack<H>, h0039::Nak> {
+        Err(h0039::Nak {
+            error: h0037::Error::NotSupported,
         })
     }
-    fn h001a_sleepmode_ack(&mut self, _data: h001a::Ack) -> Result<(), CommandError> {
+    fn h0039_fileread_ack(&mut self, _data: h0039::Ack<H>) -> Result<(), CommandError> {
         Err(CommandError::IdNotImplemented(
-            HidIoCommandId::FlashMode,
+            HidIoCommandId::FileRead,
             HidIoPacketType::Ack,
         ))
     }
-    fn h001a_sleepmode_nak(&mut self, _data: h001a::Nak) -> Result<(), CommandError> {
+    fn h0039_fileread_nak(&mut self, _data: h0039::Nak) -> Result<(), CommandError> {
         Err(CommandError::IdNotImplemented(
-            HidIoCommandId::FlashMode,
+            HidIoCommandId::FileRead,
             HidIoPacketType::Nak,
         ))
     }
-    fn h001a_sleepmode_handler(&mut self, buf: HidIoPacketBuffer<H>) -> Result<(), CommandError> {
+    fn h0039_fileread_handler(&mut self, buf: HidIoPacketBuffer<H>) -> Result<(), CommandError> {
         // Handle packet type
         match buf.ptype {
-            HidIoPacketType::Data => match self.h001a_sleepmode_cmd(h001a::Cmd {}) {
-                Ok(_ack) => self.empty_ack(buf.id),
-                Err(nak) => self.byte_nak(buf.id, nak.error as u8),
-            },
+            HidIoPacketType::Data => {
+                if buf.data.len() < 7 {
+                    return Err(CommandError::DataVecNoData);
+                }
+
+                // Retrieve fields
+                let handle = buf.data[0];
+                let offset = u32::from_le_bytes(buf.data[1..5].try_into().unwrap());
+                let len = u16::from_le_bytes(buf.data[5..7].try_into().unwrap());
+
+                match self.h0039_fileread_cmd(h0039::Cmd {
+                    handle,
+                    offset,
+                    len,
+                }) {
+                    Ok(ack) => {
+                        // Build Ack
+                        let mut buf = HidIoPacketBuffer {
+                            // Data packet
+                            ptype: HidIoPacketType::Ack,
+                            // Packet id
+                            id: buf.id,
+                            // Detect max size
+                            max_len: self.default_packet_chunk(),
+                            ..Default::default()
+                        };
+
+                        // Copy data into buffer
+                        if !buf.append_payload(&ack.data) {
+                            return Err(CommandError::DataVecTooSmall);
+                        }
+                        buf.done = true;
+                        self.tx_packetbuffer_send(&mut buf)
+                    }
+                    Err(nak) => self.byte_nak(buf.id, nak.error as u8),
+                }
+            }
             HidIoPacketType::NaData => Err(CommandError::InvalidPacketBufferType(buf.ptype)),
-            HidIoPacketType::Ack => self.h001a_sleepmode_ack(h001a::Ack {}),
+            HidIoPacketType::Ack => {
+                // Copy data into struct
+                let ack = h0039::Ack::<H> {
+                    data: match Vec::from_slice(&buf.data) {
+                        Ok(data) => data,
+                        Err(_) => {
+                            return Err(CommandError::DataVecTooSmall);
+                        }
+                    },
+                };
+
+                self.h0039_fileread_ack(ack)
+            }
             HidIoPacketType::Nak => {
                 if buf.data.len() < 1 {
                     return Err(CommandError::DataVecNoData);
                 }
 
-                let error = match h001a::Error::try_from(buf.data[0]) {
+                let error = match h0037::Error::try_from(buf.data[0]) {
                     Ok(error) => error,
                     Err(_) => {
                         return Err(CommandError::InvalidProperty8(buf.data[0]));
                     }
                 };
-                self.h001a_sleepmode_nak(h001a::Nak { error })
+                self.h0039_fileread_nak(h0039::Nak { error })
             }
             _ => Ok(()),
         }
     }
 
-    fn h0031_terminalcmd(&mut self, data: h0031::Cmd<H>, na: bool) -> Result<(), CommandError> {
+    fn h003a_filechecksum(&mut self, data: h003a::Cmd) -> Result<(), CommandError> {
         // Create appropriately sized buffer
         let mut buf = HidIoPacketBuffer {
             // Test packet id
-            id: HidIoCommandId::TerminalCmd,
+            id: HidIoCommandId::FileChecksum,
             // Detect max size
             max_len: self.default_packet_chunk(),
             // Use defaults for other fields
             ..Default::default()
         };
 
-        // Set NA (no-ack)
-        if na {
-            buf.ptype = HidIoPacketType::NaData;
-        }
-
         // Build payload
-        if !buf.append_payload(&data.command.as_bytes()) {
+        if !buf.append_payload(&[data.handle]) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+        if !buf.append_payload(&data.offset.to_le_bytes()) {
             return Err(CommandError::DataVecTooSmall);
         }
+        if !buf.append_payload(&data.len.to_le_bytes()) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+
         buf.done = true;
 
         self.tx_packetbuffer_send(&mut buf)
     }
-    fn h0031_terminalcmd_cmd(&mut self, _data: h0031::Cmd<H>) -> Result<h0031::Ack, h0031::Nak> {
-        Err(h0031::Nak {})
-    }
-    fn h0031_terminalcmd_nacmd(&mut self, _data: h0031::Cmd<H>) -> Result<(), CommandError> {
-        Err(CommandError::IdNotImplemented(
-            HidIoCommandId::TerminalCmd,
-            HidIoPacketType::NaData,
-        ))
+    fn h003a_filechecksum_cmd(&mut self, _data: h003a::Cmd) -> Result<h003a::Ack, h003a::Nak> {
+        Err(h003a::Nak {
+            error: h0037::Error::NotSupported,
+        })
     }
-    fn h0031_terminalcmd_ack(&mut self, _data: h0031::Ack) -> Result<(), CommandError> {
+    fn h003a_filechecksum_ack(&mut self, _data: h003a::Ack) -> Result<(), CommandError> {
         Err(CommandError::IdNotImplemented(
-            HidIoCommandId::TerminalCmd,
+            HidIoCommandId::FileChecksum,
             HidIoPacketType::Ack,
         ))
     }
-    fn h0031_terminalcmd_nak(&mut self, _data: h0031::Nak) -> Result<(), CommandError> {
+    fn h003a_filechecksum_nak(&mut self, _data: h003a::Nak) -> Result<(), CommandError> {
         Err(CommandError::IdNotImplemented(
-            HidIoCommandId::TerminalCmd,
+            HidIoCommandId::FileChecksum,
             HidIoPacketType::Nak,
         ))
     }
-    fn h0031_terminalcmd_handler(&mut self, buf: HidIoPacketBuffer<H>) -> Result<(), CommandError> {
+    fn h003a_filechecksum_handler(
+        &mut self,
+        buf: HidIoPacketBuffer<H>,
+    ) -> Result<(), CommandError> {
         // Handle packet type
         match buf.ptype {
             HidIoPacketType::Data => {
-                // Copy data into struct
-                let cmd = h0031::Cmd::<H> {
-                    command: match String::from_utf8(buf.data) {
-                        Ok(string) => string,
-                        Err(e) => {
-                            return Err(CommandError::InvalidUtf8(e));
-                        }
-                    },
-                };
+                if buf.data.len() < 9 {
+                    return Err(CommandError::DataVecNoData);
+                }
 
-                match self.h0031_terminalcmd_cmd(cmd) {
-                    Ok(_ack) => self.empty_ack(buf.id),
-                    Err(_nak) => self.empty_nak(buf.id),
+                // Retrieve fields
+                let handle = buf.data[0];
+                let offset = u32::from_le_bytes(buf.data[1..5].try_into().unwrap());
+                let len = u32::from_le_bytes(buf.data[5..9].try_into().unwrap());
+
+                match self.h003a_filechecksum_cmd(h003a::Cmd {
+                    handle,
+                    offset,
+                    len,
+                }) {
+                    Ok(ack) => self.short_ack(buf.id, ack.crc),
+                    Err(nak) => self.byte_nak(buf.id, nak.error as u8),
                 }
             }
-            HidIoPacketType::NaData => {
-                // Copy data into struct
-                let cmd = h0031::Cmd::<H> {
-                    command: match String::from_utf8(buf.data) {
-                        Ok(string) => string,
-                        Err(e) => {
-                            return Err(CommandError::InvalidUtf8(e));
-                        }
-                    },
-                };
+            HidIoPacketType::NaData => Err(CommandError::InvalidPacketBufferType(buf.ptype)),
+            HidIoPacketType::Ack => {
+                if buf.data.len() < 2 {
+                    return Err(CommandError::DataVecNoData);
+                }
 
-                self.h0031_terminalcmd_nacmd(cmd)
+                let crc = u16::from_le_bytes(buf.data[0..2].try_into().unwrap());
+                self.h003a_filechecksum_ack(h003a::Ack { crc })
+            }
+            HidIoPacketType::Nak => {
+                if buf.data.len() < 1 {
+                    return Err(CommandError::DataVecNoData);
+                }
+
+                let error = match h0037::Error::try_from(buf.data[0]) {
+                    Ok(error) => error,
+                    Err(_) => {
+                        return Err(CommandError::InvalidProperty8(buf.data[0]));
+                    }
+                };
+                self.h003a_filechecksum_nak(h003a::Nak { error })
             }
-            HidIoPacketType::Ack => self.h0031_terminalcmd_ack(h0031::Ack {}),
-            HidIoPacketType::Nak => self.h0031_terminalcmd_nak(h0031::Nak {}),
             _ => Ok(()),
         }
     }
 
-    fn h0034_terminalout(&mut self, data: h0034::Cmd<H>, na: bool) -> Result<(), CommandError> {
+    fn h003b_fileclose(&mut self, data: h003b::Cmd) -> Result<(), CommandError> {
         // Create appropriately sized buffer
         let mut buf = HidIoPacketBuffer {
             // Test packet id
-            id: HidIoCommandId::TerminalOut,
+            id: HidIoCommandId::FileClose,
             // Detect max size
             max_len: self.default_packet_chunk(),
             // Use defaults for other fields
             ..Default::default()
         };
 
-        // Set NA (no-ack)
-        if na {
-            buf.ptype = HidIoPacketType::NaData;
-        }
-
         // Build payload
-        if !buf.append_payload(&data.output.as_bytes()) {
+        if !buf.append_payload(&[data.handle, data.action as u8]) {
             return Err(CommandError::DataVecTooSmall);
         }
+
         buf.done = true;
 
         self.tx_packetbuffer_send(&mut buf)
     }
-    fn h0034_terminalout_cmd(&mut self, _data: h0034::Cmd<H>) -> Result<h0034::Ack, h0034::Nak> {
-        Err(h0034::Nak {})
-    }
-    fn h0034_terminalout_nacmd(&mut self, _data: h0034::Cmd<H>) -> Result<(), CommandError> {
-        Err(CommandError::IdNotImplemented(
-            HidIoCommandId::TerminalOut,
-            HidIoPacketType::NaData,
-        ))
+    fn h003b_fileclose_cmd(&mut self, _data: h003b::Cmd) -> Result<h003b::Ack, h003b::Nak> {
+        Err(h003b::Nak {
+            error: h0037::Error::NotSupported,
+        })
     }
-    fn h0034_terminalout_ack(&mut self, _data: h0034::Ack) -> Result<(), CommandError> {
+    fn h003b_fileclose_ack(&mut self, _data: h003b::Ack) -> Result<(), CommandError> {
         Err(CommandError::IdNotImplemented(
-            HidIoCommandId::TerminalOut,
+            HidIoCommandId::FileClose,
             HidIoPacketType::Ack,
         ))
     }
-    fn h0034_terminalout_nak(&mut self, _data: h0034::Nak) -> Result<(), CommandError> {
+    fn h003b_fileclose_nak(&mut self, _data: h003b::Nak) -> Result<(), CommandError> {
         Err(CommandError::IdNotImplemented(
-            HidIoCommandId::TerminalOut,
+            HidIoCommandId::FileClose,
             HidIoPacketType::Nak,
         ))
     }
-    fn h0034_terminalout_handler(&mut self, buf: HidIoPacketBuffer<H>) -> Result<(), CommandError> {
+    fn h003b_fileclose_handler(&mut self, buf: HidIoPacketBuffer<H>) -> Result<(), CommandError> {
         // Handle packet type
         match buf.ptype {
             HidIoPacketType::Data => {
-                // Copy data into struct
-                let cmd = h0034::Cmd::<H> {
-                    output: match String::from_utf8(buf.data) {
-                        Ok(string) => string,
-                        Err(e) => {
-                            return Err(CommandError::InvalidUtf8(e));
-                        }
-                    },
+                if buf.data.len() < 2 {
+                    return Err(CommandError::DataVecNoData);
+                }
+
+                // Retrieve fields
+                let handle = buf.data[0];
+                let action = match h003b::Action::try_from(buf.data[1]) {
+                    Ok(action) => action,
+                    Err(_) => {
+                        return Err(CommandError::InvalidProperty8(buf.data[1]));
+                    }
                 };
 
-                match self.h0034_terminalout_cmd(cmd) {
+                match self.h003b_fileclose_cmd(h003b::Cmd { handle, action }) {
                     Ok(_ack) => self.empty_ack(buf.id),
-                    Err(_nak) => self.empty_nak(buf.id),
+                    Err(nak) => self.byte_nak(buf.id, nak.error as u8),
                 }
             }
-            HidIoPacketType::NaData => {
-                // Copy data into struct
-                let cmd = h0034::Cmd::<H> {
-                    output: match String::from_utf8(buf.data) {
-                        Ok(string) => string,
-                        Err(e) => {
-                            return Err(CommandError::InvalidUtf8(e));
-                        }
-                    },
-                };
+            HidIoPacketType::NaData => Err(CommandError::InvalidPacketBufferType(buf.ptype)),
+            HidIoPacketType::Ack => self.h003b_fileclose_ack(h003b::Ack {}),
+            HidIoPacketType::Nak => {
+                if buf.data.len() < 1 {
+                    return Err(CommandError::DataVecNoData);
+                }
 
-                self.h0034_terminalout_nacmd(cmd)
+                let error = match h0037::Error::try_from(buf.data[0]) {
+                    Ok(error) => error,
+                    Err(_) => {
+                        return Err(CommandError::InvalidProperty8(buf.data[0]));
+                    }
+                };
+                self.h003b_fileclose_nak(h003b::Nak { error })
             }
-            HidIoPacketType::Ack => self.h0034_terminalout_ack(h0034::Ack {}),
-            HidIoPacketType::Nak => self.h0034_terminalout_nak(h0034::Nak {}),
             _ => Ok(()),
         }
     }
@@ -1721,7 +4718,7 @@ where
                 let command = u16::from_le_bytes(buf.data[0..2].try_into().unwrap());
                 let argument = u16::from_le_bytes(buf.data[2..4].try_into().unwrap());
                 let data: Vec<u8, Diff<H, U4>> = if buf.data.len() > 4 {
-                    Vec::from_slice(&buf.data[5..]).unwrap()
+                    Vec::from_slice(&buf.data[4..]).unwrap()
                 } else {
                     Vec::new()
                 };
@@ -1741,4 +4738,106 @@ where
             _ => Ok(()),
         }
     }
+
+    fn vendor(
+        &mut self,
+        id: HidIoCommandId,
+        data: vendor::Cmd<H>,
+        na: bool,
+    ) -> Result<(), CommandError> {
+        if !id.is_vendor() {
+            return Err(CommandError::IdNotMatched(id));
+        }
+
+        // Create appropriately sized buffer
+        let mut buf = HidIoPacketBuffer {
+            // Vendor packet id
+            id,
+            // Detect max size
+            max_len: self.default_packet_chunk(),
+            // Use defaults for other fields
+            ..Default::default()
+        };
+
+        // Set NA (no-ack)
+        if na {
+            buf.ptype = HidIoPacketType::NaData;
+        }
+
+        // Build payload
+        if !buf.append_payload(&data.data) {
+            return Err(CommandError::DataVecTooSmall);
+        }
+        buf.done = true;
+
+        self.tx_packetbuffer_send(&mut buf)
+    }
+    fn vendor_cmd(
+        &mut self,
+        _id: HidIoCommandId,
+        _data: vendor::Cmd<H>,
+    ) -> Result<vendor::Ack<H>, vendor::Nak<H>> {
+        Err(vendor::Nak { data: Vec::new() })
+    }
+    fn vendor_nacmd(
+        &mut self,
+        id: HidIoCommandId,
+        _data: vendor::Cmd<H>,
+    ) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(id, HidIoPacketType::NaData))
+    }
+    fn vendor_ack(
+        &mut self,
+        id: HidIoCommandId,
+        _data: vendor::Ack<H>,
+    ) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(id, HidIoPacketType::Ack))
+    }
+    fn vendor_nak(
+        &mut self,
+        id: HidIoCommandId,
+        _data: vendor::Nak<H>,
+    ) -> Result<(), CommandError> {
+        Err(CommandError::IdNotImplemented(id, HidIoPacketType::Nak))
+    }
+    fn vendor_handler(&mut self, buf: HidIoPacketBuffer<H>) -> Result<(), CommandError> {
+        // Copy raw payload
+        let data = match Vec::from_slice(&buf.data) {
+            Ok(data) => data,
+            Err(_) => {
+                return Err(CommandError::DataVecTooSmall);
+            }
+        };
+
+        // Handle packet type
+        match buf.ptype {
+            HidIoPacketType::Data => {
+                let (ptype, data) = match self.vendor_cmd(buf.id, vendor::Cmd { data }) {
+                    Ok(ack) => (HidIoPacketType::Ack, ack.data),
+                    Err(nak) => (HidIoPacketType::Nak, nak.data),
+                };
+
+                // Build Ack/Nak
+                let mut buf = HidIoPacketBuffer {
+                    // Ack or Nak
+                    ptype,
+                    // Packet id
+                    id: buf.id,
+                    // Detect max size
+                    max_len: self.default_packet_chunk(),
+                    // Use defaults for other fields
+                    ..Default::default()
+                };
+                if !buf.append_payload(&data) {
+                    return Err(CommandError::DataVecTooSmall);
+                }
+                buf.done = true;
+                self.tx_packetbuffer_send(&mut buf)
+            }
+            HidIoPacketType::NaData => self.vendor_nacmd(buf.id, vendor::Cmd { data }),
+            HidIoPacketType::Ack => self.vendor_ack(buf.id, vendor::Ack { data }),
+            HidIoPacketType::Nak => self.vendor_nak(buf.id, vendor::Nak { data }),
+            _ => Ok(()),
+        }
+    }
 }
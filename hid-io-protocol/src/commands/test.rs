@@ -31,6 +31,11 @@ use typenum::Unsigned;
 #[cfg(feature = "server")]
 use log::debug;
 
+// ----- Consts -----
+
+/// Contents of the file stored on the test device
+const TEST_FILE: [u8; 4] = [1, 2, 3, 4];
+
 // ----- Enumerations -----
 
 enum LogError {
@@ -60,7 +65,6 @@ struct CommandInterface<
     RX: ArrayLength<Vec<u8, N>>,
     N: ArrayLength<u8>,
     H: ArrayLength<u8>,
-    S: ArrayLength<u8>,
     ID: ArrayLength<HidIoCommandId> + ArrayLength<u8>,
 > where
     H: core::fmt::Debug,
@@ -71,7 +75,8 @@ struct CommandInterface<
     rx_bytebuf: buffer::Buffer<RX, N>,
     rx_packetbuf: HidIoPacketBuffer<H>,
     tx_bytebuf: buffer::Buffer<TX, N>,
-    serial_buf: Vec<u8, S>,
+    /// Negotiated protocol version and capabilities
+    negotiated: Option<(u16, u32)>,
 }
 
 impl<
@@ -79,15 +84,14 @@ impl<
         RX: ArrayLength<Vec<u8, N>>,
         N: ArrayLength<u8>,
         H: ArrayLength<u8>,
-        S: ArrayLength<u8>,
         ID: ArrayLength<HidIoCommandId> + ArrayLength<u8>,
-    > CommandInterface<TX, RX, N, H, S, ID>
+    > CommandInterface<TX, RX, N, H, ID>
 where
     H: core::fmt::Debug,
     H: Sub<B1>,
     H: Sub<U4>,
 {
-    fn new(ids: &[HidIoCommandId]) -> Result<CommandInterface<TX, RX, N, H, S, ID>, CommandError> {
+    fn new(ids: &[HidIoCommandId]) -> Result<CommandInterface<TX, RX, N, H, ID>, CommandError> {
         // Make sure we have a large enough id vec
         let ids = match Vec::from_slice(ids) {
             Ok(ids) => ids,
@@ -98,13 +102,12 @@ where
         let tx_bytebuf = buffer::Buffer::new();
         let rx_bytebuf = buffer::Buffer::new();
         let rx_packetbuf = HidIoPacketBuffer::new();
-        let serial_buf = Vec::new();
         Ok(CommandInterface {
             ids,
             rx_bytebuf,
             rx_packetbuf,
             tx_bytebuf,
-            serial_buf,
+            negotiated: None,
         })
     }
 
@@ -125,7 +128,17 @@ where
             // Retrieve vec chunk
             if let Some(buf) = self.rx_bytebuf.dequeue() {
                 // Decode chunk
-                match self.rx_packetbuf.decode_packet(&buf) {
+                let mut result = self.rx_packetbuf.decode_packet(&buf);
+
+                // Missing continued packets, decode again after discarding the buffer
+                while let Err(e) = &result {
+                    if !self.rx_packetbuffer_discarded(e)? {
+                        break;
+                    }
+                    result = self.rx_packetbuf.decode_packet(&buf);
+                }
+
+                match result {
                     Ok(_recv) => {
                         // Only handle buffer if ready
                         if self.rx_packetbuf.done {
@@ -182,16 +195,14 @@ where
 /// RX - tx byte buffer size (in multiples of N)
 /// N - Max payload length (HidIoPacketBuffer), used for default values
 /// H - Max data payload length (HidIoPacketBuffer)
-/// S - Serialization buffer size
 /// ID - Max number of HidIoCommandIds
 impl<
         TX: ArrayLength<Vec<u8, N>>,
         RX: ArrayLength<Vec<u8, N>>,
         N: ArrayLength<u8>,
         H: ArrayLength<u8>,
-        S: ArrayLength<u8>,
         ID: ArrayLength<HidIoCommandId> + ArrayLength<u8>,
-    > Commands<H, ID> for CommandInterface<TX, RX, N, H, S, ID>
+    > Commands<H, ID> for CommandInterface<TX, RX, N, H, ID>
 where
     H: core::fmt::Debug + Sub<B1> + Sub<U4>,
 {
@@ -200,35 +211,24 @@ where
     }
 
     fn tx_packetbuffer_send(&mut self, buf: &mut HidIoPacketBuffer<H>) -> Result<(), CommandError> {
-        let size = buf.serialized_len() as usize;
-        if self.serial_buf.resize_default(size).is_err() {
-            return Err(CommandError::SerializationVecTooSmall);
-        }
-        let data = match buf.serialize_buffer(&mut self.serial_buf) {
-            Ok(data) => data,
-            Err(err) => {
-                return Err(CommandError::SerializationFailed(err));
+        // Serialize each packet directly into a tx buffer entry
+        let mut offset = Some(0);
+        while let Some(pos) = offset {
+            let mut chunk = Vec::<u8, N>::new();
+            if chunk.resize_default(<N as Unsigned>::to_usize()).is_err() {
+                return Err(CommandError::TxBufferVecTooSmall);
             }
-        };
-
-        // Add serialized data to buffer
-        // May need to enqueue multiple packets depending how much
-        // was serialized
-        for pos in (0..data.len()).step_by(<N as Unsigned>::to_usize()) {
-            let len = core::cmp::min(<N as Unsigned>::to_usize(), data.len() - pos);
-            match self
-                .tx_bytebuf
-                .enqueue(match Vec::from_slice(&data[pos..len + pos]) {
-                    Ok(vec) => vec,
-                    Err(_) => {
-                        return Err(CommandError::TxBufferVecTooSmall);
-                    }
-                }) {
-                Ok(_) => {}
-                Err(_) => {
-                    return Err(CommandError::TxBufferSendFailed);
+            let (len, next) = match buf.serialize_chunk(pos, &mut chunk) {
+                Ok(result) => result,
+                Err(err) => {
+                    return Err(CommandError::SerializationFailed(err));
                 }
+            };
+            chunk.truncate(len);
+            if self.tx_bytebuf.enqueue(chunk).is_err() {
+                return Err(CommandError::TxBufferSendFailed);
             }
+            offset = next;
         }
         Ok(())
     }
@@ -315,6 +315,39 @@ where
         }
     }
 
+    fn h0004_capabilities_cmd(&mut self, data: h0004::Cmd) -> Result<h0004::Ack, h0004::Nak> {
+        let capabilities = h0004::CAP_CRC16 | h0004::CAP_COMPRESSION;
+        self.negotiated = Some((
+            data.version.min(h0004::VERSION),
+            data.capabilities & capabilities,
+        ));
+        Ok(h0004::Ack {
+            version: h0004::VERSION,
+            capabilities,
+        })
+    }
+    fn h0004_capabilities_ack(&mut self, data: h0004::Ack) -> Result<(), CommandError> {
+        if data.version != h0004::VERSION {
+            return Err(CommandError::TestFailure);
+        }
+        let capabilities = self.negotiated.map_or(0, |(_, caps)| caps);
+        if data.capabilities & capabilities != capabilities {
+            return Err(CommandError::TestFailure);
+        }
+        Ok(())
+    }
+
+    fn h0005_pair_cmd(&mut self, data: h0005::Cmd) -> Result<h0005::Ack, h0005::Nak> {
+        if data.key.iter().enumerate().all(|(i, b)| *b == i as u8) {
+            Ok(h0005::Ack {})
+        } else {
+            Err(h0005::Nak {})
+        }
+    }
+    fn h0005_pair_ack(&mut self, _data: h0005::Ack) -> Result<(), CommandError> {
+        Ok(())
+    }
+
     fn h0016_flashmode_cmd(&mut self, _data: h0016::Cmd) -> Result<h0016::Ack, h0016::Nak> {
         Ok(h0016::Ack { scancode: 15 })
     }
@@ -369,6 +402,217 @@ where
         Ok(())
     }
 
+    fn h001b_settingsread_cmd(&mut self, data: h001b::Cmd) -> Result<h001b::Ack<H>, h001b::Nak> {
+        if data.offset == 0 && data.len == 4 {
+            Ok(h001b::Ack {
+                data: Vec::from_slice(&[0x10, 0x20, 0x30, 0x40]).unwrap(),
+            })
+        } else {
+            Err(h001b::Nak {
+                error: h001b::Error::InvalidRange,
+            })
+        }
+    }
+    fn h001b_settingsread_ack(&mut self, data: h001b::Ack<H>) -> Result<(), CommandError> {
+        if data.data[..] == [0x10, 0x20, 0x30, 0x40] {
+            Ok(())
+        } else {
+            Err(CommandError::TestFailure)
+        }
+    }
+    fn h001b_settingsread_nak(&mut self, data: h001b::Nak) -> Result<(), CommandError> {
+        if data.error == h001b::Error::InvalidRange {
+            Ok(())
+        } else {
+            Err(CommandError::TestFailure)
+        }
+    }
+
+    fn h001c_settingswrite_cmd(&mut self, data: h001c::Cmd<H>) -> Result<h001c::Ack, h001c::Nak> {
+        if data.offset == 0 && data.data[..] == [1, 2, 3] {
+            Ok(h001c::Ack {})
+        } else {
+            Err(h001c::Nak {
+                error: h001c::Error::Locked,
+            })
+        }
+    }
+    fn h001c_settingswrite_ack(&mut self, _data: h001c::Ack) -> Result<(), CommandError> {
+        Ok(())
+    }
+    fn h001c_settingswrite_nak(&mut self, data: h001c::Nak) -> Result<(), CommandError> {
+        if data.error == h001c::Error::Locked {
+            Ok(())
+        } else {
+            Err(CommandError::TestFailure)
+        }
+    }
+
+    fn h001d_klllayoutread_cmd(&mut self, data: h001d::Cmd) -> Result<h001d::Ack<H>, h001d::Nak> {
+        if data.offset == 0 {
+            Ok(h001d::Ack {
+                size: 4,
+                data: Vec::from_slice(&[0x4B, 0x4C, 0x4C, 0x00]).unwrap(),
+            })
+        } else {
+            Err(h001d::Nak {
+                error: h001d::Error::InvalidOffset,
+            })
+        }
+    }
+    fn h001d_klllayoutread_ack(&mut self, data: h001d::Ack<H>) -> Result<(), CommandError> {
+        if data.size == 4 && data.data[..] == [0x4B, 0x4C, 0x4C, 0x00] {
+            Ok(())
+        } else {
+            Err(CommandError::TestFailure)
+        }
+    }
+    fn h001d_klllayoutread_nak(&mut self, data: h001d::Nak) -> Result<(), CommandError> {
+        if data.error == h001d::Error::InvalidOffset {
+            Ok(())
+        } else {
+            Err(CommandError::TestFailure)
+        }
+    }
+
+    fn h001e_klllayoutwrite_cmd(&mut self, data: h001e::Cmd<H>) -> Result<h001e::Ack, h001e::Nak> {
+        if data.offset == 0 {
+            Ok(h001e::Ack {})
+        } else {
+            Err(h001e::Nak {
+                error: h001e::Error::InvalidOffset,
+            })
+        }
+    }
+    fn h001e_klllayoutwrite_ack(&mut self, _data: h001e::Ack) -> Result<(), CommandError> {
+        Ok(())
+    }
+    fn h001e_klllayoutwrite_nak(&mut self, data: h001e::Nak) -> Result<(), CommandError> {
+        if data.error == h001e::Error::CrcMismatch {
+            Ok(())
+        } else {
+            Err(CommandError::TestFailure)
+        }
+    }
+
+    fn h001f_klllayoutcontrol_cmd(&mut self, data: h001f::Cmd) -> Result<h001f::Ack, h001f::Nak> {
+        match data.action {
+            h001f::Action::Begin if data.arg == 1024 => Ok(h001f::Ack {}),
+            _ => Err(h001f::Nak {
+                error: h001f::Error::NoPrevious,
+            }),
+        }
+    }
+    fn h001f_klllayoutcontrol_ack(&mut self, _data: h001f::Ack) -> Result<(), CommandError> {
+        Ok(())
+    }
+    fn h001f_klllayoutcontrol_nak(&mut self, data: h001f::Nak) -> Result<(), CommandError> {
+        if data.error == h001f::Error::NoPrevious {
+            Ok(())
+        } else {
+            Err(CommandError::TestFailure)
+        }
+    }
+
+    fn h0029_displaytext_cmd(&mut self, data: h0029::Cmd<H>) -> Result<h0029::Ack, h0029::Nak> {
+        if data.widget == 0 && data.text == "Layer 1" {
+            Ok(h0029::Ack {})
+        } else {
+            Err(h0029::Nak {
+                error: h0029::Error::InvalidWidget,
+            })
+        }
+    }
+    fn h0029_displaytext_ack(&mut self, _data: h0029::Ack) -> Result<(), CommandError> {
+        Ok(())
+    }
+    fn h0029_displaytext_nak(&mut self, data: h0029::Nak) -> Result<(), CommandError> {
+        if data.error == h0029::Error::InvalidWidget {
+            Ok(())
+        } else {
+            Err(CommandError::TestFailure)
+        }
+    }
+
+    fn h002a_confirmrequest_cmd(&mut self, data: h002a::Cmd<H>) -> Result<h002a::Ack, h002a::Nak> {
+        if data.request == 1 && data.timeout == 30 && data.prompt == "Type password?" {
+            Ok(h002a::Ack {})
+        } else {
+            Err(h002a::Nak {
+                error: h002a::Error::Busy,
+            })
+        }
+    }
+    fn h002a_confirmrequest_ack(&mut self, _data: h002a::Ack) -> Result<(), CommandError> {
+        Ok(())
+    }
+
+    fn h002b_confirmresponse_cmd(&mut self, data: h002b::Cmd) -> Result<h002b::Ack, h002b::Nak> {
+        if data.request == 1 && data.answer == h002b::Answer::Confirmed {
+            Ok(h002b::Ack {})
+        } else {
+            Err(h002b::Nak {
+                error: h002b::Error::UnknownRequest,
+            })
+        }
+    }
+    fn h002b_confirmresponse_ack(&mut self, _data: h002b::Ack) -> Result<(), CommandError> {
+        Ok(())
+    }
+
+    fn h002c_animationbegin_cmd(&mut self, data: h002c::Cmd) -> Result<h002c::Ack, h002c::Nak> {
+        if data.slot == 0 {
+            Ok(h002c::Ack {})
+        } else {
+            Err(h002c::Nak {
+                error: h002c::Error::InvalidSlot,
+            })
+        }
+    }
+    fn h002c_animationbegin_ack(&mut self, _data: h002c::Ack) -> Result<(), CommandError> {
+        Ok(())
+    }
+    fn h002c_animationbegin_nak(&mut self, data: h002c::Nak) -> Result<(), CommandError> {
+        if data.error == h002c::Error::InvalidSlot {
+            Ok(())
+        } else {
+            Err(CommandError::TestFailure)
+        }
+    }
+
+    fn h002d_animationdata_cmd(&mut self, data: h002d::Cmd<H>) -> Result<h002d::Ack, h002d::Nak> {
+        if data.offset == 0 && data.data[..] == [1, 2, 3, 4] {
+            Ok(h002d::Ack {})
+        } else {
+            Err(h002d::Nak {
+                error: h002d::Error::InvalidOffset,
+            })
+        }
+    }
+    fn h002d_animationdata_ack(&mut self, _data: h002d::Ack) -> Result<(), CommandError> {
+        Ok(())
+    }
+    fn h002d_animationdata_nak(&mut self, data: h002d::Nak) -> Result<(), CommandError> {
+        if data.error == h002d::Error::CrcMismatch {
+            Ok(())
+        } else {
+            Err(CommandError::TestFailure)
+        }
+    }
+
+    fn h002e_animationfinish_cmd(&mut self, data: h002e::Cmd) -> Result<h002e::Ack, h002e::Nak> {
+        if data.action == h002e::Action::Commit {
+            Ok(h002e::Ack {})
+        } else {
+            Err(h002e::Nak {
+                error: h002e::Error::NotStarted,
+            })
+        }
+    }
+    fn h002e_animationfinish_ack(&mut self, _data: h002e::Ack) -> Result<(), CommandError> {
+        Ok(())
+    }
+
     fn h0031_terminalcmd_cmd(&mut self, data: h0031::Cmd<H>) -> Result<h0031::Ack, h0031::Nak> {
         if data.command == "terminal command string\n\r" {
             Ok(h0031::Ack {})
@@ -405,6 +649,166 @@ where
         Ok(())
     }
 
+    fn h0035_hoststate_cmd(&mut self, data: h0035::Cmd) -> Result<h0035::Ack, h0035::Nak> {
+        if data.state == h0035::State::Locked && data.idle_secs == 300 && !data.game_mode {
+            Ok(h0035::Ack {})
+        } else {
+            Err(h0035::Nak {})
+        }
+    }
+    fn h0035_hoststate_nacmd(&mut self, data: h0035::Cmd) -> Result<(), CommandError> {
+        if data.state == h0035::State::Active && data.idle_secs == 0 && data.game_mode {
+            Ok(())
+        } else {
+            Err(CommandError::TestFailure)
+        }
+    }
+    fn h0035_hoststate_ack(&mut self, _data: h0035::Ack) -> Result<(), CommandError> {
+        Ok(())
+    }
+
+    fn h0036_lockhost_cmd(&mut self, _data: h0036::Cmd) -> Result<h0036::Ack, h0036::Nak> {
+        Err(h0036::Nak {
+            error: h0036::Error::RateLimited,
+        })
+    }
+    fn h0036_lockhost_nak(&mut self, data: h0036::Nak) -> Result<(), CommandError> {
+        if data.error == h0036::Error::RateLimited {
+            Ok(())
+        } else {
+            Err(CommandError::TestFailure)
+        }
+    }
+
+    fn h0037_fileopen_cmd(&mut self, data: h0037::Cmd<H>) -> Result<h0037::Ack, h0037::Nak> {
+        if data.name != "layout.kll" {
+            return Err(h0037::Nak {
+                error: h0037::Error::NotFound,
+            });
+        }
+        // First half of the file was stored before the transfer was interrupted
+        let size = match data.mode {
+            h0037::Mode::Read => TEST_FILE.len() as u32,
+            h0037::Mode::Write => 0,
+            h0037::Mode::Resume => 2,
+        };
+        Ok(h0037::Ack { handle: 1, size })
+    }
+    fn h0037_fileopen_ack(&mut self, data: h0037::Ack) -> Result<(), CommandError> {
+        if data.handle == 1 && data.size == 2 {
+            Ok(())
+        } else {
+            Err(CommandError::TestFailure)
+        }
+    }
+    fn h0037_fileopen_nak(&mut self, data: h0037::Nak) -> Result<(), CommandError> {
+        if data.error == h0037::Error::NotFound {
+            Ok(())
+        } else {
+            Err(CommandError::TestFailure)
+        }
+    }
+
+    fn h0038_filewrite_cmd(&mut self, data: h0038::Cmd<H>) -> Result<h0038::Ack, h0038::Nak> {
+        if data.handle == 1 && data.offset == 2 && data.data[..] == TEST_FILE[2..] {
+            Ok(h0038::Ack {})
+        } else {
+            Err(h0038::Nak {
+                error: h0037::Error::InvalidOffset,
+            })
+        }
+    }
+    fn h0038_filewrite_ack(&mut self, _data: h0038::Ack) -> Result<(), CommandError> {
+        Ok(())
+    }
+    fn h0038_filewrite_nak(&mut self, data: h0038::Nak) -> Result<(), CommandError> {
+        if data.error == h0037::Error::InvalidOffset {
+            Ok(())
+        } else {
+            Err(CommandError::TestFailure)
+        }
+    }
+
+    fn h0039_fileread_cmd(&mut self, data: h0039::Cmd) -> Result<h0039::Ack<H>, h0039::Nak> {
+        let offset = data.offset as usize;
+        if data.handle != 1 || offset > TEST_FILE.len() {
+            return Err(h0039::Nak {
+                error: h0037::Error::InvalidOffset,
+            });
+        }
+        let end = core::cmp::min(offset + data.len as usize, TEST_FILE.len());
+        Ok(h0039::Ack {
+            data: Vec::from_slice(&TEST_FILE[offset..end]).unwrap(),
+        })
+    }
+    fn h0039_fileread_ack(&mut self, data: h0039::Ack<H>) -> Result<(), CommandError> {
+        // Short read at the end of the file
+        if data.data[..] == TEST_FILE[2..] {
+            Ok(())
+        } else {
+            Err(CommandError::TestFailure)
+        }
+    }
+    fn h0039_fileread_nak(&mut self, data: h0039::Nak) -> Result<(), CommandError> {
+        if data.error == h0037::Error::InvalidOffset {
+            Ok(())
+        } else {
+            Err(CommandError::TestFailure)
+        }
+    }
+
+    fn h003a_filechecksum_cmd(&mut self, data: h003a::Cmd) -> Result<h003a::Ack, h003a::Nak> {
+        if data.handle != 1 {
+            return Err(h003a::Nak {
+                error: h0037::Error::InvalidHandle,
+            });
+        }
+        let start = data.offset as usize;
+        let end = start + data.len as usize;
+        if end > TEST_FILE.len() {
+            return Err(h003a::Nak {
+                error: h0037::Error::InvalidOffset,
+            });
+        }
+        Ok(h003a::Ack {
+            crc: crate::crc16(&TEST_FILE[start..end]),
+        })
+    }
+    fn h003a_filechecksum_ack(&mut self, data: h003a::Ack) -> Result<(), CommandError> {
+        if data.crc == crate::crc16(&TEST_FILE) {
+            Ok(())
+        } else {
+            Err(CommandError::TestFailure)
+        }
+    }
+    fn h003a_filechecksum_nak(&mut self, data: h003a::Nak) -> Result<(), CommandError> {
+        if data.error == h0037::Error::InvalidHandle {
+            Ok(())
+        } else {
+            Err(CommandError::TestFailure)
+        }
+    }
+
+    fn h003b_fileclose_cmd(&mut self, data: h003b::Cmd) -> Result<h003b::Ack, h003b::Nak> {
+        if data.handle == 1 {
+            Ok(h003b::Ack {})
+        } else {
+            Err(h003b::Nak {
+                error: h0037::Error::InvalidHandle,
+            })
+        }
+    }
+    fn h003b_fileclose_ack(&mut self, _data: h003b::Ack) -> Result<(), CommandError> {
+        Ok(())
+    }
+    fn h003b_fileclose_nak(&mut self, data: h003b::Nak) -> Result<(), CommandError> {
+        if data.error == h0037::Error::InvalidHandle {
+            Ok(())
+        } else {
+            Err(CommandError::TestFailure)
+        }
+    }
+
     fn h0050_manufacturing_cmd(&mut self, data: h0050::Cmd) -> Result<h0050::Ack, h0050::Nak> {
         if data.command == 0 && data.argument == 0 {
             Ok(h0050::Ack {})
@@ -426,7 +830,9 @@ where
     where
         <H as Sub<U4>>::Output: ArrayLength<u8>,
     {
-        if data.command == 0 && data.argument == 0 {
+        if data.command == 0 && data.argument == 0 && data.data.is_empty() {
+            Ok(h0051::Ack {})
+        } else if data.command == 4 && data.argument == 1 && data.data[..] == [1, 2, 3, 4, 5] {
             Ok(h0051::Ack {})
         } else {
             Err(h0051::Nak {})
@@ -438,6 +844,26 @@ where
     fn h0051_manufacturingres_nak(&mut self, _data: h0051::Nak) -> Result<(), CommandError> {
         Err(CommandError::TestFailure)
     }
+
+    fn vendor_cmd(
+        &mut self,
+        id: HidIoCommandId,
+        data: vendor::Cmd<H>,
+    ) -> Result<vendor::Ack<H>, vendor::Nak<H>> {
+        // Echo the raw payload back
+        if id == HidIoCommandId::Vendor3 {
+            Ok(vendor::Ack { data: data.data })
+        } else {
+            Err(vendor::Nak { data: data.data })
+        }
+    }
+    fn vendor_ack(&mut self, id: HidIoCommandId, data: vendor::Ack<H>) -> Result<(), CommandError> {
+        if id == HidIoCommandId::Vendor3 && data.data[..] == [0xDE, 0xAD, 0xBE, 0xEF] {
+            Ok(())
+        } else {
+            Err(CommandError::TestFailure)
+        }
+    }
 }
 
 // ----- Tests -----
@@ -454,7 +880,7 @@ fn h0000_supported_ids_test() {
     ];
 
     // Setup command interface
-    let mut intf = CommandInterface::<U8, U8, U64, U100, U110, U3>::new(&ids).unwrap();
+    let mut intf = CommandInterface::<U8, U8, U64, U100, U3>::new(&ids).unwrap();
 
     // Send command
     let send = intf.h0000_supported_ids(h0000::Cmd {});
@@ -568,7 +994,7 @@ fn h0001_info() {
     let ids = [HidIoCommandId::SupportedIds, HidIoCommandId::GetInfo];
 
     // Setup command interface
-    let mut intf = CommandInterface::<U8, U8, U64, U100, U110, U2>::new(&ids).unwrap();
+    let mut intf = CommandInterface::<U8, U8, U64, U100, U2>::new(&ids).unwrap();
 
     // Process each of the test entries
     for entry in &H0001ENTRIES {
@@ -631,7 +1057,7 @@ fn h0002_test() {
     ];
 
     // Setup command interface
-    let mut intf = CommandInterface::<U8, U8, U64, U150, U165, U3>::new(&ids).unwrap();
+    let mut intf = CommandInterface::<U8, U8, U64, U150, U3>::new(&ids).unwrap();
 
     // Normal data packets
     for entry in &H0002ENTRIES {
@@ -679,7 +1105,7 @@ fn h0002_invalid() {
     let ids = [HidIoCommandId::SupportedIds, HidIoCommandId::GetInfo];
 
     // Setup command interface
-    let mut intf = CommandInterface::<U8, U8, U64, U150, U165, U2>::new(&ids).unwrap();
+    let mut intf = CommandInterface::<U8, U8, U64, U150, U2>::new(&ids).unwrap();
 
     // Send command
     let cmd = h0002::Cmd { data: Vec::new() };
@@ -706,25 +1132,31 @@ fn h0002_invalid() {
 }
 
 #[test]
-fn h0016_flashmode() {
+fn h0004_capabilities() {
     setup_logging_lite().ok();
 
     // Build list of supported ids
-    let ids = [HidIoCommandId::FlashMode];
+    let ids = [HidIoCommandId::Capabilities];
 
     // Setup command interface
-    let mut intf = CommandInterface::<U8, U8, U64, U150, U165, U1>::new(&ids).unwrap();
+    let mut intf = CommandInterface::<U8, U8, U64, U150, U1>::new(&ids).unwrap();
 
     // Send command
-    let cmd = h0016::Cmd {};
-    let send = intf.h0016_flashmode(cmd);
-    assert!(send.is_ok(), "h0016_flashmode => {:?}", send);
+    let cmd = h0004::Cmd {
+        version: h0004::VERSION,
+        capabilities: h0004::CAP_WIDE_ID | h0004::CAP_CRC16,
+    };
+    let send = intf.h0004_capabilities(cmd);
+    assert!(send.is_ok(), "h0004_capabilities => {:?}", send);
 
     // Flush tx->rx
     // Process rx buffer
     let process = intf.process_rx();
     assert!(process.is_ok(), "process_rx1 => {:?}", process);
 
+    // Only capabilities supported by both sides are enabled
+    assert_eq!(intf.negotiated, Some((h0004::VERSION, h0004::CAP_CRC16)));
+
     // Flush tx->rx
     // Process rx buffer
     let process = intf.process_rx();
@@ -732,36 +1164,91 @@ fn h0016_flashmode() {
 }
 
 #[test]
-fn h0017_unicodetext() {
+fn h0005_pair() {
     setup_logging_lite().ok();
 
     // Build list of supported ids
-    let ids = [HidIoCommandId::UnicodeText];
+    let ids = [HidIoCommandId::Pair];
 
     // Setup command interface
-    let mut intf = CommandInterface::<U8, U8, U64, U150, U165, U1>::new(&ids).unwrap();
+    let mut intf = CommandInterface::<U8, U8, U64, U150, U1>::new(&ids).unwrap();
 
-    // Normal data packet
     // Send command
-    let cmd = h0017::Cmd {
-        string: String::from("My UTF-8 string"),
-    };
-    let send = intf.h0017_unicodetext(cmd.clone(), false);
-    assert!(send.is_ok(), "h0017_unicodetext {:?} => {:?}", cmd, send);
+    let mut key = [0; h0005::KEY_LEN];
+    for (i, b) in key.iter_mut().enumerate() {
+        *b = i as u8;
+    }
+    let send = intf.h0005_pair(h0005::Cmd { key });
+    assert!(send.is_ok(), "h0005_pair => {:?}", send);
 
     // Flush tx->rx
     // Process rx buffer
     let process = intf.process_rx();
-    assert!(process.is_ok(), "process_rx1 {:?} => {:?}", cmd, process);
+    assert!(process.is_ok(), "process_rx1 => {:?}", process);
 
     // Flush tx->rx
     // Process rx buffer
     let process = intf.process_rx();
-    assert!(process.is_ok(), "process_rx2 {:?} => {:?}", cmd, process);
+    assert!(process.is_ok(), "process_rx2 => {:?}", process);
+}
 
-    // NA (no-ack) data packets
-    // Send command
-    let cmd = h0017::Cmd {
+#[test]
+fn h0016_flashmode() {
+    setup_logging_lite().ok();
+
+    // Build list of supported ids
+    let ids = [HidIoCommandId::FlashMode];
+
+    // Setup command interface
+    let mut intf = CommandInterface::<U8, U8, U64, U150, U1>::new(&ids).unwrap();
+
+    // Send command
+    let cmd = h0016::Cmd {};
+    let send = intf.h0016_flashmode(cmd);
+    assert!(send.is_ok(), "h0016_flashmode => {:?}", send);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx1 => {:?}", process);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx2 => {:?}", process);
+}
+
+#[test]
+fn h0017_unicodetext() {
+    setup_logging_lite().ok();
+
+    // Build list of supported ids
+    let ids = [HidIoCommandId::UnicodeText];
+
+    // Setup command interface
+    let mut intf = CommandInterface::<U8, U8, U64, U150, U1>::new(&ids).unwrap();
+
+    // Normal data packet
+    // Send command
+    let cmd = h0017::Cmd {
+        string: String::from("My UTF-8 string"),
+    };
+    let send = intf.h0017_unicodetext(cmd.clone(), false);
+    assert!(send.is_ok(), "h0017_unicodetext {:?} => {:?}", cmd, send);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx1 {:?} => {:?}", cmd, process);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx2 {:?} => {:?}", cmd, process);
+
+    // NA (no-ack) data packets
+    // Send command
+    let cmd = h0017::Cmd {
         string: String::from("My UTF-8 na string"),
     };
     let send = intf.h0017_unicodetext(cmd.clone(), true);
@@ -786,7 +1273,7 @@ fn h0018_unicodestate() {
     let ids = [HidIoCommandId::UnicodeState];
 
     // Setup command interface
-    let mut intf = CommandInterface::<U8, U8, U64, U150, U165, U1>::new(&ids).unwrap();
+    let mut intf = CommandInterface::<U8, U8, U64, U150, U1>::new(&ids).unwrap();
 
     // Normal data packet
     // Send command
@@ -833,7 +1320,7 @@ fn h001a_sleepmode() {
     let ids = [HidIoCommandId::SleepMode];
 
     // Setup command interface
-    let mut intf = CommandInterface::<U8, U8, U64, U150, U165, U1>::new(&ids).unwrap();
+    let mut intf = CommandInterface::<U8, U8, U64, U150, U1>::new(&ids).unwrap();
 
     // Send command
     let cmd = h001a::Cmd {};
@@ -852,116 +1339,203 @@ fn h001a_sleepmode() {
 }
 
 #[test]
-fn h0031_terminalcmd() {
+fn h001b_settingsread() {
     setup_logging_lite().ok();
 
     // Build list of supported ids
-    let ids = [HidIoCommandId::TerminalCmd];
+    let ids = [HidIoCommandId::SettingsRead];
 
     // Setup command interface
-    let mut intf = CommandInterface::<U8, U8, U64, U150, U165, U1>::new(&ids).unwrap();
+    let mut intf = CommandInterface::<U8, U8, U64, U150, U1>::new(&ids).unwrap();
 
-    // Normal data packet
-    // Send command
-    let cmd = h0031::Cmd {
-        command: String::from("terminal command string\n\r"),
+    // Send valid region (expect ack)
+    let cmd = h001b::Cmd { offset: 0, len: 4 };
+    let send = intf.h001b_settingsread(cmd);
+    assert!(send.is_ok(), "h001b_settingsread(ack) => {:?}", send);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx1 => {:?}", process);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx2 => {:?}", process);
+
+    // Send invalid region (expect nak)
+    let cmd = h001b::Cmd {
+        offset: 0x1000,
+        len: 4,
     };
-    let send = intf.h0031_terminalcmd(cmd.clone(), false);
-    assert!(send.is_ok(), "h0031_terminalcmd {:?} => {:?}", cmd, send);
+    let send = intf.h001b_settingsread(cmd);
+    assert!(send.is_ok(), "h001b_settingsread(nak) => {:?}", send);
 
     // Flush tx->rx
     // Process rx buffer
     let process = intf.process_rx();
-    assert!(process.is_ok(), "process_rx1 {:?} => {:?}", cmd, process);
+    assert!(process.is_ok(), "process_rx3 => {:?}", process);
 
     // Flush tx->rx
     // Process rx buffer
     let process = intf.process_rx();
-    assert!(process.is_ok(), "process_rx2 {:?} => {:?}", cmd, process);
+    assert!(process.is_ok(), "process_rx4 => {:?}", process);
+}
 
-    // NA (no-ack) data packets
-    // Send command
-    let cmd = h0031::Cmd {
-        command: String::from("na terminal command string\n\r"),
+#[test]
+fn h001c_settingswrite() {
+    setup_logging_lite().ok();
+
+    // Build list of supported ids
+    let ids = [HidIoCommandId::SettingsWrite];
+
+    // Setup command interface
+    let mut intf = CommandInterface::<U8, U8, U64, U150, U1>::new(&ids).unwrap();
+
+    // Send valid write (expect ack)
+    let cmd = h001c::Cmd {
+        offset: 0,
+        data: Vec::from_slice(&[1, 2, 3]).unwrap(),
     };
-    let send = intf.h0031_terminalcmd(cmd.clone(), true);
-    assert!(
-        send.is_ok(),
-        "h0031_terminalcmd(na) {:?} => {:?}",
-        cmd,
-        send
-    );
+    let send = intf.h001c_settingswrite(cmd);
+    assert!(send.is_ok(), "h001c_settingswrite(ack) => {:?}", send);
 
     // Flush tx->rx
     // Process rx buffer
     let process = intf.process_rx();
-    assert!(process.is_ok(), "process_rx3 {:?} => {:?}", cmd, process);
+    assert!(process.is_ok(), "process_rx1 => {:?}", process);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx2 => {:?}", process);
+
+    // Send locked write (expect nak)
+    let cmd = h001c::Cmd {
+        offset: 0x1000,
+        data: Vec::from_slice(&[1, 2, 3]).unwrap(),
+    };
+    let send = intf.h001c_settingswrite(cmd);
+    assert!(send.is_ok(), "h001c_settingswrite(nak) => {:?}", send);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx3 => {:?}", process);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx4 => {:?}", process);
 }
 
 #[test]
-fn h0034_terminalout() {
+fn h001d_klllayoutread() {
     setup_logging_lite().ok();
 
     // Build list of supported ids
-    let ids = [HidIoCommandId::TerminalOut];
+    let ids = [HidIoCommandId::KllLayoutRead];
 
     // Setup command interface
-    let mut intf = CommandInterface::<U8, U8, U64, U150, U165, U1>::new(&ids).unwrap();
+    let mut intf = CommandInterface::<U8, U8, U64, U150, U1>::new(&ids).unwrap();
 
-    // Normal data packet
-    // Send command
-    let cmd = h0034::Cmd {
-        output: String::from("terminal output string\n\r\t"),
+    // Read start of layout (expect ack)
+    let cmd = h001d::Cmd { offset: 0, len: 64 };
+    let send = intf.h001d_klllayoutread(cmd);
+    assert!(send.is_ok(), "h001d_klllayoutread(ack) => {:?}", send);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx1 => {:?}", process);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx2 => {:?}", process);
+
+    // Read past end of layout (expect nak)
+    let cmd = h001d::Cmd { offset: 8, len: 64 };
+    let send = intf.h001d_klllayoutread(cmd);
+    assert!(send.is_ok(), "h001d_klllayoutread(nak) => {:?}", send);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx3 => {:?}", process);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx4 => {:?}", process);
+}
+
+#[test]
+fn h001e_klllayoutwrite() {
+    setup_logging_lite().ok();
+
+    // Build list of supported ids
+    let ids = [HidIoCommandId::KllLayoutWrite];
+
+    // Setup command interface
+    let mut intf = CommandInterface::<U8, U8, U64, U150, U1>::new(&ids).unwrap();
+
+    // Send valid chunk (expect ack)
+    let cmd = h001e::Cmd {
+        offset: 0,
+        crc: crate::crc16(&[1, 2, 3, 4]),
+        data: Vec::from_slice(&[1, 2, 3, 4]).unwrap(),
     };
-    let send = intf.h0034_terminalout(cmd.clone(), false);
-    assert!(send.is_ok(), "h0034_terminalout {:?} => {:?}", cmd, send);
+    let send = intf.h001e_klllayoutwrite(cmd);
+    assert!(send.is_ok(), "h001e_klllayoutwrite(ack) => {:?}", send);
 
     // Flush tx->rx
     // Process rx buffer
     let process = intf.process_rx();
-    assert!(process.is_ok(), "process_rx1 {:?} => {:?}", cmd, process);
+    assert!(process.is_ok(), "process_rx1 => {:?}", process);
 
     // Flush tx->rx
     // Process rx buffer
     let process = intf.process_rx();
-    assert!(process.is_ok(), "process_rx2 {:?} => {:?}", cmd, process);
+    assert!(process.is_ok(), "process_rx2 => {:?}", process);
 
-    // NA (no-ack) data packets
-    // Send command
-    let cmd = h0034::Cmd {
-        output: String::from("terminal na output string\n\r\t"),
+    // Send corrupted chunk (expect nak)
+    let cmd = h001e::Cmd {
+        offset: 0,
+        crc: crate::crc16(&[1, 2, 3, 4]) ^ 0x0001,
+        data: Vec::from_slice(&[1, 2, 3, 4]).unwrap(),
     };
-    let send = intf.h0034_terminalout(cmd.clone(), true);
-    assert!(
-        send.is_ok(),
-        "h0034_terminalout(na) {:?} => {:?}",
-        cmd,
-        send
-    );
+    let send = intf.h001e_klllayoutwrite(cmd);
+    assert!(send.is_ok(), "h001e_klllayoutwrite(nak) => {:?}", send);
 
     // Flush tx->rx
     // Process rx buffer
     let process = intf.process_rx();
-    assert!(process.is_ok(), "process_rx3 {:?} => {:?}", cmd, process);
+    assert!(process.is_ok(), "process_rx3 => {:?}", process);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx4 => {:?}", process);
 }
 
 #[test]
-fn h0050_manufacturing() {
+fn h001f_klllayoutcontrol() {
     setup_logging_lite().ok();
 
     // Build list of supported ids
-    let ids = [HidIoCommandId::ManufacturingTest];
+    let ids = [HidIoCommandId::KllLayoutControl];
 
     // Setup command interface
-    let mut intf = CommandInterface::<U8, U8, U64, U150, U165, U1>::new(&ids).unwrap();
+    let mut intf = CommandInterface::<U8, U8, U64, U150, U1>::new(&ids).unwrap();
 
-    // Send valid command (expect ack)
-    let cmd = h0050::Cmd {
-        command: 0,
-        argument: 0,
+    // Begin upload (expect ack)
+    let cmd = h001f::Cmd {
+        action: h001f::Action::Begin,
+        arg: 1024,
     };
-    let send = intf.h0050_manufacturing(cmd);
-    assert!(send.is_ok(), "h0050_manufacturing(ack) => {:?}", send);
+    let send = intf.h001f_klllayoutcontrol(cmd);
+    assert!(send.is_ok(), "h001f_klllayoutcontrol(ack) => {:?}", send);
 
     // Flush tx->rx
     // Process rx buffer
@@ -973,13 +1547,13 @@ fn h0050_manufacturing() {
     let process = intf.process_rx();
     assert!(process.is_ok(), "process_rx2 => {:?}", process);
 
-    // Send invalid command (expect nak)
-    let cmd = h0050::Cmd {
-        command: 1200,
-        argument: 5,
+    // Rollback without a previous layout (expect nak)
+    let cmd = h001f::Cmd {
+        action: h001f::Action::Rollback,
+        arg: 0,
     };
-    let send = intf.h0050_manufacturing(cmd);
-    assert!(send.is_ok(), "h0050_manufacturing(nak) => {:?}", send);
+    let send = intf.h001f_klllayoutcontrol(cmd);
+    assert!(send.is_ok(), "h001f_klllayoutcontrol(nak) => {:?}", send);
 
     // Flush tx->rx
     // Process rx buffer
@@ -989,27 +1563,77 @@ fn h0050_manufacturing() {
     // Flush tx->rx
     // Process rx buffer
     let process = intf.process_rx();
-    assert!(process.is_err(), "process_rx4 => {:?}", process);
+    assert!(process.is_ok(), "process_rx4 => {:?}", process);
 }
 
 #[test]
-fn h0051_manufacturing() {
+fn h0029_displaytext() {
     setup_logging_lite().ok();
 
     // Build list of supported ids
-    let ids = [HidIoCommandId::ManufacturingResult];
+    let ids = [HidIoCommandId::DisplayText];
 
     // Setup command interface
-    let mut intf = CommandInterface::<U8, U8, U64, U150, U165, U1>::new(&ids).unwrap();
+    let mut intf = CommandInterface::<U8, U8, U64, U150, U1>::new(&ids).unwrap();
 
-    // Send valid command (expect ack)
-    let cmd = h0051::Cmd {
-        command: 0,
-        argument: 0,
-        data: Vec::new(),
+    // Send valid widget (expect ack)
+    let cmd = h0029::Cmd {
+        widget: 0,
+        text: String::from("Layer 1"),
     };
-    let send = intf.h0051_manufacturingres(cmd);
-    assert!(send.is_ok(), "h0051_manufacturing(ack) => {:?}", send);
+    let send = intf.h0029_displaytext(cmd);
+    assert!(send.is_ok(), "h0029_displaytext(ack) => {:?}", send);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx1 => {:?}", process);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx2 => {:?}", process);
+
+    // Send invalid widget (expect nak)
+    let cmd = h0029::Cmd {
+        widget: 7,
+        text: String::from("Layer 1"),
+    };
+    let send = intf.h0029_displaytext(cmd);
+    assert!(send.is_ok(), "h0029_displaytext(nak) => {:?}", send);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx3 => {:?}", process);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx4 => {:?}", process);
+}
+
+#[test]
+fn h002a_confirmrequest() {
+    setup_logging_lite().ok();
+
+    // Build list of supported ids
+    let ids = [
+        HidIoCommandId::ConfirmRequest,
+        HidIoCommandId::ConfirmResponse,
+    ];
+
+    // Setup command interface
+    let mut intf = CommandInterface::<U8, U8, U64, U150, U1>::new(&ids).unwrap();
+
+    // Send request (expect ack)
+    let cmd = h002a::Cmd {
+        request: 1,
+        timeout: 30,
+        prompt: String::from("Type password?"),
+    };
+    let send = intf.h002a_confirmrequest(cmd);
+    assert!(send.is_ok(), "h002a_confirmrequest => {:?}", send);
 
     // Flush tx->rx
     // Process rx buffer
@@ -1020,4 +1644,840 @@ fn h0051_manufacturing() {
     // Process rx buffer
     let process = intf.process_rx();
     assert!(process.is_ok(), "process_rx2 => {:?}", process);
+
+    // Send response (expect ack)
+    let cmd = h002b::Cmd {
+        request: 1,
+        answer: h002b::Answer::Confirmed,
+    };
+    let send = intf.h002b_confirmresponse(cmd);
+    assert!(send.is_ok(), "h002b_confirmresponse => {:?}", send);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx3 => {:?}", process);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx4 => {:?}", process);
+}
+
+#[test]
+fn h002c_animationbegin() {
+    setup_logging_lite().ok();
+
+    // Build list of supported ids
+    let ids = [HidIoCommandId::AnimationBegin];
+
+    // Setup command interface
+    let mut intf = CommandInterface::<U8, U8, U64, U150, U1>::new(&ids).unwrap();
+
+    // Send valid command (expect ack)
+    let cmd = h002c::Cmd {
+        slot: 0,
+        format: h002c::Format::ThreeCh8b,
+        frames: 10,
+        frame_len: 300,
+    };
+    let send = intf.h002c_animationbegin(cmd);
+    assert!(send.is_ok(), "h002c_animationbegin(ack) => {:?}", send);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx1 => {:?}", process);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx2 => {:?}", process);
+
+    // Send invalid slot (expect nak)
+    let cmd = h002c::Cmd {
+        slot: 1,
+        format: h002c::Format::ThreeCh8b,
+        frames: 10,
+        frame_len: 300,
+    };
+    let send = intf.h002c_animationbegin(cmd);
+    assert!(send.is_ok(), "h002c_animationbegin(nak) => {:?}", send);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx3 => {:?}", process);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx4 => {:?}", process);
+}
+
+#[test]
+fn h002d_animationdata() {
+    setup_logging_lite().ok();
+
+    // Build list of supported ids
+    let ids = [HidIoCommandId::AnimationData];
+
+    // Setup command interface
+    let mut intf = CommandInterface::<U8, U8, U64, U150, U1>::new(&ids).unwrap();
+
+    // Known CRC-16/CCITT-FALSE check value
+    assert_eq!(crate::crc16(b"123456789"), 0x29B1);
+
+    // Send valid command (expect ack)
+    let data = [1, 2, 3, 4];
+    let cmd = h002d::Cmd {
+        offset: 0,
+        crc: crate::crc16(&data),
+        data: Vec::from_slice(&data).unwrap(),
+    };
+    let send = intf.h002d_animationdata(cmd);
+    assert!(send.is_ok(), "h002d_animationdata(ack) => {:?}", send);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx1 => {:?}", process);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx2 => {:?}", process);
+
+    // Send corrupted chunk (expect crc nak)
+    let cmd = h002d::Cmd {
+        offset: 0,
+        crc: crate::crc16(&data) ^ 0x1,
+        data: Vec::from_slice(&data).unwrap(),
+    };
+    let send = intf.h002d_animationdata(cmd);
+    assert!(send.is_ok(), "h002d_animationdata(nak) => {:?}", send);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx3 => {:?}", process);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx4 => {:?}", process);
+}
+
+#[test]
+fn h002e_animationfinish() {
+    setup_logging_lite().ok();
+
+    // Build list of supported ids
+    let ids = [HidIoCommandId::AnimationFinish];
+
+    // Setup command interface
+    let mut intf = CommandInterface::<U8, U8, U64, U150, U1>::new(&ids).unwrap();
+
+    // Send command
+    let cmd = h002e::Cmd {
+        action: h002e::Action::Commit,
+    };
+    let send = intf.h002e_animationfinish(cmd);
+    assert!(send.is_ok(), "h002e_animationfinish => {:?}", send);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx1 => {:?}", process);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx2 => {:?}", process);
+}
+
+#[test]
+fn h0031_terminalcmd() {
+    setup_logging_lite().ok();
+
+    // Build list of supported ids
+    let ids = [HidIoCommandId::TerminalCmd];
+
+    // Setup command interface
+    let mut intf = CommandInterface::<U8, U8, U64, U150, U1>::new(&ids).unwrap();
+
+    // Normal data packet
+    // Send command
+    let cmd = h0031::Cmd {
+        command: String::from("terminal command string\n\r"),
+    };
+    let send = intf.h0031_terminalcmd(cmd.clone(), false);
+    assert!(send.is_ok(), "h0031_terminalcmd {:?} => {:?}", cmd, send);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx1 {:?} => {:?}", cmd, process);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx2 {:?} => {:?}", cmd, process);
+
+    // NA (no-ack) data packets
+    // Send command
+    let cmd = h0031::Cmd {
+        command: String::from("na terminal command string\n\r"),
+    };
+    let send = intf.h0031_terminalcmd(cmd.clone(), true);
+    assert!(
+        send.is_ok(),
+        "h0031_terminalcmd(na) {:?} => {:?}",
+        cmd,
+        send
+    );
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx3 {:?} => {:?}", cmd, process);
+}
+
+#[test]
+fn h0034_terminalout() {
+    setup_logging_lite().ok();
+
+    // Build list of supported ids
+    let ids = [HidIoCommandId::TerminalOut];
+
+    // Setup command interface
+    let mut intf = CommandInterface::<U8, U8, U64, U150, U1>::new(&ids).unwrap();
+
+    // Normal data packet
+    // Send command
+    let cmd = h0034::Cmd {
+        output: String::from("terminal output string\n\r\t"),
+    };
+    let send = intf.h0034_terminalout(cmd.clone(), false);
+    assert!(send.is_ok(), "h0034_terminalout {:?} => {:?}", cmd, send);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx1 {:?} => {:?}", cmd, process);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx2 {:?} => {:?}", cmd, process);
+
+    // NA (no-ack) data packets
+    // Send command
+    let cmd = h0034::Cmd {
+        output: String::from("terminal na output string\n\r\t"),
+    };
+    let send = intf.h0034_terminalout(cmd.clone(), true);
+    assert!(
+        send.is_ok(),
+        "h0034_terminalout(na) {:?} => {:?}",
+        cmd,
+        send
+    );
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx3 {:?} => {:?}", cmd, process);
+}
+
+#[test]
+fn h0035_hoststate() {
+    setup_logging_lite().ok();
+
+    // Build list of supported ids
+    let ids = [HidIoCommandId::HostState];
+
+    // Setup command interface
+    let mut intf = CommandInterface::<U8, U8, U64, U150, U1>::new(&ids).unwrap();
+
+    // Normal data packet
+    // Send command
+    let cmd = h0035::Cmd {
+        state: h0035::State::Locked,
+        idle_secs: 300,
+        game_mode: false,
+    };
+    let send = intf.h0035_hoststate(cmd.clone(), false);
+    assert!(send.is_ok(), "h0035_hoststate {:?} => {:?}", cmd, send);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx1 {:?} => {:?}", cmd, process);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx2 {:?} => {:?}", cmd, process);
+
+    // NA (no-ack) data packets
+    // Send command
+    let cmd = h0035::Cmd {
+        state: h0035::State::Active,
+        idle_secs: 0,
+        game_mode: true,
+    };
+    let send = intf.h0035_hoststate(cmd.clone(), true);
+    assert!(send.is_ok(), "h0035_hoststate(na) {:?} => {:?}", cmd, send);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx3 {:?} => {:?}", cmd, process);
+}
+
+#[test]
+fn h0036_lockhost() {
+    setup_logging_lite().ok();
+
+    // Build list of supported ids
+    let ids = [HidIoCommandId::LockHost];
+
+    // Setup command interface
+    let mut intf = CommandInterface::<U8, U8, U64, U150, U1>::new(&ids).unwrap();
+
+    // Send command (expect nak)
+    let send = intf.h0036_lockhost(h0036::Cmd {});
+    assert!(send.is_ok(), "h0036_lockhost => {:?}", send);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx1 => {:?}", process);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx2 => {:?}", process);
+}
+
+#[test]
+fn h0037_fileopen() {
+    setup_logging_lite().ok();
+
+    // Build list of supported ids
+    let ids = [HidIoCommandId::FileOpen];
+
+    // Setup command interface
+    let mut intf = CommandInterface::<U8, U8, U64, U150, U1>::new(&ids).unwrap();
+
+    // Resume interrupted write (expect ack)
+    let cmd = h0037::Cmd {
+        mode: h0037::Mode::Resume,
+        size: TEST_FILE.len() as u32,
+        name: String::from("layout.kll"),
+    };
+    let send = intf.h0037_fileopen(cmd);
+    assert!(send.is_ok(), "h0037_fileopen(ack) => {:?}", send);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx1 => {:?}", process);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx2 => {:?}", process);
+
+    // Open unknown file (expect nak)
+    let cmd = h0037::Cmd {
+        mode: h0037::Mode::Read,
+        size: 0,
+        name: String::from("missing"),
+    };
+    let send = intf.h0037_fileopen(cmd);
+    assert!(send.is_ok(), "h0037_fileopen(nak) => {:?}", send);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx3 => {:?}", process);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx4 => {:?}", process);
+}
+
+#[test]
+fn h0038_filewrite() {
+    setup_logging_lite().ok();
+
+    // Build list of supported ids
+    let ids = [HidIoCommandId::FileWrite];
+
+    // Setup command interface
+    let mut intf = CommandInterface::<U8, U8, U64, U150, U1>::new(&ids).unwrap();
+
+    // Write remainder of the file (expect ack)
+    let cmd = h0038::Cmd {
+        handle: 1,
+        offset: 2,
+        data: Vec::from_slice(&TEST_FILE[2..]).unwrap(),
+    };
+    let send = intf.h0038_filewrite(cmd);
+    assert!(send.is_ok(), "h0038_filewrite(ack) => {:?}", send);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx1 => {:?}", process);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx2 => {:?}", process);
+
+    // Write past the stored data (expect nak)
+    let cmd = h0038::Cmd {
+        handle: 1,
+        offset: 8,
+        data: Vec::from_slice(&TEST_FILE[2..]).unwrap(),
+    };
+    let send = intf.h0038_filewrite(cmd);
+    assert!(send.is_ok(), "h0038_filewrite(nak) => {:?}", send);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx3 => {:?}", process);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx4 => {:?}", process);
+}
+
+#[test]
+fn h0039_fileread() {
+    setup_logging_lite().ok();
+
+    // Build list of supported ids
+    let ids = [HidIoCommandId::FileRead];
+
+    // Setup command interface
+    let mut intf = CommandInterface::<U8, U8, U64, U150, U1>::new(&ids).unwrap();
+
+    // Read past the end of the file (expect short ack)
+    let cmd = h0039::Cmd {
+        handle: 1,
+        offset: 2,
+        len: 16,
+    };
+    let send = intf.h0039_fileread(cmd);
+    assert!(send.is_ok(), "h0039_fileread(ack) => {:?}", send);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx1 => {:?}", process);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx2 => {:?}", process);
+
+    // Read at an invalid offset (expect nak)
+    let cmd = h0039::Cmd {
+        handle: 1,
+        offset: 8,
+        len: 16,
+    };
+    let send = intf.h0039_fileread(cmd);
+    assert!(send.is_ok(), "h0039_fileread(nak) => {:?}", send);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx3 => {:?}", process);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx4 => {:?}", process);
+}
+
+#[test]
+fn h003a_filechecksum() {
+    setup_logging_lite().ok();
+
+    // Build list of supported ids
+    let ids = [HidIoCommandId::FileChecksum];
+
+    // Setup command interface
+    let mut intf = CommandInterface::<U8, U8, U64, U150, U1>::new(&ids).unwrap();
+
+    // Checksum of the whole file (expect ack)
+    let cmd = h003a::Cmd {
+        handle: 1,
+        offset: 0,
+        len: TEST_FILE.len() as u32,
+    };
+    let send = intf.h003a_filechecksum(cmd);
+    assert!(send.is_ok(), "h003a_filechecksum(ack) => {:?}", send);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx1 => {:?}", process);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx2 => {:?}", process);
+
+    // Invalid handle (expect nak)
+    let cmd = h003a::Cmd {
+        handle: 2,
+        offset: 0,
+        len: TEST_FILE.len() as u32,
+    };
+    let send = intf.h003a_filechecksum(cmd);
+    assert!(send.is_ok(), "h003a_filechecksum(nak) => {:?}", send);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx3 => {:?}", process);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx4 => {:?}", process);
+}
+
+#[test]
+fn h003b_fileclose() {
+    setup_logging_lite().ok();
+
+    // Build list of supported ids
+    let ids = [HidIoCommandId::FileClose];
+
+    // Setup command interface
+    let mut intf = CommandInterface::<U8, U8, U64, U150, U1>::new(&ids).unwrap();
+
+    // Commit (expect ack)
+    let cmd = h003b::Cmd {
+        handle: 1,
+        action: h003b::Action::Commit,
+    };
+    let send = intf.h003b_fileclose(cmd);
+    assert!(send.is_ok(), "h003b_fileclose(ack) => {:?}", send);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx1 => {:?}", process);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx2 => {:?}", process);
+
+    // Invalid handle (expect nak)
+    let cmd = h003b::Cmd {
+        handle: 2,
+        action: h003b::Action::Abort,
+    };
+    let send = intf.h003b_fileclose(cmd);
+    assert!(send.is_ok(), "h003b_fileclose(nak) => {:?}", send);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx3 => {:?}", process);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx4 => {:?}", process);
+}
+
+#[test]
+fn h0050_manufacturing() {
+    setup_logging_lite().ok();
+
+    // Build list of supported ids
+    let ids = [HidIoCommandId::ManufacturingTest];
+
+    // Setup command interface
+    let mut intf = CommandInterface::<U8, U8, U64, U150, U1>::new(&ids).unwrap();
+
+    // Send valid command (expect ack)
+    let cmd = h0050::Cmd {
+        command: 0,
+        argument: 0,
+    };
+    let send = intf.h0050_manufacturing(cmd);
+    assert!(send.is_ok(), "h0050_manufacturing(ack) => {:?}", send);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx1 => {:?}", process);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx2 => {:?}", process);
+
+    // Send invalid command (expect nak)
+    let cmd = h0050::Cmd {
+        command: 1200,
+        argument: 5,
+    };
+    let send = intf.h0050_manufacturing(cmd);
+    assert!(send.is_ok(), "h0050_manufacturing(nak) => {:?}", send);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx3 => {:?}", process);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_err(), "process_rx4 => {:?}", process);
+}
+
+#[test]
+fn h0051_manufacturing() {
+    setup_logging_lite().ok();
+
+    // Build list of supported ids
+    let ids = [HidIoCommandId::ManufacturingResult];
+
+    // Setup command interface
+    let mut intf = CommandInterface::<U8, U8, U64, U150, U1>::new(&ids).unwrap();
+
+    // Send valid command (expect ack)
+    let cmd = h0051::Cmd {
+        command: 0,
+        argument: 0,
+        data: Vec::new(),
+    };
+    let send = intf.h0051_manufacturingres(cmd);
+    assert!(send.is_ok(), "h0051_manufacturing(ack) => {:?}", send);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx1 => {:?}", process);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx2 => {:?}", process);
+
+    // Send valid command with payload (expect ack)
+    let cmd = h0051::Cmd {
+        command: 4,
+        argument: 1,
+        data: Vec::from_slice(&[1, 2, 3, 4, 5]).unwrap(),
+    };
+    let send = intf.h0051_manufacturingres(cmd);
+    assert!(send.is_ok(), "h0051_manufacturing(payload) => {:?}", send);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx3 => {:?}", process);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx4 => {:?}", process);
+}
+
+#[test]
+fn vendor_passthrough() {
+    setup_logging_lite().ok();
+
+    // Build list of supported ids
+    let ids = [HidIoCommandId::Vendor3];
+
+    // Setup command interface
+    let mut intf = CommandInterface::<U8, U8, U64, U150, U1>::new(&ids).unwrap();
+
+    // Only vendor ids may be sent as raw payloads
+    let cmd = vendor::Cmd {
+        data: Vec::from_slice(&[0xDE, 0xAD, 0xBE, 0xEF]).unwrap(),
+    };
+    let send = intf.vendor(HidIoCommandId::FlashMode, cmd.clone(), false);
+    assert!(send.is_err(), "vendor(FlashMode) => {:?}", send);
+
+    // Send command
+    let send = intf.vendor(HidIoCommandId::Vendor3, cmd, false);
+    assert!(send.is_ok(), "vendor => {:?}", send);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx1 => {:?}", process);
+
+    // Flush tx->rx
+    // Process rx buffer
+    let process = intf.process_rx();
+    assert!(process.is_ok(), "process_rx2 => {:?}", process);
+}
+
+/// Command payloads received by VectorInterface
+#[derive(Debug, PartialEq)]
+enum Received {
+    H0004Cmd(h0004::Cmd),
+    H0004Ack(h0004::Ack),
+    H002CCmd(h002c::Cmd),
+    H0035NaCmd(h0035::Cmd),
+    H003aCmd(h003a::Cmd),
+    H003aAck(h003a::Ack),
+    H003bCmd(h003b::Cmd),
+    H0050Cmd(h0050::Cmd),
+}
+
+/// Keeps the last sent packet and received command payload
+/// Cmds with an Ack vector are answered using it.
+struct VectorInterface {
+    sent: Vec<u8, U64>,
+    received: Option<Received>,
+}
+
+impl VectorInterface {
+    /// Decode a single golden packet and handle it
+    fn receive(&mut self, bytes: &[u8]) -> Result<Option<Received>, CommandError> {
+        let mut buf = HidIoPacketBuffer::<U64>::new();
+        if let Err(e) = buf.decode_packet(bytes) {
+            return Err(CommandError::PacketDecodeError(e));
+        }
+        self.received = None;
+        self.rx_message_handling(buf)?;
+        Ok(self.received.take())
+    }
+}
+
+impl Commands<U64, U1> for VectorInterface {
+    fn tx_packetbuffer_send(
+        &mut self,
+        buf: &mut HidIoPacketBuffer<U64>,
+    ) -> Result<(), CommandError> {
+        let mut chunk = [0u8; 64];
+        let (len, _) = match buf.serialize_chunk(0, &mut chunk) {
+            Ok(result) => result,
+            Err(err) => {
+                return Err(CommandError::SerializationFailed(err));
+            }
+        };
+        self.sent = Vec::from_slice(&chunk[..len]).unwrap();
+        Ok(())
+    }
+
+    fn h0004_capabilities_cmd(&mut self, data: h0004::Cmd) -> Result<h0004::Ack, h0004::Nak> {
+        self.received = Some(Received::H0004Cmd(data));
+        Ok(vectors::H0004_ACK.value)
+    }
+    fn h0004_capabilities_ack(&mut self, data: h0004::Ack) -> Result<(), CommandError> {
+        self.received = Some(Received::H0004Ack(data));
+        Ok(())
+    }
+
+    fn h002c_animationbegin_cmd(&mut self, data: h002c::Cmd) -> Result<h002c::Ack, h002c::Nak> {
+        self.received = Some(Received::H002CCmd(data));
+        Ok(h002c::Ack {})
+    }
+
+    fn h0035_hoststate_nacmd(&mut self, data: h0035::Cmd) -> Result<(), CommandError> {
+        self.received = Some(Received::H0035NaCmd(data));
+        Ok(())
+    }
+
+    fn h003a_filechecksum_cmd(&mut self, data: h003a::Cmd) -> Result<h003a::Ack, h003a::Nak> {
+        self.received = Some(Received::H003aCmd(data));
+        Ok(vectors::H003A_ACK.value)
+    }
+    fn h003a_filechecksum_ack(&mut self, data: h003a::Ack) -> Result<(), CommandError> {
+        self.received = Some(Received::H003aAck(data));
+        Ok(())
+    }
+
+    fn h003b_fileclose_cmd(&mut self, data: h003b::Cmd) -> Result<h003b::Ack, h003b::Nak> {
+        self.received = Some(Received::H003bCmd(data));
+        Ok(h003b::Ack {})
+    }
+
+    fn h0050_manufacturing_cmd(&mut self, data: h0050::Cmd) -> Result<h0050::Ack, h0050::Nak> {
+        self.received = Some(Received::H0050Cmd(data));
+        Ok(h0050::Ack {})
+    }
+}
+
+/// Serializes and parses the golden command payload vectors
+#[test]
+fn command_vectors() {
+    setup_logging_lite().ok();
+
+    let mut intf = VectorInterface {
+        sent: Vec::new(),
+        received: None,
+    };
+
+    // struct -> bytes
+    intf.h0004_capabilities(vectors::H0004_CMD.value).unwrap();
+    assert_eq!(&intf.sent[..], vectors::H0004_CMD.bytes);
+    intf.h002c_animationbegin(vectors::H002C_CMD.value).unwrap();
+    assert_eq!(&intf.sent[..], vectors::H002C_CMD.bytes);
+    intf.h0035_hoststate(vectors::H0035_NACMD.value, true)
+        .unwrap();
+    assert_eq!(&intf.sent[..], vectors::H0035_NACMD.bytes);
+    intf.h003a_filechecksum(vectors::H003A_CMD.value).unwrap();
+    assert_eq!(&intf.sent[..], vectors::H003A_CMD.bytes);
+    intf.h003b_fileclose(vectors::H003B_CMD.value).unwrap();
+    assert_eq!(&intf.sent[..], vectors::H003B_CMD.bytes);
+    intf.h0050_manufacturing(vectors::H0050_CMD.value).unwrap();
+    assert_eq!(&intf.sent[..], vectors::H0050_CMD.bytes);
+
+    // bytes -> struct, Acks are serialized by the handlers
+    assert_eq!(
+        intf.receive(vectors::H0004_CMD.bytes).unwrap(),
+        Some(Received::H0004Cmd(vectors::H0004_CMD.value))
+    );
+    assert_eq!(&intf.sent[..], vectors::H0004_ACK.bytes);
+    assert_eq!(
+        intf.receive(vectors::H0004_ACK.bytes).unwrap(),
+        Some(Received::H0004Ack(vectors::H0004_ACK.value))
+    );
+    assert_eq!(
+        intf.receive(vectors::H002C_CMD.bytes).unwrap(),
+        Some(Received::H002CCmd(vectors::H002C_CMD.value))
+    );
+    assert_eq!(
+        intf.receive(vectors::H0035_NACMD.bytes).unwrap(),
+        Some(Received::H0035NaCmd(vectors::H0035_NACMD.value))
+    );
+    assert_eq!(
+        intf.receive(vectors::H003A_CMD.bytes).unwrap(),
+        Some(Received::H003aCmd(vectors::H003A_CMD.value))
+    );
+    assert_eq!(&intf.sent[..], vectors::H003A_ACK.bytes);
+    assert_eq!(
+        intf.receive(vectors::H003A_ACK.bytes).unwrap(),
+        Some(Received::H003aAck(vectors::H003A_ACK.value))
+    );
+    assert_eq!(
+        intf.receive(vectors::H003B_CMD.bytes).unwrap(),
+        Some(Received::H003bCmd(vectors::H003B_CMD.value))
+    );
+    assert_eq!(
+        intf.receive(vectors::H0050_CMD.bytes).unwrap(),
+        Some(Received::H0050Cmd(vectors::H0050_CMD.value))
+    );
 }
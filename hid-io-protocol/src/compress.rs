@@ -0,0 +1,181 @@
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in
+ * all copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+ * THE SOFTWARE.
+ */
+
+// ----- Crates -----
+
+use super::HidIoParseError;
+
+// ----- Consts -----
+
+/// Payload encoding byte, payload follows as-is
+pub const RAW: u8 = 0;
+
+/// Payload encoding byte, compressed payload follows
+pub const COMPRESSED: u8 = 1;
+
+/// Shortest match that is encoded (a match token is 3 bytes)
+const MIN_MATCH: usize = 4;
+
+/// Longest match that fits in a match token
+const MAX_MATCH: usize = 0x7F + MIN_MATCH;
+
+/// Longest literal run that fits in a literal token
+const MAX_LITERALS: usize = 0x80;
+
+/// Furthest back a match may reference
+const MAX_OFFSET: usize = 0xFFFF;
+
+/// Number of match candidates tracked by the compressor
+const HASH_SIZE: usize = 256;
+
+// ----- Functions -----
+
+/// Hash of the MIN_MATCH bytes at the start of data
+fn hash(data: &[u8]) -> usize {
+    let value = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+    (value.wrapping_mul(2_654_435_761) >> 24) as usize
+}
+
+/// Writes literal tokens to output, starting at out
+/// Returns the new output position, None if output is too small.
+fn literals(literals: &[u8], output: &mut [u8], mut out: usize) -> Option<usize> {
+    for run in literals.chunks(MAX_LITERALS) {
+        if out + 1 + run.len() > output.len() {
+            return None;
+        }
+        output[out] = (run.len() - 1) as u8;
+        output[out + 1..out + 1 + run.len()].copy_from_slice(run);
+        out += 1 + run.len();
+    }
+    Some(out)
+}
+
+/// Compresses input into output
+/// Returns the compressed length, None if the input does not compress into output or is not
+/// made any smaller.
+///
+/// # Remarks
+/// Byte oriented LZ77, small enough for devices (no heap, 256 entry match table).
+/// The compressed stream is a sequence of tokens:
+///
+/// ```text
+/// 0LLL LLLL <literals>         - L + 1 literal bytes follow (1..128)
+/// 1LLL LLLL <offset lo/hi>     - Copy L + 4 bytes (4..131) from offset bytes back
+/// ```
+pub fn compress(input: &[u8], output: &mut [u8]) -> Option<usize> {
+    let mut table = [usize::MAX; HASH_SIZE];
+    let mut out = 0;
+    let mut literal_start = 0;
+    let mut pos = 0;
+
+    while pos + MIN_MATCH <= input.len() {
+        let hash = hash(&input[pos..]);
+        let candidate = table[hash];
+        table[hash] = pos;
+
+        // Check for a match (hashes may collide)
+        if candidate == usize::MAX
+            || pos - candidate > MAX_OFFSET
+            || input[candidate..candidate + MIN_MATCH] != input[pos..pos + MIN_MATCH]
+        {
+            pos += 1;
+            continue;
+        }
+
+        // Extend match, may overlap the current position
+        let mut len = MIN_MATCH;
+        while len < MAX_MATCH
+            && pos + len < input.len()
+            && input[candidate + len] == input[pos + len]
+        {
+            len += 1;
+        }
+
+        // Flush pending literals, then the match
+        out = literals(&input[literal_start..pos], output, out)?;
+        if out + 3 > output.len() {
+            return None;
+        }
+        let offset = pos - candidate;
+        output[out] = 0x80 | (len - MIN_MATCH) as u8;
+        output[out + 1] = offset as u8;
+        output[out + 2] = (offset >> 8) as u8;
+        out += 3;
+
+        pos += len;
+        literal_start = pos;
+    }
+
+    // Remaining literals
+    out = literals(&input[literal_start..], output, out)?;
+    if out >= input.len() {
+        return None;
+    }
+    Some(out)
+}
+
+/// Decompresses input into output
+/// Returns the decompressed length.
+pub fn decompress(input: &[u8], output: &mut [u8]) -> Result<usize, HidIoParseError> {
+    let mut pos = 0;
+    let mut out = 0;
+
+    while pos < input.len() {
+        let token = input[pos];
+        pos += 1;
+
+        // Literals
+        if token & 0x80 == 0 {
+            let len = token as usize + 1;
+            if pos + len > input.len() {
+                return Err(HidIoParseError::InvalidCompressedPayload(pos));
+            }
+            if out + len > output.len() {
+                return Err(HidIoParseError::DecompressedPayloadTooLarge(output.len()));
+            }
+            output[out..out + len].copy_from_slice(&input[pos..pos + len]);
+            pos += len;
+            out += len;
+            continue;
+        }
+
+        // Match
+        if pos + 2 > input.len() {
+            return Err(HidIoParseError::InvalidCompressedPayload(pos));
+        }
+        let len = (token & 0x7F) as usize + MIN_MATCH;
+        let offset = input[pos] as usize | (input[pos + 1] as usize) << 8;
+        pos += 2;
+        if offset == 0 || offset > out {
+            return Err(HidIoParseError::InvalidCompressedPayload(pos));
+        }
+        if out + len > output.len() {
+            return Err(HidIoParseError::DecompressedPayloadTooLarge(output.len()));
+        }
+        // Byte by byte, the match may overlap itself
+        for idx in out..out + len {
+            output[idx] = output[idx - offset];
+        }
+        out += len;
+    }
+
+    Ok(out)
+}
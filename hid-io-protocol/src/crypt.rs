@@ -0,0 +1,116 @@
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in
+ * all copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+ * THE SOFTWARE.
+ */
+
+// ----- Crates -----
+
+use super::HidIoParseError;
+use chacha20poly1305::aead::{AeadInPlace, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce, Tag};
+
+// ----- Consts -----
+
+/// Pre-shared key length (ChaCha20-Poly1305)
+pub const KEY_LEN: usize = 32;
+
+/// Length of the message counter prepended to encrypted payloads
+pub const COUNTER_LEN: usize = 8;
+
+/// Length of the authentication tag appended to encrypted payloads
+pub const TAG_LEN: usize = 16;
+
+/// Bytes added to each encrypted payload
+pub const OVERHEAD: usize = COUNTER_LEN + TAG_LEN;
+
+// ----- Enumerations -----
+
+/// Direction of an encrypted payload
+/// Part of the nonce, so both sides can count from 0 using the same key.
+#[repr(u8)]
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Direction {
+    HostToDevice = 0,
+    DeviceToHost = 1,
+}
+
+// ----- Structs -----
+
+/// Authenticated encryption of packet payloads using a pre-shared (paired) key
+pub struct PayloadCipher {
+    cipher: ChaCha20Poly1305,
+}
+
+impl PayloadCipher {
+    pub fn new(key: &[u8; KEY_LEN]) -> PayloadCipher {
+        PayloadCipher {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(key)),
+        }
+    }
+
+    /// 96-bit nonce, <direction:8 bit> <0:24 bit> <counter:64 bit>
+    fn nonce(direction: Direction, counter: u64) -> [u8; 12] {
+        let mut nonce = [0; 12];
+        nonce[0] = direction as u8;
+        nonce[4..].copy_from_slice(&counter.to_le_bytes());
+        nonce
+    }
+
+    /// Encrypts buffer in place, returning the authentication tag
+    /// A counter must never be reused with the same key and direction.
+    pub fn encrypt(
+        &self,
+        direction: Direction,
+        counter: u64,
+        associated_data: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<[u8; TAG_LEN], HidIoParseError> {
+        let nonce = PayloadCipher::nonce(direction, counter);
+        let tag = self
+            .cipher
+            .encrypt_in_place_detached(Nonce::from_slice(&nonce), associated_data, buffer)
+            .map_err(|_| HidIoParseError::EncryptionFailed)?;
+        let mut result = [0; TAG_LEN];
+        result.copy_from_slice(&tag);
+        Ok(result)
+    }
+
+    /// Decrypts buffer in place, after validating the authentication tag
+    pub fn decrypt(
+        &self,
+        direction: Direction,
+        counter: u64,
+        associated_data: &[u8],
+        buffer: &mut [u8],
+        tag: &[u8],
+    ) -> Result<(), HidIoParseError> {
+        if tag.len() != TAG_LEN {
+            return Err(HidIoParseError::InvalidEncryptedPayload(tag.len()));
+        }
+        let nonce = PayloadCipher::nonce(direction, counter);
+        self.cipher
+            .decrypt_in_place_detached(
+                Nonce::from_slice(&nonce),
+                associated_data,
+                buffer,
+                Tag::from_slice(tag),
+            )
+            .map_err(|_| HidIoParseError::DecryptionFailed)
+    }
+}
@@ -27,7 +27,12 @@
 
 pub mod buffer;
 pub mod commands;
+pub mod compress;
+#[cfg(feature = "encryption")]
+pub mod crypt;
 pub mod test;
+#[cfg(any(test, feature = "test-vectors"))]
+pub mod vectors;
 
 // ----- Crates -----
 
@@ -57,6 +62,14 @@ macro_rules! error {
     ($($arg:tt)+) => {};
 }
 
+// ----- Consts -----
+
+/// First vendor specific / experimental command Id
+pub const VENDOR_ID_START: u32 = 0x60;
+
+/// Last vendor specific / experimental command Id
+pub const VENDOR_ID_END: u32 = 0x6F;
+
 // ----- Enumerations -----
 
 /// HID-IO Packet Types
@@ -64,6 +77,11 @@ macro_rules! error {
 /// # Remarks
 /// Must not be larger than 0x7, 7 is reserved.
 #[derive(PartialEq, Clone, Copy, Debug)]
+#[cfg_attr(
+    feature = "serde-support",
+    derive(serde::Serialize, serde::Deserialize)
+)]
+#[cfg_attr(feature = "arbitrary-support", derive(arbitrary::Arbitrary))]
 pub enum HidIoPacketType {
     /// Data packet
     Data = 0,
@@ -83,13 +101,20 @@ pub enum HidIoPacketType {
 
 #[repr(u32)]
 #[derive(PartialEq, Clone, Copy, Debug, IntoPrimitive, TryFromPrimitive)]
+#[cfg_attr(
+    feature = "serde-support",
+    derive(serde::Serialize, serde::Deserialize)
+)]
+#[cfg_attr(feature = "arbitrary-support", derive(arbitrary::Arbitrary))]
 /// Requests for to perform a specific action
 pub enum HidIoCommandId {
     SupportedIds = 0x00,
     GetInfo = 0x01,
     TestPacket = 0x02,
     ResetHidIo = 0x03,
-    Reserved = 0x04, // ... 0x0F
+    Capabilities = 0x04,
+    Pair = 0x05,
+    Reserved = 0x06, // ... 0x0F
 
     GetProperties = 0x10,
     KeyState = 0x11,
@@ -102,6 +127,11 @@ pub enum HidIoCommandId {
     UnicodeState = 0x18,
     HostMacro = 0x19,
     SleepMode = 0x1A,
+    SettingsRead = 0x1B,
+    SettingsWrite = 0x1C,
+    KllLayoutRead = 0x1D,
+    KllLayoutWrite = 0x1E,
+    KllLayoutControl = 0x1F,
 
     KllState = 0x20,
     PixelSetting = 0x21,
@@ -109,12 +139,26 @@ pub enum HidIoCommandId {
     PixelSet3c8b = 0x23,
     PixelSet1c16b = 0x24,
     PixelSet3c16b = 0x25,
+    // 0x26 is reserved for direct pixel buffer set
+    DisplayText = 0x29,
+    ConfirmRequest = 0x2A,
+    ConfirmResponse = 0x2B,
+    AnimationBegin = 0x2C,
+    AnimationData = 0x2D,
+    AnimationFinish = 0x2E,
 
     OpenUrl = 0x30,
     TerminalCmd = 0x31,
     GetInputLayout = 0x32,
     SetInputLayout = 0x33,
     TerminalOut = 0x34,
+    HostState = 0x35,
+    LockHost = 0x36,
+    FileOpen = 0x37,
+    FileWrite = 0x38,
+    FileRead = 0x39,
+    FileChecksum = 0x3A,
+    FileClose = 0x3B,
 
     HidKeyboard = 0x40,
     HidKeyboardLed = 0x41,
@@ -126,6 +170,24 @@ pub enum HidIoCommandId {
     ManufacturingTest = 0x50,
     ManufacturingResult = 0x51,
 
+    // Vendor specific / experimental, passed through as raw payloads
+    Vendor0 = 0x60,
+    Vendor1 = 0x61,
+    Vendor2 = 0x62,
+    Vendor3 = 0x63,
+    Vendor4 = 0x64,
+    Vendor5 = 0x65,
+    Vendor6 = 0x66,
+    Vendor7 = 0x67,
+    Vendor8 = 0x68,
+    Vendor9 = 0x69,
+    VendorA = 0x6A,
+    VendorB = 0x6B,
+    VendorC = 0x6C,
+    VendorD = 0x6D,
+    VendorE = 0x6E,
+    VendorF = 0x6F,
+
     Unused = 0xFFFF,
 }
 
@@ -135,17 +197,62 @@ pub enum HidIoCommandId {
 /// thrown when there's an issue processing byte stream.
 #[derive(Debug)]
 pub enum HidIoParseError {
+    ChunkTooSmall {
+        len: usize,
+        packet_len: usize,
+    },
+    CrcMismatch {
+        expected: u16,
+        calculated: u16,
+    },
+    DecompressedPayloadTooLarge(usize),
+    IncompleteBuffer {
+        id: HidIoCommandId,
+        ptype: HidIoPacketType,
+    },
+    DecryptionFailed,
+    EncryptionFailed,
+    InvalidChunkOffset(usize),
+    InvalidCompressedPayload(usize),
     InvalidContinuedIdByte(u8),
+    InvalidEncryptedPayload(usize),
     InvalidHidIoCommandId(u32),
     InvalidPacketIdWidth(u8),
+    InvalidPacketLen(u32),
     InvalidPacketType(u8),
+    InvalidPayloadEncoding(u8),
     MissingContinuedIdByte,
+    MissingCrcByte,
+    MissingCrcBytes {
+        len: u32,
+    },
     MissingPacketIdWidthByte,
     MissingPacketTypeByte,
     MissingPayloadLengthByte,
-    NotEnoughActualBytesPacketId { len: usize, id_width: usize },
-    NotEnoughPossibleBytesPacketId { len: u32, id_width: usize },
+    NotEnoughActualBytesPacketId {
+        len: usize,
+        id_width: usize,
+    },
+    NotEnoughActualBytesPayload {
+        len: u32,
+        payload_len: u32,
+    },
+    NotEnoughPossibleBytesPacketId {
+        len: u32,
+        id_width: usize,
+    },
     PayloadAddFailed(usize),
+    PayloadTooLarge {
+        len: usize,
+        max: usize,
+    },
+    ReplayedPayload(u64),
+    SequenceGap {
+        id: HidIoCommandId,
+        ptype: HidIoPacketType,
+        expected: u16,
+        received: Option<u16>,
+    },
     SerializationError,
     SerializationFailedResultTooSmall(usize),
     VecAddFailed,
@@ -165,6 +272,9 @@ pub struct HidIoPacketBuffer<H: ArrayLength<u8>> {
     pub ptype: HidIoPacketType,
     /// Packet Id
     pub id: HidIoCommandId,
+    /// Id not known to this version of the protocol (id is set to HidIoCommandId::Unused)
+    /// Kept so packets of newer commands can still be passed through, see raw_id().
+    pub unknown_id: Option<u32>,
     /// Packet length for serialization (in bytes)
     pub max_len: u32,
     /// Payload data, chunking is done automatically by serializer
@@ -173,6 +283,40 @@ pub struct HidIoPacketBuffer<H: ArrayLength<u8>> {
     pub done: bool,
 }
 
+/// HID-IO Packet Options
+///
+/// # Remarks
+/// Optional packet stream extensions, only use if negotiated with the other side (h0004).
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "arbitrary-support", derive(arbitrary::Arbitrary))]
+pub struct PacketOptions {
+    /// Each packet ends with a CRC16 (serialization only, detected from the header on decode)
+    pub crc: bool,
+    /// Continued packets start with the 16-bit payload offset of their data
+    pub sequence: bool,
+}
+
+/// HID-IO Packet
+///
+/// # Remarks
+/// Borrowed view of a single decoded packet (chunk).
+/// The payload references the packet byte stream, nothing is copied.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct HidIoPacket<'a> {
+    /// Type of packet
+    pub ptype: HidIoPacketType,
+    /// Packet Id (0 for Sync packets)
+    pub id: u32,
+    /// Set if continued packets follow
+    pub cont: bool,
+    /// Set if the packet carried a (valid) CRC16
+    pub crc: bool,
+    /// Payload data (without the Id or CRC16)
+    pub payload: &'a [u8],
+    /// Number of bytes of the packet stream used by the packet
+    pub len: u32,
+}
+
 // ----- Utility Functions -----
 
 /// Determines the packet type from a byte stream
@@ -358,6 +502,53 @@ pub fn continued_packet(packet_data: &[u8]) -> Result<bool, HidIoParseError> {
     }
 }
 
+/// Determines whether the packet ends with a CRC16
+///
+/// # Arguments
+/// * `packet_data` - Vector of bytes
+///
+/// # Remarks
+/// Uses a packet byte stream to determine crc field.
+/// The CRC16 is stored Little-Endian in the final two bytes of the payload (included in len).
+///
+/// ```c
+/// struct HidIo_Packet {
+///    ... (5 bits)
+///    uint8_t           crc:1;       // 0 - No CRC, 1 - CRC16 follows payload
+///    ...
+/// };
+pub fn packet_crc(packet_data: &[u8]) -> Result<bool, HidIoParseError> {
+    // Check if the byte stream is large enough
+    if packet_data.is_empty() {
+        return Err(HidIoParseError::MissingCrcByte);
+    }
+
+    Ok(packet_data[0] & 0x04 == 0x04)
+}
+
+/// Computes the CRC16 of a byte stream
+///
+/// # Arguments
+/// * `data` - Vector of bytes
+///
+/// # Remarks
+/// CRC-16/CCITT-FALSE (poly 0x1021, init 0xFFFF).
+/// Computed bitwise, no lookup table, to keep flash usage small on devices.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for byte in data {
+        crc ^= u16::from(*byte) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
 /// Determines the starting position of the payload data
 ///
 /// # Arguments
@@ -438,6 +629,101 @@ pub fn hid_vec2bitmask(codes: &[u8]) -> Result<Vec<u8, U32>, HidIoParseError> {
 
 // ----- Implementations -----
 
+impl PacketOptions {
+    /// Bytes used by the options in each packet (CRC16 and payload offset)
+    pub fn overhead(&self) -> u32 {
+        (if self.crc { 2 } else { 0 }) + (if self.sequence { 2 } else { 0 })
+    }
+
+    /// Smallest max_len that leaves room for payload in every packet
+    /// Assumes a 32-bit id, the widest id a packet can have.
+    pub fn min_packet_len(&self) -> u32 {
+        2 + 4 + self.overhead() + 1
+    }
+}
+
+impl<'a> HidIoPacket<'a> {
+    /// Decode a single packet
+    ///
+    /// # Arguments
+    /// * `packet_data` - Vector of bytes of packet data
+    ///
+    /// # Remarks
+    /// Only the header is parsed, the payload is a slice of packet_data.
+    /// Continued packets are not reassembled, see HidIoPacketBuffer::append_packet.
+    pub fn decode(packet_data: &'a [u8]) -> Result<HidIoPacket<'a>, HidIoParseError> {
+        let packet_data_len = packet_data.len() as u32;
+
+        // Get packet type
+        let ptype = packet_type(packet_data)?;
+
+        // Sync packets have no length, Id or payload
+        if ptype == HidIoPacketType::Sync {
+            return Ok(HidIoPacket {
+                ptype,
+                id: 0,
+                cont: false,
+                crc: false,
+                payload: &[],
+                len: 1,
+            });
+        }
+
+        // Get payload_len
+        let payload_len = payload_len(packet_data)?;
+
+        // Make sure there's actually payload_len available
+        if packet_data_len - 2 < payload_len {
+            return Err(HidIoParseError::NotEnoughActualBytesPayload {
+                len: packet_data_len - 2,
+                payload_len,
+            });
+        }
+
+        // Get packet Id
+        let id = packet_id(packet_data)?;
+        let id_width_len = packet_id_width(packet_data)?;
+
+        // Validate CRC16 (covers everything before it)
+        let crc = packet_crc(packet_data)?;
+        let mut payload_end = 2 + payload_len as usize;
+        if crc {
+            if (payload_len as usize) < id_width_len + 2 {
+                return Err(HidIoParseError::MissingCrcBytes { len: payload_len });
+            }
+            payload_end -= 2;
+            let expected =
+                u16::from_le_bytes([packet_data[payload_end], packet_data[payload_end + 1]]);
+            let calculated = crc16(&packet_data[..payload_end]);
+            if expected != calculated {
+                return Err(HidIoParseError::CrcMismatch {
+                    expected,
+                    calculated,
+                });
+            }
+        }
+
+        // Payload, without the Id or CRC16
+        let payload_start = payload_start(packet_data)?;
+        let payload = &packet_data[payload_start..payload_end];
+
+        Ok(HidIoPacket {
+            ptype,
+            id,
+            cont: continued_packet(packet_data)?,
+            crc,
+            payload,
+            len: payload_len + 2,
+        })
+    }
+
+    /// Packet Id as a HidIoCommandId
+    pub fn command_id(&self) -> Result<HidIoCommandId, HidIoParseError> {
+        HidIoCommandId::try_from(self.id)
+            .map_err(|_| HidIoParseError::InvalidHidIoCommandId(self.id))
+    }
+}
+
 impl<H> Default for HidIoPacketBuffer<H>
 where
     H: ArrayLength<u8>,
@@ -446,6 +732,7 @@ where
         HidIoPacketBuffer {
             ptype: HidIoPacketType::Data,
             id: HidIoCommandId::try_from(0).unwrap(),
+            unknown_id: None,
             max_len: 64, // Default size
             data: Vec::new(),
             done: false,
@@ -475,14 +762,35 @@ impl<H: ArrayLength<u8>> HidIoPacketBuffer<H> {
     pub fn set(&mut self, buf: HidIoPacketBuffer<H>) {
         self.ptype = buf.ptype;
         self.id = buf.id;
+        self.unknown_id = buf.unknown_id;
         self.max_len = buf.max_len;
         self.data = buf.data;
         self.done = buf.done;
     }
 
+    /// Packet Id as sent on the wire, including Ids not known to this version of the protocol
+    pub fn raw_id(&self) -> u32 {
+        self.unknown_id.unwrap_or(self.id as u32)
+    }
+
+    /// Set the Id from its wire value
+    /// Unknown Ids are kept in unknown_id, id is set to HidIoCommandId::Unused.
+    pub fn set_raw_id(&mut self, id: u32) {
+        match HidIoCommandId::try_from(id) {
+            Ok(id) => {
+                self.id = id;
+                self.unknown_id = None;
+            }
+            Err(_) => {
+                self.id = HidIoCommandId::Unused;
+                self.unknown_id = Some(id);
+            }
+        }
+    }
+
     /// Determine id_width
     fn id_width(&self) -> u8 {
-        match self.id as u32 {
+        match self.raw_id() {
             0x00..=0xFFFF => 0,           // 16 bit Id
             0x01_0000..=0xFFFF_FFFF => 1, // 32 bit Id
         }
@@ -559,113 +867,348 @@ impl<H: ArrayLength<u8>> HidIoPacketBuffer<H> {
     /// Does packet decoding on the fly.
     /// Will set done parameter if this is the last packet.
     pub fn decode_packet(&mut self, packet_data: &[u8]) -> Result<u32, HidIoParseError> {
+        self.decode_packet_opts(packet_data, PacketOptions::default())
+    }
+
+    /// Append packet stream using negotiated packet options
+    /// Returns the number of bytes used.
+    ///
+    /// # Arguments
+    /// * `packet_data` - Vector of bytes of packet data
+    /// * `options` - Packet options negotiated with the sender
+    ///
+    /// # Remarks
+    /// See append_packet_opts for how missing Continued packets are handled.
+    pub fn decode_packet_opts(
+        &mut self,
+        packet_data: &[u8],
+        options: PacketOptions,
+    ) -> Result<u32, HidIoParseError> {
         // Check if buffer was already finished
         if self.done {
             warn!("HidIoPacketBuffer is already 'done'");
             return Ok(0);
         }
 
-        let packet_data_len = packet_data.len() as u32;
+        let packet = match HidIoPacket::decode(packet_data) {
+            Ok(packet) => packet,
+            Err(_e @ HidIoParseError::NotEnoughActualBytesPayload { .. }) => {
+                warn!(
+                    "Dropping. Not enough bytes available in packet stream: {:?}",
+                    _e
+                );
+                return Ok(packet_data.len() as u32);
+            }
+            Err(e) => {
+                return Err(e);
+            }
+        };
+        self.append_packet_opts(&packet, options)
+    }
 
-        // Get packet type
-        let ptype = packet_type(packet_data)?;
+    /// Append decoded packet
+    /// Returns the number of bytes used.
+    ///
+    /// # Arguments
+    /// * `packet` - Decoded packet
+    ///
+    /// # Remarks
+    /// Reassembles continued packets, copying the payload into the buffer.
+    /// Will set done parameter if this is the last packet.
+    pub fn append_packet(&mut self, packet: &HidIoPacket) -> Result<u32, HidIoParseError> {
+        self.append_packet_opts(packet, PacketOptions::default())
+    }
+
+    /// Append decoded packet using negotiated packet options
+    /// Returns the number of bytes used.
+    ///
+    /// # Arguments
+    /// * `packet` - Decoded packet
+    /// * `options` - Packet options negotiated with the sender
+    ///
+    /// # Remarks
+    /// Reassembles continued packets, copying the payload into the buffer.
+    /// Will set done parameter if this is the last packet.
+    ///
+    /// If Continued packets went missing the buffer is cleared and an error returned so the
+    /// incomplete packet can be Nak'd:
+    /// * IncompleteBuffer - A new packet (or a Continued packet of another packet) was received
+    ///   before the buffer was complete.
+    /// * SequenceGap - The payload offset of a Continued packet does not match the received
+    ///   data (only with options.sequence).
+    ///
+    /// In both cases the packet was not used, decode it again. A new packet starts a new buffer,
+    /// Continued packets without a pending buffer are dropped.
+    pub fn append_packet_opts(
+        &mut self,
+        packet: &HidIoPacket,
+        options: PacketOptions,
+    ) -> Result<u32, HidIoParseError> {
+        // Check if buffer was already finished
+        if self.done {
+            warn!("HidIoPacketBuffer is already 'done'");
+            return Ok(0);
+        }
+
+        let ptype = packet.ptype;
+        let packet_len = packet.len;
+        let mut payload = packet.payload;
 
         // Check if this a sync packet
         if ptype == HidIoPacketType::Sync {
             self.ptype = ptype;
             self.done = true;
-            return Ok(1);
+            return Ok(packet_len);
         }
 
-        // Get payload_len
-        let payload_len = payload_len(packet_data)?;
-        let packet_len = payload_len + 2;
+        // Get packet Id, unknown Ids are passed through
+        let id = packet.id;
+
+        match ptype {
+            HidIoPacketType::Continued | HidIoPacketType::NaContinued => {
+                // First packet went missing, nothing to continue
+                if self.data.is_empty() {
+                    warn!(
+                        "Dropping. Invalid packet type when initializing buffer, {}",
+                        ptype
+                    );
+                    return Ok(packet_len);
+                }
 
-        // Make sure there's actually payload_len available
-        if packet_data_len - 2 < payload_len {
-            warn!(
-                "Dropping. Not enough bytes available in packet stream. got:{}, expected:{}",
-                packet_data_len - 2,
-                payload_len
-            );
-            return Ok(packet_data_len);
+                // Make sure the current buffer matches what we're expecting
+                let expected = match self.ptype {
+                    HidIoPacketType::NaData => HidIoPacketType::NaContinued,
+                    _ => HidIoPacketType::Continued,
+                };
+                if self.raw_id() != id || ptype != expected {
+                    warn!(
+                        "Incomplete buffer. Invalid incoming {}:{:#x}, expected {}:{:#x}",
+                        ptype,
+                        id,
+                        expected,
+                        self.raw_id()
+                    );
+                    return Err(self.discard());
+                }
+
+                // Validate that no Continued packets were skipped
+                if options.sequence {
+                    let expected = self.data.len() as u16;
+                    let received = if payload.len() >= 2 {
+                        Some(u16::from_le_bytes([payload[0], payload[1]]))
+                    } else {
+                        None
+                    };
+                    if received != Some(expected) {
+                        let (id, ptype) = (self.id, self.ptype);
+                        self.clear();
+                        return Err(HidIoParseError::SequenceGap {
+                            id,
+                            ptype,
+                            expected,
+                            received,
+                        });
+                    }
+                    payload = &payload[2..];
+                }
+            }
+            _ => {
+                // Final packet(s) of the previous buffer went missing
+                if !self.data.is_empty() {
+                    warn!(
+                        "Incomplete buffer. New packet {}:{:#x} before the end of {}:{:#x}",
+                        ptype,
+                        id,
+                        self.ptype,
+                        self.raw_id()
+                    );
+                    return Err(self.discard());
+                }
+
+                // More information to set, if initializing buffer
+                self.ptype = ptype;
+                self.set_raw_id(id);
+            }
         }
 
-        // Get packet Id
-        let id_num = packet_id(packet_data)?;
-        let id = match HidIoCommandId::try_from(id_num) {
-            Ok(id) => id,
-            Err(_e) => {
-                error!("Failed to convert {} to HidIoCommandId: {}", id_num, _e);
-                return Err(HidIoParseError::InvalidHidIoCommandId(id_num));
+        // Check if this buffer will be completed
+        self.done = !packet.cont;
+
+        // Add payload
+        match self.data.extend_from_slice(payload) {
+            Ok(_) => {}
+            Err(_) => {
+                return Err(HidIoParseError::PayloadAddFailed(payload.len()));
             }
-        };
+        }
 
-        // Is this a new packet?
-        // More information to set, if initializing buffer
-        if self.data.is_empty()
-            && (ptype != HidIoPacketType::Continued && ptype != HidIoPacketType::NaContinued)
-        {
-            // Set packet type
-            self.ptype = ptype;
+        // Finished
+        Ok(packet_len)
+    }
+
+    /// Clears an incomplete buffer
+    /// Returns the error to report for it.
+    fn discard(&mut self) -> HidIoParseError {
+        let (id, ptype) = (self.id, self.ptype);
+        self.clear();
+        HidIoParseError::IncompleteBuffer { id, ptype }
+    }
+
+    /// Compress payload data
+    ///
+    /// # Remarks
+    /// Only for connections that negotiated compression.
+    /// The payload is prefixed with an encoding byte (compress::RAW or compress::COMPRESSED),
+    /// payloads that do not get any smaller are sent raw.
+    /// The encoding byte is always reserved, so payloads must be at least one byte smaller than
+    /// the buffer (see max_compressed_payload_len), whether or not they compress.
+    pub fn compress_payload(&mut self) -> Result<(), HidIoParseError> {
+        let max = self.max_compressed_payload_len();
+        if self.data.len() > max {
+            return Err(HidIoParseError::PayloadTooLarge {
+                len: self.data.len(),
+                max,
+            });
+        }
 
-            // Set packet id
-            self.id = id;
+        let mut data = Vec::new();
+        if data.resize_default(self.data.len()).is_err() {
+            return Err(HidIoParseError::VecResizeFailed);
+        }
 
-        // Make sure the current buffer matches what we're expecting
+        // Leave room for the encoding byte
+        let compressed = if self.data.is_empty() {
+            None
         } else {
-            // Check for invalid packet type
-            if self.data.is_empty() && ptype == HidIoPacketType::Continued {
-                warn!("Dropping. Invalid packet type when initializing buffer, HidIoPacketType::Continued");
-                return Ok(packet_len);
+            compress::compress(&self.data, &mut data[1..])
+        };
+        match compressed {
+            Some(len) => {
+                data[0] = compress::COMPRESSED;
+                data.truncate(len + 1);
             }
-            if self.data.is_empty() && ptype == HidIoPacketType::NaContinued {
-                warn!("Dropping. Invalid packet type when initializing buffer, HidIoPacketType::NaContinued");
-                return Ok(packet_len);
+            None => {
+                data.clear();
+                if data.push(compress::RAW).is_err() || data.extend_from_slice(&self.data).is_err()
+                {
+                    return Err(HidIoParseError::PayloadAddFailed(self.data.len() + 1));
+                }
             }
+        }
+        self.data = data;
+        Ok(())
+    }
 
-            // Check if not a continued packet, and we have a payload
-            if !self.data.is_empty() {
-                match ptype {
-                    HidIoPacketType::Continued | HidIoPacketType::NaContinued => {}
-                    _ => {
-                        warn!("Dropping. Invalid packet type (non-HidIoPacketType::Continued) on a already initialized buffer: {} {}", ptype, self.data.is_empty());
-                        return Ok(packet_len);
-                    }
+    /// Largest payload that can be compressed (one byte is used for the payload encoding)
+    pub fn max_compressed_payload_len(&self) -> usize {
+        self.data.capacity() - 1
+    }
+
+    /// Decompress payload data
+    ///
+    /// # Remarks
+    /// Reverses compress_payload, only for connections that negotiated compression.
+    pub fn decompress_payload(&mut self) -> Result<(), HidIoParseError> {
+        let mut data = Vec::new();
+        match self.data.first() {
+            None => {
+                return Ok(());
+            }
+            Some(&compress::RAW) => {
+                if data.extend_from_slice(&self.data[1..]).is_err() {
+                    return Err(HidIoParseError::PayloadAddFailed(self.data.len() - 1));
                 }
             }
-
-            // Validate that we're looking at the same Id
-            if self.id != id {
-                warn!(
-                    "Dropping. Invalid incoming id:{:?}, expected:{:?}",
-                    id, self.id
-                );
-                return Ok(packet_len);
+            Some(&compress::COMPRESSED) => {
+                if data.resize_default(data.capacity()).is_err() {
+                    return Err(HidIoParseError::VecResizeFailed);
+                }
+                let len = compress::decompress(&self.data[1..], &mut data)?;
+                data.truncate(len);
+            }
+            Some(&encoding) => {
+                return Err(HidIoParseError::InvalidPayloadEncoding(encoding));
             }
         }
+        self.data = data;
+        Ok(())
+    }
 
-        // Payload start
-        let payload_start = payload_start(packet_data)?;
+    /// Packet type and Id, authenticated along with encrypted payloads
+    #[cfg(feature = "encryption")]
+    fn associated_data(&self) -> [u8; 5] {
+        let id = self.raw_id().to_le_bytes();
+        [self.ptype as u8, id[0], id[1], id[2], id[3]]
+    }
 
-        // Get id_width_len
-        let id_width_len = packet_id_width(packet_data)?;
+    /// Encrypt payload data
+    ///
+    /// # Remarks
+    /// Only for paired connections, see crypt::PayloadCipher.
+    /// The payload is replaced by <counter:64 bit> <ciphertext> <tag:128 bit>, the packet type
+    /// and Id are authenticated as well. Each counter may only be used once per direction.
+    #[cfg(feature = "encryption")]
+    pub fn encrypt_payload(
+        &mut self,
+        cipher: &crypt::PayloadCipher,
+        direction: crypt::Direction,
+        counter: u64,
+    ) -> Result<(), HidIoParseError> {
+        let len = self.data.len();
+        let associated_data = self.associated_data();
+
+        // Make room for the counter and tag
+        if self.data.resize_default(len + crypt::OVERHEAD).is_err() {
+            return Err(HidIoParseError::PayloadAddFailed(len + crypt::OVERHEAD));
+        }
+        self.data.copy_within(0..len, crypt::COUNTER_LEN);
+        self.data[..crypt::COUNTER_LEN].copy_from_slice(&counter.to_le_bytes());
+
+        let payload_end = crypt::COUNTER_LEN + len;
+        let tag = cipher.encrypt(
+            direction,
+            counter,
+            &associated_data,
+            &mut self.data[crypt::COUNTER_LEN..payload_end],
+        )?;
+        self.data[payload_end..].copy_from_slice(&tag);
+        Ok(())
+    }
 
-        // Check if this buffer will be completed
-        self.done = !continued_packet(packet_data)?;
+    /// Decrypt payload data
+    ///
+    /// # Remarks
+    /// Reverses encrypt_payload, returning the counter of the payload.
+    /// Payloads with a counter that is not larger than last_counter are rejected (replayed).
+    #[cfg(feature = "encryption")]
+    pub fn decrypt_payload(
+        &mut self,
+        cipher: &crypt::PayloadCipher,
+        direction: crypt::Direction,
+        last_counter: Option<u64>,
+    ) -> Result<u64, HidIoParseError> {
+        let len = self.data.len();
+        if len < crypt::OVERHEAD {
+            return Err(HidIoParseError::InvalidEncryptedPayload(len));
+        }
 
-        // Add payload
-        let slice =
-            &packet_data[payload_start..payload_start + payload_len as usize - id_width_len];
-        match self.data.extend_from_slice(slice) {
-            Ok(_) => {}
-            Err(_) => {
-                return Err(HidIoParseError::PayloadAddFailed(slice.len()));
+        let mut counter = [0; crypt::COUNTER_LEN];
+        counter.copy_from_slice(&self.data[..crypt::COUNTER_LEN]);
+        let counter = u64::from_le_bytes(counter);
+        if let Some(last_counter) = last_counter {
+            if counter <= last_counter {
+                return Err(HidIoParseError::ReplayedPayload(counter));
             }
         }
 
-        // Finished
-        Ok(packet_len)
+        let associated_data = self.associated_data();
+        let (payload, tag) = self.data[crypt::COUNTER_LEN..].split_at_mut(len - crypt::OVERHEAD);
+        cipher.decrypt(direction, counter, &associated_data, payload, tag)?;
+
+        self.data
+            .copy_within(crypt::COUNTER_LEN..len - crypt::TAG_LEN, 0);
+        self.data.truncate(len - crypt::OVERHEAD);
+        Ok(counter)
     }
 
     /// Serialize HidIoPacketBuffer
@@ -707,6 +1250,152 @@ impl<H: ArrayLength<u8>> HidIoPacketBuffer<H> {
         let slice = &data[1..len as usize];
         Ok(slice)
     }
+
+    /// Serialize a single packet (chunk) of the HidIoPacketBuffer
+    /// Returns the number of bytes written and the payload offset of the next packet
+    /// (None after the last packet).
+    ///
+    /// # Arguments
+    /// * `offset` - Payload offset of the packet, 0 for the first packet
+    /// * `chunk` - Packet destination, at least max_len bytes (1 byte for Sync packets)
+    ///
+    /// # Remarks
+    /// Same output as serialize_buffer, one packet at a time.
+    /// Only a single chunk needs to be available, not the whole serialized buffer.
+    pub fn serialize_chunk(
+        &self,
+        offset: usize,
+        chunk: &mut [u8],
+    ) -> Result<(usize, Option<usize>), HidIoParseError> {
+        self.serialize_chunk_opts(offset, chunk, PacketOptions::default())
+    }
+
+    /// Serialize a single packet (chunk) of the HidIoPacketBuffer, with a CRC16
+    /// Same as serialize_chunk, each packet carries 2 bytes less payload.
+    ///
+    /// # Remarks
+    /// Only use if the receiver supports the CRC16 extension.
+    pub fn serialize_chunk_crc(
+        &self,
+        offset: usize,
+        chunk: &mut [u8],
+    ) -> Result<(usize, Option<usize>), HidIoParseError> {
+        self.serialize_chunk_opts(
+            offset,
+            chunk,
+            PacketOptions {
+                crc: true,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Serialize a single packet (chunk) of the HidIoPacketBuffer using negotiated packet options
+    /// Same as serialize_chunk.
+    ///
+    /// # Remarks
+    /// Only use options the receiver supports.
+    pub fn serialize_chunk_opts(
+        &self,
+        offset: usize,
+        chunk: &mut [u8],
+        options: PacketOptions,
+    ) -> Result<(usize, Option<usize>), HidIoParseError> {
+        let crc = options.crc;
+        // Check if buffer is ready to serialize
+        if !self.done {
+            error!("HidIoPacketBuffer is not 'done'");
+            return Err(HidIoParseError::SerializationError);
+        }
+
+        // Sync packets are only a header byte
+        if self.ptype == HidIoPacketType::Sync {
+            if chunk.is_empty() {
+                return Err(HidIoParseError::ChunkTooSmall {
+                    len: 0,
+                    packet_len: 1,
+                });
+            }
+            chunk[0] = (HidIoPacketType::Sync as u8) << 5;
+            return Ok((1, None));
+        }
+
+        let data_len = self.data.len();
+        if offset > data_len {
+            return Err(HidIoParseError::InvalidChunkOffset(offset));
+        }
+
+        // Continued packets may start with the payload offset
+        let seq_len = if options.sequence && offset > 0 { 2 } else { 0 };
+        let crc_len = if crc { 2 } else { 0 };
+
+        // Every packet must have room for payload, otherwise no progress is made
+        let payload_len = self
+            .max_len
+            .checked_sub(u32::from(self.hdr_len()))
+            .filter(|len| *len > options.overhead())
+            .ok_or(HidIoParseError::InvalidPacketLen(self.max_len))?;
+
+        // Determine payload slice and if continued packets follow
+        let end = core::cmp::min(offset + payload_len as usize - crc_len - seq_len, data_len);
+        let cont = end < data_len;
+
+        // Determine ptype, every packet after the first is a continued packet
+        let ptype = if offset == 0 {
+            self.ptype
+        } else {
+            match self.ptype {
+                HidIoPacketType::Ack | HidIoPacketType::Nak | HidIoPacketType::Data => {
+                    HidIoPacketType::Continued
+                }
+                HidIoPacketType::NaData => HidIoPacketType::NaContinued,
+                _ => {
+                    return Err(HidIoParseError::InvalidPacketType(self.ptype as u8));
+                }
+            }
+        };
+
+        // Determine packet len (Id + offset + payload + CRC16)
+        let id_width_len = self.id_width_len() as usize;
+        let packet_len = end - offset + id_width_len + seq_len + crc_len;
+        if chunk.len() < packet_len + 2 {
+            return Err(HidIoParseError::ChunkTooSmall {
+                len: chunk.len(),
+                packet_len: packet_len + 2,
+            });
+        }
+
+        // Header and length
+        chunk[0] = ((ptype as u8) << 5)
+            | (if cont { 1 } else { 0 } << 4)
+            | (self.id_width() << 3)
+            | (if crc { 1 } else { 0 } << 2)
+            | ((packet_len >> 8) as u8 & 0x3);
+        chunk[1] = packet_len as u8;
+
+        // Id
+        for idx in 0..id_width_len {
+            chunk[2 + idx] = (self.raw_id() >> (idx * 8)) as u8;
+        }
+
+        // Payload offset
+        let payload_start = 2 + id_width_len + seq_len;
+        if seq_len > 0 {
+            chunk[2 + id_width_len..payload_start].copy_from_slice(&(offset as u16).to_le_bytes());
+        }
+
+        // Payload
+        let payload_end = 2 + packet_len - crc_len;
+        chunk[payload_start..payload_end].copy_from_slice(&self.data[offset..end]);
+
+        // CRC16
+        if crc {
+            let crc16 = crc16(&chunk[..payload_end]).to_le_bytes();
+            chunk[payload_end..payload_end + 2].copy_from_slice(&crc16);
+        }
+
+        Ok((packet_len + 2, if cont { Some(end) } else { None }))
+    }
 }
 
 impl<H> Serialize for HidIoPacketBuffer<H>
@@ -783,7 +1472,7 @@ where
         // Convert Id into bytes
         let mut id_vec: Vec<u8, U4> = Vec::new();
         for idx in 0..id_width_len {
-            let id = (self.id as u32 >> (idx * 8)) as u8;
+            let id = (self.raw_id() >> (idx * 8)) as u8;
             if id_vec.push(id).is_err() {
                 return Err(ser::Error::custom(
                     "HidIoPacketBuffer failed to convert Id into bytes, vec add failed.",
@@ -931,6 +1620,125 @@ where
     }
 }
 
+#[cfg(feature = "serde-support")]
+impl<'de, H> serde::Deserialize<'de> for HidIoPacketBuffer<H>
+where
+    H: ArrayLength<u8>,
+{
+    /// Deserializer for HidIoPacketBuffer
+    ///
+    /// # Remarks
+    /// Reverse of the Serialize implementation, the sequence of serialized packet bytes is
+    /// decoded packet by packet.
+    /// max_len is taken from the first packet if it was continued (i.e. a full packet),
+    /// otherwise the default is used.
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_seq(HidIoPacketBufferVisitor(core::marker::PhantomData))
+    }
+}
+
+#[cfg(feature = "serde-support")]
+struct HidIoPacketBufferVisitor<H>(core::marker::PhantomData<H>);
+
+#[cfg(feature = "serde-support")]
+impl<'de, H> serde::de::Visitor<'de> for HidIoPacketBufferVisitor<H>
+where
+    H: ArrayLength<u8>,
+{
+    type Value = HidIoPacketBuffer<H>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "a sequence of serialized HID-IO packet bytes")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: serde::de::SeqAccess<'de>,
+    {
+        use serde::de::Error;
+
+        let mut buffer = HidIoPacketBuffer::new();
+        // Largest packet, 10 bit length + header and length bytes
+        let mut packet = [0u8; 0x3FF + 2];
+        let mut first = true;
+        while !buffer.done {
+            // Header byte, Sync packets are only a header byte
+            packet[0] = match seq.next_element()? {
+                Some(byte) => byte,
+                None => {
+                    return Err(A::Error::custom("incomplete HID-IO packet"));
+                }
+            };
+            let mut packet_len = 1;
+            if packet_type(&packet[..1]).map_err(|e| A::Error::custom(format_args!("{:?}", e)))?
+                != HidIoPacketType::Sync
+            {
+                // Length byte, then the rest of the packet
+                packet[1] = seq
+                    .next_element()?
+                    .ok_or_else(|| A::Error::custom("missing HID-IO packet length"))?;
+                packet_len = payload_len(&packet[..2])
+                    .map_err(|e| A::Error::custom(format_args!("{:?}", e)))?
+                    as usize
+                    + 2;
+                for byte in packet[2..packet_len].iter_mut() {
+                    *byte = seq
+                        .next_element()?
+                        .ok_or_else(|| A::Error::custom("incomplete HID-IO packet"))?;
+                }
+            }
+
+            if first && continued_packet(&packet[..1]).unwrap_or(false) {
+                buffer.max_len = packet_len as u32;
+            }
+            first = false;
+
+            buffer
+                .decode_packet(&packet[..packet_len])
+                .map_err(|e| A::Error::custom(format_args!("{:?}", e)))?;
+        }
+        Ok(buffer)
+    }
+}
+
+#[cfg(feature = "arbitrary-support")]
+impl<'a, H> arbitrary::Arbitrary<'a> for HidIoPacketBuffer<H>
+where
+    H: ArrayLength<u8>,
+{
+    /// Arbitrary (finished) HidIoPacketBuffer
+    ///
+    /// # Remarks
+    /// max_len is kept within what a single packet length field can describe and large enough
+    /// for the header, CRC16, payload offset and at least a byte of payload.
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let ptype = u.arbitrary()?;
+        let id = u.arbitrary()?;
+        let max_len = u.int_in_range(12..=0x3FF + 2)?;
+        let len = u.int_in_range(0..=<H as typenum::Unsigned>::to_usize())?;
+        let data = Vec::from_slice(u.bytes(len)?).unwrap();
+        Ok(HidIoPacketBuffer {
+            ptype,
+            id,
+            unknown_id: None,
+            max_len,
+            data,
+            done: true,
+        })
+    }
+}
+
+impl HidIoCommandId {
+    /// Vendor specific / experimental Id (0x60..0x6F)
+    /// These Ids have no protocol defined payload, it is passed through as raw bytes.
+    pub fn is_vendor(self) -> bool {
+        (VENDOR_ID_START..=VENDOR_ID_END).contains(&(self as u32))
+    }
+}
+
 impl fmt::Display for HidIoPacketType {
     /// Display formatter for HidIoPacketType
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -952,8 +1760,13 @@ impl<H: ArrayLength<u8>> fmt::Display for HidIoPacketBuffer<H> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "\n{{\n    ptype: {}\n    id: {:?}\n    max_len: {}\n    done: {}\n    data: {:#?}\n}}",
-            self.ptype, self.id, self.max_len, self.done, self.data,
+            "\n{{\n    ptype: {}\n    id: {:?} ({:#x})\n    max_len: {}\n    done: {}\n    data: {:#?}\n}}",
+            self.ptype,
+            self.id,
+            self.raw_id(),
+            self.max_len,
+            self.done,
+            self.data,
         )
     }
 }
@@ -25,7 +25,7 @@
 
 use super::*;
 use flexi_logger::Logger;
-use heapless::consts::{U0, U1, U110, U170, U240, U60, U7};
+use heapless::consts::{U0, U1, U110, U170, U240, U500, U60, U7};
 
 // ----- Enumerations -----
 
@@ -66,6 +66,24 @@ fn loopback_serializer<H: ArrayLength<u8> + core::cmp::PartialEq>(
     // Validate serialization worked
     assert!(data.len() > 0, "Serialization bytes:{}", data.len());
 
+    // Chunked serialization must produce the same packets
+    let mut chunk = [0u8; 1024];
+    let mut pos = 0;
+    let mut offset = Some(0);
+    while let Some(next) = offset {
+        let (len, next) = match buffer.serialize_chunk(next, &mut chunk[..buffer.max_len as usize])
+        {
+            Ok(result) => result,
+            Err(err) => {
+                panic!("Serialized chunk failed: {:?}", err);
+            }
+        };
+        assert_eq!(&chunk[..len], &data[pos..pos + len]);
+        pos += len;
+        offset = next;
+    }
+    assert_eq!(pos, data.len());
+
     // Deserialize while there are bytes left
     let mut deserialized = HidIoPacketBuffer::new();
     let mut bytes_used = 0;
@@ -140,6 +158,7 @@ fn no_payload_test() {
         ptype: HidIoPacketType::Data,
         // Test packet id
         id: HidIoCommandId::TestPacket,
+        unknown_id: None,
         // Standard USB 2.0 FS packet length
         max_len: 64,
         // No payload
@@ -165,6 +184,7 @@ fn single_byte_payload_test() {
         ptype: HidIoPacketType::Data,
         // Test packet id
         id: HidIoCommandId::TestPacket,
+        unknown_id: None,
         // Standard USB 2.0 FS packet length
         max_len: 64,
         // Single byte, 0xAC
@@ -190,6 +210,7 @@ fn full_packet_payload_test() {
         ptype: HidIoPacketType::Data,
         // Test packet id
         id: HidIoCommandId::TestPacket,
+        unknown_id: None,
         // Standard USB 2.0 FS packet length
         max_len: 64,
         // 60 bytes, 0xAC; requires 2 byte header, and 2 bytes for id, which is 64 bytes
@@ -215,6 +236,7 @@ fn two_packet_continued_payload_test() {
         ptype: HidIoPacketType::Data,
         // Test packet id
         id: HidIoCommandId::TestPacket,
+        unknown_id: None,
         // Standard USB 2.0 FS packet length
         max_len: 64,
         // 110 bytes, 0xAC: 60 then 50 (62 then 52)
@@ -228,6 +250,431 @@ fn two_packet_continued_payload_test() {
     loopback_serializer(buffer, &mut data);
 }
 
+/// Decodes a two packet payload without reassembly
+/// Payload slices must reference the serialized packet stream
+#[test]
+fn borrowed_packet_decode_test() {
+    setup_logging_lite().ok();
+
+    let mut buffer = HidIoPacketBuffer::<U110> {
+        ptype: HidIoPacketType::Data,
+        id: HidIoCommandId::TestPacket,
+        unknown_id: None,
+        max_len: 64,
+        data: Vec::from_slice(&[0xAC; 110]).unwrap(),
+        done: true,
+    };
+    let mut data = [0u8; 128];
+    let data = buffer.serialize_buffer(&mut data).unwrap();
+
+    // First packet, 60 bytes of payload
+    let first = HidIoPacket::decode(data).unwrap();
+    assert_eq!(first.ptype, HidIoPacketType::Data);
+    assert_eq!(first.command_id().unwrap(), HidIoCommandId::TestPacket);
+    assert!(first.cont);
+    assert_eq!(first.payload, &[0xAC; 60][..]);
+    assert_eq!(first.payload.as_ptr(), data[4..].as_ptr());
+
+    // Second packet, 50 bytes of payload
+    let second = HidIoPacket::decode(&data[first.len as usize..]).unwrap();
+    assert_eq!(second.ptype, HidIoPacketType::Continued);
+    assert!(!second.cont);
+    assert_eq!(second.payload.len(), 50);
+
+    // Reassemble
+    let mut deserialized = HidIoPacketBuffer::<U110>::new();
+    deserialized.append_packet(&first).unwrap();
+    deserialized.append_packet(&second).unwrap();
+    deserialized.max_len = buffer.max_len;
+    assert_eq!(buffer, deserialized);
+
+    // Truncated packet
+    assert!(matches!(
+        HidIoPacket::decode(&data[..10]),
+        Err(HidIoParseError::NotEnoughActualBytesPayload {
+            len: 8,
+            payload_len: 62
+        })
+    ));
+}
+
+/// Ids unknown to this version of the protocol (e.g. newer firmware) are passed through
+/// Serializes, deserializes, then checks if same as original
+#[test]
+fn unknown_id_payload_test() {
+    setup_logging_lite().ok();
+
+    let mut buffer = HidIoPacketBuffer::<U110> {
+        ptype: HidIoPacketType::Data,
+        max_len: 64,
+        data: Vec::from_slice(&[0xAC; 110]).unwrap(),
+        done: true,
+        ..Default::default()
+    };
+    buffer.set_raw_id(0x12_3456);
+    assert_eq!(buffer.id, HidIoCommandId::Unused);
+    assert_eq!(buffer.unknown_id, Some(0x12_3456));
+    assert_eq!(buffer.raw_id(), 0x12_3456);
+
+    // Known ids are not kept as unknown
+    let mut known = buffer.clone();
+    known.set_raw_id(HidIoCommandId::TestPacket as u32);
+    assert_eq!(known.id, HidIoCommandId::TestPacket);
+    assert_eq!(known.unknown_id, None);
+
+    // 32-bit id, 58 then 52 bytes of payload
+    let mut data = [0u8; 128];
+    let packet = HidIoPacket::decode(buffer.serialize_buffer(&mut data).unwrap()).unwrap();
+    assert_eq!(packet.id, 0x12_3456);
+    assert_eq!(packet.payload.len(), 58);
+
+    // Run loopback serializer, handles all test validation
+    let mut data = [0u8; 128];
+    loopback_serializer(buffer, &mut data);
+}
+
+/// Serializes a two packet payload with CRC16, then decodes and corrupts it
+#[test]
+fn crc_packet_test() {
+    setup_logging_lite().ok();
+
+    // CRC-16/CCITT-FALSE check value
+    assert_eq!(crc16(b"123456789"), 0x29B1);
+
+    let buffer = HidIoPacketBuffer::<U110> {
+        ptype: HidIoPacketType::Data,
+        id: HidIoCommandId::TestPacket,
+        unknown_id: None,
+        max_len: 64,
+        data: Vec::from_slice(&[0xAC; 110]).unwrap(),
+        done: true,
+    };
+
+    // 58 then 52 bytes of payload
+    let mut first = [0u8; 64];
+    let (len, next) = buffer.serialize_chunk_crc(0, &mut first).unwrap();
+    assert_eq!((len, next), (64, Some(58)));
+    assert_eq!(first[0], 0x14);
+    let mut second = [0u8; 64];
+    let (len, next) = buffer.serialize_chunk_crc(58, &mut second).unwrap();
+    assert_eq!((len, next), (58, None));
+
+    // Reassemble
+    let mut deserialized = HidIoPacketBuffer::<U110>::new();
+    let packet = HidIoPacket::decode(&first).unwrap();
+    assert!(packet.crc);
+    assert_eq!(packet.payload.len(), 58);
+    deserialized.append_packet(&packet).unwrap();
+    deserialized.decode_packet(&second[..len]).unwrap();
+    deserialized.max_len = buffer.max_len;
+    assert_eq!(buffer, deserialized);
+
+    // Corrupted payload byte
+    second[10] ^= 0x01;
+    assert!(matches!(
+        HidIoPacket::decode(&second[..len]),
+        Err(HidIoParseError::CrcMismatch { .. })
+    ));
+}
+
+/// Serializes a three packet payload with sequencing, then drops packets
+#[test]
+fn sequence_packet_test() {
+    setup_logging_lite().ok();
+
+    let buffer = HidIoPacketBuffer::<U170> {
+        ptype: HidIoPacketType::Data,
+        id: HidIoCommandId::TestPacket,
+        unknown_id: None,
+        max_len: 64,
+        data: Vec::from_slice(&[0xAC; 170]).unwrap(),
+        done: true,
+    };
+    let options = PacketOptions {
+        sequence: true,
+        ..Default::default()
+    };
+
+    // 60, 58 then 52 bytes of payload
+    let mut packets = [[0u8; 64]; 3];
+    let mut lens = [0; 3];
+    let mut offset = Some(0);
+    for (i, packet) in packets.iter_mut().enumerate() {
+        let (len, next) = buffer
+            .serialize_chunk_opts(offset.unwrap(), packet, options)
+            .unwrap();
+        lens[i] = len;
+        offset = next;
+    }
+    assert_eq!(lens, [64, 64, 58]);
+    assert_eq!(offset, None);
+    assert_eq!(&packets[1][4..6], &60u16.to_le_bytes());
+
+    // Reassemble
+    let mut deserialized = HidIoPacketBuffer::<U170>::new();
+    for (packet, len) in packets.iter().zip(lens.iter()) {
+        deserialized
+            .decode_packet_opts(&packet[..*len], options)
+            .unwrap();
+    }
+    deserialized.max_len = buffer.max_len;
+    assert_eq!(buffer, deserialized);
+
+    // Dropped middle packet
+    let mut deserialized = HidIoPacketBuffer::<U170>::new();
+    deserialized
+        .decode_packet_opts(&packets[0][..lens[0]], options)
+        .unwrap();
+    assert!(matches!(
+        deserialized.decode_packet_opts(&packets[2][..lens[2]], options),
+        Err(HidIoParseError::SequenceGap {
+            id: HidIoCommandId::TestPacket,
+            ptype: HidIoPacketType::Data,
+            expected: 60,
+            received: Some(118),
+        })
+    ));
+    assert!(deserialized.data.is_empty());
+
+    // Dropped final packets, the next packet starts a new buffer
+    deserialized
+        .decode_packet_opts(&packets[0][..lens[0]], options)
+        .unwrap();
+    assert!(matches!(
+        deserialized.decode_packet_opts(&packets[0][..lens[0]], options),
+        Err(HidIoParseError::IncompleteBuffer {
+            id: HidIoCommandId::TestPacket,
+            ptype: HidIoPacketType::Data,
+        })
+    ));
+    deserialized
+        .decode_packet_opts(&packets[0][..lens[0]], options)
+        .unwrap();
+    assert_eq!(deserialized.data.len(), 60);
+}
+
+/// Packets too small for the header and the enabled options
+#[test]
+fn packet_len_options_test() {
+    setup_logging_lite().ok();
+
+    let mut buffer = HidIoPacketBuffer::<U110> {
+        ptype: HidIoPacketType::Data,
+        id: HidIoCommandId::TestPacket,
+        unknown_id: None,
+        max_len: 5,
+        data: Vec::from_slice(&[0xAC; 10]).unwrap(),
+        done: true,
+    };
+    let mut chunk = [0u8; 64];
+    let crc = PacketOptions {
+        crc: true,
+        ..Default::default()
+    };
+    let crc_sequence = PacketOptions {
+        crc: true,
+        sequence: true,
+    };
+
+    // Header and a single byte of payload, no room for the CRC16
+    assert!(buffer
+        .serialize_chunk_opts(0, &mut chunk, PacketOptions::default())
+        .is_ok());
+    assert!(matches!(
+        buffer.serialize_chunk_opts(0, &mut chunk, crc),
+        Err(HidIoParseError::InvalidPacketLen(5))
+    ));
+
+    // Smaller than the header
+    buffer.max_len = 3;
+    assert!(matches!(
+        buffer.serialize_chunk_opts(0, &mut chunk, PacketOptions::default()),
+        Err(HidIoParseError::InvalidPacketLen(3))
+    ));
+
+    // Room for the CRC16 and payload offset only, continued packets would carry no payload
+    buffer.max_len = 8;
+    assert!(matches!(
+        buffer.serialize_chunk_opts(0, &mut chunk, crc_sequence),
+        Err(HidIoParseError::InvalidPacketLen(8))
+    ));
+
+    // A single byte of payload in each continued packet
+    buffer.max_len = 9;
+    let mut packets = 0;
+    let mut offset = Some(0);
+    while let Some(pos) = offset {
+        let (_, next) = buffer
+            .serialize_chunk_opts(pos, &mut chunk, crc_sequence)
+            .unwrap();
+        offset = next;
+        packets += 1;
+    }
+    assert_eq!(packets, 8);
+    assert_eq!(crc_sequence.min_packet_len(), 11);
+}
+
+/// Serializes and reassembles the golden packet vectors
+/// Any change to these bytes breaks compatibility with deployed devices
+#[test]
+fn packet_vectors_test() {
+    setup_logging_lite().ok();
+
+    for vector in vectors::PACKETS {
+        let buffer = HidIoPacketBuffer::<U60> {
+            ptype: vector.ptype,
+            id: vector.id,
+            unknown_id: None,
+            max_len: vector.max_len,
+            data: Vec::from_slice(vector.payload).unwrap(),
+            done: true,
+        };
+
+        // Serialize
+        let mut bytes = [0u8; 128];
+        let mut pos = 0;
+        let mut offset = Some(0);
+        while let Some(next) = offset {
+            let chunk = &mut bytes[pos..pos + vector.max_len as usize];
+            let (len, next) = buffer
+                .serialize_chunk_opts(next, chunk, vector.options)
+                .unwrap();
+            pos += len;
+            offset = next;
+        }
+        assert_eq!(&bytes[..pos], vector.bytes, "{}", vector.name);
+
+        // Reassemble
+        let mut deserialized = HidIoPacketBuffer::<U60>::new();
+        let mut pos = 0;
+        while pos < vector.bytes.len() {
+            pos += deserialized
+                .decode_packet_opts(&vector.bytes[pos..], vector.options)
+                .unwrap() as usize;
+        }
+        deserialized.max_len = vector.max_len;
+        assert_eq!(buffer, deserialized, "{}", vector.name);
+    }
+}
+
+/// Compresses and decompresses payloads
+/// Incompressible payloads must be sent raw, full-size payloads are rejected
+#[test]
+fn compress_payload_test() {
+    setup_logging_lite().ok();
+
+    let mut buffer = HidIoPacketBuffer::<U110> {
+        ptype: HidIoPacketType::Data,
+        id: HidIoCommandId::TestPacket,
+        unknown_id: None,
+        max_len: 64,
+        data: Vec::from_slice(&[0xAC; 110]).unwrap(),
+        done: true,
+    };
+    let original = buffer.clone();
+
+    // 1 literal, then a 109 byte match
+    buffer.compress_payload().unwrap();
+    assert_eq!(
+        &buffer.data[..],
+        &[compress::COMPRESSED, 0x00, 0xAC, 0xE9, 0x01, 0x00]
+    );
+    buffer.decompress_payload().unwrap();
+    assert_eq!(buffer, original);
+
+    // Incompressible
+    let mut buffer = HidIoPacketBuffer::<U110> {
+        data: Vec::from_slice(&[0x01, 0x02, 0x03]).unwrap(),
+        ..original.clone()
+    };
+    buffer.compress_payload().unwrap();
+    assert_eq!(&buffer.data[..], &[compress::RAW, 0x01, 0x02, 0x03]);
+    buffer.decompress_payload().unwrap();
+    assert_eq!(&buffer.data[..], &[0x01, 0x02, 0x03]);
+
+    // Full-size random payloads leave no room for the encoding byte, rejected before encoding
+    // (xorshift, fixed seed)
+    let mut seed = 0x2545_f491u32;
+    let random: Vec<u8, U500> = (0..500)
+        .map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed as u8
+        })
+        .collect();
+    let mut buffer = HidIoPacketBuffer::<U500> {
+        ptype: HidIoPacketType::Data,
+        id: HidIoCommandId::TestPacket,
+        unknown_id: None,
+        max_len: 64,
+        data: random.clone(),
+        done: true,
+    };
+    assert!(matches!(
+        buffer.compress_payload(),
+        Err(HidIoParseError::PayloadTooLarge { len: 500, max: 499 })
+    ));
+    assert_eq!(buffer.data, random);
+
+    // Largest payload that fits, sent raw
+    buffer.data.truncate(buffer.max_compressed_payload_len());
+    buffer.compress_payload().unwrap();
+    assert_eq!(buffer.data.len(), 500);
+    assert_eq!(buffer.data[0], compress::RAW);
+    buffer.decompress_payload().unwrap();
+    assert_eq!(&buffer.data[..], &random[..499]);
+
+    // Repeated pattern, larger than the payload buffer of a single packet
+    let data: Vec<u8, U240> = (0..240).map(|i| (i % 3) as u8).collect();
+    let mut compressed = [0u8; 240];
+    let len = compress::compress(&data, &mut compressed).unwrap();
+    let mut decompressed = [0u8; 240];
+    assert_eq!(
+        compress::decompress(&compressed[..len], &mut decompressed).unwrap(),
+        240
+    );
+    assert_eq!(&decompressed[..], &data[..]);
+
+    // Match referencing data before the start of the output
+    assert!(matches!(
+        compress::decompress(&[0x80, 0x01, 0x00], &mut decompressed),
+        Err(HidIoParseError::InvalidCompressedPayload(3))
+    ));
+}
+
+/// Serializes a packet buffer, command id and command payload to JSON and back
+#[test]
+#[cfg(feature = "serde-support")]
+fn serde_json_test() {
+    setup_logging_lite().ok();
+
+    let buffer = HidIoPacketBuffer::<U110> {
+        ptype: HidIoPacketType::Data,
+        id: HidIoCommandId::TestPacket,
+        unknown_id: None,
+        max_len: 64,
+        data: Vec::from_slice(&[0xAC; 110]).unwrap(),
+        done: true,
+    };
+    let json = serde_json::to_string(&buffer).unwrap();
+    let deserialized: HidIoPacketBuffer<U110> = serde_json::from_str(&json).unwrap();
+    assert_eq!(buffer, deserialized);
+
+    let json = serde_json::to_string(&HidIoCommandId::TestPacket).unwrap();
+    assert_eq!(json, "\"TestPacket\"");
+    let id: HidIoCommandId = serde_json::from_str(&json).unwrap();
+    assert_eq!(id, HidIoCommandId::TestPacket);
+
+    let mut string = heapless::String::new();
+    string.push_str("hello").unwrap();
+    let cmd = commands::h0017::Cmd::<U7> { string };
+    let json = serde_json::to_string(&cmd).unwrap();
+    assert_eq!(json, "{\"string\":\"hello\"}");
+    let deserialized: commands::h0017::Cmd<U7> = serde_json::from_str(&json).unwrap();
+    assert_eq!(deserialized.string, cmd.string);
+}
+
 /// Generates a three packet payload buffer
 /// Serializes, deserializes, then checks if same as original
 #[test]
@@ -240,6 +687,7 @@ fn three_packet_continued_payload_test() {
         ptype: HidIoPacketType::Data,
         // Test packet id
         id: HidIoCommandId::TestPacket,
+        unknown_id: None,
         // Standard USB 2.0 FS packet length
         max_len: 64,
         // 170 bytes, 0xAC: 60, 60 then 50 (62, 62 then 52)
@@ -264,6 +712,7 @@ fn four_packet_continued_payload_test() {
         ptype: HidIoPacketType::Data,
         // Test packet id
         id: HidIoCommandId::TestPacket,
+        unknown_id: None,
         // Standard USB 2.0 FS packet length
         max_len: 64,
         // 240 bytes, 0xAC: 60, 60, 60 then 60 (64, 64, 64, 64)
@@ -307,3 +756,80 @@ fn hid_vec2bitmask2vec_test() {
         inputvec, new_vec,
     );
 }
+
+/// Encrypts and decrypts payloads with a paired key
+/// Tampered, replayed and wrong direction payloads must be rejected
+#[test]
+#[cfg(feature = "encryption")]
+fn encrypt_payload_test() {
+    setup_logging_lite().ok();
+
+    let cipher = crypt::PayloadCipher::new(&[0x42; crypt::KEY_LEN]);
+    let original = HidIoPacketBuffer::<U110> {
+        ptype: HidIoPacketType::Data,
+        id: HidIoCommandId::FlashMode,
+        unknown_id: None,
+        max_len: 64,
+        data: Vec::from_slice(&[0x01, 0x02, 0x03]).unwrap(),
+        done: true,
+    };
+
+    let mut buffer = original.clone();
+    buffer
+        .encrypt_payload(&cipher, crypt::Direction::HostToDevice, 7)
+        .unwrap();
+    assert_eq!(buffer.data.len(), 3 + crypt::OVERHEAD);
+    assert_eq!(&buffer.data[..crypt::COUNTER_LEN], &7u64.to_le_bytes());
+    let encrypted = buffer.clone();
+
+    // Round trip
+    assert_eq!(
+        buffer
+            .decrypt_payload(&cipher, crypt::Direction::HostToDevice, Some(6))
+            .unwrap(),
+        7
+    );
+    assert_eq!(buffer, original);
+
+    // Replayed counter
+    let mut buffer = encrypted.clone();
+    assert!(matches!(
+        buffer.decrypt_payload(&cipher, crypt::Direction::HostToDevice, Some(7)),
+        Err(HidIoParseError::ReplayedPayload(7))
+    ));
+
+    // Wrong direction
+    let mut buffer = encrypted.clone();
+    assert!(matches!(
+        buffer.decrypt_payload(&cipher, crypt::Direction::DeviceToHost, None),
+        Err(HidIoParseError::DecryptionFailed)
+    ));
+
+    // Id is authenticated
+    let mut buffer = HidIoPacketBuffer {
+        id: HidIoCommandId::TerminalCmd,
+        ..encrypted.clone()
+    };
+    assert!(matches!(
+        buffer.decrypt_payload(&cipher, crypt::Direction::HostToDevice, None),
+        Err(HidIoParseError::DecryptionFailed)
+    ));
+
+    // Tampered payload
+    let mut buffer = encrypted.clone();
+    buffer.data[crypt::COUNTER_LEN] ^= 0x01;
+    assert!(matches!(
+        buffer.decrypt_payload(&cipher, crypt::Direction::HostToDevice, None),
+        Err(HidIoParseError::DecryptionFailed)
+    ));
+
+    // Truncated payload
+    let mut buffer = HidIoPacketBuffer::<U110> {
+        data: Vec::from_slice(&encrypted.data[..crypt::OVERHEAD - 1]).unwrap(),
+        ..encrypted
+    };
+    assert!(matches!(
+        buffer.decrypt_payload(&cipher, crypt::Direction::HostToDevice, None),
+        Err(HidIoParseError::InvalidEncryptedPayload(23))
+    ));
+}
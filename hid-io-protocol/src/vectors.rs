@@ -0,0 +1,289 @@
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in
+ * all copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+ * THE SOFTWARE.
+ */
+
+// ----- Crates -----
+
+use super::*;
+use crate::commands::*;
+
+// ----- Structs -----
+
+/// Golden packet stream
+///
+/// # Remarks
+/// bytes are the serialized packets of the buffer (back to back), payload the reassembled data.
+/// Both host and device implementations must produce and accept exactly these bytes.
+pub struct PacketVector {
+    pub name: &'static str,
+    pub ptype: HidIoPacketType,
+    pub id: HidIoCommandId,
+    pub max_len: u32,
+    pub options: PacketOptions,
+    pub payload: &'static [u8],
+    pub bytes: &'static [u8],
+}
+
+/// Golden command payload
+///
+/// # Remarks
+/// bytes are the single packet (64 byte max_len) carrying value.
+pub struct CommandVector<T: 'static> {
+    pub name: &'static str,
+    pub ptype: HidIoPacketType,
+    pub id: HidIoCommandId,
+    pub value: T,
+    pub bytes: &'static [u8],
+}
+
+// ----- Packet Vectors -----
+
+pub const PACKETS: &[PacketVector] = &[
+    PacketVector {
+        name: "sync",
+        ptype: HidIoPacketType::Sync,
+        id: HidIoCommandId::SupportedIds,
+        max_len: 64,
+        options: PacketOptions {
+            crc: false,
+            sequence: false,
+        },
+        payload: &[],
+        bytes: &[0x60],
+    },
+    PacketVector {
+        name: "empty data",
+        ptype: HidIoPacketType::Data,
+        id: HidIoCommandId::SupportedIds,
+        max_len: 64,
+        options: PacketOptions {
+            crc: false,
+            sequence: false,
+        },
+        payload: &[],
+        bytes: &[0x00, 0x02, 0x00, 0x00],
+    },
+    PacketVector {
+        name: "single byte data",
+        ptype: HidIoPacketType::Data,
+        id: HidIoCommandId::GetInfo,
+        max_len: 64,
+        options: PacketOptions {
+            crc: false,
+            sequence: false,
+        },
+        payload: &[0x03],
+        bytes: &[0x00, 0x03, 0x01, 0x00, 0x03],
+    },
+    PacketVector {
+        name: "ack",
+        ptype: HidIoPacketType::Ack,
+        id: HidIoCommandId::GetInfo,
+        max_len: 64,
+        options: PacketOptions {
+            crc: false,
+            sequence: false,
+        },
+        payload: b"0.1",
+        bytes: &[0x20, 0x05, 0x01, 0x00, 0x30, 0x2E, 0x31],
+    },
+    PacketVector {
+        name: "nak",
+        ptype: HidIoPacketType::Nak,
+        id: HidIoCommandId::UnicodeText,
+        max_len: 64,
+        options: PacketOptions {
+            crc: false,
+            sequence: false,
+        },
+        payload: &[0x01],
+        bytes: &[0x40, 0x03, 0x17, 0x00, 0x01],
+    },
+    PacketVector {
+        name: "nadata",
+        ptype: HidIoPacketType::NaData,
+        id: HidIoCommandId::UnicodeText,
+        max_len: 64,
+        options: PacketOptions {
+            crc: false,
+            sequence: false,
+        },
+        payload: b"hid-io",
+        bytes: &[0xA0, 0x08, 0x17, 0x00, 0x68, 0x69, 0x64, 0x2D, 0x69, 0x6F],
+    },
+    PacketVector {
+        name: "continued",
+        ptype: HidIoPacketType::Data,
+        id: HidIoCommandId::TestPacket,
+        max_len: 8,
+        options: PacketOptions {
+            crc: false,
+            sequence: false,
+        },
+        payload: &[0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A],
+        bytes: &[
+            0x10, 0x06, 0x02, 0x00, 0x01, 0x02, 0x03, 0x04, // Data
+            0x90, 0x06, 0x02, 0x00, 0x05, 0x06, 0x07, 0x08, // Continued
+            0x80, 0x04, 0x02, 0x00, 0x09, 0x0A, // Continued (last)
+        ],
+    },
+    PacketVector {
+        name: "nacontinued",
+        ptype: HidIoPacketType::NaData,
+        id: HidIoCommandId::UnicodeText,
+        max_len: 8,
+        options: PacketOptions {
+            crc: false,
+            sequence: false,
+        },
+        payload: b"HID-IO text",
+        bytes: &[
+            0xB0, 0x06, 0x17, 0x00, 0x48, 0x49, 0x44, 0x2D, // NaData
+            0xD0, 0x06, 0x17, 0x00, 0x49, 0x4F, 0x20, 0x74, // NaContinued
+            0xC0, 0x05, 0x17, 0x00, 0x65, 0x78, 0x74, // NaContinued (last)
+        ],
+    },
+    PacketVector {
+        name: "crc16",
+        ptype: HidIoPacketType::Data,
+        id: HidIoCommandId::TestPacket,
+        max_len: 8,
+        options: PacketOptions {
+            crc: true,
+            sequence: false,
+        },
+        payload: &[0x10, 0x20, 0x30],
+        bytes: &[
+            0x14, 0x06, 0x02, 0x00, 0x10, 0x20, 0xC9, 0x15, // Data
+            0x84, 0x05, 0x02, 0x00, 0x30, 0xAC, 0x5E, // Continued (last)
+        ],
+    },
+    PacketVector {
+        name: "sequence",
+        ptype: HidIoPacketType::Data,
+        id: HidIoCommandId::TestPacket,
+        max_len: 8,
+        options: PacketOptions {
+            crc: false,
+            sequence: true,
+        },
+        payload: &[0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08],
+        bytes: &[
+            0x10, 0x06, 0x02, 0x00, 0x01, 0x02, 0x03, 0x04, // Data
+            0x90, 0x06, 0x02, 0x00, 0x04, 0x00, 0x05, 0x06, // Continued, offset 4
+            0x80, 0x06, 0x02, 0x00, 0x06, 0x00, 0x07, 0x08, // Continued (last), offset 6
+        ],
+    },
+];
+
+// ----- Command Vectors -----
+
+pub const H0004_CMD: CommandVector<h0004::Cmd> = CommandVector {
+    name: "h0004 cmd",
+    ptype: HidIoPacketType::Data,
+    id: HidIoCommandId::Capabilities,
+    value: h0004::Cmd {
+        version: h0004::VERSION,
+        capabilities: h0004::CAP_CRC16 | h0004::CAP_ENCRYPTION | h0004::CAP_SEQUENCE,
+    },
+    bytes: &[0x00, 0x08, 0x04, 0x00, 0x01, 0x00, 0x1A, 0x00, 0x00, 0x00],
+};
+
+pub const H0004_ACK: CommandVector<h0004::Ack> = CommandVector {
+    name: "h0004 ack",
+    ptype: HidIoPacketType::Ack,
+    id: HidIoCommandId::Capabilities,
+    value: h0004::Ack {
+        version: h0004::VERSION,
+        capabilities: h0004::CAP_CRC16 | h0004::CAP_SEQUENCE,
+    },
+    bytes: &[0x20, 0x08, 0x04, 0x00, 0x01, 0x00, 0x12, 0x00, 0x00, 0x00],
+};
+
+pub const H002C_CMD: CommandVector<h002c::Cmd> = CommandVector {
+    name: "h002c cmd",
+    ptype: HidIoPacketType::Data,
+    id: HidIoCommandId::AnimationBegin,
+    value: h002c::Cmd {
+        slot: 2,
+        format: h002c::Format::ThreeCh8b,
+        frames: 30,
+        frame_len: 0x120,
+    },
+    bytes: &[
+        0x00, 0x09, 0x2C, 0x00, 0x02, 0x00, 0x01, 0x1E, 0x00, 0x20, 0x01,
+    ],
+};
+
+pub const H0035_NACMD: CommandVector<h0035::Cmd> = CommandVector {
+    name: "h0035 nacmd",
+    ptype: HidIoPacketType::NaData,
+    id: HidIoCommandId::HostState,
+    value: h0035::Cmd {
+        state: h0035::State::Idle,
+        idle_secs: 300,
+        game_mode: false,
+    },
+    bytes: &[0xA0, 0x08, 0x35, 0x00, 0x01, 0x2C, 0x01, 0x00, 0x00, 0x00],
+};
+
+pub const H003A_CMD: CommandVector<h003a::Cmd> = CommandVector {
+    name: "h003a cmd",
+    ptype: HidIoPacketType::Data,
+    id: HidIoCommandId::FileChecksum,
+    value: h003a::Cmd {
+        handle: 1,
+        offset: 0,
+        len: 4,
+    },
+    bytes: &[
+        0x00, 0x0B, 0x3A, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00,
+    ],
+};
+
+pub const H003A_ACK: CommandVector<h003a::Ack> = CommandVector {
+    name: "h003a ack",
+    ptype: HidIoPacketType::Ack,
+    id: HidIoCommandId::FileChecksum,
+    value: h003a::Ack { crc: 0x8A5B },
+    bytes: &[0x20, 0x04, 0x3A, 0x00, 0x5B, 0x8A],
+};
+
+pub const H003B_CMD: CommandVector<h003b::Cmd> = CommandVector {
+    name: "h003b cmd",
+    ptype: HidIoPacketType::Data,
+    id: HidIoCommandId::FileClose,
+    value: h003b::Cmd {
+        handle: 1,
+        action: h003b::Action::Suspend,
+    },
+    bytes: &[0x00, 0x04, 0x3B, 0x00, 0x01, 0x02],
+};
+
+pub const H0050_CMD: CommandVector<h0050::Cmd> = CommandVector {
+    name: "h0050 cmd",
+    ptype: HidIoPacketType::Data,
+    id: HidIoCommandId::ManufacturingTest,
+    value: h0050::Cmd {
+        command: 0x0001,
+        argument: 0x0003,
+    },
+    bytes: &[0x00, 0x06, 0x50, 0x00, 0x01, 0x00, 0x03, 0x00],
+};
@@ -24,6 +24,8 @@ pub use crate::keyboard_capnp;
 
 use crate::api::*;
 use crate::built_info;
+use crate::device::exclusive::Access;
+use crate::device::polling::ReadMode;
 use crate::mailbox;
 use crate::RUNNING;
 use ::capnp::capability::Promise;
@@ -57,6 +59,9 @@ const AUTH_LEVEL: AuthLevel = AuthLevel::Debug;
 #[cfg(not(debug_assertions))]
 const AUTH_LEVEL: AuthLevel = AuthLevel::Secure;
 
+/// h0050/h0051 Key matrix test command
+const MANUFACTURING_MATRIX_TEST: u16 = 0x0004;
+
 // ----- Functions -----
 
 impl std::fmt::Display for common_capnp::NodeType {
@@ -66,6 +71,7 @@ impl std::fmt::Display for common_capnp::NodeType {
             common_capnp::NodeType::HidioApi => write!(f, "HidioApi"),
             common_capnp::NodeType::UsbKeyboard => write!(f, "UsbKeyboard"),
             common_capnp::NodeType::BleKeyboard => write!(f, "BleKeyboard"),
+            common_capnp::NodeType::BtKeyboard => write!(f, "BtKeyboard"),
             common_capnp::NodeType::HidKeyboard => write!(f, "HidKeyboard"),
             common_capnp::NodeType::HidMouse => write!(f, "HidMouse"),
             common_capnp::NodeType::HidJoystick => write!(f, "HidJoystick"),
@@ -396,6 +402,18 @@ impl hidio_capnp::hid_io::Server for HidIoImpl {
             node.set_name(&n.name);
             node.set_serial(&n.serial);
             node.set_id(n.uid);
+            node.set_seat(&n.seat);
+            if let common_capnp::NodeType::UsbKeyboard
+            | common_capnp::NodeType::BleKeyboard
+            | common_capnp::NodeType::BtKeyboard = n.type_
+            {
+                set_link_stats(
+                    node.reborrow().init_link_stats(),
+                    &n.link_stats.read().unwrap(),
+                );
+                set_access(node.reborrow(), &n.access);
+                set_device_info(node.reborrow().init_device_info(), &n.hidapi);
+            }
             let mut node = node.init_node();
             match n.type_ {
                 common_capnp::NodeType::HidioDaemon => {
@@ -407,7 +425,9 @@ impl hidio_capnp::hid_io::Server for HidIoImpl {
                         self.subscriptions.clone(),
                     )));
                 }
-                common_capnp::NodeType::UsbKeyboard | common_capnp::NodeType::BleKeyboard => {
+                common_capnp::NodeType::UsbKeyboard
+                | common_capnp::NodeType::BleKeyboard
+                | common_capnp::NodeType::BtKeyboard => {
                     node.set_keyboard(capnp_rpc::new_client(KeyboardNodeImpl::new(
                         self.mailbox.clone(),
                         self.node.clone(),
@@ -470,6 +490,80 @@ impl hidio_capnp::hid_io::Server for HidIoImpl {
         self.subscriptions.write().unwrap().nodes_next_id += 1;
         Promise::ok(())
     }
+
+    fn batch(
+        &mut self,
+        params: hidio_capnp::hid_io::BatchParams,
+        mut results: hidio_capnp::hid_io::BatchResults,
+    ) -> Promise<(), Error> {
+        use crate::module::batch::{self, BatchCommand};
+        use hidio_capnp::hid_io::batch_command;
+        use std::convert::TryFrom;
+
+        match self.auth {
+            AuthLevel::Secure | AuthLevel::Debug => {
+                let params = pry!(params.get());
+
+                // Build filter
+                let filter = pry!(params.get_filter());
+                let name = pry!(filter.get_name());
+                let supported_id = match filter.get_supported_id() {
+                    0xFFFF => None,
+                    id => Some(pry!(HidIoCommandId::try_from(id).map_err(|_| {
+                        capnp::Error {
+                            kind: ::capnp::ErrorKind::Failed,
+                            description: format!("Invalid supportedId: {}", id),
+                        }
+                    }))),
+                };
+                let filter = mailbox::NodeFilter {
+                    vendor_id: match filter.get_vendor_id() {
+                        0 => None,
+                        vid => Some(vid),
+                    },
+                    name: if name.is_empty() {
+                        None
+                    } else {
+                        Some(name.to_string())
+                    },
+                };
+
+                // Build command
+                let command = match pry!(pry!(params.get_command()).which()) {
+                    batch_command::Sleep(()) => BatchCommand::Sleep,
+                    batch_command::DisplayText(text) => BatchCommand::DisplayText {
+                        widget: text.get_widget(),
+                        text: pry!(text.get_text()).to_string(),
+                    },
+                    batch_command::Raw(raw) => BatchCommand::Raw {
+                        id: raw.get_id(),
+                        data: pry!(raw.get_data()).to_vec(),
+                    },
+                };
+
+                let mailbox = self.mailbox.clone();
+                let src = mailbox::Address::ApiCapnp { uid: self.node.uid };
+                Promise::from_future(async move {
+                    let batch_results =
+                        batch::run(mailbox, src, filter, supported_id, command).await;
+                    let mut list = results.get().init_results(batch_results.len() as u32);
+                    for (i, res) in batch_results.iter().enumerate() {
+                        let mut entry = list.reborrow().get(i as u32);
+                        entry.set_id(res.uid);
+                        match &res.result {
+                            Ok(_) => entry.set_success(()),
+                            Err(e) => entry.set_error(e),
+                        }
+                    }
+                    Ok(())
+                })
+            }
+            _ => Promise::err(capnp::Error {
+                kind: ::capnp::ErrorKind::Failed,
+                description: "Insufficient authorization level".to_string(),
+            }),
+        }
+    }
 }
 
 struct NodesSubscriberHandle {
@@ -998,6 +1092,143 @@ impl hidio_capnp::node::Server for KeyboardNodeImpl {
         });
         Promise::ok(())
     }
+
+    fn link_stats(
+        &mut self,
+        _params: hidio_capnp::node::LinkStatsParams,
+        mut results: hidio_capnp::node::LinkStatsResults,
+    ) -> Promise<(), Error> {
+        let nodes = self.mailbox.nodes.read().unwrap();
+        let node = match nodes.iter().find(|node| node.uid == self.uid) {
+            Some(node) => node,
+            None => {
+                return Promise::err(capnp::Error {
+                    kind: ::capnp::ErrorKind::Failed,
+                    description: format!("Node {} is not connected", self.uid),
+                });
+            }
+        };
+        set_link_stats(results.get().init_stats(), &node.link_stats.read().unwrap());
+        Promise::ok(())
+    }
+
+    fn read_mode(
+        &mut self,
+        _params: hidio_capnp::node::ReadModeParams,
+        mut results: hidio_capnp::node::ReadModeResults,
+    ) -> Promise<(), Error> {
+        let nodes = self.mailbox.nodes.read().unwrap();
+        let node = match nodes.iter().find(|node| node.uid == self.uid) {
+            Some(node) => node,
+            None => {
+                return Promise::err(capnp::Error {
+                    kind: ::capnp::ErrorKind::Failed,
+                    description: format!("Node {} is not connected", self.uid),
+                });
+            }
+        };
+        let mut mode = results.get().init_mode();
+        match *node.read_mode.read().unwrap() {
+            None => mode.set_default(()),
+            Some(ReadMode::Blocking(timeout)) => mode.set_blocking(timeout),
+            Some(ReadMode::Poll(interval)) => mode.set_poll(interval),
+        }
+        Promise::ok(())
+    }
+
+    fn set_read_mode(
+        &mut self,
+        params: hidio_capnp::node::SetReadModeParams,
+        _results: hidio_capnp::node::SetReadModeResults,
+    ) -> Promise<(), Error> {
+        match self.auth {
+            AuthLevel::Secure | AuthLevel::Debug => {
+                use hidio_capnp::node::read_mode;
+
+                let mode = match pry!(pry!(pry!(params.get()).get_mode()).which()) {
+                    read_mode::Default(()) => None,
+                    read_mode::Blocking(timeout) => Some(ReadMode::Blocking(timeout)),
+                    read_mode::Poll(interval) => Some(ReadMode::Poll(interval)),
+                };
+                if let Some(Err(e)) = mode.map(|mode| mode.validate()) {
+                    return Promise::err(capnp::Error {
+                        kind: ::capnp::ErrorKind::Failed,
+                        description: format!("Invalid read mode: {}", e),
+                    });
+                }
+
+                let nodes = self.mailbox.nodes.read().unwrap();
+                match nodes.iter().find(|node| node.uid == self.uid) {
+                    Some(node) => {
+                        info!("uid:{} read mode {:?}", self.uid, mode);
+                        *node.read_mode.write().unwrap() = mode;
+                        Promise::ok(())
+                    }
+                    None => Promise::err(capnp::Error {
+                        kind: ::capnp::ErrorKind::Failed,
+                        description: format!("Node {} is not connected", self.uid),
+                    }),
+                }
+            }
+            _ => Promise::err(capnp::Error {
+                kind: ::capnp::ErrorKind::Failed,
+                description: "Insufficient authorization level".to_string(),
+            }),
+        }
+    }
+
+    fn pair(
+        &mut self,
+        _params: hidio_capnp::node::PairParams,
+        _results: hidio_capnp::node::PairResults,
+    ) -> Promise<(), Error> {
+        match self.auth {
+            AuthLevel::Secure | AuthLevel::Debug => {
+                // The device controller creates the key, the request has no payload
+                let msg = mailbox::Message::new(
+                    mailbox::Address::ApiCapnp { uid: self.node.uid },
+                    mailbox::Address::DeviceHidio { uid: self.uid },
+                    mailbox::HidIoPacketBuffer {
+                        ptype: HidIoPacketType::Data,
+                        id: HidIoCommandId::Pair,
+                        done: true,
+                        ..Default::default()
+                    },
+                );
+
+                // Waits for the user to confirm on the device, run off the RPC thread
+                // Allow for the controller to finish what it was doing first
+                let timeout = std::time::Duration::from_millis(crate::device::PAIR_TIMEOUT_MS)
+                    + *self.mailbox.ack_timeout.read().unwrap();
+                let mailbox = self.mailbox.clone();
+                let response = self
+                    .mailbox
+                    .rt
+                    .spawn_blocking(move || mailbox.try_send_message_timeout(msg, timeout));
+                Promise::from_future(async move {
+                    match response.await {
+                        Ok(Ok(Some(msg))) if msg.data.ptype == HidIoPacketType::Ack => Ok(()),
+                        Ok(Ok(_)) => Err(capnp::Error {
+                            kind: ::capnp::ErrorKind::Failed,
+                            description: "Error (pair): Device was not paired".to_string(),
+                        }),
+                        Ok(Err(e)) => Err(capnp::Error {
+                            kind: ::capnp::ErrorKind::Failed,
+                            description: format!("Error (pair): {:?}", e),
+                        }),
+                        Err(e) => Err(capnp::Error {
+                            kind: ::capnp::ErrorKind::Failed,
+                            description: format!("Error (pair): {:?}", e),
+                        }),
+                    }
+                })
+            }
+            _ => Promise::err(capnp::Error {
+                kind: ::capnp::ErrorKind::Failed,
+                description: "Insufficient authorization level".to_string(),
+            }),
+        }
+    }
 }
 
 impl keyboard_capnp::keyboard::Server for KeyboardNodeImpl {
@@ -1026,9 +1257,433 @@ impl keyboard_capnp::keyboard::Server for KeyboardNodeImpl {
             }
         };
 
+        let client = pry!(pry!(params.get()).get_subscriber());
+        results
+            .get()
+            .set_subscription(capnp_rpc::new_client(self.add_subscriber(client, false)));
+        Promise::ok(())
+    }
+
+    fn matrix_test(
+        &mut self,
+        params: keyboard_capnp::keyboard::MatrixTestParams,
+        mut results: keyboard_capnp::keyboard::MatrixTestResults,
+    ) -> Promise<(), Error> {
+        match self.auth {
+            AuthLevel::Secure | AuthLevel::Debug => {
+                let client = pry!(pry!(params.get()).get_subscriber());
+
+                // Enable key matrix test mode
+                let src = mailbox::Address::ApiCapnp { uid: self.node.uid };
+                let dst = mailbox::Address::DeviceHidio { uid: self.uid };
+                let mut data = MANUFACTURING_MATRIX_TEST.to_le_bytes().to_vec();
+                data.extend(&1u16.to_le_bytes());
+                match self.mailbox.try_send_command(
+                    src,
+                    dst,
+                    HidIoCommandId::ManufacturingTest,
+                    data,
+                    true,
+                ) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        return Promise::err(capnp::Error {
+                            kind: ::capnp::ErrorKind::Failed,
+                            description: format!("Error (matrix_test): {:?}", e),
+                        });
+                    }
+                }
+
+                results
+                    .get()
+                    .set_subscription(capnp_rpc::new_client(self.add_subscriber(client, true)));
+                Promise::ok(())
+            }
+            _ => Promise::err(capnp::Error {
+                kind: ::capnp::ErrorKind::Failed,
+                description: "Insufficient authorization level".to_string(),
+            }),
+        }
+    }
+
+    fn upload_animation(
+        &mut self,
+        params: keyboard_capnp::keyboard::UploadAnimationParams,
+        _results: keyboard_capnp::keyboard::UploadAnimationResults,
+    ) -> Promise<(), Error> {
+        use crate::module::animation;
+        use keyboard_capnp::keyboard::AnimationFormat;
+
+        match self.auth {
+            AuthLevel::Secure | AuthLevel::Debug => {
+                let params = pry!(params.get());
+                let slot = params.get_slot();
+                let frame_len = params.get_frame_length();
+                let format = match pry!(params.get_format()) {
+                    AnimationFormat::OneChannel8bit => h002c::Format::OneCh8b,
+                    AnimationFormat::ThreeChannel8bit => h002c::Format::ThreeCh8b,
+                    AnimationFormat::OneChannel16bit => h002c::Format::OneCh16b,
+                    AnimationFormat::ThreeChannel16bit => h002c::Format::ThreeCh16b,
+                };
+                let frames = pry!(params.get_frames()).to_vec();
+
+                // Each chunk waits on an ack, run the upload off the RPC thread
+                let mailbox = self.mailbox.clone();
+                let src = mailbox::Address::ApiCapnp { uid: self.node.uid };
+                let dst = mailbox::Address::DeviceHidio { uid: self.uid };
+                let upload = self.mailbox.rt.spawn_blocking(move || {
+                    animation::upload(mailbox, src, dst, slot, format, frame_len, &frames)
+                });
+                Promise::from_future(async move {
+                    match upload.await {
+                        Ok(Ok(_)) => Ok(()),
+                        Ok(Err(e)) => Err(capnp::Error {
+                            kind: ::capnp::ErrorKind::Failed,
+                            description: format!("Error (upload_animation): {}", e),
+                        }),
+                        Err(e) => Err(capnp::Error {
+                            kind: ::capnp::ErrorKind::Failed,
+                            description: format!("Error (upload_animation): {:?}", e),
+                        }),
+                    }
+                })
+            }
+            _ => Promise::err(capnp::Error {
+                kind: ::capnp::ErrorKind::Failed,
+                description: "Insufficient authorization level".to_string(),
+            }),
+        }
+    }
+
+    fn read_settings(
+        &mut self,
+        params: keyboard_capnp::keyboard::ReadSettingsParams,
+        mut results: keyboard_capnp::keyboard::ReadSettingsResults,
+    ) -> Promise<(), Error> {
+        use crate::module::settings;
+
+        match self.auth {
+            AuthLevel::Secure | AuthLevel::Debug => {
+                let params = pry!(params.get());
+                let offset = params.get_offset();
+                let len = params.get_length() as usize;
+
+                let mailbox = self.mailbox.clone();
+                let src = mailbox::Address::ApiCapnp { uid: self.node.uid };
+                let dst = mailbox::Address::DeviceHidio { uid: self.uid };
+                let read = self
+                    .mailbox
+                    .rt
+                    .spawn_blocking(move || settings::read(mailbox, src, dst, offset, len));
+                Promise::from_future(async move {
+                    match read.await {
+                        Ok(Ok(data)) => {
+                            results.get().set_data(&data);
+                            Ok(())
+                        }
+                        Ok(Err(e)) => Err(capnp::Error {
+                            kind: ::capnp::ErrorKind::Failed,
+                            description: format!("Error (read_settings): {}", e),
+                        }),
+                        Err(e) => Err(capnp::Error {
+                            kind: ::capnp::ErrorKind::Failed,
+                            description: format!("Error (read_settings): {:?}", e),
+                        }),
+                    }
+                })
+            }
+            _ => Promise::err(capnp::Error {
+                kind: ::capnp::ErrorKind::Failed,
+                description: "Insufficient authorization level".to_string(),
+            }),
+        }
+    }
+
+    fn write_settings(
+        &mut self,
+        params: keyboard_capnp::keyboard::WriteSettingsParams,
+        _results: keyboard_capnp::keyboard::WriteSettingsResults,
+    ) -> Promise<(), Error> {
+        use crate::module::settings;
+
+        match self.auth {
+            AuthLevel::Secure | AuthLevel::Debug => {
+                let params = pry!(params.get());
+                let offset = params.get_offset();
+                let verify = params.get_verify();
+                let data = pry!(params.get_data()).to_vec();
+
+                let mailbox = self.mailbox.clone();
+                let src = mailbox::Address::ApiCapnp { uid: self.node.uid };
+                let dst = mailbox::Address::DeviceHidio { uid: self.uid };
+                let write = self.mailbox.rt.spawn_blocking(move || {
+                    settings::write(mailbox, src, dst, offset, &data, verify)
+                });
+                Promise::from_future(async move {
+                    match write.await {
+                        Ok(Ok(_)) => Ok(()),
+                        Ok(Err(e)) => Err(capnp::Error {
+                            kind: ::capnp::ErrorKind::Failed,
+                            description: format!("Error (write_settings): {}", e),
+                        }),
+                        Err(e) => Err(capnp::Error {
+                            kind: ::capnp::ErrorKind::Failed,
+                            description: format!("Error (write_settings): {:?}", e),
+                        }),
+                    }
+                })
+            }
+            _ => Promise::err(capnp::Error {
+                kind: ::capnp::ErrorKind::Failed,
+                description: "Insufficient authorization level".to_string(),
+            }),
+        }
+    }
+
+    fn download_layout(
+        &mut self,
+        _params: keyboard_capnp::keyboard::DownloadLayoutParams,
+        mut results: keyboard_capnp::keyboard::DownloadLayoutResults,
+    ) -> Promise<(), Error> {
+        use crate::module::kll;
+
+        match self.auth {
+            AuthLevel::Secure | AuthLevel::Debug => {
+                let mailbox = self.mailbox.clone();
+                let src = mailbox::Address::ApiCapnp { uid: self.node.uid };
+                let dst = mailbox::Address::DeviceHidio { uid: self.uid };
+                let download = self
+                    .mailbox
+                    .rt
+                    .spawn_blocking(move || kll::download(mailbox, src, dst));
+                Promise::from_future(async move {
+                    match download.await {
+                        Ok(Ok(layout)) => {
+                            results.get().set_layout(&layout);
+                            Ok(())
+                        }
+                        Ok(Err(e)) => Err(capnp::Error {
+                            kind: ::capnp::ErrorKind::Failed,
+                            description: format!("Error (download_layout): {}", e),
+                        }),
+                        Err(e) => Err(capnp::Error {
+                            kind: ::capnp::ErrorKind::Failed,
+                            description: format!("Error (download_layout): {:?}", e),
+                        }),
+                    }
+                })
+            }
+            _ => Promise::err(capnp::Error {
+                kind: ::capnp::ErrorKind::Failed,
+                description: "Insufficient authorization level".to_string(),
+            }),
+        }
+    }
+
+    fn upload_layout(
+        &mut self,
+        params: keyboard_capnp::keyboard::UploadLayoutParams,
+        _results: keyboard_capnp::keyboard::UploadLayoutResults,
+    ) -> Promise<(), Error> {
+        use crate::module::kll;
+
+        match self.auth {
+            AuthLevel::Secure | AuthLevel::Debug => {
+                let layout = pry!(pry!(params.get()).get_layout()).to_vec();
+
+                let mailbox = self.mailbox.clone();
+                let src = mailbox::Address::ApiCapnp { uid: self.node.uid };
+                let dst = mailbox::Address::DeviceHidio { uid: self.uid };
+                let upload = self
+                    .mailbox
+                    .rt
+                    .spawn_blocking(move || kll::upload(mailbox, src, dst, &layout));
+                Promise::from_future(async move {
+                    match upload.await {
+                        Ok(Ok(_)) => Ok(()),
+                        Ok(Err(e)) => Err(capnp::Error {
+                            kind: ::capnp::ErrorKind::Failed,
+                            description: format!("Error (upload_layout): {}", e),
+                        }),
+                        Err(e) => Err(capnp::Error {
+                            kind: ::capnp::ErrorKind::Failed,
+                            description: format!("Error (upload_layout): {:?}", e),
+                        }),
+                    }
+                })
+            }
+            _ => Promise::err(capnp::Error {
+                kind: ::capnp::ErrorKind::Failed,
+                description: "Insufficient authorization level".to_string(),
+            }),
+        }
+    }
+
+    fn upload_file(
+        &mut self,
+        params: keyboard_capnp::keyboard::UploadFileParams,
+        _results: keyboard_capnp::keyboard::UploadFileResults,
+    ) -> Promise<(), Error> {
+        use crate::module::filetransfer;
+
+        match self.auth {
+            AuthLevel::Secure | AuthLevel::Debug => {
+                let params = pry!(params.get());
+                let name = pry!(params.get_name()).to_string();
+                let data = pry!(params.get_data()).to_vec();
+
+                let mailbox = self.mailbox.clone();
+                let src = mailbox::Address::ApiCapnp { uid: self.node.uid };
+                let dst = mailbox::Address::DeviceHidio { uid: self.uid };
+                let upload = self
+                    .mailbox
+                    .rt
+                    .spawn_blocking(move || filetransfer::upload(mailbox, src, dst, &name, &data));
+                Promise::from_future(async move {
+                    match upload.await {
+                        Ok(Ok(_)) => Ok(()),
+                        Ok(Err(e)) => Err(capnp::Error {
+                            kind: ::capnp::ErrorKind::Failed,
+                            description: format!("Error (upload_file): {}", e),
+                        }),
+                        Err(e) => Err(capnp::Error {
+                            kind: ::capnp::ErrorKind::Failed,
+                            description: format!("Error (upload_file): {:?}", e),
+                        }),
+                    }
+                })
+            }
+            _ => Promise::err(capnp::Error {
+                kind: ::capnp::ErrorKind::Failed,
+                description: "Insufficient authorization level".to_string(),
+            }),
+        }
+    }
+
+    fn download_file(
+        &mut self,
+        params: keyboard_capnp::keyboard::DownloadFileParams,
+        mut results: keyboard_capnp::keyboard::DownloadFileResults,
+    ) -> Promise<(), Error> {
+        use crate::module::filetransfer;
+
+        match self.auth {
+            AuthLevel::Secure | AuthLevel::Debug => {
+                let name = pry!(pry!(params.get()).get_name()).to_string();
+
+                let mailbox = self.mailbox.clone();
+                let src = mailbox::Address::ApiCapnp { uid: self.node.uid };
+                let dst = mailbox::Address::DeviceHidio { uid: self.uid };
+                let download = self.mailbox.rt.spawn_blocking(move || {
+                    let mut data = vec![];
+                    filetransfer::download(mailbox, src, dst, &name, &mut data).map(|_| data)
+                });
+                Promise::from_future(async move {
+                    match download.await {
+                        Ok(Ok(data)) => {
+                            results.get().set_data(&data);
+                            Ok(())
+                        }
+                        Ok(Err(e)) => Err(capnp::Error {
+                            kind: ::capnp::ErrorKind::Failed,
+                            description: format!("Error (download_file): {}", e),
+                        }),
+                        Err(e) => Err(capnp::Error {
+                            kind: ::capnp::ErrorKind::Failed,
+                            description: format!("Error (download_file): {:?}", e),
+                        }),
+                    }
+                })
+            }
+            _ => Promise::err(capnp::Error {
+                kind: ::capnp::ErrorKind::Failed,
+                description: "Insufficient authorization level".to_string(),
+            }),
+        }
+    }
+
+    fn set_widget_text(
+        &mut self,
+        params: keyboard_capnp::keyboard::SetWidgetTextParams,
+        _results: keyboard_capnp::keyboard::SetWidgetTextResults,
+    ) -> Promise<(), Error> {
+        use crate::module::widget;
+
+        match self.auth {
+            AuthLevel::Secure | AuthLevel::Debug => {
+                let params = pry!(params.get());
+                let widget = params.get_widget();
+                let text = pry!(params.get_text());
+
+                let src = mailbox::Address::ApiCapnp { uid: self.node.uid };
+                let dst = mailbox::Address::DeviceHidio { uid: self.uid };
+                match widget::set_text(self.mailbox.clone(), src, dst, widget, text) {
+                    Ok(_) => Promise::ok(()),
+                    Err(e) => Promise::err(capnp::Error {
+                        kind: ::capnp::ErrorKind::Failed,
+                        description: format!("Error (set_widget_text): {}", e),
+                    }),
+                }
+            }
+            _ => Promise::err(capnp::Error {
+                kind: ::capnp::ErrorKind::Failed,
+                description: "Insufficient authorization level".to_string(),
+            }),
+        }
+    }
+
+    fn type_confirmed(
+        &mut self,
+        params: keyboard_capnp::keyboard::TypeConfirmedParams,
+        _results: keyboard_capnp::keyboard::TypeConfirmedResults,
+    ) -> Promise<(), Error> {
+        use crate::module::securetype;
+
+        match self.auth {
+            AuthLevel::Secure | AuthLevel::Debug => {
+                let params = pry!(params.get());
+                let prompt = pry!(params.get_prompt()).to_string();
+                let string = zeroize::Zeroizing::new(pry!(params.get_string()).to_string());
+                let timeout = params.get_timeout();
+
+                // Waits for the user to answer on the device, run off the RPC thread
+                let mailbox = self.mailbox.clone();
+                let src = mailbox::Address::ApiCapnp { uid: self.node.uid };
+                let uid = self.uid;
+                let confirmed = self.mailbox.rt.spawn_blocking(move || {
+                    securetype::type_confirmed(mailbox, src, uid, &prompt, timeout, string)
+                });
+                Promise::from_future(async move {
+                    match confirmed.await {
+                        Ok(Ok(_)) => Ok(()),
+                        Ok(Err(e)) => Err(capnp::Error {
+                            kind: ::capnp::ErrorKind::Failed,
+                            description: format!("Error (type_confirmed): {}", e),
+                        }),
+                        Err(e) => Err(capnp::Error {
+                            kind: ::capnp::ErrorKind::Failed,
+                            description: format!("Error (type_confirmed): {:?}", e),
+                        }),
+                    }
+                })
+            }
+            _ => Promise::err(capnp::Error {
+                kind: ::capnp::ErrorKind::Failed,
+                description: "Insufficient authorization level".to_string(),
+            }),
+        }
+    }
+}
+
+impl KeyboardNodeImpl {
+    /// Register a keyboard subscriber, the returned subscription removes it when dropped
+    fn add_subscriber(
+        &mut self,
+        client: keyboard_capnp::keyboard::subscriber::Client,
+        matrix_test: bool,
+    ) -> KeyboardSubscriptionImpl {
         let sid = self.subscriptions.read().unwrap().keyboard_node_next_id;
         info!("Adding KeyboardNode watcher sid:{} uid:{}", sid, self.uid);
-        let client = pry!(pry!(params.get()).get_subscriber());
         self.subscriptions
             .write()
             .unwrap()
@@ -1041,21 +1696,19 @@ impl keyboard_capnp::keyboard::Server for KeyboardNodeImpl {
                     _auth: self.auth,
                     _node: self.node.clone(),
                     uid: self.uid,
+                    matrix_test,
                 },
             );
-
-        results
-            .get()
-            .set_subscription(capnp_rpc::new_client(KeyboardSubscriptionImpl::new(
-                self.mailbox.clone(),
-                self.node.clone(),
-                self.uid,
-                sid,
-                self.subscriptions.clone(),
-            )));
-
         self.subscriptions.write().unwrap().keyboard_node_next_id += 1;
-        Promise::ok(())
+
+        KeyboardSubscriptionImpl::new(
+            self.mailbox.clone(),
+            self.node.clone(),
+            self.uid,
+            sid,
+            self.subscriptions.clone(),
+            matrix_test,
+        )
     }
 }
 
@@ -1064,6 +1717,7 @@ struct KeyboardSubscriberHandle {
     _auth: AuthLevel,
     _node: Endpoint,
     uid: u64,
+    matrix_test: bool, // Only key matrix test events are sent
 }
 
 struct KeyboardSubscriberMap {
@@ -1080,10 +1734,11 @@ impl KeyboardSubscriberMap {
 
 struct KeyboardSubscriptionImpl {
     mailbox: mailbox::Mailbox,
-    _node: Endpoint, // API Node information
-    uid: u64,        // Device endpoint uid
-    sid: u64,        // Subscription id
+    node: Endpoint, // API Node information
+    uid: u64,       // Device endpoint uid
+    sid: u64,       // Subscription id
     subscriptions: Arc<RwLock<Subscriptions>>,
+    matrix_test: bool, // Key matrix test is active
 }
 
 impl KeyboardSubscriptionImpl {
@@ -1093,13 +1748,15 @@ impl KeyboardSubscriptionImpl {
         uid: u64,
         sid: u64,
         subscriptions: Arc<RwLock<Subscriptions>>,
+        matrix_test: bool,
     ) -> KeyboardSubscriptionImpl {
         KeyboardSubscriptionImpl {
             mailbox,
-            _node: node,
+            node,
             uid,
             sid,
             subscriptions,
+            matrix_test,
         }
     }
 }
@@ -1110,6 +1767,20 @@ impl Drop for KeyboardSubscriptionImpl {
             "KeyboardNode watcher dropped uid:{} sid:{}",
             self.uid, self.sid
         );
+        if self.matrix_test {
+            // Disable key matrix test mode, don't wait for the Ack (device may be gone)
+            let mut data = MANUFACTURING_MATRIX_TEST.to_le_bytes().to_vec();
+            data.extend(&0u16.to_le_bytes());
+            if let Err(e) = self.mailbox.try_send_command(
+                mailbox::Address::ApiCapnp { uid: self.node.uid },
+                mailbox::Address::DeviceHidio { uid: self.uid },
+                HidIoCommandId::ManufacturingTest,
+                data,
+                false,
+            ) {
+                warn!("Could not stop matrix test uid:{} - {:?}", self.uid, e);
+            }
+        }
         self.mailbox.drop_subscriber(self.uid, self.sid);
         self.subscriptions
             .write()
@@ -1339,6 +2010,265 @@ impl daemon_capnp::daemon::Server for DaemonNodeImpl {
         info.set_host_name(built_info::PKG_NAME);
         Promise::ok(())
     }
+
+    fn modules(
+        &mut self,
+        _params: daemon_capnp::daemon::ModulesParams,
+        mut results: daemon_capnp::daemon::ModulesResults,
+    ) -> Promise<(), Error> {
+        let modules = crate::module::MODULES.list();
+        let mut list = results.get().init_modules(modules.len() as u32);
+        for (i, module) in modules.iter().enumerate() {
+            set_module_info(list.reborrow().get(i as u32), module);
+        }
+        Promise::ok(())
+    }
+
+    fn set_module_enabled(
+        &mut self,
+        params: daemon_capnp::daemon::SetModuleEnabledParams,
+        mut results: daemon_capnp::daemon::SetModuleEnabledResults,
+    ) -> Promise<(), Error> {
+        match self.auth {
+            AuthLevel::Secure | AuthLevel::Debug => {
+                let params = pry!(params.get());
+                let name = pry!(params.get_name());
+                let result = if params.get_enable() {
+                    crate::module::MODULES.enable(name)
+                } else {
+                    crate::module::MODULES.disable(name)
+                };
+                match result {
+                    Ok(module) => {
+                        set_module_info(results.get().init_module(), &module);
+                        Promise::ok(())
+                    }
+                    Err(e) => Promise::err(capnp::Error {
+                        kind: ::capnp::ErrorKind::Failed,
+                        description: format!("Error (set_module_enabled): {}", e),
+                    }),
+                }
+            }
+            _ => Promise::err(capnp::Error {
+                kind: ::capnp::ErrorKind::Failed,
+                description: "Insufficient authorization level".to_string(),
+            }),
+        }
+    }
+
+    fn schedule(
+        &mut self,
+        _params: daemon_capnp::daemon::ScheduleParams,
+        mut results: daemon_capnp::daemon::ScheduleResults,
+    ) -> Promise<(), Error> {
+        let entries = crate::module::scheduler::entries();
+        let mut list = results.get().init_entries(entries.len() as u32);
+        for (i, (id, entry)) in entries.iter().enumerate() {
+            let mut item = list.reborrow().get(i as u32);
+            item.set_id(*id);
+            item.set_entry(entry);
+        }
+        Promise::ok(())
+    }
+
+    fn add_schedule(
+        &mut self,
+        params: daemon_capnp::daemon::AddScheduleParams,
+        mut results: daemon_capnp::daemon::AddScheduleResults,
+    ) -> Promise<(), Error> {
+        match self.auth {
+            AuthLevel::Secure | AuthLevel::Debug => {
+                let entry = pry!(pry!(params.get()).get_entry());
+                match crate::module::scheduler::add(entry) {
+                    Ok(id) => {
+                        results.get().set_id(id);
+                        Promise::ok(())
+                    }
+                    Err(e) => Promise::err(capnp::Error {
+                        kind: ::capnp::ErrorKind::Failed,
+                        description: format!("Error (add_schedule): {}", e),
+                    }),
+                }
+            }
+            _ => Promise::err(capnp::Error {
+                kind: ::capnp::ErrorKind::Failed,
+                description: "Insufficient authorization level".to_string(),
+            }),
+        }
+    }
+
+    fn remove_schedule(
+        &mut self,
+        params: daemon_capnp::daemon::RemoveScheduleParams,
+        _results: daemon_capnp::daemon::RemoveScheduleResults,
+    ) -> Promise<(), Error> {
+        match self.auth {
+            AuthLevel::Secure | AuthLevel::Debug => {
+                let id = pry!(params.get()).get_id();
+                match crate::module::scheduler::remove(id) {
+                    Ok(_) => Promise::ok(()),
+                    Err(e) => Promise::err(capnp::Error {
+                        kind: ::capnp::ErrorKind::Failed,
+                        description: format!("Error (remove_schedule): {}", e),
+                    }),
+                }
+            }
+            _ => Promise::err(capnp::Error {
+                kind: ::capnp::ErrorKind::Failed,
+                description: "Insufficient authorization level".to_string(),
+            }),
+        }
+    }
+
+    fn diagnostics(
+        &mut self,
+        _params: daemon_capnp::daemon::DiagnosticsParams,
+        mut results: daemon_capnp::daemon::DiagnosticsResults,
+    ) -> Promise<(), Error> {
+        match crate::module::diagnostics::write_report() {
+            Ok((path, report)) => {
+                results.get().set_path(&path.to_string_lossy());
+                results.get().set_report(&report);
+                Promise::ok(())
+            }
+            Err(e) => Promise::err(capnp::Error {
+                kind: ::capnp::ErrorKind::Failed,
+                description: format!("Error (diagnostics): {}", e),
+            }),
+        }
+    }
+
+    fn device_filter(
+        &mut self,
+        _params: daemon_capnp::daemon::DeviceFilterParams,
+        mut results: daemon_capnp::daemon::DeviceFilterResults,
+    ) -> Promise<(), Error> {
+        let rules = crate::device::filter::rules();
+        let mut list = results.get().init_rules(rules.len() as u32);
+        for (i, (id, rule)) in rules.iter().enumerate() {
+            let mut item = list.reborrow().get(i as u32);
+            item.set_id(*id);
+            item.set_rule(rule);
+        }
+        Promise::ok(())
+    }
+
+    fn add_device_filter(
+        &mut self,
+        params: daemon_capnp::daemon::AddDeviceFilterParams,
+        mut results: daemon_capnp::daemon::AddDeviceFilterResults,
+    ) -> Promise<(), Error> {
+        match self.auth {
+            AuthLevel::Secure | AuthLevel::Debug => {
+                let rule = pry!(pry!(params.get()).get_rule());
+                match crate::device::filter::add(rule) {
+                    Ok(id) => {
+                        results.get().set_id(id);
+                        Promise::ok(())
+                    }
+                    Err(e) => Promise::err(capnp::Error {
+                        kind: ::capnp::ErrorKind::Failed,
+                        description: format!("Error (add_device_filter): {}", e),
+                    }),
+                }
+            }
+            _ => Promise::err(capnp::Error {
+                kind: ::capnp::ErrorKind::Failed,
+                description: "Insufficient authorization level".to_string(),
+            }),
+        }
+    }
+
+    fn remove_device_filter(
+        &mut self,
+        params: daemon_capnp::daemon::RemoveDeviceFilterParams,
+        _results: daemon_capnp::daemon::RemoveDeviceFilterResults,
+    ) -> Promise<(), Error> {
+        match self.auth {
+            AuthLevel::Secure | AuthLevel::Debug => {
+                let id = pry!(params.get()).get_id();
+                match crate::device::filter::remove(id) {
+                    Ok(_) => Promise::ok(()),
+                    Err(e) => Promise::err(capnp::Error {
+                        kind: ::capnp::ErrorKind::Failed,
+                        description: format!("Error (remove_device_filter): {}", e),
+                    }),
+                }
+            }
+            _ => Promise::err(capnp::Error {
+                kind: ::capnp::ErrorKind::Failed,
+                description: "Insufficient authorization level".to_string(),
+            }),
+        }
+    }
+}
+
+/// Fill in the enumeration details of a device node
+fn set_device_info(mut builder: common_capnp::device_info::Builder, info: &HidApiInfo) {
+    builder.set_vendor_id(info.vendor_id);
+    builder.set_product_id(info.product_id);
+    builder.set_release_number(info.release_number);
+    builder.set_manufacturer(&info.manufacturer_string);
+    builder.set_product(&info.product_string);
+    builder.set_interface_number(info.interface_number);
+    builder.set_port_path(&info.port_path);
+}
+
+/// Fill in the access mode of a device node
+fn set_access(mut builder: common_capnp::destination::Builder, access: &Access) {
+    match access {
+        Access::Shared => builder.set_access(common_capnp::Access::Shared),
+        Access::Exclusive => builder.set_access(common_capnp::Access::Exclusive),
+        Access::SharedFallback(reason) => {
+            builder.set_access(common_capnp::Access::SharedFallback);
+            builder.set_access_error(reason);
+        }
+    }
+}
+
+/// Fill in a LinkStats struct from the statistics of a device node
+fn set_link_stats(
+    mut builder: common_capnp::link_stats::Builder,
+    stats: &crate::device::LinkStats,
+) {
+    let micros = |latency: Option<std::time::Duration>| {
+        latency.map_or(0, |latency| {
+            latency.as_micros().min(u32::MAX as u128) as u32
+        })
+    };
+    builder.set_latency_us(micros(stats.latency));
+    builder.set_average_latency_us(micros(stats.average_latency));
+    builder.set_naks_sent(stats.naks_sent);
+    builder.set_naks_received(stats.naks_received);
+    builder.set_sync_timeouts(stats.sync_timeouts);
+    builder.set_reconnects(stats.reconnects);
+    builder.set_decode_errors(stats.decode_errors);
+}
+
+/// Fill in a daemon Module struct from the module registry
+fn set_module_info(
+    mut builder: daemon_capnp::daemon::module::Builder,
+    module: &crate::module::registry::ModuleInfo,
+) {
+    use crate::module::registry::ModuleStatus;
+
+    builder.set_name(module.name);
+    let mut deps = builder
+        .reborrow()
+        .init_dependencies(module.dependencies.len() as u32);
+    for (i, dep) in module.dependencies.iter().enumerate() {
+        deps.set(i as u32, dep);
+    }
+    builder.set_enabled(module.enabled);
+    builder.set_stoppable(module.stoppable);
+    builder.set_status(match module.status {
+        ModuleStatus::Pending => daemon_capnp::daemon::ModuleStatus::Pending,
+        ModuleStatus::Running => daemon_capnp::daemon::ModuleStatus::Running,
+        ModuleStatus::Finished => daemon_capnp::daemon::ModuleStatus::Finished,
+        ModuleStatus::Failed => daemon_capnp::daemon::ModuleStatus::Failed,
+        ModuleStatus::Disabled => daemon_capnp::daemon::ModuleStatus::Disabled,
+        ModuleStatus::Blocked => daemon_capnp::daemon::ModuleStatus::Blocked,
+    });
 }
 
 struct DaemonSubscriberHandle {
@@ -1627,6 +2557,8 @@ async fn server_subscriptions_daemon(
             // TODO Split into multiple stream paths? Or just handle here?
             while let Some(msg) = stream.next().await {
                 debug!("DISDAM {:?}", msg);
+                // Not entered, the span must not be held across awaits
+                let span = msg.span("api.daemon");
 
                 // Forward message to api callback
                 let mut request = subscriptions
@@ -1650,6 +2582,7 @@ async fn server_subscriptions_daemon(
 
                 // Block on each send, drop subscription on failure
                 if let Err(e) = request.send().promise.await {
+                    tracing::warn!(parent: &span, "delivery failed");
                     warn!("daemonwatcher packet error: {:?}. Dropping subscriber.", e);
                     subscriptions
                         .write()
@@ -1727,14 +2660,33 @@ async fn server_subscriptions_keyboard(
             //  host macro (TODO)
             //  kll trigger (TODO)
             //  layer (TODO)
+            //  key matrix test (matrix test subscriptions only)
+            let matrix_test = subscriptions
+                .read()
+                .unwrap()
+                .keyboard_node
+                .subscribers
+                .get(&last_keyboard_next_id)
+                .unwrap()
+                .matrix_test;
             let mut stream = stream.filter(|msg| {
+                let is_matrix_test = msg.data.id == HidIoCommandId::ManufacturingResult
+                    && msg.data.data.len() >= 2
+                    && u16::from_le_bytes([msg.data.data[0], msg.data.data[1]])
+                        == MANUFACTURING_MATRIX_TEST;
+                if matrix_test {
+                    return is_matrix_test;
+                }
                 msg.data.id == HidIoCommandId::TerminalOut
                     || msg.data.id == HidIoCommandId::KllState
                     || msg.data.id == HidIoCommandId::HostMacro
+                    || (msg.data.id == HidIoCommandId::ManufacturingResult && !is_matrix_test)
             });
 
             // Handle stream
             while let Some(msg) = stream.next().await {
+                // Not entered, the span must not be held across awaits
+                let span = msg.span("api.keyboard");
                 let src = msg.src;
                 let dst = msg.dst;
 
@@ -1806,6 +2758,23 @@ async fn server_subscriptions_keyboard(
                                 .expect("Time went backwards")
                                 .as_millis() as u64,
                         );
+                        if data.command == MANUFACTURING_MATRIX_TEST {
+                            // [<scancode:16 bits> <row:8 bits> <column:8 bits> <state:8 bits>...]
+                            let events = data.data.chunks_exact(5).collect::<Vec<&[u8]>>();
+                            let mut list = signal
+                                .init_data()
+                                .init_matrix_test()
+                                .init_events(events.len() as u32);
+                            for (i, event) in events.iter().enumerate() {
+                                let mut key = list.reborrow().get(i as u32);
+                                key.set_scan_code(u16::from_le_bytes([event[0], event[1]]));
+                                key.set_row(event[2]);
+                                key.set_column(event[3]);
+                                key.set_pressed(event[4] != 0);
+                            }
+                            return Ok(h0051::Ack {});
+                        }
+
                         let mut result = signal.init_data().init_manufacturing();
                         result.set_cmd(data.command);
                         result.set_arg(data.argument);
@@ -1836,12 +2805,16 @@ async fn server_subscriptions_keyboard(
 
                 // Process incoming message
                 // TODO(HaaTa): Determine the best way to return some sort of error (capnp) if this fails
-                if let Err(err) = intf.rx_message_handling(msg.data) {
-                    error!("rx_message_handling failed!: {:?}", err);
+                {
+                    let _enter = span.enter();
+                    if let Err(err) = intf.rx_message_handling(msg.data) {
+                        error!("rx_message_handling failed!: {:?}", err);
+                    }
                 }
 
                 // Block on each send, drop subscription on failure
                 if let Err(e) = intf.request.send().promise.await {
+                    tracing::warn!(parent: &span, "delivery failed");
                     warn!(
                         "keyboardwatcher packet error: {:?}. Dropping subscriber.",
                         e
@@ -1929,6 +2902,9 @@ async fn server_subscriptions_hidiowatcher(
             }
 
             while let Some(msg) = stream.next().await {
+                // Not entered, the span must not be held across awaits
+                let span = msg.span("api.hidiowatcher");
+
                 // Forward message to api callback
                 let mut request = subscriptions
                     .read()
@@ -1961,7 +2937,7 @@ async fn server_subscriptions_hidiowatcher(
                     HidIoPacketType::Nak => hidio_capnp::hid_io::packet::Type::Nak,
                     _ => hidio_capnp::hid_io::packet::Type::Unknown,
                 });
-                packet.set_id(msg.data.id as u32);
+                packet.set_id(msg.data.raw_id());
                 let mut data = packet.init_data(msg.data.data.len() as u32);
                 for (index, elem) in msg.data.data.iter().enumerate() {
                     data.set(index as u32, *elem);
@@ -1969,6 +2945,7 @@ async fn server_subscriptions_hidiowatcher(
 
                 // Block on each send, drop subscription on failure
                 if let Err(e) = request.send().promise.await {
+                    tracing::warn!(parent: &span, "delivery failed");
                     warn!("hidiowatcher packet error: {:?}. Dropping subscriber.", e);
                     subscriptions
                         .write()
@@ -2048,7 +3025,8 @@ async fn server_subscriptions(
         let mut cur_node_count = 0;
 
         nodes.read().unwrap().iter().for_each(|endpoint| {
-            if let Some(_duration) = endpoint.created.checked_duration_since(last_node_refresh) {
+            // Nodes are also resent when changed (e.g. resynchronized after host resume)
+            if let Some(_duration) = endpoint.updated.checked_duration_since(last_node_refresh) {
                 nodes_update = true;
             }
             // Count total nodes, if total count doesn't match the last loop
@@ -2081,6 +3059,18 @@ async fn server_subscriptions(
                             node.set_name(&n.name);
                             node.set_serial(&n.serial);
                             node.set_id(n.uid);
+                            node.set_seat(&n.seat);
+                            if let common_capnp::NodeType::UsbKeyboard
+                            | common_capnp::NodeType::BleKeyboard
+                            | common_capnp::NodeType::BtKeyboard = n.type_
+                            {
+                                set_link_stats(
+                                    node.reborrow().init_link_stats(),
+                                    &n.link_stats.read().unwrap(),
+                                );
+                                set_access(node.reborrow(), &n.access);
+                                set_device_info(node.reborrow().init_device_info(), &n.hidapi);
+                            }
                             let mut node = node.init_node();
                             match n.type_ {
                                 common_capnp::NodeType::HidioDaemon => {
@@ -2093,7 +3083,8 @@ async fn server_subscriptions(
                                     )));
                                 }
                                 common_capnp::NodeType::UsbKeyboard
-                                | common_capnp::NodeType::BleKeyboard => {
+                                | common_capnp::NodeType::BleKeyboard
+                                | common_capnp::NodeType::BtKeyboard => {
                                     node.set_keyboard(capnp_rpc::new_client(
                                         KeyboardNodeImpl::new(
                                             mailbox.clone(),
@@ -2155,9 +3146,24 @@ async fn server_subscriptions(
 /// Supported Ids by this module
 pub fn supported_ids() -> Vec<HidIoCommandId> {
     vec![
+        HidIoCommandId::AnimationBegin,
+        HidIoCommandId::AnimationData,
+        HidIoCommandId::AnimationFinish,
+        HidIoCommandId::DisplayText,
+        HidIoCommandId::FileChecksum,
+        HidIoCommandId::FileClose,
+        HidIoCommandId::FileOpen,
+        HidIoCommandId::FileRead,
+        HidIoCommandId::FileWrite,
         HidIoCommandId::FlashMode,
         HidIoCommandId::HostMacro,
+        HidIoCommandId::KllLayoutControl,
+        HidIoCommandId::KllLayoutRead,
+        HidIoCommandId::KllLayoutWrite,
         HidIoCommandId::KllState,
+        HidIoCommandId::Pair,
+        HidIoCommandId::SettingsRead,
+        HidIoCommandId::SettingsWrite,
         HidIoCommandId::SleepMode,
         HidIoCommandId::TerminalCmd,
         HidIoCommandId::TerminalOut,
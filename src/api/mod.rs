@@ -24,10 +24,21 @@ mod capnp;
 #[cfg(feature = "api")]
 pub use crate::common_capnp;
 
+use crate::device::descriptor::ReportDescriptor;
+use crate::device::exclusive::Access;
+use crate::device::polling::{self, ReadMode};
+use crate::device::LinkStats;
 use crate::mailbox;
+use crate::module::seat;
 use hid_io_protocol::HidIoCommandId;
+use std::sync::{Arc, RwLock};
 use std::time::Instant;
 
+// ----- Consts -----
+
+/// Chunk size used for nodes that do not report one (full-speed USB packet)
+pub const DEFAULT_MAX_PACKET_LEN: u32 = 64;
+
 // ----- Functions -----
 
 /// Authorization level for a remote node
@@ -146,13 +157,17 @@ pub struct HidApiInfo {
     pub usage_page: u16,
     pub usage: u16,
     pub interface_number: i32,
+    /// USB bus/port path (e.g. 1-2.3), empty if unavailable
+    pub port_path: String,
 }
 
 impl HidApiInfo {
-    /// Generate a unique string based off of hidapi information (excluding path/physical location)
+    /// Generate a unique string based off of hidapi information (excluding path)
+    /// Devices without a serial number also include the USB port path, so identical devices keep
+    /// their uid as long as they stay plugged into the same port.
     pub fn key(&mut self) -> String {
         format!(
-            "vid:{:04x} pid:{:04x} serial:{} manufacturer:{} product:{} usage_page:{:x} usage:{:x} interface:{}",
+            "vid:{:04x} pid:{:04x} serial:{} manufacturer:{} product:{} usage_page:{:x} usage:{:x} interface:{} port:{}",
             self.vendor_id,
             self.product_id,
             self.serial_number,
@@ -161,9 +176,15 @@ impl HidApiInfo {
             self.usage_page,
             self.usage,
             self.interface_number,
+            if self.has_serial() { "" } else { self.port_path.as_str() },
         )
     }
 
+    /// Whether the serial number identifies the device (i.e. is set)
+    pub fn has_serial(&self) -> bool {
+        !self.serial_number.is_empty() && self.serial_number != "<Serial Unset>"
+    }
+
     #[cfg(feature = "hidapi-devices")]
     pub fn new(device_info: &hidapi::DeviceInfo) -> HidApiInfo {
         HidApiInfo {
@@ -186,6 +207,7 @@ impl HidApiInfo {
             usage_page: device_info.usage_page(),
             usage: device_info.usage(),
             interface_number: device_info.interface_number(),
+            port_path: crate::device::hidapi::port_path(device_info),
         }
     }
 }
@@ -196,6 +218,7 @@ pub mod common_capnp {
     #[derive(Clone, Copy, Debug, PartialEq)]
     pub enum NodeType {
         BleKeyboard,
+        BtKeyboard,
         HidJoystick,
         HidKeyboard,
         HidMouse,
@@ -212,9 +235,17 @@ pub struct Endpoint {
     serial: String, // Used for hidio (e.g. hidioDaemon, hidioApi) types
     pub uid: u64,
     created: Instant,
+    updated: Instant,
     hidapi: HidApiInfo,
     evdev: EvdevInfo,
     uhid: UhidInfo,
+    seat: String,
+    max_packet_len: u32,
+    descriptor: Option<ReportDescriptor>,
+    firmware_version: String,
+    link_stats: Arc<RwLock<LinkStats>>,
+    access: Access,
+    read_mode: Arc<RwLock<Option<ReadMode>>>,
 }
 
 impl std::fmt::Display for Endpoint {
@@ -224,6 +255,14 @@ impl std::fmt::Display for Endpoint {
                 "id:{} {} {}",
                 self.uid,
                 match self.type_ {
+                    common_capnp::NodeType::BtKeyboard => format!(
+                        "BT [{:04x}:{:04x}-{:x}:{:x}] {}",
+                        self.hidapi.vendor_id,
+                        self.hidapi.product_id,
+                        self.hidapi.usage_page,
+                        self.hidapi.usage,
+                        self.hidapi.product_string,
+                    ),
                     common_capnp::NodeType::BleKeyboard => format!(
                         "BLE [{:04x}:{:04x}-{:x}:{:x}] {}",
                         self.hidapi.vendor_id,
@@ -245,8 +284,9 @@ impl std::fmt::Display for Endpoint {
                     _ => self.name.clone(),
                 },
                 match self.type_ {
-                    common_capnp::NodeType::BleKeyboard | common_capnp::NodeType::UsbKeyboard =>
-                        self.hidapi.serial_number.clone(),
+                    common_capnp::NodeType::BleKeyboard
+                    | common_capnp::NodeType::BtKeyboard
+                    | common_capnp::NodeType::UsbKeyboard => self.hidapi.serial_number.clone(),
                     _ => self.serial.clone(),
                 },
             )
@@ -257,12 +297,14 @@ impl std::fmt::Display for Endpoint {
 
 impl Endpoint {
     pub fn new(type_: common_capnp::NodeType, uid: u64) -> Endpoint {
+        let created = Instant::now();
         Endpoint {
             type_,
             name: "".to_string(),
             serial: "".to_string(),
             uid,
-            created: Instant::now(),
+            created,
+            updated: created,
             hidapi: HidApiInfo {
                 ..Default::default()
             },
@@ -272,6 +314,13 @@ impl Endpoint {
             uhid: UhidInfo {
                 ..Default::default()
             },
+            seat: seat::DEFAULT_SEAT.to_string(),
+            max_packet_len: DEFAULT_MAX_PACKET_LEN,
+            descriptor: None,
+            firmware_version: "".to_string(),
+            link_stats: Arc::new(RwLock::new(LinkStats::default())),
+            access: Access::Shared,
+            read_mode: Arc::new(RwLock::new(None)),
         }
     }
 
@@ -295,6 +344,7 @@ impl Endpoint {
         self.hidapi = info;
         self.name = self.name();
         self.serial = self.serial();
+        *self.read_mode.write().unwrap() = polling::lookup(&self.hidapi);
     }
 
     pub fn set_uhid_params(&mut self, info: UhidInfo) {
@@ -303,17 +353,44 @@ impl Endpoint {
         self.serial = self.serial();
     }
 
+    pub fn set_seat(&mut self, seat: String) {
+        self.seat = seat;
+    }
+
+    pub fn set_max_packet_len(&mut self, max_packet_len: u32) {
+        self.max_packet_len = max_packet_len;
+    }
+
+    pub fn set_descriptor(&mut self, descriptor: Option<ReportDescriptor>) {
+        self.descriptor = descriptor;
+    }
+
     pub fn set_hidapi_path(&mut self, path: String) {
         self.hidapi.path = path;
     }
 
+    /// Firmware version reported by the device (h0001)
+    pub fn set_firmware_version(&mut self, firmware_version: String) {
+        self.firmware_version = firmware_version;
+    }
+
+    /// How the device interface was opened (see device::exclusive)
+    pub fn set_access(&mut self, access: Access) {
+        self.access = access;
+    }
+
+    /// Marks the node as changed, node list subscribers are sent the updated list
+    pub fn set_updated(&mut self) {
+        self.updated = Instant::now();
+    }
+
     pub fn type_(&mut self) -> common_capnp::NodeType {
         self.type_
     }
 
     pub fn name(&mut self) -> String {
         match self.type_ {
-            common_capnp::NodeType::BleKeyboard => format!(
+            common_capnp::NodeType::BleKeyboard | common_capnp::NodeType::BtKeyboard => format!(
                 "[{:04x}:{:04x}-{:x}:{:x}] {}",
                 self.hidapi.vendor_id,
                 self.hidapi.product_id,
@@ -341,18 +418,18 @@ impl Endpoint {
     /// Does not include release number as this may be incrementing
     pub fn key(&mut self) -> String {
         match self.type_ {
-            common_capnp::NodeType::BleKeyboard | common_capnp::NodeType::UsbKeyboard => {
-                self.hidapi.key()
-            }
+            common_capnp::NodeType::BleKeyboard
+            | common_capnp::NodeType::BtKeyboard
+            | common_capnp::NodeType::UsbKeyboard => self.hidapi.key(),
             _ => format!("name:{} serial:{}", self.name, self.serial,),
         }
     }
 
     pub fn serial(&mut self) -> String {
         match self.type_ {
-            common_capnp::NodeType::BleKeyboard | common_capnp::NodeType::UsbKeyboard => {
-                self.hidapi.serial_number.clone()
-            }
+            common_capnp::NodeType::BleKeyboard
+            | common_capnp::NodeType::BtKeyboard
+            | common_capnp::NodeType::UsbKeyboard => self.hidapi.serial_number.clone(),
             _ => self.serial.clone(),
         }
     }
@@ -365,9 +442,86 @@ impl Endpoint {
         self.created
     }
 
+    /// Last time the node was added or changed
+    pub fn updated(&mut self) -> Instant {
+        self.updated
+    }
+
     pub fn path(&mut self) -> String {
         self.hidapi.path.clone()
     }
+
+    pub fn vendor_id(&mut self) -> u16 {
+        self.hidapi.vendor_id
+    }
+
+    pub fn product_id(&mut self) -> u16 {
+        self.hidapi.product_id
+    }
+
+    pub fn manufacturer(&mut self) -> String {
+        self.hidapi.manufacturer_string.clone()
+    }
+
+    pub fn product(&mut self) -> String {
+        self.hidapi.product_string.clone()
+    }
+
+    /// USB interface number, -1 if unknown (e.g. macOS or Bluetooth)
+    pub fn interface_number(&mut self) -> i32 {
+        self.hidapi.interface_number
+    }
+
+    /// bcdDevice
+    pub fn release_number(&mut self) -> u16 {
+        self.hidapi.release_number
+    }
+
+    /// USB bus/port path, empty if unavailable
+    /// Unlike the serial number, this differs between identical devices.
+    pub fn hidapi(&self) -> &HidApiInfo {
+        &self.hidapi
+    }
+
+    pub fn port_path(&mut self) -> String {
+        self.hidapi.port_path.clone()
+    }
+
+    /// Seat the device is attached to (see module::seat)
+    pub fn seat(&mut self) -> String {
+        self.seat.clone()
+    }
+
+    /// Chunk size used when sending packets to the device
+    pub fn max_packet_len(&self) -> u32 {
+        self.max_packet_len
+    }
+
+    /// Parsed HID report descriptor, None if unavailable (or not a HID device)
+    pub fn descriptor(&self) -> Option<&ReportDescriptor> {
+        self.descriptor.as_ref()
+    }
+
+    /// Firmware version reported by the device, empty if unknown
+    pub fn firmware_version(&self) -> String {
+        self.firmware_version.clone()
+    }
+
+    /// Link health statistics, shared by clones of the node (and its device controller)
+    pub fn link_stats(&self) -> Arc<RwLock<LinkStats>> {
+        self.link_stats.clone()
+    }
+
+    /// Read mode of the device, None uses the default of the device module
+    /// Shared by clones of the node, changes apply to the running device.
+    pub fn read_mode(&self) -> Arc<RwLock<Option<ReadMode>>> {
+        self.read_mode.clone()
+    }
+
+    /// Access mode of the device interface
+    pub fn access(&self) -> Access {
+        self.access.clone()
+    }
 }
 
 /// Supported Ids by this module
@@ -0,0 +1,138 @@
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * This file is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This file is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this file.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+#[cfg(all(feature = "hidapi-devices", feature = "remote-devices"))]
+#[macro_use]
+extern crate log;
+
+/// Forwards a locally attached HID-IO device to a remote hid-io-core (remote-devices)
+///
+/// Uses the same remote-devices configuration file (key) as hid-io-core.
+#[cfg(all(feature = "hidapi-devices", feature = "remote-devices"))]
+fn main() -> Result<(), std::io::Error> {
+    use clap::{App, Arg};
+    use hid_io_core::api::HidApiInfo;
+    use hid_io_core::built_info;
+    use hid_io_core::device::descriptor::ReportDescriptor;
+    use hid_io_core::device::exclusive;
+    use hid_io_core::device::hidapi;
+    use hid_io_core::device::quirks;
+    use hid_io_core::device::remote;
+    use hid_io_core::device::selection;
+    use hid_io_core::logging;
+    use hid_io_protocol::crypt;
+    use std::io::{Read, Write};
+
+    /// Read timeout of both sides, chunks are forwarded as soon as they arrive
+    const TIMEOUT_MS: i32 = 10;
+
+    logging::setup_logging_lite()?;
+
+    let matches = App::new("hid-io-bridge")
+        .version(built_info::PKG_VERSION)
+        .author(built_info::PKG_AUTHORS)
+        .about("Forwards a HID-IO device to hid-io-core running on another machine")
+        .arg(
+            Arg::with_name("address")
+                .help("hid-io-core address, e.g. desktop:7186")
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("path")
+                .long("path")
+                .takes_value(true)
+                .help("hidapi path of the device (defaults to the first HID-IO device)"),
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .takes_value(true)
+                .help("remote-devices configuration file (key=...)"),
+        )
+        .get_matches();
+
+    let invalid = |msg: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, msg);
+    let config_file = match matches.value_of("config") {
+        Some(path) => std::path::PathBuf::from(path),
+        None => remote::Config::path()
+            .ok_or_else(|| invalid("Could not determine config directory".to_string()))?,
+    };
+    let config = remote::Config::load(&config_file)
+        .ok_or_else(|| invalid(format!("Could not load {:?}", config_file)))?;
+    let cipher = crypt::PayloadCipher::new(&config.key);
+
+    // Locate device
+    let api = ::hidapi::HidApi::new()
+        .map_err(|e| invalid(format!("HID API object creation failed - {}", e)))?;
+    let device_info = api
+        .device_list()
+        .find(|info| match matches.value_of("path") {
+            Some(path) => info.path().to_string_lossy() == path,
+            None => hidapi::match_device(info),
+        })
+        .ok_or_else(|| invalid("No HID-IO device found".to_string()))?;
+    let mut quirks = quirks::lookup(
+        device_info.vendor_id(),
+        device_info.product_id(),
+        device_info.interface_number(),
+    );
+    let mut info = HidApiInfo::new(device_info);
+    info.path = device_info.path().to_string_lossy().to_string();
+    let selection = selection::lookup(info.vendor_id, info.product_id);
+    let descriptor = ReportDescriptor::read(&info.path);
+    hidapi::apply_report_id(descriptor.as_ref(), selection, &mut quirks);
+    let max_packet_len = quirks.packet_len(hidapi::max_packet_len(descriptor.as_ref(), selection));
+    quirks.seize |= exclusive::requested(&info);
+    let (device, _access) = hidapi::open_device(&api, device_info.path(), &quirks)
+        .map_err(|e| invalid(format!("Could not open {} - {}", info.path, e)))?;
+    let mut device = hidapi::HidApiDevice::new(device, TIMEOUT_MS, quirks);
+    info!("Forwarding {:?}", info);
+
+    // Connect to hid-io-core
+    let address = matches.value_of("address").unwrap();
+    let stream = std::net::TcpStream::connect(address)?;
+    stream.set_nodelay(true)?;
+    let mut remote = remote::RemoteDevice::new(stream);
+    remote.set_timeout(std::time::Duration::from_secs(5))?;
+    remote.connect(
+        &cipher,
+        &remote::Hello {
+            info,
+            max_packet_len,
+        },
+    )?;
+    remote.set_timeout(std::time::Duration::from_millis(TIMEOUT_MS as u64))?;
+    info!("Connected to {}", address);
+
+    // Forward chunks until either side disconnects
+    let mut buf = [0; 1024];
+    loop {
+        let len = device.read(&mut buf)?;
+        if len > 0 {
+            remote.write_all(&buf[..len])?;
+        }
+        let len = remote.read(&mut buf)?;
+        if len > 0 && device.write(&buf[..len])? < len {
+            warn!("Short write to device ({} bytes)", len);
+        }
+    }
+}
+
+#[cfg(not(all(feature = "hidapi-devices", feature = "remote-devices")))]
+fn main() {
+    eprintln!("hid-io-bridge requires the hidapi-devices and remote-devices features");
+    std::process::exit(1);
+}
@@ -28,7 +28,7 @@ use hid_io_core::RUNNING;
 #[cfg(windows)]
 use std::sync::atomic::Ordering;
 
-use clap::App;
+use clap::{App, Arg};
 use hid_io_core::api;
 use hid_io_core::built_info;
 use hid_io_core::device;
@@ -88,11 +88,18 @@ fn start() {
 
         // Process command-line arguments
         // Most of the information is generated from Cargo.toml using built crate (build.rs)
-        App::new(built_info::PKG_NAME.to_string())
+        let matches = App::new(built_info::PKG_NAME.to_string())
             .version(version_info.as_str())
             .author(built_info::PKG_AUTHORS)
             .about(format!("\n{}", built_info::PKG_DESCRIPTION).as_str())
             .after_help(after_info.as_str())
+            .arg(
+                Arg::with_name("replay")
+                    .long("replay")
+                    .takes_value(true)
+                    .multiple(true)
+                    .help("Replay a device traffic capture as a node"),
+            )
             .get_matches();
 
         // Start initialization
@@ -101,6 +108,13 @@ fn start() {
         // Setup mailbox
         let mailbox = mailbox::Mailbox::new(rt.clone());
 
+        // Replay captures (see device-traffic)
+        for path in matches.values_of("replay").into_iter().flatten() {
+            if let Err(e) = device::traffic::replay(mailbox.clone(), std::path::Path::new(path)) {
+                error!("Could not replay {} - {}", path, e);
+            }
+        }
+
         // Wait until completion
         let (_, _, _) = tokio::join!(
             // Initialize Modules
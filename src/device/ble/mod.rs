@@ -0,0 +1,420 @@
+#![cfg(all(feature = "ble-devices", target_os = "linux"))]
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * This file is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This file is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this file.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// ----- Crates -----
+
+use crate::api::common_capnp::NodeType;
+use crate::api::HidApiInfo;
+use crate::device::*;
+use crate::RUNNING;
+use dbus::arg::{PropMap, RefArg};
+use dbus::blocking::stdintf::org_freedesktop_dbus::ObjectManager;
+use dbus::blocking::Connection;
+use std::collections::HashMap;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, RwLock};
+
+// ----- Consts -----
+
+/// HID-IO GATT service
+pub const SERVICE_UUID: &str = "0000ff1c-1100-4849-4449-4f0000000000";
+
+/// Characteristic used for device to host packets (notify)
+pub const RX_CHAR_UUID: &str = "0000ff1c-1101-4849-4449-4f0000000000";
+
+/// Characteristic used for host to device packets (write without response)
+pub const TX_CHAR_UUID: &str = "0000ff1c-1102-4849-4449-4f0000000000";
+
+/// ATT header size, not available for the HID-IO packet
+const ATT_HEADER_SIZE: u16 = 3;
+
+/// Smallest usable HID-IO packet (header, 16-bit id and at least 1 byte of payload)
+const MIN_PACKET_SIZE: u16 = 5;
+
+const ENUMERATE_DELAY_MS: u64 = 2000;
+const TIMEOUT_MS: i32 = 500;
+const DBUS_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Paired devices that are not connected are only retried this often
+const CONNECT_RETRY: std::time::Duration = std::time::Duration::from_secs(30);
+
+const BLUEZ: &str = "org.bluez";
+const DEVICE_IFACE: &str = "org.bluez.Device1";
+const SERVICE_IFACE: &str = "org.bluez.GattService1";
+const CHARACTERISTIC_IFACE: &str = "org.bluez.GattCharacteristic1";
+
+// ----- Structs -----
+
+/// BLE device exposing the HID-IO GATT service
+///
+/// Uses the bluez AcquireNotify/AcquireWrite sockets, each notification/write is a single
+/// HID-IO packet.
+pub struct BleDevice {
+    notify: std::fs::File,
+    write: std::fs::File,
+    timeout: i32,
+}
+
+impl BleDevice {
+    pub fn new(notify: std::fs::File, write: std::fs::File, timeout: i32) -> BleDevice {
+        BleDevice {
+            notify,
+            write,
+            timeout,
+        }
+    }
+}
+
+impl std::io::Read for BleDevice {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut fds = [libc::pollfd {
+            fd: self.notify.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        }];
+        let res = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, self.timeout) };
+        if res < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        if res == 0 {
+            // Timeout, nothing to read
+            return Ok(0);
+        }
+        if fds[0].revents & libc::POLLIN == 0 {
+            // Socket is closed by bluez when the device disconnects
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotConnected,
+                "BLE notification socket closed",
+            ));
+        }
+
+        let len = self.notify.read(buf)?;
+        trace!("Received {} bytes", len);
+        trace!("{:x?}", &buf[0..len]);
+        Ok(len)
+    }
+}
+
+impl std::io::Write for BleDevice {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = self.write.write(buf)?;
+        trace!("Sent {} bytes", len);
+        trace!("{:x?}", &buf[0..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl HidIoTransport for BleDevice {
+    fn set_read_timeout(&mut self, timeout: Option<i32>) {
+        self.timeout = timeout.unwrap_or(TIMEOUT_MS);
+    }
+}
+
+/// HID-IO GATT service found on a connected device
+struct GattDevice {
+    path: String,
+    info: HidApiInfo,
+    rx: String,
+    tx: String,
+}
+
+// ----- Functions -----
+
+fn prop_str<'a>(props: &'a PropMap, key: &str) -> Option<&'a str> {
+    props.get(key).and_then(|v| v.0.as_str())
+}
+
+fn prop_bool(props: &PropMap, key: &str) -> bool {
+    props
+        .get(key)
+        .and_then(|v| v.0.as_u64())
+        .map_or(false, |v| v != 0)
+}
+
+fn has_service(props: &PropMap) -> bool {
+    props
+        .get("UUIDs")
+        .and_then(|v| v.0.as_iter())
+        .map_or(false, |mut uuids| {
+            uuids.any(|uuid| uuid.as_str() == Some(SERVICE_UUID))
+        })
+}
+
+/// Builds the device information from the bluez Device1 properties
+/// The Modalias (e.g. bluetooth:v1C11pB04Ed0001) provides the vendor and product ids.
+fn device_info(path: &str, props: &PropMap) -> HidApiInfo {
+    let mut info = HidApiInfo {
+        path: path.to_string(),
+        serial_number: prop_str(props, "Address")
+            .unwrap_or("<Serial Unset>")
+            .to_string(),
+        product_string: prop_str(props, "Alias")
+            .or_else(|| prop_str(props, "Name"))
+            .unwrap_or("<Product Unset>")
+            .to_string(),
+        manufacturer_string: "<Manufacturer Unset>".to_string(),
+        ..Default::default()
+    };
+    if let Some(modalias) = prop_str(props, "Modalias") {
+        let ids = modalias.splitn(2, ':').nth(1).unwrap_or("");
+        if ids.len() == 15 && ids.is_ascii() {
+            info.vendor_id = u16::from_str_radix(&ids[1..5], 16).unwrap_or(0);
+            info.product_id = u16::from_str_radix(&ids[6..10], 16).unwrap_or(0);
+            info.release_number = u16::from_str_radix(&ids[11..15], 16).unwrap_or(0);
+        }
+    }
+    info
+}
+
+/// Finds connected devices with the HID-IO GATT service
+/// Paired devices that advertise the service but are not connected are connected to.
+fn scan(
+    conn: &Connection,
+    connect_attempts: &mut HashMap<String, std::time::Instant>,
+) -> Result<Vec<GattDevice>, dbus::Error> {
+    let objects = conn
+        .with_proxy(BLUEZ, "/", DBUS_TIMEOUT)
+        .get_managed_objects()?;
+
+    // HID-IO characteristics, by service
+    let mut rx = HashMap::new();
+    let mut tx = HashMap::new();
+    for (path, ifaces) in objects.iter() {
+        if let Some(props) = ifaces.get(CHARACTERISTIC_IFACE) {
+            let service = prop_str(props, "Service").unwrap_or("").to_string();
+            match prop_str(props, "UUID") {
+                Some(RX_CHAR_UUID) => {
+                    rx.insert(service, path.to_string());
+                }
+                Some(TX_CHAR_UUID) => {
+                    tx.insert(service, path.to_string());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let mut devices = vec![];
+    for (path, ifaces) in objects.iter() {
+        let props = match ifaces.get(SERVICE_IFACE) {
+            Some(props) => props,
+            None => {
+                continue;
+            }
+        };
+        if prop_str(props, "UUID") != Some(SERVICE_UUID) {
+            continue;
+        }
+        let service = path.to_string();
+        let (rx, tx) = match (rx.get(&service), tx.get(&service)) {
+            (Some(rx), Some(tx)) => (rx.clone(), tx.clone()),
+            _ => {
+                warn!("{} is missing HID-IO characteristics", service);
+                continue;
+            }
+        };
+        let device = prop_str(props, "Device").unwrap_or("");
+        let props = match objects
+            .iter()
+            .find(|(path, _)| &***path == device)
+            .and_then(|(_, ifaces)| ifaces.get(DEVICE_IFACE))
+        {
+            Some(props) => props,
+            None => {
+                continue;
+            }
+        };
+        if !prop_bool(props, "Connected") {
+            continue;
+        }
+        devices.push(GattDevice {
+            path: device.to_string(),
+            info: device_info(device, props),
+            rx,
+            tx,
+        });
+    }
+
+    // Connect to paired devices, bluez only reconnects automatically if the device initiates
+    for (path, ifaces) in objects.iter() {
+        let props = match ifaces.get(DEVICE_IFACE) {
+            Some(props) => props,
+            None => {
+                continue;
+            }
+        };
+        if !prop_bool(props, "Paired") || prop_bool(props, "Connected") || !has_service(props) {
+            continue;
+        }
+        let path = path.to_string();
+        if let Some(last) = connect_attempts.get(&path) {
+            if last.elapsed() < CONNECT_RETRY {
+                continue;
+            }
+        }
+        connect_attempts.insert(path.clone(), std::time::Instant::now());
+        debug!("Connecting to BLE device {}", path);
+        let result: Result<(), dbus::Error> = conn
+            .with_proxy(BLUEZ, &path, DBUS_TIMEOUT)
+            .method_call(DEVICE_IFACE, "Connect", ());
+        if let Err(e) = result {
+            debug!("Could not connect to {} - {}", path, e);
+        }
+    }
+
+    Ok(devices)
+}
+
+/// Acquires a notify or write socket of a characteristic
+/// Returns the socket and the usable packet size.
+fn acquire(
+    conn: &Connection,
+    path: &str,
+    method: &str,
+) -> Result<(std::fs::File, u16), dbus::Error> {
+    let (fd, mtu): (dbus::arg::OwnedFd, u16) = conn
+        .with_proxy(BLUEZ, path, DBUS_TIMEOUT)
+        .method_call(CHARACTERISTIC_IFACE, method, (PropMap::new(),))?;
+    let file = unsafe { std::fs::File::from_raw_fd(fd.into_fd()) };
+    Ok((file, mtu.saturating_sub(ATT_HEADER_SIZE)))
+}
+
+/// Opens the HID-IO characteristics of a device
+fn open_device(conn: &Connection, device: &GattDevice) -> Result<(BleDevice, u16), dbus::Error> {
+    let (notify, rx_len) = acquire(conn, &device.rx, "AcquireNotify")?;
+    let (write, tx_len) = acquire(conn, &device.tx, "AcquireWrite")?;
+    Ok((
+        BleDevice::new(notify, write, TIMEOUT_MS),
+        rx_len.min(tx_len),
+    ))
+}
+
+/// BLE processing
+///
+/// Scans bluez for connected devices with the HID-IO GATT service.
+/// Each device is then handled by its own thread, the same way as hidapi devices.
+async fn processing(mailbox: mailbox::Mailbox) {
+    info!("Spawning BLE spawning thread...");
+
+    let conn = match Connection::new_system() {
+        Ok(conn) => conn,
+        Err(e) => {
+            warn!("Could not connect to the system bus, BLE disabled - {}", e);
+            return;
+        }
+    };
+
+    // List of allocated device uids
+    let uids: Arc<RwLock<HashMap<u64, tokio::task::JoinHandle<()>>>> =
+        Arc::new(RwLock::new(HashMap::new()));
+    let mut connect_attempts = HashMap::new();
+
+    // Prepare the runtime
+    let rt = mailbox.rt.clone();
+
+    // Loop infinitely, the watcher only exits if the daemon is quit
+    loop {
+        if !RUNNING.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let devices = match scan(&conn, &mut connect_attempts) {
+            Ok(devices) => devices,
+            Err(e) => {
+                debug!("BLE scan failed (is bluez running?) - {}", e);
+                vec![]
+            }
+        };
+
+        for device in devices {
+            let mut info = device.info.clone();
+            let key = info.key();
+            let uid = match mailbox.clone().assign_uid(key, device.path.clone()) {
+                Ok(uid) => uid,
+                Err(_) => {
+                    // Device has already been registered, or is invalid
+                    continue;
+                }
+            };
+            if uids.read().unwrap().contains_key(&uid) {
+                continue;
+            }
+
+            info!("Connecting to uid:{} {} (BLE)", uid, device.path);
+            let ble_device = open_device(&conn, &device);
+
+            // Start thread
+            let uids_outer = uids.clone();
+            let uids = uids.clone();
+            let mailbox = mailbox.clone();
+            let handle = rt.clone().spawn_blocking(move || {
+                // Create node
+                let mut node = Endpoint::new(NodeType::BleKeyboard, uid);
+                node.set_hidapi_params(info);
+
+                let (ble_device, packet_len) = match ble_device {
+                    Ok((_, len)) if len < MIN_PACKET_SIZE => {
+                        warn!("BLE MTU too small for {}: {}", device.path, len);
+                        uids.write().unwrap().remove(&uid);
+                        return;
+                    }
+                    Ok(result) => result,
+                    Err(e) => {
+                        // Could not acquire the characteristics (likely disconnected)
+                        warn!("Failed to open BLE device:{} - {}", device.path, e);
+                        uids.write().unwrap().remove(&uid);
+                        return;
+                    }
+                };
+                println!("Connected to {}", node);
+                let mut device = HidIoEndpoint::new(Box::new(ble_device), packet_len as u32);
+                device.set_trusted(false);
+                serve(mailbox, uid, node, device);
+                uids.write().unwrap().remove(&uid);
+            });
+
+            // Add uid to hashmap
+            uids_outer.write().unwrap().insert(uid, handle);
+        }
+
+        tokio::time::sleep(std::time::Duration::from_millis(ENUMERATE_DELAY_MS)).await;
+    }
+}
+
+/// BLE initialization
+///
+/// Sets up a processing thread for BLE devices.
+pub async fn initialize(mailbox: mailbox::Mailbox) {
+    info!("Initializing device/ble...");
+
+    // Spawn watcher thread (tokio)
+    let rt = mailbox.rt.clone();
+    rt.clone()
+        .spawn_blocking(move || {
+            rt.block_on(async {
+                let local = tokio::task::LocalSet::new();
+                local.run_until(processing(mailbox)).await;
+            });
+        })
+        .await
+        .unwrap();
+}
@@ -0,0 +1,516 @@
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * This file is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This file is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this file.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// ----- Crates -----
+
+use crate::api::common_capnp::NodeType;
+use crate::api::HidApiInfo;
+use crate::device::descriptor::ReportDescriptor;
+use crate::device::exclusive::{self, Access};
+use crate::device::hidapi::{apply_report_id, max_packet_len};
+use crate::device::quirks::ReportIdMode;
+use crate::device::selection::Selection;
+use crate::device::*;
+use crate::module::seat;
+use crate::RUNNING;
+use dbus::arg::{PropMap, RefArg};
+use dbus::blocking::stdintf::org_freedesktop_dbus::ObjectManager;
+use dbus::blocking::Connection;
+use std::collections::{HashMap, HashSet};
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, RwLock};
+
+// ----- Consts -----
+
+/// Bluetooth Classic HID profile (HIDP)
+pub const HID_PROFILE_UUID: &str = "00001124-0000-1000-8000-00805f9b34fb";
+
+/// HID_ID bus type of Bluetooth devices (BUS_BLUETOOTH)
+const BUS_BLUETOOTH: u16 = 0x0005;
+
+const ENUMERATE_DELAY_MS: u64 = 2000;
+const TIMEOUT_MS: i32 = 500;
+const DBUS_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Known devices that are not connected are only retried this often
+const CONNECT_RETRY: std::time::Duration = std::time::Duration::from_secs(30);
+
+const BLUEZ: &str = "org.bluez";
+const DEVICE_IFACE: &str = "org.bluez.Device1";
+
+// ----- Structs -----
+
+/// Bluetooth Classic HID device, accessed through its hidraw node
+///
+/// The kernel hidp driver handles the L2CAP control and interrupt channels, each hidraw
+/// read/write is a single (interrupt) report.
+pub struct BtDevice {
+    file: std::fs::File,
+    timeout: i32,
+    report_id: ReportIdMode,
+    pool: BufferPool,
+}
+
+impl BtDevice {
+    pub fn new(file: std::fs::File, timeout: i32, report_id: ReportIdMode) -> BtDevice {
+        BtDevice {
+            file,
+            timeout,
+            report_id,
+            pool: BufferPool::default(),
+        }
+    }
+}
+
+impl std::io::Read for BtDevice {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut fds = [libc::pollfd {
+            fd: self.file.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        }];
+        let res = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, self.timeout) };
+        if res < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        if res == 0 {
+            // Timeout, nothing to read
+            return Ok(0);
+        }
+        if fds[0].revents & libc::POLLIN == 0 {
+            // hidraw node is removed by hidp when the device disconnects
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotConnected,
+                "hidraw node closed",
+            ));
+        }
+
+        let len = match self.report_id {
+            ReportIdMode::Unnumbered => self.file.read(buf)?,
+            ReportIdMode::Numbered(id) => {
+                // Numbered reports are prefixed with the report id, strip it
+                let mut rbuf = self.pool.take(buf.len() + 1);
+                let len = match self.file.read(&mut rbuf) {
+                    Ok(0) => Ok(0),
+                    Ok(len) if rbuf[0] != id => {
+                        warn!(
+                            "Dropping report with unexpected id {:#x} (expected {:#x}): {:x?}",
+                            rbuf[0],
+                            id,
+                            &rbuf[0..len]
+                        );
+                        Ok(0)
+                    }
+                    Ok(len) => {
+                        buf[0..len - 1].copy_from_slice(&rbuf[1..len]);
+                        Ok(len - 1)
+                    }
+                    Err(e) => Err(e),
+                };
+                self.pool.give(rbuf);
+                len?
+            }
+        };
+        if len > 0 {
+            trace!("Received {} bytes", len);
+            trace!("{:x?}", &buf[0..len]);
+        }
+        Ok(len)
+    }
+}
+
+impl std::io::Write for BtDevice {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // hidraw writes always start with the report id (0 if reports are unnumbered)
+        let mut wbuf = self.pool.take(0);
+        wbuf.push(match self.report_id {
+            ReportIdMode::Unnumbered => 0x00,
+            ReportIdMode::Numbered(id) => id,
+        });
+        wbuf.extend_from_slice(buf);
+        let res = self.file.write(&wbuf);
+        if let Ok(len) = res {
+            trace!("Sent {} bytes", len);
+            trace!("{:x?}", &wbuf[0..len]);
+        }
+        self.pool.give(wbuf);
+        res.map(|len| len.saturating_sub(1))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl HidIoTransport for BtDevice {
+    fn set_read_timeout(&mut self, timeout: Option<i32>) {
+        self.timeout = timeout.unwrap_or(TIMEOUT_MS);
+    }
+}
+
+/// HID device properties from the hidraw uevent file
+#[derive(Debug, Default, PartialEq)]
+struct Uevent {
+    bus: u16,
+    vendor_id: u16,
+    product_id: u16,
+    name: String,
+    uniq: String,
+}
+
+impl Uevent {
+    /// e.g. HID_ID=0005:00001C11:0000B04D
+    fn parse(contents: &str) -> Option<Uevent> {
+        let mut uevent = Uevent::default();
+        for line in contents.lines() {
+            let mut field = line.splitn(2, '=');
+            match (field.next(), field.next()) {
+                (Some("HID_ID"), Some(val)) => {
+                    let ids: Vec<&str> = val.split(':').collect();
+                    if ids.len() != 3 {
+                        return None;
+                    }
+                    uevent.bus = u32::from_str_radix(ids[0], 16).ok()? as u16;
+                    uevent.vendor_id = u32::from_str_radix(ids[1], 16).ok()? as u16;
+                    uevent.product_id = u32::from_str_radix(ids[2], 16).ok()? as u16;
+                }
+                (Some("HID_NAME"), Some(val)) => uevent.name = val.to_string(),
+                (Some("HID_UNIQ"), Some(val)) => uevent.uniq = val.to_lowercase(),
+                _ => {}
+            }
+        }
+        Some(uevent)
+    }
+}
+
+/// HID-IO interface of a connected Bluetooth Classic device
+struct HidrawDevice {
+    bluez_path: String,
+    devnode: String,
+    info: HidApiInfo,
+    descriptor: Option<ReportDescriptor>,
+    selection: Selection,
+}
+
+// ----- Functions -----
+
+fn prop_str<'a>(props: &'a PropMap, key: &str) -> Option<&'a str> {
+    props.get(key).and_then(|v| v.0.as_str())
+}
+
+fn prop_bool(props: &PropMap, key: &str) -> bool {
+    props
+        .get(key)
+        .and_then(|v| v.0.as_u64())
+        .map_or(false, |v| v != 0)
+}
+
+fn has_hid_profile(props: &PropMap) -> bool {
+    props
+        .get("UUIDs")
+        .and_then(|v| v.0.as_iter())
+        .map_or(false, |mut uuids| {
+            uuids.any(|uuid| uuid.as_str() == Some(HID_PROFILE_UUID))
+        })
+}
+
+/// Finds the HID-IO hidraw node of a connected device (by Bluetooth address)
+/// Only hidp (kernel) devices are used, bluez uhid devices are BLE.
+fn find_hidraw(bluez_path: &str, address: &str) -> Option<HidrawDevice> {
+    let address = address.to_lowercase();
+    for entry in std::fs::read_dir("/sys/class/hidraw").ok()?.flatten() {
+        let sysfs = entry.path().join("device");
+        let uevent = match std::fs::read_to_string(sysfs.join("uevent"))
+            .ok()
+            .and_then(|contents| Uevent::parse(&contents))
+        {
+            Some(uevent) => uevent,
+            None => {
+                continue;
+            }
+        };
+        if uevent.bus != BUS_BLUETOOTH || uevent.uniq != address {
+            continue;
+        }
+        if !std::fs::canonicalize(&sysfs)
+            .map_or(false, |path| path.to_string_lossy().contains("/bluetooth/"))
+        {
+            continue;
+        }
+
+        // Composite devices have a hidraw node per HID device, the HID-IO one has the usage
+        let devnode = format!("/dev/{}", entry.file_name().to_string_lossy());
+        let descriptor = ReportDescriptor::read(&devnode);
+        let selection = selection::lookup(uevent.vendor_id, uevent.product_id);
+        let (usage_page, usage) = match selection {
+            Selection::Usage { usage_page, usage } => (usage_page, usage),
+            Selection::Interface(_) => (0, 0),
+        };
+        if descriptor
+            .as_ref()
+            .and_then(|descriptor| selection.collection(descriptor))
+            .is_none()
+        {
+            continue;
+        }
+
+        let info = HidApiInfo {
+            path: devnode.clone(),
+            vendor_id: uevent.vendor_id,
+            product_id: uevent.product_id,
+            serial_number: uevent.uniq,
+            manufacturer_string: "<Manufacturer Unset>".to_string(),
+            product_string: uevent.name,
+            usage_page,
+            usage,
+            interface_number: -1,
+            ..Default::default()
+        };
+        return Some(HidrawDevice {
+            bluez_path: bluez_path.to_string(),
+            devnode,
+            info,
+            descriptor,
+            selection,
+        });
+    }
+    None
+}
+
+/// Finds connected Bluetooth Classic HID devices with a HID-IO interface
+/// Devices that had a HID-IO interface before are reconnected if they are paired but not
+/// connected (the HID profile is usually only reconnected by the device).
+fn scan(
+    conn: &Connection,
+    known: &mut HashSet<String>,
+    connect_attempts: &mut HashMap<String, std::time::Instant>,
+) -> Result<Vec<HidrawDevice>, dbus::Error> {
+    let objects = conn
+        .with_proxy(BLUEZ, "/", DBUS_TIMEOUT)
+        .get_managed_objects()?;
+
+    let mut devices = vec![];
+    for (path, ifaces) in objects.iter() {
+        let props = match ifaces.get(DEVICE_IFACE) {
+            Some(props) => props,
+            None => {
+                continue;
+            }
+        };
+        if !has_hid_profile(props) {
+            continue;
+        }
+        let path = path.to_string();
+        let address = prop_str(props, "Address").unwrap_or("");
+
+        if prop_bool(props, "Connected") {
+            if let Some(device) = find_hidraw(&path, address) {
+                known.insert(path);
+                devices.push(device);
+            }
+            continue;
+        }
+
+        if !prop_bool(props, "Paired") || !known.contains(&path) {
+            continue;
+        }
+        if let Some(last) = connect_attempts.get(&path) {
+            if last.elapsed() < CONNECT_RETRY {
+                continue;
+            }
+        }
+        connect_attempts.insert(path.clone(), std::time::Instant::now());
+        debug!("Connecting to Bluetooth device {}", path);
+        let result: Result<(), dbus::Error> = conn
+            .with_proxy(BLUEZ, &path, DBUS_TIMEOUT)
+            .method_call(DEVICE_IFACE, "ConnectProfile", (HID_PROFILE_UUID,));
+        if let Err(e) = result {
+            debug!("Could not connect to {} - {}", path, e);
+        }
+    }
+
+    Ok(devices)
+}
+
+/// Bluetooth Classic processing
+///
+/// Scans bluez for connected devices using the HID profile, and locates the HID-IO hidraw
+/// node created by the kernel hidp driver.
+/// Each device is then handled by its own thread, the same way as hidapi devices.
+async fn processing(mailbox: mailbox::Mailbox) {
+    info!("Spawning Bluetooth Classic spawning thread...");
+
+    let conn = match Connection::new_system() {
+        Ok(conn) => conn,
+        Err(e) => {
+            warn!(
+                "Could not connect to the system bus, Bluetooth Classic disabled - {}",
+                e
+            );
+            return;
+        }
+    };
+
+    // List of allocated device uids
+    let uids: Arc<RwLock<HashMap<u64, tokio::task::JoinHandle<()>>>> =
+        Arc::new(RwLock::new(HashMap::new()));
+    let mut known = HashSet::new();
+    let mut connect_attempts = HashMap::new();
+
+    // Prepare the runtime
+    let rt = mailbox.rt.clone();
+
+    // Loop infinitely, the watcher only exits if the daemon is quit
+    loop {
+        if !RUNNING.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let devices = match scan(&conn, &mut known, &mut connect_attempts) {
+            Ok(devices) => devices,
+            Err(e) => {
+                debug!("Bluetooth scan failed (is bluez running?) - {}", e);
+                vec![]
+            }
+        };
+
+        for device in devices {
+            let mut info = device.info.clone();
+            if !filter::allowed(&info) {
+                continue;
+            }
+            let key = info.key();
+            let uid = match mailbox.clone().assign_uid(key, device.devnode.clone()) {
+                Ok(uid) => uid,
+                Err(_) => {
+                    // Device has already been registered, or is invalid
+                    continue;
+                }
+            };
+            if uids.read().unwrap().contains_key(&uid) {
+                continue;
+            }
+
+            info!(
+                "Connecting to uid:{} {} {} (Bluetooth Classic)",
+                uid, device.bluez_path, device.devnode
+            );
+
+            // Lookup any device specific handling
+            let mut quirks = quirks::lookup(info.vendor_id, info.product_id, -1);
+            apply_report_id(device.descriptor.as_ref(), device.selection, &mut quirks);
+            let max_packet_len = max_packet_len(device.descriptor.as_ref(), device.selection);
+            let access = if exclusive::requested(&info) {
+                Access::SharedFallback(
+                    "Exclusive access is not supported for Bluetooth Classic devices".to_string(),
+                )
+            } else {
+                Access::Shared
+            };
+            let file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&device.devnode);
+            let seat = seat::device_seat(&device.devnode);
+
+            // Start thread
+            let uids_outer = uids.clone();
+            let uids = uids.clone();
+            let mailbox = mailbox.clone();
+            let handle = rt.clone().spawn_blocking(move || {
+                // Create node
+                let mut node = Endpoint::new(NodeType::BtKeyboard, uid);
+                node.set_hidapi_params(info);
+                node.set_seat(seat);
+                node.set_descriptor(device.descriptor);
+                node.set_access(access);
+
+                let file = match file {
+                    Ok(file) => file,
+                    Err(e) => {
+                        // Could not open the hidraw node (likely disconnected, or permissions)
+                        warn!("Failed to open device:{} - {}", device.devnode, e);
+                        uids.write().unwrap().remove(&uid);
+                        return;
+                    }
+                };
+                println!("Connected to {}", node);
+                let bt_device = BtDevice::new(file, TIMEOUT_MS, quirks.report_id);
+                let mut device = HidIoEndpoint::new(Box::new(bt_device), max_packet_len);
+                device.set_trusted(false);
+                device.apply_quirks(&quirks);
+                serve(mailbox, uid, node, device);
+                uids.write().unwrap().remove(&uid);
+            });
+
+            // Add uid to hashmap
+            uids_outer.write().unwrap().insert(uid, handle);
+        }
+
+        tokio::time::sleep(std::time::Duration::from_millis(ENUMERATE_DELAY_MS)).await;
+    }
+}
+
+/// Bluetooth Classic initialization
+///
+/// Sets up a processing thread for Bluetooth Classic HID devices.
+pub async fn initialize(mailbox: mailbox::Mailbox) {
+    info!("Initializing device/bt...");
+
+    // Spawn watcher thread (tokio)
+    let rt = mailbox.rt.clone();
+    rt.clone()
+        .spawn_blocking(move || {
+            rt.block_on(async {
+                let local = tokio::task::LocalSet::new();
+                local.run_until(processing(mailbox)).await;
+            });
+        })
+        .await
+        .unwrap();
+}
+
+// ----- Tests -----
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn uevent_test() {
+        let uevent = Uevent::parse(
+            "DRIVER=hid-generic\n\
+             HID_ID=0005:00001C11:0000B04D\n\
+             HID_NAME=Keyboard\n\
+             HID_PHYS=00:1a:7d:da:71:13\n\
+             HID_UNIQ=DC:2C:26:00:00:01\n\
+             MODALIAS=hid:b0005g0001v00001C11p0000B04D\n",
+        )
+        .unwrap();
+        assert_eq!(
+            uevent,
+            Uevent {
+                bus: BUS_BLUETOOTH,
+                vendor_id: 0x1c11,
+                product_id: 0xb04d,
+                name: "Keyboard".to_string(),
+                uniq: "dc:2c:26:00:00:01".to_string(),
+            }
+        );
+        assert_eq!(Uevent::parse("HID_ID=0005:00001C11\n"), None);
+    }
+}
@@ -0,0 +1,260 @@
+#![cfg(feature = "dev-capture")]
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * This file is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This file is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this file.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// ----- Crates -----
+
+#[cfg(any(target_os = "windows", target_os = "macos"))]
+use crate::mailbox;
+use crate::module::config_path;
+#[cfg(any(target_os = "windows", target_os = "macos"))]
+use hid_io_protocol::HidIoCommandId;
+
+// ----- Consts -----
+
+/// Capture configuration, stored in the hid-io-core config directory
+/// Devices are only captured if this file exists (and selects at least one device).
+pub const CONFIG_FILE: &str = "capture-devices";
+
+// ----- Enumerations -----
+
+/// Kind of captured device, the key of each configuration line
+///
+/// Mice and gamepads are only captured by evdev.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DeviceClass {
+    Keyboard,
+    Mouse,
+    Gamepad,
+}
+
+/// Device selector
+///
+/// keyboard=* selects all keyboards
+/// keyboard=<vid>:<pid> (hex) selects a specific keyboard
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Selector {
+    All,
+    Device(u16, u16),
+}
+
+// ----- Structs -----
+
+/// Per-device capture options (evdev only)
+///
+/// keyboard=<selector> grab remap=KEY_CAPSLOCK:KEY_LEFTCTRL,...
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Options {
+    /// Exclusive grab, keys are only seen by hid-io-core
+    pub grab: bool,
+    /// Key name pairs (from, to), the remapped keys are re-emitted to the OS
+    pub remap: Vec<(String, String)>,
+}
+
+/// Capture configuration
+#[derive(Debug, PartialEq)]
+pub struct Config {
+    devices: Vec<(DeviceClass, Selector, Options)>,
+}
+
+impl Config {
+    /// Loads the configuration from the config directory
+    /// Returns None if the file does not exist (or selects no keyboards).
+    pub fn load() -> Option<Config> {
+        let path = config_path(CONFIG_FILE)?;
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return None;
+            }
+            Err(e) => {
+                error!("Could not read {:?}: {}", path, e);
+                return None;
+            }
+        };
+
+        let config = Config::parse(&contents);
+        if config.is_none() {
+            warn!("No devices selected in {:?}", path);
+        }
+        config
+    }
+
+    fn parse(contents: &str) -> Option<Config> {
+        let mut config = Config { devices: vec![] };
+        for line in contents.lines() {
+            let mut field = line.trim().splitn(2, '=');
+            let (class, val) = match (field.next(), field.next()) {
+                (Some("keyboard"), Some(val)) => (DeviceClass::Keyboard, val),
+                (Some("mouse"), Some(val)) => (DeviceClass::Mouse, val),
+                (Some("gamepad"), Some(val)) => (DeviceClass::Gamepad, val),
+                _ => continue,
+            };
+
+            let mut args = val.split_whitespace();
+            let selector = match args.next() {
+                Some("*") => Selector::All,
+                Some(ids) => {
+                    let mut ids = ids.splitn(2, ':');
+                    let vid = ids.next().and_then(|id| u16::from_str_radix(id, 16).ok());
+                    let pid = ids.next().and_then(|id| u16::from_str_radix(id, 16).ok());
+                    match (vid, pid) {
+                        (Some(vid), Some(pid)) => Selector::Device(vid, pid),
+                        _ => {
+                            warn!("Invalid capture device {}", val);
+                            continue;
+                        }
+                    }
+                }
+                None => continue,
+            };
+
+            let mut options = Options::default();
+            for arg in args {
+                match arg.splitn(2, '=').collect::<Vec<_>>().as_slice() {
+                    ["grab"] => options.grab = true,
+                    ["remap", keys] => {
+                        for pair in keys.split(',') {
+                            match pair.splitn(2, ':').collect::<Vec<_>>().as_slice() {
+                                [from, to] => {
+                                    options.remap.push((from.to_string(), to.to_string()))
+                                }
+                                _ => warn!("Invalid remap {}", pair),
+                            }
+                        }
+                    }
+                    _ => warn!("Unknown capture option {}", arg),
+                }
+            }
+            config.devices.push((class, selector, options));
+        }
+
+        if config.devices.is_empty() {
+            return None;
+        }
+        Some(config)
+    }
+
+    /// Capture options of the device (first matching line)
+    /// None if the device should not be captured
+    pub fn options(&self, class: DeviceClass, vid: u16, pid: u16) -> Option<&Options> {
+        self.devices
+            .iter()
+            .filter(|(c, _, _)| *c == class)
+            .find(|(_, selector, _)| match selector {
+                Selector::All => true,
+                Selector::Device(v, p) => *v == vid && *p == pid,
+            })
+            .map(|(_, _, options)| options)
+    }
+
+    /// Whether the device should be captured
+    pub fn matches(&self, class: DeviceClass, vid: u16, pid: u16) -> bool {
+        self.options(class, vid, pid).is_some()
+    }
+}
+
+/// Captured keyboard
+#[cfg(any(target_os = "windows", target_os = "macos"))]
+pub struct Keyboard {
+    pub uid: u64,
+    /// Currently pressed HID keyboard usages
+    pressed: Vec<u8>,
+}
+
+#[cfg(any(target_os = "windows", target_os = "macos"))]
+impl Keyboard {
+    pub fn new(uid: u64) -> Keyboard {
+        Keyboard {
+            uid,
+            pressed: vec![],
+        }
+    }
+
+    /// Updates the pressed keys, then sends the keyboard state (same format as evdev)
+    pub fn update(&mut self, mailbox: &mailbox::Mailbox, usage: u8, pressed: bool) {
+        let prev = self.pressed.clone();
+        if pressed {
+            if !self.pressed.contains(&usage) {
+                self.pressed.push(usage);
+            }
+        } else {
+            self.pressed.retain(|&x| x != usage);
+        }
+
+        // Key repeat does not change the state
+        if prev == self.pressed {
+            return;
+        }
+        if let Err(e) = mailbox.try_send_command(
+            mailbox::Address::DeviceHid { uid: self.uid },
+            mailbox::Address::All,
+            HidIoCommandId::HidKeyboard,
+            self.pressed.clone(),
+            false,
+        ) {
+            debug!("uid:{} could not send key event - {:?}", self.uid, e);
+        }
+    }
+}
+
+// ----- Tests -----
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn config_test() {
+        let config = Config::parse("keyboard=308f:0013\nkeyboard=zz\n").unwrap();
+        assert!(config.matches(DeviceClass::Keyboard, 0x308f, 0x0013));
+        assert!(!config.matches(DeviceClass::Keyboard, 0x308f, 0x0011));
+        assert!(Config::parse("keyboard=*").unwrap().matches(
+            DeviceClass::Keyboard,
+            0x1234,
+            0x5678
+        ));
+        assert_eq!(Config::parse("keyboard=zz\n"), None);
+
+        let config = Config::parse(
+            "keyboard=308f:0013 grab remap=KEY_CAPSLOCK:KEY_LEFTCTRL,x\nkeyboard=*\n",
+        )
+        .unwrap();
+        let options = config
+            .options(DeviceClass::Keyboard, 0x308f, 0x0013)
+            .unwrap();
+        assert!(options.grab);
+        assert_eq!(
+            options.remap,
+            vec![("KEY_CAPSLOCK".to_string(), "KEY_LEFTCTRL".to_string())]
+        );
+        assert_eq!(
+            config.options(DeviceClass::Keyboard, 0x1234, 0x5678),
+            Some(&Options::default())
+        );
+        assert!(!config.matches(DeviceClass::Mouse, 0x1234, 0x5678));
+
+        let config = Config::parse("mouse=046d:c52b grab\ngamepad=*\n").unwrap();
+        assert!(
+            config
+                .options(DeviceClass::Mouse, 0x046d, 0xc52b)
+                .unwrap()
+                .grab
+        );
+        assert!(config.matches(DeviceClass::Gamepad, 0x045e, 0x028e));
+        assert!(!config.matches(DeviceClass::Keyboard, 0x046d, 0xc52b));
+    }
+}
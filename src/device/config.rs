@@ -0,0 +1,195 @@
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * This file is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This file is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this file.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// ----- Crates -----
+
+use crate::api::HidApiInfo;
+use crate::module::config_path;
+
+// ----- Structs -----
+
+/// Devices matched by a line of a device config file
+///
+/// <field=value>[,<field=value>...]
+///
+/// Fields: vid=<hex>, pid=<hex>, serial=<string>
+/// Config files may accept additional fields (e.g. interface=<n>). All fields must match.
+///
+/// e.g. vid=1c11,pid=b04d
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DeviceMatch {
+    pub vendor_id: Option<u16>,
+    pub product_id: Option<u16>,
+    pub serial: Option<String>,
+}
+
+impl DeviceMatch {
+    /// Parses the comma separated fields
+    /// Other fields are passed to extra, which returns false if the field is unknown.
+    pub fn parse<F>(fields: &str, mut extra: F) -> Result<DeviceMatch, String>
+    where
+        F: FnMut(&str, &str) -> Result<bool, String>,
+    {
+        let mut device = DeviceMatch::default();
+        for part in fields.split(',') {
+            let mut field = part.splitn(2, '=');
+            match (field.next(), field.next()) {
+                (Some("vid"), Some(val)) => device.vendor_id = Some(parse_hex(val)?),
+                (Some("pid"), Some(val)) => device.product_id = Some(parse_hex(val)?),
+                (Some("serial"), Some(val)) => device.serial = Some(val.to_string()),
+                (Some(key), Some(val)) if extra(key, val)? => {}
+                _ => {
+                    return Err(format!("Invalid field '{}'", part));
+                }
+            }
+        }
+        Ok(device)
+    }
+
+    /// Config files matching devices by vendor (and optionally product and serial number)
+    pub fn require_vid(self) -> Result<DeviceMatch, String> {
+        match self.vendor_id {
+            Some(_) => Ok(self),
+            None => Err("vid is required".to_string()),
+        }
+    }
+
+    /// Config files matching devices by vid and pid, before the serial number is known
+    pub fn require_ids(&self) -> Result<(u16, u16), String> {
+        if self.serial.is_some() {
+            return Err("serial is not supported".to_string());
+        }
+        match (self.vendor_id, self.product_id) {
+            (Some(vendor_id), Some(product_id)) => Ok((vendor_id, product_id)),
+            _ => Err("vid and pid are required".to_string()),
+        }
+    }
+
+    /// Unset fields match any device
+    pub fn matches(&self, info: &HidApiInfo) -> bool {
+        self.vendor_id.map_or(true, |vid| vid == info.vendor_id)
+            && self.product_id.map_or(true, |pid| pid == info.product_id)
+            && self
+                .serial
+                .as_ref()
+                .map_or(true, |serial| *serial == info.serial_number)
+    }
+}
+
+// ----- Functions -----
+
+pub fn parse_hex(val: &str) -> Result<u16, String> {
+    u16::from_str_radix(val, 16).map_err(|_| format!("Invalid hex value '{}'", val))
+}
+
+/// Reads a config file from the hid-io-core config directory
+/// Returns the contents and the name used in log messages, None if there is no file.
+pub fn read(file: &str, description: &str) -> Option<(String, String)> {
+    let path = config_path(file)?;
+    match std::fs::read_to_string(&path) {
+        Ok(contents) => Some((contents, format!("{:?}", path))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => {
+            error!("Could not read {} {:?}: {}", description, path, e);
+            None
+        }
+    }
+}
+
+/// Parses each line of a config file, empty lines and # comments are skipped
+/// Invalid lines are logged and skipped.
+pub fn parse_lines<T, F>(contents: &str, name: &str, mut parse: F) -> Vec<T>
+where
+    F: FnMut(&str) -> Result<T, String>,
+{
+    let mut entries = vec![];
+    for (num, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match parse(line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => error!("{}:{} {}", name, num + 1, e),
+        }
+    }
+    entries
+}
+
+/// Loads a config file (see read and parse_lines)
+pub fn load<T, F>(file: &str, description: &str, parse: F) -> Vec<T>
+where
+    F: FnMut(&str) -> Result<T, String>,
+{
+    match read(file, description) {
+        Some((contents, name)) => parse_lines(&contents, &name, parse),
+        None => vec![],
+    }
+}
+
+// ----- Tests -----
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn device_match_test() {
+        let mut interface = None;
+        let device = DeviceMatch::parse("vid=1c11,pid=b04d,interface=5", |key, val| {
+            match key {
+                "interface" => interface = Some(val.to_string()),
+                _ => {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        })
+        .unwrap();
+        assert_eq!(interface.as_deref(), Some("5"));
+        assert_eq!(device.require_ids(), Ok((0x1c11, 0xb04d)));
+
+        let no_extra = |_: &str, _: &str| Ok(false);
+        assert!(DeviceMatch::parse("vid=1c11,interface=5", no_extra).is_err());
+        assert!(DeviceMatch::parse("vid=xyz", no_extra).is_err());
+        assert!(DeviceMatch::parse("pid=b04d", no_extra)
+            .unwrap()
+            .require_vid()
+            .is_err());
+        assert!(DeviceMatch::parse("vid=1c11,pid=b04d,serial=abc", no_extra)
+            .unwrap()
+            .require_ids()
+            .is_err());
+
+        let device = DeviceMatch::parse("vid=308f,serial=abc", no_extra).unwrap();
+        let info = |product_id, serial: &str| HidApiInfo {
+            vendor_id: 0x308f,
+            product_id,
+            serial_number: serial.to_string(),
+            ..Default::default()
+        };
+        assert!(device.matches(&info(0x0011, "abc")));
+        assert!(!device.matches(&info(0x0011, "def")));
+    }
+
+    #[test]
+    fn parse_lines_test() {
+        let entries = parse_lines("# Comment\n\n  1 \nx\n2\n", "test", |line| {
+            line.parse::<u32>().map_err(|e| e.to_string())
+        });
+        assert_eq!(entries, vec![1, 2]);
+    }
+}
@@ -0,0 +1,369 @@
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * This file is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This file is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this file.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// ----- Crates -----
+
+use std::path::Path;
+
+// ----- Consts -----
+
+/// Generic Desktop usage page
+pub const GENERIC_DESKTOP: u16 = 0x01;
+
+// Generic Desktop application usages
+pub const USAGE_MOUSE: u16 = 0x02;
+pub const USAGE_JOYSTICK: u16 = 0x04;
+pub const USAGE_GAMEPAD: u16 = 0x05;
+pub const USAGE_KEYBOARD: u16 = 0x06;
+pub const USAGE_KEYPAD: u16 = 0x07;
+
+// Main items
+const INPUT: u8 = 0x80;
+const OUTPUT: u8 = 0x90;
+const FEATURE: u8 = 0xB0;
+const COLLECTION: u8 = 0xA0;
+const END_COLLECTION: u8 = 0xC0;
+
+// Global items
+const USAGE_PAGE: u8 = 0x04;
+const REPORT_SIZE: u8 = 0x74;
+const REPORT_ID: u8 = 0x84;
+const REPORT_COUNT: u8 = 0x94;
+
+// Local items
+const USAGE: u8 = 0x08;
+
+/// Long item prefix
+const LONG_ITEM: u8 = 0xFE;
+
+/// Application collection type
+const APPLICATION: u32 = 0x01;
+
+// ----- Enumerations -----
+
+#[derive(Debug, PartialEq)]
+pub enum DescriptorError {
+    /// Item at the given offset is cut short
+    Truncated(usize),
+    /// End Collection without a matching Collection at the given offset
+    UnbalancedCollection(usize),
+}
+
+impl std::fmt::Display for DescriptorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DescriptorError::Truncated(pos) => write!(f, "Truncated item at offset {}", pos),
+            DescriptorError::UnbalancedCollection(pos) => {
+                write!(f, "Unbalanced End Collection at offset {}", pos)
+            }
+        }
+    }
+}
+
+// ----- Structs -----
+
+/// Sizes of a single report id (in bits, excluding the report id)
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Report {
+    /// 0 if the interface does not use numbered reports
+    pub id: u8,
+    pub input_bits: u32,
+    pub output_bits: u32,
+    pub feature_bits: u32,
+}
+
+impl Report {
+    pub fn input_len(&self) -> u32 {
+        (self.input_bits + 7) / 8
+    }
+
+    pub fn output_len(&self) -> u32 {
+        (self.output_bits + 7) / 8
+    }
+
+    pub fn feature_len(&self) -> u32 {
+        (self.feature_bits + 7) / 8
+    }
+}
+
+/// Top-level application collection (e.g. keyboard, mouse, HID-IO RawHID)
+///
+/// Reports of nested (physical/logical) collections are included.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Collection {
+    pub usage_page: u16,
+    pub usage: u16,
+    pub reports: Vec<Report>,
+}
+
+impl Collection {
+    /// Payload length (in bytes, excluding the report id) of the largest report
+    ///
+    /// This is the largest chunk the device accepts (output reports), falls back to the input
+    /// report size if there are no output reports.
+    pub fn report_len(&self) -> Option<u32> {
+        let report = self
+            .reports
+            .iter()
+            .max_by_key(|r| r.output_bits.max(r.input_bits))?;
+        let len = if report.output_bits > 0 {
+            report.output_len()
+        } else {
+            report.input_len()
+        };
+        match len {
+            0 => None,
+            len => Some(len),
+        }
+    }
+
+    /// Report id used by the collection
+    /// None if the collection uses more than one report id
+    pub fn report_id(&self) -> Option<u8> {
+        match self.reports.as_slice() {
+            [report] => Some(report.id),
+            _ => None,
+        }
+    }
+
+    fn report(&mut self, id: u8) -> &mut Report {
+        match self.reports.iter().position(|r| r.id == id) {
+            Some(pos) => &mut self.reports[pos],
+            None => {
+                self.reports.push(Report {
+                    id,
+                    ..Default::default()
+                });
+                self.reports.last_mut().unwrap()
+            }
+        }
+    }
+}
+
+/// Parsed HID report descriptor
+///
+/// Only the information needed to select and talk to an interface is kept (application
+/// collections, report ids and report sizes), individual fields are not.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReportDescriptor {
+    pub collections: Vec<Collection>,
+}
+
+impl ReportDescriptor {
+    pub fn parse(descriptor: &[u8]) -> Result<ReportDescriptor, DescriptorError> {
+        let mut parsed = ReportDescriptor::default();
+        let mut page = 0;
+        let mut report_size = 0;
+        let mut report_count = 0;
+        let mut report_id = 0;
+        let mut usages: Vec<u32> = vec![];
+        let mut depth = 0;
+
+        let mut pos = 0;
+        while pos < descriptor.len() {
+            let prefix = descriptor[pos];
+            if prefix == LONG_ITEM {
+                // <prefix> <data size> <tag> <data>, not used by any standard items
+                let size = *descriptor
+                    .get(pos + 1)
+                    .ok_or(DescriptorError::Truncated(pos))? as usize;
+                pos += 3 + size;
+                continue;
+            }
+            let size = match prefix & 0x03 {
+                3 => 4,
+                size => size as usize,
+            };
+            let data = descriptor
+                .get(pos + 1..pos + 1 + size)
+                .ok_or(DescriptorError::Truncated(pos))?;
+            let value = data
+                .iter()
+                .rev()
+                .fold(0u32, |value, byte| value << 8 | *byte as u32);
+
+            match prefix & 0xFC {
+                USAGE_PAGE => page = value,
+                REPORT_SIZE => report_size = value,
+                REPORT_COUNT => report_count = value,
+                REPORT_ID => report_id = value as u8,
+                // 4 byte usages include the usage page
+                USAGE if size == 4 => usages.push(value),
+                USAGE => usages.push(page << 16 | value),
+                COLLECTION => {
+                    if depth == 0 && value == APPLICATION {
+                        let usage = usages.first().copied().unwrap_or(page << 16);
+                        parsed.collections.push(Collection {
+                            usage_page: (usage >> 16) as u16,
+                            usage: usage as u16,
+                            reports: vec![],
+                        });
+                    }
+                    depth += 1;
+                }
+                END_COLLECTION => {
+                    depth = depth
+                        .checked_sub(1)
+                        .ok_or(DescriptorError::UnbalancedCollection(pos))?;
+                }
+                INPUT | OUTPUT | FEATURE if depth > 0 => {
+                    let bits = report_size * report_count;
+                    if let Some(collection) = parsed.collections.last_mut() {
+                        let report = collection.report(report_id);
+                        match prefix & 0xFC {
+                            INPUT => report.input_bits += bits,
+                            OUTPUT => report.output_bits += bits,
+                            _ => report.feature_bits += bits,
+                        }
+                    }
+                }
+                _ => {}
+            }
+
+            // Local items only apply to the next main item
+            if prefix & 0x0C == 0x00 {
+                usages.clear();
+            }
+            pos += 1 + size;
+        }
+
+        Ok(parsed)
+    }
+
+    /// Reads and parses a report descriptor file (e.g. from sysfs)
+    pub fn from_file(path: &Path) -> Option<ReportDescriptor> {
+        let descriptor = match std::fs::read(path) {
+            Ok(descriptor) => descriptor,
+            Err(e) => {
+                debug!("Could not read {:?} - {}", path, e);
+                return None;
+            }
+        };
+        match ReportDescriptor::parse(&descriptor) {
+            Ok(parsed) => Some(parsed),
+            Err(e) => {
+                warn!("Invalid report descriptor {:?} - {}", path, e);
+                None
+            }
+        }
+    }
+
+    /// Reads the report descriptor of a hidapi (hidraw) device
+    #[cfg(target_os = "linux")]
+    pub fn read(path: &str) -> Option<ReportDescriptor> {
+        let name = Path::new(path).file_name()?;
+        let sysfs = Path::new("/sys/class/hidraw")
+            .join(name)
+            .join("device/report_descriptor");
+        ReportDescriptor::from_file(&sysfs)
+    }
+
+    /// Report descriptors are not exposed by hidapi on this platform
+    #[cfg(not(target_os = "linux"))]
+    pub fn read(_path: &str) -> Option<ReportDescriptor> {
+        None
+    }
+
+    /// Application collection with the given usage
+    pub fn collection(&self, usage_page: u16, usage: u16) -> Option<&Collection> {
+        self.collections
+            .iter()
+            .find(|c| c.usage_page == usage_page && c.usage == usage)
+    }
+
+    /// Whether there is an application collection with the given usage
+    pub fn has_collection(&self, usage_page: u16, usage: u16) -> bool {
+        self.collection(usage_page, usage).is_some()
+    }
+}
+
+// ----- Tests -----
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// HID-IO RawHID descriptor, <size> byte input/output reports
+    fn rawio(report_id: Option<u8>, size: u8) -> Vec<u8> {
+        let mut descriptor = vec![
+            0x05, 0x01, // Usage Page (Generic Desktop)
+            0x09, 0x06, // Usage (Keyboard)
+            0xA1, 0x01, // Collection (Application)
+            0x75, 0x08, //   Report Size (8)
+            0x95, 0x08, //   Report Count (8)
+            0x81, 0x02, //   Input (Data,Var,Abs)
+            0xC0, //       End Collection
+            0x06, 0x1C, 0xFF, // Usage Page (Vendor Defined) 0xFF1C
+            0x0A, 0x00, 0x11, // Usage (0x1100)
+            0xA1, 0x01, //       Collection (Application)
+        ];
+        if let Some(id) = report_id {
+            descriptor.extend_from_slice(&[0x85, id]); // Report ID
+        }
+        descriptor.extend_from_slice(&[
+            0x75, 0x08, // Report Size (8)
+            0x95, size, // Report Count
+            0x09, 0x01, // Usage (Output)
+            0x91, 0x02, // Output (Data,Var,Abs)
+            0x95, size, // Report Count
+            0x09, 0x02, // Usage (Input)
+            0x81, 0x02, // Input (Data,Var,Abs)
+            0xC0, //     End Collection
+        ]);
+        descriptor
+    }
+
+    #[test]
+    fn parse_test() {
+        let parsed = ReportDescriptor::parse(&rawio(Some(2), 32)).unwrap();
+        assert_eq!(parsed.collections.len(), 2);
+        assert!(parsed.has_collection(GENERIC_DESKTOP, USAGE_KEYBOARD));
+
+        let keyboard = parsed.collection(GENERIC_DESKTOP, USAGE_KEYBOARD).unwrap();
+        assert_eq!(keyboard.report_id(), Some(0));
+        assert_eq!(keyboard.reports[0].input_len(), 8);
+
+        let rawio = parsed.collection(0xFF1C, 0x1100).unwrap();
+        assert_eq!(rawio.report_id(), Some(2));
+        assert_eq!(rawio.reports[0].output_len(), 32);
+        assert_eq!(rawio.reports[0].feature_len(), 0);
+    }
+
+    #[test]
+    fn report_len_test() {
+        let report_len = |descriptor: &[u8]| {
+            ReportDescriptor::parse(descriptor)
+                .ok()?
+                .collection(0xFF1C, 0x1100)?
+                .report_len()
+        };
+        assert_eq!(report_len(&rawio(None, 64)), Some(64));
+        assert_eq!(report_len(&rawio(None, 32)), Some(32));
+        assert_eq!(report_len(&rawio(Some(2), 128)), Some(128));
+
+        // Missing End Collection is tolerated, truncated items are not
+        let descriptor = rawio(None, 64);
+        assert_eq!(report_len(&descriptor[..descriptor.len() - 1]), Some(64));
+        assert_eq!(
+            ReportDescriptor::parse(&descriptor[..22]),
+            Err(DescriptorError::Truncated(21))
+        );
+        assert_eq!(
+            ReportDescriptor::parse(&[0xC0]),
+            Err(DescriptorError::UnbalancedCollection(0))
+        );
+    }
+}
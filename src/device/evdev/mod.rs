@@ -20,9 +20,19 @@
 use crate::api::common_capnp;
 use crate::api::Endpoint;
 use crate::api::EvdevInfo;
+use crate::device::capture;
+use crate::device::descriptor::*;
 use crate::mailbox;
+use crate::module::hoststate;
+use crate::module::seat;
 use crate::module::vhid;
+use crate::RUNNING;
 use hid_io_protocol::*;
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+
+/// How often input devices are scanned for newly attached keyboards
+const RESCAN_DELAY_MS: u64 = 2000;
 
 // TODO This should be converted to use hid-io/layouts (may need a rust package to handle
 // conversion)
@@ -683,34 +693,184 @@ fn evdev2basehid(code: evdev_rs::enums::EventCode) -> std::io::Result<(HidIoComm
     }
 }
 
+/// Mouse buttons (BTN_LEFT..BTN_TASK), bit 0 is BTN_LEFT
+const MOUSE_BUTTONS: std::ops::RangeInclusive<u32> = 0x110..=0x117;
+
+/// Joystick and gamepad buttons (BTN_TRIGGER..BTN_THUMBR), bit 0 is BTN_TRIGGER
+const JOYSTICK_BUTTONS: std::ops::RangeInclusive<u32> = 0x120..=0x13F;
+
+/// Number of axes sent in HidMouse/HidJoystick messages
+const AXES: usize = 8;
+
+/// Pointer/controller state, sent as HidMouse or HidJoystick messages on each SYN_REPORT
+///
+/// HidMouse payload (little endian, relative movement since the last message)
+/// <buttons: u8> <x: i16> <y: i16> <wheel: i16> <hwheel: i16>
+///
+/// HidJoystick payload (little endian, absolute, scaled to -32768..32767)
+/// <buttons: u32> <x: i16> <y: i16> <z: i16> <rx: i16> <ry: i16> <rz: i16> <hat0x: i16> <hat0y: i16>
+#[derive(Debug, Default, PartialEq)]
+struct AxisState {
+    buttons: u32,
+    axes: [i16; AXES],
+    changed: bool,
+}
+
+impl AxisState {
+    fn button(&mut self, range: std::ops::RangeInclusive<u32>, code: u32, value: i32) {
+        if !range.contains(&code) {
+            return;
+        }
+        let bit = 1 << (code - range.start());
+        if value != 0 {
+            self.buttons |= bit;
+        } else {
+            self.buttons &= !bit;
+        }
+        self.changed = true;
+    }
+
+    fn mouse_event(&mut self, event: &evdev_rs::InputEvent) {
+        use evdev_rs::enums::{EventCode, EV_REL};
+        match &event.event_code {
+            EventCode::EV_KEY(key) => self.button(MOUSE_BUTTONS, key.clone() as u32, event.value),
+            EventCode::EV_REL(rel) => {
+                let axis = match rel {
+                    EV_REL::REL_X => 0,
+                    EV_REL::REL_Y => 1,
+                    EV_REL::REL_WHEEL => 2,
+                    EV_REL::REL_HWHEEL => 3,
+                    _ => {
+                        return;
+                    }
+                };
+                let value = self.axes[axis] as i32 + event.value;
+                self.axes[axis] = value.max(i16::MIN as i32).min(i16::MAX as i32) as i16;
+                self.changed = true;
+            }
+            _ => {}
+        }
+    }
+
+    fn joystick_event(&mut self, device: &evdev_rs::Device, event: &evdev_rs::InputEvent) {
+        use evdev_rs::enums::{EventCode, EV_ABS};
+        match &event.event_code {
+            EventCode::EV_KEY(key) => {
+                self.button(JOYSTICK_BUTTONS, key.clone() as u32, event.value)
+            }
+            EventCode::EV_ABS(abs) => {
+                let axis = match abs {
+                    EV_ABS::ABS_X => 0,
+                    EV_ABS::ABS_Y => 1,
+                    EV_ABS::ABS_Z => 2,
+                    EV_ABS::ABS_RX => 3,
+                    EV_ABS::ABS_RY => 4,
+                    EV_ABS::ABS_RZ => 5,
+                    EV_ABS::ABS_HAT0X => 6,
+                    EV_ABS::ABS_HAT0Y => 7,
+                    _ => {
+                        return;
+                    }
+                };
+                let (min, max) = device
+                    .abs_info(&event.event_code)
+                    .map(|info| (info.minimum, info.maximum))
+                    .unwrap_or((i16::MIN as i32, i16::MAX as i32));
+                self.axes[axis] = scale_axis(event.value, min, max);
+                self.changed = true;
+            }
+            _ => {}
+        }
+    }
+
+    /// HidMouse payload, relative axes are reset
+    fn mouse_payload(&mut self) -> Vec<u8> {
+        let mut data = vec![self.buttons as u8];
+        for axis in self.axes[..4].iter() {
+            data.extend_from_slice(&axis.to_le_bytes());
+        }
+        self.axes = [0; AXES];
+        self.changed = false;
+        data
+    }
+
+    /// HidJoystick payload
+    fn joystick_payload(&mut self) -> Vec<u8> {
+        let mut data = self.buttons.to_le_bytes().to_vec();
+        for axis in self.axes.iter() {
+            data.extend_from_slice(&axis.to_le_bytes());
+        }
+        self.changed = false;
+        data
+    }
+}
+
+/// Scales an absolute axis value (min..max) to -32768..32767
+fn scale_axis(value: i32, min: i32, max: i32) -> i16 {
+    if max <= min {
+        return 0;
+    }
+    let value = value.max(min).min(max) as i64 - min as i64;
+    (value * 65535 / (max as i64 - min as i64) - 32768) as i16
+}
+
+/// Convert key names (e.g. KEY_CAPSLOCK) into an evdev remapping table
+pub fn remap_table(
+    remap: &[(String, String)],
+) -> std::io::Result<HashMap<u32, evdev_rs::enums::EV_KEY>> {
+    use evdev_rs::enums::{EventCode, EventType};
+    let key = |name: &str| match EventCode::from_str(&EventType::EV_KEY, name) {
+        Some(EventCode::EV_KEY(key)) => Ok(key),
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Unknown key name {}", name),
+        )),
+    };
+
+    let mut table = HashMap::new();
+    for (from, to) in remap {
+        table.insert(key(from)? as u32, key(to)?);
+    }
+    Ok(table)
+}
+
+/// Opens an input event device
+fn open_device(fd_path: &str) -> std::io::Result<evdev_rs::Device> {
+    // Initialize new evdev handle
+    let mut device = match evdev_rs::Device::new() {
+        Some(device) => device,
+        None => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "Could not create evdev device",
+            ));
+        }
+    };
+
+    // Apply file descriptor to evdev handle
+    let file = std::fs::File::open(fd_path)?;
+    device.set_fd(file)?;
+    Ok(device)
+}
+
 /// Device state container for evdev devices
 pub struct EvdevDevice {
     mailbox: mailbox::Mailbox,
     uid: u64,
     endpoint: Endpoint,
     fd_path: String,
+    grab: bool,
+    remap: HashMap<u32, evdev_rs::enums::EV_KEY>,
 }
 
 impl EvdevDevice {
     pub fn new(mailbox: mailbox::Mailbox, fd_path: String) -> std::io::Result<EvdevDevice> {
         // We query evdev here for information, but we don't grab the input until running process()
-        // Initialize new evdev handle
-        let mut device = match evdev_rs::Device::new() {
-            Some(device) => device,
-            None => {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    "Could not create evdev device",
-                ));
-            }
-        };
-
-        // Apply file descriptor to evdev handle
-        let file = std::fs::File::open(fd_path.clone())?;
-        device.set_fd(file)?;
+        let device = open_device(&fd_path)?;
 
         // Determine type of device
-        let devtype = device_type(&device, fd_path.clone())?;
+        let descriptor = hid_descriptor(&fd_path);
+        let devtype = device_type(&device, fd_path.clone(), descriptor.as_ref())?;
 
         // Assign uid to newly created device (need path location for uniqueness)
         let mut evdev_info = EvdevInfo::new(device);
@@ -722,6 +882,8 @@ impl EvdevDevice {
         // Setup Endpoint
         let mut endpoint = Endpoint::new(devtype, uid);
         endpoint.set_evdev_params(evdev_info);
+        endpoint.set_seat(seat::device_seat(&fd_path));
+        endpoint.set_descriptor(descriptor);
 
         // Register node
         mailbox.clone().register_node(endpoint.clone());
@@ -731,9 +893,24 @@ impl EvdevDevice {
             uid,
             endpoint,
             fd_path,
+            grab: true,
+            remap: HashMap::new(),
         })
     }
 
+    /// Exclusive grab (default), events are only seen by hid-io-core
+    /// Without the grab events are forwarded, but also reach the OS as usual.
+    pub fn set_grab(&mut self, grab: bool) {
+        self.grab = grab;
+    }
+
+    /// Key remapping table (evdev key code -> key)
+    /// Grabbed events are re-emitted to the OS using uinput, with the keys remapped.
+    /// Messages sent to the mailbox still contain the original keys.
+    pub fn set_remap(&mut self, remap: HashMap<u32, evdev_rs::enums::EV_KEY>) {
+        self.remap = remap;
+    }
+
     /// Process evdev events
     /// NOTE: evdev doesn't necessarily group all event codes from a single Hid message into a
     /// single EV_SYN scan report. While annoying (and makes it hard to perfectly emulate hid) this
@@ -741,26 +918,28 @@ impl EvdevDevice {
     /// On each scan report additional keys will be added to the HidIo packet so you'll eventually
     /// get the full set (just communication more "chatty"). This also complicates unit testing :/
     pub fn process(&mut self) -> std::io::Result<()> {
-        let fd_path = self.fd_path.clone();
-
-        // Initialize new evdev handle
-        let mut device = match evdev_rs::Device::new() {
-            Some(device) => device,
-            None => {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    "Could not create evdev device",
-                ));
-            }
-        };
-
-        // Apply file descriptor to evdev handle
-        let file = std::fs::File::open(fd_path)?;
-        device.set_fd(file)?;
+        let mut device = open_device(&self.fd_path)?;
         info!("Connection event uid:{} {}", self.uid, device_name(&device));
 
         // Take all event information (block events from other processes)
-        device.grab(evdev_rs::GrabMode::Grab).unwrap();
+        if self.grab {
+            device.grab(evdev_rs::GrabMode::Grab)?;
+        }
+        let mut grabbed = self.grab;
+
+        // Virtual device used to re-emit remapped events
+        let uinput = if self.grab && !self.remap.is_empty() {
+            let uinput = evdev_rs::UInputDevice::create_from_device(&device)?;
+            info!(
+                "uid:{} remapping {} key(s) using {}",
+                self.uid,
+                self.remap.len(),
+                uinput.devnode().unwrap_or("uinput")
+            );
+            Some(uinput)
+        } else {
+            None
+        };
 
         // Queue up evdev events to send
         // Each event is received individually, but we want all events that come from an
@@ -770,10 +949,31 @@ impl EvdevDevice {
         let mut event_queue_command = HidIoCommandId::HidKeyboard; // Default to a keyboard message
         let mut drop_until_next_syn_report = false;
 
+        // Mouse and joystick events update the state directly (instead of being queued)
+        let mut axis_state = AxisState::default();
+
         let mut event: std::io::Result<(evdev_rs::ReadStatus, evdev_rs::InputEvent)>;
         // Continuously scan for new events
         // This loop will block at next_event()
         loop {
+            // Release the grab while a fullscreen application is focused (game mode) so it gets
+            // the input directly, events are still forwarded
+            // NOTE: Only checked between events, the first event after a change still uses the
+            //       previous grab mode
+            if self.grab && hoststate::game_mode() == grabbed {
+                grabbed = !grabbed;
+                let mode = if grabbed {
+                    info!("uid:{} grabbing input (game mode ended)", self.uid);
+                    evdev_rs::GrabMode::Grab
+                } else {
+                    info!("uid:{} releasing input grab (game mode)", self.uid);
+                    evdev_rs::GrabMode::Ungrab
+                };
+                if let Err(e) = device.grab(mode) {
+                    warn!("uid:{} could not change grab mode: {}", self.uid, e);
+                }
+            }
+
             // TODO Implement ppoll (or similar) like on udev to handle timeout (to get the latency as low
             // as possible without pinning the cpu)
             // Currently we are just blocking and using a tokio blocking thread (also low latency)
@@ -810,6 +1010,21 @@ impl EvdevDevice {
                         warn!("Resyncing successful.");
                     }
                     evdev_rs::ReadStatus::Success => {
+                        // Re-emit to the OS (not while the grab is released for game mode,
+                        // the OS already sees the original events)
+                        if let (Some(uinput), true) = (&uinput, grabbed) {
+                            let mut event = result.1.clone();
+                            if let evdev_rs::enums::EventCode::EV_KEY(key) = &event.event_code {
+                                if let Some(to) = self.remap.get(&(key.clone() as u32)) {
+                                    event.event_code =
+                                        evdev_rs::enums::EventCode::EV_KEY(to.clone());
+                                }
+                            }
+                            if let Err(e) = uinput.write_event(&event) {
+                                warn!("uid:{} could not re-emit event: {}", self.uid, e);
+                            }
+                        }
+
                         match &result.1.event_code {
                             // Check if we've received an EV_SYN(SYN_REPORT) which indicates the event
                             // queue should be flushed
@@ -819,6 +1034,8 @@ impl EvdevDevice {
                                 if drop_until_next_syn_report {
                                     // Drop any queued events
                                     event_queue = vec![];
+                                    axis_state.axes = [0; AXES];
+                                    axis_state.changed = false;
                                     drop_until_next_syn_report = false;
                                 } else {
                                     // - Send enqueued events -
@@ -854,12 +1071,13 @@ impl EvdevDevice {
                                             }
                                             data
                                         }
-                                        // TODO Currently ignoring other send events
+                                        HidIoCommandId::HidMouse if axis_state.changed => {
+                                            axis_state.mouse_payload()
+                                        }
+                                        HidIoCommandId::HidJoystick if axis_state.changed => {
+                                            axis_state.joystick_payload()
+                                        }
                                         _ => {
-                                            debug!(
-                                                "Ignoring send: uid:{} {:?}",
-                                                self.uid, event_queue
-                                            );
                                             continue;
                                         }
                                     };
@@ -898,16 +1116,16 @@ impl EvdevDevice {
                                 HidIoCommandId::HidKeyboard
                             }
                             common_capnp::NodeType::HidMouse => {
-                                // Filter for mouse events
-                                // TODO
-                                // TODO We may need to handle more complicated mouse packets
-                                HidIoCommandId::HidMouse
+                                // Buttons and relative axes
+                                event_queue_command = HidIoCommandId::HidMouse;
+                                axis_state.mouse_event(&result.1);
+                                continue;
                             }
                             common_capnp::NodeType::HidJoystick => {
-                                // Filter for joystick events
-                                // TODO
-                                // TODO We may need to handle more complicated joystick packets
-                                HidIoCommandId::HidJoystick
+                                // Buttons and absolute axes
+                                event_queue_command = HidIoCommandId::HidJoystick;
+                                axis_state.joystick_event(&device, &result.1);
+                                continue;
                             }
                             _ => {
                                 panic!(
@@ -975,9 +1193,25 @@ fn device_name(device: &evdev_rs::Device) -> String {
 fn device_type(
     device: &evdev_rs::Device,
     fd_path: String,
+    descriptor: Option<&ReportDescriptor>,
 ) -> std::io::Result<common_capnp::NodeType> {
     use evdev_rs::enums::*;
 
+    // Use the application collections of HID devices, unless there are several kinds (composite
+    // devices may be split into multiple input devices by the kernel)
+    if let Some(descriptor) = descriptor {
+        let types = descriptor
+            .collections
+            .iter()
+            .filter_map(|c| collection_type(c.usage_page, c.usage))
+            .collect::<Vec<_>>();
+        if let Some(devtype) = types.first() {
+            if types.iter().all(|t| t == devtype) {
+                return Ok(*devtype);
+            }
+        }
+    }
+
     if device.has(&EventCode::EV_KEY(EV_KEY::KEY_F))
         || device.has(&EventCode::EV_KEY(EV_KEY::KEY_J))
     {
@@ -994,16 +1228,29 @@ fn device_type(
     }
 }
 
-/// evdev processing
-///
-/// TODO
-/// udev to wait on new evdev devices
-/// udev to scan for already attached devices
-/// Allocate uid per unique device
-/// Have list of evdev devices to query
-/// Handle removal and re-insertion with same uid
-/// Use async to wait for evdev events (block on next event, using spawn_blocking)
-/// Send mailbox message with necessary info (API will handle re-routing message)
+/// Node type of a Generic Desktop application collection
+fn collection_type(usage_page: u16, usage: u16) -> Option<common_capnp::NodeType> {
+    match (usage_page, usage) {
+        (GENERIC_DESKTOP, USAGE_KEYBOARD) | (GENERIC_DESKTOP, USAGE_KEYPAD) => {
+            Some(common_capnp::NodeType::HidKeyboard)
+        }
+        (GENERIC_DESKTOP, USAGE_MOUSE) => Some(common_capnp::NodeType::HidMouse),
+        (GENERIC_DESKTOP, USAGE_JOYSTICK) | (GENERIC_DESKTOP, USAGE_GAMEPAD) => {
+            Some(common_capnp::NodeType::HidJoystick)
+        }
+        _ => None,
+    }
+}
+
+/// Report descriptor of the HID device backing an input event device
+/// None for non-HID input devices (e.g. PS/2 keyboards)
+fn hid_descriptor(fd_path: &str) -> Option<ReportDescriptor> {
+    let name = std::path::Path::new(fd_path).file_name()?;
+    let sysfs = std::path::Path::new("/sys/class/input")
+        .join(name)
+        .join("device/device/report_descriptor");
+    ReportDescriptor::from_file(&sysfs)
+}
 
 /// hidapi processing
 ///
@@ -1113,6 +1360,7 @@ async fn processing(mut mailbox: mailbox::Mailbox) {
                         let device = HidApiDevice::new(device);
                         let mut device =
                             HidIoEndpoint::new(Box::new(device), USB_FULLSPEED_PACKET_SIZE as u32);
+                        device.set_trusted(!is_ble);
 
                         if let Err(e) = device.send_sync() {
                             // Could not open device (likely removed, or in use)
@@ -1122,7 +1370,15 @@ async fn processing(mut mailbox: mailbox::Mailbox) {
 
                         // Setup device controller (handles communication and protocol conversion
                         // for the HidIo device)
-                        let master = HidIoController::new(mailbox.clone(), uid, device);
+                        let mut master = HidIoController::new(mailbox.clone(), uid, device);
+
+                        // Devices that fail to negotiate only use the base protocol
+                        if let Err(e) = master.identify() {
+                            warn!("Failed to query serial number - {}", e);
+                        }
+                        if let Err(e) = master.negotiate() {
+                            warn!("Failed to negotiate capabilities - {}", e);
+                        }
                         devices.push(master);
 
                         // Add device to node list
@@ -1214,19 +1470,116 @@ pub fn supported_ids() -> Vec<HidIoCommandId> {
     ids
 }
 
+/// Starts capturing a keyboard, mouse or gamepad, if selected by the capture configuration
+fn capture_device(mailbox: &mailbox::Mailbox, config: &capture::Config, fd_path: String) {
+    let device = match open_device(&fd_path) {
+        Ok(device) => device,
+        Err(e) => {
+            debug!("Could not open {} - {}", fd_path, e);
+            return;
+        }
+    };
+    let descriptor = hid_descriptor(&fd_path);
+    let class = match device_type(&device, fd_path.clone(), descriptor.as_ref()) {
+        Ok(common_capnp::NodeType::HidKeyboard) => capture::DeviceClass::Keyboard,
+        Ok(common_capnp::NodeType::HidMouse) => capture::DeviceClass::Mouse,
+        Ok(common_capnp::NodeType::HidJoystick) => capture::DeviceClass::Gamepad,
+        _ => {
+            return;
+        }
+    };
+    let options = match config.options(class, device.vendor_id(), device.product_id()) {
+        Some(options) => options.clone(),
+        None => {
+            return;
+        }
+    };
+
+    // Already captured
+    let mut info = EvdevInfo::new(device);
+    if mailbox.clone().get_uid(info.key(), fd_path.clone()) == Some(0) {
+        return;
+    }
+
+    let remap = match remap_table(&options.remap) {
+        Ok(remap) => remap,
+        Err(e) => {
+            error!("Not capturing {} - {}", fd_path, e);
+            return;
+        }
+    };
+    let mailbox = mailbox.clone();
+    mailbox.rt.clone().spawn_blocking(move || {
+        let mut device = match EvdevDevice::new(mailbox, fd_path.clone()) {
+            Ok(device) => device,
+            Err(e) => {
+                warn!("Could not capture {} - {}", fd_path, e);
+                return;
+            }
+        };
+        // Remapped keys can only be re-emitted if the original keys are grabbed
+        device.set_grab(options.grab || !remap.is_empty());
+        device.set_remap(remap);
+        if let Err(e) = device.process() {
+            warn!("Capture of {} failed - {}", fd_path, e);
+        }
+    });
+}
+
+/// evdev processing
+///
+/// Periodically scans for devices selected by the capture configuration.
+fn processing(mailbox: mailbox::Mailbox, config: capture::Config) {
+    info!("Spawning evdev capture thread...");
+
+    while RUNNING.load(Ordering::SeqCst) {
+        let mut enumerator = match udev::Enumerator::new() {
+            Ok(enumerator) => enumerator,
+            Err(e) => {
+                error!("Could not enumerate input devices - {}", e);
+                return;
+            }
+        };
+        enumerator.match_subsystem("input").unwrap();
+        for device in enumerator.scan_devices().unwrap() {
+            // Skip virtual devices (e.g. remapped devices re-emitted by hid-io-core)
+            if device.syspath().starts_with("/sys/devices/virtual") {
+                continue;
+            }
+            let fd_path = match device.devnode() {
+                Some(devnode) if device.sysname().to_string_lossy().starts_with("event") => {
+                    devnode.to_string_lossy().to_string()
+                }
+                _ => continue,
+            };
+            capture_device(&mailbox, &config, fd_path);
+        }
+
+        std::thread::sleep(std::time::Duration::from_millis(RESCAN_DELAY_MS));
+    }
+}
+
 /// evdev initialization
 ///
-/// Sets up processing threads for udev and evdev.
-pub async fn initialize(_mailbox: mailbox::Mailbox) {
+/// Captures the keyboards, mice and gamepads selected in the capture-devices file.
+pub async fn initialize(mailbox: mailbox::Mailbox) {
     info!("Initializing device/evdev...");
 
-    // Spawn watcher thread (tokio)
-    // TODO - udev monitoring (waiting for devices to reconnect)
-    // TODO - evev monitoring (monitoring is done by api request, grabbing is an option)
-    /*
-    let local = tokio::task::LocalSet::new();
-    local.run_until(processing(mailbox)).await;
-    */
+    let config = match capture::Config::load() {
+        Some(config) => config,
+        None => {
+            info!(
+                "evdev capture disabled, create {} in the config directory to enable",
+                capture::CONFIG_FILE
+            );
+            return;
+        }
+    };
+
+    let rt = mailbox.rt.clone();
+    rt.spawn_blocking(move || processing(mailbox, config))
+        .await
+        .unwrap();
 }
 
 /// Finds an input event device handle using udev
@@ -1268,6 +1621,31 @@ mod test {
     use crate::logging::setup_logging_lite;
     use std::sync::{Arc, RwLock};
 
+    #[test]
+    fn axis_state_test() {
+        assert_eq!(scale_axis(0, 0, 255), -32768);
+        assert_eq!(scale_axis(255, 0, 255), 32767);
+        assert_eq!(scale_axis(-1, -1, 1), -32768);
+        assert_eq!(scale_axis(0, -1, 1), -1);
+        assert_eq!(scale_axis(5, 5, 5), 0);
+
+        let mut state = AxisState::default();
+        state.button(MOUSE_BUTTONS, 0x110, 1); // BTN_LEFT
+        state.button(MOUSE_BUTTONS, 0x130, 1); // BTN_SOUTH, not a mouse button
+        state.axes[0] = -2;
+        assert!(state.changed);
+        assert_eq!(
+            state.mouse_payload(),
+            vec![0x01, 0xFE, 0xFF, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(state.axes, [0; AXES]);
+        assert!(!state.changed);
+
+        state.button(MOUSE_BUTTONS, 0x110, 0);
+        state.button(JOYSTICK_BUTTONS, 0x130, 1); // BTN_SOUTH
+        assert_eq!(state.joystick_payload()[..4], [0x00, 0x00, 0x01, 0x00]);
+    }
+
     #[test]
     #[ignore]
     fn uhid_evdev_keyboard_test() {
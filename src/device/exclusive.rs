@@ -0,0 +1,98 @@
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * This file is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This file is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this file.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// ----- Crates -----
+
+use crate::api::HidApiInfo;
+use crate::device::config::{self, DeviceMatch};
+use lazy_static::lazy_static;
+
+// ----- Consts -----
+
+/// Exclusive access file name, stored in the hid-io-core config directory
+const EXCLUSIVE_FILE: &str = "device-exclusive";
+
+lazy_static! {
+    static ref DEVICES: Vec<DeviceMatch> =
+        config::load(EXCLUSIVE_FILE, "exclusive access list", parse);
+}
+
+// ----- Enumerations -----
+
+/// Access mode of an opened HID-IO interface
+#[derive(Clone, Debug, PartialEq)]
+pub enum Access {
+    /// Other processes may also open the interface
+    Shared,
+    /// Interface is only accessible by hid-io-core
+    Exclusive,
+    /// Exclusive access was requested, but the interface had to be opened shared
+    SharedFallback(String),
+}
+
+impl Default for Access {
+    fn default() -> Self {
+        Access::Shared
+    }
+}
+
+// ----- Functions -----
+
+/// Device opened exclusively
+///
+/// vid=<hex>[,pid=<hex>][,serial=<string>]
+///
+/// e.g. vid=1c11,pid=b04d
+fn parse(line: &str) -> Result<DeviceMatch, String> {
+    DeviceMatch::parse(line, |_, _| Ok(false))?.require_vid()
+}
+
+/// Whether the HID-IO interface of the device should be opened exclusively
+pub fn requested(info: &HidApiInfo) -> bool {
+    DEVICES.iter().any(|device| device.matches(info))
+}
+
+// ----- Tests -----
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_test() {
+        let devices = config::parse_lines(
+            "# Comment\n\
+             vid=1c11,pid=b04d\n\
+             vid=308f,serial=abc\n\
+             pid=b04d\n\
+             vid=1c11,product=b04d\n",
+            "test",
+            parse,
+        );
+        assert_eq!(devices.len(), 2);
+
+        let info = |vendor_id, product_id, serial: &str| HidApiInfo {
+            vendor_id,
+            product_id,
+            serial_number: serial.to_string(),
+            ..Default::default()
+        };
+        assert!(devices[0].matches(&info(0x1c11, 0xb04d, "")));
+        assert!(!devices[0].matches(&info(0x1c11, 0xb04e, "")));
+        assert!(devices[1].matches(&info(0x308f, 0x0011, "abc")));
+        assert!(!devices[1].matches(&info(0x308f, 0x0011, "def")));
+    }
+}
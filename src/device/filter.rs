@@ -0,0 +1,288 @@
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * This file is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This file is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this file.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// ----- Crates -----
+
+use crate::api::HidApiInfo;
+use crate::device::config::{self, parse_hex, DeviceMatch};
+use crate::module::config_path;
+use lazy_static::lazy_static;
+use std::sync::RwLock;
+
+// ----- Consts -----
+
+/// Device filter file name, stored in the hid-io-core config directory
+const FILTER_FILE: &str = "device-filter";
+
+lazy_static! {
+    static ref FILTER: RwLock<Filter> = RwLock::new(load());
+}
+
+// ----- Enumerations -----
+
+#[derive(Debug)]
+pub enum FilterError {
+    /// Rule could not be parsed
+    Parse(String),
+    /// No rule with the given id
+    UnknownRule(u32),
+    /// Filter file could not be written
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for FilterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FilterError::Parse(reason) => write!(f, "Invalid device filter rule: {}", reason),
+            FilterError::UnknownRule(id) => write!(f, "Unknown device filter rule: {}", id),
+            FilterError::Io(e) => write!(f, "Could not save device filter: {}", e),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Action {
+    Allow,
+    Deny,
+}
+
+// ----- Structs -----
+
+/// Device filter rule
+///
+/// <allow|deny> <field=value>[,<field=value>...]
+///
+/// Fields: vid=<hex>, pid=<hex>, usage_page=<hex>, serial=<string>
+/// All fields of a rule must match.
+///
+/// e.g. deny vid=1050 (all Yubico devices)
+#[derive(Clone, Debug)]
+struct Rule {
+    id: u32,
+    line: String,
+    action: Action,
+    device: DeviceMatch,
+    usage_page: Option<u16>,
+}
+
+impl Rule {
+    fn parse(id: u32, line: &str) -> Result<Rule, FilterError> {
+        let line = line.trim();
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (action, matches) = match fields.as_slice() {
+            ["allow", matches] => (Action::Allow, matches),
+            ["deny", matches] => (Action::Deny, matches),
+            _ => {
+                return Err(FilterError::Parse(
+                    "Expected <allow|deny> <field=value>[,<field=value>...]".to_string(),
+                ));
+            }
+        };
+
+        let mut usage_page = None;
+        let device = DeviceMatch::parse(matches, |key, val| match key {
+            "usage_page" => {
+                usage_page = Some(parse_hex(val)?);
+                Ok(true)
+            }
+            _ => Ok(false),
+        })
+        .map_err(FilterError::Parse)?;
+        Ok(Rule {
+            id,
+            line: line.to_string(),
+            action,
+            device,
+            usage_page,
+        })
+    }
+
+    fn matches(&self, info: &HidApiInfo) -> bool {
+        self.device.matches(info)
+            && self
+                .usage_page
+                .map_or(true, |usage_page| usage_page == info.usage_page)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Filter {
+    rules: Vec<Rule>,
+    next_id: u32,
+}
+
+impl Filter {
+    /// Deny rules always take precedence
+    /// If there are any allow rules, only matching devices are allowed.
+    fn allowed(&self, info: &HidApiInfo) -> bool {
+        let mut allow_rules = false;
+        let mut allowed = false;
+        for rule in &self.rules {
+            let matches = rule.matches(info);
+            match rule.action {
+                Action::Deny if matches => {
+                    return false;
+                }
+                Action::Deny => {}
+                Action::Allow => {
+                    allow_rules = true;
+                    allowed |= matches;
+                }
+            }
+        }
+        allowed || !allow_rules
+    }
+}
+
+// ----- Functions -----
+
+/// Loads the filter file, invalid rules are skipped
+fn load() -> Filter {
+    let mut filter = Filter::default();
+    let (contents, name) = match config::read(FILTER_FILE, "device filter") {
+        Some(file) => file,
+        None => {
+            return filter;
+        }
+    };
+
+    let mut next_id = 0;
+    filter.rules = config::parse_lines(&contents, &name, |line| {
+        let rule = Rule::parse(next_id, line).map_err(|e| e.to_string())?;
+        next_id += 1;
+        Ok(rule)
+    });
+    filter.next_id = next_id;
+    info!(
+        "Loaded {} device filter rules from {}",
+        filter.rules.len(),
+        name
+    );
+    filter
+}
+
+/// Writes the filter file
+fn save(filter: &Filter) -> Result<(), FilterError> {
+    let path = match config_path(FILTER_FILE) {
+        Some(path) => path,
+        None => {
+            return Ok(());
+        }
+    };
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(FilterError::Io)?;
+    }
+    let mut contents = String::new();
+    for rule in &filter.rules {
+        contents.push_str(&rule.line);
+        contents.push('\n');
+    }
+    std::fs::write(&path, contents).map_err(FilterError::Io)
+}
+
+/// Whether hid-io-core may open the device
+pub fn allowed(info: &HidApiInfo) -> bool {
+    FILTER.read().unwrap().allowed(info)
+}
+
+/// Current filter rules (id, line)
+pub fn rules() -> Vec<(u32, String)> {
+    FILTER
+        .read()
+        .unwrap()
+        .rules
+        .iter()
+        .map(|rule| (rule.id, rule.line.clone()))
+        .collect()
+}
+
+/// Adds a filter rule, see Rule for the format
+/// The filter file is updated. Devices that are already connected are not affected.
+pub fn add(line: &str) -> Result<u32, FilterError> {
+    let mut filter = FILTER.write().unwrap();
+    let rule = Rule::parse(filter.next_id, line)?;
+    let id = rule.id;
+    filter.rules.push(rule);
+    filter.next_id += 1;
+    save(&filter)?;
+    Ok(id)
+}
+
+/// Removes a filter rule
+/// The filter file is updated.
+pub fn remove(id: u32) -> Result<(), FilterError> {
+    let mut filter = FILTER.write().unwrap();
+    match filter.rules.iter().position(|rule| rule.id == id) {
+        Some(pos) => {
+            filter.rules.remove(pos);
+            save(&filter)
+        }
+        None => Err(FilterError::UnknownRule(id)),
+    }
+}
+
+// ----- Tests -----
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn info(vendor_id: u16, product_id: u16, serial: &str) -> HidApiInfo {
+        HidApiInfo {
+            vendor_id,
+            product_id,
+            serial_number: serial.to_string(),
+            usage_page: 0xff1c,
+            ..Default::default()
+        }
+    }
+
+    fn filter(lines: &[&str]) -> Filter {
+        let mut filter = Filter::default();
+        for line in lines {
+            filter
+                .rules
+                .push(Rule::parse(filter.next_id, line).unwrap());
+            filter.next_id += 1;
+        }
+        filter
+    }
+
+    #[test]
+    fn rule_parse_test() {
+        assert!(Rule::parse(0, "deny vid=1050").is_ok());
+        assert!(Rule::parse(0, "allow vid=308f,pid=0013,usage_page=ff1c,serial=abc").is_ok());
+        assert!(Rule::parse(0, "block vid=1050").is_err());
+        assert!(Rule::parse(0, "deny vid=xyz").is_err());
+        assert!(Rule::parse(0, "deny product=1").is_err());
+    }
+
+    #[test]
+    fn allowed_test() {
+        // No rules, everything is allowed
+        assert!(filter(&[]).allowed(&info(0x1050, 0x0407, "")));
+
+        let deny = filter(&["deny vid=1050"]);
+        assert!(!deny.allowed(&info(0x1050, 0x0407, "")));
+        assert!(deny.allowed(&info(0x308f, 0x0013, "")));
+
+        // Deny takes precedence over allow
+        let mixed = filter(&["allow vid=308f", "deny vid=308f,serial=1234"]);
+        assert!(mixed.allowed(&info(0x308f, 0x0013, "5678")));
+        assert!(!mixed.allowed(&info(0x308f, 0x0013, "1234")));
+        assert!(!mixed.allowed(&info(0x1c11, 0xb04d, "")));
+    }
+}
@@ -0,0 +1,296 @@
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * This file is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This file is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this file.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// ----- Crates -----
+
+#[cfg(target_os = "linux")]
+use tokio::io::unix::AsyncFd;
+
+#[cfg(target_os = "windows")]
+use std::sync::Arc;
+#[cfg(target_os = "windows")]
+use tokio::sync::Notify;
+#[cfg(target_os = "windows")]
+use winapi::shared::guiddef::GUID;
+#[cfg(target_os = "windows")]
+use winapi::shared::minwindef::{LPARAM, LRESULT, TRUE, UINT, WPARAM};
+#[cfg(target_os = "windows")]
+use winapi::shared::windef::HWND;
+#[cfg(target_os = "windows")]
+use winapi::um::{dbt, libloaderapi, winuser};
+
+// ----- Consts -----
+
+/// hidapi uses the hidraw backend on Linux
+#[cfg(target_os = "linux")]
+const SUBSYSTEM: &str = "hidraw";
+
+/// GUID_DEVINTERFACE_HID {4D1E55B2-F16F-11CF-88CB-001111000030}
+#[cfg(target_os = "windows")]
+const GUID_DEVINTERFACE_HID: GUID = GUID {
+    Data1: 0x4D1E_55B2,
+    Data2: 0xF16F,
+    Data3: 0x11CF,
+    Data4: [0x88, 0xCB, 0x00, 0x11, 0x11, 0x00, 0x00, 0x30],
+};
+
+// ----- Structs -----
+
+/// Waits for hid devices to be added or removed
+///
+/// On Linux a udev netlink monitor is used so new devices are enumerated right away.
+/// On Windows a hidden message window receives WM_DEVICECHANGE for HID interfaces.
+/// Elsewhere (or if the monitor could not be created) the device list is polled.
+pub struct Hotplug {
+    #[cfg(target_os = "linux")]
+    monitor: Option<AsyncFd<udev::MonitorSocket>>,
+    #[cfg(target_os = "windows")]
+    notify: Option<Arc<Notify>>,
+}
+
+impl Hotplug {
+    #[cfg(target_os = "linux")]
+    pub fn new() -> Hotplug {
+        let monitor = udev::MonitorBuilder::new()
+            .and_then(|builder| builder.match_subsystem(SUBSYSTEM))
+            .and_then(|builder| builder.listen())
+            .and_then(AsyncFd::new);
+        match monitor {
+            Ok(monitor) => {
+                info!("Watching {} udev events", SUBSYSTEM);
+                Hotplug {
+                    monitor: Some(monitor),
+                }
+            }
+            Err(e) => {
+                warn!("Could not create udev monitor, polling for devices - {}", e);
+                Hotplug { monitor: None }
+            }
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    pub fn new() -> Hotplug {
+        let notify = Arc::new(Notify::new());
+        let (result_tx, result_rx) = std::sync::mpsc::channel();
+        let window_notify = notify.clone();
+        std::thread::spawn(move || message_window(window_notify, result_tx));
+        let result = result_rx
+            .recv()
+            .unwrap_or_else(|_| Err("Message window thread exited".to_string()));
+        match result {
+            Ok(()) => {
+                info!("Watching WM_DEVICECHANGE HID interface events");
+                Hotplug {
+                    notify: Some(notify),
+                }
+            }
+            Err(e) => {
+                warn!(
+                    "Could not register for device notifications, polling for devices - {}",
+                    e
+                );
+                Hotplug { notify: None }
+            }
+        }
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "windows")))]
+    pub fn new() -> Hotplug {
+        Hotplug {}
+    }
+
+    /// Whether device changes are notified, polling is only needed to retry devices
+    #[cfg(target_os = "linux")]
+    pub fn active(&self) -> bool {
+        self.monitor.is_some()
+    }
+
+    #[cfg(target_os = "windows")]
+    pub fn active(&self) -> bool {
+        self.notify.is_some()
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "windows")))]
+    pub fn active(&self) -> bool {
+        false
+    }
+
+    /// Waits until a device is added/removed or the poll interval elapses
+    /// Returns true if a hotplug event was received.
+    pub async fn wait(&mut self, interval: std::time::Duration) -> bool {
+        tokio::select! {
+            added = self.event() => added,
+            _ = tokio::time::sleep(interval) => false,
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn event(&mut self) -> bool {
+        let monitor = match &mut self.monitor {
+            Some(monitor) => monitor,
+            None => {
+                return std::future::pending().await;
+            }
+        };
+        loop {
+            match monitor.readable().await {
+                Ok(mut guard) => guard.clear_ready(),
+                Err(e) => {
+                    warn!("udev monitor failed, polling for devices - {}", e);
+                    self.monitor = None;
+                    return std::future::pending().await;
+                }
+            }
+
+            // Drain all pending events
+            let mut changed = false;
+            for event in monitor.get_mut() {
+                debug!(
+                    "udev {:?} {:?}",
+                    event.event_type(),
+                    event.devnode().unwrap_or_else(|| event.syspath())
+                );
+                changed |= matches!(
+                    event.event_type(),
+                    udev::EventType::Add | udev::EventType::Remove
+                );
+            }
+            if changed {
+                return true;
+            }
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    async fn event(&mut self) -> bool {
+        match &self.notify {
+            // Notifications received while enumerating are coalesced into a single wakeup
+            Some(notify) => {
+                notify.notified().await;
+                true
+            }
+            None => std::future::pending().await,
+        }
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "windows")))]
+    async fn event(&mut self) -> bool {
+        std::future::pending().await
+    }
+}
+
+// ----- Functions -----
+
+/// Message-only window receiving HID interface arrival/removal notifications
+/// Runs the message loop until hid-io-core exits.
+#[cfg(target_os = "windows")]
+fn message_window(notify: Arc<Notify>, result: std::sync::mpsc::Sender<Result<(), String>>) {
+    let class_name: Vec<u16> = "hid-io-core-hotplug\0".encode_utf16().collect();
+    let hwnd = unsafe {
+        let instance = libloaderapi::GetModuleHandleW(std::ptr::null());
+        let mut class: winuser::WNDCLASSW = std::mem::zeroed();
+        class.lpfnWndProc = Some(window_proc);
+        class.hInstance = instance;
+        class.lpszClassName = class_name.as_ptr();
+        if winuser::RegisterClassW(&class) == 0 {
+            result
+                .send(Err(format!(
+                    "RegisterClassW {}",
+                    std::io::Error::last_os_error()
+                )))
+                .ok();
+            return;
+        }
+
+        let hwnd = winuser::CreateWindowExW(
+            0,
+            class_name.as_ptr(),
+            class_name.as_ptr(),
+            0,
+            0,
+            0,
+            0,
+            0,
+            winuser::HWND_MESSAGE,
+            std::ptr::null_mut(),
+            instance,
+            std::ptr::null_mut(),
+        );
+        if hwnd.is_null() {
+            result
+                .send(Err(format!(
+                    "CreateWindowExW {}",
+                    std::io::Error::last_os_error()
+                )))
+                .ok();
+            return;
+        }
+
+        // Owned by the window for the lifetime of the process
+        winuser::SetWindowLongPtrW(hwnd, winuser::GWLP_USERDATA, Arc::into_raw(notify) as isize);
+
+        let mut filter: dbt::DEV_BROADCAST_DEVICEINTERFACE_W = std::mem::zeroed();
+        filter.dbcc_size = std::mem::size_of::<dbt::DEV_BROADCAST_DEVICEINTERFACE_W>() as u32;
+        filter.dbcc_devicetype = dbt::DBT_DEVTYP_DEVICEINTERFACE;
+        filter.dbcc_classguid = GUID_DEVINTERFACE_HID;
+        let handle = winuser::RegisterDeviceNotificationW(
+            hwnd as _,
+            &mut filter as *mut _ as _,
+            winuser::DEVICE_NOTIFY_WINDOW_HANDLE,
+        );
+        if handle.is_null() {
+            result
+                .send(Err(format!(
+                    "RegisterDeviceNotificationW {}",
+                    std::io::Error::last_os_error()
+                )))
+                .ok();
+            return;
+        }
+        hwnd
+    };
+    result.send(Ok(())).ok();
+
+    let mut msg: winuser::MSG = unsafe { std::mem::zeroed() };
+    while unsafe { winuser::GetMessageW(&mut msg, hwnd, 0, 0) } > 0 {
+        unsafe {
+            winuser::TranslateMessage(&msg);
+            winuser::DispatchMessageW(&msg);
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+unsafe extern "system" fn window_proc(
+    hwnd: HWND,
+    msg: UINT,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    if msg == winuser::WM_DEVICECHANGE
+        && (wparam == dbt::DBT_DEVICEARRIVAL as WPARAM
+            || wparam == dbt::DBT_DEVICEREMOVECOMPLETE as WPARAM)
+    {
+        let notify = winuser::GetWindowLongPtrW(hwnd, winuser::GWLP_USERDATA) as *const Notify;
+        if !notify.is_null() {
+            debug!("WM_DEVICECHANGE {:#x}", wparam);
+            (*notify).notify_one();
+        }
+        return TRUE as LRESULT;
+    }
+    winuser::DefWindowProcW(hwnd, msg, wparam, lparam)
+}
@@ -15,41 +15,91 @@
  * along with this file.  If not, see <http://www.gnu.org/licenses/>.
  */
 
+// ----- Modules -----
+
+mod hotplug;
+
 // ----- Crates -----
 
 use crate::api::common_capnp::NodeType;
 use crate::api::Endpoint;
 use crate::api::HidApiInfo;
+use crate::device::descriptor::ReportDescriptor;
+use crate::device::exclusive::Access;
+use crate::device::quirks::{ControlChannel, Quirks, ReportIdMode};
+use crate::device::selection::Selection;
 use crate::device::*;
+use crate::module::seat;
 use crate::RUNNING;
 use lazy_static::lazy_static;
 use regex::Regex;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::atomic::Ordering;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 
-pub const USAGE_PAGE: u16 = 0xFF1C;
-pub const USAGE: u16 = 0x1100;
+pub use crate::device::selection::{USAGE, USAGE_PAGE};
 
-const USB_FULLSPEED_PACKET_SIZE: usize = 64;
+/// Used if the report size cannot be read from the report descriptor
+const USB_FULLSPEED_PACKET_SIZE: u32 = 64;
 const ENUMERATE_DELAY_MS: u64 = 1000;
+/// Devices are only rescanned this often when hotplug events are available (retries failed opens)
+const HOTPLUG_RESCAN_DELAY_MS: u64 = 10000;
 const TIMEOUT_MS: i32 = 500;
+const CONTROL_POLL_MS: u64 = 50;
 
 pub struct HidApiDevice {
     device: ::hidapi::HidDevice,
     timeout: i32,
+    report_id: ReportIdMode,
+    control_channel: ControlChannel,
+    last_control_poll: std::time::Instant,
+    pool: BufferPool,
 }
 
 impl HidApiDevice {
-    pub fn new(device: ::hidapi::HidDevice, timeout: i32) -> HidApiDevice {
+    pub fn new(device: ::hidapi::HidDevice, timeout: i32, quirks: Quirks) -> HidApiDevice {
         device.set_blocking_mode(true).unwrap(); // Enable blocking mode, use timeouts to unblock
-        HidApiDevice { device, timeout }
+        HidApiDevice {
+            device,
+            timeout,
+            report_id: quirks.report_id,
+            control_channel: quirks.control_channel,
+            last_control_poll: std::time::Instant::now(),
+            pool: BufferPool::default(),
+        }
     }
 }
 
 impl std::io::Read for HidApiDevice {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        match self.device.read_timeout(buf, self.timeout) {
+        let res = match self.report_id {
+            ReportIdMode::Unnumbered => self.device.read_timeout(buf, self.timeout),
+            ReportIdMode::Numbered(id) => {
+                // Numbered reports are always prefixed with the report id, strip it
+                let mut rbuf = self.pool.take(buf.len() + 1);
+                let res = match self.device.read_timeout(&mut rbuf, self.timeout) {
+                    Ok(0) => Ok(0),
+                    Ok(len) if rbuf[0] != id => {
+                        warn!(
+                            "Dropping report with unexpected id {:#x} (expected {:#x}): {:x?}",
+                            rbuf[0],
+                            id,
+                            &rbuf[0..len]
+                        );
+                        Ok(0)
+                    }
+                    Ok(len) => {
+                        buf[0..len - 1].copy_from_slice(&rbuf[1..len]);
+                        Ok(len - 1)
+                    }
+                    Err(e) => Err(e),
+                };
+                self.pool.give(rbuf);
+                res
+            }
+        };
+
+        match res {
             Ok(len) => {
                 if len > 0 {
                     trace!("Received {} bytes", len);
@@ -72,7 +122,11 @@ impl std::io::Write for HidApiDevice {
         let buf = {
             #[allow(clippy::needless_bool)]
             #[allow(clippy::if_same_then_else)]
-            let prepend = if cfg!(target_os = "linux") || cfg!(target_os = "macos") {
+            let prepend = if let ReportIdMode::Numbered(_) = self.report_id {
+                // Numbered reports must always start with the report id
+                // Windows fails the write otherwise
+                true
+            } else if cfg!(target_os = "linux") || cfg!(target_os = "macos") {
                 // If the first byte is a 0 its not tranmitted
                 // https://github.com/node-hid/node-hid/issues/187#issuecomment-282863702
                 //_buf[0] == 0x00
@@ -90,16 +144,18 @@ impl std::io::Write for HidApiDevice {
 
             // Add a report id (unused) if needed so our actual first byte
             // of the packet is sent correctly
+            let mut new_buf = self.pool.take(0);
             if prepend {
-                let mut new_buf = vec![0x00];
-                new_buf.extend(_buf);
-                new_buf
-            } else {
-                _buf.to_vec()
+                new_buf.push(match self.report_id {
+                    ReportIdMode::Unnumbered => 0x00,
+                    ReportIdMode::Numbered(id) => id,
+                });
             }
+            new_buf.extend_from_slice(_buf);
+            new_buf
         };
 
-        match self.device.write(&buf) {
+        let res = match self.device.write(&buf) {
             Ok(len) => {
                 trace!("Sent {} bytes", len);
                 trace!("{:x?}", &buf[0..len]);
@@ -112,7 +168,9 @@ impl std::io::Write for HidApiDevice {
                     format!("{:?}", e),
                 ))
             }
-        }
+        };
+        self.pool.give(buf);
+        res
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
@@ -120,7 +178,81 @@ impl std::io::Write for HidApiDevice {
     }
 }
 
-impl HidIoTransport for HidApiDevice {}
+impl HidIoTransport for HidApiDevice {
+    fn has_control_channel(&self) -> bool {
+        self.control_channel != ControlChannel::Interrupt
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<i32>) {
+        self.timeout = timeout.unwrap_or(TIMEOUT_MS);
+    }
+
+    fn write_control(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let id = match self.control_channel {
+            ControlChannel::Interrupt => {
+                return self.write(buf);
+            }
+            ControlChannel::Feature(id) => id,
+        };
+
+        let mut report = self.pool.take(0);
+        report.push(id);
+        report.extend_from_slice(buf);
+        let res = match self.device.send_feature_report(&report) {
+            Ok(_) => {
+                trace!("Sent feature report {} bytes", report.len());
+                trace!("{:x?}", report);
+                Ok(buf.len())
+            }
+            Err(e) => {
+                warn!("Write feature report - {:?} {:x?}", e, report);
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    format!("{:?}", e),
+                ))
+            }
+        };
+        self.pool.give(report);
+        res
+    }
+
+    fn read_control(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let id = match self.control_channel {
+            ControlChannel::Interrupt => {
+                return Ok(0);
+            }
+            ControlChannel::Feature(id) => id,
+        };
+
+        // Feature reports must be polled, keep it to a low rate
+        if self.last_control_poll.elapsed().as_millis() < CONTROL_POLL_MS as u128 {
+            return Ok(0);
+        }
+        self.last_control_poll = std::time::Instant::now();
+
+        let mut report = self.pool.take(buf.len() + 1);
+        report[0] = id;
+        let res = match self.device.get_feature_report(&mut report) {
+            // All-zero report means nothing is pending
+            Ok(len) if len <= 1 || report[1..len].iter().all(|b| *b == 0) => Ok(0),
+            Ok(len) => {
+                trace!("Received feature report {} bytes", len);
+                trace!("{:x?}", &report[0..len]);
+                buf[0..len - 1].copy_from_slice(&report[1..len]);
+                Ok(len - 1)
+            }
+            Err(e) => {
+                warn!("Read feature report - {:?}", e);
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    format!("{:?}", e),
+                ))
+            }
+        };
+        self.pool.give(report);
+        res
+    }
+}
 
 fn device_name(device_info: &::hidapi::DeviceInfo) -> String {
     let mut string = format!(
@@ -143,206 +275,480 @@ fn device_name(device_info: &::hidapi::DeviceInfo) -> String {
     string
 }
 
+/// Determine if the device is the HID-IO interface of a device
+/// The interface is selected by usage unless configured otherwise (see device::selection).
+pub fn match_device(device_info: &::hidapi::DeviceInfo) -> bool {
+    match selection::lookup(device_info.vendor_id(), device_info.product_id()) {
+        Selection::Usage { usage_page, usage } => match_usage(device_info, usage_page, usage),
+        Selection::Interface(interface) => device_info.interface_number() == interface,
+    }
+}
+
 #[cfg(target_os = "linux")]
-fn match_device(device_info: &::hidapi::DeviceInfo) -> bool {
+fn match_usage(device_info: &::hidapi::DeviceInfo, usage_page: u16, usage: u16) -> bool {
     // NOTE: This requires some patches to hidapi (https://github.com/libusb/hidapi/pull/139)
     // interface number and usage are both queryable. Prefer usage
-    device_info.usage_page() == USAGE_PAGE && device_info.usage() == USAGE
+    if device_info.usage_page() != 0 {
+        return device_info.usage_page() == usage_page && device_info.usage() == usage;
+    }
+
+    // Unpatched hidapi does not report the usage, look for the collection in the descriptor
+    // This also finds the HID-IO collection if it is not the first one of the interface
+    ReportDescriptor::read(&device_info.path().to_string_lossy()).map_or(false, |descriptor| {
+        descriptor.has_collection(usage_page, usage)
+    })
 }
 
 #[cfg(target_os = "macos")]
-fn match_device(device_info: &::hidapi::DeviceInfo) -> bool {
+fn match_usage(device_info: &::hidapi::DeviceInfo, usage_page: u16, usage: u16) -> bool {
     // interface_number is always -1 but usage is fine
-    device_info.usage_page() == USAGE_PAGE && device_info.usage() == USAGE
+    device_info.usage_page() == usage_page && device_info.usage() == usage
 }
 
 #[cfg(target_os = "windows")]
-fn match_device(device_info: &::hidapi::DeviceInfo) -> bool {
+fn match_usage(device_info: &::hidapi::DeviceInfo, usage_page: u16, usage: u16) -> bool {
     // interface and usage are both queryable. Prefer usage
-    device_info.usage_page() == USAGE_PAGE && device_info.usage() == USAGE
+    device_info.usage_page() == usage_page && device_info.usage() == usage
+}
+
+#[cfg(target_os = "macos")]
+extern "C" {
+    // Sets kIOHIDOptionsTypeSeizeDevice for any devices opened afterwards
+    fn hid_darwin_set_open_exclusive(open_exclusive: std::os::raw::c_int);
+}
+
+/// Opens the HID-IO interface, exclusively if requested (quirks.seize)
+/// If exclusive access cannot be obtained the interface is opened shared, the reason is
+/// returned with the access mode so it can be shown through the API.
+#[cfg(target_os = "macos")]
+pub fn open_device(
+    api: &::hidapi::HidApi,
+    path: &std::ffi::CStr,
+    quirks: &Quirks,
+) -> ::hidapi::HidResult<(::hidapi::HidDevice, Access)> {
+    if !quirks.seize {
+        unsafe { hid_darwin_set_open_exclusive(0) };
+        return api.open_path(path).map(|device| (device, Access::Shared));
+    }
+
+    unsafe { hid_darwin_set_open_exclusive(1) };
+    let device = api.open_path(path);
+    unsafe { hid_darwin_set_open_exclusive(0) };
+    match device {
+        Ok(device) => Ok((device, Access::Exclusive)),
+        Err(e) => {
+            // Seizing usually fails due to missing Input Monitoring permissions, or because
+            // another process already has the device open
+            let reason = format!(
+                "Could not open exclusively (seize) - {}. \
+                 Check that hid-io-core has been granted Input Monitoring access \
+                 (System Preferences -> Security & Privacy -> Privacy) and that no other \
+                 application has the device open",
+                e
+            );
+            error!("{:?} {}. Falling back to shared access.", path, reason);
+            api.open_path(path)
+                .map(|device| (device, Access::SharedFallback(reason)))
+        }
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+pub fn open_device(
+    api: &::hidapi::HidApi,
+    path: &std::ffi::CStr,
+    quirks: &Quirks,
+) -> ::hidapi::HidResult<(::hidapi::HidDevice, Access)> {
+    let access = if quirks.seize {
+        // hidraw and the Windows HID driver are always opened shared by hidapi
+        let reason = "Exclusive access is not supported on this platform".to_string();
+        warn!("{:?} {}, opening shared", path, reason);
+        Access::SharedFallback(reason)
+    } else {
+        Access::Shared
+    };
+    api.open_path(path).map(|device| (device, access))
+}
+
+/// Chunk size of the HID-IO interface, taken from its report descriptor
+///
+/// Falls back to full-speed USB packets if the descriptor is unavailable (or invalid).
+pub fn max_packet_len(descriptor: Option<&ReportDescriptor>, selection: Selection) -> u32 {
+    descriptor
+        .and_then(|descriptor| selection.collection(descriptor))
+        .and_then(|collection| collection.report_len())
+        .filter(|len| *len as usize <= MAX_RECV_SIZE)
+        .unwrap_or(USB_FULLSPEED_PACKET_SIZE)
+}
+
+/// Uses numbered reports if the HID-IO collection declares a report id
+/// Quirks table entries take precedence.
+pub fn apply_report_id(
+    descriptor: Option<&ReportDescriptor>,
+    selection: Selection,
+    quirks: &mut Quirks,
+) {
+    if quirks.report_id != ReportIdMode::Unnumbered {
+        return;
+    }
+    let id = descriptor
+        .and_then(|descriptor| selection.collection(descriptor))
+        .and_then(|collection| collection.report_id());
+    if let Some(id) = id.filter(|id| *id != 0) {
+        quirks.report_id = ReportIdMode::Numbered(id);
+    }
+}
+
+/// Determine the transport a hidapi device is connected over
+///
+/// hidapi does not expose the bus type, so this relies on platform specific device paths.
+/// Bluetooth Classic devices (hidp/BT HID profile) enumerate as regular HID devices, the same
+/// as BLE (HID over GATT) devices.
+fn node_type(device_info: &::hidapi::DeviceInfo) -> NodeType {
+    // If serial number is a MAC address, this is a bluetooth device
+    lazy_static! {
+        static ref RE: Regex =
+            Regex::new(r"([0-9a-fA-F][0-9a-fA-F]:){5}([0-9a-fA-F][0-9a-fA-F])").unwrap();
+    }
+    let is_bt = RE.is_match(match device_info.serial_number() {
+        Some(s) => s,
+        _ => "",
+    });
+
+    if is_bt_classic(device_info) {
+        NodeType::BtKeyboard
+    } else if is_bt {
+        NodeType::BleKeyboard
+    } else {
+        NodeType::UsbKeyboard
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn is_bt_classic(device_info: &::hidapi::DeviceInfo) -> bool {
+    // BT Classic devices are created by the kernel hidp driver (under the hci device)
+    // BLE devices are created by bluez using uhid
+    let path = device_info.path().to_string_lossy();
+    let name = match path.rsplit('/').next() {
+        Some(name) => name,
+        None => {
+            return false;
+        }
+    };
+    match std::fs::canonicalize(format!("/sys/class/hidraw/{}/device", name)) {
+        Ok(sysfs) => {
+            let sysfs = sysfs.to_string_lossy();
+            sysfs.contains("/bluetooth/") && !sysfs.contains("/uhid/")
+        }
+        Err(_) => false,
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn is_bt_classic(device_info: &::hidapi::DeviceInfo) -> bool {
+    // BT Classic devices are handled by IOBluetoothHIDDriver
+    device_info
+        .path()
+        .to_string_lossy()
+        .contains("IOBluetoothHIDDriver")
+}
+
+#[cfg(target_os = "windows")]
+fn is_bt_classic(device_info: &::hidapi::DeviceInfo) -> bool {
+    // BT Classic devices use the HID profile service class (0x1124)
+    // BLE devices use the HID over GATT service (0x1812)
+    device_info
+        .path()
+        .to_string_lossy()
+        .to_lowercase()
+        .contains("00001124-0000-1000-8000-00805f9b34fb")
+}
+
+/// USB bus/port path of the device (e.g. 1-2.3), empty if unavailable (or not a USB device)
+#[cfg(target_os = "linux")]
+pub fn port_path(device_info: &::hidapi::DeviceInfo) -> String {
+    let path = device_info.path().to_string_lossy();
+    let name = match path.rsplit('/').next() {
+        Some(name) => name,
+        None => {
+            return String::new();
+        }
+    };
+    std::fs::canonicalize(format!("/sys/class/hidraw/{}/device", name))
+        .ok()
+        .and_then(|sysfs| usb_port_path(&sysfs.to_string_lossy()))
+        .unwrap_or_default()
+}
+
+/// Port paths are not exposed by hidapi on this platform
+#[cfg(not(target_os = "linux"))]
+pub fn port_path(_device_info: &::hidapi::DeviceInfo) -> String {
+    String::new()
+}
+
+/// Port path from the sysfs path of a USB HID device
+/// The USB interface is named <bus>-<port>[.<port>...]:<config>.<interface>
+/// e.g. /sys/devices/pci0000:00/0000:00:14.0/usb1/1-2/1-2.3/1-2.3:1.2/0003:1C11:B04D.0005
+#[cfg(target_os = "linux")]
+fn usb_port_path(sysfs: &str) -> Option<String> {
+    // Bluetooth devices are children of the (USB) adapter
+    if sysfs.contains("/bluetooth/") {
+        return None;
+    }
+    let digits = |val: &str| !val.is_empty() && val.chars().all(|c| c.is_ascii_digit());
+    sysfs.split('/').find_map(|component| {
+        let mut parts = component.splitn(2, ':');
+        let (port, interface) = (parts.next()?, parts.next()?);
+        let mut bus = port.splitn(2, '-');
+        let (bus, ports) = (bus.next()?, bus.next()?);
+        if digits(bus) && ports.split('.').all(digits) && interface.split('.').all(digits) {
+            Some(port.to_string())
+        } else {
+            None
+        }
+    })
+}
+
+/// Locate a device again after it failed
+/// The device may have been re-enumerated at a new path, in which case it is matched by key.
+/// Returns None if the device is not currently attached.
+fn find_device(
+    api: &mut ::hidapi::HidApi,
+    path: &std::ffi::CStr,
+    key: &str,
+) -> Option<std::ffi::CString> {
+    if let Err(e) = api.refresh_devices() {
+        warn!("Failed to refresh devices - {}", e);
+        return None;
+    }
+    if api
+        .device_list()
+        .any(|device_info| device_info.path() == path)
+    {
+        return Some(path.to_owned());
+    }
+    api.device_list()
+        .find(|device_info| match_device(device_info) && HidApiInfo::new(device_info).key() == key)
+        .map(|device_info| device_info.path().to_owned())
 }
 
 /// hidapi processing
 ///
-/// This thread periodically refreshes the USB device list to see if a new device needs to be attached
-/// The thread also handles reading/writing from connected interfaces
+/// This thread refreshes the USB device list to see if a new device needs to be attached
+/// On Linux (udev) and Windows (WM_DEVICECHANGE) the list is refreshed on hotplug events,
+/// otherwise it is polled.
+/// Each interface is run by a tokio task on the daemon runtime. Only opening the device and
+/// single reads (blocking for at most the read timeout) are run on the blocking pool, and the
+/// supervisor reopens the device (with backoff) after it fails.
+/// Once the daemon quits all device tasks are waited on, so every node is removed cleanly.
 ///
-/// XXX (HaaTa) hidapi is not thread-safe on all platforms, so don't try to create a thread per device
-/// TODO ^ Is this still valid?
+/// XXX (HaaTa) hidapi is not thread-safe on all platforms, so the api object is shared behind a
+/// lock (devices are only opened and enumerated while holding it)
 async fn processing(mailbox: mailbox::Mailbox) {
     info!("Spawning hidapi spawning thread...");
 
     // Initialize HID interface
-    let mut api: ::hidapi::HidApi =
-        ::hidapi::HidApi::new().expect("HID API object creation failed");
+    let api: Arc<Mutex<::hidapi::HidApi>> = Arc::new(Mutex::new(
+        ::hidapi::HidApi::new().expect("HID API object creation failed"),
+    ));
 
     // List of allocated device uids
     let uids: Arc<RwLock<HashMap<u64, tokio::task::JoinHandle<()>>>> =
         Arc::new(RwLock::new(HashMap::new()));
 
-    // Prepare the runtime
+    // Device tasks are run on the daemon runtime
     let rt = mailbox.rt.clone();
 
+    // Device add/remove notifications
+    let mut hotplug = hotplug::Hotplug::new();
+
+    // Devices skipped by the device filter (only logged once)
+    let mut ignored = HashSet::new();
+
     // Loop infinitely, the watcher only exits if the daemon is quit
-    // TODO (HaaTa) - There should be a better way using hotplug events (e.g. udev) in a cross
-    // platform way
     loop {
         if !RUNNING.load(Ordering::SeqCst) {
             // When the capnproto api isn't enabled use this loop to cancel
             // some of the hidio message filters that may be waiting
             #[cfg(not(feature = "api"))]
             mailbox.drop_all_subscribers();
+
+            // Wait for the device tasks to stop
+            let handles: Vec<_> = uids.write().unwrap().drain().map(|(_, h)| h).collect();
+            for handle in handles {
+                if let Err(e) = handle.await {
+                    warn!("hidapi device task failed - {}", e);
+                }
+            }
             return;
         }
 
-        // Refresh devices list
-        api.refresh_devices().unwrap();
-
-        // Iterate over found USB interfaces and select usable ones
-        debug!("Scanning for devices");
-        for device_info in api.device_list() {
-            let device_str = format!(
-                "Device: {:#?}\n    {} R:{}",
-                device_info.path(),
-                device_name(device_info),
-                device_info.release_number()
-            );
-            debug!("{}", device_str);
+        // The api lock must not be held while waiting for hotplug events
+        {
+            // Refresh devices list
+            let mut api_guard = api.lock().unwrap();
+            api_guard.refresh_devices().unwrap();
+
+            // Iterate over found USB interfaces and select usable ones
+            debug!("Scanning for devices");
+            for device_info in api_guard.device_list() {
+                let device_str = format!(
+                    "Device: {:#?}\n    {} R:{}",
+                    device_info.path(),
+                    device_name(device_info),
+                    device_info.release_number()
+                );
+                debug!("{}", device_str);
+
+                // Use usage page and usage for matching HID-IO compatible device
+                if !match_device(device_info) {
+                    continue;
+                }
 
-            // Use usage page and usage for matching HID-IO compatible device
-            if !match_device(device_info) {
-                continue;
-            }
+                // Build set of HID info to make unique comparisons
+                let mut info = HidApiInfo::new(device_info);
 
-            // Build set of HID info to make unique comparisons
-            let mut info = HidApiInfo::new(device_info);
-
-            // Determine if id can be reused
-            // Criteria
-            // 1. Must match (even if field isn't valid)
-            //    vid, pid, usage page, usage, manufacturer, product, serial, interface
-            // 2. Must not currently be in use (generally, use path to differentiate)
-            let key = info.key();
-            let uid = match mailbox
-                .clone()
-                .assign_uid(key.clone(), format!("{:#?}", device_info.path()))
-            {
-                Ok(uid) => uid,
-                Err(_) => {
-                    // Device has already been registered, or is invalid
+                // Skip devices the user does not want hid-io-core to touch
+                if !filter::allowed(&info) {
+                    if ignored.insert(device_info.path().to_owned()) {
+                        info!("Ignoring {} (device-filter)", device_name(device_info));
+                    }
                     continue;
                 }
-            };
 
-            // If serial number is a MAC address, this is a bluetooth device
-            lazy_static! {
-                static ref RE: Regex =
-                    Regex::new(r"([0-9a-fA-F][0-9a-fA-F]:){5}([0-9a-fA-F][0-9a-fA-F])").unwrap();
-            }
-            let is_ble = RE.is_match(match device_info.serial_number() {
-                Some(s) => s,
-                _ => "",
-            });
+                // Determine transport (USB, BLE or Bluetooth Classic)
+                let node_type = node_type(device_info);
 
-            // Basically, we need to copy the path string to deal with lifetime issues
-            let device_path = std::ffi::CString::new(device_info.path().to_bytes())
-                .expect("hidapi path generation failed");
-
-            // Start thread if uid not it map (i.e. not already processing)
-            if !uids.clone().read().unwrap().contains_key(&uid) {
-                // Add device
-                info!("Connecting to uid:{} {}", uid, device_str);
-
-                // Connect to device
-                let hid_device = api.open_path(&device_path);
-
-                // Start thread
-                let uids = uids.clone();
-                let uids_outer = uids.clone();
-                let mailbox = mailbox.clone();
-                let handle = rt.clone().spawn_blocking(move || {
-                    // Create node
-                    let mut node = Endpoint::new(
-                        if is_ble {
-                            NodeType::BleKeyboard
-                        } else {
-                            NodeType::UsbKeyboard
-                        },
-                        uid,
-                    );
-                    node.set_hidapi_params(info);
-
-                    // Setup device
-                    debug!("Attempting to setup {:#?}", node);
-                    match hid_device {
-                        Ok(device) => {
-                            println!("Connected to {}", node);
-                            let device = HidApiDevice::new(device, TIMEOUT_MS);
-                            let mut device = HidIoEndpoint::new(
-                                Box::new(device),
-                                USB_FULLSPEED_PACKET_SIZE as u32,
-                            );
-
-                            // Attempt to synchronize device (sync packet)
-                            if let Err(e) = device.send_sync() {
-                                // Could not open device (likely removed, or in use)
-                                warn!("Failed to sync device - {}", e);
-                            } else {
-                                // Setup device controller (handles communication and protocol conversion
-                                // for the HidIo device)
-                                let mut master = HidIoController::new(mailbox.clone(), uid, device);
-
-                                // Add device to node list
-                                mailbox.nodes.write().unwrap().push(node);
-
-                                loop {
-                                    // Stop processing, daemon trying to quit
-                                    if !RUNNING.load(Ordering::SeqCst) {
-                                        break;
-                                    }
-
-                                    // Process loop for device
-                                    let ret = master.process();
-                                    if ret.is_err() {
-                                        info!("{} disconnected. No longer polling it", uid);
-                                        // Remove handle from map
-                                        uids.write().unwrap().remove(&uid);
-
-                                        // Remove node from index
-                                        {
-                                            let mut nodes = mailbox.nodes.write().unwrap();
-                                            let index =
-                                                nodes.iter().position(|x| x.uid == uid).unwrap();
-                                            nodes.remove(index);
-                                        }
-                                        break;
-                                    }
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            // Could not open device (likely removed, or in use)
-                            warn!("Failed to open device:{:?} - {}", device_path, e);
-                            // Remove handle from map
-                            uids.write().unwrap().remove(&uid);
-                        }
-                    };
-                });
+                // Bluetooth Classic devices are handled by device::bt
+                if cfg!(all(target_os = "linux", feature = "bt-devices"))
+                    && node_type == NodeType::BtKeyboard
+                {
+                    continue;
+                }
+
+                // Determine if id can be reused
+                // Criteria
+                // 1. Must match (even if field isn't valid)
+                //    vid, pid, usage page, usage, manufacturer, product, serial, interface
+                //    and USB port path (only for devices without a serial number)
+                // 2. Must not currently be in use (generally, use path to differentiate)
+                let key = info.key();
+                let uid = match mailbox
+                    .clone()
+                    .assign_uid(key.clone(), format!("{:#?}", device_info.path()))
+                {
+                    Ok(uid) => uid,
+                    Err(_) => {
+                        // Device has already been registered, or is invalid
+                        continue;
+                    }
+                };
+
+                // Lookup any device specific handling
+                let mut quirks = quirks::lookup(
+                    device_info.vendor_id(),
+                    device_info.product_id(),
+                    device_info.interface_number(),
+                );
+
+                // Report layout of the interface (if available)
+                let selection =
+                    selection::lookup(device_info.vendor_id(), device_info.product_id());
+                let descriptor = ReportDescriptor::read(&device_info.path().to_string_lossy());
+                apply_report_id(descriptor.as_ref(), selection, &mut quirks);
+                let max_packet_len = max_packet_len(descriptor.as_ref(), selection);
+                quirks.seize |= exclusive::requested(&info);
+                if quirks != Quirks::default() {
+                    info!("Using quirks for uid:{} {:?}", uid, quirks);
+                }
+                if max_packet_len != USB_FULLSPEED_PACKET_SIZE {
+                    info!("Using {} byte packets for uid:{}", max_packet_len, uid);
+                }
 
-                // Add uid to hashmap
-                uids_outer.write().unwrap().insert(uid, handle);
+                // Basically, we need to copy the path string to deal with lifetime issues
+                let device_path = std::ffi::CString::new(device_info.path().to_bytes())
+                    .expect("hidapi path generation failed");
+                let seat = seat::device_seat(&device_path.to_string_lossy());
+
+                // Start thread if uid not it map (i.e. not already processing)
+                if !uids.clone().read().unwrap().contains_key(&uid) {
+                    // Add device
+                    info!("Connecting to uid:{} {}", uid, device_str);
+
+                    // Start device task
+                    let uids = uids.clone();
+                    let uids_outer = uids.clone();
+                    let mailbox = mailbox.clone();
+                    let api = api.clone();
+                    let handle = rt.spawn(async move {
+                        // Create node
+                        let mut node = Endpoint::new(node_type, uid);
+                        node.set_hidapi_params(info);
+                        node.set_seat(seat);
+                        node.set_descriptor(descriptor);
+
+                        // Connect to device, after a failure the device is located again first
+                        let mut path = device_path;
+                        let mut reconnect = false;
+                        let connect = move |node: &mut Endpoint| {
+                            let mut api = api.lock().unwrap();
+                            if reconnect {
+                                path = find_device(&mut api, &path, &key).ok_or_else(|| {
+                                    std::io::Error::new(
+                                        std::io::ErrorKind::NotFound,
+                                        "Device is not attached",
+                                    )
+                                })?;
+                                node.set_hidapi_path(format!("{:#?}", path.as_c_str()));
+                            }
+                            reconnect = true;
+
+                            // Setup device
+                            debug!("Attempting to setup {:#?}", node);
+                            let (device, access) =
+                                open_device(&api, &path, &quirks).map_err(|e| {
+                                    // Could not open device (likely removed, or in use)
+                                    std::io::Error::new(
+                                        std::io::ErrorKind::Other,
+                                        format!("{:?} - {}", path, e),
+                                    )
+                                })?;
+                            node.set_access(access);
+                            println!("Connected to {}", node);
+                            let device = HidApiDevice::new(device, TIMEOUT_MS, quirks.clone());
+                            let mut device = HidIoEndpoint::new(Box::new(device), max_packet_len);
+                            device.set_trusted(node_type == NodeType::UsbKeyboard);
+                            device.apply_quirks(&quirks);
+                            Ok(device)
+                        };
+                        supervise(mailbox, uid, node, connect).await;
+
+                        // Remove handle from map
+                        uids.write().unwrap().remove(&uid);
+                    });
+
+                    // Add uid to hashmap
+                    uids_outer.write().unwrap().insert(uid, handle);
+                }
             }
         }
 
-        // Sleep so we don't starve the CPU
+        // Wait for a hotplug event, polling is kept as a fallback (and to retry failed devices)
         // XXX - Rewrite hidapi with rust and include async
-        tokio::time::sleep(std::time::Duration::from_millis(ENUMERATE_DELAY_MS)).await;
+        let delay = if hotplug.active() {
+            HOTPLUG_RESCAN_DELAY_MS
+        } else {
+            ENUMERATE_DELAY_MS
+        };
+        if hotplug.wait(std::time::Duration::from_millis(delay)).await {
+            debug!("Hotplug event, rescanning devices");
+        }
     }
 }
 
 /// hidapi initialization
 ///
-/// Sets up a processing thread for hidapi.
+/// Sets up a processing thread for hidapi
 pub async fn initialize(mailbox: mailbox::Mailbox) {
     info!("Initializing device/hidapi...");
 
@@ -358,3 +764,35 @@ pub async fn initialize(mailbox: mailbox::Mailbox) {
         .await
         .unwrap();
 }
+
+// ----- Tests -----
+
+#[cfg(test)]
+mod test {
+    #[cfg(target_os = "linux")]
+    use super::*;
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn usb_port_path_test() {
+        assert_eq!(
+            usb_port_path(
+                "/sys/devices/pci0000:00/0000:00:14.0/usb1/1-2/1-2.3/1-2.3:1.2/0003:1C11:B04D.0005"
+            ),
+            Some("1-2.3".to_string())
+        );
+        assert_eq!(
+            usb_port_path(
+                "/sys/devices/pci0000:00/0000:00:14.0/usb3/3-1/3-1:1.0/0003:1C11:B04D.0001"
+            ),
+            Some("3-1".to_string())
+        );
+        // Bluetooth (hidp) devices are not USB devices
+        assert_eq!(
+            usb_port_path(
+                "/sys/devices/pci0000:00/0000:00:14.0/usb1/1-10/1-10:1.0/bluetooth/hci0/hci0:256/0005:1C11:B04D.0006"
+            ),
+            None
+        );
+    }
+}
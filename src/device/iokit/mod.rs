@@ -0,0 +1,650 @@
+#![cfg(all(feature = "iokit-devices", target_os = "macos"))]
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * This file is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This file is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this file.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// ----- Crates -----
+
+use crate::api::common_capnp::NodeType;
+use crate::api::Endpoint;
+use crate::api::HidApiInfo;
+use crate::device::descriptor::ReportDescriptor;
+use crate::device::exclusive::{self, Access};
+use crate::device::hidapi::{apply_report_id, max_packet_len, USAGE, USAGE_PAGE};
+use crate::device::quirks::{ControlChannel, ReportIdMode};
+use crate::device::selection;
+use crate::device::*;
+use crate::module::seat;
+use crate::RUNNING;
+use core_foundation::array::{CFArray, CFArrayRef};
+use core_foundation::base::{kCFAllocatorDefault, CFAllocatorRef, CFIndex, CFRelease, CFRetain};
+use core_foundation::base::{CFType, CFTypeRef, TCFType};
+use core_foundation::data::CFData;
+use core_foundation::dictionary::CFDictionary;
+use core_foundation::number::CFNumber;
+use core_foundation::runloop::{kCFRunLoopDefaultMode, CFRunLoop, CFRunLoopRef};
+use core_foundation::string::{CFString, CFStringRef};
+use std::collections::HashMap;
+use std::os::raw::c_void;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+
+// ----- Consts -----
+
+type IOHIDManagerRef = *mut c_void;
+type IOHIDDeviceRef = *mut c_void;
+type IOReturn = i32;
+
+type IOHIDDeviceCallback = extern "C" fn(
+    context: *mut c_void,
+    result: IOReturn,
+    sender: *mut c_void,
+    device: IOHIDDeviceRef,
+);
+type IOHIDReportCallback = extern "C" fn(
+    context: *mut c_void,
+    result: IOReturn,
+    sender: *mut c_void,
+    report_type: u32,
+    report_id: u32,
+    report: *mut u8,
+    report_length: CFIndex,
+);
+
+const IO_RETURN_SUCCESS: IOReturn = 0;
+/// kIOReturnNotPermitted, Input Monitoring has not been granted
+const IO_RETURN_NOT_PERMITTED: IOReturn = 0xE000_02E2_u32 as IOReturn;
+
+const IOHID_OPTIONS_TYPE_NONE: u32 = 0x00;
+const IOHID_OPTIONS_TYPE_SEIZE_DEVICE: u32 = 0x01;
+
+const IOHID_REPORT_TYPE_OUTPUT: u32 = 1;
+const IOHID_REPORT_TYPE_FEATURE: u32 = 2;
+
+/// Read timeout of a connected device
+const TIMEOUT_MS: u64 = 10;
+
+/// How often the run loop checks whether the daemon is quitting
+const RUNLOOP_MS: u64 = 500;
+
+/// Feature reports must be polled, keep it to a low rate
+const CONTROL_POLL_MS: u64 = 50;
+
+#[link(name = "IOKit", kind = "framework")]
+extern "C" {
+    fn IOHIDManagerCreate(allocator: CFAllocatorRef, options: u32) -> IOHIDManagerRef;
+    fn IOHIDManagerSetDeviceMatchingMultiple(manager: IOHIDManagerRef, multiple: CFArrayRef);
+    fn IOHIDManagerRegisterDeviceMatchingCallback(
+        manager: IOHIDManagerRef,
+        callback: IOHIDDeviceCallback,
+        context: *mut c_void,
+    );
+    fn IOHIDManagerRegisterDeviceRemovalCallback(
+        manager: IOHIDManagerRef,
+        callback: IOHIDDeviceCallback,
+        context: *mut c_void,
+    );
+    fn IOHIDManagerScheduleWithRunLoop(
+        manager: IOHIDManagerRef,
+        run_loop: CFRunLoopRef,
+        mode: CFStringRef,
+    );
+    fn IOHIDManagerUnscheduleFromRunLoop(
+        manager: IOHIDManagerRef,
+        run_loop: CFRunLoopRef,
+        mode: CFStringRef,
+    );
+
+    fn IOHIDDeviceGetProperty(device: IOHIDDeviceRef, key: CFStringRef) -> CFTypeRef;
+    fn IOHIDDeviceGetService(device: IOHIDDeviceRef) -> u32;
+    fn IOHIDDeviceOpen(device: IOHIDDeviceRef, options: u32) -> IOReturn;
+    fn IOHIDDeviceClose(device: IOHIDDeviceRef, options: u32) -> IOReturn;
+    fn IOHIDDeviceSetReport(
+        device: IOHIDDeviceRef,
+        report_type: u32,
+        report_id: CFIndex,
+        report: *const u8,
+        report_length: CFIndex,
+    ) -> IOReturn;
+    fn IOHIDDeviceGetReport(
+        device: IOHIDDeviceRef,
+        report_type: u32,
+        report_id: CFIndex,
+        report: *mut u8,
+        report_length: *mut CFIndex,
+    ) -> IOReturn;
+    fn IOHIDDeviceRegisterInputReportCallback(
+        device: IOHIDDeviceRef,
+        report: *mut u8,
+        report_length: CFIndex,
+        callback: Option<IOHIDReportCallback>,
+        context: *mut c_void,
+    );
+
+    fn IORegistryEntryGetRegistryEntryID(entry: u32, entry_id: *mut u64) -> i32;
+}
+
+// ----- Structs -----
+
+/// IOKit HID device, opened by the HID manager
+///
+/// Input reports are delivered on the manager run loop thread and queued, so reads only wait on
+/// the queue. Output and feature reports are sent directly from the device thread.
+pub struct IoKitDevice {
+    device: IOHIDDeviceRef,
+    receiver: mpsc::Receiver<Vec<u8>>,
+    timeout: u64,
+    report_id: ReportIdMode,
+    control_channel: ControlChannel,
+    last_control_poll: std::time::Instant,
+}
+
+// IOHIDDevice functions used by IoKitDevice (SetReport/GetReport/Close) may be called from any
+// thread, the reference is retained until the device is dropped.
+unsafe impl Send for IoKitDevice {}
+
+impl IoKitDevice {
+    fn set_report(&mut self, report_type: u32, id: u8, buf: &[u8]) -> std::io::Result<usize> {
+        // Numbered reports must start with the report id
+        let mut report = Vec::with_capacity(buf.len() + 1);
+        if id != 0 {
+            report.push(id);
+        }
+        report.extend_from_slice(buf);
+
+        let ret = unsafe {
+            IOHIDDeviceSetReport(
+                self.device,
+                report_type,
+                id as CFIndex,
+                report.as_ptr(),
+                report.len() as CFIndex,
+            )
+        };
+        if ret != IO_RETURN_SUCCESS {
+            warn!("IOHIDDeviceSetReport({}) failed {:#x}", report_type, ret);
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!("IOHIDDeviceSetReport failed {:#x}", ret),
+            ));
+        }
+        trace!("Sent {} bytes", report.len());
+        trace!("{:x?}", report);
+        Ok(buf.len())
+    }
+}
+
+impl std::io::Read for IoKitDevice {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let report = match self
+            .receiver
+            .recv_timeout(std::time::Duration::from_millis(self.timeout))
+        {
+            Ok(report) => report,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                return Ok(0);
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::NotConnected,
+                    "IOKit device removed",
+                ));
+            }
+        };
+
+        // Numbered reports are always prefixed with the report id, strip it
+        let data = match self.report_id {
+            ReportIdMode::Unnumbered => &report[..],
+            ReportIdMode::Numbered(id) if report.first() == Some(&id) => &report[1..],
+            ReportIdMode::Numbered(id) => {
+                warn!(
+                    "Dropping report with unexpected id (expected {:#x}): {:x?}",
+                    id, report
+                );
+                return Ok(0);
+            }
+        };
+        let len = std::cmp::min(data.len(), buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        trace!("Received {} bytes", len);
+        trace!("{:x?}", &buf[0..len]);
+        Ok(len)
+    }
+}
+
+impl std::io::Write for IoKitDevice {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let id = match self.report_id {
+            ReportIdMode::Unnumbered => 0,
+            ReportIdMode::Numbered(id) => id,
+        };
+        self.set_report(IOHID_REPORT_TYPE_OUTPUT, id, buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl HidIoTransport for IoKitDevice {
+    fn has_control_channel(&self) -> bool {
+        self.control_channel != ControlChannel::Interrupt
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<i32>) {
+        self.timeout = timeout.map_or(TIMEOUT_MS, |timeout| timeout.max(0) as u64);
+    }
+
+    fn write_control(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self.control_channel {
+            ControlChannel::Interrupt => self.write(buf),
+            ControlChannel::Feature(id) => self.set_report(IOHID_REPORT_TYPE_FEATURE, id, buf),
+        }
+    }
+
+    fn read_control(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let id = match self.control_channel {
+            ControlChannel::Interrupt => {
+                return Ok(0);
+            }
+            ControlChannel::Feature(id) => id,
+        };
+
+        if self.last_control_poll.elapsed().as_millis() < CONTROL_POLL_MS as u128 {
+            return Ok(0);
+        }
+        self.last_control_poll = std::time::Instant::now();
+
+        let mut report = vec![0; buf.len() + 1];
+        let mut len = report.len() as CFIndex;
+        let ret = unsafe {
+            IOHIDDeviceGetReport(
+                self.device,
+                IOHID_REPORT_TYPE_FEATURE,
+                id as CFIndex,
+                report.as_mut_ptr(),
+                &mut len,
+            )
+        };
+        if ret != IO_RETURN_SUCCESS {
+            warn!("IOHIDDeviceGetReport failed {:#x}", ret);
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!("IOHIDDeviceGetReport failed {:#x}", ret),
+            ));
+        }
+
+        // The report id is included, all-zero report means nothing is pending
+        let len = len as usize;
+        if len <= 1 || report[1..len].iter().all(|b| *b == 0) {
+            return Ok(0);
+        }
+        buf[0..len - 1].copy_from_slice(&report[1..len]);
+        Ok(len - 1)
+    }
+}
+
+impl Drop for IoKitDevice {
+    fn drop(&mut self) {
+        unsafe {
+            IOHIDDeviceClose(self.device, IOHID_OPTIONS_TYPE_NONE);
+            CFRelease(self.device);
+        }
+    }
+}
+
+/// Open device, owned by the run loop thread
+struct Connection {
+    path: String,
+    sender: mpsc::Sender<Vec<u8>>,
+    /// Input report buffer used by IOKit, must stay allocated while the callback is registered
+    report: Vec<u8>,
+}
+
+/// HID manager state, only accessed from the run loop thread (callbacks)
+struct Manager {
+    mailbox: mailbox::Mailbox,
+    connections: HashMap<usize, Box<Connection>>,
+}
+
+impl Manager {
+    /// A HID-IO interface was attached (or was already attached when the manager started)
+    fn matched(&mut self, device: IOHIDDeviceRef) {
+        let mut info = device_info(device);
+        if !filter::allowed(&info) {
+            info!("Ignoring {} (device-filter)", info.path);
+            return;
+        }
+        let uid = match self
+            .mailbox
+            .clone()
+            .assign_uid(info.key(), info.path.clone())
+        {
+            Ok(uid) => uid,
+            Err(_) => {
+                // Device has already been registered, or is invalid
+                return;
+            }
+        };
+        info!("Connecting to uid:{} {:?}", uid, info);
+
+        // Lookup any device specific handling
+        let mut quirks = quirks::lookup(info.vendor_id, info.product_id, info.interface_number);
+        let descriptor = data_property(device, "ReportDescriptor")
+            .and_then(|data| ReportDescriptor::parse(&data).ok());
+        let selection = selection::lookup(info.vendor_id, info.product_id);
+        apply_report_id(descriptor.as_ref(), selection, &mut quirks);
+        let max_packet_len = max_packet_len(descriptor.as_ref(), selection);
+
+        quirks.seize |= exclusive::requested(&info);
+        let (options, mut access) = if quirks.seize {
+            (IOHID_OPTIONS_TYPE_SEIZE_DEVICE, Access::Exclusive)
+        } else {
+            (IOHID_OPTIONS_TYPE_NONE, Access::Shared)
+        };
+        let mut ret = unsafe { IOHIDDeviceOpen(device, options) };
+        if quirks.seize && ret != IO_RETURN_SUCCESS && ret != IO_RETURN_NOT_PERMITTED {
+            // Another process already has the device open, fall back to shared access
+            let reason = format!(
+                "Could not open exclusively (seize) - {:#x}. \
+                 Check that no other application has the device open",
+                ret
+            );
+            error!("{} {}. Falling back to shared access.", info.path, reason);
+            access = Access::SharedFallback(reason);
+            ret = unsafe { IOHIDDeviceOpen(device, IOHID_OPTIONS_TYPE_NONE) };
+        }
+        match ret {
+            IO_RETURN_SUCCESS => {}
+            IO_RETURN_NOT_PERMITTED => {
+                error!(
+                    "Not permitted to open {} - allow hid-io-core under System Preferences > Security & Privacy > Input Monitoring",
+                    info.path
+                );
+                return;
+            }
+            _ => {
+                warn!("Failed to open device:{} - {:#x}", info.path, ret);
+                return;
+            }
+        }
+
+        // Queue input reports for the device thread
+        // The device is scheduled on the run loop together with the manager.
+        let report_len = number_property(device, "MaxInputReportSize")
+            .filter(|len| *len > 0)
+            .unwrap_or(max_packet_len as i64 + 1);
+        let (sender, receiver) = mpsc::channel();
+        let mut connection = Box::new(Connection {
+            path: info.path.clone(),
+            sender,
+            report: vec![0; report_len as usize],
+        });
+        unsafe {
+            IOHIDDeviceRegisterInputReportCallback(
+                device,
+                connection.report.as_mut_ptr(),
+                connection.report.len() as CFIndex,
+                Some(input_report_callback),
+                &mut *connection as *mut Connection as *mut c_void,
+            );
+            CFRetain(device);
+        }
+        self.connections.insert(device as usize, connection);
+
+        let transport = IoKitDevice {
+            device,
+            receiver,
+            timeout: TIMEOUT_MS,
+            report_id: quirks.report_id,
+            control_channel: quirks.control_channel,
+            last_control_poll: std::time::Instant::now(),
+        };
+
+        // Start thread
+        let node_type = node_type(device);
+        let seat = seat::device_seat(&info.path);
+        let mailbox = self.mailbox.clone();
+        self.mailbox.rt.spawn_blocking(move || {
+            // Create node
+            let mut node = Endpoint::new(node_type, uid);
+            node.set_hidapi_params(info);
+            node.set_seat(seat);
+            node.set_descriptor(descriptor);
+            node.set_access(access);
+            println!("Connected to {}", node);
+
+            let mut device = HidIoEndpoint::new(Box::new(transport), max_packet_len);
+            device.set_trusted(node_type == NodeType::UsbKeyboard);
+            device.apply_quirks(&quirks);
+            serve(mailbox, uid, node, device);
+        });
+    }
+
+    /// A HID-IO interface was removed
+    fn removed(&mut self, device: IOHIDDeviceRef) {
+        if let Some(mut connection) = self.connections.remove(&(device as usize)) {
+            // Unregister before the report buffer is freed
+            // Dropping the sender stops the device thread.
+            unsafe {
+                IOHIDDeviceRegisterInputReportCallback(
+                    device,
+                    connection.report.as_mut_ptr(),
+                    connection.report.len() as CFIndex,
+                    None,
+                    std::ptr::null_mut(),
+                );
+            }
+            info!("Removed {}", connection.path);
+        }
+    }
+}
+
+// ----- Functions -----
+
+extern "C" fn matched_callback(
+    context: *mut c_void,
+    _result: IOReturn,
+    _sender: *mut c_void,
+    device: IOHIDDeviceRef,
+) {
+    let manager = unsafe { &mut *(context as *mut Manager) };
+    manager.matched(device);
+}
+
+extern "C" fn removed_callback(
+    context: *mut c_void,
+    _result: IOReturn,
+    _sender: *mut c_void,
+    device: IOHIDDeviceRef,
+) {
+    let manager = unsafe { &mut *(context as *mut Manager) };
+    manager.removed(device);
+}
+
+extern "C" fn input_report_callback(
+    context: *mut c_void,
+    result: IOReturn,
+    _sender: *mut c_void,
+    _report_type: u32,
+    _report_id: u32,
+    report: *mut u8,
+    report_length: CFIndex,
+) {
+    if result != IO_RETURN_SUCCESS || report.is_null() {
+        return;
+    }
+    let connection = unsafe { &*(context as *const Connection) };
+    let report = unsafe { std::slice::from_raw_parts(report, report_length as usize) };
+    // The device thread may already have stopped, it is cleaned up on removal
+    connection.sender.send(report.to_vec()).ok();
+}
+
+fn property(device: IOHIDDeviceRef, key: &'static str) -> Option<CFType> {
+    let key = CFString::from_static_string(key);
+    let value = unsafe { IOHIDDeviceGetProperty(device, key.as_concrete_TypeRef()) };
+    if value.is_null() {
+        return None;
+    }
+    Some(unsafe { CFType::wrap_under_get_rule(value) })
+}
+
+fn string_property(device: IOHIDDeviceRef, key: &'static str) -> Option<String> {
+    property(device, key)
+        .and_then(|value| value.downcast::<CFString>())
+        .map(|value| value.to_string())
+}
+
+fn number_property(device: IOHIDDeviceRef, key: &'static str) -> Option<i64> {
+    property(device, key)
+        .and_then(|value| value.downcast::<CFNumber>())
+        .and_then(|value| value.to_i64())
+}
+
+fn data_property(device: IOHIDDeviceRef, key: &'static str) -> Option<Vec<u8>> {
+    property(device, key)
+        .and_then(|value| value.downcast::<CFData>())
+        .map(|value| value.bytes().to_vec())
+}
+
+/// Same path format as hidapi, so paths can be used interchangeably (e.g. hid-io-bridge --path)
+fn device_path(device: IOHIDDeviceRef) -> String {
+    let mut id = 0;
+    let ret = unsafe { IORegistryEntryGetRegistryEntryID(IOHIDDeviceGetService(device), &mut id) };
+    if ret != 0 {
+        return format!("{:?}", device);
+    }
+    format!("DevSrvsID:{}", id)
+}
+
+fn device_info(device: IOHIDDeviceRef) -> HidApiInfo {
+    let number = |key| number_property(device, key).unwrap_or(0);
+    HidApiInfo {
+        path: device_path(device),
+        vendor_id: number("VendorID") as u16,
+        product_id: number("ProductID") as u16,
+        serial_number: string_property(device, "SerialNumber")
+            .unwrap_or_else(|| "<Serial Unset>".to_string()),
+        release_number: number("VersionNumber") as u16,
+        manufacturer_string: string_property(device, "Manufacturer")
+            .unwrap_or_else(|| "<Unset>".to_string()),
+        product_string: string_property(device, "Product").unwrap_or_else(|| "<Unset>".to_string()),
+        usage_page: number_property(device, "PrimaryUsagePage").map_or(USAGE_PAGE, |v| v as u16),
+        usage: number_property(device, "PrimaryUsage").map_or(USAGE, |v| v as u16),
+        // Not available on macOS (same as hidapi)
+        interface_number: -1,
+        // IOKit identifies the USB port by location id
+        port_path: number_property(device, "LocationID")
+            .map_or_else(String::new, |location| format!("{:08x}", location)),
+    }
+}
+
+/// Determine the transport using the Transport property (USB, Bluetooth, Bluetooth Low Energy)
+fn node_type(device: IOHIDDeviceRef) -> NodeType {
+    match string_property(device, "Transport") {
+        Some(transport) if transport.contains("Low Energy") => NodeType::BleKeyboard,
+        Some(transport) if transport.starts_with("Bluetooth") => NodeType::BtKeyboard,
+        _ => NodeType::UsbKeyboard,
+    }
+}
+
+/// IOKit processing
+///
+/// Devices are matched by the HID manager (usage page/usage), connections and removals are
+/// handled by callbacks on this thread's run loop.
+fn processing(mailbox: mailbox::Mailbox) {
+    info!("Spawning IOKit HID manager thread...");
+
+    let mut manager_state = Box::new(Manager {
+        mailbox,
+        connections: HashMap::new(),
+    });
+    let context = &mut *manager_state as *mut Manager as *mut c_void;
+
+    // Only match HID-IO interfaces
+    // Devices with a configured usage (see device::selection) are matched as well
+    let usage_matching = |usage_page: u16, usage: u16| {
+        vec![
+            (
+                CFString::from_static_string("DeviceUsagePage").as_CFType(),
+                CFNumber::from(usage_page as i32).as_CFType(),
+            ),
+            (
+                CFString::from_static_string("DeviceUsage").as_CFType(),
+                CFNumber::from(usage as i32).as_CFType(),
+            ),
+        ]
+    };
+    let mut matching = vec![CFDictionary::from_CFType_pairs(&usage_matching(
+        USAGE_PAGE, USAGE,
+    ))];
+    for (vid, pid, usage_page, usage) in selection::usage_overrides() {
+        let mut pairs = usage_matching(usage_page, usage);
+        pairs.push((
+            CFString::from_static_string("VendorID").as_CFType(),
+            CFNumber::from(vid as i32).as_CFType(),
+        ));
+        pairs.push((
+            CFString::from_static_string("ProductID").as_CFType(),
+            CFNumber::from(pid as i32).as_CFType(),
+        ));
+        matching.push(CFDictionary::from_CFType_pairs(&pairs));
+    }
+    let matching = CFArray::from_CFTypes(&matching);
+
+    let run_loop = CFRunLoop::get_current();
+    let manager = unsafe {
+        let manager = IOHIDManagerCreate(kCFAllocatorDefault, IOHID_OPTIONS_TYPE_NONE);
+        IOHIDManagerSetDeviceMatchingMultiple(manager, matching.as_concrete_TypeRef());
+        IOHIDManagerRegisterDeviceMatchingCallback(manager, matched_callback, context);
+        IOHIDManagerRegisterDeviceRemovalCallback(manager, removed_callback, context);
+        IOHIDManagerScheduleWithRunLoop(
+            manager,
+            run_loop.as_concrete_TypeRef(),
+            kCFRunLoopDefaultMode,
+        );
+        manager
+    };
+
+    // Callbacks are only called while the run loop is running
+    while RUNNING.load(Ordering::SeqCst) {
+        CFRunLoop::run_in_mode(
+            unsafe { kCFRunLoopDefaultMode },
+            std::time::Duration::from_millis(RUNLOOP_MS),
+            false,
+        );
+    }
+
+    unsafe {
+        IOHIDManagerUnscheduleFromRunLoop(
+            manager,
+            run_loop.as_concrete_TypeRef(),
+            kCFRunLoopDefaultMode,
+        );
+        CFRelease(manager);
+    }
+}
+
+/// IOKit initialization
+///
+/// Replaces the hidapi watcher on macOS with an event-driven HID manager.
+pub async fn initialize(mailbox: mailbox::Mailbox) {
+    info!("Initializing device/iokit...");
+
+    let rt = mailbox.rt.clone();
+    rt.spawn_blocking(move || processing(mailbox))
+        .await
+        .unwrap();
+}
@@ -0,0 +1,388 @@
+#![cfg(all(feature = "dev-capture", target_os = "macos"))]
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * This file is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This file is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this file.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// ----- Crates -----
+
+use crate::api::common_capnp::NodeType;
+use crate::api::Endpoint;
+use crate::device::capture::{Config, DeviceClass, Keyboard, CONFIG_FILE};
+use crate::device::descriptor::{GENERIC_DESKTOP, USAGE_KEYBOARD};
+use crate::mailbox;
+use crate::RUNNING;
+use core_foundation::base::{kCFAllocatorDefault, CFAllocatorRef, CFIndex, CFRelease};
+use core_foundation::base::{CFType, CFTypeRef, TCFType};
+use core_foundation::dictionary::{CFDictionary, CFDictionaryRef};
+use core_foundation::number::CFNumber;
+use core_foundation::runloop::{kCFRunLoopDefaultMode, CFRunLoop, CFRunLoopRef};
+use core_foundation::string::{CFString, CFStringRef};
+use std::collections::HashMap;
+use std::os::raw::c_void;
+use std::sync::atomic::Ordering;
+
+// ----- Consts -----
+
+type IOHIDManagerRef = *mut c_void;
+type IOHIDDeviceRef = *mut c_void;
+type IOHIDElementRef = *mut c_void;
+type IOHIDValueRef = *mut c_void;
+type IOReturn = i32;
+
+type IOHIDDeviceCallback = extern "C" fn(
+    context: *mut c_void,
+    result: IOReturn,
+    sender: *mut c_void,
+    device: IOHIDDeviceRef,
+);
+type IOHIDValueCallback = extern "C" fn(
+    context: *mut c_void,
+    result: IOReturn,
+    sender: *mut c_void,
+    value: IOHIDValueRef,
+);
+
+const IO_RETURN_SUCCESS: IOReturn = 0;
+/// kIOReturnNotPermitted, Input Monitoring has not been granted
+const IO_RETURN_NOT_PERMITTED: IOReturn = 0xE000_02E2_u32 as IOReturn;
+
+const IOHID_OPTIONS_TYPE_NONE: u32 = 0x00;
+
+/// kIOHIDRequestTypeListenEvent (Input Monitoring)
+const IOHID_REQUEST_TYPE_LISTEN_EVENT: u32 = 1;
+
+// IOHIDAccessType
+const IOHID_ACCESS_TYPE_GRANTED: u32 = 0;
+const IOHID_ACCESS_TYPE_DENIED: u32 = 1;
+
+/// Keyboard/Keypad usage page
+const KEYBOARD_PAGE: u32 = 0x07;
+
+/// Key usages (excludes the error/rollover usages)
+const KEY_USAGES: std::ops::RangeInclusive<u32> = 0x04..=0xE7;
+
+/// How often the run loop checks whether the daemon is quitting
+const RUNLOOP_MS: u64 = 500;
+
+#[link(name = "IOKit", kind = "framework")]
+extern "C" {
+    fn IOHIDManagerCreate(allocator: CFAllocatorRef, options: u32) -> IOHIDManagerRef;
+    fn IOHIDManagerSetDeviceMatching(manager: IOHIDManagerRef, matching: CFDictionaryRef);
+    fn IOHIDManagerSetInputValueMatching(manager: IOHIDManagerRef, matching: CFDictionaryRef);
+    fn IOHIDManagerRegisterDeviceMatchingCallback(
+        manager: IOHIDManagerRef,
+        callback: IOHIDDeviceCallback,
+        context: *mut c_void,
+    );
+    fn IOHIDManagerRegisterDeviceRemovalCallback(
+        manager: IOHIDManagerRef,
+        callback: IOHIDDeviceCallback,
+        context: *mut c_void,
+    );
+    fn IOHIDManagerRegisterInputValueCallback(
+        manager: IOHIDManagerRef,
+        callback: IOHIDValueCallback,
+        context: *mut c_void,
+    );
+    fn IOHIDManagerScheduleWithRunLoop(
+        manager: IOHIDManagerRef,
+        run_loop: CFRunLoopRef,
+        mode: CFStringRef,
+    );
+    fn IOHIDManagerUnscheduleFromRunLoop(
+        manager: IOHIDManagerRef,
+        run_loop: CFRunLoopRef,
+        mode: CFStringRef,
+    );
+    fn IOHIDManagerOpen(manager: IOHIDManagerRef, options: u32) -> IOReturn;
+    fn IOHIDManagerClose(manager: IOHIDManagerRef, options: u32) -> IOReturn;
+
+    fn IOHIDDeviceGetProperty(device: IOHIDDeviceRef, key: CFStringRef) -> CFTypeRef;
+    fn IOHIDDeviceGetService(device: IOHIDDeviceRef) -> u32;
+
+    fn IOHIDValueGetElement(value: IOHIDValueRef) -> IOHIDElementRef;
+    fn IOHIDValueGetIntegerValue(value: IOHIDValueRef) -> CFIndex;
+    fn IOHIDElementGetDevice(element: IOHIDElementRef) -> IOHIDDeviceRef;
+    fn IOHIDElementGetUsagePage(element: IOHIDElementRef) -> u32;
+    fn IOHIDElementGetUsage(element: IOHIDElementRef) -> u32;
+
+    fn IOHIDCheckAccess(request_type: u32) -> u32;
+    fn IOHIDRequestAccess(request_type: u32) -> bool;
+
+    fn IORegistryEntryGetRegistryEntryID(entry: u32, entry_id: *mut u64) -> i32;
+}
+
+// ----- Structs -----
+
+/// Capture state, only accessed from the run loop thread (callbacks)
+struct Capture {
+    mailbox: mailbox::Mailbox,
+    config: Config,
+    keyboards: HashMap<usize, Keyboard>,
+}
+
+impl Capture {
+    /// A keyboard was attached (or was already attached when the manager started)
+    fn matched(&mut self, device: IOHIDDeviceRef) {
+        let number = |key| number_property(device, key).unwrap_or(0);
+        let vid = number("VendorID") as u16;
+        let pid = number("ProductID") as u16;
+        let path = device_path(device);
+        if !self.config.matches(DeviceClass::Keyboard, vid, pid) {
+            debug!("Not capturing {}", path);
+            return;
+        }
+
+        let key = format!("iokit vid:{:04x} pid:{:04x} path:{}", vid, pid, path);
+        let uid = match self.mailbox.clone().assign_uid(key, path.clone()) {
+            Ok(uid) => uid,
+            Err(_) => {
+                // Device has already been registered
+                return;
+            }
+        };
+
+        let product =
+            string_property(device, "Product").unwrap_or_else(|| "IOKit keyboard".to_string());
+        let mut endpoint = Endpoint::new(NodeType::HidKeyboard, uid);
+        endpoint.set_hidio_params(format!("[{:04x}:{:04x}] {}", vid, pid, product), path);
+        info!("Capturing {}", endpoint);
+        self.mailbox.clone().register_node(endpoint);
+        self.keyboards.insert(device as usize, Keyboard::new(uid));
+    }
+
+    /// A keyboard was removed
+    fn removed(&mut self, device: IOHIDDeviceRef) {
+        if let Some(keyboard) = self.keyboards.remove(&(device as usize)) {
+            info!("Disconnection event uid:{}", keyboard.uid);
+            self.mailbox.unregister_node(keyboard.uid);
+        }
+    }
+
+    /// Key pressed/released
+    fn value(&mut self, value: IOHIDValueRef) {
+        let (device, page, usage, pressed) = unsafe {
+            let element = IOHIDValueGetElement(value);
+            (
+                IOHIDElementGetDevice(element),
+                IOHIDElementGetUsagePage(element),
+                IOHIDElementGetUsage(element),
+                IOHIDValueGetIntegerValue(value) != 0,
+            )
+        };
+        if page != KEYBOARD_PAGE || !KEY_USAGES.contains(&usage) {
+            return;
+        }
+        if let Some(keyboard) = self.keyboards.get_mut(&(device as usize)) {
+            keyboard.update(&self.mailbox, usage as u8, pressed);
+        }
+    }
+
+    /// Unregisters all captured keyboards
+    fn clear(&mut self) {
+        for (_, keyboard) in self.keyboards.drain() {
+            self.mailbox.unregister_node(keyboard.uid);
+        }
+    }
+}
+
+// ----- Functions -----
+
+extern "C" fn matched_callback(
+    context: *mut c_void,
+    _result: IOReturn,
+    _sender: *mut c_void,
+    device: IOHIDDeviceRef,
+) {
+    let capture = unsafe { &mut *(context as *mut Capture) };
+    capture.matched(device);
+}
+
+extern "C" fn removed_callback(
+    context: *mut c_void,
+    _result: IOReturn,
+    _sender: *mut c_void,
+    device: IOHIDDeviceRef,
+) {
+    let capture = unsafe { &mut *(context as *mut Capture) };
+    capture.removed(device);
+}
+
+extern "C" fn value_callback(
+    context: *mut c_void,
+    result: IOReturn,
+    _sender: *mut c_void,
+    value: IOHIDValueRef,
+) {
+    if result != IO_RETURN_SUCCESS || value.is_null() {
+        return;
+    }
+    let capture = unsafe { &mut *(context as *mut Capture) };
+    capture.value(value);
+}
+
+fn property(device: IOHIDDeviceRef, key: &'static str) -> Option<CFType> {
+    let key = CFString::from_static_string(key);
+    let value = unsafe { IOHIDDeviceGetProperty(device, key.as_concrete_TypeRef()) };
+    if value.is_null() {
+        return None;
+    }
+    Some(unsafe { CFType::wrap_under_get_rule(value) })
+}
+
+fn string_property(device: IOHIDDeviceRef, key: &'static str) -> Option<String> {
+    property(device, key)
+        .and_then(|value| value.downcast::<CFString>())
+        .map(|value| value.to_string())
+}
+
+fn number_property(device: IOHIDDeviceRef, key: &'static str) -> Option<i64> {
+    property(device, key)
+        .and_then(|value| value.downcast::<CFNumber>())
+        .and_then(|value| value.to_i64())
+}
+
+/// Same path format as hidapi (and the iokit backend)
+fn device_path(device: IOHIDDeviceRef) -> String {
+    let mut id = 0;
+    let ret = unsafe { IORegistryEntryGetRegistryEntryID(IOHIDDeviceGetService(device), &mut id) };
+    if ret != 0 {
+        return format!("{:?}", device);
+    }
+    format!("DevSrvsID:{}", id)
+}
+
+/// Checks for Input Monitoring access, prompting the user if it has not been decided yet
+fn access() -> bool {
+    match unsafe { IOHIDCheckAccess(IOHID_REQUEST_TYPE_LISTEN_EVENT) } {
+        IOHID_ACCESS_TYPE_GRANTED => true,
+        IOHID_ACCESS_TYPE_DENIED => {
+            error!("Key capture disabled - allow hid-io-core under System Preferences > Security & Privacy > Input Monitoring");
+            false
+        }
+        _ => {
+            // Shows the Input Monitoring prompt
+            if unsafe { IOHIDRequestAccess(IOHID_REQUEST_TYPE_LISTEN_EVENT) } {
+                return true;
+            }
+            error!("Key capture disabled - Input Monitoring was not granted, restart hid-io-core once allowed");
+            false
+        }
+    }
+}
+
+/// IOKit capture processing
+///
+/// Keyboards are matched by the HID manager, key values are delivered by callbacks on this
+/// thread's run loop.
+fn processing(mailbox: mailbox::Mailbox, config: Config) {
+    info!("Spawning IOKit capture thread...");
+
+    if !access() {
+        return;
+    }
+
+    let mut capture = Box::new(Capture {
+        mailbox,
+        config,
+        keyboards: HashMap::new(),
+    });
+    let context = &mut *capture as *mut Capture as *mut c_void;
+
+    let device_matching = CFDictionary::from_CFType_pairs(&[
+        (
+            CFString::from_static_string("DeviceUsagePage").as_CFType(),
+            CFNumber::from(GENERIC_DESKTOP as i32).as_CFType(),
+        ),
+        (
+            CFString::from_static_string("DeviceUsage").as_CFType(),
+            CFNumber::from(USAGE_KEYBOARD as i32).as_CFType(),
+        ),
+    ]);
+    let value_matching = CFDictionary::from_CFType_pairs(&[(
+        CFString::from_static_string("UsagePage").as_CFType(),
+        CFNumber::from(KEYBOARD_PAGE as i32).as_CFType(),
+    )]);
+
+    let run_loop = CFRunLoop::get_current();
+    let manager = unsafe {
+        let manager = IOHIDManagerCreate(kCFAllocatorDefault, IOHID_OPTIONS_TYPE_NONE);
+        IOHIDManagerSetDeviceMatching(manager, device_matching.as_concrete_TypeRef());
+        IOHIDManagerSetInputValueMatching(manager, value_matching.as_concrete_TypeRef());
+        IOHIDManagerRegisterDeviceMatchingCallback(manager, matched_callback, context);
+        IOHIDManagerRegisterDeviceRemovalCallback(manager, removed_callback, context);
+        IOHIDManagerRegisterInputValueCallback(manager, value_callback, context);
+        IOHIDManagerScheduleWithRunLoop(
+            manager,
+            run_loop.as_concrete_TypeRef(),
+            kCFRunLoopDefaultMode,
+        );
+        manager
+    };
+
+    // Keyboards are opened shared, keys are still seen by other applications
+    match unsafe { IOHIDManagerOpen(manager, IOHID_OPTIONS_TYPE_NONE) } {
+        IO_RETURN_SUCCESS => {}
+        IO_RETURN_NOT_PERMITTED => {
+            error!("Not permitted to capture keyboards - allow hid-io-core under System Preferences > Security & Privacy > Input Monitoring");
+        }
+        ret => {
+            warn!("IOHIDManagerOpen failed {:#x}", ret);
+        }
+    }
+
+    // Callbacks are only called while the run loop is running
+    while RUNNING.load(Ordering::SeqCst) {
+        CFRunLoop::run_in_mode(
+            unsafe { kCFRunLoopDefaultMode },
+            std::time::Duration::from_millis(RUNLOOP_MS),
+            false,
+        );
+    }
+
+    unsafe {
+        IOHIDManagerClose(manager, IOHID_OPTIONS_TYPE_NONE);
+        IOHIDManagerUnscheduleFromRunLoop(
+            manager,
+            run_loop.as_concrete_TypeRef(),
+            kCFRunLoopDefaultMode,
+        );
+        CFRelease(manager);
+    }
+    capture.clear();
+}
+
+/// IOKit capture initialization
+///
+/// Captures key events from the keyboards selected in the capture-devices file.
+pub async fn initialize(mailbox: mailbox::Mailbox) {
+    info!("Initializing device/iokitcapture...");
+
+    let config = match Config::load() {
+        Some(config) => config,
+        None => {
+            info!(
+                "IOKit key capture disabled, create {} in the config directory to enable",
+                CONFIG_FILE
+            );
+            return;
+        }
+    };
+
+    let rt = mailbox.rt.clone();
+    rt.spawn_blocking(move || processing(mailbox, config))
+        .await
+        .unwrap();
+}
@@ -14,63 +14,637 @@
  * along with this file.  If not, see <http://www.gnu.org/licenses/>.
  */
 
+/// BLE devices exposing the HID-IO GATT service
+pub mod ble;
+/// Bluetooth Classic HID devices (Linux hidp)
+#[cfg(all(target_os = "linux", feature = "bt-devices"))]
+pub mod bt;
+/// Key capture configuration shared by the evdev, Raw Input and IOKit capture backends
+pub mod capture;
+/// Device matching and line-oriented parsing shared by the device config files
+pub mod config;
+/// HID report descriptor parsing
+pub mod descriptor;
 pub mod evdev;
+/// Devices whose HID-IO interface is opened exclusively
+pub mod exclusive;
+/// Allow/deny rules for devices hid-io-core may open
+pub mod filter;
 pub mod hidapi;
+/// Event-driven IOKit HID manager backend (macOS)
+pub mod iokit;
+/// Key capture using IOKit HID value callbacks (macOS)
+pub mod iokitcapture;
+/// Paired keys for encrypted payloads
+pub mod pairing;
+/// Per-device read strategy (poll interval or blocking read)
+pub mod polling;
+pub mod quirks;
+/// Key capture using the Raw Input API (Windows)
+pub mod rawinput;
+/// Devices attached to other machines, forwarded over TCP by hid-io-bridge
+pub mod remote;
+/// Selection of the HID-IO interface of composite devices
+pub mod selection;
+/// HID-IO devices exposed as serial ports (CDC-ACM/UART)
+pub mod serial;
+/// Capture of raw device traffic and replay of captures
+pub mod traffic;
+/// Emulated devices (e.g. browser keyboard emulators) connected over WebSocket
+pub mod websocket;
 
 /// Handles hidapi devices
 ///
 /// Works with both USB and BLE HID devices
+use crate::api::{Endpoint, HidApiInfo};
 use crate::mailbox;
 use hid_io_protocol::*;
 use std::io::{Read, Write};
-use std::time::Instant;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime};
 use tokio::sync::broadcast;
 
 /// A duplex stream for HidIo to communicate over
-pub trait HidIoTransport: Read + Write {}
+///
+/// Read/Write is the main (e.g. interrupt) channel.
+/// Transports may also provide a separate, low-rate, control channel (e.g. feature reports).
+pub trait HidIoTransport: Read + Write + Send {
+    /// Whether a separate control channel is available
+    fn has_control_channel(&self) -> bool {
+        false
+    }
+
+    /// Write a chunk to the control channel
+    fn write_control(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.write(buf)
+    }
+
+    /// Read a chunk from the control channel, returns 0 if nothing is available
+    fn read_control(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+        Ok(0)
+    }
+
+    /// Set the read timeout (ms), 0 returns immediately if nothing was received
+    /// None restores the default timeout of the transport.
+    /// Transports with a fixed timeout ignore this.
+    fn set_read_timeout(&mut self, _timeout: Option<i32>) {}
+}
 
 const MAX_RECV_SIZE: usize = 1024;
 
+/// Maximum number of free buffers kept by a BufferPool
+const POOL_SIZE: usize = 4;
+
+/// Maximum number of chunks to discard when flushing (device may be streaming)
+const MAX_FLUSH_CHUNKS: usize = 256;
+
+/// Undecodable chunks in a row before the device is disconnected (and reopened)
+const MAX_DECODE_ERRORS: u32 = 8;
+
+/// Seconds without traffic before the device is sent a Sync (see quirks::Quirks::sync_interval_s)
+const SYNC_INTERVAL_S: u64 = 5;
+
+/// Time to wait for each resync query response
+const RESYNC_TIMEOUT_MS: u64 = 2000;
+
+/// Wall-clock time passed beyond the monotonic clock that indicates the host was suspended
+/// Monotonic clocks do not advance while suspended (on most platforms), the wall-clock does.
+const RESUME_GAP_S: u64 = 10;
+
+/// Device Sync packets within this time of a host Sync are not treated as a wake event
+/// Neither are Syncs from a device that was not silent for a sync interval (e.g. keepalives).
+const SYNC_HOLDOFF_MS: u64 = 1000;
+
+/// Delay before the first reconnect attempt, doubled after each failed attempt
+const RECONNECT_DELAY_MS: u64 = 100;
+
+/// Upper limit of the reconnect delay
+const RECONNECT_MAX_DELAY_MS: u64 = 5000;
+
+/// Number of reconnect attempts before the device is given up on (left to the next scan)
+const RECONNECT_ATTEMPTS: u32 = 8;
+
+/// Poll interval of devices without a read mode (see polling::ReadMode)
+/// Reads do not block, so an idle device does not occupy a thread of the blocking pool.
+const DEFAULT_POLL_MS: u32 = 8;
+
+/// Interval between link test packets (h0002) used to measure round-trip latency
+const LINK_TEST_INTERVAL_S: u64 = 30;
+
+/// Length of the random token sent in link test packets
+const LINK_TEST_TOKEN_LEN: usize = 8;
+
+/// Optional protocol features supported by hid-io-core
+const CAPABILITIES: u32 = commands::h0004::CAP_CRC16
+    | commands::h0004::CAP_COMPRESSION
+    | commands::h0004::CAP_ENCRYPTION
+    | commands::h0004::CAP_SEQUENCE;
+
+/// Time to wait for the device to accept a pairing key (may require user confirmation)
+pub const PAIR_TIMEOUT_MS: u64 = 30000;
+
+/// Commands that are only sent over trusted (wired) transports or encrypted connections
+const SENSITIVE_IDS: &[HidIoCommandId] = &[
+    HidIoCommandId::FlashMode,
+    HidIoCommandId::SettingsWrite,
+    HidIoCommandId::KllLayoutWrite,
+    HidIoCommandId::FileWrite,
+    HidIoCommandId::TerminalCmd,
+];
+
+/// Commands sent over the control channel (when the transport has one)
+const CONTROL_IDS: &[HidIoCommandId] = &[
+    HidIoCommandId::SupportedIds,
+    HidIoCommandId::GetInfo,
+    HidIoCommandId::ResetHidIo,
+    HidIoCommandId::Capabilities,
+    HidIoCommandId::Pair,
+    HidIoCommandId::SleepMode,
+];
+
+/// Reusable byte buffers for the per-chunk device path
+///
+/// Buffers are taken from the pool and given back once done, so high rate traffic (e.g. pixel
+/// streaming) does not allocate for every chunk read or written.
+#[derive(Default)]
+pub struct BufferPool {
+    free: Vec<Vec<u8>>,
+}
+
+impl BufferPool {
+    /// Take a zeroed buffer of len bytes
+    pub fn take(&mut self, len: usize) -> Vec<u8> {
+        let mut buf = self.free.pop().unwrap_or_default();
+        buf.clear();
+        buf.resize(len, 0);
+        buf
+    }
+
+    /// Give a buffer back, dropped if the pool is full
+    pub fn give(&mut self, buf: Vec<u8>) {
+        if self.free.len() < POOL_SIZE {
+            self.free.push(buf);
+        }
+    }
+}
+
+/// Link health statistics of an endpoint
+///
+/// Shared between the node (API) and the device controller, kept across reconnects.
+#[derive(Clone, Debug, Default)]
+pub struct LinkStats {
+    /// Round-trip time of the last link test packet (h0002)
+    pub latency: Option<Duration>,
+    /// Smoothed round-trip time (1/8 weight for each new sample)
+    pub average_latency: Option<Duration>,
+    /// Naks sent to the device (packets that failed validation)
+    pub naks_sent: u32,
+    /// Naks received from the device
+    pub naks_received: u32,
+    /// Times the device was silent long enough for a Sync to be sent
+    pub sync_timeouts: u32,
+    /// Times the device was reopened after failing
+    pub reconnects: u32,
+    /// Chunks that could not be decoded (discarded)
+    pub decode_errors: u32,
+}
+
+impl LinkStats {
+    /// Add a round-trip time sample
+    pub fn add_latency(&mut self, latency: Duration) {
+        self.latency = Some(latency);
+        self.average_latency = Some(match self.average_latency {
+            Some(average) => (average * 7 + latency) / 8,
+            None => latency,
+        });
+    }
+}
+
 /// A raw transport plus any associated metadata
 ///
 /// Contains helpers to encode/decode HidIo packets
 pub struct HidIoEndpoint {
     socket: Box<dyn HidIoTransport>,
     max_packet_len: u32,
+    control_received: mailbox::HidIoPacketBuffer,
+    crc: bool,
+    compress: bool,
+    sequence: bool,
+    version: u16,
+    capabilities: u32,
+    trusted: bool,
+    pairing: Option<pairing::Pairing>,
+    pool: BufferPool,
+    stats: Arc<RwLock<LinkStats>>,
+    /// Undecodable chunks in a row
+    decode_errors: u32,
+    recorder: Option<traffic::Recorder>,
+    sync_interval: Duration,
+    blocked_ids: Vec<HidIoCommandId>,
 }
 
 impl HidIoEndpoint {
     pub fn new(socket: Box<dyn HidIoTransport>, max_packet_len: u32) -> HidIoEndpoint {
+        let mut control_received = HidIoPacketBuffer::new();
+        control_received.max_len = max_packet_len;
         HidIoEndpoint {
             socket,
             max_packet_len,
+            control_received,
+            crc: false,
+            compress: false,
+            sequence: false,
+            version: 0,
+            capabilities: 0,
+            trusted: false,
+            pairing: None,
+            pool: BufferPool::default(),
+            stats: Arc::new(RwLock::new(LinkStats::default())),
+            decode_errors: 0,
+            recorder: None,
+            sync_interval: Duration::from_secs(SYNC_INTERVAL_S),
+            blocked_ids: vec![],
+        }
+    }
+
+    /// Share link statistics with a node (see Endpoint::link_stats)
+    pub fn set_link_stats(&mut self, stats: Arc<RwLock<LinkStats>>) {
+        self.stats = stats;
+    }
+
+    pub fn link_stats(&self) -> Arc<RwLock<LinkStats>> {
+        self.stats.clone()
+    }
+
+    /// Apply the device quirks that affect the protocol (chunk size, syncs and blocked commands)
+    pub fn apply_quirks(&mut self, quirks: &quirks::Quirks) {
+        self.max_packet_len = quirks.packet_len(self.max_packet_len);
+        self.control_received.max_len = self.max_packet_len;
+        if let Some(interval) = quirks.sync_interval_s {
+            self.sync_interval = Duration::from_secs(interval as u64);
+        }
+        self.blocked_ids = quirks.blocked_ids.to_vec();
+    }
+
+    /// Time without traffic before the device is sent a Sync
+    pub fn sync_interval(&self) -> Duration {
+        self.sync_interval
+    }
+
+    /// Whether the command must never be sent to the device
+    pub fn blocked(&self, id: HidIoCommandId) -> bool {
+        self.blocked_ids.contains(&id)
+    }
+
+    /// Record all chunks and decoded packets to a new capture file
+    /// Returns the path of the capture.
+    pub fn start_capture(
+        &mut self,
+        info: &HidApiInfo,
+    ) -> Result<std::path::PathBuf, std::io::Error> {
+        let recorder = traffic::Recorder::create(&traffic::Header {
+            info: info.clone(),
+            max_packet_len: self.max_packet_len,
+            trusted: self.trusted,
+            control: self.socket.has_control_channel(),
+        })?;
+        let path = recorder.path().to_path_buf();
+        self.recorder = Some(recorder);
+        Ok(path)
+    }
+
+    fn record_chunk(&mut self, event: traffic::Event, chunk: &[u8]) {
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.chunk(event, chunk);
+        }
+    }
+
+    fn record_packet(&mut self, event: traffic::Event, packet: &mailbox::HidIoPacketBuffer) {
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.packet(event, packet);
+        }
+    }
+
+    /// See HidIoTransport::set_read_timeout
+    pub fn set_read_timeout(&mut self, timeout: Option<i32>) {
+        self.socket.set_read_timeout(timeout);
+    }
+
+    /// Transport is trusted (e.g. wired USB)
+    /// Pairing keys are only exchanged over trusted transports, and sensitive commands are only
+    /// sent over untrusted transports (e.g. BLE) if the connection is encrypted.
+    pub fn set_trusted(&mut self, trusted: bool) {
+        self.trusted = trusted;
+    }
+
+    pub fn trusted(&self) -> bool {
+        self.trusted
+    }
+
+    /// Encrypt payloads using the paired key of the device
+    /// Only used if both sides negotiated encryption (h0004).
+    pub fn set_pairing(&mut self, pairing: Option<pairing::Pairing>) {
+        self.pairing = pairing;
+    }
+
+    /// Removes the pairing, payloads are no longer encrypted
+    pub fn take_pairing(&mut self) -> Option<pairing::Pairing> {
+        self.pairing.take()
+    }
+
+    /// Payloads are encrypted
+    pub fn encrypted(&self) -> bool {
+        self.pairing.is_some()
+    }
+
+    /// Store the protocol version and capabilities negotiated with the device (h0004)
+    /// Optional features are enabled only if both sides support them. A version of 0 means
+    /// the device did not negotiate, only the base protocol is used.
+    pub fn set_capabilities(&mut self, version: u16, capabilities: u32) {
+        self.version = std::cmp::min(version, commands::h0004::VERSION);
+        self.capabilities = capabilities & self.supported_capabilities();
+        self.crc = self.capabilities & commands::h0004::CAP_CRC16 != 0;
+        self.compress = self.capabilities & commands::h0004::CAP_COMPRESSION != 0;
+        self.sequence = self.capabilities & commands::h0004::CAP_SEQUENCE != 0;
+    }
+
+    /// Capabilities usable with the chunk size of the device
+    /// Packet options (CRC16, payload offset) are left out if they leave no room for payload.
+    pub fn supported_capabilities(&self) -> u32 {
+        let fits =
+            |crc, sequence| PacketOptions { crc, sequence }.min_packet_len() <= self.max_packet_len;
+        let mut capabilities = CAPABILITIES;
+        if !fits(true, true) {
+            capabilities &= !commands::h0004::CAP_SEQUENCE;
+        }
+        if !fits(true, false) {
+            capabilities &= !(commands::h0004::CAP_CRC16 | commands::h0004::CAP_SEQUENCE);
+        }
+        capabilities
+    }
+
+    /// Negotiated protocol version, 0 if not negotiated
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    /// Negotiated capabilities (h0004::CAP_*)
+    pub fn capabilities(&self) -> u32 {
+        self.capabilities
+    }
+
+    /// Append a CRC16 to each outgoing packet
+    /// Enabled automatically once the device sends a packet with a CRC16.
+    pub fn set_crc(&mut self, crc: bool) {
+        self.crc = crc;
+    }
+
+    pub fn crc(&self) -> bool {
+        self.crc
+    }
+
+    /// Compress payloads of outgoing packets and decompress incoming payloads
+    /// Both sides must have agreed on compression for the connection.
+    pub fn set_compress(&mut self, compress: bool) {
+        self.compress = compress;
+    }
+
+    pub fn compress(&self) -> bool {
+        self.compress
+    }
+
+    /// Largest payload that can be sent
+    /// Compression (encoding byte) and encryption (counter and tag) need room in the payload.
+    pub fn max_payload_len(&self) -> usize {
+        let mut len = self.create_buffer().data.capacity();
+        if self.compress {
+            len -= 1;
+        }
+        if self.encrypted() {
+            len -= crypt::OVERHEAD;
+        }
+        len
+    }
+
+    /// Continued packets carry their payload offset, to detect dropped packets
+    /// Both sides must have agreed on sequencing for the connection.
+    pub fn set_sequence(&mut self, sequence: bool) {
+        self.sequence = sequence;
+    }
+
+    pub fn sequence(&self) -> bool {
+        self.sequence
+    }
+
+    fn options(&self) -> PacketOptions {
+        PacketOptions {
+            crc: self.crc,
+            sequence: self.sequence,
+        }
+    }
+
+    /// Decrypt and decompress a completed packet, if enabled
+    fn decode_payload(
+        &mut self,
+        buffer: &mut mailbox::HidIoPacketBuffer,
+    ) -> Result<(), HidIoParseError> {
+        if !buffer.done || buffer.ptype == HidIoPacketType::Sync {
+            return Ok(());
+        }
+        if let Some(pairing) = self.pairing.as_mut() {
+            pairing.decrypt(buffer)?;
+        }
+        if self.compress {
+            buffer.decompress_payload()?;
+        }
+        Ok(())
+    }
+
+    /// Decode a chunk into a reassembly buffer
+    /// Buffers with missing Continued packets are discarded, Data packets are Nak'd so the
+    /// device can resend them.
+    fn decode_chunk(
+        &mut self,
+        buffer: &mut mailbox::HidIoPacketBuffer,
+        chunk: &[u8],
+    ) -> Result<u32, HidIoParseError> {
+        loop {
+            // Raw id of the buffer in case it is discarded (may be unknown to hid-io-core)
+            let raw_id = buffer.raw_id();
+            let (id, ptype) = match buffer.decode_packet_opts(chunk, self.options()) {
+                Err(HidIoParseError::IncompleteBuffer { id, ptype }) => (id, ptype),
+                Err(HidIoParseError::SequenceGap {
+                    id,
+                    ptype,
+                    expected,
+                    received,
+                }) => {
+                    warn!("Continued packet offset {:?} != {}", received, expected);
+                    (id, ptype)
+                }
+                result => {
+                    return result;
+                }
+            };
+            warn!(
+                "Missing continued packet(s), discarding {} {:?} ({:#x})",
+                ptype, id, raw_id
+            );
+            if ptype == HidIoPacketType::Data {
+                if let Err(e) = self.send_nak(raw_id) {
+                    warn!("Could not nak {:?}: {}", id, e);
+                }
+            }
+
+            // Chunk was not used, decode again
+        }
+    }
+
+    /// Receive a chunk from the control channel
+    /// The control channel has its own reassembly buffer so it may be interleaved with the main
+    /// channel. Returns the packet once complete.
+    pub fn recv_control(&mut self) -> Result<Option<mailbox::HidIoPacketBuffer>, std::io::Error> {
+        if !self.socket.has_control_channel() {
+            return Ok(None);
+        }
+
+        let mut rbuf = self.pool.take(MAX_RECV_SIZE);
+        let len = match self.socket.read_control(&mut rbuf) {
+            Ok(0) => {
+                self.pool.give(rbuf);
+                return Ok(None);
+            }
+            Ok(len) => len,
+            Err(e) => {
+                self.pool.give(rbuf);
+                return Err(e);
+            }
+        };
+        self.record_chunk(traffic::Event::RecvControl, &rbuf[0..len]);
+        let mut control_received = std::mem::take(&mut self.control_received);
+        let result = self.decode_chunk(&mut control_received, &rbuf[0..len]);
+        self.control_received = control_received;
+        if let Err(e) = result {
+            warn!("recv_control({}) {:?} {:x?}", len, e, &rbuf[0..len]);
+            self.pool.give(rbuf);
+            self.control_received = self.create_buffer();
+            return Ok(None);
+        }
+        self.pool.give(rbuf);
+        if !self.control_received.done {
+            return Ok(None);
+        }
+        debug!(
+            "C{} {:x?}",
+            self.control_received.data.len(),
+            self.control_received
+        );
+        let mut buffer = std::mem::replace(&mut self.control_received, self.create_buffer());
+        if let Err(e) = self.decode_payload(&mut buffer) {
+            warn!("recv_control({}) {:?} {:?}", len, buffer.id, e);
+            if buffer.ptype == HidIoPacketType::Data {
+                self.send_nak(buffer.raw_id())?;
+            }
+            return Ok(None);
         }
+        self.record_packet(traffic::Event::PacketRecv, &buffer);
+        Ok(Some(buffer))
+    }
+
+    /// Determine if the packet should be sent over the control channel
+    /// Sync packets always use the main channel as they reset its reassembly state.
+    fn is_control(&self, packet: &mailbox::HidIoPacketBuffer) -> bool {
+        self.socket.has_control_channel()
+            && packet.ptype != HidIoPacketType::Sync
+            && CONTROL_IDS.contains(&packet.id)
     }
 
     pub fn recv_chunk(
         &mut self,
         buffer: &mut mailbox::HidIoPacketBuffer,
     ) -> Result<usize, std::io::Error> {
-        let mut rbuf = [0; MAX_RECV_SIZE];
-        match self.socket.read(&mut rbuf) {
+        let mut rbuf = self.pool.take(MAX_RECV_SIZE);
+        let result = match self.socket.read(&mut rbuf) {
             Ok(len) => {
                 if len > 0 {
-                    let slice = &rbuf[0..len];
-                    let ret = buffer.decode_packet(&slice.to_vec());
-                    if let Err(e) = ret {
-                        error!("recv_chunk({}) {:?}", len, e);
-                        println!("received: {:?}", slice);
-                        println!("current state: {:?}", buffer);
-                        std::process::exit(2);
-                    } else {
-                        debug!("R{} {:x?}", buffer.data.len(), buffer);
-                    }
+                    self.record_chunk(traffic::Event::Recv, &rbuf[0..len]);
+                    self.handle_chunk(buffer, &rbuf[0..len]).map(|_| len)
+                } else {
+                    Ok(len)
                 }
-
-                Ok(len)
             }
             Err(e) => Err(e),
+        };
+        self.pool.give(rbuf);
+        result
+    }
+
+    /// Decode a chunk read by recv_chunk
+    fn handle_chunk(
+        &mut self,
+        buffer: &mut mailbox::HidIoPacketBuffer,
+        slice: &[u8],
+    ) -> Result<(), std::io::Error> {
+        let len = slice.len();
+        let span = tracing::trace_span!("device.decode", len);
+        let _enter = span.enter();
+
+        match self.decode_chunk(buffer, slice) {
+            Ok(_) => {
+                self.decode_errors = 0;
+                if !self.crc
+                    && packet_crc(slice).unwrap_or(false)
+                    && self.supported_capabilities() & commands::h0004::CAP_CRC16 != 0
+                {
+                    info!("Device sends CRC16, enabling for outgoing packets");
+                    self.crc = true;
+                }
+                if let Err(e) = self.decode_payload(buffer) {
+                    warn!("recv_chunk({}) {:?} {:?}", len, buffer.id, e);
+                    let (id, ptype) = (buffer.raw_id(), buffer.ptype);
+                    buffer.clear();
+                    if ptype == HidIoPacketType::Data {
+                        self.send_nak(id)?;
+                    }
+                }
+                debug!("R{} {:x?}", buffer.data.len(), buffer);
+                if buffer.done {
+                    self.record_packet(traffic::Event::PacketRecv, buffer);
+                }
+            }
+            Err(HidIoParseError::CrcMismatch {
+                expected,
+                calculated,
+            }) => {
+                warn!(
+                    "recv_chunk({}) CRC16 mismatch {:04x} != {:04x}, dropping {:?}",
+                    len, expected, calculated, buffer.id
+                );
+                buffer.clear();
+                self.send_decode_nak(slice)?;
+            }
+            Err(e) => {
+                // Start over with an empty buffer, the device resends after the Nak (or Sync)
+                warn!("recv_chunk({}) {:?}, dropping {:x?}", len, e, slice);
+                debug!("current state: {:?}", buffer);
+                buffer.clear();
+                self.stats.write().unwrap().decode_errors += 1;
+                self.decode_errors += 1;
+                if self.decode_errors >= MAX_DECODE_ERRORS {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("{} undecodable chunks in a row", self.decode_errors),
+                    ));
+                }
+                self.send_decode_nak(slice)?;
+            }
         }
+        Ok(())
+    }
+
+    /// Chunk size of the device (i.e. report size)
+    pub fn max_packet_len(&self) -> u32 {
+        self.max_packet_len
     }
 
     pub fn create_buffer(&self) -> mailbox::HidIoPacketBuffer {
@@ -83,25 +657,82 @@ impl HidIoEndpoint {
         &mut self,
         mut packet: mailbox::HidIoPacketBuffer,
     ) -> Result<(), std::io::Error> {
+        let control = self.is_control(&packet);
+        self.record_packet(traffic::Event::PacketSend, &packet);
+        if self.compress && packet.ptype != HidIoPacketType::Sync {
+            packet.compress_payload().map_err(|e| {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{:?}", e))
+            })?;
+        }
+        if let Some(pairing) = self.pairing.as_mut() {
+            pairing.encrypt(&mut packet)?;
+        }
         debug!(
             "Sending {:x?} len:{} chunk:{}",
             packet,
             packet.serialized_len(),
             self.max_packet_len
         );
-        let mut buf: Vec<u8> = Vec::new();
-        buf.resize_with(packet.serialized_len() as usize, Default::default);
-        let buf = packet.serialize_buffer(&mut buf).unwrap().to_vec();
-        for chunk in buf
-            .chunks(self.max_packet_len as usize)
-            .collect::<Vec<&[u8]>>()
-            .iter()
-        {
-            let _i = self.socket.write(chunk)?;
+
+        // Packets are always chunked to the report size of the device
+        packet.max_len = self.max_packet_len;
+        let mut chunk = self.pool.take(self.max_packet_len as usize);
+        let result = self.write_chunks(&packet, &mut chunk, control);
+        self.pool.give(chunk);
+        result
+    }
+
+    /// Serialize one packet at a time, directly into the chunk that is written
+    fn write_chunks(
+        &mut self,
+        packet: &mailbox::HidIoPacketBuffer,
+        chunk: &mut [u8],
+        control: bool,
+    ) -> Result<(), std::io::Error> {
+        let mut offset = Some(0);
+        while let Some(pos) = offset {
+            let result = packet.serialize_chunk_opts(pos, chunk, self.options());
+            let (len, next) = result.map_err(|e| {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{:?}", e))
+            })?;
+            let _i = if control {
+                self.socket.write_control(&chunk[..len])?
+            } else {
+                self.socket.write(&chunk[..len])?
+            };
+            self.record_chunk(
+                if control {
+                    traffic::Event::SendControl
+                } else {
+                    traffic::Event::Send
+                },
+                &chunk[..len],
+            );
+            offset = next;
         }
         Ok(())
     }
 
+    /// Naks a packet that failed validation
+    /// Falls back to a Sync if the Id could not be read.
+    fn send_decode_nak(&mut self, packet_data: &[u8]) -> Result<(), std::io::Error> {
+        match packet_id(packet_data) {
+            Ok(id) => self.send_nak(id),
+            Err(_) => self.send_sync(),
+        }
+    }
+
+    /// Naks a packet without a payload
+    /// Ids unknown to hid-io-core are Nak'd using the raw id.
+    fn send_nak(&mut self, id: u32) -> Result<(), std::io::Error> {
+        self.stats.write().unwrap().naks_sent += 1;
+        let mut packet = self.create_buffer();
+        packet.ptype = HidIoPacketType::Nak;
+        packet.set_raw_id(id);
+        packet.done = true;
+        self.send_packet(packet)
+    }
+
     pub fn send_sync(&mut self) -> Result<(), std::io::Error> {
         self.send_packet(mailbox::HidIoPacketBuffer {
             ptype: HidIoPacketType::Sync,
@@ -109,6 +740,28 @@ impl HidIoEndpoint {
             ..Default::default()
         })
     }
+
+    /// Discard any data waiting to be read from the device
+    /// Returns the number of discarded chunks
+    pub fn flush_recv(&mut self) -> Result<usize, std::io::Error> {
+        let mut rbuf = self.pool.take(MAX_RECV_SIZE);
+        let mut chunks = 0;
+        let mut result = Ok(());
+        while chunks < MAX_FLUSH_CHUNKS {
+            match self.socket.read(&mut rbuf) {
+                Ok(0) => break,
+                Ok(_) => chunks += 1,
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+        self.pool.give(rbuf);
+        result?;
+        self.control_received = self.create_buffer();
+        Ok(chunks)
+    }
 }
 
 /// A R/W channel for a single endpoint
@@ -124,6 +777,13 @@ pub struct HidIoController {
     received: mailbox::HidIoPacketBuffer,
     receiver: broadcast::Receiver<mailbox::Message>,
     last_sync: Instant,
+    last_sync_sent: Instant,
+    /// Last time anything was received from the device
+    last_recv: Instant,
+    last_process: (SystemTime, Instant),
+    last_link_test: Instant,
+    link_test: Option<([u8; LINK_TEST_TOKEN_LEN], Instant)>,
+    serial: Option<String>,
 }
 
 impl HidIoController {
@@ -139,20 +799,464 @@ impl HidIoController {
             received,
             receiver,
             last_sync,
+            last_sync_sent: last_sync,
+            last_recv: last_sync,
+            last_process: (SystemTime::now(), last_sync),
+            last_link_test: last_sync,
+            link_test: None,
+            serial: None,
+        }
+    }
+
+    /// Resynchronize with the device
+    ///
+    /// Used after the device wakes up or the host resumes from suspend.
+    /// 1. Flush any stale data from the device
+    /// 2. Send Sync
+    /// 3. Renegotiate h0004 (Capabilities)
+    /// 4. Re-query h0000 (SupportedIds) and h0001 (GetInfo)
+    /// 5. Verify the device responds (Ack or Nak) to each query
+    ///
+    /// An error is returned if the device does not respond, the caller should treat the device
+    /// as disconnected.
+    pub fn resync(&mut self) -> Result<(), std::io::Error> {
+        info!("Resynchronizing uid:{}", self.uid);
+
+        // Flush
+        let flushed = self.device.flush_recv()?;
+        if flushed > 0 {
+            debug!("Flushed {} stale chunks from uid:{}", flushed, self.uid);
+        }
+        self.received = self.device.create_buffer();
+
+        // Sync
+        self.device.send_sync()?;
+        self.last_sync = Instant::now();
+        self.last_sync_sent = self.last_sync;
+
+        // Capabilities may have changed (e.g. firmware update while suspended)
+        self.negotiate()?;
+
+        // Re-query
+        let queries = [
+            (HidIoCommandId::SupportedIds, vec![]),
+            (
+                HidIoCommandId::GetInfo,
+                vec![commands::h0001::Property::MajorVersion as u8],
+            ),
+        ];
+        for (id, data) in queries.iter() {
+            if self.device.blocked(*id) {
+                continue;
+            }
+            let mut packet = self.device.create_buffer();
+            packet.ptype = HidIoPacketType::Data;
+            packet.id = *id;
+            packet.data = heapless::Vec::from_slice(data).unwrap();
+            packet.done = true;
+            self.device.send_packet(packet)?;
+            self.wait_response(*id, Duration::from_millis(RESYNC_TIMEOUT_MS))?;
+        }
+
+        // Republish the node, the firmware may have been updated while suspended
+        let firmware_version = self.query_firmware_version()?;
+        self.mailbox.update_node(self.uid, |node| {
+            node.set_firmware_version(firmware_version.unwrap_or_default());
+        });
+
+        info!("Resynchronized uid:{}", self.uid);
+        Ok(())
+    }
+
+    /// Wait for an Ack/Nak for the given id
+    /// Any other packets received in the meantime are forwarded to the mailbox.
+    fn wait_response(
+        &mut self,
+        id: HidIoCommandId,
+        timeout: Duration,
+    ) -> Result<mailbox::HidIoPacketBuffer, std::io::Error> {
+        let start = Instant::now();
+        while start.elapsed() < timeout {
+            if self.device.recv_chunk(&mut self.received)? > 0 {
+                self.last_recv = Instant::now();
+            }
+            if !self.received.done {
+                continue;
+            }
+
+            let packet = std::mem::replace(&mut self.received, self.device.create_buffer());
+            if packet.id == id
+                && (packet.ptype == HidIoPacketType::Ack || packet.ptype == HidIoPacketType::Nak)
+            {
+                self.last_sync = Instant::now();
+                return Ok(packet);
+            }
+            if packet.ptype != HidIoPacketType::Sync
+                && !self.handle_link_test(&packet)
+                && !self.handle_capabilities(&packet)?
+            {
+                self.forward(packet);
+            }
+        }
+
+        warn!("uid:{} did not respond to {:?}", self.uid, id);
+        Err(std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            format!("No response to {:?}", id),
+        ))
+    }
+
+    /// Query the serial number of the device (h0001), used to look up its pairing key
+    ///
+    /// Only needed once per connection, before the first negotiate. The serial number is kept
+    /// across resyncs.
+    pub fn identify(&mut self) -> Result<(), std::io::Error> {
+        self.serial = self.query_property(commands::h0001::Property::DeviceSerialNumber)?;
+        Ok(())
+    }
+
+    /// Exchange protocol version and capabilities with the device (h0004)
+    ///
+    /// Must be called right after a Sync, before any optional feature is used.
+    /// Devices that Nak (or do not answer) the request only use the base protocol.
+    /// A stored pairing key is used if both sides support encryption, new keys are only created
+    /// when pairing is requested through the API.
+    pub fn negotiate(&mut self) -> Result<(), std::io::Error> {
+        self.device.set_capabilities(0, 0);
+        self.device.set_pairing(None);
+        if self.device.blocked(HidIoCommandId::Capabilities) {
+            return Ok(());
+        }
+
+        // Paired key is looked up using the serial number, the same for all transports
+        let pairing = self.serial.as_deref().and_then(pairing::Pairing::load);
+        let capabilities = self.local_capabilities(pairing.is_some());
+
+        let mut packet = self.device.create_buffer();
+        packet.ptype = HidIoPacketType::Data;
+        packet.id = HidIoCommandId::Capabilities;
+        packet.append_payload(&commands::h0004::VERSION.to_le_bytes());
+        packet.append_payload(&capabilities.to_le_bytes());
+        packet.done = true;
+        self.device.send_packet(packet)?;
+
+        let packet = match self.wait_response(
+            HidIoCommandId::Capabilities,
+            Duration::from_millis(RESYNC_TIMEOUT_MS),
+        ) {
+            Ok(packet) => packet,
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {
+                return Ok(());
+            }
+            Err(e) => {
+                return Err(e);
+            }
+        };
+        if packet.ptype == HidIoPacketType::Ack {
+            if let Some((version, peer)) = parse_capabilities(&packet.data) {
+                self.device.set_capabilities(version, peer & capabilities);
+            }
+        }
+
+        if self.device.capabilities() & commands::h0004::CAP_ENCRYPTION != 0 {
+            match pairing {
+                Some(pairing) => self.device.set_pairing(Some(pairing)),
+                None => info!(
+                    "uid:{} supports encryption, but has not been paired",
+                    self.uid
+                ),
+            }
+        }
+        info!(
+            "uid:{} protocol version:{} capabilities:{:#x} encrypted:{}",
+            self.uid,
+            self.device.version(),
+            self.device.capabilities(),
+            self.device.encrypted()
+        );
+        Ok(())
+    }
+
+    /// Capabilities advertised to the device
+    /// Encryption is only offered if the device has been paired, or can be paired now.
+    fn local_capabilities(&self, paired: bool) -> u32 {
+        let capabilities = self.device.supported_capabilities();
+        if paired || self.device.trusted() {
+            capabilities
+        } else {
+            capabilities & !commands::h0004::CAP_ENCRYPTION
         }
     }
 
+    /// Query the firmware version of the device (h0001)
+    /// Returns None if the device does not provide one.
+    pub fn query_firmware_version(&mut self) -> Result<Option<String>, std::io::Error> {
+        self.query_property(commands::h0001::Property::FirmwareVersion)
+    }
+
+    /// Query a string property of the device (h0001)
+    /// Returns None if the device does not provide it.
+    fn query_property(
+        &mut self,
+        property: commands::h0001::Property,
+    ) -> Result<Option<String>, std::io::Error> {
+        if self.device.blocked(HidIoCommandId::GetInfo) {
+            return Ok(None);
+        }
+        let mut packet = self.device.create_buffer();
+        packet.ptype = HidIoPacketType::Data;
+        packet.id = HidIoCommandId::GetInfo;
+        packet.append_payload(&[property as u8]);
+        packet.done = true;
+        self.device.send_packet(packet)?;
+
+        let packet = match self.wait_response(
+            HidIoCommandId::GetInfo,
+            Duration::from_millis(RESYNC_TIMEOUT_MS),
+        ) {
+            Ok(packet) => packet,
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {
+                return Ok(None);
+            }
+            Err(e) => {
+                return Err(e);
+            }
+        };
+        if packet.ptype != HidIoPacketType::Ack || packet.data.len() < 2 {
+            return Ok(None);
+        }
+        let value = String::from_utf8_lossy(&packet.data[1..]).to_string();
+        Ok(Some(value).filter(|value| !value.is_empty()))
+    }
+
+    /// Pair with the device (h0005), storing a new key once the device accepts it
+    /// Only possible over trusted transports, with devices that negotiated encryption.
+    /// Returns false if the device could not be paired (or declined).
+    fn pair(&mut self) -> Result<bool, std::io::Error> {
+        if self.device.blocked(HidIoCommandId::Pair) {
+            warn!("Not pairing uid:{}, blocked by device quirks", self.uid);
+            return Ok(false);
+        }
+        if !self.device.trusted() {
+            warn!(
+                "Not pairing uid:{}, connect it over USB to pair it",
+                self.uid
+            );
+            return Ok(false);
+        }
+        if self.device.capabilities() & commands::h0004::CAP_ENCRYPTION == 0 {
+            warn!("Not pairing uid:{}, encryption is not supported", self.uid);
+            return Ok(false);
+        }
+        let serial = match self.serial.as_deref() {
+            Some(serial) => serial,
+            None => {
+                warn!("Not pairing uid:{}, no serial number", self.uid);
+                return Ok(false);
+            }
+        };
+        let mut pairing = match pairing::Pairing::create(serial) {
+            Some(pairing) => pairing,
+            None => {
+                warn!("Could not determine config directory, not pairing");
+                return Ok(false);
+            }
+        };
+
+        info!("Pairing uid:{}, confirm on the device", self.uid);
+        let mut packet = self.device.create_buffer();
+        packet.ptype = HidIoPacketType::Data;
+        packet.id = HidIoCommandId::Pair;
+        packet.append_payload(pairing.key());
+        packet.done = true;
+        self.device.send_packet(packet)?;
+
+        let packet = match self
+            .wait_response(HidIoCommandId::Pair, Duration::from_millis(PAIR_TIMEOUT_MS))
+        {
+            Ok(packet) => packet,
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {
+                return Ok(false);
+            }
+            Err(e) => {
+                return Err(e);
+            }
+        };
+        if packet.ptype != HidIoPacketType::Ack {
+            warn!("uid:{} declined pairing", self.uid);
+            return Ok(false);
+        }
+        pairing.save()?;
+        self.device.set_pairing(Some(pairing));
+        info!("Paired uid:{}", self.uid);
+        Ok(true)
+    }
+
+    /// Pairs the device on request of the API (h0005 sent to the device)
+    /// The key is created here, any payload of the request is ignored. The result is answered
+    /// like a device response.
+    fn handle_pair_request(&mut self, msg: &mailbox::Message) -> Result<(), std::io::Error> {
+        if msg.data.ptype != HidIoPacketType::Data {
+            return Ok(());
+        }
+        let paired = self.pair()?;
+        let mut packet = self.device.create_buffer();
+        packet.ptype = if paired {
+            HidIoPacketType::Ack
+        } else {
+            HidIoPacketType::Nak
+        };
+        packet.id = HidIoCommandId::Pair;
+        packet.done = true;
+        let src = mailbox::Address::DeviceHidio { uid: self.uid };
+        let msg = mailbox::Message::new(src, mailbox::Address::All, packet);
+        self.mailbox.sender.send(msg).ok();
+        Ok(())
+    }
+
+    /// Answers a capabilities request (h0004) sent by the device
+    /// Returns false if the packet is not a capabilities request.
+    fn handle_capabilities(
+        &mut self,
+        packet: &mailbox::HidIoPacketBuffer,
+    ) -> Result<bool, std::io::Error> {
+        if packet.id != HidIoCommandId::Capabilities || packet.ptype != HidIoPacketType::Data {
+            return Ok(false);
+        }
+        let (version, capabilities) = match parse_capabilities(&packet.data) {
+            Some(peer) => peer,
+            None => {
+                warn!("uid:{} invalid capabilities request", self.uid);
+                self.device.send_nak(packet.id)?;
+                return Ok(true);
+            }
+        };
+
+        // Device restarted the exchange (e.g. after a reset), the Ack is sent using the base
+        // protocol and the negotiated features are only enabled afterwards.
+        // The pairing is kept, it is used again if encryption is renegotiated.
+        let pairing = self.device.take_pairing();
+        self.device.set_capabilities(0, 0);
+        let local = self.local_capabilities(pairing.is_some());
+        let mut ack = self.device.create_buffer();
+        ack.ptype = HidIoPacketType::Ack;
+        ack.id = packet.id;
+        ack.append_payload(&commands::h0004::VERSION.to_le_bytes());
+        ack.append_payload(&local.to_le_bytes());
+        ack.done = true;
+        self.device.send_packet(ack)?;
+
+        self.device.set_capabilities(version, capabilities & local);
+        if self.device.capabilities() & commands::h0004::CAP_ENCRYPTION != 0 {
+            self.device.set_pairing(pairing);
+        }
+        info!(
+            "uid:{} protocol version:{} capabilities:{:#x} encrypted:{}",
+            self.uid,
+            self.device.version(),
+            self.device.capabilities(),
+            self.device.encrypted()
+        );
+        Ok(true)
+    }
+
+    /// Send a link test packet (h0002), the Ack is used to measure the round-trip time
+    /// A random token is sent so the Ack is not mistaken for one of an API test packet.
+    fn send_link_test(&mut self) -> Result<(), std::io::Error> {
+        let token: [u8; LINK_TEST_TOKEN_LEN] = rand::random();
+        let mut packet = self.device.create_buffer();
+        packet.ptype = HidIoPacketType::Data;
+        packet.id = HidIoCommandId::TestPacket;
+        packet.append_payload(&token);
+        packet.done = true;
+        self.device.send_packet(packet)?;
+        self.link_test = Some((token, Instant::now()));
+        self.last_link_test = Instant::now();
+        Ok(())
+    }
+
+    /// Records the round-trip time if the packet is the Ack of the pending link test
+    /// Returns false if the packet is not a link test Ack.
+    fn handle_link_test(&mut self, packet: &mailbox::HidIoPacketBuffer) -> bool {
+        if packet.id != HidIoCommandId::TestPacket || packet.ptype != HidIoPacketType::Ack {
+            return false;
+        }
+        match self.link_test {
+            Some((token, sent)) if packet.data[..] == token[..] => {
+                self.link_test = None;
+                self.device
+                    .link_stats()
+                    .write()
+                    .unwrap()
+                    .add_latency(sent.elapsed());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Send a packet received from the device to the mailbox
+    fn forward(&self, packet: mailbox::HidIoPacketBuffer) {
+        if packet.ptype == HidIoPacketType::Nak {
+            self.device.link_stats().write().unwrap().naks_received += 1;
+        }
+
+        let span = tracing::debug_span!(
+            "device.rx",
+            uid = self.uid,
+            id = ?packet.id,
+            ptype = ?packet.ptype,
+            len = packet.data.len()
+        );
+        let _enter = span.enter();
+
+        let src = mailbox::Address::DeviceHidio { uid: self.uid };
+        let dst = mailbox::Address::All;
+        let msg = mailbox::Message::new(src, dst, packet);
+        self.mailbox.sender.send(msg).unwrap();
+    }
+
     pub fn process(&mut self) -> Result<usize, std::io::Error> {
         let mut io_events = 0;
+
+        // Detect host suspend/resume (monotonic clocks may not advance while suspended)
+        // Comparing against the monotonic clock ignores wall-clock changes (e.g. NTP)
+        let now = (SystemTime::now(), Instant::now());
+        let gap = suspend_gap(
+            now.0
+                .duration_since(self.last_process.0)
+                .unwrap_or_else(|_| Duration::from_secs(0)),
+            now.1.duration_since(self.last_process.1),
+        );
+        self.last_process = now;
+        if gap.as_secs() >= RESUME_GAP_S {
+            info!("Host resume detected ({:?} gap)", gap);
+            self.resync()?;
+            return Ok(io_events + 1);
+        }
+
         match self.device.recv_chunk(&mut self.received) {
             Ok(recv) => {
                 if recv > 0 {
                     io_events += 1;
-                    self.last_sync = Instant::now();
+                    let idle = self.last_recv.elapsed();
+                    self.last_recv = Instant::now();
+                    self.last_sync = self.last_recv;
 
                     // Handle sync packets
                     if let HidIoPacketType::Sync = &self.received.ptype {
                         self.received = self.device.create_buffer();
+
+                        // An unsolicited Sync from a silent device indicates it has woken up
+                        if woke_up(
+                            idle,
+                            self.last_sync_sent.elapsed(),
+                            self.device.sync_interval(),
+                        ) {
+                            info!("uid:{} woke up", self.uid);
+                            self.resync()?;
+                            return Ok(io_events);
+                        }
                     }
                 }
             }
@@ -163,33 +1267,110 @@ impl HidIoController {
 
         if self.received.done {
             // Send message to mailbox
-            let src = mailbox::Address::DeviceHidio { uid: self.uid };
-            let dst = mailbox::Address::All;
-            let msg = mailbox::Message::new(src, dst, self.received.clone());
-            self.mailbox.sender.send(msg).unwrap();
-            self.received = self.device.create_buffer();
+            let packet = std::mem::replace(&mut self.received, self.device.create_buffer());
+            if !self.handle_link_test(&packet) && !self.handle_capabilities(&packet)? {
+                self.forward(packet);
+            }
         }
 
-        if self.last_sync.elapsed().as_secs() >= 5 {
+        // Control channel (if available) is processed independently
+        if let Some(packet) = self.device.recv_control()? {
             io_events += 1;
+            if !self.handle_link_test(&packet) && !self.handle_capabilities(&packet)? {
+                self.forward(packet);
+            }
+        }
+
+        if self.last_sync.elapsed() >= self.device.sync_interval() {
+            io_events += 1;
+            self.device.link_stats().write().unwrap().sync_timeouts += 1;
             if self.device.send_sync().is_err() {
                 return Err(std::io::Error::new(std::io::ErrorKind::BrokenPipe, ""));
             };
             self.received = self.device.create_buffer();
             self.last_sync = Instant::now();
+            self.last_sync_sent = self.last_sync;
             return Ok(io_events);
         }
 
+        // Measure the round-trip time periodically (an unanswered test is replaced by the next one)
+        if self.last_link_test.elapsed().as_secs() >= LINK_TEST_INTERVAL_S
+            && !self.device.blocked(HidIoCommandId::TestPacket)
+        {
+            io_events += 1;
+            self.send_link_test()?;
+        }
+
         loop {
             match self.receiver.try_recv() {
-                Ok(mut msg) => {
+                Ok(msg) => {
                     // Only look at packets addressed to this endpoint
                     if msg.dst == (mailbox::Address::DeviceHidio { uid: self.uid }) {
-                        msg.data.max_len = self.device.max_packet_len;
-                        self.device.send_packet(msg.data.clone())?;
+                        let span = tracing::debug_span!(
+                            "device.tx",
+                            uid = self.uid,
+                            src = ?msg.src,
+                            id = ?msg.data.id,
+                            ptype = ?msg.data.ptype,
+                            len = msg.data.data.len()
+                        );
+                        let _enter = span.enter();
+
+                        // Creating a pairing key is only done on request
+                        if msg.data.id == HidIoCommandId::Pair {
+                            self.handle_pair_request(&msg)?;
+                            continue;
+                        }
+
+                        // Some firmware must never receive certain commands (device quirks)
+                        if self.device.blocked(msg.data.id) {
+                            warn!(
+                                "Not sending {:?} to uid:{}, blocked by device quirks",
+                                msg.data.id, self.uid
+                            );
+                            if msg.data.ptype == HidIoPacketType::Data {
+                                msg.send_nak(self.mailbox.sender.clone(), vec![]);
+                            }
+                            continue;
+                        }
+
+                        // Sensitive commands must not be sent in the clear over the air
+                        if SENSITIVE_IDS.contains(&msg.data.id)
+                            && !self.device.trusted()
+                            && !self.device.encrypted()
+                        {
+                            warn!(
+                                "Not sending {:?} to uid:{}, connection is not encrypted",
+                                msg.data.id, self.uid
+                            );
+                            if msg.data.ptype == HidIoPacketType::Data {
+                                msg.send_nak(self.mailbox.sender.clone(), vec![]);
+                            }
+                            continue;
+                        }
 
-                        if msg.data.ptype == HidIoPacketType::Sync {
+                        // Payloads that can't be encoded would fail the connection
+                        if msg.data.data.len() > self.device.max_payload_len() {
+                            warn!(
+                                "Not sending {:?} to uid:{}, payload too large ({} > {} bytes)",
+                                msg.data.id,
+                                self.uid,
+                                msg.data.data.len(),
+                                self.device.max_payload_len()
+                            );
+                            if msg.data.ptype == HidIoPacketType::Data {
+                                msg.send_nak(self.mailbox.sender.clone(), vec![]);
+                            }
+                            continue;
+                        }
+
+                        // The message is owned, send its packet without copying it
+                        let ptype = msg.data.ptype;
+                        self.device.send_packet(msg.data)?;
+
+                        if ptype == HidIoPacketType::Sync {
                             self.received = self.device.create_buffer();
+                            self.last_sync_sent = Instant::now();
                         }
                     }
                 }
@@ -206,6 +1387,29 @@ impl HidIoController {
     }
 }
 
+/// Time the host was suspended, given the wall-clock and monotonic time passed
+fn suspend_gap(wall: Duration, monotonic: Duration) -> Duration {
+    wall.checked_sub(monotonic)
+        .unwrap_or_else(|| Duration::from_secs(0))
+}
+
+/// Whether a Sync from the device indicates it woke up
+/// The device must have been silent for at least a sync interval (i.e. suspended), and the Sync
+/// must not be the answer to a host Sync.
+fn woke_up(idle: Duration, since_sync_sent: Duration, sync_interval: Duration) -> bool {
+    idle >= sync_interval && since_sync_sent >= Duration::from_millis(SYNC_HOLDOFF_MS)
+}
+
+/// Reads the version and capabilities of a h0004 payload
+fn parse_capabilities(data: &[u8]) -> Option<(u16, u32)> {
+    if data.len() < 6 {
+        return None;
+    }
+    let version = u16::from_le_bytes([data[0], data[1]]);
+    let capabilities = u32::from_le_bytes([data[2], data[3], data[4], data[5]]);
+    Some((version, capabilities))
+}
+
 /// Supported Ids by this module
 /// recursive option applies supported ids from child modules as well
 #[allow(unused_variables)]
@@ -234,6 +1438,233 @@ pub fn supported_ids(_recursive: bool) -> Vec<HidIoCommandId> {
     vec![]
 }
 
+/// Runs a connected device until it disconnects (or the daemon quits)
+///
+/// The device is synchronized and its capabilities negotiated first, then the node is added to
+/// the node list (and removed again once the device disconnects).
+pub fn serve(mailbox: mailbox::Mailbox, uid: u64, node: Endpoint, device: HidIoEndpoint) {
+    let rt = mailbox.rt.clone();
+    rt.block_on(serve_task(&mailbox, uid, node, device));
+    mailbox.publish_connection_state(uid, mailbox::ConnectionState::Disconnected);
+}
+
+/// Runs a device as a tokio task, reconnecting it with exponential backoff after it fails
+///
+/// connect is called to (re)open the device, it may update the node (e.g. a new path).
+/// The uid is kept across reconnects. Once RECONNECT_ATTEMPTS attempts in a row have failed
+/// the device is given up on, it is picked up again by the next scan of the device module.
+/// Opening the device blocks, it is run on the blocking pool (as are processing steps, see
+/// serve_task).
+pub async fn supervise<F>(mailbox: mailbox::Mailbox, uid: u64, mut node: Endpoint, mut connect: F)
+where
+    F: FnMut(&mut Endpoint) -> Result<HidIoEndpoint, std::io::Error> + Send + 'static,
+{
+    let mut attempt = 0;
+    let mut delay = Duration::from_millis(RECONNECT_DELAY_MS);
+    let mut served = false;
+    loop {
+        let opened = tokio::task::spawn_blocking(move || {
+            let result = connect(&mut node);
+            (connect, node, result)
+        })
+        .await;
+        let result = match opened {
+            Ok((opened_connect, opened_node, result)) => {
+                connect = opened_connect;
+                node = opened_node;
+                result
+            }
+            Err(e) => {
+                warn!("uid:{} device task failed - {}", uid, e);
+                break;
+            }
+        };
+        match result {
+            Ok(device) => {
+                if served {
+                    node.link_stats().write().unwrap().reconnects += 1;
+                }
+                if serve_task(&mailbox, uid, node.clone(), device).await {
+                    // Device was running, start over with the shortest delay
+                    served = true;
+                    attempt = 0;
+                    delay = Duration::from_millis(RECONNECT_DELAY_MS);
+                }
+            }
+            Err(e) => {
+                warn!("Failed to open uid:{} - {}", uid, e);
+            }
+        }
+
+        if !crate::RUNNING.load(std::sync::atomic::Ordering::SeqCst) {
+            break;
+        }
+        attempt += 1;
+        if attempt > RECONNECT_ATTEMPTS {
+            warn!(
+                "Giving up on uid:{} after {} attempts",
+                uid, RECONNECT_ATTEMPTS
+            );
+            break;
+        }
+        mailbox.publish_connection_state(uid, mailbox::ConnectionState::Reconnecting { attempt });
+        // Wait in short steps so the daemon does not have to wait on the backoff to quit
+        let start = Instant::now();
+        while start.elapsed() < delay && crate::RUNNING.load(std::sync::atomic::Ordering::SeqCst) {
+            tokio::time::sleep(Duration::from_millis(RECONNECT_DELAY_MS)).await;
+        }
+        delay = std::cmp::min(delay * 2, Duration::from_millis(RECONNECT_MAX_DELAY_MS));
+    }
+    mailbox.publish_connection_state(uid, mailbox::ConnectionState::Disconnected);
+}
+
+/// Runs a connected device until it fails (or the daemon quits)
+/// Returns true if the device was synchronized and added to the node list before it stopped.
+///
+/// The controller is handed to the blocking pool for each processing step (a single read, and
+/// any queued outgoing packets). Devices without a read mode use non-blocking reads and are
+/// polled every DEFAULT_POLL_MS, the task sleeps between idle steps so no thread is occupied.
+/// Devices set to ReadMode::Blocking keep a blocking thread busy while waiting on a read.
+async fn serve_task(
+    mailbox: &mailbox::Mailbox,
+    uid: u64,
+    node: Endpoint,
+    device: HidIoEndpoint,
+) -> bool {
+    // Synchronization waits on the device
+    let setup = mailbox.clone();
+    let started = tokio::task::spawn_blocking(move || start(&setup, uid, node, device)).await;
+    let (mut master, read_mode) = match started {
+        Ok(Some(started)) => started,
+        Ok(None) => {
+            return false;
+        }
+        Err(e) => {
+            warn!("uid:{} device task failed - {}", uid, e);
+            stop(mailbox, uid);
+            return false;
+        }
+    };
+    let mut applied = None;
+
+    loop {
+        // Stop processing, daemon trying to quit
+        if !crate::RUNNING.load(std::sync::atomic::Ordering::SeqCst) {
+            break;
+        }
+
+        let mode = read_mode
+            .read()
+            .unwrap()
+            .unwrap_or(polling::ReadMode::Poll(DEFAULT_POLL_MS));
+        if Some(mode) != applied {
+            master.device.set_read_timeout(Some(mode.timeout()));
+            applied = Some(mode);
+        }
+
+        // Process loop for device
+        let step = tokio::task::spawn_blocking(move || {
+            let result = master.process();
+            (master, result)
+        })
+        .await;
+        let result = match step {
+            Ok((step_master, result)) => {
+                master = step_master;
+                result
+            }
+            Err(e) => {
+                warn!("uid:{} device task failed - {}", uid, e);
+                break;
+            }
+        };
+        match result {
+            Ok(0) => {
+                // Idle, wait for the next poll
+                if let Some(delay) = mode.idle_delay() {
+                    tokio::time::sleep(delay).await;
+                }
+            }
+            Ok(_) => {}
+            Err(e) => {
+                info!("{} disconnected ({}). No longer polling it", uid, e);
+                break;
+            }
+        }
+    }
+
+    stop(mailbox, uid);
+    true
+}
+
+/// Synchronizes a connected device and adds its node to the node list
+/// Returns the device controller and the read mode of the node, None if the device could not be
+/// synchronized.
+fn start(
+    mailbox: &mailbox::Mailbox,
+    uid: u64,
+    mut node: Endpoint,
+    mut device: HidIoEndpoint,
+) -> Option<(HidIoController, Arc<RwLock<Option<polling::ReadMode>>>)> {
+    device.set_link_stats(node.link_stats());
+    if traffic::requested(node.hidapi()) {
+        match device.start_capture(node.hidapi()) {
+            Ok(path) => info!("Capturing uid:{} traffic to {:?}", uid, path),
+            Err(e) => warn!("Could not capture uid:{} traffic - {}", uid, e),
+        }
+    }
+
+    // Attempt to synchronize device (sync packet)
+    if let Err(e) = device.send_sync() {
+        // Could not open device (likely removed, or in use)
+        warn!("Failed to sync device - {}", e);
+        return None;
+    }
+
+    // Setup device controller (handles communication and protocol conversion
+    // for the HidIo device)
+    let mut master = HidIoController::new(mailbox.clone(), uid, device);
+
+    // Exchange protocol version and capabilities
+    if let Err(e) = master.identify() {
+        warn!("Failed to query serial number - {}", e);
+    }
+    if let Err(e) = master.negotiate() {
+        warn!("Failed to negotiate capabilities - {}", e);
+    }
+    match master.query_firmware_version() {
+        Ok(version) => node.set_firmware_version(version.unwrap_or_default()),
+        Err(e) => warn!("Failed to query firmware version - {}", e),
+    }
+
+    // Read mode may be changed through the API while the device is running
+    let read_mode = node.read_mode();
+
+    // Add device to node list
+    node.set_max_packet_len(master.device.max_packet_len());
+    mailbox.nodes.write().unwrap().push(node);
+    mailbox.publish_connection_state(uid, mailbox::ConnectionState::Connected);
+    Some((master, read_mode))
+}
+
+/// Removes the node of a stopped device from the node list
+fn stop(mailbox: &mailbox::Mailbox, uid: u64) {
+    let mut nodes = mailbox.nodes.write().unwrap();
+    if let Some(index) = nodes.iter().position(|x| x.uid == uid) {
+        nodes.remove(index);
+    }
+}
+
+/// Key capture initialization (macOS)
+///
+/// Only enabled with the dev-capture feature.
+#[allow(unused_variables)]
+#[cfg(target_os = "macos")]
+async fn capture_initialize(mailbox: mailbox::Mailbox) {
+    #[cfg(feature = "dev-capture")]
+    iokitcapture::initialize(mailbox).await;
+}
+
 /// Module initialization
 ///
 /// # Remarks
@@ -252,15 +1683,76 @@ pub async fn initialize(mailbox: mailbox::Mailbox) {
         hidapi::initialize(mailbox.clone()),
         // Initialize evdev watcher
         evdev::initialize(mailbox.clone()),
+        // Initialize BLE watcher
+        ble::initialize(mailbox.clone()),
+        // Initialize Bluetooth Classic watcher
+        bt::initialize(mailbox.clone()),
+        // Initialize remote device listeners
+        remote::initialize(mailbox.clone()),
+        websocket::initialize(mailbox.clone()),
+        // Initialize serial port watcher
+        serial::initialize(mailbox.clone()),
+    );
+
+    // Initialize BLE watcher
+    #[cfg(all(target_os = "linux", not(feature = "hidapi-devices")))]
+    tokio::join!(
+        ble::initialize(mailbox.clone()),
+        remote::initialize(mailbox.clone()),
+        websocket::initialize(mailbox.clone()),
+        serial::initialize(mailbox.clone()),
     );
 
     // Initialize hidapi watcher
-    #[cfg(all(target_os = "macos", feature = "hidapi-devices"))]
-    hidapi::initialize(mailbox.clone()).await;
+    #[cfg(all(
+        target_os = "macos",
+        feature = "hidapi-devices",
+        not(feature = "iokit-devices")
+    ))]
+    tokio::join!(
+        hidapi::initialize(mailbox.clone()),
+        capture_initialize(mailbox.clone()),
+        remote::initialize(mailbox.clone()),
+        websocket::initialize(mailbox.clone()),
+        serial::initialize(mailbox.clone()),
+    );
+
+    // Initialize IOKit HID manager (replaces the hidapi watcher)
+    #[cfg(all(target_os = "macos", feature = "iokit-devices"))]
+    tokio::join!(
+        iokit::initialize(mailbox.clone()),
+        capture_initialize(mailbox.clone()),
+        remote::initialize(mailbox.clone()),
+        websocket::initialize(mailbox.clone()),
+        serial::initialize(mailbox.clone()),
+    );
+
+    // Initialize hidapi watcher and Raw Input capture
+    #[cfg(all(
+        target_os = "windows",
+        feature = "hidapi-devices",
+        feature = "dev-capture"
+    ))]
+    tokio::join!(
+        hidapi::initialize(mailbox.clone()),
+        rawinput::initialize(mailbox.clone()),
+        remote::initialize(mailbox.clone()),
+        websocket::initialize(mailbox.clone()),
+        serial::initialize(mailbox.clone()),
+    );
 
     // Initialize hidapi watcher
-    #[cfg(all(target_os = "windows", feature = "hidapi-devices"))]
-    hidapi::initialize(mailbox.clone()).await;
+    #[cfg(all(
+        target_os = "windows",
+        feature = "hidapi-devices",
+        not(feature = "dev-capture")
+    ))]
+    tokio::join!(
+        hidapi::initialize(mailbox.clone()),
+        remote::initialize(mailbox.clone()),
+        websocket::initialize(mailbox.clone()),
+        serial::initialize(mailbox.clone()),
+    );
 }
 
 #[cfg(not(feature = "dev-capture"))]
@@ -270,3 +1762,225 @@ mod evdev {
     #[allow(dead_code)]
     pub async fn initialize(_mailbox: mailbox::Mailbox) {}
 }
+
+#[cfg(not(feature = "ble-devices"))]
+mod ble {
+    use crate::mailbox;
+
+    #[allow(dead_code)]
+    pub async fn initialize(_mailbox: mailbox::Mailbox) {}
+}
+
+#[cfg(not(all(target_os = "linux", feature = "bt-devices")))]
+mod bt {
+    use crate::mailbox;
+
+    #[allow(dead_code)]
+    pub async fn initialize(_mailbox: mailbox::Mailbox) {}
+}
+
+#[cfg(not(feature = "remote-devices"))]
+mod remote {
+    use crate::mailbox;
+
+    #[allow(dead_code)]
+    pub async fn initialize(_mailbox: mailbox::Mailbox) {}
+}
+
+#[cfg(not(feature = "websocket-devices"))]
+mod websocket {
+    use crate::mailbox;
+
+    #[allow(dead_code)]
+    pub async fn initialize(_mailbox: mailbox::Mailbox) {}
+}
+
+#[cfg(not(feature = "serial-devices"))]
+mod serial {
+    use crate::mailbox;
+
+    #[allow(dead_code)]
+    pub async fn initialize(_mailbox: mailbox::Mailbox) {}
+}
+
+// ----- Tests -----
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn suspend_gap_test() {
+        // Running normally, both clocks advance together
+        assert_eq!(
+            suspend_gap(Duration::from_millis(510), Duration::from_millis(500)),
+            Duration::from_millis(10)
+        );
+        // Suspended, only the wall-clock advanced
+        assert_eq!(
+            suspend_gap(Duration::from_secs(3600), Duration::from_millis(500)).as_secs(),
+            3599
+        );
+        // Wall-clock moved backwards (e.g. NTP correction)
+        assert_eq!(
+            suspend_gap(Duration::from_secs(0), Duration::from_millis(500)),
+            Duration::from_secs(0)
+        );
+    }
+
+    /// Records written chunks, reads return the queued chunks (then nothing)
+    #[derive(Default)]
+    struct NullTransport {
+        written: Arc<RwLock<Vec<Vec<u8>>>>,
+        recv: std::collections::VecDeque<Vec<u8>>,
+    }
+
+    impl Read for NullTransport {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match self.recv.pop_front() {
+                Some(chunk) => {
+                    buf[..chunk.len()].copy_from_slice(&chunk);
+                    Ok(chunk.len())
+                }
+                None => Ok(0),
+            }
+        }
+    }
+
+    impl Write for NullTransport {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.written.write().unwrap().push(buf.to_vec());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl HidIoTransport for NullTransport {}
+
+    #[test]
+    fn decode_error_test() {
+        let written = Arc::new(RwLock::new(vec![]));
+        let transport = NullTransport {
+            written: written.clone(),
+            ..Default::default()
+        };
+        let mut endpoint = HidIoEndpoint::new(Box::new(transport), 64);
+        let mut buffer = endpoint.create_buffer();
+
+        // Invalid packet type, each chunk is dropped and answered
+        let garbage = [0xFF; 8];
+        for _ in 1..MAX_DECODE_ERRORS {
+            assert!(endpoint.handle_chunk(&mut buffer, &garbage).is_ok());
+        }
+        assert_eq!(written.read().unwrap().len() as u32, MAX_DECODE_ERRORS - 1);
+
+        // Repeated failures disconnect the device
+        assert!(endpoint.handle_chunk(&mut buffer, &garbage).is_err());
+        assert_eq!(
+            endpoint.link_stats().read().unwrap().decode_errors,
+            MAX_DECODE_ERRORS
+        );
+    }
+
+    /// Device renegotiates (e.g. after a reset) without encryption and compression
+    #[test]
+    fn renegotiate_test() {
+        let rt = Arc::new(
+            tokio::runtime::Builder::new_current_thread()
+                .build()
+                .unwrap(),
+        );
+        let written = Arc::new(RwLock::new(vec![]));
+        let transport = NullTransport {
+            written: written.clone(),
+            ..Default::default()
+        };
+        let mut endpoint = HidIoEndpoint::new(Box::new(transport), 64);
+        endpoint.set_trusted(true);
+        endpoint.set_capabilities(commands::h0004::VERSION, CAPABILITIES);
+        endpoint.set_pairing(pairing::Pairing::create("renegotiate-test"));
+        assert!(endpoint.encrypted());
+        assert!(endpoint.compress());
+        let mut master = HidIoController::new(mailbox::Mailbox::new(rt), 1, endpoint);
+
+        let mut request = master.device.create_buffer();
+        request.ptype = HidIoPacketType::Data;
+        request.id = HidIoCommandId::Capabilities;
+        request.append_payload(&commands::h0004::VERSION.to_le_bytes());
+        request.append_payload(&commands::h0004::CAP_CRC16.to_le_bytes());
+        request.done = true;
+        assert!(master.handle_capabilities(&request).unwrap());
+
+        // Ack uses the base protocol (no encoding byte, not encrypted, no CRC16)
+        let mut ack = master.device.create_buffer();
+        ack.ptype = HidIoPacketType::Ack;
+        ack.id = HidIoCommandId::Capabilities;
+        ack.append_payload(&commands::h0004::VERSION.to_le_bytes());
+        ack.append_payload(&CAPABILITIES.to_le_bytes());
+        ack.done = true;
+        let mut chunk = [0; 64];
+        let (len, _) = ack
+            .serialize_chunk_opts(0, &mut chunk, PacketOptions::default())
+            .unwrap();
+        assert_eq!(written.read().unwrap().last().unwrap()[..], chunk[..len]);
+
+        // Only the features the device still supports are used afterwards
+        assert_eq!(master.device.capabilities(), commands::h0004::CAP_CRC16);
+        assert!(master.device.crc());
+        assert!(!master.device.compress());
+        assert!(!master.device.encrypted());
+    }
+
+    #[test]
+    fn woke_up_test() {
+        let interval = Duration::from_secs(SYNC_INTERVAL_S);
+        let holdoff = Duration::from_millis(SYNC_HOLDOFF_MS);
+        // Silent device (e.g. suspended) sends a Sync
+        assert!(woke_up(interval * 2, holdoff * 2, interval));
+        // Answer to a host Sync
+        assert!(!woke_up(interval * 2, holdoff / 2, interval));
+        // Active device (e.g. keepalive)
+        assert!(!woke_up(interval / 5, holdoff * 2, interval));
+    }
+
+    /// Device sending periodic Syncs (e.g. keepalives) is not resynchronized
+    #[test]
+    fn periodic_sync_test() {
+        let rt = Arc::new(
+            tokio::runtime::Builder::new_current_thread()
+                .build()
+                .unwrap(),
+        );
+        let written = Arc::new(RwLock::new(vec![]));
+        let sync = vec![(HidIoPacketType::Sync as u8) << 5];
+        let transport = NullTransport {
+            written: written.clone(),
+            recv: vec![sync; 3].into(),
+        };
+        let endpoint = HidIoEndpoint::new(Box::new(transport), 64);
+        let mut master = HidIoController::new(mailbox::Mailbox::new(rt), 1, endpoint);
+
+        // Last host Sync was long ago, only the device Syncs keep the link active
+        master.last_sync_sent = Instant::now()
+            .checked_sub(Duration::from_secs(SYNC_INTERVAL_S * 2))
+            .unwrap();
+        for _ in 0..3 {
+            assert_eq!(master.process().unwrap(), 1);
+        }
+        // A resync would have flushed and sent a Sync
+        assert!(written.read().unwrap().is_empty());
+    }
+
+    #[test]
+    fn link_stats_latency_test() {
+        let mut stats = LinkStats::default();
+        stats.add_latency(Duration::from_millis(8));
+        assert_eq!(stats.average_latency, Some(Duration::from_millis(8)));
+        stats.add_latency(Duration::from_millis(16));
+        assert_eq!(stats.latency, Some(Duration::from_millis(16)));
+        assert_eq!(stats.average_latency, Some(Duration::from_millis(9)));
+    }
+}
@@ -0,0 +1,243 @@
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * This file is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This file is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this file.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// ----- Crates -----
+
+use crate::mailbox;
+use crate::module::config_path;
+use hid_io_protocol::crypt;
+use hid_io_protocol::{HidIoCommandId, HidIoPacketType};
+use rand::RngCore;
+use std::io::Write;
+use std::path::PathBuf;
+use zeroize::Zeroizing;
+
+// ----- Consts -----
+
+/// Pairing keys are stored in this directory, under the hid-io-core config directory
+const PAIRING_DIR: &str = "pairing";
+
+/// Number of transmit counters reserved ahead of use
+/// The pairing file only needs to be rewritten once per block, a crash skips the remainder.
+const COUNTER_BLOCK: u64 = 1024;
+
+/// Packets that are never encrypted, needed to establish the encrypted connection
+const PLAINTEXT_IDS: &[HidIoCommandId] = &[HidIoCommandId::Capabilities, HidIoCommandId::Pair];
+
+// ----- Structs -----
+
+/// Paired key of a device, plus the message counters used with it
+///
+/// Counters are never reset while the key is in use, they are persisted in the pairing file.
+pub struct Pairing {
+    path: PathBuf,
+    key: Zeroizing<[u8; crypt::KEY_LEN]>,
+    cipher: crypt::PayloadCipher,
+    tx_counter: u64,
+    tx_reserved: u64,
+    rx_counter: Option<u64>,
+    stored: bool,
+}
+
+impl Pairing {
+    /// Location of the pairing file for the given device serial number
+    fn path(serial: &str) -> Option<PathBuf> {
+        let name: String = serial
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        config_path(PAIRING_DIR).map(|dir| dir.join(format!("{}.key", name)))
+    }
+
+    /// Loads the pairing of the device with the given serial number
+    /// Returns None if the device has not been paired (or the file is invalid).
+    pub fn load(serial: &str) -> Option<Pairing> {
+        let path = Pairing::path(serial)?;
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => Zeroizing::new(contents),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return None;
+            }
+            Err(e) => {
+                error!("Could not read {:?}: {}", path, e);
+                return None;
+            }
+        };
+
+        let mut key = None;
+        let mut tx_counter = 0;
+        let mut rx_counter = None;
+        for line in contents.lines() {
+            let mut field = line.trim().splitn(2, '=');
+            match (field.next(), field.next()) {
+                (Some("key"), Some(hex)) => key = parse_key(hex),
+                (Some("tx"), Some(val)) => tx_counter = val.parse().ok()?,
+                (Some("rx"), Some(val)) => rx_counter = Some(val.parse().ok()?),
+                _ => {}
+            }
+        }
+        let key = match key {
+            Some(key) => key,
+            None => {
+                warn!("Invalid pairing file {:?}", path);
+                return None;
+            }
+        };
+
+        let mut pairing = Pairing::new(path, key, tx_counter, rx_counter);
+        if let Err(e) = pairing.reserve() {
+            error!("Could not update {:?}: {}", pairing.path, e);
+            return None;
+        }
+        Some(pairing)
+    }
+
+    /// Creates a new random key for the device with the given serial number
+    /// Nothing is stored until save is called (i.e. once the device has acknowledged the key).
+    pub fn create(serial: &str) -> Option<Pairing> {
+        let path = Pairing::path(serial)?;
+        let mut key = Zeroizing::new([0; crypt::KEY_LEN]);
+        rand::rngs::OsRng.fill_bytes(&mut key[..]);
+        Some(Pairing::new(path, key, 0, None))
+    }
+
+    fn new(
+        path: PathBuf,
+        key: Zeroizing<[u8; crypt::KEY_LEN]>,
+        tx_counter: u64,
+        rx_counter: Option<u64>,
+    ) -> Pairing {
+        let cipher = crypt::PayloadCipher::new(&key);
+        Pairing {
+            path,
+            key,
+            cipher,
+            tx_counter,
+            tx_reserved: tx_counter,
+            rx_counter,
+            stored: false,
+        }
+    }
+
+    pub fn key(&self) -> &[u8; crypt::KEY_LEN] {
+        &self.key
+    }
+
+    /// Writes the pairing file, only readable by the current user
+    pub fn save(&mut self) -> Result<(), std::io::Error> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let contents = Zeroizing::new(format!(
+            "key={}\ntx={}\n{}",
+            self.key
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<String>(),
+            self.tx_reserved,
+            self.rx_counter
+                .map_or_else(String::new, |rx| format!("rx={}\n", rx)),
+        ));
+
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options.open(&self.path)?.write_all(contents.as_bytes())?;
+        self.stored = true;
+        Ok(())
+    }
+
+    /// Reserves the next block of transmit counters
+    fn reserve(&mut self) -> Result<(), std::io::Error> {
+        self.tx_reserved = self.tx_counter + COUNTER_BLOCK;
+        self.save()
+    }
+
+    /// Determine if the packet is sent/received encrypted
+    pub fn is_encrypted(packet: &mailbox::HidIoPacketBuffer) -> bool {
+        packet.ptype != HidIoPacketType::Sync && !PLAINTEXT_IDS.contains(&packet.id)
+    }
+
+    /// Encrypts an outgoing packet (host to device)
+    pub fn encrypt(
+        &mut self,
+        packet: &mut mailbox::HidIoPacketBuffer,
+    ) -> Result<(), std::io::Error> {
+        if !Pairing::is_encrypted(packet) {
+            return Ok(());
+        }
+        if self.tx_counter >= self.tx_reserved {
+            self.reserve()?;
+        }
+        packet
+            .encrypt_payload(
+                &self.cipher,
+                crypt::Direction::HostToDevice,
+                self.tx_counter,
+            )
+            .map_err(|e| {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{:?}", e))
+            })?;
+        self.tx_counter += 1;
+        Ok(())
+    }
+
+    /// Decrypts an incoming packet (device to host)
+    /// Replayed packets are rejected.
+    pub fn decrypt(
+        &mut self,
+        packet: &mut mailbox::HidIoPacketBuffer,
+    ) -> Result<(), hid_io_protocol::HidIoParseError> {
+        if !Pairing::is_encrypted(packet) {
+            return Ok(());
+        }
+        let counter = packet.decrypt_payload(
+            &self.cipher,
+            crypt::Direction::DeviceToHost,
+            self.rx_counter,
+        )?;
+        self.rx_counter = Some(counter);
+        Ok(())
+    }
+}
+
+impl Drop for Pairing {
+    fn drop(&mut self) {
+        // Persist the last received counter so packets can't be replayed after reconnecting
+        if !self.stored {
+            return;
+        }
+        if let Err(e) = self.save() {
+            error!("Could not update {:?}: {}", self.path, e);
+        }
+    }
+}
+
+// ----- Functions -----
+
+/// Parses a hex encoded key
+pub fn parse_key(hex: &str) -> Option<Zeroizing<[u8; crypt::KEY_LEN]>> {
+    let hex = hex.trim();
+    if hex.len() != crypt::KEY_LEN * 2 || !hex.is_ascii() {
+        return None;
+    }
+    let mut key = Zeroizing::new([0; crypt::KEY_LEN]);
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(key)
+}
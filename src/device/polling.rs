@@ -0,0 +1,172 @@
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * This file is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This file is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this file.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// ----- Crates -----
+
+use crate::api::HidApiInfo;
+use crate::device::config::{self, DeviceMatch};
+use lazy_static::lazy_static;
+
+// ----- Consts -----
+
+/// Read mode file name, stored in the hid-io-core config directory
+const POLLING_FILE: &str = "device-polling";
+
+/// Upper limit of read timeouts and poll intervals
+/// Devices are sent a Sync after 5 seconds of silence, reads must return well before that.
+pub const MAX_READ_INTERVAL_MS: u32 = 1000;
+
+lazy_static! {
+    static ref DEVICES: Vec<Device> =
+        config::load(POLLING_FILE, "device read modes", Device::parse);
+}
+
+// ----- Enumerations -----
+
+/// Device read strategy
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReadMode {
+    /// Blocking read, returns after the timeout (ms) if nothing was received
+    /// Lowest latency, queued outgoing packets are sent once the read returns.
+    Blocking(u32),
+    /// Non-blocking read, an idle device is polled at this interval (ms)
+    /// Fewer wakeups (e.g. battery-powered hosts), incoming packets wait for the next poll.
+    Poll(u32),
+}
+
+impl ReadMode {
+    /// Read timeout for the transport, 0 returns immediately
+    pub fn timeout(&self) -> i32 {
+        match *self {
+            ReadMode::Blocking(timeout) => timeout as i32,
+            ReadMode::Poll(_) => 0,
+        }
+    }
+
+    /// Time to wait after a read that returned nothing
+    pub fn idle_delay(&self) -> Option<std::time::Duration> {
+        match *self {
+            ReadMode::Blocking(_) => None,
+            ReadMode::Poll(interval) => Some(std::time::Duration::from_millis(interval as u64)),
+        }
+    }
+
+    /// Checks the timeout or interval is usable
+    pub fn validate(&self) -> Result<(), String> {
+        match *self {
+            ReadMode::Blocking(0) => Err("Blocking read timeout must not be 0".to_string()),
+            ReadMode::Blocking(ms) | ReadMode::Poll(ms) if ms > MAX_READ_INTERVAL_MS => Err(
+                format!("{} ms is longer than {} ms", ms, MAX_READ_INTERVAL_MS),
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+// ----- Structs -----
+
+/// Per-device read mode
+///
+/// vid=<hex>[,pid=<hex>][,serial=<string>] <poll=<ms>|blocking=<ms>>
+///
+/// e.g. vid=1c11,pid=b04d poll=8
+#[derive(Clone, Debug, PartialEq)]
+struct Device {
+    device: DeviceMatch,
+    read_mode: ReadMode,
+}
+
+impl Device {
+    fn parse(line: &str) -> Result<Device, String> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (device, mode) = match fields.as_slice() {
+            [device, mode] => (device, mode),
+            _ => {
+                return Err("Expected vid=<hex>[,...] <poll=<ms>|blocking=<ms>>".to_string());
+            }
+        };
+
+        let device = DeviceMatch::parse(device, |_, _| Ok(false))?.require_vid()?;
+
+        let ms = |val: &str| {
+            val.parse::<u32>()
+                .map_err(|_| format!("Invalid milliseconds '{}'", val))
+        };
+        let mut field = mode.splitn(2, '=');
+        let read_mode = match (field.next(), field.next()) {
+            (Some("poll"), Some(val)) => ReadMode::Poll(ms(val)?),
+            (Some("blocking"), Some(val)) => ReadMode::Blocking(ms(val)?),
+            _ => {
+                return Err(format!("Invalid read mode '{}'", mode));
+            }
+        };
+        read_mode.validate()?;
+
+        Ok(Device { device, read_mode })
+    }
+}
+
+// ----- Functions -----
+
+/// Configured read mode of the device
+/// None uses the default (polled every 8 ms, see device::serve).
+pub fn lookup(info: &HidApiInfo) -> Option<ReadMode> {
+    lookup_table(&DEVICES, info)
+}
+
+fn lookup_table(table: &[Device], info: &HidApiInfo) -> Option<ReadMode> {
+    table
+        .iter()
+        .find(|entry| entry.device.matches(info))
+        .map(|entry| entry.read_mode)
+}
+
+// ----- Tests -----
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_test() {
+        let table = config::parse_lines(
+            "# Comment\n\
+             vid=1c11,pid=b04d poll=8\n\
+             vid=308f blocking=100\n\
+             vid=308f,pid=0011 poll=5000\n\
+             vid=308f,pid=0012 blocking=0\n\
+             vid=308f,pid=0013 sleep=10\n",
+            "test",
+            Device::parse,
+        );
+        assert_eq!(table.len(), 2);
+
+        let info = |vendor_id, product_id| HidApiInfo {
+            vendor_id,
+            product_id,
+            ..Default::default()
+        };
+        assert_eq!(
+            lookup_table(&table, &info(0x1c11, 0xb04d)),
+            Some(ReadMode::Poll(8))
+        );
+        assert_eq!(
+            lookup_table(&table, &info(0x308f, 0x0011)),
+            Some(ReadMode::Blocking(100))
+        );
+        assert_eq!(lookup_table(&table, &info(0x1c11, 0xb04e)), None);
+    }
+}
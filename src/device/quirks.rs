@@ -0,0 +1,351 @@
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * This file is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This file is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this file.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// ----- Crates -----
+
+use crate::device::config::{self, DeviceMatch};
+use hid_io_protocol::HidIoCommandId;
+use lazy_static::lazy_static;
+use std::borrow::Cow;
+use std::convert::TryFrom;
+
+// ----- Enumerations -----
+
+/// Report ID handling for a HID interface
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReportIdMode {
+    /// Interface does not use report ids (most HID-IO firmware)
+    /// An unused 0x00 report id is prepended on write, reads are passed through as-is
+    Unnumbered,
+    /// Interface enumerates with numbered reports
+    /// The report id is prepended on write, and checked + stripped on read
+    /// Windows will reject writes that do not start with the correct report id.
+    Numbered(u8),
+}
+
+/// Control channel used for low-rate HID-IO control exchanges
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ControlChannel {
+    /// No separate control channel, everything uses interrupt reports
+    Interrupt,
+    /// Control exchanges use feature reports with the given report id
+    /// The device must return an all-zero report when it has nothing to send.
+    Feature(u8),
+}
+
+// ----- Structs -----
+
+/// Per-interface device quirks
+#[derive(Clone, Debug, PartialEq)]
+pub struct Quirks {
+    pub report_id: ReportIdMode,
+    /// Open the device exclusively (macOS only, kIOHIDOptionsTypeSeizeDevice)
+    /// Also set for devices listed in the device-exclusive config file.
+    /// Prevents the OS from also interpreting reports from the interface.
+    pub seize: bool,
+    pub control_channel: ControlChannel,
+    /// Seconds without traffic before the device is sent a Sync (None uses the default)
+    /// For firmware that is slow to respond, or treats frequent Syncs as a reset.
+    pub sync_interval_s: Option<u32>,
+    /// Upper limit of the chunk size, for firmware that can not handle full reports
+    pub max_packet_len: Option<u32>,
+    /// Commands that are never sent to the device (e.g. crash the firmware)
+    pub blocked_ids: Cow<'static, [HidIoCommandId]>,
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks {
+            report_id: ReportIdMode::Unnumbered,
+            seize: false,
+            control_channel: ControlChannel::Interrupt,
+            sync_interval_s: None,
+            max_packet_len: None,
+            blocked_ids: Cow::Borrowed(&[]),
+        }
+    }
+}
+
+impl Quirks {
+    /// Chunk size to use, given the report size of the interface
+    pub fn packet_len(&self, max_packet_len: u32) -> u32 {
+        self.max_packet_len
+            .map_or(max_packet_len, |limit| std::cmp::min(limit, max_packet_len))
+    }
+}
+
+/// Quirks table entry
+/// interface set to None matches all interfaces of the device
+struct QuirkEntry {
+    vid: u16,
+    pid: u16,
+    interface: Option<i32>,
+    quirks: Quirks,
+}
+
+/// User quirks, applied on top of the built-in quirks
+///
+/// vid=<hex>,pid=<hex>[,interface=<n>] <quirk> [<quirk> ...]
+///
+/// Quirks: report_id=<hex>, seize, feature_report=<hex>, sync_interval=<s>,
+/// max_packet_len=<bytes>, block=<hex id>[,<hex id>...]
+///
+/// e.g. vid=1c11,pid=b04d sync_interval=15 block=0031,0050
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Override {
+    vid: u16,
+    pid: u16,
+    interface: Option<i32>,
+    report_id: Option<ReportIdMode>,
+    seize: Option<bool>,
+    control_channel: Option<ControlChannel>,
+    sync_interval_s: Option<u32>,
+    max_packet_len: Option<u32>,
+    blocked_ids: Option<Vec<HidIoCommandId>>,
+}
+
+impl Override {
+    fn parse(line: &str) -> Result<Override, String> {
+        let mut fields = line.split_whitespace();
+        let device = fields
+            .next()
+            .ok_or_else(|| "Expected vid=<hex>,pid=<hex> <quirk>...".to_string())?;
+
+        let number = |val: &str| {
+            val.parse::<u32>()
+                .map_err(|_| format!("Invalid number '{}'", val))
+        };
+        let mut entry = Override::default();
+        let (vid, pid) = DeviceMatch::parse(device, |key, val| match key {
+            "interface" => {
+                entry.interface = Some(
+                    val.parse::<i32>()
+                        .map_err(|_| format!("Invalid interface '{}'", val))?,
+                );
+                Ok(true)
+            }
+            _ => Ok(false),
+        })?
+        .require_ids()?;
+        entry.vid = vid;
+        entry.pid = pid;
+
+        for quirk in fields {
+            let mut field = quirk.splitn(2, '=');
+            match (field.next(), field.next()) {
+                (Some("report_id"), Some(val)) => {
+                    entry.report_id = Some(ReportIdMode::Numbered(report_id(val)?))
+                }
+                (Some("seize"), None) => entry.seize = Some(true),
+                (Some("feature_report"), Some(val)) => {
+                    entry.control_channel = Some(ControlChannel::Feature(report_id(val)?))
+                }
+                (Some("sync_interval"), Some(val)) => match number(val)? {
+                    0 => {
+                        return Err("sync_interval must not be 0".to_string());
+                    }
+                    interval => entry.sync_interval_s = Some(interval),
+                },
+                (Some("max_packet_len"), Some(val)) => match number(val)? {
+                    len if len < MIN_PACKET_LEN => {
+                        return Err(format!(
+                            "max_packet_len must be at least {}",
+                            MIN_PACKET_LEN
+                        ));
+                    }
+                    len => entry.max_packet_len = Some(len),
+                },
+                (Some("block"), Some(val)) => {
+                    let mut ids = vec![];
+                    for id in val.split(',') {
+                        let raw = u32::from_str_radix(id, 16)
+                            .map_err(|_| format!("Invalid hex value '{}'", id))?;
+                        ids.push(
+                            HidIoCommandId::try_from(raw)
+                                .map_err(|_| format!("Unknown command id '{}'", id))?,
+                        );
+                    }
+                    entry.blocked_ids = Some(ids);
+                }
+                _ => {
+                    return Err(format!("Invalid quirk '{}'", quirk));
+                }
+            }
+        }
+        Ok(entry)
+    }
+
+    fn matches(&self, vid: u16, pid: u16, interface: i32) -> bool {
+        self.vid == vid && self.pid == pid && self.interface.map_or(true, |i| i == interface)
+    }
+
+    fn apply(&self, quirks: &mut Quirks) {
+        if let Some(report_id) = self.report_id {
+            quirks.report_id = report_id;
+        }
+        if let Some(seize) = self.seize {
+            quirks.seize = seize;
+        }
+        if let Some(control_channel) = self.control_channel {
+            quirks.control_channel = control_channel;
+        }
+        if let Some(sync_interval_s) = self.sync_interval_s {
+            quirks.sync_interval_s = Some(sync_interval_s);
+        }
+        if let Some(max_packet_len) = self.max_packet_len {
+            quirks.max_packet_len = Some(max_packet_len);
+        }
+        if let Some(blocked_ids) = &self.blocked_ids {
+            quirks.blocked_ids = Cow::Owned(blocked_ids.clone());
+        }
+    }
+}
+
+// ----- Consts -----
+
+/// User quirks file name, stored in the hid-io-core config directory
+const QUIRKS_FILE: &str = "device-quirks";
+
+/// Smallest usable chunk size (packet header, 32 bit id and some payload)
+const MIN_PACKET_LEN: u32 = 8;
+
+/// Built-in quirks
+/// Entries are matched in order, the first match is used.
+const QUIRKS: &[QuirkEntry] = &[];
+
+lazy_static! {
+    static ref OVERRIDES: Vec<Override> =
+        config::load(QUIRKS_FILE, "device quirks", Override::parse);
+}
+
+// ----- Functions -----
+
+fn report_id(val: &str) -> Result<u8, String> {
+    match u8::from_str_radix(val, 16) {
+        Ok(0) | Err(_) => Err(format!("Invalid report id '{}'", val)),
+        Ok(id) => Ok(id),
+    }
+}
+
+/// Lookup quirks for the given device interface
+/// Devices without an entry get the default quirks, user quirks take precedence.
+pub fn lookup(vid: u16, pid: u16, interface: i32) -> Quirks {
+    let mut quirks = lookup_table(QUIRKS, vid, pid, interface);
+    apply_overrides(&OVERRIDES, vid, pid, interface, &mut quirks);
+    quirks
+}
+
+fn apply_overrides(
+    overrides: &[Override],
+    vid: u16,
+    pid: u16,
+    interface: i32,
+    quirks: &mut Quirks,
+) {
+    if let Some(entry) = overrides
+        .iter()
+        .find(|entry| entry.matches(vid, pid, interface))
+    {
+        entry.apply(quirks);
+    }
+}
+
+fn lookup_table(table: &[QuirkEntry], vid: u16, pid: u16, interface: i32) -> Quirks {
+    table
+        .iter()
+        .find(|entry| {
+            entry.vid == vid && entry.pid == pid && entry.interface.map_or(true, |i| i == interface)
+        })
+        .map(|entry| entry.quirks.clone())
+        .unwrap_or_default()
+}
+
+// ----- Tests -----
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lookup_test() {
+        let table = [
+            QuirkEntry {
+                vid: 0x1c11,
+                pid: 0xb04d,
+                interface: Some(5),
+                quirks: Quirks {
+                    report_id: ReportIdMode::Numbered(2),
+                    ..Default::default()
+                },
+            },
+            QuirkEntry {
+                vid: 0x1c11,
+                pid: 0xb04d,
+                interface: None,
+                quirks: Quirks {
+                    report_id: ReportIdMode::Numbered(1),
+                    ..Default::default()
+                },
+            },
+        ];
+        assert_eq!(
+            lookup_table(&table, 0x1c11, 0xb04d, 5).report_id,
+            ReportIdMode::Numbered(2)
+        );
+        assert_eq!(
+            lookup_table(&table, 0x1c11, 0xb04d, 3).report_id,
+            ReportIdMode::Numbered(1)
+        );
+        assert_eq!(lookup_table(&table, 0x1c11, 0xb04e, 5), Quirks::default());
+    }
+
+    #[test]
+    fn parse_test() {
+        let overrides = config::parse_lines(
+            "# Comment\n\
+             vid=1c11,pid=b04d,interface=5 sync_interval=15 block=0031,0050\n\
+             vid=1c11,pid=b04d max_packet_len=32 feature_report=3\n\
+             vid=1c11,pid=b04e max_packet_len=4\n\
+             vid=1c11,pid=b04e block=ffff\n\
+             vid=1c11 seize\n",
+            "test",
+            Override::parse,
+        );
+        assert_eq!(overrides.len(), 2);
+
+        // User quirks are applied on top of the built-in quirks
+        let mut quirks = Quirks {
+            report_id: ReportIdMode::Numbered(2),
+            ..Default::default()
+        };
+        apply_overrides(&overrides, 0x1c11, 0xb04d, 5, &mut quirks);
+        assert_eq!(quirks.report_id, ReportIdMode::Numbered(2));
+        assert_eq!(quirks.sync_interval_s, Some(15));
+        assert_eq!(
+            &quirks.blocked_ids[..],
+            &[
+                HidIoCommandId::TerminalCmd,
+                HidIoCommandId::ManufacturingTest
+            ]
+        );
+
+        let mut quirks = Quirks::default();
+        apply_overrides(&overrides, 0x1c11, 0xb04d, 3, &mut quirks);
+        assert_eq!(quirks.control_channel, ControlChannel::Feature(3));
+        assert_eq!(quirks.packet_len(64), 32);
+        assert_eq!(quirks.packet_len(16), 16);
+    }
+}
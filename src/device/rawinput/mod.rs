@@ -0,0 +1,521 @@
+#![cfg(all(feature = "dev-capture", target_os = "windows"))]
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * This file is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This file is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this file.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// ----- Crates -----
+
+use crate::api::common_capnp::NodeType;
+use crate::api::Endpoint;
+use crate::device::capture::{Config, DeviceClass, Keyboard, CONFIG_FILE};
+use crate::mailbox;
+use crate::RUNNING;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use winapi::shared::minwindef::{LPARAM, LRESULT, UINT, WPARAM};
+use winapi::shared::windef::HWND;
+use winapi::um::{libloaderapi, winuser};
+
+// ----- Consts -----
+
+/// Generic Desktop usage page, keyboard usage
+const USAGE_PAGE: u16 = 0x01;
+const USAGE: u16 = 0x06;
+
+/// How often the message loop checks whether the daemon is quitting
+const RUNNING_TIMER_MS: UINT = 1000;
+
+/// Fake shift/overrun events have no virtual key
+const KEYBOARD_OVERRUN_MAKE_CODE: u16 = 0xFF;
+
+thread_local! {
+    static CAPTURE: RefCell<Option<Capture>> = RefCell::new(None);
+}
+
+// ----- Structs -----
+
+/// Capture state, only accessed from the message window thread
+struct Capture {
+    mailbox: mailbox::Mailbox,
+    config: Config,
+    keyboards: HashMap<usize, Keyboard>,
+}
+
+impl Capture {
+    /// Keyboard attached (or already attached when input was registered)
+    fn arrival(&mut self, handle: usize) {
+        let path = match device_name(handle) {
+            Some(path) => path,
+            None => {
+                return;
+            }
+        };
+        let (vid, pid) = device_ids(&path);
+        if !self.config.matches(DeviceClass::Keyboard, vid, pid) {
+            debug!("Not capturing {}", path);
+            return;
+        }
+
+        let key = format!("rawinput vid:{:04x} pid:{:04x} path:{}", vid, pid, path);
+        let uid = match self.mailbox.clone().assign_uid(key, path.clone()) {
+            Ok(uid) => uid,
+            Err(_) => {
+                // Device has already been registered
+                return;
+            }
+        };
+
+        let mut endpoint = Endpoint::new(NodeType::HidKeyboard, uid);
+        endpoint.set_hidio_params(
+            format!("[{:04x}:{:04x}] Raw Input keyboard", vid, pid),
+            path,
+        );
+        info!("Capturing {}", endpoint);
+        self.mailbox.clone().register_node(endpoint);
+        self.keyboards.insert(handle, Keyboard::new(uid));
+    }
+
+    fn removal(&mut self, handle: usize) {
+        if let Some(keyboard) = self.keyboards.remove(&handle) {
+            info!("Disconnection event uid:{}", keyboard.uid);
+            self.mailbox.unregister_node(keyboard.uid);
+        }
+    }
+
+    /// Key pressed/released
+    fn key(&mut self, handle: usize, make_code: u16, flags: u16, vkey: u16) {
+        let keyboard = match self.keyboards.get_mut(&handle) {
+            Some(keyboard) => keyboard,
+            None => {
+                return;
+            }
+        };
+        if make_code == KEYBOARD_OVERRUN_MAKE_CODE || vkey == 0xFF {
+            return;
+        }
+
+        let code = if vkey == winuser::VK_PAUSE as u16 {
+            // Pause is sent as E1 1D (LCtrl), use the virtual key instead
+            Some(0x48)
+        } else {
+            scancode2hid(make_code, flags & winuser::RI_KEY_E0 as u16 != 0)
+        };
+        let code = match code {
+            Some(code) => code,
+            None => {
+                debug!("uid:{} unmapped scancode {:#x}", keyboard.uid, make_code);
+                return;
+            }
+        };
+
+        let pressed = flags & winuser::RI_KEY_BREAK as u16 == 0;
+        keyboard.update(&self.mailbox, code, pressed);
+    }
+
+    /// Unregisters all captured keyboards
+    fn clear(&mut self) {
+        for (_, keyboard) in self.keyboards.drain() {
+            self.mailbox.unregister_node(keyboard.uid);
+        }
+    }
+}
+
+// ----- Functions -----
+
+/// Converts a PC/AT (set 1) scancode into a HID keyboard usage
+fn scancode2hid(code: u16, e0: bool) -> Option<u8> {
+    let usage = if e0 {
+        match code {
+            0x1C => 0x58, // Keypad Enter
+            0x1D => 0xE4, // Right Ctrl
+            0x35 => 0x54, // Keypad /
+            0x37 => 0x46, // Print Screen
+            0x38 => 0xE6, // Right Alt
+            0x47 => 0x4A, // Home
+            0x48 => 0x52, // Up
+            0x49 => 0x4B, // Page Up
+            0x4B => 0x50, // Left
+            0x4D => 0x4F, // Right
+            0x4F => 0x4D, // End
+            0x50 => 0x51, // Down
+            0x51 => 0x4E, // Page Down
+            0x52 => 0x49, // Insert
+            0x53 => 0x4C, // Delete
+            0x5B => 0xE3, // Left GUI
+            0x5C => 0xE7, // Right GUI
+            0x5D => 0x65, // Application
+            _ => {
+                return None;
+            }
+        }
+    } else {
+        match code {
+            0x01 => 0x29,                              // Esc
+            0x02..=0x0A => 0x1E + (code - 0x02) as u8, // 1-9
+            0x0B => 0x27,                              // 0
+            0x0C => 0x2D,                              // -
+            0x0D => 0x2E,                              // =
+            0x0E => 0x2A,                              // Backspace
+            0x0F => 0x2B,                              // Tab
+            0x10 => 0x14,                              // Q
+            0x11 => 0x1A,                              // W
+            0x12 => 0x08,                              // E
+            0x13 => 0x15,                              // R
+            0x14 => 0x17,                              // T
+            0x15 => 0x1C,                              // Y
+            0x16 => 0x18,                              // U
+            0x17 => 0x0C,                              // I
+            0x18 => 0x12,                              // O
+            0x19 => 0x13,                              // P
+            0x1A => 0x2F,                              // [
+            0x1B => 0x30,                              // ]
+            0x1C => 0x28,                              // Enter
+            0x1D => 0xE0,                              // Left Ctrl
+            0x1E => 0x04,                              // A
+            0x1F => 0x16,                              // S
+            0x20 => 0x07,                              // D
+            0x21 => 0x09,                              // F
+            0x22 => 0x0A,                              // G
+            0x23 => 0x0B,                              // H
+            0x24 => 0x0D,                              // J
+            0x25 => 0x0E,                              // K
+            0x26 => 0x0F,                              // L
+            0x27 => 0x33,                              // ;
+            0x28 => 0x34,                              // '
+            0x29 => 0x35,                              // `
+            0x2A => 0xE1,                              // Left Shift
+            0x2B => 0x31,                              // Backslash
+            0x2C => 0x1D,                              // Z
+            0x2D => 0x1B,                              // X
+            0x2E => 0x06,                              // C
+            0x2F => 0x19,                              // V
+            0x30 => 0x05,                              // B
+            0x31 => 0x11,                              // N
+            0x32 => 0x10,                              // M
+            0x33 => 0x36,                              // ,
+            0x34 => 0x37,                              // .
+            0x35 => 0x38,                              // /
+            0x36 => 0xE5,                              // Right Shift
+            0x37 => 0x55,                              // Keypad *
+            0x38 => 0xE2,                              // Left Alt
+            0x39 => 0x2C,                              // Space
+            0x3A => 0x39,                              // Caps Lock
+            0x3B..=0x44 => 0x3A + (code - 0x3B) as u8, // F1-F10
+            0x45 => 0x53,                              // Num Lock
+            0x46 => 0x47,                              // Scroll Lock
+            0x47 => 0x5F,                              // Keypad 7
+            0x48 => 0x60,                              // Keypad 8
+            0x49 => 0x61,                              // Keypad 9
+            0x4A => 0x56,                              // Keypad -
+            0x4B => 0x5C,                              // Keypad 4
+            0x4C => 0x5D,                              // Keypad 5
+            0x4D => 0x5E,                              // Keypad 6
+            0x4E => 0x57,                              // Keypad +
+            0x4F => 0x59,                              // Keypad 1
+            0x50 => 0x5A,                              // Keypad 2
+            0x51 => 0x5B,                              // Keypad 3
+            0x52 => 0x62,                              // Keypad 0
+            0x53 => 0x63,                              // Keypad .
+            0x56 => 0x64,                              // Non-US Backslash
+            0x57 => 0x44,                              // F11
+            0x58 => 0x45,                              // F12
+            0x64..=0x6E => 0x68 + (code - 0x64) as u8, // F13-F23
+            0x70 => 0x88,                              // Katakana/Hiragana
+            0x73 => 0x87,                              // Ro
+            0x76 => 0x73,                              // F24
+            0x79 => 0x8A,                              // Henkan
+            0x7B => 0x8B,                              // Muhenkan
+            0x7D => 0x89,                              // Yen
+            _ => {
+                return None;
+            }
+        }
+    };
+    Some(usage)
+}
+
+/// Raw Input device name (device interface path)
+fn device_name(handle: usize) -> Option<String> {
+    let mut len: UINT = 0;
+    unsafe {
+        winuser::GetRawInputDeviceInfoW(
+            handle as _,
+            winuser::RIDI_DEVICENAME,
+            std::ptr::null_mut(),
+            &mut len,
+        );
+    }
+    if len == 0 {
+        return None;
+    }
+    let mut name = vec![0u16; len as usize];
+    let res = unsafe {
+        winuser::GetRawInputDeviceInfoW(
+            handle as _,
+            winuser::RIDI_DEVICENAME,
+            name.as_mut_ptr() as _,
+            &mut len,
+        )
+    };
+    if res == UINT::MAX {
+        return None;
+    }
+    let end = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+    Some(String::from_utf16_lossy(&name[..end]))
+}
+
+/// Vendor and product id from a device interface path (e.g. \\?\HID#VID_308F&PID_0013&MI_00#...)
+/// Non-USB keyboards (e.g. PS/2) use 0:0
+fn device_ids(path: &str) -> (u16, u16) {
+    let path = path.to_uppercase();
+    let id = |prefix: &str| {
+        path.find(prefix)
+            .and_then(|pos| path.get(pos + prefix.len()..pos + prefix.len() + 4))
+            .and_then(|id| u16::from_str_radix(id, 16).ok())
+            .unwrap_or(0)
+    };
+    (id("VID_"), id("PID_"))
+}
+
+/// Reads a WM_INPUT keyboard event
+unsafe fn input(lparam: LPARAM) {
+    let header_size = std::mem::size_of::<winuser::RAWINPUTHEADER>() as UINT;
+    let mut size: UINT = 0;
+    winuser::GetRawInputData(
+        lparam as _,
+        winuser::RID_INPUT,
+        std::ptr::null_mut(),
+        &mut size,
+        header_size,
+    );
+    if size == 0 {
+        return;
+    }
+
+    // RAWINPUT must be aligned
+    let mut buf = vec![0u64; (size as usize + 7) / 8];
+    if winuser::GetRawInputData(
+        lparam as _,
+        winuser::RID_INPUT,
+        buf.as_mut_ptr() as _,
+        &mut size,
+        header_size,
+    ) != size
+    {
+        return;
+    }
+    let raw = &*(buf.as_ptr() as *const winuser::RAWINPUT);
+    if raw.header.dwType != winuser::RIM_TYPEKEYBOARD {
+        return;
+    }
+    let keyboard = raw.data.keyboard();
+    let handle = raw.header.hDevice as usize;
+    CAPTURE.with(|capture| {
+        if let Some(capture) = capture.borrow_mut().as_mut() {
+            capture.key(handle, keyboard.MakeCode, keyboard.Flags, keyboard.VKey);
+        }
+    });
+}
+
+unsafe extern "system" fn window_proc(
+    hwnd: HWND,
+    msg: UINT,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    match msg {
+        winuser::WM_INPUT => {
+            input(lparam);
+        }
+        winuser::WM_INPUT_DEVICE_CHANGE => {
+            let handle = lparam as usize;
+            CAPTURE.with(|capture| {
+                if let Some(capture) = capture.borrow_mut().as_mut() {
+                    match wparam as u32 {
+                        winuser::GIDC_ARRIVAL => capture.arrival(handle),
+                        winuser::GIDC_REMOVAL => capture.removal(handle),
+                        _ => {}
+                    }
+                }
+            });
+            return 0;
+        }
+        winuser::WM_TIMER => {
+            if !RUNNING.load(Ordering::SeqCst) {
+                winuser::PostQuitMessage(0);
+            }
+            return 0;
+        }
+        _ => {}
+    }
+    // WM_INPUT must also be passed on for cleanup
+    winuser::DefWindowProcW(hwnd, msg, wparam, lparam)
+}
+
+/// Creates a message-only window receiving keyboard Raw Input (also while not in the foreground)
+fn register() -> Result<HWND, String> {
+    let class_name: Vec<u16> = "hid-io-core-rawinput\0".encode_utf16().collect();
+    unsafe {
+        let instance = libloaderapi::GetModuleHandleW(std::ptr::null());
+        let mut class: winuser::WNDCLASSW = std::mem::zeroed();
+        class.lpfnWndProc = Some(window_proc);
+        class.hInstance = instance;
+        class.lpszClassName = class_name.as_ptr();
+        if winuser::RegisterClassW(&class) == 0 {
+            return Err(format!(
+                "RegisterClassW {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+
+        let hwnd = winuser::CreateWindowExW(
+            0,
+            class_name.as_ptr(),
+            class_name.as_ptr(),
+            0,
+            0,
+            0,
+            0,
+            0,
+            winuser::HWND_MESSAGE,
+            std::ptr::null_mut(),
+            instance,
+            std::ptr::null_mut(),
+        );
+        if hwnd.is_null() {
+            return Err(format!(
+                "CreateWindowExW {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+
+        // Arrival notifications are also sent for keyboards that are already attached
+        let device = winuser::RAWINPUTDEVICE {
+            usUsagePage: USAGE_PAGE,
+            usUsage: USAGE,
+            dwFlags: winuser::RIDEV_INPUTSINK | winuser::RIDEV_DEVNOTIFY,
+            hwndTarget: hwnd,
+        };
+        if winuser::RegisterRawInputDevices(
+            &device,
+            1,
+            std::mem::size_of::<winuser::RAWINPUTDEVICE>() as UINT,
+        ) == 0
+        {
+            return Err(format!(
+                "RegisterRawInputDevices {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+
+        winuser::SetTimer(hwnd, 1, RUNNING_TIMER_MS, None);
+        Ok(hwnd)
+    }
+}
+
+/// Raw Input processing
+///
+/// Runs the message loop of the capture window until hid-io-core exits.
+fn processing(mailbox: mailbox::Mailbox, config: Config) {
+    info!("Spawning Raw Input capture thread...");
+
+    CAPTURE.with(|capture| {
+        *capture.borrow_mut() = Some(Capture {
+            mailbox,
+            config,
+            keyboards: HashMap::new(),
+        });
+    });
+
+    let hwnd = match register() {
+        Ok(hwnd) => hwnd,
+        Err(e) => {
+            error!("Could not register for Raw Input, capture disabled - {}", e);
+            return;
+        }
+    };
+
+    let mut msg: winuser::MSG = unsafe { std::mem::zeroed() };
+    while unsafe { winuser::GetMessageW(&mut msg, hwnd, 0, 0) } > 0 {
+        unsafe {
+            winuser::TranslateMessage(&msg);
+            winuser::DispatchMessageW(&msg);
+        }
+    }
+
+    CAPTURE.with(|capture| {
+        if let Some(mut capture) = capture.borrow_mut().take() {
+            capture.clear();
+        }
+    });
+    unsafe {
+        winuser::DestroyWindow(hwnd);
+    }
+}
+
+/// Raw Input initialization
+///
+/// Captures key events from the keyboards selected in the capture-devices file.
+/// Unlike evdev the keyboards are not grabbed, the input is still seen by the OS.
+pub async fn initialize(mailbox: mailbox::Mailbox) {
+    info!("Initializing device/rawinput...");
+
+    let config = match Config::load() {
+        Some(config) => config,
+        None => {
+            info!(
+                "Raw Input capture disabled, create {} in the config directory to enable",
+                CONFIG_FILE
+            );
+            return;
+        }
+    };
+
+    let rt = mailbox.rt.clone();
+    rt.spawn_blocking(move || processing(mailbox, config))
+        .await
+        .unwrap();
+}
+
+// ----- Tests -----
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn scancode2hid_test() {
+        assert_eq!(scancode2hid(0x1E, false), Some(0x04)); // A
+        assert_eq!(scancode2hid(0x0B, false), Some(0x27)); // 0
+        assert_eq!(scancode2hid(0x44, false), Some(0x43)); // F10
+        assert_eq!(scancode2hid(0x1D, true), Some(0xE4)); // Right Ctrl
+        assert_eq!(scancode2hid(0x2A, true), None); // Fake shift
+    }
+
+    #[test]
+    fn device_ids_test() {
+        assert_eq!(
+            device_ids(
+                r"\\?\HID#VID_308F&PID_0013&MI_00#7&1c3e5ac&0&0000#{884b96c3-56ef-11d1-bc8c-00a0c91405dd}"
+            ),
+            (0x308f, 0x0013)
+        );
+        assert_eq!(
+            device_ids(r"\\?\ACPI#MSF0001#4&1e9bd7f4&0#{884b96c3}"),
+            (0, 0)
+        );
+    }
+}
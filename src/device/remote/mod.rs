@@ -0,0 +1,478 @@
+#![cfg(feature = "remote-devices")]
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * This file is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This file is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this file.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// ----- Crates -----
+
+use crate::api::common_capnp::NodeType;
+use crate::api::HidApiInfo;
+use crate::device::pairing::parse_key;
+use crate::device::*;
+use crate::module::config_path;
+use crate::RUNNING;
+use hid_io_protocol::crypt;
+use rand::RngCore;
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::Ordering;
+use zeroize::Zeroizing;
+
+// ----- Consts -----
+
+/// Remote device configuration, stored in the hid-io-core config directory
+/// The listener is only started if this file exists (and contains a key).
+pub const CONFIG_FILE: &str = "remote-devices";
+
+/// Default listen address
+pub const DEFAULT_LISTEN: &str = "0.0.0.0:7186";
+
+/// Largest chunk that can be forwarded
+const MAX_FRAME_SIZE: usize = MAX_RECV_SIZE;
+
+/// Random challenge, <counter:64 bit> <random:128 bit>
+const CHALLENGE_LEN: usize = crypt::COUNTER_LEN + 16;
+
+/// Time allowed for the handshake
+const HANDSHAKE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Read timeout of a connected device
+const TIMEOUT_MS: u64 = 50;
+
+/// How often the listener checks if the daemon is quitting
+const ACCEPT_POLL_MS: u64 = 100;
+
+// ----- Structs -----
+
+/// Remote device configuration
+///
+/// key=<64 hex characters>, pre-shared between hid-io-core and the bridge
+/// listen=<address:port>, only used by hid-io-core
+pub struct Config {
+    pub key: Zeroizing<[u8; crypt::KEY_LEN]>,
+    pub listen: String,
+}
+
+impl Config {
+    /// Location of the configuration file
+    pub fn path() -> Option<std::path::PathBuf> {
+        config_path(CONFIG_FILE)
+    }
+
+    /// Loads the configuration
+    /// Returns None if the file does not exist (or is invalid).
+    pub fn load(path: &std::path::Path) -> Option<Config> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => Zeroizing::new(contents),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return None;
+            }
+            Err(e) => {
+                error!("Could not read {:?}: {}", path, e);
+                return None;
+            }
+        };
+
+        let mut key = None;
+        let mut listen = DEFAULT_LISTEN.to_string();
+        for line in contents.lines() {
+            let mut field = line.trim().splitn(2, '=');
+            match (field.next(), field.next()) {
+                (Some("key"), Some(hex)) => key = parse_key(hex),
+                (Some("listen"), Some(addr)) => listen = addr.trim().to_string(),
+                _ => {}
+            }
+        }
+        match key {
+            Some(key) => Some(Config { key, listen }),
+            None => {
+                warn!("Invalid remote device configuration {:?}", path);
+                None
+            }
+        }
+    }
+}
+
+/// Device information sent by the bridge once authenticated
+///
+/// Sent as a single frame of key=value lines.
+#[derive(Debug, Clone, Default)]
+pub struct Hello {
+    pub info: HidApiInfo,
+    pub max_packet_len: u32,
+}
+
+impl Hello {
+    pub fn encode(&self) -> String {
+        format!(
+            "vid={:04x}\npid={:04x}\nrelease={:04x}\nusage_page={:04x}\nusage={:04x}\n\
+             interface={}\nmanufacturer={}\nproduct={}\nserial={}\npath={}\nport_path={}\n\
+             max_len={}\n",
+            self.info.vendor_id,
+            self.info.product_id,
+            self.info.release_number,
+            self.info.usage_page,
+            self.info.usage,
+            self.info.interface_number,
+            self.info.manufacturer_string.replace('\n', " "),
+            self.info.product_string.replace('\n', " "),
+            self.info.serial_number.replace('\n', " "),
+            self.info.path.replace('\n', " "),
+            self.info.port_path.replace('\n', " "),
+            self.max_packet_len,
+        )
+    }
+
+    pub fn decode(data: &str) -> Result<Hello, std::io::Error> {
+        let mut hello = Hello::default();
+        for line in data.lines() {
+            let mut field = line.splitn(2, '=');
+            let (key, val) = match (field.next(), field.next()) {
+                (Some(key), Some(val)) => (key, val),
+                _ => {
+                    continue;
+                }
+            };
+            let hex = || u16::from_str_radix(val, 16).map_err(invalid_data);
+            match key {
+                "vid" => hello.info.vendor_id = hex()?,
+                "pid" => hello.info.product_id = hex()?,
+                "release" => hello.info.release_number = hex()?,
+                "usage_page" => hello.info.usage_page = hex()?,
+                "usage" => hello.info.usage = hex()?,
+                "interface" => hello.info.interface_number = val.parse().map_err(invalid_data)?,
+                "manufacturer" => hello.info.manufacturer_string = val.to_string(),
+                "product" => hello.info.product_string = val.to_string(),
+                "serial" => hello.info.serial_number = val.to_string(),
+                "path" => hello.info.path = val.to_string(),
+                "port_path" => hello.info.port_path = val.to_string(),
+                "max_len" => hello.max_packet_len = val.parse().map_err(invalid_data)?,
+                _ => {}
+            }
+        }
+        if hello.max_packet_len == 0 || hello.max_packet_len as usize > MAX_FRAME_SIZE {
+            return Err(invalid_data(format!(
+                "Invalid max packet length {}",
+                hello.max_packet_len
+            )));
+        }
+        Ok(hello)
+    }
+}
+
+/// HID-IO chunks forwarded over TCP
+///
+/// Each chunk is sent as a frame, <length:16 bit le> <chunk>.
+/// Reads return a single chunk, or 0 if nothing was received before the read timeout.
+pub struct RemoteDevice {
+    stream: TcpStream,
+    received: Vec<u8>,
+}
+
+impl RemoteDevice {
+    pub fn new(stream: TcpStream) -> RemoteDevice {
+        RemoteDevice {
+            stream,
+            received: Vec::with_capacity(MAX_FRAME_SIZE + 2),
+        }
+    }
+
+    pub fn set_timeout(&self, timeout: std::time::Duration) -> std::io::Result<()> {
+        self.stream.set_read_timeout(Some(timeout))?;
+        self.stream.set_write_timeout(Some(timeout))
+    }
+
+    /// Removes a complete frame from the receive buffer
+    fn take_frame(&mut self, buf: &mut [u8]) -> std::io::Result<Option<usize>> {
+        if self.received.len() < 2 {
+            return Ok(None);
+        }
+        let len = u16::from_le_bytes([self.received[0], self.received[1]]) as usize;
+        if len > MAX_FRAME_SIZE || len > buf.len() {
+            return Err(invalid_data(format!("Frame too large: {}", len)));
+        }
+        if self.received.len() < len + 2 {
+            return Ok(None);
+        }
+        buf[..len].copy_from_slice(&self.received[2..len + 2]);
+        self.received.drain(..len + 2);
+        Ok(Some(len))
+    }
+
+    /// Waits for a complete frame (handshake)
+    fn read_frame(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            if let Some(len) = self.take_frame(buf)? {
+                return Ok(len);
+            }
+            let mut chunk = [0; MAX_FRAME_SIZE];
+            match self.stream.read(&mut chunk)? {
+                0 => {
+                    return Err(std::io::ErrorKind::UnexpectedEof.into());
+                }
+                len => self.received.extend_from_slice(&chunk[..len]),
+            }
+        }
+    }
+
+    /// Server side of the handshake, authenticates the bridge then returns its device information
+    pub fn accept(&mut self, cipher: &crypt::PayloadCipher) -> std::io::Result<Hello> {
+        let mut challenge = [0; CHALLENGE_LEN];
+        rand::rngs::OsRng.fill_bytes(&mut challenge);
+        self.stream.write_all(&challenge)?;
+
+        // <tag> <bridge challenge>
+        let mut response = [0; crypt::TAG_LEN + CHALLENGE_LEN];
+        self.stream.read_exact(&mut response)?;
+        verify(
+            cipher,
+            crypt::Direction::DeviceToHost,
+            &challenge,
+            &response[..crypt::TAG_LEN],
+        )?;
+        let tag = prove(
+            cipher,
+            crypt::Direction::HostToDevice,
+            &response[crypt::TAG_LEN..],
+        )?;
+        self.stream.write_all(&tag)?;
+
+        let mut hello = [0; MAX_FRAME_SIZE];
+        let len = self.read_frame(&mut hello)?;
+        Hello::decode(&String::from_utf8_lossy(&hello[..len]))
+    }
+
+    /// Bridge side of the handshake, authenticates hid-io-core then sends the device information
+    pub fn connect(&mut self, cipher: &crypt::PayloadCipher, hello: &Hello) -> std::io::Result<()> {
+        let mut challenge = [0; CHALLENGE_LEN];
+        self.stream.read_exact(&mut challenge)?;
+
+        let mut response = [0; crypt::TAG_LEN + CHALLENGE_LEN];
+        response[..crypt::TAG_LEN].copy_from_slice(&prove(
+            cipher,
+            crypt::Direction::DeviceToHost,
+            &challenge,
+        )?);
+        rand::rngs::OsRng.fill_bytes(&mut response[crypt::TAG_LEN..]);
+        self.stream.write_all(&response)?;
+
+        let mut tag = [0; crypt::TAG_LEN];
+        self.stream.read_exact(&mut tag)?;
+        verify(
+            cipher,
+            crypt::Direction::HostToDevice,
+            &response[crypt::TAG_LEN..],
+            &tag,
+        )?;
+
+        self.write(hello.encode().as_bytes())?;
+        Ok(())
+    }
+}
+
+impl std::io::Read for RemoteDevice {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if let Some(len) = self.take_frame(buf)? {
+            return Ok(len);
+        }
+        let mut chunk = [0; MAX_FRAME_SIZE];
+        match self.stream.read(&mut chunk) {
+            Ok(0) => Err(std::io::Error::new(
+                std::io::ErrorKind::NotConnected,
+                "Remote device disconnected",
+            )),
+            Ok(len) => {
+                self.received.extend_from_slice(&chunk[..len]);
+                Ok(self.take_frame(buf)?.unwrap_or(0))
+            }
+            Err(e)
+                if e.kind() == std::io::ErrorKind::WouldBlock
+                    || e.kind() == std::io::ErrorKind::TimedOut =>
+            {
+                Ok(0)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl std::io::Write for RemoteDevice {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if buf.len() > MAX_FRAME_SIZE {
+            return Err(invalid_data(format!("Frame too large: {}", buf.len())));
+        }
+        let mut frame = Vec::with_capacity(buf.len() + 2);
+        frame.extend_from_slice(&(buf.len() as u16).to_le_bytes());
+        frame.extend_from_slice(buf);
+        self.stream.write_all(&frame)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl HidIoTransport for RemoteDevice {}
+
+// ----- Functions -----
+
+fn invalid_data<E>(e: E) -> std::io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+}
+
+/// Authentication tag of a challenge
+/// The challenge starts with a random counter, so the nonce is not reused.
+fn prove(
+    cipher: &crypt::PayloadCipher,
+    direction: crypt::Direction,
+    challenge: &[u8],
+) -> std::io::Result<[u8; crypt::TAG_LEN]> {
+    let mut counter = [0; crypt::COUNTER_LEN];
+    counter.copy_from_slice(&challenge[..crypt::COUNTER_LEN]);
+    cipher
+        .encrypt(direction, u64::from_le_bytes(counter), challenge, &mut [])
+        .map_err(|e| invalid_data(format!("{:?}", e)))
+}
+
+fn verify(
+    cipher: &crypt::PayloadCipher,
+    direction: crypt::Direction,
+    challenge: &[u8],
+    tag: &[u8],
+) -> std::io::Result<()> {
+    let mut counter = [0; crypt::COUNTER_LEN];
+    counter.copy_from_slice(&challenge[..crypt::COUNTER_LEN]);
+    cipher
+        .decrypt(
+            direction,
+            u64::from_le_bytes(counter),
+            challenge,
+            &mut [],
+            tag,
+        )
+        .map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "Remote authentication failed",
+            )
+        })
+}
+
+/// Handles a single bridge connection
+fn connection(mailbox: mailbox::Mailbox, cipher: &crypt::PayloadCipher, stream: TcpStream) {
+    let peer = match stream.peer_addr() {
+        Ok(peer) => peer,
+        Err(_) => {
+            return;
+        }
+    };
+    let mut device = RemoteDevice::new(stream);
+    let hello = match device
+        .set_timeout(HANDSHAKE_TIMEOUT)
+        .and_then(|_| device.accept(cipher))
+    {
+        Ok(hello) => hello,
+        Err(e) => {
+            warn!("Rejected remote device {} - {}", peer, e);
+            return;
+        }
+    };
+
+    let mut info = hello.info;
+    info.path = format!("tcp://{}/{}", peer, info.path);
+    let uid = match mailbox.clone().assign_uid(info.key(), info.path.clone()) {
+        Ok(uid) => uid,
+        Err(e) => {
+            warn!("Could not register remote device {} - {}", peer, e);
+            return;
+        }
+    };
+    if let Err(e) = device.set_timeout(std::time::Duration::from_millis(TIMEOUT_MS)) {
+        warn!("Remote device {} - {}", peer, e);
+        return;
+    }
+
+    // Create node
+    let mut node = Endpoint::new(NodeType::UsbKeyboard, uid);
+    node.set_hidapi_params(info);
+    println!("Connected to {} (remote)", node);
+
+    // Network connections are never trusted, pairing keys are not sent over them
+    let mut device = HidIoEndpoint::new(Box::new(device), hello.max_packet_len);
+    device.set_trusted(false);
+    serve(mailbox, uid, node, device);
+}
+
+/// Accepts bridge connections until the daemon quits
+fn listen(mailbox: mailbox::Mailbox, config: Config) -> std::io::Result<()> {
+    let listener = TcpListener::bind(&config.listen)?;
+    listener.set_nonblocking(true)?;
+    info!("Listening for remote devices on {}", config.listen);
+
+    let cipher = std::sync::Arc::new(crypt::PayloadCipher::new(&config.key));
+    while RUNNING.load(Ordering::SeqCst) {
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                std::thread::sleep(std::time::Duration::from_millis(ACCEPT_POLL_MS));
+                continue;
+            }
+            Err(e) => {
+                warn!("Remote device accept failed - {}", e);
+                continue;
+            }
+        };
+        stream.set_nonblocking(false)?;
+        stream.set_nodelay(true)?;
+
+        let mailbox = mailbox.clone();
+        let cipher = cipher.clone();
+        mailbox
+            .rt
+            .clone()
+            .spawn_blocking(move || connection(mailbox, &cipher, stream));
+    }
+    Ok(())
+}
+
+/// Remote device initialization
+///
+/// Listens for bridges forwarding devices attached to other machines (see hid-io-bridge).
+pub async fn initialize(mailbox: mailbox::Mailbox) {
+    info!("Initializing device/remote...");
+
+    let config = match Config::path().and_then(|path| Config::load(&path)) {
+        Some(config) => config,
+        None => {
+            info!(
+                "Remote devices disabled, create {} in the config directory to enable",
+                CONFIG_FILE
+            );
+            return;
+        }
+    };
+
+    let rt = mailbox.rt.clone();
+    rt.spawn_blocking(move || {
+        if let Err(e) = listen(mailbox, config) {
+            error!("Remote device listener failed - {}", e);
+        }
+    })
+    .await
+    .unwrap();
+}
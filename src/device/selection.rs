@@ -0,0 +1,197 @@
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * This file is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This file is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this file.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// ----- Crates -----
+
+use crate::device::config::{self, parse_hex, DeviceMatch};
+use crate::device::descriptor::{Collection, ReportDescriptor};
+use lazy_static::lazy_static;
+
+// ----- Consts -----
+
+/// Interface selection file name, stored in the hid-io-core config directory
+const SELECTION_FILE: &str = "device-interfaces";
+
+/// HID-IO interface usage page and usage
+pub const USAGE_PAGE: u16 = 0xFF1C;
+pub const USAGE: u16 = 0x1100;
+
+/// First vendor-defined usage page
+const VENDOR_USAGE_PAGE: u16 = 0xFF00;
+
+lazy_static! {
+    static ref OVERRIDES: Vec<Override> =
+        config::load(SELECTION_FILE, "interface selection", Override::parse);
+}
+
+// ----- Enumerations -----
+
+/// Selects the HID-IO interface of a (composite) device
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Selection {
+    /// Interface with a top-level collection using this usage page and usage
+    Usage { usage_page: u16, usage: u16 },
+    /// Interface number (not available on macOS)
+    Interface(i32),
+}
+
+impl Default for Selection {
+    fn default() -> Self {
+        Selection::Usage {
+            usage_page: USAGE_PAGE,
+            usage: USAGE,
+        }
+    }
+}
+
+impl Selection {
+    /// HID-IO collection of the interface report descriptor
+    /// Interfaces selected by number use the first vendor-defined collection.
+    pub fn collection<'a>(&self, descriptor: &'a ReportDescriptor) -> Option<&'a Collection> {
+        match *self {
+            Selection::Usage { usage_page, usage } => descriptor.collection(usage_page, usage),
+            Selection::Interface(_) => descriptor
+                .collections
+                .iter()
+                .find(|collection| collection.usage_page >= VENDOR_USAGE_PAGE),
+        }
+    }
+}
+
+// ----- Structs -----
+
+/// Per-device interface selection
+///
+/// vid=<hex>,pid=<hex> <usage_page=<hex>,usage=<hex>|interface=<n>>
+///
+/// e.g. vid=1c11,pid=b04d usage_page=ff31,usage=0200
+#[derive(Clone, Debug, PartialEq)]
+struct Override {
+    vendor_id: u16,
+    product_id: u16,
+    selection: Selection,
+}
+
+impl Override {
+    fn parse(line: &str) -> Result<Override, String> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (device, selection) = match fields.as_slice() {
+            [device, selection] => (device, selection),
+            _ => {
+                return Err("Expected vid=<hex>,pid=<hex> <selection>".to_string());
+            }
+        };
+
+        let (vendor_id, product_id) =
+            DeviceMatch::parse(device, |_, _| Ok(false))?.require_ids()?;
+
+        let mut usage_page = None;
+        let mut usage = None;
+        let mut interface = None;
+        for part in selection.split(',') {
+            let mut field = part.splitn(2, '=');
+            match (field.next(), field.next()) {
+                (Some("usage_page"), Some(val)) => usage_page = Some(parse_hex(val)?),
+                (Some("usage"), Some(val)) => usage = Some(parse_hex(val)?),
+                (Some("interface"), Some(val)) => {
+                    interface = Some(
+                        val.parse::<i32>()
+                            .map_err(|_| format!("Invalid interface '{}'", val))?,
+                    )
+                }
+                _ => {
+                    return Err(format!("Invalid field '{}'", part));
+                }
+            }
+        }
+
+        let selection = match (usage_page, usage, interface) {
+            (Some(usage_page), Some(usage), None) => Selection::Usage { usage_page, usage },
+            (None, None, Some(interface)) => Selection::Interface(interface),
+            _ => {
+                return Err("Expected either usage_page and usage, or interface".to_string());
+            }
+        };
+        Ok(Override {
+            vendor_id,
+            product_id,
+            selection,
+        })
+    }
+}
+
+// ----- Functions -----
+
+/// HID-IO interface selection for the device
+/// Devices without a configured override use the HID-IO usage page and usage.
+pub fn lookup(vid: u16, pid: u16) -> Selection {
+    lookup_table(&OVERRIDES, vid, pid)
+}
+
+/// Devices with a configured usage override (vid, pid, usage_page, usage)
+/// Used by backends that match devices by usage before enumeration (e.g. IOKit).
+pub fn usage_overrides() -> Vec<(u16, u16, u16, u16)> {
+    OVERRIDES
+        .iter()
+        .filter_map(|entry| match entry.selection {
+            Selection::Usage { usage_page, usage } => {
+                Some((entry.vendor_id, entry.product_id, usage_page, usage))
+            }
+            Selection::Interface(_) => None,
+        })
+        .collect()
+}
+
+fn lookup_table(table: &[Override], vid: u16, pid: u16) -> Selection {
+    table
+        .iter()
+        .find(|entry| entry.vendor_id == vid && entry.product_id == pid)
+        .map(|entry| entry.selection)
+        .unwrap_or_default()
+}
+
+// ----- Tests -----
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_test() {
+        let table = config::parse_lines(
+            "# Comment\n\
+             vid=1c11,pid=b04d usage_page=ff31,usage=0200\n\
+             vid=1c11,pid=b04e interface=2\n\
+             vid=1c11 interface=2\n\
+             vid=1c11,pid=b04f usage_page=ff31,interface=2\n",
+            "test",
+            Override::parse,
+        );
+        assert_eq!(table.len(), 2);
+        assert_eq!(
+            lookup_table(&table, 0x1c11, 0xb04d),
+            Selection::Usage {
+                usage_page: 0xff31,
+                usage: 0x0200
+            }
+        );
+        assert_eq!(
+            lookup_table(&table, 0x1c11, 0xb04e),
+            Selection::Interface(2)
+        );
+        assert_eq!(lookup_table(&table, 0x1c11, 0xb04f), Selection::default());
+    }
+}
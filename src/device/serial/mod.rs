@@ -0,0 +1,389 @@
+#![cfg(feature = "serial-devices")]
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * This file is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This file is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this file.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// ----- Crates -----
+
+use crate::api::common_capnp::NodeType;
+use crate::api::HidApiInfo;
+use crate::device::*;
+use crate::module::config_path;
+use crate::RUNNING;
+use std::collections::HashSet;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, RwLock};
+
+// ----- Consts -----
+
+/// Serial device configuration, stored in the hid-io-core config directory
+/// Serial ports are only opened if this file exists (and contains at least one port pattern).
+pub const CONFIG_FILE: &str = "serial-devices";
+
+const DEFAULT_BAUD: u32 = 115_200;
+const DEFAULT_MAX_PACKET_LEN: u32 = 64;
+
+/// Largest chunk that can be received (COBS adds 1 byte per 254)
+const MAX_FRAME_SIZE: usize = MAX_RECV_SIZE + MAX_RECV_SIZE / 254 + 1;
+
+/// COBS frame delimiter
+const DELIMITER: u8 = 0;
+
+/// Read timeout of a connected device
+const TIMEOUT_MS: u64 = 10;
+
+const ENUMERATE_DELAY_MS: u64 = 1000;
+
+// ----- Structs -----
+
+/// Serial device configuration
+///
+/// port=<glob>, may be repeated, e.g. port=/dev/ttyACM* or port=COM5
+/// baud=<baud rate>, ignored by CDC-ACM devices
+/// max_len=<max packet length>
+pub struct Config {
+    pub ports: Vec<glob::Pattern>,
+    pub baud: u32,
+    pub max_packet_len: u32,
+}
+
+impl Config {
+    /// Loads the configuration
+    /// Returns None if the file does not exist (or has no port patterns).
+    pub fn load(path: &std::path::Path) -> Option<Config> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return None;
+            }
+            Err(e) => {
+                error!("Could not read {:?}: {}", path, e);
+                return None;
+            }
+        };
+
+        let mut config = Config {
+            ports: Vec::new(),
+            baud: DEFAULT_BAUD,
+            max_packet_len: DEFAULT_MAX_PACKET_LEN,
+        };
+        for line in contents.lines() {
+            let mut field = line.trim().splitn(2, '=');
+            let (key, val) = match (field.next(), field.next()) {
+                (Some(key), Some(val)) => (key, val.trim()),
+                _ => {
+                    continue;
+                }
+            };
+            match key {
+                "port" => match glob::Pattern::new(val) {
+                    Ok(pattern) => config.ports.push(pattern),
+                    Err(e) => warn!("Invalid serial port pattern {} - {}", val, e),
+                },
+                "baud" => match val.parse() {
+                    Ok(baud) => config.baud = baud,
+                    Err(e) => warn!("Invalid serial baud rate {} - {}", val, e),
+                },
+                "max_len" => match val.parse() {
+                    Ok(len) if len > 0 && len as usize <= MAX_RECV_SIZE => {
+                        config.max_packet_len = len
+                    }
+                    _ => warn!("Invalid serial max packet length {}", val),
+                },
+                _ => {}
+            }
+        }
+
+        if config.ports.is_empty() {
+            warn!("No serial port patterns in {:?}", path);
+            return None;
+        }
+        Some(config)
+    }
+
+    fn matches(&self, port_name: &str) -> bool {
+        self.ports.iter().any(|pattern| pattern.matches(port_name))
+    }
+}
+
+/// HID-IO chunks over a serial port (CDC-ACM or UART)
+///
+/// Each chunk is COBS encoded and terminated with a 0 byte, so the receiver can
+/// resynchronize after dropped or corrupted bytes.
+/// Reads return a single chunk, or 0 if nothing was received before the read timeout.
+pub struct SerialDevice {
+    port: Box<dyn serialport::SerialPort>,
+    received: Vec<u8>,
+}
+
+impl SerialDevice {
+    pub fn new(port: Box<dyn serialport::SerialPort>) -> SerialDevice {
+        SerialDevice {
+            port,
+            received: Vec::with_capacity(MAX_FRAME_SIZE),
+        }
+    }
+
+    /// Removes a complete frame from the receive buffer
+    fn take_frame(&mut self, buf: &mut [u8]) -> Option<usize> {
+        while let Some(end) = self.received.iter().position(|b| *b == DELIMITER) {
+            let len = cobs_decode(&self.received[..end], buf);
+            self.received.drain(..=end);
+            match len {
+                // Empty frames are used to flush the receiver
+                Some(0) => {}
+                Some(len) => {
+                    return Some(len);
+                }
+                None => warn!("Dropping invalid serial frame"),
+            }
+        }
+
+        // Discard garbage (e.g. boot messages) that can never be a valid frame
+        if self.received.len() > MAX_FRAME_SIZE {
+            warn!("Dropping {} bytes of serial data", self.received.len());
+            self.received.clear();
+        }
+        None
+    }
+}
+
+impl std::io::Read for SerialDevice {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if let Some(len) = self.take_frame(buf) {
+            return Ok(len);
+        }
+        let mut chunk = [0; MAX_FRAME_SIZE];
+        match self.port.read(&mut chunk) {
+            Ok(0) => Err(std::io::Error::new(
+                std::io::ErrorKind::NotConnected,
+                "Serial device disconnected",
+            )),
+            Ok(len) => {
+                self.received.extend_from_slice(&chunk[..len]);
+                Ok(self.take_frame(buf).unwrap_or(0))
+            }
+            Err(e)
+                if e.kind() == std::io::ErrorKind::WouldBlock
+                    || e.kind() == std::io::ErrorKind::TimedOut =>
+            {
+                Ok(0)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl std::io::Write for SerialDevice {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut frame = Vec::with_capacity(buf.len() + buf.len() / 254 + 2);
+        cobs_encode(buf, &mut frame);
+        frame.push(DELIMITER);
+        self.port.write_all(&frame)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.port.flush()
+    }
+}
+
+impl HidIoTransport for SerialDevice {}
+
+// ----- Functions -----
+
+/// COBS encodes data (without the trailing delimiter)
+fn cobs_encode(data: &[u8], out: &mut Vec<u8>) {
+    let mut code_pos = out.len();
+    out.push(0);
+    let mut code = 1u8;
+    for byte in data {
+        if *byte == 0 {
+            out[code_pos] = code;
+            code_pos = out.len();
+            out.push(0);
+            code = 1;
+            continue;
+        }
+        out.push(*byte);
+        code += 1;
+        if code == 0xFF {
+            out[code_pos] = code;
+            code_pos = out.len();
+            out.push(0);
+            code = 1;
+        }
+    }
+    out[code_pos] = code;
+}
+
+/// Decodes a COBS frame (without the trailing delimiter)
+/// Returns None if the frame is invalid or does not fit into buf.
+fn cobs_decode(frame: &[u8], buf: &mut [u8]) -> Option<usize> {
+    let mut len = 0;
+    let mut pos = 0;
+    while pos < frame.len() {
+        let code = frame[pos] as usize;
+        if code == 0 || pos + code > frame.len() {
+            return None;
+        }
+        let block = &frame[pos + 1..pos + code];
+        buf.get_mut(len..len + block.len())?.copy_from_slice(block);
+        len += block.len();
+        pos += code;
+
+        // A zero follows every block, except for full blocks and the last one
+        if code < 0xFF && pos < frame.len() {
+            *buf.get_mut(len)? = 0;
+            len += 1;
+        }
+    }
+    Some(len)
+}
+
+fn device_info(port: &serialport::SerialPortInfo) -> HidApiInfo {
+    let mut info = HidApiInfo {
+        path: port.port_name.clone(),
+        ..Default::default()
+    };
+    if let serialport::SerialPortType::UsbPort(usb) = &port.port_type {
+        info.vendor_id = usb.vid;
+        info.product_id = usb.pid;
+        info.serial_number = usb.serial_number.clone().unwrap_or_default();
+        info.manufacturer_string = usb.manufacturer.clone().unwrap_or_default();
+        info.product_string = usb.product.clone().unwrap_or_default();
+    }
+    info
+}
+
+/// Serial device processing
+///
+/// Periodically enumerates serial ports and starts a thread for each newly matched port.
+fn processing(mailbox: mailbox::Mailbox, config: Config) {
+    info!("Spawning serial spawning thread...");
+
+    // List of ports currently in use
+    let ports: Arc<RwLock<HashSet<String>>> = Arc::new(RwLock::new(HashSet::new()));
+    let rt = mailbox.rt.clone();
+
+    while RUNNING.load(Ordering::SeqCst) {
+        let available = match serialport::available_ports() {
+            Ok(available) => available,
+            Err(e) => {
+                warn!("Could not enumerate serial ports - {}", e);
+                Vec::new()
+            }
+        };
+
+        for port_info in available {
+            if !config.matches(&port_info.port_name)
+                || ports.read().unwrap().contains(&port_info.port_name)
+            {
+                continue;
+            }
+
+            let mut info = device_info(&port_info);
+            let uid = match mailbox
+                .clone()
+                .assign_uid(info.key(), port_info.port_name.clone())
+            {
+                Ok(uid) => uid,
+                Err(_) => {
+                    // Device has already been registered, or is invalid
+                    continue;
+                }
+            };
+
+            info!("Connecting to uid:{} {}", uid, port_info.port_name);
+            let port = match serialport::new(&port_info.port_name, config.baud)
+                .timeout(std::time::Duration::from_millis(TIMEOUT_MS))
+                .open()
+            {
+                Ok(port) => port,
+                Err(e) => {
+                    // Could not open port (likely removed, or in use)
+                    warn!("Failed to open {} - {}", port_info.port_name, e);
+                    continue;
+                }
+            };
+
+            // Start thread
+            let port_name = port_info.port_name.clone();
+            ports.write().unwrap().insert(port_name.clone());
+            let ports = ports.clone();
+            let mailbox = mailbox.clone();
+            let max_packet_len = config.max_packet_len;
+            rt.spawn_blocking(move || {
+                // Create node
+                let mut node = Endpoint::new(NodeType::UsbKeyboard, uid);
+                node.set_hidapi_params(info);
+                println!("Connected to {} (serial)", node);
+
+                let device = HidIoEndpoint::new(Box::new(SerialDevice::new(port)), max_packet_len);
+                serve(mailbox, uid, node, device);
+                ports.write().unwrap().remove(&port_name);
+            });
+        }
+
+        std::thread::sleep(std::time::Duration::from_millis(ENUMERATE_DELAY_MS));
+    }
+}
+
+/// Serial device initialization
+///
+/// Sets up a processing thread for HID-IO devices exposed as serial ports.
+pub async fn initialize(mailbox: mailbox::Mailbox) {
+    info!("Initializing device/serial...");
+
+    let config = match config_path(CONFIG_FILE).and_then(|path| Config::load(&path)) {
+        Some(config) => config,
+        None => {
+            info!(
+                "Serial devices disabled, create {} in the config directory to enable",
+                CONFIG_FILE
+            );
+            return;
+        }
+    };
+
+    let rt = mailbox.rt.clone();
+    rt.spawn_blocking(move || processing(mailbox, config))
+        .await
+        .unwrap();
+}
+
+// ----- Tests -----
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cobs_test() {
+        let mut long = vec![0x11; 300];
+        long[254] = 0;
+        let cases: [&[u8]; 5] = [&[], &[0], &[0, 0], &[1, 2, 0, 3], &long];
+        for data in cases.iter() {
+            let mut frame = Vec::new();
+            cobs_encode(data, &mut frame);
+            assert!(!frame.contains(&DELIMITER));
+
+            let mut buf = [0; 512];
+            let len = cobs_decode(&frame, &mut buf).unwrap();
+            assert_eq!(&buf[..len], *data);
+        }
+        assert_eq!(cobs_decode(&[3, 1], &mut [0; 8]), None);
+    }
+}
@@ -0,0 +1,557 @@
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * This file is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This file is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this file.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// ----- Crates -----
+
+use crate::api::common_capnp::NodeType;
+use crate::api::{Endpoint, HidApiInfo};
+use crate::device::config::{self, DeviceMatch};
+use crate::device::{serve, HidIoEndpoint, HidIoTransport};
+use crate::mailbox;
+use crate::module::config_path;
+use lazy_static::lazy_static;
+use std::collections::VecDeque;
+use std::io::{LineWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+// ----- Consts -----
+
+/// Traffic capture file name, stored in the hid-io-core config directory
+const TRAFFIC_FILE: &str = "device-traffic";
+
+/// Directory captures are written to, in the hid-io-core config directory
+const CAPTURE_DIR: &str = "captures";
+
+/// First line of every capture file
+const CAPTURE_MAGIC: &str = "# hid-io-core capture v1";
+
+/// Path prefix of replayed nodes
+pub const REPLAY_SCHEME: &str = "replay://";
+
+/// Default time a replay read waits for the next chunk
+const TIMEOUT_MS: i32 = 10;
+
+lazy_static! {
+    static ref DEVICES: Vec<DeviceMatch> =
+        config::load(TRAFFIC_FILE, "traffic capture list", parse);
+}
+
+// ----- Enumerations -----
+
+/// Kind of capture record
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    /// Chunk read from the device
+    Recv,
+    /// Chunk written to the device
+    Send,
+    /// Chunk read from the control channel
+    RecvControl,
+    /// Chunk written to the control channel
+    SendControl,
+    /// Decoded packet received from the device (informational, not replayed)
+    PacketRecv,
+    /// Packet sent to the device, before compression and encryption (informational)
+    PacketSend,
+}
+
+impl Event {
+    fn name(&self) -> &'static str {
+        match *self {
+            Event::Recv => "rx",
+            Event::Send => "tx",
+            Event::RecvControl => "rx-control",
+            Event::SendControl => "tx-control",
+            Event::PacketRecv => "packet-rx",
+            Event::PacketSend => "packet-tx",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Event> {
+        match name {
+            "rx" => Some(Event::Recv),
+            "tx" => Some(Event::Send),
+            "rx-control" => Some(Event::RecvControl),
+            "tx-control" => Some(Event::SendControl),
+            "packet-rx" => Some(Event::PacketRecv),
+            "packet-tx" => Some(Event::PacketSend),
+            _ => None,
+        }
+    }
+}
+
+// ----- Structs -----
+
+/// Device and link parameters, stored at the start of each capture
+#[derive(Clone, Debug, Default)]
+pub struct Header {
+    pub info: HidApiInfo,
+    pub max_packet_len: u32,
+    /// Transport was trusted (see HidIoEndpoint::set_trusted)
+    pub trusted: bool,
+    /// Transport had a separate control channel
+    pub control: bool,
+}
+
+impl Header {
+    fn encode(&self) -> String {
+        format!(
+            "{}\n# vid={:04x}\n# pid={:04x}\n# release={:04x}\n# usage_page={:04x}\n\
+             # usage={:04x}\n# interface={}\n# manufacturer={}\n# product={}\n# serial={}\n\
+             # port_path={}\n# max_len={}\n# trusted={}\n# control={}\n# started={}\n",
+            CAPTURE_MAGIC,
+            self.info.vendor_id,
+            self.info.product_id,
+            self.info.release_number,
+            self.info.usage_page,
+            self.info.usage,
+            self.info.interface_number,
+            self.info.manufacturer_string.replace('\n', " "),
+            self.info.product_string.replace('\n', " "),
+            self.info.serial_number.replace('\n', " "),
+            self.info.port_path.replace('\n', " "),
+            self.max_packet_len,
+            self.trusted,
+            self.control,
+            chrono::Local::now().to_rfc3339(),
+        )
+    }
+
+    /// Decodes a header line (without the leading #), unknown keys are ignored
+    fn decode(&mut self, line: &str) -> Result<(), String> {
+        let mut field = line.splitn(2, '=');
+        let (key, val) = match (field.next(), field.next()) {
+            (Some(key), Some(val)) => (key, val),
+            _ => {
+                return Ok(());
+            }
+        };
+        let hex = || u16::from_str_radix(val, 16).map_err(|_| format!("Invalid {} '{}'", key, val));
+        let flag = || {
+            val.parse::<bool>()
+                .map_err(|_| format!("Invalid {} '{}'", key, val))
+        };
+        match key {
+            "vid" => self.info.vendor_id = hex()?,
+            "pid" => self.info.product_id = hex()?,
+            "release" => self.info.release_number = hex()?,
+            "usage_page" => self.info.usage_page = hex()?,
+            "usage" => self.info.usage = hex()?,
+            "interface" => {
+                self.info.interface_number = val
+                    .parse()
+                    .map_err(|_| format!("Invalid interface '{}'", val))?
+            }
+            "manufacturer" => self.info.manufacturer_string = val.to_string(),
+            "product" => self.info.product_string = val.to_string(),
+            "serial" => self.info.serial_number = val.to_string(),
+            "port_path" => self.info.port_path = val.to_string(),
+            "max_len" => {
+                self.max_packet_len = val
+                    .parse()
+                    .map_err(|_| format!("Invalid max_len '{}'", val))?
+            }
+            "trusted" => self.trusted = flag()?,
+            "control" => self.control = flag()?,
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Writes the traffic of a device to a capture file
+///
+/// Each line is a record, <seconds since start> <event> <data>.
+/// Chunks are written as hex, decoded packets in their debug representation.
+pub struct Recorder {
+    path: PathBuf,
+    file: Option<LineWriter<std::fs::File>>,
+    start: Instant,
+}
+
+impl Recorder {
+    /// Creates a new, timestamped, capture file in the captures directory
+    pub fn create(header: &Header) -> Result<Recorder, std::io::Error> {
+        let dir = config_path(CAPTURE_DIR).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "Could not determine config directory",
+            )
+        })?;
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join(format!(
+            "{:04x}-{:04x}-{}.capture",
+            header.info.vendor_id,
+            header.info.product_id,
+            chrono::Local::now().format("%Y%m%d-%H%M%S%.3f"),
+        ));
+        let mut file = LineWriter::new(std::fs::File::create(&path)?);
+        file.write_all(header.encode().as_bytes())?;
+        Ok(Recorder {
+            path,
+            file: Some(file),
+            start: Instant::now(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Records a raw chunk
+    pub fn chunk(&mut self, event: Event, data: &[u8]) {
+        let data: String = data.iter().map(|byte| format!("{:02x}", byte)).collect();
+        self.write(event, &data);
+    }
+
+    /// Records a decoded packet
+    pub fn packet(&mut self, event: Event, packet: &mailbox::HidIoPacketBuffer) {
+        self.write(event, &format!("{:x?}", packet));
+    }
+
+    /// Capturing stops (without affecting the device) if the file can no longer be written
+    fn write(&mut self, event: Event, data: &str) {
+        let time = self.start.elapsed();
+        if let Some(file) = self.file.as_mut() {
+            let result = writeln!(
+                file,
+                "{}.{:06} {} {}",
+                time.as_secs(),
+                time.subsec_micros(),
+                event.name(),
+                data
+            );
+            if let Err(e) = result {
+                warn!("Stopped capture {:?} - {}", self.path, e);
+                self.file = None;
+            }
+        }
+    }
+}
+
+/// Parsed capture file
+#[derive(Clone, Debug, Default)]
+pub struct Capture {
+    pub header: Header,
+    /// Chunk records (time since start, event, chunk), decoded packets are skipped
+    records: Vec<(Duration, Event, Vec<u8>)>,
+}
+
+impl Capture {
+    pub fn load(path: &Path) -> Result<Capture, std::io::Error> {
+        let contents = std::fs::read_to_string(path)?;
+        Capture::parse(&contents)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+
+    fn parse(contents: &str) -> Result<Capture, String> {
+        let mut lines = contents.lines().enumerate();
+        match lines.next() {
+            Some((_, CAPTURE_MAGIC)) => {}
+            _ => {
+                return Err("Not a hid-io-core capture".to_string());
+            }
+        }
+
+        let mut capture = Capture::default();
+        for (num, line) in lines {
+            let error = |e: String| format!("{}: {}", num + 1, e);
+            if let Some(line) = line.strip_prefix('#') {
+                capture.header.decode(line.trim()).map_err(error)?;
+                continue;
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let mut field = line.splitn(3, ' ');
+            let (time, event, data) = match (field.next(), field.next(), field.next()) {
+                (Some(time), Some(event), Some(data)) => (time, event, data),
+                _ => {
+                    return Err(error("Expected <time> <event> <data>".to_string()));
+                }
+            };
+            let time = match time.parse::<f64>() {
+                Ok(secs) if secs.is_finite() && secs >= 0.0 => Duration::from_secs_f64(secs),
+                _ => {
+                    return Err(error(format!("Invalid time '{}'", time)));
+                }
+            };
+            let event =
+                Event::parse(event).ok_or_else(|| error(format!("Invalid event '{}'", event)))?;
+            match event {
+                Event::PacketRecv | Event::PacketSend => {}
+                _ => {
+                    capture
+                        .records
+                        .push((time, event, decode_hex(data).map_err(error)?));
+                }
+            }
+        }
+
+        if capture.header.max_packet_len == 0 {
+            return Err("max_len is missing".to_string());
+        }
+        Ok(capture)
+    }
+}
+
+/// Transport that feeds a capture back through the stack
+///
+/// Received chunks are returned at the time they were captured (relative to the start of the
+/// replay). Written chunks are compared against the capture, differences are logged.
+pub struct ReplayDevice {
+    start: Instant,
+    timeout: i32,
+    control: bool,
+    recv: VecDeque<(Duration, Vec<u8>)>,
+    send: VecDeque<Vec<u8>>,
+    recv_control: VecDeque<(Duration, Vec<u8>)>,
+    send_control: VecDeque<Vec<u8>>,
+    finished: bool,
+}
+
+impl ReplayDevice {
+    pub fn new(capture: &Capture) -> ReplayDevice {
+        let mut device = ReplayDevice {
+            start: Instant::now(),
+            timeout: TIMEOUT_MS,
+            control: capture.header.control,
+            recv: VecDeque::new(),
+            send: VecDeque::new(),
+            recv_control: VecDeque::new(),
+            send_control: VecDeque::new(),
+            finished: false,
+        };
+        for (time, event, data) in &capture.records {
+            match event {
+                Event::Recv => device.recv.push_back((*time, data.clone())),
+                Event::Send => device.send.push_back(data.clone()),
+                Event::RecvControl => device.recv_control.push_back((*time, data.clone())),
+                Event::SendControl => device.send_control.push_back(data.clone()),
+                Event::PacketRecv | Event::PacketSend => {}
+            }
+        }
+        device
+    }
+}
+
+impl Read for ReplayDevice {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.recv.is_empty() && !self.finished {
+            info!("Replay finished, device stays idle");
+            self.finished = true;
+        }
+        Ok(replay_read(&mut self.recv, self.start, self.timeout, buf))
+    }
+}
+
+impl Write for ReplayDevice {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        replay_write(&mut self.send, buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl HidIoTransport for ReplayDevice {
+    fn has_control_channel(&self) -> bool {
+        self.control
+    }
+
+    fn write_control(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        replay_write(&mut self.send_control, buf);
+        Ok(buf.len())
+    }
+
+    fn read_control(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        // Never waits, the main channel read handles the timeout
+        Ok(replay_read(&mut self.recv_control, self.start, 0, buf))
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<i32>) {
+        self.timeout = timeout.unwrap_or(TIMEOUT_MS);
+    }
+}
+
+// ----- Functions -----
+
+/// Device whose traffic is captured
+///
+/// vid=<hex>[,pid=<hex>][,serial=<string>]
+///
+/// e.g. vid=1c11,pid=b04d
+fn parse(line: &str) -> Result<DeviceMatch, String> {
+    DeviceMatch::parse(line, |_, _| Ok(false))?.require_vid()
+}
+
+/// Whether the traffic of the device should be captured
+/// Replayed captures are never captured again.
+pub fn requested(info: &HidApiInfo) -> bool {
+    !info.path.starts_with(REPLAY_SCHEME) && DEVICES.iter().any(|device| device.matches(info))
+}
+
+fn decode_hex(data: &str) -> Result<Vec<u8>, String> {
+    if data.len() % 2 != 0 || !data.is_ascii() {
+        return Err(format!("Invalid hex data '{}'", data));
+    }
+    (0..data.len())
+        .step_by(2)
+        .map(|pos| {
+            u8::from_str_radix(&data[pos..pos + 2], 16)
+                .map_err(|_| format!("Invalid hex data '{}'", data))
+        })
+        .collect()
+}
+
+/// Returns the next chunk once it is due, waits at most timeout ms
+fn replay_read(
+    queue: &mut VecDeque<(Duration, Vec<u8>)>,
+    start: Instant,
+    timeout: i32,
+    buf: &mut [u8],
+) -> usize {
+    let wait = Duration::from_millis(timeout.max(0) as u64);
+    let due = match queue.front() {
+        Some((due, _)) => *due,
+        None => {
+            std::thread::sleep(wait);
+            return 0;
+        }
+    };
+    let elapsed = start.elapsed();
+    if due > elapsed {
+        std::thread::sleep(std::cmp::min(due - elapsed, wait));
+        if start.elapsed() < due {
+            return 0;
+        }
+    }
+
+    let (_, chunk) = queue.pop_front().unwrap();
+    let len = std::cmp::min(chunk.len(), buf.len());
+    buf[..len].copy_from_slice(&chunk[..len]);
+    len
+}
+
+/// Compares a written chunk against the next captured one
+fn replay_write(queue: &mut VecDeque<Vec<u8>>, buf: &[u8]) {
+    match queue.pop_front() {
+        Some(expected) if expected == buf => {}
+        Some(expected) => warn!(
+            "Replay diverged, sent {:x?} (captured {:x?})",
+            buf, expected
+        ),
+        None => warn!(
+            "Replay diverged, sent {:x?} after the end of the capture",
+            buf
+        ),
+    }
+}
+
+/// Replays a capture as a new node
+///
+/// The node is served by its own thread until the daemon quits.
+pub fn replay(mailbox: mailbox::Mailbox, path: &Path) -> Result<(), std::io::Error> {
+    let capture = Capture::load(path)?;
+    let mut info = capture.header.info.clone();
+    info.path = format!("{}{}", REPLAY_SCHEME, path.display());
+    let uid = mailbox.clone().assign_uid(info.key(), info.path.clone())?;
+
+    std::thread::spawn(move || {
+        let mut node = Endpoint::new(NodeType::UsbKeyboard, uid);
+        node.set_hidapi_params(info);
+        println!("Connected to {} (replay)", node);
+
+        let mut device = HidIoEndpoint::new(
+            Box::new(ReplayDevice::new(&capture)),
+            capture.header.max_packet_len,
+        );
+        device.set_trusted(capture.header.trusted);
+        serve(mailbox, uid, node, device);
+    });
+    Ok(())
+}
+
+// ----- Tests -----
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_test() {
+        let devices = config::parse_lines(
+            "# Comment\n\
+             vid=1c11,pid=b04d\n\
+             pid=b04d\n",
+            "test",
+            parse,
+        );
+        assert_eq!(devices.len(), 1);
+
+        let mut info = HidApiInfo {
+            vendor_id: 0x1c11,
+            product_id: 0xb04d,
+            ..Default::default()
+        };
+        assert!(devices[0].matches(&info));
+        info.product_id = 0xb04e;
+        assert!(!devices[0].matches(&info));
+    }
+
+    #[test]
+    fn replay_test() {
+        let header = Header {
+            info: HidApiInfo {
+                vendor_id: 0x1c11,
+                product_id: 0xb04d,
+                serial_number: "abc".to_string(),
+                ..Default::default()
+            },
+            max_packet_len: 64,
+            trusted: true,
+            control: false,
+        };
+        let contents = format!(
+            "{}0.000100 tx 0600\n\
+             0.000200 packet-tx Sync\n\
+             0.001000 rx 0600\n\
+             0.002000 rx 02030100\n",
+            header.encode()
+        );
+        let capture = Capture::parse(&contents).unwrap();
+        assert_eq!(capture.header.info.serial_number, "abc");
+        assert_eq!(capture.header.max_packet_len, 64);
+        assert!(capture.header.trusted);
+        assert_eq!(capture.records.len(), 3);
+
+        // Chunks are returned once due
+        let mut device = ReplayDevice::new(&capture);
+        device.set_read_timeout(Some(100));
+        let mut buf = [0; 64];
+        assert_eq!(device.read(&mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], &[0x06, 0x00]);
+        assert_eq!(device.read(&mut buf).unwrap(), 4);
+        assert_eq!(device.read(&mut buf).unwrap(), 0);
+
+        assert!(Capture::parse("0.0 rx 00\n").is_err());
+        assert!(Capture::parse(&format!("{}0.0 rx 0\n", header.encode())).is_err());
+    }
+}
@@ -0,0 +1,338 @@
+#![cfg(feature = "websocket-devices")]
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * This file is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This file is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this file.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// ----- Crates -----
+
+use crate::api::common_capnp::NodeType;
+use crate::device::remote::Hello;
+use crate::device::*;
+use crate::module::config_path;
+use crate::RUNNING;
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::Ordering;
+use tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tungstenite::{Message, WebSocket};
+
+// ----- Consts -----
+
+/// WebSocket device configuration, stored in the hid-io-core config directory
+/// The listener is only started if this file exists.
+pub const CONFIG_FILE: &str = "websocket-devices";
+
+/// Default listen address, only local connections
+pub const DEFAULT_LISTEN: &str = "127.0.0.1:7187";
+
+/// Time allowed for the WebSocket handshake and the device information message
+const HANDSHAKE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Read timeout of a connected device
+const TIMEOUT_MS: u64 = 50;
+
+/// How often the listener checks if the daemon is quitting
+const ACCEPT_POLL_MS: u64 = 100;
+
+// ----- Structs -----
+
+/// WebSocket device configuration
+///
+/// listen=<address:port>
+/// origin=<origin>, may be repeated. Browser connections from any other origin are rejected.
+/// Connections without an Origin header (e.g. test rigs) are always accepted.
+pub struct Config {
+    pub listen: String,
+    pub origins: Vec<String>,
+}
+
+impl Config {
+    /// Loads the configuration
+    /// Returns None if the file does not exist.
+    pub fn load(path: &std::path::Path) -> Option<Config> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return None;
+            }
+            Err(e) => {
+                error!("Could not read {:?}: {}", path, e);
+                return None;
+            }
+        };
+
+        let mut config = Config {
+            listen: DEFAULT_LISTEN.to_string(),
+            origins: Vec::new(),
+        };
+        for line in contents.lines() {
+            let mut field = line.trim().splitn(2, '=');
+            match (field.next(), field.next()) {
+                (Some("listen"), Some(addr)) => config.listen = addr.trim().to_string(),
+                (Some("origin"), Some(origin)) => config.origins.push(origin.trim().to_string()),
+                _ => {}
+            }
+        }
+        Some(config)
+    }
+
+    /// Whether a connection with the given Origin header is allowed
+    fn allowed(&self, origin: Option<&str>) -> bool {
+        match origin {
+            Some(origin) => self.origins.iter().any(|allowed| allowed == origin),
+            None => true,
+        }
+    }
+}
+
+/// HID-IO chunks over a WebSocket
+///
+/// Each binary message is a single chunk.
+/// The first message sent by the device is a text message with its information
+/// (same key=value format as remote devices, see remote::Hello).
+/// Reads return a single chunk, or 0 if nothing was received before the read timeout.
+pub struct WebSocketDevice {
+    socket: WebSocket<TcpStream>,
+}
+
+impl WebSocketDevice {
+    pub fn new(socket: WebSocket<TcpStream>) -> WebSocketDevice {
+        WebSocketDevice { socket }
+    }
+
+    pub fn set_timeout(&self, timeout: std::time::Duration) -> std::io::Result<()> {
+        self.socket.get_ref().set_read_timeout(Some(timeout))?;
+        self.socket.get_ref().set_write_timeout(Some(timeout))
+    }
+
+    /// Waits for the device information message
+    pub fn hello(&mut self) -> std::io::Result<Hello> {
+        loop {
+            match self.socket.read_message().map_err(ws_error)? {
+                Message::Text(text) => {
+                    return Hello::decode(&text);
+                }
+                Message::Binary(_) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "Expected device information before chunks",
+                    ));
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl std::io::Read for WebSocketDevice {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self.socket.read_message() {
+            Ok(Message::Binary(data)) => {
+                if data.len() > buf.len() {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("Chunk too large: {}", data.len()),
+                    ));
+                }
+                buf[..data.len()].copy_from_slice(&data);
+                Ok(data.len())
+            }
+            Ok(Message::Close(_)) => Err(std::io::Error::new(
+                std::io::ErrorKind::NotConnected,
+                "WebSocket device disconnected",
+            )),
+            // Ping/Pong are handled by tungstenite, text is ignored
+            Ok(_) => Ok(0),
+            Err(tungstenite::Error::Io(e))
+                if e.kind() == std::io::ErrorKind::WouldBlock
+                    || e.kind() == std::io::ErrorKind::TimedOut =>
+            {
+                Ok(0)
+            }
+            Err(e) => Err(ws_error(e)),
+        }
+    }
+}
+
+impl std::io::Write for WebSocketDevice {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self.socket.write_message(Message::Binary(buf.to_vec())) {
+            Ok(()) => Ok(buf.len()),
+            // Message is queued and sent with the next read/write
+            Err(tungstenite::Error::Io(e)) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                Ok(buf.len())
+            }
+            Err(e) => Err(ws_error(e)),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.socket.write_pending() {
+            Err(tungstenite::Error::Io(e)) if e.kind() == std::io::ErrorKind::WouldBlock => Ok(()),
+            result => result.map_err(ws_error),
+        }
+    }
+}
+
+impl HidIoTransport for WebSocketDevice {}
+
+// ----- Functions -----
+
+fn ws_error(e: tungstenite::Error) -> std::io::Error {
+    match e {
+        tungstenite::Error::Io(e) => e,
+        tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed => {
+            std::io::Error::new(
+                std::io::ErrorKind::NotConnected,
+                "WebSocket device disconnected",
+            )
+        }
+        e => std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()),
+    }
+}
+
+/// Handles a single WebSocket connection
+fn connection(mailbox: mailbox::Mailbox, config: &Config, stream: TcpStream) {
+    let peer = match stream.peer_addr() {
+        Ok(peer) => peer,
+        Err(_) => {
+            return;
+        }
+    };
+    if let Err(e) = stream
+        .set_read_timeout(Some(HANDSHAKE_TIMEOUT))
+        .and_then(|_| stream.set_write_timeout(Some(HANDSHAKE_TIMEOUT)))
+    {
+        warn!("WebSocket device {} - {}", peer, e);
+        return;
+    }
+
+    // Browsers always send an Origin header, only allow configured pages
+    let check_origin = |request: &Request, response: Response| {
+        let origin = request
+            .headers()
+            .get("Origin")
+            .and_then(|origin| origin.to_str().ok());
+        if config.allowed(origin) {
+            Ok(response)
+        } else {
+            warn!(
+                "Rejected WebSocket device {} from origin {:?}",
+                peer, origin
+            );
+            let mut error = ErrorResponse::new(Some("Origin not allowed".to_string()));
+            *error.status_mut() = tungstenite::http::StatusCode::FORBIDDEN;
+            Err(error)
+        }
+    };
+    let socket = match tungstenite::accept_hdr(stream, check_origin) {
+        Ok(socket) => socket,
+        Err(e) => {
+            warn!("WebSocket handshake with {} failed - {}", peer, e);
+            return;
+        }
+    };
+
+    let mut device = WebSocketDevice::new(socket);
+    let hello = match device.hello() {
+        Ok(hello) => hello,
+        Err(e) => {
+            warn!("Rejected WebSocket device {} - {}", peer, e);
+            return;
+        }
+    };
+
+    let mut info = hello.info;
+    info.path = format!("ws://{}/{}", peer, info.path);
+    let uid = match mailbox.clone().assign_uid(info.key(), info.path.clone()) {
+        Ok(uid) => uid,
+        Err(e) => {
+            warn!("Could not register WebSocket device {} - {}", peer, e);
+            return;
+        }
+    };
+    if let Err(e) = device.set_timeout(std::time::Duration::from_millis(TIMEOUT_MS)) {
+        warn!("WebSocket device {} - {}", peer, e);
+        return;
+    }
+
+    // Create node
+    let mut node = Endpoint::new(NodeType::UsbKeyboard, uid);
+    node.set_hidapi_params(info);
+    println!("Connected to {} (websocket)", node);
+
+    // Emulated devices are never trusted, pairing keys are not sent to them
+    let mut device = HidIoEndpoint::new(Box::new(device), hello.max_packet_len);
+    device.set_trusted(false);
+    serve(mailbox, uid, node, device);
+}
+
+/// Accepts WebSocket connections until the daemon quits
+fn listen(mailbox: mailbox::Mailbox, config: Config) -> std::io::Result<()> {
+    let listener = TcpListener::bind(&config.listen)?;
+    listener.set_nonblocking(true)?;
+    info!("Listening for WebSocket devices on ws://{}", config.listen);
+
+    let config = std::sync::Arc::new(config);
+    while RUNNING.load(Ordering::SeqCst) {
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                std::thread::sleep(std::time::Duration::from_millis(ACCEPT_POLL_MS));
+                continue;
+            }
+            Err(e) => {
+                warn!("WebSocket device accept failed - {}", e);
+                continue;
+            }
+        };
+        stream.set_nonblocking(false)?;
+        stream.set_nodelay(true)?;
+
+        let mailbox = mailbox.clone();
+        let config = config.clone();
+        mailbox
+            .rt
+            .clone()
+            .spawn_blocking(move || connection(mailbox, &config, stream));
+    }
+    Ok(())
+}
+
+/// WebSocket device initialization
+///
+/// Listens for emulated devices (e.g. web-based keyboard emulators, test rigs).
+pub async fn initialize(mailbox: mailbox::Mailbox) {
+    info!("Initializing device/websocket...");
+
+    let config = match config_path(CONFIG_FILE).and_then(|path| Config::load(&path)) {
+        Some(config) => config,
+        None => {
+            info!(
+                "WebSocket devices disabled, create {} in the config directory to enable",
+                CONFIG_FILE
+            );
+            return;
+        }
+    };
+
+    let rt = mailbox.rt.clone();
+    rt.spawn_blocking(move || {
+        if let Err(e) = listen(mailbox, config) {
+            error!("WebSocket device listener failed - {}", e);
+        }
+    })
+    .await
+    .unwrap();
+}
@@ -42,11 +42,40 @@ pub fn setup_logging() -> Result<(), std::io::Error> {
         Ok(_) => {
             info!("-------------------------- HID-IO Core starting! --------------------------");
             info!("Log location -> {:?}", env::temp_dir());
+            setup_tracing();
             Ok(())
         }
     }
 }
 
+/// Tracing setup
+/// Packet lifecycle spans (device -> mailbox -> module -> api) are printed to stderr if
+/// HID_IO_TRACE is set to a filter (e.g. hid_io_core=trace).
+/// Closed spans are printed with their duration, the uid and command id fields can be used to
+/// follow a packet between spans.
+#[cfg(feature = "trace")]
+pub fn setup_tracing() {
+    use tracing_subscriber::fmt::format::FmtSpan;
+
+    let filter = match env::var("HID_IO_TRACE") {
+        Ok(filter) => filter,
+        Err(_) => {
+            return;
+        }
+    };
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::new(filter))
+        .with_span_events(FmtSpan::CLOSE)
+        .with_writer(std::io::stderr)
+        .finish();
+    if let Err(e) = tracing::subscriber::set_global_default(subscriber) {
+        warn!("Could not start tracing: {}", e);
+    }
+}
+
+#[cfg(not(feature = "trace"))]
+pub fn setup_tracing() {}
+
 /// Lite logging setup
 pub fn setup_logging_lite() -> Result<(), std::io::Error> {
     match Logger::with_env_or_str("")
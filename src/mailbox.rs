@@ -18,7 +18,8 @@
 /// Handles message passing between devices, modules and api calls
 /// Uses a broadcast channel to handle communication
 // ----- Modules -----
-use crate::api::Endpoint;
+use crate::api::common_capnp::NodeType;
+use crate::api::{Endpoint, DEFAULT_MAX_PACKET_LEN};
 use heapless::consts::U500;
 use hid_io_protocol::commands::CommandError;
 use hid_io_protocol::{HidIoCommandId, HidIoPacketType};
@@ -66,14 +67,80 @@ pub enum Address {
     Module,
 }
 
+/// Connection state of a device endpoint
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConnectionState {
+    /// Device has been synchronized and added to the node list
+    Connected,
+    /// Device failed, reopening it (uid is kept)
+    Reconnecting { attempt: u32 },
+    /// Device has been removed from the node list
+    Disconnected,
+}
+
 // ----- Consts -----
 
 /// Number of message slots for the mailbox broadcast channel
 /// Must be equal to the largest queue needed for the slowest receiver
 const CHANNEL_SLOTS: usize = 100;
 
+/// Number of slots for the connection event channel
+const CONNECTION_SLOTS: usize = 16;
+
 // ----- Structs -----
 
+/// Node selection filter
+/// Used to address a group of HID-IO devices, unset fields match any device.
+#[derive(Clone, Debug, Default)]
+pub struct NodeFilter {
+    /// USB vendor id
+    pub vendor_id: Option<u16>,
+    /// Wildcard pattern (* and ?) matched against the node name
+    pub name: Option<String>,
+}
+
+impl NodeFilter {
+    pub fn matches(&self, node: &mut Endpoint) -> bool {
+        if let Some(vendor_id) = self.vendor_id {
+            if node.vendor_id() != vendor_id {
+                return false;
+            }
+        }
+        if let Some(pattern) = &self.name {
+            if !wildcard_match(pattern, &node.name()) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+impl std::str::FromStr for NodeFilter {
+    type Err = String;
+
+    /// Parses a target selector
+    /// Either * (any device) or a comma separated list of vid=<hex> and name=<pattern>
+    fn from_str(target: &str) -> Result<Self, Self::Err> {
+        let mut filter = NodeFilter::default();
+        if target == "*" {
+            return Ok(filter);
+        }
+        for part in target.split(',') {
+            if let Some(vid) = part.strip_prefix("vid=") {
+                filter.vendor_id = Some(
+                    u16::from_str_radix(vid, 16)
+                        .map_err(|_| format!("Invalid vendor id '{}'", vid))?,
+                );
+            } else if let Some(name) = part.strip_prefix("name=") {
+                filter.name = Some(name.to_string());
+            } else {
+                return Err(format!("Invalid target '{}'", part));
+            }
+        }
+        Ok(filter)
+    }
+}
+
 /// HID-IO Mailbox
 ///
 /// Handles passing messages to various components inside of HID-IO
@@ -91,6 +158,7 @@ pub struct Mailbox {
     pub last_uid: Arc<RwLock<u64>>,
     pub lookup: Arc<RwLock<HashMap<String, Vec<u64>>>>,
     pub sender: broadcast::Sender<Message>,
+    pub connection: broadcast::Sender<ConnectionEvent>,
     pub ack_timeout: Arc<RwLock<std::time::Duration>>,
     pub rt: Arc<tokio::runtime::Runtime>,
 }
@@ -99,6 +167,8 @@ impl Mailbox {
     pub fn new(rt: Arc<tokio::runtime::Runtime>) -> Mailbox {
         // Create broadcast channel
         let (sender, _) = broadcast::channel::<Message>(CHANNEL_SLOTS);
+        // Setup connection event channel
+        let (connection, _) = broadcast::channel::<ConnectionEvent>(CONNECTION_SLOTS);
         // Setup nodes list
         let nodes = Arc::new(RwLock::new(vec![]));
         // Setup nodes lookup table
@@ -113,11 +183,19 @@ impl Mailbox {
             last_uid,
             lookup,
             sender,
+            connection,
             ack_timeout,
             rt,
         }
     }
 
+    /// Publish a connection state change of a device endpoint
+    /// Dropped silently if nothing is subscribed.
+    pub fn publish_connection_state(&self, uid: u64, state: ConnectionState) {
+        info!("uid:{} {:?}", uid, state);
+        let _ = self.connection.send(ConnectionEvent { uid, state });
+    }
+
     /// Attempt to locate an unused id for the device key
     pub fn get_uid(&mut self, key: String, path: String) -> Option<u64> {
         let mut lookup = self.lookup.write().unwrap();
@@ -191,6 +269,64 @@ impl Mailbox {
             .collect::<Vec<_>>();
     }
 
+    /// Update a registered node, node list subscribers are sent the updated list
+    /// Does nothing if the node is not registered.
+    pub fn update_node<F: FnOnce(&mut Endpoint)>(&self, uid: u64, update: F) {
+        let mut nodes = self.nodes.write().unwrap();
+        if let Some(node) = nodes.iter_mut().find(|node| node.uid == uid) {
+            update(node);
+            node.set_updated();
+        }
+    }
+
+    /// Seat of the device node that sent a message
+    /// None if the source is not a device (e.g. an API client)
+    pub fn seat(&self, src: Address) -> Option<String> {
+        let uid = match src {
+            Address::DeviceHidio { uid } | Address::DeviceHid { uid } => uid,
+            _ => {
+                return None;
+            }
+        };
+        let mut nodes = self.nodes.write().unwrap();
+        nodes
+            .iter_mut()
+            .find(|node| node.uid == uid)
+            .map(|node| node.seat())
+    }
+
+    /// Chunk size of the device node a message is sent to
+    /// The default is used for nodes that are not devices (or are not registered yet)
+    pub fn max_packet_len(&self, dst: Address) -> u32 {
+        let uid = match dst {
+            Address::DeviceHidio { uid } | Address::DeviceHid { uid } => uid,
+            _ => {
+                return DEFAULT_MAX_PACKET_LEN;
+            }
+        };
+        let nodes = self.nodes.read().unwrap();
+        nodes
+            .iter()
+            .find(|node| node.uid == uid)
+            .map_or(DEFAULT_MAX_PACKET_LEN, |node| node.max_packet_len())
+    }
+
+    /// Uids of the HID-IO device nodes matching the filter
+    pub fn group(&self, filter: &NodeFilter) -> Vec<u64> {
+        let mut nodes = self.nodes.write().unwrap();
+        let mut uids = vec![];
+        for node in nodes.iter_mut() {
+            let hidio = matches!(
+                node.type_(),
+                NodeType::BleKeyboard | NodeType::BtKeyboard | NodeType::UsbKeyboard
+            );
+            if hidio && filter.matches(node) {
+                uids.push(node.uid());
+            }
+        }
+        uids
+    }
+
     /// Convenience function to send a HidIo Command to device using the mailbox
     /// Returns the Ack message if enabled.
     /// Ack will timeout if it exceeds self.ack_timeout
@@ -212,11 +348,15 @@ impl Mailbox {
         */
         let ptype = HidIoPacketType::Data;
 
+        // Not entered, the span must not be held across awaits
+        let span = tracing::debug_span!("mailbox.command", src = ?src, dst = ?dst, id = ?id, ack);
+
         // Construct command packet
         let data = HidIoPacketBuffer {
             ptype,
             id,
-            max_len: 64, //..Defaults
+            unknown_id: None,
+            max_len: self.max_packet_len(dst),
             data: heapless::Vec::from_slice(&data).unwrap(),
             done: true,
         };
@@ -267,10 +407,12 @@ impl Mailbox {
                     if let Some(msg) = msg {
                         match msg.data.ptype {
                             HidIoPacketType::Ack => {
+                                tracing::debug!(parent: &span, "ack");
                                 return Ok(Some(msg));
                             }
                             // We may still want the message data from a Nak
                             HidIoPacketType::Nak => {
+                                tracing::debug!(parent: &span, "nak");
                                 let msg = Box::new(msg);
                                 return Err(AckWaitError::NakReceived { msg });
                             }
@@ -281,6 +423,7 @@ impl Mailbox {
                     }
                 }
                 Err(_) => {
+                    tracing::warn!(parent: &span, "ack timeout");
                     warn!("Timeout ({:?}) receiving Ack for: {}", ack_timeout, data);
                     return Err(AckWaitError::Timeout);
                 }
@@ -291,6 +434,26 @@ impl Mailbox {
     /// Convenience function to send a HidIoPacketBuffer using the mailbox
     /// Returns the Ack message if available and applicable
     pub fn try_send_message(&self, msg: Message) -> Result<Option<Message>, CommandError> {
+        let timeout = *self.ack_timeout.read().unwrap();
+        self.try_send_message_timeout(msg, timeout)
+    }
+
+    /// Same as try_send_message, for commands that take longer than self.ack_timeout
+    /// (e.g. waiting for user confirmation on the device)
+    pub fn try_send_message_timeout(
+        &self,
+        msg: Message,
+        timeout: std::time::Duration,
+    ) -> Result<Option<Message>, CommandError> {
+        let span = tracing::debug_span!(
+            "mailbox.message",
+            src = ?msg.src,
+            dst = ?msg.dst,
+            id = ?msg.data.id,
+            ptype = ?msg.data.ptype
+        );
+        let _enter = span.enter();
+
         // Check receiver count
         if self.sender.receiver_count() == 0 {
             error!("send_command (no active receivers)");
@@ -321,13 +484,12 @@ impl Mailbox {
         let start_time = std::time::Instant::now();
         loop {
             // Check for timeout
-            if start_time.elapsed() >= *self.ack_timeout.read().unwrap() {
+            if start_time.elapsed() >= timeout {
                 warn!(
                     "Timeout ({:?}) receiving Ack for command: src:{:?} dst:{:?}",
-                    *self.ack_timeout.read().unwrap(),
-                    msg.src,
-                    msg.dst
+                    timeout, msg.src, msg.dst
                 );
+                tracing::warn!("ack timeout");
                 return Err(CommandError::RxTimeout);
             }
 
@@ -337,10 +499,11 @@ impl Mailbox {
                     // Packet must have the same address as was sent, except reversed
                     if rcvmsg.dst == Address::All
                         && rcvmsg.src == msg.dst
-                        && rcvmsg.data.id == msg.data.id
+                        && rcvmsg.data.raw_id() == msg.data.raw_id()
                     {
                         match rcvmsg.data.ptype {
                             HidIoPacketType::Ack | HidIoPacketType::Nak => {
+                                tracing::debug!(ptype = ?rcvmsg.data.ptype, "response");
                                 return Ok(Some(rcvmsg));
                             }
                             _ => {}
@@ -372,6 +535,20 @@ impl Mailbox {
         id: HidIoCommandId,
         data: Vec<u8>,
         ack: bool,
+    ) -> Result<Option<Message>, AckWaitError> {
+        self.try_send_raw_command(src, dst, id as u32, data, ack)
+    }
+
+    /// Same as try_send_command(), but using the raw (wire) id
+    /// Ids unknown to this version of hid-io-protocol are passed through as-is, so newer device
+    /// commands can still be used.
+    pub fn try_send_raw_command(
+        &self,
+        src: Address,
+        dst: Address,
+        id: u32,
+        data: Vec<u8>,
+        ack: bool,
     ) -> Result<Option<Message>, AckWaitError> {
         // Select packet type
         /* TODO Add firmware support for NAData
@@ -383,14 +560,18 @@ impl Mailbox {
         */
         let ptype = HidIoPacketType::Data;
 
+        let span = tracing::debug_span!("mailbox.command", src = ?src, dst = ?dst, id, ack);
+        let _enter = span.enter();
+
         // Construct command packet
-        let data = HidIoPacketBuffer {
+        let mut data = HidIoPacketBuffer {
             ptype,
-            id,
-            max_len: 64, //..Defaults
+            max_len: self.max_packet_len(dst),
             data: heapless::Vec::from_slice(&data).unwrap(),
             done: true,
+            ..Default::default()
         };
+        data.set_raw_id(id);
 
         // Check receiver count
         if self.sender.receiver_count() == 0 {
@@ -429,6 +610,7 @@ impl Mailbox {
                     src,
                     dst
                 );
+                tracing::warn!("ack timeout");
                 return Err(AckWaitError::Timeout);
             }
 
@@ -437,13 +619,15 @@ impl Mailbox {
                 Ok(msg) => {
                     // Packet must have the same address as was sent, except reversed
                     // The HIDIO device does not keep track of senders, so it will be all
-                    if msg.dst == Address::All && msg.src == dst && msg.data.id == id {
+                    if msg.dst == Address::All && msg.src == dst && msg.data.raw_id() == id {
                         match msg.data.ptype {
                             HidIoPacketType::Ack => {
+                                tracing::debug!("ack");
                                 return Ok(Some(msg));
                             }
                             // We may still want the message data from a Nak
                             HidIoPacketType::Nak => {
+                                tracing::debug!("nak");
                                 let msg = Box::new(msg);
                                 return Err(AckWaitError::NakReceived { msg });
                             }
@@ -525,6 +709,19 @@ impl Message {
         Message { src, dst, data }
     }
 
+    /// Tracing span for a pipeline stage handling this message (e.g. module.displayserver)
+    /// The address and command id fields are used to follow a packet between stages.
+    pub fn span(&self, stage: &'static str) -> tracing::Span {
+        tracing::debug_span!(
+            "message",
+            stage,
+            src = ?self.src,
+            dst = ?self.dst,
+            id = ?self.data.id,
+            ptype = ?self.data.ptype
+        )
+    }
+
     /// Acknowledgement of a HidIo packet
     pub fn send_ack(&self, sender: broadcast::Sender<Message>, data: Vec<u8>) {
         let src = self.dst;
@@ -534,12 +731,14 @@ impl Message {
         let data = HidIoPacketBuffer {
             ptype: HidIoPacketType::Ack,
             id: self.data.id, // id,
-            max_len: 64,      // Default
+            unknown_id: self.data.unknown_id,
+            max_len: self.data.max_len,
             data: heapless::Vec::from_slice(&data).unwrap(),
             done: true,
         };
 
         // Construct ack message and broadcast
+        tracing::debug!(src = ?src, dst = ?dst, id = ?data.id, "send ack");
         let result = sender.send(Message { src, dst, data });
 
         if let Err(e) = result {
@@ -556,12 +755,14 @@ impl Message {
         let data = HidIoPacketBuffer {
             ptype: HidIoPacketType::Nak,
             id: self.data.id, // id,
-            max_len: 64,      // Default
+            unknown_id: self.data.unknown_id,
+            max_len: self.data.max_len,
             data: heapless::Vec::from_slice(&data).unwrap(),
             done: true,
         };
 
         // Construct ack message and broadcast
+        tracing::debug!(src = ?src, dst = ?dst, id = ?data.id, "send nak");
         let result = sender.send(Message { src, dst, data });
 
         if let Err(e) = result {
@@ -570,6 +771,13 @@ impl Message {
     }
 }
 
+/// Connection state change of a device endpoint
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ConnectionEvent {
+    pub uid: u64,
+    pub state: ConnectionState,
+}
+
 #[derive(Debug)]
 pub enum AckWaitError {
     TooManySyncs,
@@ -579,3 +787,133 @@ pub enum AckWaitError {
     Timeout,
     ChannelClosed,
 }
+
+// ----- Functions -----
+
+/// Matches text against a wildcard pattern
+/// * matches any number of characters, ? matches a single character
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    // Position to resume from after the last *
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut t) = (0, 0);
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((sp, st)) = star {
+            // Let the * consume one more character
+            p = sp + 1;
+            t = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+// ----- Tests -----
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn wildcard_match_test() {
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match(
+            "*Keyboard*",
+            "[1c11:b04d-ff1c:1100] Keyboard (K-Type)"
+        ));
+        assert!(wildcard_match("K-Typ?", "K-Type"));
+        assert!(!wildcard_match("K-Typ?", "K-Typ"));
+        assert!(wildcard_match("a*b*c", "aXXbYYc"));
+        assert!(!wildcard_match("a*b*c", "aXXbYY"));
+    }
+
+    /// Identical devices without serial numbers are told apart by their port
+    #[test]
+    fn uid_port_path_test() {
+        let rt = Arc::new(
+            tokio::runtime::Builder::new_current_thread()
+                .build()
+                .unwrap(),
+        );
+        let mut mailbox = Mailbox::new(rt);
+        let info = |port_path: &str| crate::api::HidApiInfo {
+            vendor_id: 0x1c11,
+            product_id: 0xb04d,
+            serial_number: "<Serial Unset>".to_string(),
+            port_path: port_path.to_string(),
+            ..Default::default()
+        };
+        let first = mailbox
+            .assign_uid(info("1-2").key(), "a".to_string())
+            .unwrap();
+        let second = mailbox
+            .assign_uid(info("1-3").key(), "b".to_string())
+            .unwrap();
+        assert_ne!(first, second);
+
+        // Reconnected in the opposite order
+        assert_eq!(
+            mailbox
+                .assign_uid(info("1-3").key(), "c".to_string())
+                .unwrap(),
+            second
+        );
+        assert_eq!(
+            mailbox
+                .assign_uid(info("1-2").key(), "d".to_string())
+                .unwrap(),
+            first
+        );
+
+        // Devices with a serial number keep their uid on any port
+        let mut serial = info("1-4");
+        serial.serial_number = "abc".to_string();
+        let third = mailbox.assign_uid(serial.key(), "e".to_string()).unwrap();
+        serial.port_path = "1-5".to_string();
+        assert_eq!(
+            mailbox.assign_uid(serial.key(), "f".to_string()).unwrap(),
+            third
+        );
+    }
+
+    /// Golden packets must survive the host reassembly buffer unchanged
+    #[test]
+    fn packet_vectors_test() {
+        for vector in hid_io_protocol::vectors::PACKETS {
+            let mut buffer = HidIoPacketBuffer::new();
+            let mut pos = 0;
+            while pos < vector.bytes.len() {
+                pos += buffer
+                    .decode_packet_opts(&vector.bytes[pos..], vector.options)
+                    .unwrap() as usize;
+            }
+            assert!(buffer.done, "{}", vector.name);
+            assert_eq!(buffer.ptype, vector.ptype, "{}", vector.name);
+            assert_eq!(buffer.id, vector.id, "{}", vector.name);
+            assert_eq!(&buffer.data[..], vector.payload, "{}", vector.name);
+
+            buffer.max_len = vector.max_len;
+            let mut bytes = vec![];
+            let mut offset = Some(0);
+            while let Some(next) = offset {
+                let mut chunk = vec![0; vector.max_len as usize];
+                let (len, next) = buffer
+                    .serialize_chunk_opts(next, &mut chunk, vector.options)
+                    .unwrap();
+                bytes.extend_from_slice(&chunk[..len]);
+                offset = next;
+            }
+            assert_eq!(&bytes[..], vector.bytes, "{}", vector.name);
+        }
+    }
+}
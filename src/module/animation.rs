@@ -0,0 +1,240 @@
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * This file is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This file is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this file.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// ----- Crates -----
+
+use crate::mailbox;
+use heapless::consts::U0;
+use hid_io_protocol::commands::*;
+use hid_io_protocol::crc16;
+
+// ----- Consts -----
+
+/// Frame data bytes per h002d chunk
+const CHUNK_SIZE: usize = 256;
+
+/// Number of times a NAK'd chunk is resent before giving up
+const CHUNK_RETRIES: usize = 3;
+
+// ----- Enumerations -----
+
+#[derive(Debug)]
+pub enum UploadError {
+    /// Data length is not a multiple of the frame length
+    InvalidLength { frame_len: usize, len: usize },
+    /// Too many frames for a single upload
+    TooManyFrames(usize),
+    /// Device rejected the upload
+    Begin(h002c::Error),
+    /// Device rejected a chunk
+    Data { offset: u32, error: h002d::Error },
+    /// Device could not store the animation
+    Finish(h002e::Error),
+    /// Device did not respond
+    NoResponse,
+    /// Command could not be sent
+    Command(CommandError),
+}
+
+impl std::fmt::Display for UploadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UploadError::InvalidLength { frame_len, len } => write!(
+                f,
+                "Data length ({}) is not a multiple of the frame length ({})",
+                len, frame_len
+            ),
+            UploadError::TooManyFrames(frames) => write!(f, "Too many frames: {}", frames),
+            UploadError::Begin(e) => write!(f, "Upload rejected: {:?}", e),
+            UploadError::Data { offset, error } => {
+                write!(f, "Chunk rejected (offset {}): {:?}", offset, error)
+            }
+            UploadError::Finish(e) => write!(f, "Commit failed: {:?}", e),
+            UploadError::NoResponse => write!(f, "No response from device"),
+            UploadError::Command(e) => write!(f, "Command failed: {:?}", e),
+        }
+    }
+}
+
+// ----- Structs -----
+
+struct CommandInterface {
+    src: mailbox::Address,
+    dst: mailbox::Address,
+    mailbox: mailbox::Mailbox,
+    result: Option<Result<(), UploadError>>,
+    offset: u32,
+}
+
+impl Commands<mailbox::HidIoPacketBufferDataSize, U0> for CommandInterface {
+    fn tx_packetbuffer_send(
+        &mut self,
+        buf: &mut mailbox::HidIoPacketBuffer,
+    ) -> Result<(), CommandError> {
+        if let Some(rcvmsg) = self.mailbox.try_send_message(mailbox::Message {
+            src: self.src,
+            dst: self.dst,
+            data: buf.clone(),
+        })? {
+            // Handle ack/nak
+            self.rx_message_handling(rcvmsg.data)?;
+        }
+        Ok(())
+    }
+    fn h002c_animationbegin_ack(&mut self, _data: h002c::Ack) -> Result<(), CommandError> {
+        self.result = Some(Ok(()));
+        Ok(())
+    }
+    fn h002c_animationbegin_nak(&mut self, data: h002c::Nak) -> Result<(), CommandError> {
+        self.result = Some(Err(UploadError::Begin(data.error)));
+        Ok(())
+    }
+    fn h002d_animationdata_ack(&mut self, _data: h002d::Ack) -> Result<(), CommandError> {
+        self.result = Some(Ok(()));
+        Ok(())
+    }
+    fn h002d_animationdata_nak(&mut self, data: h002d::Nak) -> Result<(), CommandError> {
+        self.result = Some(Err(UploadError::Data {
+            offset: self.offset,
+            error: data.error,
+        }));
+        Ok(())
+    }
+    fn h002e_animationfinish_ack(&mut self, _data: h002e::Ack) -> Result<(), CommandError> {
+        self.result = Some(Ok(()));
+        Ok(())
+    }
+    fn h002e_animationfinish_nak(&mut self, data: h002e::Nak) -> Result<(), CommandError> {
+        self.result = Some(Err(UploadError::Finish(data.error)));
+        Ok(())
+    }
+}
+
+impl CommandInterface {
+    /// Result of the last command
+    fn take_result(&mut self, sent: Result<(), CommandError>) -> Result<(), UploadError> {
+        if let Err(e) = sent {
+            return Err(UploadError::Command(e));
+        }
+        match self.result.take() {
+            Some(result) => result,
+            None => Err(UploadError::NoResponse),
+        }
+    }
+
+    fn begin(&mut self, cmd: h002c::Cmd) -> Result<(), UploadError> {
+        let sent = self.h002c_animationbegin(cmd);
+        self.take_result(sent)
+    }
+
+    fn chunk(&mut self, offset: u32, data: &[u8]) -> Result<(), UploadError> {
+        self.offset = offset;
+        let cmd = h002d::Cmd {
+            offset,
+            crc: crc16(data),
+            data: heapless::Vec::from_slice(data).unwrap(),
+        };
+        let sent = self.h002d_animationdata(cmd);
+        self.take_result(sent)
+    }
+
+    fn finish(&mut self, action: h002e::Action) -> Result<(), UploadError> {
+        let sent = self.h002e_animationfinish(h002e::Cmd { action });
+        self.take_result(sent)
+    }
+}
+
+// ----- Functions -----
+
+/// Upload an animation into device storage
+///
+/// data contains all frames back-to-back, each frame_len bytes long.
+/// Chunks rejected by the device (e.g. CRC mismatch) are resent a few times.
+/// If anything fails after the upload has started, the upload is aborted.
+pub fn upload(
+    mailbox: mailbox::Mailbox,
+    src: mailbox::Address,
+    dst: mailbox::Address,
+    slot: u16,
+    format: h002c::Format,
+    frame_len: u16,
+    data: &[u8],
+) -> Result<(), UploadError> {
+    if frame_len == 0 || data.is_empty() || data.len() % frame_len as usize != 0 {
+        return Err(UploadError::InvalidLength {
+            frame_len: frame_len as usize,
+            len: data.len(),
+        });
+    }
+    let frames = data.len() / frame_len as usize;
+    if frames > u16::MAX as usize {
+        return Err(UploadError::TooManyFrames(frames));
+    }
+
+    let mut intf = CommandInterface {
+        src,
+        dst,
+        mailbox,
+        result: None,
+        offset: 0,
+    };
+
+    info!(
+        "Uploading animation slot:{} {:?} frames:{} ({} bytes) to {:?}",
+        slot,
+        format,
+        frames,
+        data.len(),
+        dst
+    );
+    intf.begin(h002c::Cmd {
+        slot,
+        format,
+        frames: frames as u16,
+        frame_len,
+    })?;
+
+    let result = (|| {
+        for (i, chunk) in data.chunks(CHUNK_SIZE).enumerate() {
+            let offset = (i * CHUNK_SIZE) as u32;
+            let mut retries = 0;
+            loop {
+                match intf.chunk(offset, chunk) {
+                    Ok(_) => break,
+                    Err(UploadError::Data {
+                        error: h002d::Error::CrcMismatch,
+                        ..
+                    }) if retries < CHUNK_RETRIES => {
+                        warn!("CRC mismatch at offset {}, resending", offset);
+                        retries += 1;
+                    }
+                    Err(e) => {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+        intf.finish(h002e::Action::Commit)
+    })();
+
+    if let Err(e) = &result {
+        warn!("Animation upload failed: {}. Aborting.", e);
+        if let Err(e) = intf.finish(h002e::Action::Abort) {
+            warn!("Could not abort animation upload: {}", e);
+        }
+    }
+    result
+}
@@ -0,0 +1,177 @@
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * This file is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This file is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this file.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// ----- Crates -----
+
+use crate::mailbox;
+use crate::module::widget;
+use heapless::consts::U256;
+use hid_io_protocol::commands::*;
+use hid_io_protocol::{HidIoCommandId, HidIoPacketType};
+
+// ----- Enumerations -----
+
+/// Command applied to each device of a batch
+#[derive(Clone, Debug)]
+pub enum BatchCommand {
+    /// h001a Sleep Mode
+    Sleep,
+    /// h0029 Display Text
+    DisplayText { widget: u16, text: String },
+    /// Any other HID-IO command (e.g. text or layer commands supported by the firmware)
+    /// The id may be unknown to hid-io-core, it is sent as-is.
+    Raw { id: u32, data: Vec<u8> },
+}
+
+// ----- Structs -----
+
+/// Result of a batch command for a single device
+#[derive(Clone, Debug)]
+pub struct BatchResult {
+    pub uid: u64,
+    pub result: Result<(), String>,
+}
+
+/// Queries the supported ids of a device (h0000)
+struct CommandInterface {
+    src: mailbox::Address,
+    dst: mailbox::Address,
+    mailbox: mailbox::Mailbox,
+    ids: Option<Vec<HidIoCommandId>>,
+}
+
+impl Commands<mailbox::HidIoPacketBufferDataSize, U256> for CommandInterface {
+    fn tx_packetbuffer_send(
+        &mut self,
+        buf: &mut mailbox::HidIoPacketBuffer,
+    ) -> Result<(), CommandError> {
+        if let Some(rcvmsg) = self.mailbox.try_send_message(mailbox::Message {
+            src: self.src,
+            dst: self.dst,
+            data: buf.clone(),
+        })? {
+            // Handle ack/nak
+            self.rx_message_handling(rcvmsg.data)?;
+        }
+        Ok(())
+    }
+    fn h0000_supported_ids_ack(&mut self, data: h0000::Ack<U256>) -> Result<(), CommandError> {
+        self.ids = Some(data.ids.to_vec());
+        Ok(())
+    }
+}
+
+// ----- Functions -----
+
+/// Checks whether the device supports the given command id
+fn supports(
+    mailbox: mailbox::Mailbox,
+    src: mailbox::Address,
+    dst: mailbox::Address,
+    id: HidIoCommandId,
+) -> Result<bool, String> {
+    let mut intf = CommandInterface {
+        src,
+        dst,
+        mailbox,
+        ids: None,
+    };
+    intf.h0000_supported_ids(h0000::Cmd {})
+        .map_err(|e| format!("{:?}", e))?;
+    match intf.ids {
+        Some(ids) => Ok(ids.contains(&id)),
+        None => Err("Supported ids query failed".to_string()),
+    }
+}
+
+/// Sends the command to a single device
+fn apply(
+    mailbox: mailbox::Mailbox,
+    src: mailbox::Address,
+    dst: mailbox::Address,
+    command: BatchCommand,
+) -> Result<(), String> {
+    let (id, data) = match command {
+        BatchCommand::Sleep => (HidIoCommandId::SleepMode as u32, vec![]),
+        BatchCommand::DisplayText { widget, text } => {
+            return widget::set_text(mailbox, src, dst, widget, &text).map_err(|e| e.to_string());
+        }
+        BatchCommand::Raw { id, data } => (id, data),
+    };
+
+    match mailbox.try_send_raw_command(src, dst, id, data, true) {
+        Ok(Some(msg)) => match msg.data.ptype {
+            HidIoPacketType::Ack => Ok(()),
+            _ => Err(format!("{:?} {:?}", msg.data.ptype, msg.data.data)),
+        },
+        Ok(None) => Err("No response from device".to_string()),
+        Err(e) => Err(format!("{:?}", e)),
+    }
+}
+
+/// Applies a command to every HID-IO device matching the filter
+///
+/// If supported_id is set, only devices that report the id (h0000) are used.
+/// Each device is handled in parallel, the results are in device order.
+pub async fn run(
+    mailbox: mailbox::Mailbox,
+    src: mailbox::Address,
+    filter: mailbox::NodeFilter,
+    supported_id: Option<HidIoCommandId>,
+    command: BatchCommand,
+) -> Vec<BatchResult> {
+    let uids = mailbox.group(&filter);
+    info!("Batch {:?} to {:?} ({:?})", command, uids, filter);
+
+    // Tasks start running as soon as they are spawned
+    let tasks: Vec<_> = uids
+        .iter()
+        .map(|uid| {
+            let mailbox = mailbox.clone();
+            let command = command.clone();
+            let uid = *uid;
+            mailbox.rt.clone().spawn_blocking(move || {
+                let dst = mailbox::Address::DeviceHidio { uid };
+                if let Some(id) = supported_id {
+                    match supports(mailbox.clone(), src, dst, id) {
+                        Ok(true) => {}
+                        Ok(false) => {
+                            return None;
+                        }
+                        Err(e) => {
+                            return Some(Err(e));
+                        }
+                    }
+                }
+                Some(apply(mailbox, src, dst, command))
+            })
+        })
+        .collect();
+
+    let mut results = vec![];
+    for (uid, task) in uids.iter().zip(tasks.into_iter()) {
+        let result = match task.await {
+            Ok(Some(result)) => result,
+            Ok(None) => {
+                // Does not support the requested id
+                continue;
+            }
+            Err(e) => Err(format!("{:?}", e)),
+        };
+        results.push(BatchResult { uid: *uid, result });
+    }
+    results
+}
@@ -0,0 +1,342 @@
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * This file is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This file is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this file.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// ----- Crates -----
+
+use crate::built_info;
+use crate::mailbox;
+use crate::module::registry::ModuleStatus;
+use crate::module::{config_path, MODULES};
+use crate::RUNNING;
+use hid_io_protocol::{HidIoCommandId, HidIoPacketType};
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+use std::time::Instant;
+use tokio::stream::StreamExt;
+
+// ----- Consts -----
+
+/// Diagnostics are only collected if this file exists in the hid-io-core config directory
+const ENABLE_FILE: &str = "diagnostics";
+
+/// Report file, written to the hid-io-core config directory
+const REPORT_FILE: &str = "diagnostics-report.txt";
+
+/// How often the node list and module states are checked
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// How often the report file is rewritten
+const REPORT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Commands whose payloads may contain user text or input, never included in the report
+const TEXT_IDS: [HidIoCommandId; 15] = [
+    HidIoCommandId::GetInfo,
+    HidIoCommandId::KeyState,
+    HidIoCommandId::UnicodeText,
+    HidIoCommandId::UnicodeState,
+    HidIoCommandId::HostMacro,
+    HidIoCommandId::SettingsRead,
+    HidIoCommandId::SettingsWrite,
+    HidIoCommandId::DisplayText,
+    HidIoCommandId::ConfirmRequest,
+    HidIoCommandId::OpenUrl,
+    HidIoCommandId::TerminalCmd,
+    HidIoCommandId::GetInputLayout,
+    HidIoCommandId::SetInputLayout,
+    HidIoCommandId::TerminalOut,
+    HidIoCommandId::HidKeyboard,
+];
+
+lazy_static! {
+    static ref DIAGNOSTICS: RwLock<Diagnostics> = RwLock::new(Diagnostics::default());
+}
+
+/// Set while diagnostics are being collected
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+// ----- Enumerations -----
+
+#[derive(Debug)]
+pub enum DiagnosticsError {
+    /// Diagnostics have not been enabled
+    Disabled,
+    /// Config directory could not be determined
+    NoConfigDir,
+    /// Report could not be written
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for DiagnosticsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DiagnosticsError::Disabled => write!(
+                f,
+                "Diagnostics are disabled, create {} in the config directory to enable",
+                ENABLE_FILE
+            ),
+            DiagnosticsError::NoConfigDir => write!(f, "Could not determine config directory"),
+            DiagnosticsError::Io(e) => write!(f, "Could not write report: {}", e),
+        }
+    }
+}
+
+// ----- Structs -----
+
+/// Counters for a single device node
+#[derive(Clone, Debug, Default)]
+struct DeviceStats {
+    node: String,
+    connects: u32,
+    packets: u64,
+    naks_sent: u64,
+    naks_received: u64,
+    last_nak: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct Diagnostics {
+    started: Option<Instant>,
+    devices: HashMap<u64, DeviceStats>,
+    module_failures: HashMap<&'static str, u32>,
+}
+
+impl Diagnostics {
+    fn report(&self) -> String {
+        let mut report = String::new();
+        writeln!(report, "HID-IO Core diagnostics").ok();
+        writeln!(
+            report,
+            "Version: {}{}",
+            built_info::PKG_VERSION,
+            built_info::GIT_VERSION.map_or_else(|| "".to_owned(), |v| format!(" (git {})", v)),
+        )
+        .ok();
+        writeln!(report, "Target: {}", built_info::TARGET).ok();
+        writeln!(
+            report,
+            "Uptime: {}s",
+            self.started
+                .map_or(0, |started| started.elapsed().as_secs())
+        )
+        .ok();
+
+        writeln!(report, "\nDevices").ok();
+        let mut uids: Vec<&u64> = self.devices.keys().collect();
+        uids.sort();
+        for uid in uids {
+            let stats = &self.devices[uid];
+            let naks = stats.naks_sent + stats.naks_received;
+            writeln!(
+                report,
+                " * uid:{} {} reconnects:{} packets:{} naks sent:{} received:{} ({:.2}%){}",
+                uid,
+                stats.node,
+                stats.connects.saturating_sub(1),
+                stats.packets,
+                stats.naks_sent,
+                stats.naks_received,
+                if stats.packets > 0 {
+                    naks as f64 * 100.0 / stats.packets as f64
+                } else {
+                    0.0
+                },
+                stats
+                    .last_nak
+                    .as_ref()
+                    .map_or_else(|| "".to_string(), |nak| format!(" last nak: {}", nak)),
+            )
+            .ok();
+        }
+
+        writeln!(report, "\nModules").ok();
+        for module in MODULES.list() {
+            writeln!(
+                report,
+                " * {} {:?} failures:{}",
+                module.name,
+                module.status,
+                self.module_failures.get(module.name).unwrap_or(&0),
+            )
+            .ok();
+        }
+        report
+    }
+}
+
+// ----- Functions -----
+
+/// Formats a packet payload for the report
+/// Payloads of commands that may carry user text or input are reduced to their length.
+/// Vendor specific payloads and payloads of ids unknown to hid-io-core (Unused) have an unknown
+/// format and are treated the same way.
+fn redact(id: HidIoCommandId, data: &[u8]) -> String {
+    if TEXT_IDS.contains(&id) || id.is_vendor() || id == HidIoCommandId::Unused {
+        return format!("{:?} <{} bytes redacted>", id, data.len());
+    }
+    format!("{:?} {:02x?}", id, data)
+}
+
+/// Device uid of a mailbox address
+fn device_uid(address: mailbox::Address) -> Option<u64> {
+    match address {
+        mailbox::Address::DeviceHidio { uid } | mailbox::Address::DeviceHid { uid } => Some(uid),
+        _ => None,
+    }
+}
+
+/// Location of the report file
+pub fn report_path() -> Option<PathBuf> {
+    config_path(REPORT_FILE)
+}
+
+/// Writes the current report to the report file
+/// Returns the report location and contents.
+pub fn write_report() -> Result<(PathBuf, String), DiagnosticsError> {
+    if !ENABLED.load(Ordering::SeqCst) {
+        return Err(DiagnosticsError::Disabled);
+    }
+    let path = report_path().ok_or(DiagnosticsError::NoConfigDir)?;
+    let report = DIAGNOSTICS.read().unwrap().report();
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(DiagnosticsError::Io)?;
+    }
+    std::fs::write(&path, &report).map_err(DiagnosticsError::Io)?;
+    Ok((path, report))
+}
+
+/// Counts packets and naks per device
+fn record(msg: &mailbox::Message) {
+    let mut diagnostics = DIAGNOSTICS.write().unwrap();
+    if let Some(uid) = device_uid(msg.src) {
+        let stats = diagnostics.devices.entry(uid).or_default();
+        stats.packets += 1;
+        if msg.data.ptype == HidIoPacketType::Nak {
+            stats.naks_received += 1;
+            stats.last_nak = Some(redact(msg.data.id, &msg.data.data));
+        }
+    }
+    if let Some(uid) = device_uid(msg.dst) {
+        if msg.data.ptype == HidIoPacketType::Nak {
+            let stats = diagnostics.devices.entry(uid).or_default();
+            stats.naks_sent += 1;
+            stats.last_nak = Some(redact(msg.data.id, &msg.data.data));
+        }
+    }
+}
+
+/// Tracks device (re)connections and module failures
+fn poll(
+    mailbox: &mailbox::Mailbox,
+    connected: &mut Vec<u64>,
+    modules: &mut HashMap<&'static str, ModuleStatus>,
+) {
+    let mut diagnostics = DIAGNOSTICS.write().unwrap();
+
+    let mut nodes = mailbox.nodes.read().unwrap().clone();
+    let uids: Vec<u64> = nodes.iter_mut().map(|node| node.uid()).collect();
+    for node in nodes.iter_mut() {
+        if connected.contains(&node.uid()) {
+            continue;
+        }
+        let stats = diagnostics.devices.entry(node.uid()).or_default();
+        stats.connects += 1;
+        // Serial numbers are left out, they identify the device owner
+        stats.node = format!("{:?} {}", node.type_(), node.name());
+    }
+    *connected = uids;
+
+    for module in MODULES.list() {
+        let previous = modules.insert(module.name, module.status);
+        if module.status == ModuleStatus::Failed && previous != Some(ModuleStatus::Failed) {
+            *diagnostics.module_failures.entry(module.name).or_insert(0) += 1;
+        }
+    }
+}
+
+/// Diagnostics module
+/// Opt-in, aggregates device error rates, reconnects and module failures into a local report
+/// No packet payloads are stored except for (redacted) nak payloads.
+pub async fn initialize(mailbox: mailbox::Mailbox) {
+    info!("Initializing diagnostics...");
+    match config_path(ENABLE_FILE) {
+        Some(path) if path.exists() => {}
+        _ => {
+            info!(
+                "Diagnostics disabled, create {} in the config directory to enable",
+                ENABLE_FILE
+            );
+            return;
+        }
+    }
+    DIAGNOSTICS.write().unwrap().started = Some(Instant::now());
+    ENABLED.store(true, Ordering::SeqCst);
+
+    // Setup receiver stream
+    let sender = mailbox.sender.clone();
+    let receiver = sender.subscribe();
+    tokio::pin! {
+        let stream = receiver.into_stream()
+            .filter(Result::is_ok).map(Result::unwrap)
+            .take_while(|msg|
+                msg.src != mailbox::Address::DropSubscription &&
+                msg.dst != mailbox::Address::CancelAllSubscriptions
+            );
+    }
+
+    let mut poll_interval = tokio::time::interval(POLL_INTERVAL);
+    let mut report_interval = tokio::time::interval(REPORT_INTERVAL);
+    let mut connected = vec![];
+    let mut modules = HashMap::new();
+    while RUNNING.load(Ordering::SeqCst) {
+        tokio::select! {
+            msg = stream.next() => match msg {
+                Some(msg) => record(&msg),
+                None => break,
+            },
+            _ = poll_interval.tick() => poll(&mailbox, &mut connected, &mut modules),
+            _ = report_interval.tick() => {
+                if let Err(e) = write_report() {
+                    warn!("{}", e);
+                }
+            }
+        }
+    }
+
+    ENABLED.store(false, Ordering::SeqCst);
+}
+
+// ----- Tests -----
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn redact_test() {
+        assert_eq!(
+            redact(HidIoCommandId::UnicodeText, b"hunter2"),
+            "UnicodeText <7 bytes redacted>"
+        );
+        assert_eq!(
+            redact(HidIoCommandId::KllState, &[0x01, 0x02]),
+            "KllState [01, 02]"
+        );
+    }
+}
@@ -33,11 +33,26 @@ pub mod winapi;
 pub mod quartz;
 
 use crate::mailbox;
+use crate::module::seat;
 use crate::RUNNING;
 use hid_io_protocol::{HidIoCommandId, HidIoPacketType};
+use lazy_static::lazy_static;
+use std::collections::HashMap;
 use std::string::FromUtf8Error;
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
 use tokio::stream::StreamExt;
+use tokio::sync::mpsc;
+use zeroize::Zeroizing;
+
+lazy_static! {
+    /// Sensitive typing requests, set while the display server is running
+    static ref SENSITIVE_TX: Mutex<Option<mpsc::UnboundedSender<SensitiveRequest>>> =
+        Mutex::new(None);
+}
+
+/// Set while a sensitive string is being typed
+static SENSITIVE: AtomicBool = AtomicBool::new(false);
 
 #[cfg(all(feature = "displayserver", target_os = "linux"))]
 use crate::module::displayserver::x11::*;
@@ -133,9 +148,19 @@ impl DisplayOutput for StubOutput {
     }
 }
 
+/// Request to type a sensitive string
+/// Passed directly to the display server, never through the mailbox (and any taps on it)
+struct SensitiveRequest {
+    seat: String,
+    string: Zeroizing<String>,
+    result: std::sync::mpsc::Sender<Result<(), DisplayOutputError>>,
+}
+
 /// Our "internal" node responsible for handling required commands
 struct Module {
     display: Box<dyn DisplayOutput>,
+    seat: String,
+    seats: HashMap<String, Box<dyn DisplayOutput>>,
 }
 
 #[cfg(not(feature = "displayserver"))]
@@ -163,6 +188,35 @@ fn get_display() -> Box<dyn DisplayOutput> {
     Box::new(QuartzConnection::new())
 }
 
+/// Display of the active session on another seat
+/// Only X11 sessions can be reached from outside of the session (hid-io-core must be allowed to
+/// connect to the display, e.g. using xhost or XAUTHORITY).
+#[cfg(all(feature = "displayserver", target_os = "linux"))]
+fn get_seat_display(seat: &str) -> Option<Box<dyn DisplayOutput>> {
+    let session = seat::active_session(seat)?;
+    if session.display.is_empty() {
+        warn!(
+            "Session {} on {} is not an X11 session: {}",
+            session.id, seat, session.type_
+        );
+        return None;
+    }
+
+    info!("Using display {} for {}", session.display, seat);
+    match XConnection::with_display(&session.display) {
+        Some(connection) => Some(Box::new(connection)),
+        None => {
+            warn!("Could not open display {} for {}", session.display, seat);
+            None
+        }
+    }
+}
+
+#[cfg(not(all(feature = "displayserver", target_os = "linux")))]
+fn get_seat_display(_seat: &str) -> Option<Box<dyn DisplayOutput>> {
+    None
+}
+
 impl Module {
     fn new() -> Module {
         let connection = get_display();
@@ -178,7 +232,91 @@ impl Module {
 
         Module {
             display: connection,
+            seat: seat::own_seat(),
+            seats: HashMap::new(),
+        }
+    }
+
+    /// Display for the given seat
+    /// The seat hid-io-core is running on uses the default display, other seats are connected
+    /// to on first use.
+    fn seat_display(&mut self, seat: &str) -> Option<&mut Box<dyn DisplayOutput>> {
+        if seat == self.seat {
+            return Some(&mut self.display);
+        }
+        if !self.seats.contains_key(seat) {
+            let display = get_seat_display(seat)?;
+            self.seats.insert(seat.to_string(), display);
         }
+        self.seats.get_mut(seat)
+    }
+
+    /// Drops the display of another seat so it is reconnected on next use
+    /// The active session of the seat may have changed.
+    fn reset_seat(&mut self, seat: &str) {
+        self.seats.remove(seat);
+    }
+
+    fn type_sensitive(&mut self, request: SensitiveRequest) {
+        let display = match self.seat_display(&request.seat) {
+            Some(display) => display,
+            None => {
+                let error = format!("No display for {}", request.seat);
+                request
+                    .result
+                    .send(Err(DisplayOutputError::General(error)))
+                    .ok();
+                return;
+            }
+        };
+
+        SENSITIVE.store(true, Ordering::SeqCst);
+        let result = match display.type_string(&request.string) {
+            // Do not leak the symbol through the error
+            Err(DisplayOutputError::AllocationFailed(_)) => Err(DisplayOutputError::General(
+                "Could not allocate a symbol".to_string(),
+            )),
+            result => result,
+        };
+        SENSITIVE.store(false, Ordering::SeqCst);
+        request.result.send(result).ok();
+    }
+}
+
+/// Returns false while a sensitive string is being typed
+/// Display server implementations must not log typed symbols unless this is set.
+pub fn log_symbols() -> bool {
+    !SENSITIVE.load(Ordering::SeqCst)
+}
+
+/// Types a sensitive string (e.g. a password) on the focused window of the given seat
+/// The string is not logged and does not pass through the mailbox.
+/// Blocks until the string has been typed.
+pub fn type_sensitive(seat: &str, string: Zeroizing<String>) -> Result<(), DisplayOutputError> {
+    let sender = match SENSITIVE_TX.lock().unwrap().clone() {
+        Some(sender) => sender,
+        None => {
+            return Err(DisplayOutputError::General(
+                "Display server is not running".to_string(),
+            ));
+        }
+    };
+
+    let (result, receiver) = std::sync::mpsc::channel();
+    let seat = seat.to_string();
+    if sender
+        .send(SensitiveRequest {
+            seat,
+            string,
+            result,
+        })
+        .is_err()
+    {
+        return Err(DisplayOutputError::LostConnection);
+    }
+    match receiver.recv() {
+        Ok(result) => result,
+        Err(_) => Err(DisplayOutputError::LostConnection),
     }
 }
 
@@ -207,20 +345,50 @@ async fn process(mailbox: mailbox::Mailbox) {
             .filter(|msg| msg.data.ptype == HidIoPacketType::Data || msg.data.ptype == HidIoPacketType::NaData);
     }
 
-    // Process filtered message stream
-    while let Some(msg) = stream.next().await {
+    // Setup sensitive typing channel
+    let (sensitive_tx, mut sensitive_rx) = mpsc::unbounded_channel();
+    *SENSITIVE_TX.lock().unwrap() = Some(sensitive_tx);
+
+    // Process filtered message stream and sensitive typing requests
+    loop {
+        let msg = tokio::select! {
+            msg = stream.next() => match msg {
+                Some(msg) => msg,
+                None => break,
+            },
+            Some(request) = sensitive_rx.recv() => {
+                module.type_sensitive(request);
+                continue;
+            }
+        };
+        let span = msg.span("module.displayserver");
+        let _enter = span.enter();
         let mydata = msg.data.data.clone();
         debug!("Processing command: {:?}", msg.data.id);
+
+        // Output goes to the seat the device is attached to
+        let seat = mailbox.seat(msg.src).unwrap_or_else(|| module.seat.clone());
+        let display = match module.seat_display(&seat) {
+            Some(display) => display,
+            None => {
+                warn!("No display for {}, dropping {:?}", seat, msg.data.id);
+                if msg.data.ptype == HidIoPacketType::Data {
+                    msg.send_nak(sender.clone(), vec![]);
+                }
+                continue;
+            }
+        };
         match msg.data.id {
             HidIoCommandId::UnicodeText => {
                 let s = String::from_utf8(mydata.to_vec()).unwrap();
                 debug!("UnicodeText (start): {}", s);
-                match module.display.type_string(&s) {
+                match display.type_string(&s) {
                     Ok(_) => {
                         msg.send_ack(sender.clone(), vec![]);
                     }
                     Err(_) => {
                         warn!("Failed to type Unicode string");
+                        module.reset_seat(&seat);
                         msg.send_nak(sender.clone(), vec![]);
                     }
                 }
@@ -229,12 +397,13 @@ async fn process(mailbox: mailbox::Mailbox) {
             HidIoCommandId::UnicodeState => {
                 let s = String::from_utf8(mydata.to_vec()).unwrap();
                 debug!("UnicodeState (start): {}", s);
-                match module.display.set_held(&s) {
+                match display.set_held(&s) {
                     Ok(_) => {
                         msg.send_ack(sender.clone(), vec![]);
                     }
                     Err(_) => {
                         warn!("Failed to set Unicode key");
+                        module.reset_seat(&seat);
                         msg.send_nak(sender.clone(), vec![]);
                     }
                 }
@@ -242,7 +411,7 @@ async fn process(mailbox: mailbox::Mailbox) {
             }
             HidIoCommandId::GetInputLayout => {
                 debug!("GetInputLayout (start)");
-                match module.display.get_layout() {
+                match display.get_layout() {
                     Ok(layout) => {
                         info!("Current layout: {}", layout);
                         msg.send_ack(sender.clone(), layout.as_bytes().to_vec());
@@ -268,6 +437,8 @@ async fn process(mailbox: mailbox::Mailbox) {
             _ => {}
         }
     }
+
+    *SENSITIVE_TX.lock().unwrap() = None;
 }
 
 /// Display Server initialization
@@ -14,7 +14,7 @@
  * along with this file.  If not, see <http://www.gnu.org/licenses/>.
  */
 
-use crate::module::displayserver::{DisplayOutput, DisplayOutputError};
+use crate::module::displayserver::{log_symbols, DisplayOutput, DisplayOutputError};
 use std::collections::{HashMap, VecDeque};
 
 use std::convert::TryInto;
@@ -226,7 +226,9 @@ impl Keymap {
                 xkbcommon::xkb::keysym_from_name(&codepoint, xkbcommon::xkb::KEYSYM_NO_FLAGS)
             }
         };
-        trace!("{} {:04X} -> U{:04X}", c, c as u32, keysym);
+        if log_symbols() {
+            trace!("{} {:04X} -> U{:04X}", c, c as u32, keysym);
+        }
 
         // Make sure the keysym is valid
         if keysym != xkbcommon::xkb::keysyms::KEY_NoSymbol {
@@ -246,7 +248,9 @@ impl Keymap {
         let mut keysym_pairs: Vec<(char, xkbcommon::xkb::Keysym)> = Vec::new();
         let mut keycode_sequence: Vec<u32> = Vec::new();
         let mut regenerate = false;
-        trace!("add({:?})", chars);
+        if log_symbols() {
+            trace!("add({:?})", chars);
+        }
 
         // Lookup each of the keysyms
         for c in chars.clone() {
@@ -295,7 +299,9 @@ impl Keymap {
         // Regenerate layout if necessary
         if regenerate && self.automatic_layout_regen {
             let layout = self.generate_keymap_string()?;
-            trace!("add({:?}) regenerate {}", chars, layout);
+            if log_symbols() {
+                trace!("add({:?}) regenerate {}", chars, layout);
+            }
             self.apply_layout(layout)?;
         }
 
@@ -307,7 +313,9 @@ impl Keymap {
     /// counter has reached zero.
     pub fn remove(&mut self, chars: std::str::Chars) -> Result<(), DisplayOutputError> {
         let mut regenerate = false;
-        trace!("remove({:?})", chars);
+        if log_symbols() {
+            trace!("remove({:?})", chars);
+        }
 
         // Lookup each of the keysyms, decrementing the reference counters
         for c in chars {
@@ -374,7 +382,9 @@ impl Keymap {
         } else {
             return Err(DisplayOutputError::NoKeycode);
         };
-        debug!("time:{} keycode:{}:{} state:{}", time, c, keycode, state);
+        if log_symbols() {
+            debug!("time:{} keycode:{}:{} state:{}", time, c, keycode, state);
+        }
 
         // Prepare key event message
         if self.virtual_keyboard.as_ref().is_alive() {
@@ -398,7 +408,9 @@ impl Keymap {
         } else {
             return Err(DisplayOutputError::NoKeycode);
         };
-        debug!("time:{} keycode:{}:{}", time, c, keycode);
+        if log_symbols() {
+            debug!("time:{} keycode:{}:{}", time, c, keycode);
+        }
 
         // Prepare key event message
         if self.virtual_keyboard.as_ref().is_alive() {
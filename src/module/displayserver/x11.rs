@@ -15,7 +15,7 @@
  * along with this file.  If not, see <http://www.gnu.org/licenses/>.
  */
 
-use crate::module::displayserver::{DisplayOutput, DisplayOutputError};
+use crate::module::displayserver::{log_symbols, DisplayOutput, DisplayOutputError};
 use std::collections::HashMap;
 use std::convert::TryInto;
 use std::ffi::CString;
@@ -45,8 +45,25 @@ impl Default for XConnection {
 
 impl XConnection {
     pub fn new() -> XConnection {
+        Self::open(null())
+    }
+
+    /// Connects to a specific X11 display (e.g. :1 for a second seat)
+    /// Returns None if the display cannot be opened (e.g. not authorized)
+    pub fn with_display(name: &str) -> Option<XConnection> {
+        let name = CString::new(name).ok()?;
+        let connection = Self::open(name.as_ptr());
+        if connection.display.is_null() {
+            // Nothing to release, skip Drop
+            std::mem::forget(connection);
+            return None;
+        }
+        Some(connection)
+    }
+
+    fn open(name: *const std::os::raw::c_char) -> XConnection {
         unsafe {
-            let display = XOpenDisplay(null());
+            let display = XOpenDisplay(name);
             let charmap = HashMap::new();
             let held = Vec::new();
             let last_event_before_delays = std::time::Instant::now();
@@ -285,10 +302,14 @@ impl DisplayOutput for XConnection {
                 continue;
             }
             if let Some(keycode) = self.get_sym(c) {
-                debug!("Type {} => {:x?}", keycode, c);
+                if log_symbols() {
+                    debug!("Type {} => {:x?}", keycode, c);
+                }
                 keycodes.push(keycode);
             } else {
-                error!("Could not allocate a keysym for unicode '{}'", c);
+                if log_symbols() {
+                    error!("Could not allocate a keysym for unicode '{}'", c);
+                }
                 return Err(DisplayOutputError::AllocationFailed(c));
             }
         }
@@ -0,0 +1,408 @@
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * This file is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This file is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this file.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// ----- Crates -----
+
+use crate::mailbox;
+use heapless::consts::U0;
+use hid_io_protocol::commands::*;
+use hid_io_protocol::crc16;
+
+// ----- Consts -----
+
+/// File bytes per h0038/h0039 packet
+const CHUNK_SIZE: usize = 256;
+
+// ----- Enumerations -----
+
+#[derive(Debug)]
+pub enum TransferError {
+    /// Name is empty or does not fit in a single packet
+    InvalidName(String),
+    /// File does not fit in the 32-bit file address space
+    InvalidSize(usize),
+    /// Device could not open the file
+    Open(h0037::Error),
+    /// Device rejected a chunk
+    Write { offset: u32, error: h0037::Error },
+    /// Device could not read a chunk
+    Read { offset: u32, error: h0037::Error },
+    /// Device could not calculate a checksum
+    Checksum(h0037::Error),
+    /// Device could not close the file
+    Close {
+        action: h003b::Action,
+        error: h0037::Error,
+    },
+    /// Transferred data does not match the data stored on the device
+    Verify { expected: u16, received: u16 },
+    /// Device did not respond
+    NoResponse,
+    /// Command could not be sent
+    Command(CommandError),
+}
+
+impl std::fmt::Display for TransferError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransferError::InvalidName(name) => write!(f, "Invalid file name: {:?}", name),
+            TransferError::InvalidSize(size) => write!(f, "Invalid file size: {}", size),
+            TransferError::Open(e) => write!(f, "Open failed: {:?}", e),
+            TransferError::Write { offset, error } => {
+                write!(f, "Write failed (offset {}): {:?}", offset, error)
+            }
+            TransferError::Read { offset, error } => {
+                write!(f, "Read failed (offset {}): {:?}", offset, error)
+            }
+            TransferError::Checksum(e) => write!(f, "Checksum failed: {:?}", e),
+            TransferError::Close { action, error } => {
+                write!(f, "{:?} failed: {:?}", action, error)
+            }
+            TransferError::Verify { expected, received } => write!(
+                f,
+                "Checksum mismatch: expected {:04x}, device has {:04x}",
+                expected, received
+            ),
+            TransferError::NoResponse => write!(f, "No response from device"),
+            TransferError::Command(e) => write!(f, "Command failed: {:?}", e),
+        }
+    }
+}
+
+// ----- Structs -----
+
+struct CommandInterface {
+    src: mailbox::Address,
+    dst: mailbox::Address,
+    mailbox: mailbox::Mailbox,
+    result: Option<Result<(), TransferError>>,
+    handle: u8,
+    offset: u32,
+    action: h003b::Action,
+    /// Size from the last h0037 ack
+    size: u32,
+    /// Data from the last h0039 ack
+    read: Vec<u8>,
+    /// Checksum from the last h003a ack
+    crc: u16,
+}
+
+impl Commands<mailbox::HidIoPacketBufferDataSize, U0> for CommandInterface {
+    fn tx_packetbuffer_send(
+        &mut self,
+        buf: &mut mailbox::HidIoPacketBuffer,
+    ) -> Result<(), CommandError> {
+        if let Some(rcvmsg) = self.mailbox.try_send_message(mailbox::Message {
+            src: self.src,
+            dst: self.dst,
+            data: buf.clone(),
+        })? {
+            // Handle ack/nak
+            self.rx_message_handling(rcvmsg.data)?;
+        }
+        Ok(())
+    }
+    fn h0037_fileopen_ack(&mut self, data: h0037::Ack) -> Result<(), CommandError> {
+        self.handle = data.handle;
+        self.size = data.size;
+        self.result = Some(Ok(()));
+        Ok(())
+    }
+    fn h0037_fileopen_nak(&mut self, data: h0037::Nak) -> Result<(), CommandError> {
+        self.result = Some(Err(TransferError::Open(data.error)));
+        Ok(())
+    }
+    fn h0038_filewrite_ack(&mut self, _data: h0038::Ack) -> Result<(), CommandError> {
+        self.result = Some(Ok(()));
+        Ok(())
+    }
+    fn h0038_filewrite_nak(&mut self, data: h0038::Nak) -> Result<(), CommandError> {
+        self.result = Some(Err(TransferError::Write {
+            offset: self.offset,
+            error: data.error,
+        }));
+        Ok(())
+    }
+    fn h0039_fileread_ack(
+        &mut self,
+        data: h0039::Ack<mailbox::HidIoPacketBufferDataSize>,
+    ) -> Result<(), CommandError> {
+        self.read = data.data.to_vec();
+        self.result = Some(Ok(()));
+        Ok(())
+    }
+    fn h0039_fileread_nak(&mut self, data: h0039::Nak) -> Result<(), CommandError> {
+        self.result = Some(Err(TransferError::Read {
+            offset: self.offset,
+            error: data.error,
+        }));
+        Ok(())
+    }
+    fn h003a_filechecksum_ack(&mut self, data: h003a::Ack) -> Result<(), CommandError> {
+        self.crc = data.crc;
+        self.result = Some(Ok(()));
+        Ok(())
+    }
+    fn h003a_filechecksum_nak(&mut self, data: h003a::Nak) -> Result<(), CommandError> {
+        self.result = Some(Err(TransferError::Checksum(data.error)));
+        Ok(())
+    }
+    fn h003b_fileclose_ack(&mut self, _data: h003b::Ack) -> Result<(), CommandError> {
+        self.result = Some(Ok(()));
+        Ok(())
+    }
+    fn h003b_fileclose_nak(&mut self, data: h003b::Nak) -> Result<(), CommandError> {
+        self.result = Some(Err(TransferError::Close {
+            action: self.action,
+            error: data.error,
+        }));
+        Ok(())
+    }
+}
+
+impl CommandInterface {
+    fn new(mailbox: mailbox::Mailbox, src: mailbox::Address, dst: mailbox::Address) -> Self {
+        CommandInterface {
+            src,
+            dst,
+            mailbox,
+            result: None,
+            handle: 0,
+            offset: 0,
+            action: h003b::Action::Abort,
+            size: 0,
+            read: vec![],
+            crc: 0,
+        }
+    }
+
+    /// Result of the last command
+    fn take_result(&mut self, sent: Result<(), CommandError>) -> Result<(), TransferError> {
+        if let Err(e) = sent {
+            return Err(TransferError::Command(e));
+        }
+        match self.result.take() {
+            Some(result) => result,
+            None => Err(TransferError::NoResponse),
+        }
+    }
+
+    /// Opens the file, returns the size from the ack
+    fn open(&mut self, mode: h0037::Mode, size: u32, name: &str) -> Result<u32, TransferError> {
+        let mut string = heapless::String::new();
+        if name.is_empty() || string.push_str(name).is_err() {
+            return Err(TransferError::InvalidName(name.to_string()));
+        }
+        let sent = self.h0037_fileopen(h0037::Cmd {
+            mode,
+            size,
+            name: string,
+        });
+        self.take_result(sent)?;
+        Ok(self.size)
+    }
+
+    fn write_chunk(&mut self, offset: u32, data: &[u8]) -> Result<(), TransferError> {
+        self.offset = offset;
+        let sent = self.h0038_filewrite(h0038::Cmd {
+            handle: self.handle,
+            offset,
+            data: heapless::Vec::from_slice(data).unwrap(),
+        });
+        self.take_result(sent)
+    }
+
+    fn read_chunk(&mut self, offset: u32) -> Result<Vec<u8>, TransferError> {
+        self.offset = offset;
+        let sent = self.h0039_fileread(h0039::Cmd {
+            handle: self.handle,
+            offset,
+            len: CHUNK_SIZE as u16,
+        });
+        self.take_result(sent)?;
+        Ok(std::mem::take(&mut self.read))
+    }
+
+    fn checksum(&mut self, offset: u32, len: u32) -> Result<u16, TransferError> {
+        let sent = self.h003a_filechecksum(h003a::Cmd {
+            handle: self.handle,
+            offset,
+            len,
+        });
+        self.take_result(sent)?;
+        Ok(self.crc)
+    }
+
+    /// Compares the first data.len() bytes stored on the device with data
+    fn verify(&mut self, data: &[u8]) -> Result<(), TransferError> {
+        let expected = crc16(data);
+        let received = self.checksum(0, data.len() as u32)?;
+        if expected != received {
+            return Err(TransferError::Verify { expected, received });
+        }
+        Ok(())
+    }
+
+    fn close(&mut self, action: h003b::Action) -> Result<(), TransferError> {
+        self.action = action;
+        let sent = self.h003b_fileclose(h003b::Cmd {
+            handle: self.handle,
+            action,
+        });
+        self.take_result(sent)
+    }
+
+    /// Close after a failure, logging any further errors
+    fn close_failed(&mut self, action: h003b::Action) {
+        if let Err(e) = self.close(action) {
+            warn!("Could not close file ({:?}): {}", action, e);
+        }
+    }
+
+    /// Opens the file for writing, resuming an interrupted upload of the same data if possible
+    /// Returns the offset to continue writing at.
+    fn open_resume(&mut self, name: &str, data: &[u8]) -> Result<usize, TransferError> {
+        let stored = self.open(h0037::Mode::Resume, data.len() as u32, name)? as usize;
+        if stored == 0 {
+            return Ok(0);
+        }
+
+        // Only resume if the stored data is a prefix of the new file
+        let result = if stored <= data.len() {
+            self.verify(&data[..stored])
+        } else {
+            Err(TransferError::InvalidSize(stored))
+        };
+        match result {
+            Ok(_) => {
+                info!("Resuming upload of {} at offset {}", name, stored);
+                Ok(stored)
+            }
+            Err(TransferError::Verify { .. }) | Err(TransferError::InvalidSize(_)) => {
+                info!("Stored data of {} does not match, restarting upload", name);
+                self.close(h003b::Action::Abort)?;
+                self.open(h0037::Mode::Write, data.len() as u32, name)?;
+                Ok(0)
+            }
+            Err(e) => {
+                self.close_failed(h003b::Action::Suspend);
+                Err(e)
+            }
+        }
+    }
+
+    fn send(&mut self, data: &[u8], start: usize) -> Result<(), TransferError> {
+        for offset in (start..data.len()).step_by(CHUNK_SIZE) {
+            let end = std::cmp::min(offset + CHUNK_SIZE, data.len());
+            self.write_chunk(offset as u32, &data[offset..end])?;
+        }
+        Ok(())
+    }
+
+    fn receive(&mut self, data: &mut Vec<u8>, size: usize) -> Result<(), TransferError> {
+        while data.len() < size {
+            let chunk = self.read_chunk(data.len() as u32)?;
+            if chunk.is_empty() {
+                return Err(TransferError::Read {
+                    offset: data.len() as u32,
+                    error: h0037::Error::InvalidOffset,
+                });
+            }
+            data.extend(chunk);
+        }
+        data.truncate(size);
+        Ok(())
+    }
+}
+
+// ----- Functions -----
+
+/// Upload a file (e.g. firmware image, KLL layout or display asset) into device storage
+///
+/// An interrupted upload of the same file is resumed, the data already stored on the device is
+/// verified first. If the upload fails the partial data is kept on the device so calling upload
+/// again continues where it stopped. The file is only committed once the whole file has been
+/// verified.
+pub fn upload(
+    mailbox: mailbox::Mailbox,
+    src: mailbox::Address,
+    dst: mailbox::Address,
+    name: &str,
+    data: &[u8],
+) -> Result<(), TransferError> {
+    if data.len() > u32::MAX as usize {
+        return Err(TransferError::InvalidSize(data.len()));
+    }
+    let mut intf = CommandInterface::new(mailbox, src, dst);
+
+    info!("Uploading {} ({} bytes) to {:?}", name, data.len(), dst);
+    let start = intf.open_resume(name, data)?;
+
+    if let Err(e) = intf.send(data, start) {
+        warn!("Upload of {} failed: {}. Suspending.", name, e);
+        intf.close_failed(h003b::Action::Suspend);
+        return Err(e);
+    }
+    if let Err(e) = intf.verify(data) {
+        warn!("Upload of {} failed: {}. Aborting.", name, e);
+        intf.close_failed(h003b::Action::Abort);
+        return Err(e);
+    }
+    intf.close(h003b::Action::Commit)
+}
+
+/// Download a file from device storage
+///
+/// data may contain a partial download of the same file, the download continues after it if it
+/// still matches the file on the device. On failure data contains everything received so far.
+pub fn download(
+    mailbox: mailbox::Mailbox,
+    src: mailbox::Address,
+    dst: mailbox::Address,
+    name: &str,
+    data: &mut Vec<u8>,
+) -> Result<(), TransferError> {
+    let mut intf = CommandInterface::new(mailbox, src, dst);
+
+    let size = intf.open(h0037::Mode::Read, 0, name)? as usize;
+    info!("Downloading {} ({} bytes) from {:?}", name, size, dst);
+
+    let result = (|| {
+        // Discard partial data that no longer matches
+        if data.len() > size {
+            data.clear();
+        }
+        if !data.is_empty() {
+            match intf.verify(data) {
+                Ok(_) => info!("Resuming download of {} at offset {}", name, data.len()),
+                Err(TransferError::Verify { .. }) => data.clear(),
+                Err(e) => {
+                    return Err(e);
+                }
+            }
+        }
+
+        intf.receive(data, size)?;
+        intf.verify(data)
+    })();
+
+    if let Err(e) = result {
+        warn!("Download of {} failed: {}", name, e);
+        intf.close_failed(h003b::Action::Abort);
+        return Err(e);
+    }
+    intf.close(h003b::Action::Commit)
+}
@@ -0,0 +1,580 @@
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * This file is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This file is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this file.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// ----- Crates -----
+
+use crate::mailbox;
+use crate::module::config_path;
+use crate::RUNNING;
+use hid_io_protocol::commands::{h0035, h0036};
+use hid_io_protocol::{HidIoCommandId, HidIoPacketType};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+use tokio::stream::StreamExt;
+
+// ----- Consts -----
+
+/// How often the workstation state is polled
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Seconds without any user input before the workstation is considered idle
+/// Only used on platforms that do not report an idle hint themselves
+#[cfg(any(
+    target_os = "macos",
+    all(feature = "displayserver", target_os = "windows")
+))]
+const IDLE_THRESHOLD: u32 = 300;
+
+/// Devices allowed to lock the workstation (h0036), stored in the hid-io-core config directory
+/// One target per line, either * or e.g. vid=1c11,name=*Keystone*
+const LOCK_FILE: &str = "lockhost";
+
+/// Minimum time between lock requests from the same device
+const LOCK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Set while a fullscreen/exclusive application is focused
+static GAME_MODE: AtomicBool = AtomicBool::new(false);
+
+// ----- Structs -----
+
+/// Workstation lock/idle state
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct HostState {
+    locked: bool,
+    idle: bool,
+    idle_secs: u32,
+    game_mode: bool,
+}
+
+impl HostState {
+    fn state(&self) -> h0035::State {
+        if self.locked {
+            h0035::State::Locked
+        } else if self.idle {
+            h0035::State::Idle
+        } else {
+            h0035::State::Active
+        }
+    }
+}
+
+// ----- Functions -----
+
+/// Parses the output of loginctl show-session
+/// now is the current CLOCK_REALTIME time in microseconds
+#[cfg(target_os = "linux")]
+fn parse_loginctl(output: &str, now: u64) -> Option<HostState> {
+    let mut locked = None;
+    let mut idle = None;
+    let mut idle_since = 0;
+    for line in output.lines() {
+        let mut field = line.trim().splitn(2, '=');
+        match (field.next(), field.next()) {
+            (Some("LockedHint"), Some(val)) => locked = Some(val == "yes"),
+            (Some("IdleHint"), Some(val)) => idle = Some(val == "yes"),
+            (Some("IdleSinceHint"), Some(val)) => idle_since = val.parse::<u64>().unwrap_or(0),
+            _ => {}
+        }
+    }
+
+    let idle = idle?;
+    let idle_secs = if idle && idle_since > 0 && now > idle_since {
+        ((now - idle_since) / 1_000_000) as u32
+    } else {
+        0
+    };
+    Some(HostState {
+        locked: locked?,
+        idle,
+        idle_secs,
+        game_mode: false,
+    })
+}
+
+/// Queries the current session from logind
+/// The desktop environment is responsible for setting the lock and idle hints
+#[cfg(target_os = "linux")]
+fn query() -> Option<HostState> {
+    let session = std::env::var("XDG_SESSION_ID").unwrap_or_else(|_| "auto".to_string());
+    let output = std::process::Command::new("loginctl")
+        .args(&[
+            "show-session",
+            &session,
+            "-p",
+            "LockedHint",
+            "-p",
+            "IdleHint",
+            "-p",
+            "IdleSinceHint",
+        ])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()?
+        .as_micros() as u64;
+    parse_loginctl(&String::from_utf8_lossy(&output.stdout), now)
+}
+
+/// Queries the HID idle time and console lock state from the IO registry
+#[cfg(target_os = "macos")]
+fn query() -> Option<HostState> {
+    let hid = std::process::Command::new("ioreg")
+        .args(&["-c", "IOHIDSystem", "-d", "4"])
+        .output()
+        .ok()?;
+    let hid = String::from_utf8_lossy(&hid.stdout);
+    let idle_ns = hid.lines().find_map(|line| {
+        let mut field = line.splitn(2, '=');
+        if field
+            .next()?
+            .trim()
+            .trim_start_matches(|c| c == '|' || c == ' ')
+            == "\"HIDIdleTime\""
+        {
+            field.next()?.trim().parse::<u64>().ok()
+        } else {
+            None
+        }
+    })?;
+    let idle_secs = (idle_ns / 1_000_000_000) as u32;
+
+    let root = std::process::Command::new("ioreg")
+        .args(&["-n", "Root", "-d", "1"])
+        .output()
+        .ok()?;
+    let locked = String::from_utf8_lossy(&root.stdout).contains("\"CGSSessionScreenIsLocked\"=Yes");
+
+    Some(HostState {
+        locked,
+        idle: idle_secs >= IDLE_THRESHOLD,
+        idle_secs,
+        game_mode: false,
+    })
+}
+
+/// Queries the last input time and whether the input desktop is accessible
+/// The input desktop cannot be switched to while the workstation is locked
+#[cfg(all(feature = "displayserver", target_os = "windows"))]
+fn query() -> Option<HostState> {
+    use winapi::um::{sysinfoapi, winuser};
+
+    let mut info = winuser::LASTINPUTINFO {
+        cbSize: std::mem::size_of::<winuser::LASTINPUTINFO>() as u32,
+        dwTime: 0,
+    };
+    if unsafe { winuser::GetLastInputInfo(&mut info) } == 0 {
+        return None;
+    }
+    let idle_secs = unsafe { sysinfoapi::GetTickCount() }.wrapping_sub(info.dwTime) / 1000;
+
+    let locked = unsafe {
+        let desktop = winuser::OpenInputDesktop(0, 0, winuser::DESKTOP_SWITCHDESKTOP);
+        if desktop.is_null() {
+            true
+        } else {
+            let switched = winuser::SwitchDesktop(desktop);
+            winuser::CloseDesktop(desktop);
+            switched == 0
+        }
+    };
+
+    Some(HostState {
+        locked,
+        idle: idle_secs >= IDLE_THRESHOLD,
+        idle_secs,
+        game_mode: false,
+    })
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "macos",
+    all(feature = "displayserver", target_os = "windows")
+)))]
+fn query() -> Option<HostState> {
+    None
+}
+
+/// Parses the window id from xprop -root _NET_ACTIVE_WINDOW
+/// e.g. _NET_ACTIVE_WINDOW(WINDOW): window id # 0x3a00007
+#[cfg(target_os = "linux")]
+fn parse_active_window(output: &str) -> Option<&str> {
+    let window = output.trim().rsplit(' ').next()?;
+    if window.starts_with("0x") && window != "0x0" {
+        Some(window)
+    } else {
+        None
+    }
+}
+
+/// Checks the output of xprop -id <window> _NET_WM_STATE for the fullscreen atom
+/// e.g. _NET_WM_STATE(ATOM) = _NET_WM_STATE_FULLSCREEN, _NET_WM_STATE_FOCUSED
+#[cfg(target_os = "linux")]
+fn parse_fullscreen(output: &str) -> bool {
+    output.splitn(2, '=').nth(1).map_or(false, |atoms| {
+        atoms
+            .split(',')
+            .any(|atom| atom.trim() == "_NET_WM_STATE_FULLSCREEN")
+    })
+}
+
+/// Checks whether the focused X11 window is fullscreen using xprop
+/// Wayland compositors do not expose the focused window, game mode is never detected there.
+#[cfg(target_os = "linux")]
+fn fullscreen() -> bool {
+    if std::env::var_os("DISPLAY").is_none() {
+        return false;
+    }
+    let xprop = |args: &[&str]| {
+        std::process::Command::new("xprop")
+            .args(args)
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).to_string())
+    };
+
+    let active = match xprop(&["-root", "_NET_ACTIVE_WINDOW"]) {
+        Some(active) => active,
+        None => {
+            return false;
+        }
+    };
+    match parse_active_window(&active) {
+        Some(window) => {
+            xprop(&["-id", window, "_NET_WM_STATE"]).map_or(false, |state| parse_fullscreen(&state))
+        }
+        None => false,
+    }
+}
+
+/// Asks the shell whether a fullscreen or Direct3D exclusive application is running
+#[cfg(all(feature = "displayserver", target_os = "windows"))]
+fn fullscreen() -> bool {
+    use winapi::um::shellapi;
+
+    let mut state = 0;
+    if unsafe { shellapi::SHQueryUserNotificationState(&mut state) } != 0 {
+        return false;
+    }
+    state == shellapi::QUNS_BUSY
+        || state == shellapi::QUNS_RUNNING_D3D_FULL_SCREEN
+        || state == shellapi::QUNS_PRESENTATION_MODE
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    all(feature = "displayserver", target_os = "windows")
+)))]
+fn fullscreen() -> bool {
+    false
+}
+
+/// Whether a fullscreen/exclusive application is currently focused
+/// Used to relax input capture grabs while gaming.
+pub fn game_mode() -> bool {
+    GAME_MODE.load(Ordering::SeqCst)
+}
+
+/// Locks the current session using logind
+#[cfg(target_os = "linux")]
+fn lock() -> Result<(), h0036::Error> {
+    let mut cmd = std::process::Command::new("loginctl");
+    cmd.arg("lock-session");
+    if let Ok(session) = std::env::var("XDG_SESSION_ID") {
+        cmd.arg(session);
+    }
+    match cmd.status() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => {
+            warn!("loginctl lock-session failed: {}", status);
+            Err(h0036::Error::Failed)
+        }
+        Err(e) => {
+            warn!("Could not run loginctl: {}", e);
+            Err(h0036::Error::Failed)
+        }
+    }
+}
+
+/// Locks the current session by switching to the login window
+#[cfg(target_os = "macos")]
+fn lock() -> Result<(), h0036::Error> {
+    match std::process::Command::new(
+        "/System/Library/CoreServices/Menu Extras/User.menu/Contents/Resources/CGSession",
+    )
+    .arg("-suspend")
+    .status()
+    {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => {
+            warn!("CGSession -suspend failed: {}", status);
+            Err(h0036::Error::Failed)
+        }
+        Err(e) => {
+            warn!("Could not run CGSession: {}", e);
+            Err(h0036::Error::Failed)
+        }
+    }
+}
+
+#[cfg(all(feature = "displayserver", target_os = "windows"))]
+fn lock() -> Result<(), h0036::Error> {
+    if unsafe { winapi::um::winuser::LockWorkStation() } == 0 {
+        warn!(
+            "LockWorkStation failed: {}",
+            std::io::Error::last_os_error()
+        );
+        return Err(h0036::Error::Failed);
+    }
+    Ok(())
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "macos",
+    all(feature = "displayserver", target_os = "windows")
+)))]
+fn lock() -> Result<(), h0036::Error> {
+    Err(h0036::Error::NotSupported)
+}
+
+/// Device filters read from the lock file
+/// Locking is disabled for all devices if the file does not exist.
+fn lock_filters() -> Vec<mailbox::NodeFilter> {
+    let path = match config_path(LOCK_FILE) {
+        Some(path) => path,
+        None => {
+            return vec![];
+        }
+    };
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return vec![];
+        }
+        Err(e) => {
+            error!("Could not read {:?}: {}", path, e);
+            return vec![];
+        }
+    };
+
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| match line.parse::<mailbox::NodeFilter>() {
+            Ok(filter) => Some(filter),
+            Err(e) => {
+                warn!("Skipping invalid entry in {:?}: {}", path, e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Supported Ids by this module
+pub fn supported_ids() -> Vec<HidIoCommandId> {
+    vec![HidIoCommandId::HostState, HidIoCommandId::LockHost]
+}
+
+/// Handles lock requests from devices (h0036)
+async fn lock_requests(mailbox: mailbox::Mailbox) {
+    // Setup receiver stream
+    let sender = mailbox.sender.clone();
+    let receiver = sender.clone().subscribe();
+    tokio::pin! {
+        let stream = receiver.into_stream()
+            .filter(Result::is_ok).map(Result::unwrap)
+            .take_while(|msg|
+                msg.src != mailbox::Address::DropSubscription &&
+                msg.dst != mailbox::Address::CancelAllSubscriptions
+            )
+            .filter(|msg| msg.dst == mailbox::Address::Module)
+            .filter(|msg| msg.data.id == HidIoCommandId::LockHost)
+            .filter(|msg| msg.data.ptype == HidIoPacketType::Data);
+    }
+
+    // Time of the last lock request per device uid
+    let mut last_request: HashMap<u64, Instant> = HashMap::new();
+
+    // Process filtered message stream
+    while let Some(msg) = stream.next().await {
+        let uid = match msg.src {
+            mailbox::Address::DeviceHidio { uid } => uid,
+            _ => {
+                continue;
+            }
+        };
+
+        let permitted = lock_filters()
+            .iter()
+            .any(|filter| mailbox.group(filter).contains(&uid));
+        let result = if !permitted {
+            warn!(
+                "Lock request from {} denied, not enabled in {}",
+                uid, LOCK_FILE
+            );
+            Err(h0036::Error::NotPermitted)
+        } else if last_request
+            .get(&uid)
+            .map_or(false, |last| last.elapsed() < LOCK_INTERVAL)
+        {
+            warn!("Lock request from {} rate limited", uid);
+            Err(h0036::Error::RateLimited)
+        } else {
+            last_request.insert(uid, Instant::now());
+            info!("Locking workstation (requested by {})", uid);
+            tokio::task::spawn_blocking(lock)
+                .await
+                .unwrap_or(Err(h0036::Error::Failed))
+        };
+
+        match result {
+            Ok(_) => msg.send_ack(sender.clone(), vec![]),
+            Err(e) => msg.send_nak(sender.clone(), vec![e as u8]),
+        }
+    }
+}
+
+/// Polls the workstation lock/idle/game mode state and pushes changes to HID-IO devices (h0035)
+/// Newly connected devices are sent the current state.
+async fn notify(mailbox: mailbox::Mailbox) {
+    let mut current: Option<HostState> = None;
+    let mut notified: Vec<u64> = vec![];
+    let mut supported = true;
+    while RUNNING.load(Ordering::SeqCst) {
+        tokio::time::sleep(POLL_INTERVAL).await;
+
+        let host = match tokio::task::spawn_blocking(|| {
+            query().map(|host| HostState {
+                game_mode: fullscreen(),
+                ..host
+            })
+        })
+        .await
+        {
+            Ok(Some(host)) => {
+                supported = true;
+                host
+            }
+            Ok(None) => {
+                if supported {
+                    warn!("Could not determine workstation lock/idle state");
+                    supported = false;
+                }
+                continue;
+            }
+            Err(e) => {
+                error!("Host state query failed: {}", e);
+                continue;
+            }
+        };
+
+        // Every device is notified again when the state changes
+        let state = |host: HostState| (host.state(), host.game_mode);
+        if current.map(state) != Some(state(host)) {
+            info!(
+                "Host state: {:?} game mode: {}",
+                host.state(),
+                host.game_mode
+            );
+            GAME_MODE.store(host.game_mode, Ordering::SeqCst);
+            notified.clear();
+        }
+        current = Some(host);
+
+        let uids = mailbox.group(&mailbox::NodeFilter::default());
+        notified.retain(|uid| uids.contains(uid));
+        for uid in uids {
+            if notified.contains(&uid) {
+                continue;
+            }
+            notified.push(uid);
+
+            let mut data = vec![host.state() as u8];
+            data.extend_from_slice(&host.idle_secs.to_le_bytes());
+            data.push(host.game_mode as u8);
+            if let Err(e) = mailbox
+                .send_command(
+                    mailbox::Address::Module,
+                    mailbox::Address::DeviceHidio { uid },
+                    HidIoCommandId::HostState,
+                    data,
+                    false,
+                )
+                .await
+            {
+                warn!("Could not send host state to {}: {:?}", uid, e);
+            }
+        }
+    }
+}
+
+/// Host state module
+/// Notifies devices of workstation lock/idle/game mode changes and lets permitted devices lock it
+pub async fn initialize(mailbox: mailbox::Mailbox) {
+    info!("Initializing hoststate...");
+    tokio::join!(notify(mailbox.clone()), lock_requests(mailbox));
+}
+
+// ----- Tests -----
+
+#[cfg(test)]
+#[cfg(target_os = "linux")]
+mod test {
+    use super::*;
+
+    #[test]
+    fn loginctl_test() {
+        let output = "LockedHint=no\nIdleHint=no\nIdleSinceHint=0\n";
+        let host = parse_loginctl(output, 100_000_000).unwrap();
+        assert_eq!(host.state(), h0035::State::Active);
+        assert_eq!(host.idle_secs, 0);
+
+        let output = "LockedHint=no\nIdleHint=yes\nIdleSinceHint=40000000\n";
+        let host = parse_loginctl(output, 100_000_000).unwrap();
+        assert_eq!(host.state(), h0035::State::Idle);
+        assert_eq!(host.idle_secs, 60);
+
+        let output = "LockedHint=yes\nIdleHint=yes\nIdleSinceHint=40000000\n";
+        let host = parse_loginctl(output, 100_000_000).unwrap();
+        assert_eq!(host.state(), h0035::State::Locked);
+
+        // Older logind without LockedHint
+        assert!(parse_loginctl("IdleHint=no\n", 0).is_none());
+    }
+
+    #[test]
+    fn fullscreen_test() {
+        let output = "_NET_ACTIVE_WINDOW(WINDOW): window id # 0x3a00007\n";
+        assert_eq!(parse_active_window(output), Some("0x3a00007"));
+        let output = "_NET_ACTIVE_WINDOW(WINDOW): window id # 0x0\n";
+        assert_eq!(parse_active_window(output), None);
+
+        let output = "_NET_WM_STATE(ATOM) = _NET_WM_STATE_FULLSCREEN, _NET_WM_STATE_FOCUSED\n";
+        assert!(parse_fullscreen(output));
+        let output = "_NET_WM_STATE(ATOM) = _NET_WM_STATE_MAXIMIZED_VERT, _NET_WM_STATE_FOCUSED\n";
+        assert!(!parse_fullscreen(output));
+        assert!(!parse_fullscreen("_NET_WM_STATE:  not found.\n"));
+    }
+}
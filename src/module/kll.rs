@@ -0,0 +1,288 @@
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * This file is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This file is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this file.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// ----- Crates -----
+
+use crate::mailbox;
+use heapless::consts::U0;
+use hid_io_protocol::commands::*;
+use hid_io_protocol::crc16;
+
+// ----- Consts -----
+
+/// Layout bytes per h001d/h001e packet
+const CHUNK_SIZE: usize = 256;
+
+/// Number of times a NAK'd chunk is resent before giving up
+const CHUNK_RETRIES: usize = 3;
+
+// ----- Enumerations -----
+
+#[derive(Debug)]
+pub enum LayoutError {
+    /// Device rejected a layout read
+    Read { offset: u32, error: h001d::Error },
+    /// Device rejected a layout chunk
+    Write { offset: u32, error: h001e::Error },
+    /// Device rejected a control action
+    Control {
+        action: h001f::Action,
+        error: h001f::Error,
+    },
+    /// Applied layout does not match the uploaded one
+    Verify,
+    /// Layout is empty or does not fit in the 32-bit layout address space
+    InvalidSize(usize),
+    /// Device did not respond
+    NoResponse,
+    /// Command could not be sent
+    Command(CommandError),
+}
+
+impl std::fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LayoutError::Read { offset, error } => {
+                write!(f, "Read failed (offset {}): {:?}", offset, error)
+            }
+            LayoutError::Write { offset, error } => {
+                write!(f, "Chunk rejected (offset {}): {:?}", offset, error)
+            }
+            LayoutError::Control { action, error } => write!(f, "{:?} failed: {:?}", action, error),
+            LayoutError::Verify => write!(f, "Applied layout does not match"),
+            LayoutError::InvalidSize(size) => write!(f, "Invalid layout size: {}", size),
+            LayoutError::NoResponse => write!(f, "No response from device"),
+            LayoutError::Command(e) => write!(f, "Command failed: {:?}", e),
+        }
+    }
+}
+
+// ----- Structs -----
+
+struct CommandInterface {
+    src: mailbox::Address,
+    dst: mailbox::Address,
+    mailbox: mailbox::Mailbox,
+    result: Option<Result<(), LayoutError>>,
+    offset: u32,
+    action: h001f::Action,
+    /// Total layout size and data from the last h001d ack
+    read: (u32, Vec<u8>),
+}
+
+impl Commands<mailbox::HidIoPacketBufferDataSize, U0> for CommandInterface {
+    fn tx_packetbuffer_send(
+        &mut self,
+        buf: &mut mailbox::HidIoPacketBuffer,
+    ) -> Result<(), CommandError> {
+        if let Some(rcvmsg) = self.mailbox.try_send_message(mailbox::Message {
+            src: self.src,
+            dst: self.dst,
+            data: buf.clone(),
+        })? {
+            // Handle ack/nak
+            self.rx_message_handling(rcvmsg.data)?;
+        }
+        Ok(())
+    }
+    fn h001d_klllayoutread_ack(
+        &mut self,
+        data: h001d::Ack<mailbox::HidIoPacketBufferDataSize>,
+    ) -> Result<(), CommandError> {
+        self.read = (data.size, data.data.to_vec());
+        self.result = Some(Ok(()));
+        Ok(())
+    }
+    fn h001d_klllayoutread_nak(&mut self, data: h001d::Nak) -> Result<(), CommandError> {
+        self.result = Some(Err(LayoutError::Read {
+            offset: self.offset,
+            error: data.error,
+        }));
+        Ok(())
+    }
+    fn h001e_klllayoutwrite_ack(&mut self, _data: h001e::Ack) -> Result<(), CommandError> {
+        self.result = Some(Ok(()));
+        Ok(())
+    }
+    fn h001e_klllayoutwrite_nak(&mut self, data: h001e::Nak) -> Result<(), CommandError> {
+        self.result = Some(Err(LayoutError::Write {
+            offset: self.offset,
+            error: data.error,
+        }));
+        Ok(())
+    }
+    fn h001f_klllayoutcontrol_ack(&mut self, _data: h001f::Ack) -> Result<(), CommandError> {
+        self.result = Some(Ok(()));
+        Ok(())
+    }
+    fn h001f_klllayoutcontrol_nak(&mut self, data: h001f::Nak) -> Result<(), CommandError> {
+        self.result = Some(Err(LayoutError::Control {
+            action: self.action,
+            error: data.error,
+        }));
+        Ok(())
+    }
+}
+
+impl CommandInterface {
+    fn new(mailbox: mailbox::Mailbox, src: mailbox::Address, dst: mailbox::Address) -> Self {
+        CommandInterface {
+            src,
+            dst,
+            mailbox,
+            result: None,
+            offset: 0,
+            action: h001f::Action::Abort,
+            read: (0, vec![]),
+        }
+    }
+
+    /// Result of the last command
+    fn take_result(&mut self, sent: Result<(), CommandError>) -> Result<(), LayoutError> {
+        if let Err(e) = sent {
+            return Err(LayoutError::Command(e));
+        }
+        match self.result.take() {
+            Some(result) => result,
+            None => Err(LayoutError::NoResponse),
+        }
+    }
+
+    fn read_chunk(&mut self, offset: u32) -> Result<(u32, Vec<u8>), LayoutError> {
+        self.offset = offset;
+        let sent = self.h001d_klllayoutread(h001d::Cmd {
+            offset,
+            len: CHUNK_SIZE as u16,
+        });
+        self.take_result(sent)?;
+        Ok(std::mem::take(&mut self.read))
+    }
+
+    fn write_chunk(&mut self, offset: u32, data: &[u8]) -> Result<(), LayoutError> {
+        self.offset = offset;
+        let sent = self.h001e_klllayoutwrite(h001e::Cmd {
+            offset,
+            crc: crc16(data),
+            data: heapless::Vec::from_slice(data).unwrap(),
+        });
+        self.take_result(sent)
+    }
+
+    fn control(&mut self, action: h001f::Action, arg: u32) -> Result<(), LayoutError> {
+        self.action = action;
+        let sent = self.h001f_klllayoutcontrol(h001f::Cmd { action, arg });
+        self.take_result(sent)
+    }
+
+    fn download(&mut self) -> Result<Vec<u8>, LayoutError> {
+        let mut layout = vec![];
+        loop {
+            let (size, chunk) = self.read_chunk(layout.len() as u32)?;
+            if chunk.is_empty() && layout.len() < size as usize {
+                return Err(LayoutError::Read {
+                    offset: layout.len() as u32,
+                    error: h001d::Error::InvalidOffset,
+                });
+            }
+            layout.extend(chunk);
+            if layout.len() >= size as usize {
+                layout.truncate(size as usize);
+                return Ok(layout);
+            }
+        }
+    }
+
+    fn send(&mut self, layout: &[u8]) -> Result<(), LayoutError> {
+        for (i, chunk) in layout.chunks(CHUNK_SIZE).enumerate() {
+            let offset = (i * CHUNK_SIZE) as u32;
+            let mut retries = 0;
+            loop {
+                match self.write_chunk(offset, chunk) {
+                    Ok(_) => break,
+                    Err(LayoutError::Write {
+                        error: h001e::Error::CrcMismatch,
+                        ..
+                    }) if retries < CHUNK_RETRIES => {
+                        warn!("CRC mismatch at offset {}, resending", offset);
+                        retries += 1;
+                    }
+                    Err(e) => {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// ----- Functions -----
+
+/// Download the active KLL layout definition from a device
+pub fn download(
+    mailbox: mailbox::Mailbox,
+    src: mailbox::Address,
+    dst: mailbox::Address,
+) -> Result<Vec<u8>, LayoutError> {
+    CommandInterface::new(mailbox, src, dst).download()
+}
+
+/// Upload and apply a new KLL layout definition
+///
+/// The device validates the layout on apply, it is then read back and compared.
+/// If anything fails before apply the upload is aborted, after apply the previous layout is
+/// restored (rollback).
+pub fn upload(
+    mailbox: mailbox::Mailbox,
+    src: mailbox::Address,
+    dst: mailbox::Address,
+    layout: &[u8],
+) -> Result<(), LayoutError> {
+    if layout.is_empty() || layout.len() > u32::MAX as usize {
+        return Err(LayoutError::InvalidSize(layout.len()));
+    }
+    let mut intf = CommandInterface::new(mailbox, src, dst);
+
+    info!("Uploading KLL layout ({} bytes) to {:?}", layout.len(), dst);
+    intf.control(h001f::Action::Begin, layout.len() as u32)?;
+
+    // Send layout
+    let result = intf
+        .send(layout)
+        .and_then(|_| intf.control(h001f::Action::Apply, crc16(layout) as u32));
+    if let Err(e) = result {
+        warn!("KLL layout upload failed: {}. Aborting.", e);
+        if let Err(e) = intf.control(h001f::Action::Abort, 0) {
+            warn!("Could not abort KLL layout upload: {}", e);
+        }
+        return Err(e);
+    }
+
+    // Verify applied layout
+    let result = match intf.download() {
+        Ok(applied) if applied == layout => intf.control(h001f::Action::Confirm, 0),
+        Ok(_) => Err(LayoutError::Verify),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        warn!("KLL layout verification failed: {}. Rolling back.", e);
+        if let Err(e) = intf.control(h001f::Action::Rollback, 0) {
+            error!("Could not roll back KLL layout: {}", e);
+        }
+        return Err(e);
+    }
+    Ok(())
+}
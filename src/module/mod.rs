@@ -14,17 +14,52 @@
  * along with this file.  If not, see <http://www.gnu.org/licenses/>.
  */
 
+/// LED animation upload to device storage
+pub mod animation;
+/// Apply commands to groups of devices
+pub mod batch;
 /// Platform specific character output and IME control
 pub mod daemonnode;
+/// Opt-in local diagnostics report (device error rates, reconnects, module failures)
+pub mod diagnostics;
 pub mod displayserver;
+/// Chunked file transfers to/from device storage with resume
+pub mod filetransfer;
+/// Workstation lock/idle state notifications
+pub mod hoststate;
+/// KLL layout download/upload
+pub mod kll;
+/// Module lifecycle management (dependency ordering, runtime enable/disable)
+pub mod registry;
+/// Scheduled and recurring commands
+pub mod scheduler;
+/// Multi-seat (systemd-logind) device and display lookup
+pub mod seat;
+/// Typing of sensitive strings after confirmation on the device
+pub mod securetype;
+/// Device settings storage access
+pub mod settings;
+/// Raw passthrough of vendor specific / experimental commands
+pub mod vendor;
 pub mod vhid;
+/// Device display text widgets
+pub mod widget;
 
 use crate::api;
 use crate::device;
 use crate::mailbox;
+use crate::RUNNING;
 use hid_io_protocol::{HidIoCommandId, HidIoPacketType};
+use lazy_static::lazy_static;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
 use tokio::stream::StreamExt;
 
+lazy_static! {
+    /// Module registry, used to query and enable/disable modules at runtime
+    pub static ref MODULES: registry::ModuleRegistry = registry::ModuleRegistry::new();
+}
+
 /// Supported Ids by this module
 /// recursive option applies supported ids from child modules as well
 pub fn supported_ids(recursive: bool) -> Vec<HidIoCommandId> {
@@ -37,10 +72,33 @@ pub fn supported_ids(recursive: bool) -> Vec<HidIoCommandId> {
     ];
     if recursive {
         ids.extend(displayserver::supported_ids().iter().cloned());
+        ids.extend(hoststate::supported_ids().iter().cloned());
+        ids.extend(securetype::supported_ids().iter().cloned());
+        ids.extend(vendor::supported_ids().iter().cloned());
+        ids.extend(vhid::supported_ids().iter().cloned());
     }
     ids
 }
 
+/// Location of a file in the hid-io-core config directory
+/// Returns None if the config directory could not be determined
+pub fn config_path(file: &str) -> Option<PathBuf> {
+    #[cfg(target_os = "windows")]
+    let dir = std::env::var_os("APPDATA").map(PathBuf::from);
+    #[cfg(target_os = "macos")]
+    let dir = std::env::var_os("HOME").map(|home| {
+        PathBuf::from(home)
+            .join("Library")
+            .join("Application Support")
+    });
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    let dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+
+    dir.map(|dir| dir.join("hid-io-core").join(file))
+}
+
 /// Device initialization
 /// Sets up a scanning thread per Device type (using tokio).
 /// Each scanning thread will create a new thread per device found.
@@ -49,153 +107,206 @@ pub fn supported_ids(recursive: bool) -> Vec<HidIoCommandId> {
 pub async fn initialize(mailbox: mailbox::Mailbox) {
     info!("Initializing modules...");
 
-    // Setup local thread
-    // Due to some of the setup in the Module struct we need to run processing in the same local
-    // thread.
-    let mailbox1 = mailbox.clone();
-    let data = tokio::spawn(async move {
-        // Setup receiver stream
-        let sender = mailbox1.clone().sender.clone();
-        let receiver = sender.clone().subscribe();
-        tokio::pin! {
-            let stream = receiver.into_stream()
-                .filter(Result::is_ok).map(Result::unwrap)
-                .take_while(|msg|
-                    msg.src != mailbox::Address::DropSubscription &&
-                    msg.dst != mailbox::Address::CancelAllSubscriptions
-                )
-                .filter(|msg| msg.dst == mailbox::Address::Module || msg.dst == mailbox::Address::All)
-                .filter(|msg| supported_ids(false).contains(&msg.data.id))
-                .filter(|msg| msg.data.ptype == HidIoPacketType::Data || msg.data.ptype == HidIoPacketType::NaData);
+    // Register modules and their dependencies
+    // Unsupported ids should only be Nak'd once every module that handles ids is up
+    for result in vec![
+        // Node is kept alive by a detached task
+        MODULES.register_unstoppable("daemonnode", &[], |mailbox| {
+            Box::pin(daemonnode::initialize(mailbox))
+        }),
+        // Runs on a dedicated thread until exit
+        MODULES.register_unstoppable("displayserver", &[], |mailbox| {
+            Box::pin(displayserver::initialize(mailbox))
+        }),
+        // Loopback devices run on dedicated threads
+        MODULES.register_unstoppable("vhid", &[], |mailbox| Box::pin(vhid::initialize(mailbox))),
+        MODULES.register("commands", &[], |mailbox| Box::pin(commands(mailbox))),
+        MODULES.register("hoststate", &[], |mailbox| {
+            Box::pin(hoststate::initialize(mailbox))
+        }),
+        MODULES.register("securetype", &[], |mailbox| {
+            Box::pin(securetype::initialize(mailbox))
+        }),
+        MODULES.register("scheduler", &[], |mailbox| {
+            Box::pin(scheduler::initialize(mailbox))
+        }),
+        MODULES.register("diagnostics", &[], |mailbox| {
+            Box::pin(diagnostics::initialize(mailbox))
+        }),
+        MODULES.register(
+            "unsupported",
+            &[
+                "commands",
+                "displayserver",
+                "hoststate",
+                "securetype",
+                "vhid",
+            ],
+            |mailbox| Box::pin(unsupported(mailbox)),
+        ),
+    ] {
+        if let Err(e) = result {
+            error!("Could not register module: {}", e);
         }
+    }
 
-        // Process filtered message stream
-        while let Some(msg) = stream.next().await {
-            let _mydata = msg.data.data.clone();
-            debug!("Processing command: {:?}", msg.data.id);
-            /* TODO
-            match msg.data.id {
-                HidIoCommandId::SupportedIDs => {
-                    let ids = supported_ids(false)
-                        .iter()
-                        .map(|x| *x as u16)
-                        .collect::<Vec<u16>>();
-                    trace!("Acking SupportedIDs");
-                    msg.send_ack(sender.clone(), as_u8_slice(&ids).to_vec());
-                }
-                HidIoCommandId::GetProperties => {
-                    use crate::built_info;
-                    let property: HidIoPropertyID = unsafe { std::mem::transmute(mydata[0]) };
-                    info!("Get prop {:?}", property);
-                    match property {
-                        HidIoPropertyID::HidIoMajor => {
-                            let v = built_info::PKG_VERSION_MAJOR.parse::<u16>().unwrap();
-                            msg.send_ack(sender.clone(), as_u8_slice(&[v]).to_vec());
-                        }
-                        HidIoPropertyID::HidIoMinor => {
-                            let v = built_info::PKG_VERSION_MINOR.parse::<u16>().unwrap();
-                            msg.send_ack(sender.clone(), as_u8_slice(&[v]).to_vec());
-                        }
-                        HidIoPropertyID::HidIoPatch => {
-                            let v = built_info::PKG_VERSION_PATCH.parse::<u16>().unwrap();
-                            msg.send_ack(sender.clone(), as_u8_slice(&[v]).to_vec());
-                        }
-                        HidIoPropertyID::HostOS => {
-                            let os = match built_info::CFG_OS {
-                                "windows" => HostOSID::Windows,
-                                "macos" => HostOSID::Mac,
-                                "ios" => HostOSID::IOS,
-                                "linux" => HostOSID::Linux,
-                                "android" => HostOSID::Android,
-                                "freebsd" => HostOSID::FreeBSD,
-                                "openbsd" => HostOSID::OpenBSD,
-                                "netbsd" => HostOSID::NetBSD,
-                                _ => HostOSID::Unknown,
-                            };
-                            msg.send_ack(sender.clone(), vec![os as u8]);
+    // Start modules in dependency order
+    if let Err(e) = MODULES.start(mailbox) {
+        error!("Could not start modules: {}", e);
+        return;
+    }
+
+    // Wait until all modules have finished (or we're exiting)
+    while RUNNING.load(Ordering::SeqCst) && MODULES.running() {
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+}
+
+/// Built-in command handlers
+async fn commands(mailbox: mailbox::Mailbox) {
+    // Setup receiver stream
+    let sender = mailbox.sender.clone();
+    let receiver = sender.clone().subscribe();
+    tokio::pin! {
+        let stream = receiver.into_stream()
+            .filter(Result::is_ok).map(Result::unwrap)
+            .take_while(|msg|
+                msg.src != mailbox::Address::DropSubscription &&
+                msg.dst != mailbox::Address::CancelAllSubscriptions
+            )
+            .filter(|msg| msg.dst == mailbox::Address::Module || msg.dst == mailbox::Address::All)
+            .filter(|msg| supported_ids(false).contains(&msg.data.id))
+            .filter(|msg| msg.data.ptype == HidIoPacketType::Data || msg.data.ptype == HidIoPacketType::NaData);
+    }
+
+    // Process filtered message stream
+    while let Some(msg) = stream.next().await {
+        let span = msg.span("module.commands");
+        let _enter = span.enter();
+        let _mydata = msg.data.data.clone();
+        debug!("Processing command: {:?}", msg.data.id);
+        /* TODO
+        match msg.data.id {
+            HidIoCommandId::SupportedIDs => {
+                let ids = supported_ids(false)
+                    .iter()
+                    .map(|x| *x as u16)
+                    .collect::<Vec<u16>>();
+                trace!("Acking SupportedIDs");
+                msg.send_ack(sender.clone(), as_u8_slice(&ids).to_vec());
+            }
+            HidIoCommandId::GetProperties => {
+                use crate::built_info;
+                let property: HidIoPropertyID = unsafe { std::mem::transmute(mydata[0]) };
+                info!("Get prop {:?}", property);
+                match property {
+                    HidIoPropertyID::HidIoMajor => {
+                        let v = built_info::PKG_VERSION_MAJOR.parse::<u16>().unwrap();
+                        msg.send_ack(sender.clone(), as_u8_slice(&[v]).to_vec());
+                    }
+                    HidIoPropertyID::HidIoMinor => {
+                        let v = built_info::PKG_VERSION_MINOR.parse::<u16>().unwrap();
+                        msg.send_ack(sender.clone(), as_u8_slice(&[v]).to_vec());
+                    }
+                    HidIoPropertyID::HidIoPatch => {
+                        let v = built_info::PKG_VERSION_PATCH.parse::<u16>().unwrap();
+                        msg.send_ack(sender.clone(), as_u8_slice(&[v]).to_vec());
+                    }
+                    HidIoPropertyID::HostOS => {
+                        let os = match built_info::CFG_OS {
+                            "windows" => HostOSID::Windows,
+                            "macos" => HostOSID::Mac,
+                            "ios" => HostOSID::IOS,
+                            "linux" => HostOSID::Linux,
+                            "android" => HostOSID::Android,
+                            "freebsd" => HostOSID::FreeBSD,
+                            "openbsd" => HostOSID::OpenBSD,
+                            "netbsd" => HostOSID::NetBSD,
+                            _ => HostOSID::Unknown,
+                        };
+                        msg.send_ack(sender.clone(), vec![os as u8]);
+                    }
+                    HidIoPropertyID::OSVersion => match sys_info::os_release() {
+                        Ok(version) => {
+                            msg.send_ack(sender.clone(), version.as_bytes().to_vec());
                         }
-                        HidIoPropertyID::OSVersion => match sys_info::os_release() {
-                            Ok(version) => {
-                                msg.send_ack(sender.clone(), version.as_bytes().to_vec());
-                            }
-                            Err(e) => {
-                                error!("OS Release retrieval failed: {}", e);
-                                msg.send_nak(sender.clone(), vec![]);
-                            }
-                        },
-                        HidIoPropertyID::HostName => {
-                            let name = built_info::PKG_NAME;
-                            msg.send_ack(sender.clone(), name.as_bytes().to_vec());
+                        Err(e) => {
+                            error!("OS Release retrieval failed: {}", e);
+                            msg.send_nak(sender.clone(), vec![]);
                         }
-                    };
-                    trace!("Acking GetProperties");
-                }
-                HidIoCommandId::HostMacro => {
-                    warn!("Host Macro not implemented");
-                    msg.send_nak(sender.clone(), vec![]);
-                }
-                HidIoCommandId::KLLState => {
-                    warn!("KLL State not implemented");
-                    msg.send_nak(sender.clone(), vec![]);
-                }
-                HidIoCommandId::OpenURL => {
-                    let s = String::from_utf8(mydata).unwrap();
-                    println!("Open url: {}", s);
-                    open::that(s).unwrap();
-                    trace!("Acking OpenURL");
-                    msg.send_ack(sender.clone(), vec![]);
-                }
-                HidIoCommandId::TerminalOut => {
-                    if msg.data.ptype == HidIoPacketType::Data {
-                        trace!("Acking TerminalOut");
-                        msg.send_ack(sender.clone(), vec![]);
+                    },
+                    HidIoPropertyID::HostName => {
+                        let name = built_info::PKG_NAME;
+                        msg.send_ack(sender.clone(), name.as_bytes().to_vec());
                     }
+                };
+                trace!("Acking GetProperties");
+            }
+            HidIoCommandId::HostMacro => {
+                warn!("Host Macro not implemented");
+                msg.send_nak(sender.clone(), vec![]);
+            }
+            HidIoCommandId::KLLState => {
+                warn!("KLL State not implemented");
+                msg.send_nak(sender.clone(), vec![]);
+            }
+            HidIoCommandId::OpenURL => {
+                let s = String::from_utf8(mydata).unwrap();
+                println!("Open url: {}", s);
+                open::that(s).unwrap();
+                trace!("Acking OpenURL");
+                msg.send_ack(sender.clone(), vec![]);
+            }
+            HidIoCommandId::TerminalOut => {
+                if msg.data.ptype == HidIoPacketType::Data {
+                    trace!("Acking TerminalOut");
+                    msg.send_ack(sender.clone(), vec![]);
                 }
-                _ => {}
             }
-                */
+            _ => {}
         }
-    });
+            */
+    }
+}
 
-    // NAK unsupported command ids
-    let mailbox2 = mailbox.clone();
-    let naks = tokio::spawn(async move {
-        // Setup receiver stream
-        let sender = mailbox2.clone().sender.clone();
-        let receiver = sender.clone().subscribe();
-        tokio::pin! {
-            let stream = receiver.into_stream()
-                .filter(Result::is_ok).map(Result::unwrap)
-                .take_while(|msg|
-                    msg.src != mailbox::Address::DropSubscription &&
-                    msg.dst != mailbox::Address::CancelAllSubscriptions
-                )
-                .filter(|msg| !(
-                    supported_ids(true).contains(&msg.data.id) ||
-                    api::supported_ids().contains(&msg.data.id) ||
-                    device::supported_ids(true).contains(&msg.data.id)
-                ))
-                .filter(|msg| msg.data.ptype == HidIoPacketType::Data || msg.data.ptype == HidIoPacketType::NaData);
-        }
+/// NAK unsupported command ids
+async fn unsupported(mailbox: mailbox::Mailbox) {
+    // Setup receiver stream
+    let sender = mailbox.sender.clone();
+    let receiver = sender.clone().subscribe();
+    tokio::pin! {
+        let stream = receiver.into_stream()
+            .filter(Result::is_ok).map(Result::unwrap)
+            .take_while(|msg|
+                msg.src != mailbox::Address::DropSubscription &&
+                msg.dst != mailbox::Address::CancelAllSubscriptions
+            )
+            .filter(|msg| !(
+                supported_ids(true).contains(&msg.data.id) ||
+                api::supported_ids().contains(&msg.data.id) ||
+                device::supported_ids(true).contains(&msg.data.id)
+            ))
+            // Ids unknown to hid-io-core may be sent to devices as raw commands (newer firmware)
+            .filter(|msg| msg.data.unknown_id.is_none() || !matches!(msg.dst, mailbox::Address::DeviceHidio { .. }))
+            .filter(|msg| msg.data.ptype == HidIoPacketType::Data || msg.data.ptype == HidIoPacketType::NaData);
+    }
 
-        // Process filtered message stream
-        while let Some(msg) = stream.next().await {
-            warn!("Unknown command ID: {:?} ({})", msg.data.id, msg.data.ptype);
-            // Only send NAK with Data packets (NaData packets don't have acknowledgements, so just
-            // warn)
-            if msg.data.ptype == HidIoPacketType::Data {
-                msg.send_nak(sender.clone(), vec![]);
-            }
+    // Process filtered message stream
+    while let Some(msg) = stream.next().await {
+        let span = msg.span("module.unsupported");
+        let _enter = span.enter();
+        warn!(
+            "Unknown command ID: {:?} {:#x} ({})",
+            msg.data.id,
+            msg.data.raw_id(),
+            msg.data.ptype
+        );
+        // Only send NAK with Data packets (NaData packets don't have acknowledgements, so just
+        // warn)
+        if msg.data.ptype == HidIoPacketType::Data {
+            msg.send_nak(sender.clone(), vec![]);
         }
-    });
-
-    let (_, _, _, _, _) = tokio::join!(
-        daemonnode::initialize(mailbox.clone()),
-        displayserver::initialize(mailbox.clone()),
-        naks,
-        data,
-        vhid::initialize(mailbox.clone()),
-    );
+    }
 }
 
 /// Used when displayserver feature is disabled
@@ -208,12 +319,19 @@ mod displayserver {
     pub fn supported_ids() -> Vec<HidIoCommandId> {
         vec![]
     }
+    pub fn type_sensitive(_seat: &str, _string: zeroize::Zeroizing<String>) -> Result<(), String> {
+        Err("Display server support is not enabled".to_string())
+    }
 }
 
-/// Used when displayserver feature is disabled
-#[cfg(not(feature = "dev-capture"))]
+/// Used when vhid feature is disabled
+#[cfg(not(feature = "vhid"))]
 mod vhid {
     use crate::mailbox;
+    use hid_io_protocol::HidIoCommandId;
 
     pub async fn initialize(_mailbox: mailbox::Mailbox) {}
+    pub fn supported_ids() -> Vec<HidIoCommandId> {
+        vec![]
+    }
 }
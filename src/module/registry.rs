@@ -0,0 +1,557 @@
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * This file is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This file is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this file.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// ----- Crates -----
+
+use crate::mailbox;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+
+// ----- Types -----
+
+/// Module entry point
+/// Usually a wrapper around the module's async initialize function, e.g.
+/// |mailbox| Box::pin(displayserver::initialize(mailbox))
+pub type ModuleInit = fn(mailbox::Mailbox) -> Pin<Box<dyn Future<Output = ()> + Send>>;
+
+// ----- Enumerations -----
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ModuleStatus {
+    /// Registered, not started yet
+    Pending,
+    /// Module task is running
+    Running,
+    /// Module task has finished (e.g. setup done, work handed off to other tasks)
+    Finished,
+    /// Module task panicked
+    Failed,
+    /// Module has been disabled
+    Disabled,
+    /// A dependency is not running
+    Blocked,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ModuleError {
+    /// Module name has already been registered
+    AlreadyRegistered(String),
+    /// Module name is not registered
+    UnknownModule(String),
+    /// Dependency is not registered
+    UnknownDependency { module: String, dependency: String },
+    /// Dependencies form a cycle, includes the modules that could not be ordered
+    DependencyCycle(Vec<String>),
+    /// Registry has not been started (no mailbox yet)
+    NotStarted,
+    /// Module (or a running dependent) does its work outside of its task and can't be stopped
+    NotStoppable(String),
+}
+
+impl std::fmt::Display for ModuleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ModuleError::AlreadyRegistered(name) => {
+                write!(f, "Module already registered: {}", name)
+            }
+            ModuleError::UnknownModule(name) => write!(f, "Unknown module: {}", name),
+            ModuleError::UnknownDependency { module, dependency } => {
+                write!(
+                    f,
+                    "Module {} depends on unknown module {}",
+                    module, dependency
+                )
+            }
+            ModuleError::DependencyCycle(names) => {
+                write!(f, "Module dependency cycle: {:?}", names)
+            }
+            ModuleError::NotStarted => write!(f, "Module registry has not been started"),
+            ModuleError::NotStoppable(name) => {
+                write!(
+                    f,
+                    "Module {} can't be stopped while hid-io-core is running",
+                    name
+                )
+            }
+        }
+    }
+}
+
+// ----- Structs -----
+
+/// Externally visible module information
+#[derive(Clone, Debug)]
+pub struct ModuleInfo {
+    pub name: &'static str,
+    pub dependencies: Vec<&'static str>,
+    pub enabled: bool,
+    pub stoppable: bool,
+    pub status: ModuleStatus,
+}
+
+struct ModuleEntry {
+    name: &'static str,
+    dependencies: Vec<&'static str>,
+    init: ModuleInit,
+    enabled: bool,
+    // Module only runs inside its task, dropping the task stops it
+    stoppable: bool,
+    status: ModuleStatus,
+    // Incremented on each start, used to ignore stale task completions
+    generation: u64,
+    cancel: Option<tokio::sync::oneshot::Sender<()>>,
+}
+
+/// Module registry
+///
+/// Can be safely cloned, all clones refer to the same set of modules.
+#[derive(Clone, Default)]
+pub struct ModuleRegistry {
+    modules: Arc<RwLock<Vec<ModuleEntry>>>,
+    mailbox: Arc<RwLock<Option<mailbox::Mailbox>>>,
+}
+
+impl ModuleRegistry {
+    pub fn new() -> ModuleRegistry {
+        ModuleRegistry {
+            ..Default::default()
+        }
+    }
+
+    /// Register a module
+    /// Dependencies are validated when the registry is started.
+    pub fn register(
+        &self,
+        name: &'static str,
+        dependencies: &[&'static str],
+        init: ModuleInit,
+    ) -> Result<(), ModuleError> {
+        self.add(name, dependencies, init, true)
+    }
+
+    /// Register a module that hands its work off to dedicated threads or detached tasks
+    /// Cancelling the module task would not stop that work, so once started the module can't be
+    /// disabled (or blocked by a disabled dependency).
+    pub fn register_unstoppable(
+        &self,
+        name: &'static str,
+        dependencies: &[&'static str],
+        init: ModuleInit,
+    ) -> Result<(), ModuleError> {
+        self.add(name, dependencies, init, false)
+    }
+
+    fn add(
+        &self,
+        name: &'static str,
+        dependencies: &[&'static str],
+        init: ModuleInit,
+        stoppable: bool,
+    ) -> Result<(), ModuleError> {
+        let mut modules = self.modules.write().unwrap();
+        if modules.iter().any(|m| m.name == name) {
+            return Err(ModuleError::AlreadyRegistered(name.to_string()));
+        }
+        modules.push(ModuleEntry {
+            name,
+            dependencies: dependencies.to_vec(),
+            init,
+            enabled: true,
+            stoppable,
+            status: ModuleStatus::Pending,
+            generation: 0,
+            cancel: None,
+        });
+        Ok(())
+    }
+
+    /// Determine module start order
+    /// Dependencies always come first, otherwise registration order is kept.
+    pub fn start_order(&self) -> Result<Vec<&'static str>, ModuleError> {
+        let modules = self.modules.read().unwrap();
+
+        // Validate dependencies
+        for module in modules.iter() {
+            for dep in &module.dependencies {
+                if !modules.iter().any(|m| m.name == *dep) {
+                    return Err(ModuleError::UnknownDependency {
+                        module: module.name.to_string(),
+                        dependency: dep.to_string(),
+                    });
+                }
+            }
+        }
+
+        let mut order: Vec<&'static str> = vec![];
+        while order.len() < modules.len() {
+            // Pick the first module with all dependencies already ordered
+            let next = modules.iter().find(|m| {
+                !order.contains(&m.name) && m.dependencies.iter().all(|dep| order.contains(dep))
+            });
+            match next {
+                Some(module) => order.push(module.name),
+                None => {
+                    return Err(ModuleError::DependencyCycle(
+                        modules
+                            .iter()
+                            .filter(|m| !order.contains(&m.name))
+                            .map(|m| m.name.to_string())
+                            .collect(),
+                    ));
+                }
+            }
+        }
+        Ok(order)
+    }
+
+    /// Start all enabled modules in dependency order
+    pub fn start(&self, mailbox: mailbox::Mailbox) -> Result<(), ModuleError> {
+        let order = self.start_order()?;
+        *self.mailbox.write().unwrap() = Some(mailbox);
+        for name in order {
+            self.start_module(name)?;
+        }
+        Ok(())
+    }
+
+    /// Enable a module, starting it (and any blocked dependents) if possible
+    pub fn enable(&self, name: &str) -> Result<ModuleInfo, ModuleError> {
+        self.entry_mut(name, |entry| entry.enabled = true)?;
+        if self.mailbox.read().unwrap().is_none() {
+            return Err(ModuleError::NotStarted);
+        }
+
+        // Start anything that was waiting on this module, dependencies first
+        for module in self.start_order()? {
+            let status = self.info(module)?.status;
+            if status == ModuleStatus::Blocked
+                || status == ModuleStatus::Disabled
+                || status == ModuleStatus::Pending
+                || (status == ModuleStatus::Failed && module == name)
+            {
+                self.start_module(module)?;
+            }
+        }
+        self.info(name)
+    }
+
+    /// Disable a module, stopping it and any module that depends on it
+    ///
+    /// NOTE: Module tasks are cancelled at their next await point. Fails with NotStoppable if the
+    ///       module, or a dependent that would have to be stopped, was registered as unstoppable
+    ///       and has already been started.
+    pub fn disable(&self, name: &str) -> Result<ModuleInfo, ModuleError> {
+        self.info(name)?;
+        if let Some(module) = self.unstoppable(name) {
+            return Err(ModuleError::NotStoppable(module.to_string()));
+        }
+        self.entry_mut(name, |entry| {
+            entry.enabled = false;
+        })?;
+        self.stop_module(name, ModuleStatus::Disabled);
+        self.block_dependents(name);
+        self.info(name)
+    }
+
+    /// Status of a single module
+    pub fn info(&self, name: &str) -> Result<ModuleInfo, ModuleError> {
+        self.modules
+            .read()
+            .unwrap()
+            .iter()
+            .find(|m| m.name == name)
+            .map(|m| ModuleInfo {
+                name: m.name,
+                dependencies: m.dependencies.clone(),
+                enabled: m.enabled,
+                stoppable: m.stoppable,
+                status: m.status,
+            })
+            .ok_or_else(|| ModuleError::UnknownModule(name.to_string()))
+    }
+
+    /// Status of all modules (registration order)
+    pub fn list(&self) -> Vec<ModuleInfo> {
+        self.modules
+            .read()
+            .unwrap()
+            .iter()
+            .map(|m| ModuleInfo {
+                name: m.name,
+                dependencies: m.dependencies.clone(),
+                enabled: m.enabled,
+                stoppable: m.stoppable,
+                status: m.status,
+            })
+            .collect()
+    }
+
+    /// Returns true while at least one module is running
+    pub fn running(&self) -> bool {
+        self.modules
+            .read()
+            .unwrap()
+            .iter()
+            .any(|m| m.status == ModuleStatus::Running)
+    }
+
+    fn entry_mut<F>(&self, name: &str, func: F) -> Result<(), ModuleError>
+    where
+        F: FnOnce(&mut ModuleEntry),
+    {
+        let mut modules = self.modules.write().unwrap();
+        match modules.iter_mut().find(|m| m.name == name) {
+            Some(entry) => {
+                func(entry);
+                Ok(())
+            }
+            None => Err(ModuleError::UnknownModule(name.to_string())),
+        }
+    }
+
+    fn start_module(&self, name: &'static str) -> Result<(), ModuleError> {
+        let mailbox = match self.mailbox.read().unwrap().clone() {
+            Some(mailbox) => mailbox,
+            None => {
+                return Err(ModuleError::NotStarted);
+            }
+        };
+
+        let mut modules = self.modules.write().unwrap();
+        let deps_running = {
+            let entry = modules.iter().find(|m| m.name == name).unwrap();
+            entry.dependencies.iter().all(|dep| {
+                modules.iter().any(|m| {
+                    m.name == *dep
+                        && (m.status == ModuleStatus::Running || m.status == ModuleStatus::Finished)
+                })
+            })
+        };
+        let entry = modules.iter_mut().find(|m| m.name == name).unwrap();
+
+        if !entry.enabled {
+            entry.status = ModuleStatus::Disabled;
+            return Ok(());
+        }
+        if !deps_running {
+            info!("Module {} blocked, dependencies not running", name);
+            entry.status = ModuleStatus::Blocked;
+            return Ok(());
+        }
+
+        info!("Starting module {}", name);
+        let (cancel, cancelled) = tokio::sync::oneshot::channel::<()>();
+        let init = entry.init;
+        entry.generation += 1;
+        entry.status = ModuleStatus::Running;
+        entry.cancel = Some(cancel);
+        let generation = entry.generation;
+
+        let rt = mailbox.rt.clone();
+        let task = rt.spawn(async move {
+            tokio::select! {
+                _ = init(mailbox) => {}
+                _ = cancelled => {}
+            }
+        });
+
+        // Watch for task completion
+        let registry = self.clone();
+        rt.spawn(async move {
+            let status = match task.await {
+                Ok(_) => ModuleStatus::Finished,
+                Err(e) => {
+                    error!("Module {} failed: {}", name, e);
+                    ModuleStatus::Failed
+                }
+            };
+            registry.finished(name, generation, status);
+        });
+        Ok(())
+    }
+
+    fn stop_module(&self, name: &str, status: ModuleStatus) {
+        let mut modules = self.modules.write().unwrap();
+        if let Some(entry) = modules.iter_mut().find(|m| m.name == name) {
+            if let Some(cancel) = entry.cancel.take() {
+                info!("Stopping module {}", name);
+                cancel.send(()).ok();
+            }
+            entry.status = status;
+        }
+    }
+
+    /// Finds a started, unstoppable module that stopping this module would also stop
+    fn unstoppable(&self, name: &str) -> Option<&'static str> {
+        let modules = self.modules.read().unwrap();
+        let mut pending = vec![name];
+        let mut visited: Vec<&str> = vec![];
+        while let Some(name) = pending.pop() {
+            if visited.contains(&name) {
+                continue;
+            }
+            visited.push(name);
+            for module in modules.iter() {
+                let started = module.status == ModuleStatus::Running
+                    || module.status == ModuleStatus::Finished;
+                if module.name == name && started && !module.stoppable {
+                    return Some(module.name);
+                }
+                if started && module.dependencies.iter().any(|dep| *dep == name) {
+                    pending.push(module.name);
+                }
+            }
+        }
+        None
+    }
+
+    fn block_dependents(&self, name: &str) {
+        let dependents = self
+            .modules
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|m| m.dependencies.iter().any(|dep| *dep == name))
+            .filter(|m| m.status == ModuleStatus::Running || m.status == ModuleStatus::Finished)
+            // Can't be stopped, keeps running (and reporting so) without the dependency
+            .filter(|m| m.stoppable)
+            .map(|m| m.name)
+            .collect::<Vec<&'static str>>();
+        for dependent in dependents {
+            self.stop_module(dependent, ModuleStatus::Blocked);
+            self.block_dependents(dependent);
+        }
+    }
+
+    fn finished(&self, name: &'static str, generation: u64, status: ModuleStatus) {
+        {
+            let mut modules = self.modules.write().unwrap();
+            let entry = match modules.iter_mut().find(|m| m.name == name) {
+                Some(entry) => entry,
+                None => {
+                    return;
+                }
+            };
+
+            // Ignore completions of a previous run, or of a module that was stopped on purpose
+            if entry.generation != generation || entry.status != ModuleStatus::Running {
+                return;
+            }
+            info!("Module {} finished: {:?}", name, status);
+            entry.status = status;
+            entry.cancel = None;
+        }
+
+        // Dependents can no longer rely on a failed module
+        if status == ModuleStatus::Failed {
+            self.block_dependents(name);
+        }
+    }
+}
+
+// ----- Tests -----
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn noop(_mailbox: mailbox::Mailbox) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        Box::pin(async {})
+    }
+
+    #[test]
+    fn start_order_test() {
+        let registry = ModuleRegistry::new();
+        registry
+            .register("unicode", &["displayserver"], noop)
+            .unwrap();
+        registry
+            .register("profiles", &["window", "unicode"], noop)
+            .unwrap();
+        registry.register("displayserver", &[], noop).unwrap();
+        registry
+            .register("window", &["displayserver"], noop)
+            .unwrap();
+        assert_eq!(
+            registry.start_order().unwrap(),
+            vec!["displayserver", "unicode", "window", "profiles"]
+        );
+    }
+
+    #[test]
+    fn start_order_errors_test() {
+        let registry = ModuleRegistry::new();
+        registry.register("a", &["b"], noop).unwrap();
+        assert_eq!(
+            registry.register("a", &[], noop),
+            Err(ModuleError::AlreadyRegistered("a".to_string()))
+        );
+        assert_eq!(
+            registry.start_order(),
+            Err(ModuleError::UnknownDependency {
+                module: "a".to_string(),
+                dependency: "b".to_string(),
+            })
+        );
+
+        registry.register("b", &["a"], noop).unwrap();
+        registry.register("c", &[], noop).unwrap();
+        assert_eq!(
+            registry.start_order(),
+            Err(ModuleError::DependencyCycle(vec![
+                "a".to_string(),
+                "b".to_string()
+            ]))
+        );
+    }
+
+    #[test]
+    fn unstoppable_test() {
+        let registry = ModuleRegistry::new();
+        registry.register("displayserver", &[], noop).unwrap();
+        registry
+            .register_unstoppable("unicode", &["displayserver"], noop)
+            .unwrap();
+        registry.register("window", &["unicode"], noop).unwrap();
+
+        // Not started yet, nothing to stop
+        assert_eq!(registry.unstoppable("displayserver"), None);
+
+        for name in &["displayserver", "unicode", "window"] {
+            registry
+                .entry_mut(name, |entry| entry.status = ModuleStatus::Running)
+                .unwrap();
+        }
+        assert_eq!(registry.unstoppable("window"), None);
+        assert_eq!(registry.unstoppable("unicode"), Some("unicode"));
+        assert_eq!(registry.unstoppable("displayserver"), Some("unicode"));
+        assert_eq!(
+            registry.disable("displayserver").err(),
+            Some(ModuleError::NotStoppable("unicode".to_string()))
+        );
+        let info = registry.info("displayserver").unwrap();
+        assert!(info.enabled);
+        assert_eq!(info.status, ModuleStatus::Running);
+
+        // Dependents that can't be stopped are not reported as blocked
+        registry.block_dependents("displayserver");
+        assert_eq!(
+            registry.info("unicode").unwrap().status,
+            ModuleStatus::Running
+        );
+    }
+}
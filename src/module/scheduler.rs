@@ -0,0 +1,455 @@
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * This file is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This file is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this file.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// ----- Crates -----
+
+use crate::mailbox;
+use crate::module::batch::{self, BatchCommand};
+use crate::module::config_path;
+use crate::RUNNING;
+use chrono::{Datelike, Local, Timelike};
+use lazy_static::lazy_static;
+use std::sync::atomic::Ordering;
+use std::sync::RwLock;
+
+// ----- Consts -----
+
+/// Schedule file name, stored in the hid-io-core config directory
+const SCHEDULE_FILE: &str = "schedule";
+
+lazy_static! {
+    static ref SCHEDULE: RwLock<Schedule> = RwLock::new(Schedule::default());
+}
+
+// ----- Enumerations -----
+
+#[derive(Debug)]
+pub enum ScheduleError {
+    /// Schedule entry could not be parsed
+    Parse(String),
+    /// No entry with the given id
+    UnknownEntry(u32),
+    /// Schedule file could not be written
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScheduleError::Parse(reason) => write!(f, "Invalid schedule entry: {}", reason),
+            ScheduleError::UnknownEntry(id) => write!(f, "Unknown schedule entry: {}", id),
+            ScheduleError::Io(e) => write!(f, "Could not save schedule: {}", e),
+        }
+    }
+}
+
+// ----- Structs -----
+
+/// Single cron field, bit n is set if value n matches
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct CronField(u64);
+
+impl CronField {
+    /// Parses a cron field
+    /// Supports *, values, lists (a,b), ranges (a-b) and steps (*/n, a-b/n)
+    fn parse(field: &str, min: u32, max: u32) -> Result<CronField, ScheduleError> {
+        let invalid = || ScheduleError::Parse(format!("Invalid field '{}'", field));
+        let value = |v: &str| -> Result<u32, ScheduleError> {
+            match v.parse::<u32>() {
+                Ok(v) if v >= min && v <= max => Ok(v),
+                _ => Err(invalid()),
+            }
+        };
+
+        let mut bits = 0;
+        for part in field.split(',') {
+            let (range, step) = match part.find('/') {
+                Some(pos) => match part[pos + 1..].parse::<u32>() {
+                    Ok(step) if step > 0 => (&part[..pos], step),
+                    _ => {
+                        return Err(invalid());
+                    }
+                },
+                None => (part, 1),
+            };
+            let (start, end) = if range == "*" {
+                (min, max)
+            } else if let Some(pos) = range.find('-') {
+                (value(&range[..pos])?, value(&range[pos + 1..])?)
+            } else {
+                let start = value(range)?;
+                (start, start)
+            };
+            if start > end {
+                return Err(invalid());
+            }
+            for v in (start..=end).step_by(step as usize) {
+                bits |= 1 << v;
+            }
+        }
+        Ok(CronField(bits))
+    }
+
+    fn contains(&self, value: u32) -> bool {
+        self.0 & (1 << value) != 0
+    }
+}
+
+/// Cron expression (minute hour day-of-month month day-of-week)
+#[derive(Clone, Debug, PartialEq)]
+struct CronExpr {
+    minute: CronField,
+    hour: CronField,
+    day: CronField,
+    month: CronField,
+    weekday: CronField,
+    /// Set when day-of-month is restricted (not *)
+    day_restricted: bool,
+    /// Set when day-of-week is restricted (not *)
+    weekday_restricted: bool,
+}
+
+impl CronExpr {
+    fn parse(fields: &[&str]) -> Result<CronExpr, ScheduleError> {
+        if fields.len() != 5 {
+            return Err(ScheduleError::Parse(
+                "Expected 5 time fields (minute hour day month weekday)".to_string(),
+            ));
+        }
+        // Sunday may be given as 0 or 7
+        let mut weekday = CronField::parse(fields[4], 0, 7)?;
+        if weekday.contains(7) {
+            weekday.0 = (weekday.0 | 1) & !(1 << 7);
+        }
+        Ok(CronExpr {
+            minute: CronField::parse(fields[0], 0, 59)?,
+            hour: CronField::parse(fields[1], 0, 23)?,
+            day: CronField::parse(fields[2], 1, 31)?,
+            month: CronField::parse(fields[3], 1, 12)?,
+            weekday,
+            day_restricted: fields[2] != "*",
+            weekday_restricted: fields[4] != "*",
+        })
+    }
+
+    /// Weekday is days since Sunday (0-6)
+    /// As with cron, if both day-of-month and day-of-week are restricted either may match.
+    fn matches(&self, minute: u32, hour: u32, day: u32, month: u32, weekday: u32) -> bool {
+        let day_match = if self.day_restricted && self.weekday_restricted {
+            self.day.contains(day) || self.weekday.contains(weekday)
+        } else {
+            self.day.contains(day) && self.weekday.contains(weekday)
+        };
+        self.minute.contains(minute)
+            && self.hour.contains(hour)
+            && self.month.contains(month)
+            && day_match
+    }
+}
+
+/// Schedule entry
+///
+/// Uses a crontab style line:
+/// <minute> <hour> <day> <month> <weekday> <target> <command...>
+///
+/// target is * (all devices), or a comma separated list of vid=<hex> and name=<pattern>
+/// command is one of:
+///  sleep
+///  text <widget> <text...>
+///  raw <id> [<hex byte>...]
+///
+/// e.g. 0 22 * * * vid=1c11 raw 0x21 01000000
+#[derive(Clone, Debug)]
+struct Entry {
+    id: u32,
+    line: String,
+    cron: CronExpr,
+    filter: mailbox::NodeFilter,
+    command: BatchCommand,
+}
+
+impl Entry {
+    fn parse(id: u32, line: &str) -> Result<Entry, ScheduleError> {
+        let line = line.trim();
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 7 {
+            return Err(ScheduleError::Parse(
+                "Expected <minute> <hour> <day> <month> <weekday> <target> <command>".to_string(),
+            ));
+        }
+        let cron = CronExpr::parse(&fields[0..5])?;
+        let filter = fields[5]
+            .parse::<mailbox::NodeFilter>()
+            .map_err(ScheduleError::Parse)?;
+        let command = parse_command(&fields[6..])?;
+
+        Ok(Entry {
+            id,
+            line: line.to_string(),
+            cron,
+            filter,
+            command,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct Schedule {
+    entries: Vec<Entry>,
+    next_id: u32,
+}
+
+// ----- Functions -----
+
+/// Parses the command part of an entry
+fn parse_command(fields: &[&str]) -> Result<BatchCommand, ScheduleError> {
+    match fields {
+        ["sleep"] => Ok(BatchCommand::Sleep),
+        ["text", widget, text @ ..] => Ok(BatchCommand::DisplayText {
+            widget: widget
+                .parse::<u16>()
+                .map_err(|_| ScheduleError::Parse(format!("Invalid widget '{}'", widget)))?,
+            text: text.join(" "),
+        }),
+        ["raw", id, data @ ..] => {
+            let id = u32::from_str_radix(id.trim_start_matches("0x"), 16)
+                .ok()
+                .ok_or_else(|| ScheduleError::Parse(format!("Invalid command id '{}'", id)))?;
+            let hex = data.concat();
+            if hex.len() % 2 != 0 {
+                return Err(ScheduleError::Parse(format!("Invalid data '{}'", hex)));
+            }
+            let data = (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+                .collect::<Result<Vec<u8>, _>>()
+                .map_err(|_| ScheduleError::Parse(format!("Invalid data '{}'", hex)))?;
+            Ok(BatchCommand::Raw { id, data })
+        }
+        _ => Err(ScheduleError::Parse(format!(
+            "Unknown command '{}'",
+            fields.join(" ")
+        ))),
+    }
+}
+
+/// Loads the schedule file, invalid entries are skipped
+fn load() {
+    let path = match config_path(SCHEDULE_FILE) {
+        Some(path) => path,
+        None => {
+            warn!("Could not determine config directory, schedule will not be persisted");
+            return;
+        }
+    };
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return;
+        }
+        Err(e) => {
+            error!("Could not read schedule {:?}: {}", path, e);
+            return;
+        }
+    };
+
+    let mut schedule = SCHEDULE.write().unwrap();
+    for (num, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        match Entry::parse(schedule.next_id, line) {
+            Ok(entry) => {
+                schedule.entries.push(entry);
+                schedule.next_id += 1;
+            }
+            Err(e) => {
+                error!("{:?}:{} {}", path, num + 1, e);
+            }
+        }
+    }
+    info!(
+        "Loaded {} schedule entries from {:?}",
+        schedule.entries.len(),
+        path
+    );
+}
+
+/// Writes the schedule file
+fn save(schedule: &Schedule) -> Result<(), ScheduleError> {
+    let path = match config_path(SCHEDULE_FILE) {
+        Some(path) => path,
+        None => {
+            return Ok(());
+        }
+    };
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(ScheduleError::Io)?;
+    }
+    let mut contents = String::new();
+    for entry in &schedule.entries {
+        contents.push_str(&entry.line);
+        contents.push('\n');
+    }
+    std::fs::write(&path, contents).map_err(ScheduleError::Io)
+}
+
+/// Current schedule entries (id, line)
+pub fn entries() -> Vec<(u32, String)> {
+    SCHEDULE
+        .read()
+        .unwrap()
+        .entries
+        .iter()
+        .map(|entry| (entry.id, entry.line.clone()))
+        .collect()
+}
+
+/// Adds a schedule entry, see Entry for the format
+/// The schedule file is updated.
+pub fn add(line: &str) -> Result<u32, ScheduleError> {
+    let mut schedule = SCHEDULE.write().unwrap();
+    let entry = Entry::parse(schedule.next_id, line)?;
+    let id = entry.id;
+    schedule.entries.push(entry);
+    schedule.next_id += 1;
+    save(&schedule)?;
+    Ok(id)
+}
+
+/// Removes a schedule entry
+/// The schedule file is updated.
+pub fn remove(id: u32) -> Result<(), ScheduleError> {
+    let mut schedule = SCHEDULE.write().unwrap();
+    match schedule.entries.iter().position(|entry| entry.id == id) {
+        Some(pos) => {
+            schedule.entries.remove(pos);
+            save(&schedule)
+        }
+        None => Err(ScheduleError::UnknownEntry(id)),
+    }
+}
+
+/// Scheduler module
+/// Checks the schedule once per (local time) minute and sends any matching commands.
+pub async fn initialize(mailbox: mailbox::Mailbox) {
+    info!("Initializing scheduler...");
+    load();
+
+    let mut last_minute = None;
+    while RUNNING.load(Ordering::SeqCst) {
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+
+        let now = Local::now();
+        let minute = now.timestamp() / 60;
+        if last_minute == Some(minute) {
+            continue;
+        }
+        last_minute = Some(minute);
+
+        let due: Vec<Entry> = SCHEDULE
+            .read()
+            .unwrap()
+            .entries
+            .iter()
+            .filter(|entry| {
+                entry.cron.matches(
+                    now.minute(),
+                    now.hour(),
+                    now.day(),
+                    now.month(),
+                    now.weekday().num_days_from_sunday(),
+                )
+            })
+            .cloned()
+            .collect();
+        for entry in due {
+            info!("Schedule {}: {}", entry.id, entry.line);
+            let results = batch::run(
+                mailbox.clone(),
+                mailbox::Address::Module,
+                entry.filter,
+                None,
+                entry.command,
+            )
+            .await;
+            for result in results {
+                if let Err(e) = result.result {
+                    warn!("Schedule {} failed on uid:{}: {}", entry.id, result.uid, e);
+                }
+            }
+        }
+    }
+}
+
+// ----- Tests -----
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hid_io_protocol::HidIoCommandId;
+
+    #[test]
+    fn cron_test() {
+        // 22:00 every day
+        let cron = CronExpr::parse(&["0", "22", "*", "*", "*"]).unwrap();
+        assert!(cron.matches(0, 22, 14, 3, 2));
+        assert!(!cron.matches(1, 22, 14, 3, 2));
+
+        // Every 15 minutes during working hours on weekdays
+        let cron = CronExpr::parse(&["*/15", "9-17", "*", "*", "1-5"]).unwrap();
+        assert!(cron.matches(45, 9, 1, 1, 1));
+        assert!(!cron.matches(50, 9, 1, 1, 1));
+        assert!(!cron.matches(45, 9, 1, 1, 0));
+
+        // Sunday as 7, either day restriction matches
+        let cron = CronExpr::parse(&["0", "8", "1", "*", "7"]).unwrap();
+        assert!(cron.matches(0, 8, 1, 6, 3));
+        assert!(cron.matches(0, 8, 9, 6, 0));
+        assert!(!cron.matches(0, 8, 9, 6, 3));
+
+        assert!(CronExpr::parse(&["60", "*", "*", "*", "*"]).is_err());
+        assert!(CronExpr::parse(&["5-1", "*", "*", "*", "*"]).is_err());
+        assert!(CronExpr::parse(&["*", "*", "*", "*"]).is_err());
+    }
+
+    #[test]
+    fn entry_test() {
+        let entry = Entry::parse(0, "0 22 * * * vid=1c11 raw 0x21 0100 0000").unwrap();
+        assert_eq!(entry.filter.vendor_id, Some(0x1c11));
+        match &entry.command {
+            BatchCommand::Raw { id, data } => {
+                assert_eq!(*id, HidIoCommandId::PixelSetting as u32);
+                assert_eq!(*data, vec![0x01, 0x00, 0x00, 0x00]);
+            }
+            _ => panic!("Unexpected command {:?}", entry.command),
+        }
+
+        let entry = Entry::parse(1, "30 7 * * 1-5 name=*K-Type* text 0 Good morning").unwrap();
+        assert_eq!(entry.filter.name, Some("*K-Type*".to_string()));
+        match &entry.command {
+            BatchCommand::DisplayText { widget, text } => {
+                assert_eq!(*widget, 0);
+                assert_eq!(text, "Good morning");
+            }
+            _ => panic!("Unexpected command {:?}", entry.command),
+        }
+
+        assert!(Entry::parse(2, "0 22 * * * * sleep").is_ok());
+        assert!(Entry::parse(3, "0 22 * * * * dim").is_err());
+        assert!(Entry::parse(4, "0 22 * * * foo=1 sleep").is_err());
+    }
+}
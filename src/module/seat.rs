@@ -0,0 +1,125 @@
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * This file is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This file is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this file.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// ----- Consts -----
+
+/// Seat used when a device is not assigned to any seat
+/// Windows and macOS only have a single interactive console session, every device belongs to
+/// the default seat there.
+pub const DEFAULT_SEAT: &str = "seat0";
+
+// ----- Structs -----
+
+/// Active session of a seat
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SeatSession {
+    /// logind session id
+    pub id: String,
+    /// Session type (e.g. x11, wayland, tty)
+    pub type_: String,
+    /// X11 display of the session (e.g. :1), empty if not an X11 session
+    pub display: String,
+}
+
+// ----- Functions -----
+
+/// Parses a property from the output of udevadm info --query=property or loginctl show-*
+#[cfg(target_os = "linux")]
+fn parse_property(output: &str, name: &str) -> Option<String> {
+    output.lines().find_map(|line| {
+        let mut field = line.trim().splitn(2, '=');
+        match (field.next(), field.next()) {
+            (Some(key), Some(val)) if key == name && !val.is_empty() => Some(val.to_string()),
+            _ => None,
+        }
+    })
+}
+
+/// Runs a command, returning stdout on success
+#[cfg(target_os = "linux")]
+fn output(cmd: &str, args: &[&str]) -> Option<String> {
+    std::process::Command::new(cmd)
+        .args(args)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Seat the device node (e.g. /dev/hidraw3 or /dev/input/event4) is attached to
+/// Devices are assigned to systemd-logind seats using loginctl attach (udev ID_SEAT property).
+#[cfg(target_os = "linux")]
+pub fn device_seat(devnode: &str) -> String {
+    output("udevadm", &["info", "--query=property", "--name", devnode])
+        .and_then(|output| parse_property(&output, "ID_SEAT"))
+        .unwrap_or_else(|| DEFAULT_SEAT.to_string())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn device_seat(_devnode: &str) -> String {
+    DEFAULT_SEAT.to_string()
+}
+
+/// Seat of the session hid-io-core is running in
+#[cfg(target_os = "linux")]
+pub fn own_seat() -> String {
+    std::env::var("XDG_SEAT").unwrap_or_else(|_| DEFAULT_SEAT.to_string())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn own_seat() -> String {
+    DEFAULT_SEAT.to_string()
+}
+
+/// Active session of the given seat
+#[cfg(target_os = "linux")]
+pub fn active_session(seat: &str) -> Option<SeatSession> {
+    let id = output("loginctl", &["show-seat", seat, "-p", "ActiveSession"])
+        .and_then(|output| parse_property(&output, "ActiveSession"))?;
+    let session = output(
+        "loginctl",
+        &["show-session", &id, "-p", "Type", "-p", "Display"],
+    )?;
+    Some(SeatSession {
+        type_: parse_property(&session, "Type").unwrap_or_default(),
+        display: parse_property(&session, "Display").unwrap_or_default(),
+        id,
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn active_session(_seat: &str) -> Option<SeatSession> {
+    None
+}
+
+// ----- Tests -----
+
+#[cfg(test)]
+#[cfg(target_os = "linux")]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_property_test() {
+        let output = "DEVNAME=/dev/hidraw3\nSUBSYSTEM=hidraw\nID_SEAT=seat1\nTAGS=:seat:\n";
+        assert_eq!(parse_property(output, "ID_SEAT"), Some("seat1".to_string()));
+        assert_eq!(parse_property(output, "ID_PATH"), None);
+
+        let output = "Type=x11\nDisplay=\n";
+        assert_eq!(parse_property(output, "Type"), Some("x11".to_string()));
+        assert_eq!(parse_property(output, "Display"), None);
+    }
+}
@@ -0,0 +1,273 @@
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * This file is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This file is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this file.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// ----- Crates -----
+
+use crate::mailbox;
+use crate::module::displayserver;
+use crate::module::seat;
+use heapless::consts::U0;
+use hid_io_protocol::commands::*;
+use hid_io_protocol::{HidIoCommandId, HidIoPacketType};
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::convert::TryInto;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::Mutex;
+use tokio::stream::StreamExt;
+use zeroize::Zeroizing;
+
+// ----- Consts -----
+
+/// Additional time given to the device to send its answer after the confirmation timeout
+const RESPONSE_GRACE: std::time::Duration = std::time::Duration::from_secs(2);
+
+lazy_static! {
+    /// Confirmations waiting for a device answer, by request id
+    static ref PENDING: Mutex<HashMap<u16, Pending>> = Mutex::new(HashMap::new());
+}
+
+/// Next confirmation request id
+static NEXT_REQUEST: AtomicU16 = AtomicU16::new(0);
+
+// ----- Enumerations -----
+
+#[derive(Debug)]
+pub enum SecureTypeError {
+    /// Device rejected the confirmation request
+    Request(h002a::Error),
+    /// User denied the confirmation on the device
+    Denied,
+    /// User did not answer in time
+    Timeout,
+    /// Prompt does not fit in a single packet
+    PromptTooLong(usize),
+    /// Device did not respond
+    NoResponse,
+    /// Command could not be sent
+    Command(CommandError),
+    /// String could not be typed
+    Output(String),
+}
+
+impl std::fmt::Display for SecureTypeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SecureTypeError::Request(e) => write!(f, "Confirmation rejected: {:?}", e),
+            SecureTypeError::Denied => write!(f, "Denied on device"),
+            SecureTypeError::Timeout => write!(f, "Not confirmed in time"),
+            SecureTypeError::PromptTooLong(len) => write!(f, "Prompt too long: {} bytes", len),
+            SecureTypeError::NoResponse => write!(f, "No response from device"),
+            SecureTypeError::Command(e) => write!(f, "Command failed: {:?}", e),
+            SecureTypeError::Output(e) => write!(f, "Could not type string: {}", e),
+        }
+    }
+}
+
+// ----- Structs -----
+
+/// Confirmation waiting for an answer from the device with the given uid
+struct Pending {
+    uid: u64,
+    answer: std::sync::mpsc::Sender<h002b::Answer>,
+}
+
+struct CommandInterface {
+    src: mailbox::Address,
+    dst: mailbox::Address,
+    mailbox: mailbox::Mailbox,
+    result: Option<Result<(), SecureTypeError>>,
+}
+
+impl Commands<mailbox::HidIoPacketBufferDataSize, U0> for CommandInterface {
+    fn tx_packetbuffer_send(
+        &mut self,
+        buf: &mut mailbox::HidIoPacketBuffer,
+    ) -> Result<(), CommandError> {
+        if let Some(rcvmsg) = self.mailbox.try_send_message(mailbox::Message {
+            src: self.src,
+            dst: self.dst,
+            data: buf.clone(),
+        })? {
+            // Handle ack/nak
+            self.rx_message_handling(rcvmsg.data)?;
+        }
+        Ok(())
+    }
+    fn h002a_confirmrequest_ack(&mut self, _data: h002a::Ack) -> Result<(), CommandError> {
+        self.result = Some(Ok(()));
+        Ok(())
+    }
+    fn h002a_confirmrequest_nak(&mut self, data: h002a::Nak) -> Result<(), CommandError> {
+        self.result = Some(Err(SecureTypeError::Request(data.error)));
+        Ok(())
+    }
+}
+
+// ----- Functions -----
+
+/// Supported Ids by this module
+pub fn supported_ids() -> Vec<HidIoCommandId> {
+    vec![
+        HidIoCommandId::ConfirmRequest,
+        HidIoCommandId::ConfirmResponse,
+    ]
+}
+
+/// Asks the user to confirm on the device, then types the string on the focused window of the
+/// seat the device is attached to
+///
+/// The string is only handed to the display server once confirmed, it never passes through the
+/// mailbox and is zeroized when dropped. timeout is the number of seconds the device waits for
+/// the user.
+pub fn type_confirmed(
+    mailbox: mailbox::Mailbox,
+    src: mailbox::Address,
+    uid: u64,
+    prompt: &str,
+    timeout: u8,
+    string: Zeroizing<String>,
+) -> Result<(), SecureTypeError> {
+    let request = NEXT_REQUEST.fetch_add(1, Ordering::SeqCst);
+    let (answer, receiver) = std::sync::mpsc::channel();
+    PENDING
+        .lock()
+        .unwrap()
+        .insert(request, Pending { uid, answer });
+
+    // Typed on the seat the confirming device is attached to
+    let seat = mailbox
+        .seat(mailbox::Address::DeviceHidio { uid })
+        .unwrap_or_else(seat::own_seat);
+
+    let result = confirm(mailbox, src, uid, request, prompt, timeout, receiver);
+    PENDING.lock().unwrap().remove(&request);
+    result?;
+
+    info!(
+        "Typing confirmed string (request {} from {} on {})",
+        request, uid, seat
+    );
+    displayserver::type_sensitive(&seat, string).map_err(|e| SecureTypeError::Output(e.to_string()))
+}
+
+fn confirm(
+    mailbox: mailbox::Mailbox,
+    src: mailbox::Address,
+    uid: u64,
+    request: u16,
+    prompt: &str,
+    timeout: u8,
+    receiver: std::sync::mpsc::Receiver<h002b::Answer>,
+) -> Result<(), SecureTypeError> {
+    let mut string = heapless::String::new();
+    if string.push_str(prompt).is_err() {
+        return Err(SecureTypeError::PromptTooLong(prompt.len()));
+    }
+
+    let mut intf = CommandInterface {
+        src,
+        dst: mailbox::Address::DeviceHidio { uid },
+        mailbox,
+        result: None,
+    };
+
+    let sent = intf.h002a_confirmrequest(h002a::Cmd {
+        request,
+        timeout,
+        prompt: string,
+    });
+    if let Err(e) = sent {
+        return Err(SecureTypeError::Command(e));
+    }
+    match intf.result.take() {
+        Some(result) => result?,
+        None => {
+            return Err(SecureTypeError::NoResponse);
+        }
+    }
+
+    let wait = std::time::Duration::from_secs(timeout as u64) + RESPONSE_GRACE;
+    match receiver.recv_timeout(wait) {
+        Ok(h002b::Answer::Confirmed) => Ok(()),
+        Ok(h002b::Answer::Denied) => Err(SecureTypeError::Denied),
+        Ok(h002b::Answer::Timeout) | Err(_) => Err(SecureTypeError::Timeout),
+    }
+}
+
+/// Secure typing module
+/// Forwards confirmation answers from devices (h002b) to the pending requests
+pub async fn initialize(mailbox: mailbox::Mailbox) {
+    info!("Initializing securetype...");
+
+    // Setup receiver stream
+    let sender = mailbox.sender.clone();
+    let receiver = sender.clone().subscribe();
+    tokio::pin! {
+        let stream = receiver.into_stream()
+            .filter(Result::is_ok).map(Result::unwrap)
+            .take_while(|msg|
+                msg.src != mailbox::Address::DropSubscription &&
+                msg.dst != mailbox::Address::CancelAllSubscriptions
+            )
+            .filter(|msg| msg.dst == mailbox::Address::Module)
+            .filter(|msg| msg.data.id == HidIoCommandId::ConfirmResponse)
+            .filter(|msg| msg.data.ptype == HidIoPacketType::Data);
+    }
+
+    // Process filtered message stream
+    while let Some(msg) = stream.next().await {
+        let span = msg.span("module.securetype");
+        let _enter = span.enter();
+        let uid = match msg.src {
+            mailbox::Address::DeviceHidio { uid } => uid,
+            _ => {
+                continue;
+            }
+        };
+        let data = &msg.data.data;
+        let answer = match data.get(2).and_then(|a| h002b::Answer::try_from(*a).ok()) {
+            Some(answer) => answer,
+            None => {
+                warn!("Invalid confirm response from {}: {:?}", uid, data);
+                msg.send_nak(sender.clone(), vec![]);
+                continue;
+            }
+        };
+        let request = u16::from_le_bytes(data[0..2].try_into().unwrap());
+
+        // Only the device that was asked may answer
+        let pending = {
+            let mut pending = PENDING.lock().unwrap();
+            match pending.get(&request) {
+                Some(entry) if entry.uid == uid => pending.remove(&request),
+                _ => None,
+            }
+        };
+        match pending {
+            Some(pending) => {
+                debug!("Confirm response {} from {}: {:?}", request, uid, answer);
+                pending.answer.send(answer).ok();
+                msg.send_ack(sender.clone(), vec![]);
+            }
+            None => {
+                warn!("Unexpected confirm response {} from {}", request, uid);
+                msg.send_nak(sender.clone(), vec![h002b::Error::UnknownRequest as u8]);
+            }
+        }
+    }
+}
@@ -0,0 +1,228 @@
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * This file is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This file is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this file.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// ----- Crates -----
+
+use crate::mailbox;
+use heapless::consts::U0;
+use hid_io_protocol::commands::*;
+
+// ----- Consts -----
+
+/// Settings bytes per h001b/h001c packet
+const CHUNK_SIZE: usize = 256;
+
+// ----- Enumerations -----
+
+#[derive(Debug)]
+pub enum SettingsError {
+    /// Device rejected a read
+    Read { offset: u32, error: h001b::Error },
+    /// Device returned a different amount of data than requested
+    ShortRead { offset: u32, len: usize },
+    /// Device rejected a write
+    Write { offset: u32, error: h001c::Error },
+    /// Read back data does not match what was written
+    Verify { offset: u32 },
+    /// Region does not fit in the 32-bit settings address space
+    InvalidRange,
+    /// Device did not respond
+    NoResponse,
+    /// Command could not be sent
+    Command(CommandError),
+}
+
+impl std::fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SettingsError::Read { offset, error } => {
+                write!(f, "Read failed (offset {}): {:?}", offset, error)
+            }
+            SettingsError::ShortRead { offset, len } => {
+                write!(f, "Short read (offset {}): {} bytes", offset, len)
+            }
+            SettingsError::Write { offset, error } => {
+                write!(f, "Write failed (offset {}): {:?}", offset, error)
+            }
+            SettingsError::Verify { offset } => {
+                write!(f, "Verification failed (offset {})", offset)
+            }
+            SettingsError::InvalidRange => write!(f, "Invalid settings region"),
+            SettingsError::NoResponse => write!(f, "No response from device"),
+            SettingsError::Command(e) => write!(f, "Command failed: {:?}", e),
+        }
+    }
+}
+
+// ----- Structs -----
+
+struct CommandInterface {
+    src: mailbox::Address,
+    dst: mailbox::Address,
+    mailbox: mailbox::Mailbox,
+    result: Option<Result<Vec<u8>, SettingsError>>,
+    offset: u32,
+}
+
+impl Commands<mailbox::HidIoPacketBufferDataSize, U0> for CommandInterface {
+    fn tx_packetbuffer_send(
+        &mut self,
+        buf: &mut mailbox::HidIoPacketBuffer,
+    ) -> Result<(), CommandError> {
+        if let Some(rcvmsg) = self.mailbox.try_send_message(mailbox::Message {
+            src: self.src,
+            dst: self.dst,
+            data: buf.clone(),
+        })? {
+            // Handle ack/nak
+            self.rx_message_handling(rcvmsg.data)?;
+        }
+        Ok(())
+    }
+    fn h001b_settingsread_ack(
+        &mut self,
+        data: h001b::Ack<mailbox::HidIoPacketBufferDataSize>,
+    ) -> Result<(), CommandError> {
+        self.result = Some(Ok(data.data.to_vec()));
+        Ok(())
+    }
+    fn h001b_settingsread_nak(&mut self, data: h001b::Nak) -> Result<(), CommandError> {
+        self.result = Some(Err(SettingsError::Read {
+            offset: self.offset,
+            error: data.error,
+        }));
+        Ok(())
+    }
+    fn h001c_settingswrite_ack(&mut self, _data: h001c::Ack) -> Result<(), CommandError> {
+        self.result = Some(Ok(vec![]));
+        Ok(())
+    }
+    fn h001c_settingswrite_nak(&mut self, data: h001c::Nak) -> Result<(), CommandError> {
+        self.result = Some(Err(SettingsError::Write {
+            offset: self.offset,
+            error: data.error,
+        }));
+        Ok(())
+    }
+}
+
+impl CommandInterface {
+    fn new(mailbox: mailbox::Mailbox, src: mailbox::Address, dst: mailbox::Address) -> Self {
+        CommandInterface {
+            src,
+            dst,
+            mailbox,
+            result: None,
+            offset: 0,
+        }
+    }
+
+    /// Result of the last command
+    fn take_result(&mut self, sent: Result<(), CommandError>) -> Result<Vec<u8>, SettingsError> {
+        if let Err(e) = sent {
+            return Err(SettingsError::Command(e));
+        }
+        match self.result.take() {
+            Some(result) => result,
+            None => Err(SettingsError::NoResponse),
+        }
+    }
+
+    fn read_chunk(&mut self, offset: u32, len: u16) -> Result<Vec<u8>, SettingsError> {
+        self.offset = offset;
+        let sent = self.h001b_settingsread(h001b::Cmd { offset, len });
+        let data = self.take_result(sent)?;
+        if data.len() != len as usize {
+            return Err(SettingsError::ShortRead {
+                offset,
+                len: data.len(),
+            });
+        }
+        Ok(data)
+    }
+
+    fn write_chunk(&mut self, offset: u32, data: &[u8]) -> Result<(), SettingsError> {
+        self.offset = offset;
+        let sent = self.h001c_settingswrite(h001c::Cmd {
+            offset,
+            data: heapless::Vec::from_slice(data).unwrap(),
+        });
+        self.take_result(sent).map(|_| ())
+    }
+}
+
+// ----- Functions -----
+
+/// Checks that the region fits in the settings address space
+fn check_range(offset: u32, len: usize) -> Result<(), SettingsError> {
+    if (offset as u64) + (len as u64) > u32::MAX as u64 + 1 {
+        return Err(SettingsError::InvalidRange);
+    }
+    Ok(())
+}
+
+/// Read a region of the device settings storage
+pub fn read(
+    mailbox: mailbox::Mailbox,
+    src: mailbox::Address,
+    dst: mailbox::Address,
+    offset: u32,
+    len: usize,
+) -> Result<Vec<u8>, SettingsError> {
+    check_range(offset, len)?;
+    let mut intf = CommandInterface::new(mailbox, src, dst);
+
+    let mut data = Vec::with_capacity(len);
+    while data.len() < len {
+        let chunk_len = std::cmp::min(CHUNK_SIZE, len - data.len());
+        let chunk = intf.read_chunk(offset + data.len() as u32, chunk_len as u16)?;
+        data.extend(chunk);
+    }
+    Ok(data)
+}
+
+/// Write a region of the device settings storage
+///
+/// When verify is set, each chunk is read back after it is written and compared.
+pub fn write(
+    mailbox: mailbox::Mailbox,
+    src: mailbox::Address,
+    dst: mailbox::Address,
+    offset: u32,
+    data: &[u8],
+    verify: bool,
+) -> Result<(), SettingsError> {
+    check_range(offset, data.len())?;
+    let mut intf = CommandInterface::new(mailbox, src, dst);
+
+    info!(
+        "Writing settings offset:{} ({} bytes) to {:?}",
+        offset,
+        data.len(),
+        dst
+    );
+    for (i, chunk) in data.chunks(CHUNK_SIZE).enumerate() {
+        let chunk_offset = offset + (i * CHUNK_SIZE) as u32;
+        intf.write_chunk(chunk_offset, chunk)?;
+
+        if verify && intf.read_chunk(chunk_offset, chunk.len() as u16)? != chunk {
+            return Err(SettingsError::Verify {
+                offset: chunk_offset,
+            });
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,130 @@
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * This file is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This file is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this file.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// ----- Crates -----
+
+use crate::mailbox;
+use hid_io_protocol::{HidIoCommandId, HidIoPacketType};
+use lazy_static::lazy_static;
+use std::sync::RwLock;
+use tokio::sync::broadcast;
+
+// ----- Consts -----
+
+lazy_static! {
+    /// Vendor ids with a registered handler
+    static ref HANDLERS: RwLock<Vec<HidIoCommandId>> = RwLock::new(vec![]);
+}
+
+// ----- Enumerations -----
+
+#[derive(Debug)]
+pub enum VendorError {
+    /// Id is not in the vendor specific range (0x60..0x6F)
+    NotVendorId(HidIoCommandId),
+    /// Another handler is already registered for the id
+    AlreadyRegistered(HidIoCommandId),
+}
+
+impl std::fmt::Display for VendorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VendorError::NotVendorId(id) => write!(f, "{:?} is not a vendor id", id),
+            VendorError::AlreadyRegistered(id) => {
+                write!(f, "{:?} already has a registered handler", id)
+            }
+        }
+    }
+}
+
+// ----- Structs -----
+
+/// Mailbox subscription for a vendor specific id
+/// Receives the raw packets sent by devices, the handler replies using Message::send_ack or
+/// Message::send_nak. The id is unregistered (and Nak'd again) once dropped.
+pub struct VendorHandler {
+    id: HidIoCommandId,
+    receiver: broadcast::Receiver<mailbox::Message>,
+}
+
+impl VendorHandler {
+    pub fn id(&self) -> HidIoCommandId {
+        self.id
+    }
+
+    /// Wait for the next Data/NaData packet with the registered id from a device
+    /// Returns None once the mailbox is closed.
+    pub async fn recv(&mut self) -> Option<mailbox::Message> {
+        loop {
+            let msg = match self.receiver.recv().await {
+                Ok(msg) => msg,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("{:?} handler lagged, {} messages skipped", self.id, skipped);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => {
+                    return None;
+                }
+            };
+            if msg.data.id != self.id {
+                continue;
+            }
+            if !matches!(msg.src, mailbox::Address::DeviceHidio { .. }) {
+                continue;
+            }
+            if msg.data.ptype == HidIoPacketType::Data || msg.data.ptype == HidIoPacketType::NaData
+            {
+                return Some(msg);
+            }
+        }
+    }
+}
+
+impl Drop for VendorHandler {
+    fn drop(&mut self) {
+        HANDLERS.write().unwrap().retain(|id| *id != self.id);
+    }
+}
+
+// ----- Functions -----
+
+/// Vendor ids that currently have a registered handler
+pub fn supported_ids() -> Vec<HidIoCommandId> {
+    HANDLERS.read().unwrap().clone()
+}
+
+/// Registers a handler for a vendor specific id
+/// Packets with vendor ids that have no handler are Nak'd by the unsupported module.
+pub fn register(
+    mailbox: &mailbox::Mailbox,
+    id: HidIoCommandId,
+) -> Result<VendorHandler, VendorError> {
+    if !id.is_vendor() {
+        return Err(VendorError::NotVendorId(id));
+    }
+
+    let mut handlers = HANDLERS.write().unwrap();
+    if handlers.contains(&id) {
+        return Err(VendorError::AlreadyRegistered(id));
+    }
+    handlers.push(id);
+
+    info!("Registered vendor handler for {:?}", id);
+    Ok(VendorHandler {
+        id,
+        receiver: mailbox.sender.subscribe(),
+    })
+}
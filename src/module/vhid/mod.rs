@@ -18,12 +18,15 @@
 pub mod uhid;
 
 use crate::mailbox;
+use hid_io_protocol::HidIoCommandId;
 
 /// USB VID:PID pairs for Virtual HID Devices
 /// These are assigned by Input Club (as these are Input Club HID descriptors)
 pub const IC_VID: u16 = 0x308F;
 pub const IC_PID_KEYBOARD: u16 = 0x0030;
 pub const IC_PID_MOUSE: u16 = 0x0031;
+/// uhid loopback device (simulated HID-IO firmware, see uhid::loopback)
+pub const IC_PID_LOOPBACK: u16 = 0x0032;
 
 /// Standard 6KRO HID keyboard descriptor
 /// Mirrors one used on Input Club keyboards
@@ -378,6 +381,17 @@ pub const RAWIO: [u8; 28] = [
     0xC0, //             End Collection
 ];
 
+/// Supported Ids by this module
+#[cfg(target_os = "linux")]
+pub fn supported_ids() -> Vec<HidIoCommandId> {
+    uhid::supported_ids()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn supported_ids() -> Vec<HidIoCommandId> {
+    vec![]
+}
+
 /// vhid initialization
 /// Handles setting up the vhid interface
 /// Depending on the platform, there may be support for dynamically created/configured hid devices
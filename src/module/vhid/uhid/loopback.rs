@@ -0,0 +1,302 @@
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * This file is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This file is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this file.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// ----- Crates -----
+
+use crate::built_info;
+use crate::mailbox;
+use crate::module::vhid;
+use crate::RUNNING;
+use core::ops::Sub;
+use heapless::consts::U32;
+use hid_io_protocol::commands::*;
+use hid_io_protocol::{HidIoCommandId, HidIoPacketType};
+use std::io::{Error, ErrorKind};
+use std::sync::atomic::Ordering;
+
+// ----- Consts -----
+
+/// Set to the number of loopback devices to create (e.g. HID_IO_LOOPBACK=1)
+pub const LOOPBACK_ENV: &str = "HID_IO_LOOPBACK";
+
+/// Input/Output report size of the RAWIO descriptor
+const REPORT_LEN: usize = 64;
+
+/// Commands handled by the simulated firmware
+const SUPPORTED_IDS: &[HidIoCommandId] = &[
+    HidIoCommandId::SupportedIds,
+    HidIoCommandId::GetInfo,
+    HidIoCommandId::TestPacket,
+    HidIoCommandId::ResetHidIo,
+    HidIoCommandId::Capabilities,
+    HidIoCommandId::TerminalCmd,
+];
+
+// ----- Structs -----
+
+/// uhid HID-IO loopback device
+///
+/// Creates a virtual RawHID interface (usage page 0xFF1C, usage 0x1100) that is picked up by
+/// device/hidapi like any other HID-IO keyboard. Packets written by hid-io-core are handled by a
+/// small simulated firmware, so the full hidapi -> mailbox -> api path can be exercised without
+/// hardware.
+/// To create multiple unique devices, make sure to set uniq to a unique value.
+pub struct Loopback {
+    params: uhid_virt::CreateParams,
+    device: uhid_virt::UHIDDevice<std::fs::File>,
+    received: mailbox::HidIoPacketBuffer,
+    /// Terminal output queued while handling a command, sent after the Ack
+    terminal: Vec<String>,
+}
+
+impl Loopback {
+    pub fn new(name: String, uniq: String) -> std::io::Result<Loopback> {
+        // Setup creation parameters
+        let params = uhid_virt::CreateParams {
+            name,
+            phys: "".to_string(),
+            uniq,
+            bus: uhid_virt::Bus::USB,
+            vendor: vhid::IC_VID as u32,
+            product: vhid::IC_PID_LOOPBACK as u32,
+            version: 0,
+            country: 0,
+            rd_data: vhid::RAWIO.to_vec(),
+        };
+
+        // Initialize uhid device
+        let device = uhid_virt::UHIDDevice::create(params.clone())?;
+
+        let mut received = mailbox::HidIoPacketBuffer::new();
+        received.max_len = REPORT_LEN as u32;
+        Ok(Loopback {
+            params,
+            device,
+            received,
+            terminal: vec![],
+        })
+    }
+
+    /// Process a single event
+    /// This command will block, so make sure to call it in a separate thread
+    pub fn process(&mut self) -> Result<(), Error> {
+        // Blocks until an event is received
+        let output_event = self.device.read();
+
+        if let Ok(uhid_virt::OutputEvent::Output { data }) = &output_event {
+            // Unnumbered reports are prefixed with report number 0
+            let chunk = if data.len() > REPORT_LEN {
+                &data[1..]
+            } else {
+                &data[..]
+            };
+            self.handle_chunk(chunk);
+            return Ok(());
+        }
+
+        // Default event handler
+        super::default_output_event(output_event, self.params.clone())
+    }
+
+    /// Reassembles a packet then handles it once complete
+    fn handle_chunk(&mut self, chunk: &[u8]) {
+        if let Err(e) = self.received.decode_packet(chunk) {
+            warn!("Loopback({}) decode error {:?}", self.params.uniq, e);
+            self.received.clear();
+            return;
+        }
+        if !self.received.done {
+            return;
+        }
+
+        let buffer = std::mem::replace(&mut self.received, mailbox::HidIoPacketBuffer::new());
+        self.received.max_len = REPORT_LEN as u32;
+        if buffer.ptype == HidIoPacketType::Sync {
+            debug!("Loopback({}) Sync", self.params.uniq);
+            return;
+        }
+        if let Err(e) = self.rx_message_handling(buffer) {
+            warn!("Loopback({}) {:?}", self.params.uniq, e);
+        }
+
+        // Send any terminal output generated by the command
+        for output in std::mem::take(&mut self.terminal) {
+            let cmd = h0034::Cmd {
+                output: heapless::String::from(output.as_str()),
+            };
+            if let Err(e) = self.h0034_terminalout(cmd, true) {
+                warn!("Loopback({}) terminal output {:?}", self.params.uniq, e);
+            }
+        }
+    }
+
+    fn info(&self, property: h0001::Property) -> Option<(u16, String)> {
+        use h0001::Property;
+
+        let version = |v: &str| v.parse::<u16>().unwrap_or(0);
+        Some(match property {
+            Property::MajorVersion => (version(built_info::PKG_VERSION_MAJOR), "".to_string()),
+            Property::MinorVersion => (version(built_info::PKG_VERSION_MINOR), "".to_string()),
+            Property::PatchVersion => (version(built_info::PKG_VERSION_PATCH), "".to_string()),
+            Property::DeviceName => (0, self.params.name.clone()),
+            Property::DeviceSerialNumber => (0, self.params.uniq.clone()),
+            Property::DeviceVendor => (0, "HID-IO".to_string()),
+            Property::FirmwareName => (0, "hid-io-core loopback".to_string()),
+            Property::FirmwareVersion => (0, built_info::PKG_VERSION.to_string()),
+            _ => {
+                return None;
+            }
+        })
+    }
+}
+
+impl Commands<mailbox::HidIoPacketBufferDataSize, U32> for Loopback {
+    fn tx_packetbuffer_send(
+        &mut self,
+        buf: &mut mailbox::HidIoPacketBuffer,
+    ) -> Result<(), CommandError> {
+        // Each chunk is sent as a full input report
+        let mut offset = Some(0);
+        while let Some(pos) = offset {
+            let mut chunk = [0; REPORT_LEN];
+            let (_len, next) = buf
+                .serialize_chunk(pos, &mut chunk)
+                .map_err(CommandError::SerializationFailed)?;
+            if let Err(e) = self.device.write(&chunk) {
+                warn!("Loopback({}) write failed {}", self.params.uniq, e);
+                return Err(CommandError::TxBufferSendFailed);
+            }
+            offset = next;
+        }
+        Ok(())
+    }
+
+    fn h0000_supported_ids_cmd(
+        &mut self,
+        _data: h0000::Cmd,
+    ) -> Result<h0000::Ack<U32>, h0000::Nak> {
+        Ok(h0000::Ack {
+            ids: heapless::Vec::from_slice(SUPPORTED_IDS).unwrap(),
+        })
+    }
+
+    fn h0001_info_cmd(
+        &mut self,
+        data: h0001::Cmd,
+    ) -> Result<h0001::Ack<Sub1<mailbox::HidIoPacketBufferDataSize>>, h0001::Nak>
+    where
+        <mailbox::HidIoPacketBufferDataSize as Sub<B1>>::Output: ArrayLength<u8>,
+    {
+        match self.info(data.property) {
+            Some((number, string)) => Ok(h0001::Ack {
+                property: data.property,
+                os: h0001::OsType::Unknown,
+                number,
+                string: heapless::String::from(string.as_str()),
+            }),
+            None => Err(h0001::Nak {
+                property: data.property,
+            }),
+        }
+    }
+
+    fn h0002_test_cmd(
+        &mut self,
+        data: h0002::Cmd<mailbox::HidIoPacketBufferDataSize>,
+    ) -> Result<h0002::Ack<mailbox::HidIoPacketBufferDataSize>, h0002::Nak> {
+        Ok(h0002::Ack { data: data.data })
+    }
+
+    fn h0002_test_nacmd(
+        &mut self,
+        _data: h0002::Cmd<mailbox::HidIoPacketBufferDataSize>,
+    ) -> Result<(), CommandError> {
+        Ok(())
+    }
+
+    fn h0003_resethidio_cmd(&mut self, _data: h0003::Cmd) -> Result<h0003::Ack, h0003::Nak> {
+        self.received.clear();
+        self.terminal.clear();
+        Ok(h0003::Ack {})
+    }
+
+    fn h0004_capabilities_cmd(&mut self, _data: h0004::Cmd) -> Result<h0004::Ack, h0004::Nak> {
+        // No optional protocol features
+        Ok(h0004::Ack {
+            version: h0004::VERSION,
+            capabilities: 0,
+        })
+    }
+
+    fn h0031_terminalcmd_cmd(
+        &mut self,
+        data: h0031::Cmd<mailbox::HidIoPacketBufferDataSize>,
+    ) -> Result<h0031::Ack, h0031::Nak> {
+        // Echo the command back to the terminal
+        self.terminal
+            .push(format!("loopback: {}\r\n", data.command.trim_end()));
+        Ok(h0031::Ack {})
+    }
+
+    fn h0031_terminalcmd_nacmd(
+        &mut self,
+        data: h0031::Cmd<mailbox::HidIoPacketBufferDataSize>,
+    ) -> Result<(), CommandError> {
+        self.terminal
+            .push(format!("loopback: {}\r\n", data.command.trim_end()));
+        Ok(())
+    }
+}
+
+// ----- Functions -----
+
+/// Number of loopback devices requested using the HID_IO_LOOPBACK environment variable
+pub fn requested() -> usize {
+    match std::env::var(LOOPBACK_ENV) {
+        Ok(count) => count.parse().unwrap_or(1),
+        Err(_) => 0,
+    }
+}
+
+/// Creates a loopback device and runs its firmware until hid-io-core exits
+pub fn run(index: usize) -> Result<(), Error> {
+    let mut device = Loopback::new(
+        format!("HID-IO Loopback {}", index),
+        format!("hid-io-loopback-{}", index),
+    )?;
+    info!("Created uhid loopback device {}", index);
+
+    while RUNNING.load(Ordering::SeqCst) {
+        device.process()?;
+    }
+    Ok(())
+}
+
+/// Starts the requested loopback devices
+pub fn initialize(mailbox: mailbox::Mailbox) {
+    for index in 0..requested() {
+        mailbox.rt.spawn_blocking(move || {
+            if let Err(e) = run(index) {
+                match e.kind() {
+                    ErrorKind::PermissionDenied => {
+                        error!("Loopback device {} needs write access to /dev/uhid", index)
+                    }
+                    _ => error!("Loopback device {} failed: {}", index, e),
+                }
+            }
+        });
+    }
+}
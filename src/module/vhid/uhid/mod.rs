@@ -20,10 +20,14 @@ use crate::api::Endpoint;
 use crate::api::UhidInfo;
 use crate::mailbox;
 use crate::module::vhid;
-use hid_io_protocol::HidIoCommandId;
+use hid_io_protocol::{HidIoCommandId, HidIoPacketType};
 use libc::{c_int, c_short, c_ulong, c_void};
 use std::io::{Error, ErrorKind};
 use std::os::unix::io::AsRawFd;
+use tokio::stream::StreamExt;
+
+/// Virtual HID-IO device driven by a simulated firmware
+pub mod loopback;
 
 /// Default OutputEvent handler
 /// Prints useful debug information when even when the events aren't normally used
@@ -482,15 +486,22 @@ impl Drop for Xbox360Controller {
     }
 }
 
+*/
+
+/// uhid System Control + Consumer Control device
+/// Both controls are 1KRO and share a single report, the last sent state of each is kept
 pub struct SysCtrlConsControl {
     mailbox: mailbox::Mailbox,
     uid: u64,
-    endpoint: Endpoint,
+    _endpoint: Endpoint,
     params: uhid_virt::CreateParams,
     device: uhid_virt::UHIDDevice<std::fs::File>,
+    consumer: u16,
+    system: u8,
 }
 
 impl SysCtrlConsControl {
+    #![allow(clippy::too_many_arguments)]
     pub fn new(
         mailbox: mailbox::Mailbox,
         name: String,
@@ -530,7 +541,47 @@ impl SysCtrlConsControl {
         // Register node
         mailbox.clone().register_node(endpoint.clone());
 
-        Ok(SysCtrlConsControl { mailbox, uid, endpoint, params, device })
+        Ok(SysCtrlConsControl {
+            mailbox,
+            uid,
+            _endpoint: endpoint,
+            params,
+            device,
+            consumer: 0,
+            system: 0,
+        })
+    }
+
+    /// Sets the pressed consumer control usage (0 releases)
+    pub fn send_consumer(&mut self, usage: u16) -> Result<(), Error> {
+        self.consumer = usage;
+        self.send()
+    }
+
+    /// Sets the pressed system control usage (0 releases)
+    /// Usages outside of the descriptor range (0x81-0xB7) are treated as a release.
+    pub fn send_system(&mut self, usage: u8) -> Result<(), Error> {
+        self.system = match usage {
+            0x81..=0xB7 => usage - 0x80,
+            _ => 0,
+        };
+        self.send()
+    }
+
+    /// Sends the current control state
+    fn send(&mut self) -> Result<(), Error> {
+        // 3 byte message
+        // Byte 0-1: Consumer Control usage
+        // Byte 2: System Control usage (offset by 0x80)
+        let mut data = self.consumer.to_le_bytes().to_vec();
+        data.push(self.system);
+        debug!("SysCtrlConsCtrl: {:?}", data);
+
+        // Write message
+        match self.device.write(&data) {
+            Ok(_) => Ok(()),
+            Err(msg) => Err(msg),
+        }
     }
 
     /// Process a single event
@@ -539,23 +590,6 @@ impl SysCtrlConsControl {
         // Blocks until an event is received
         let output_event = self.device.read();
 
-        // Handle LED events
-        if let Ok(event) = &output_event {
-            match event {
-                uhid_virt::OutputEvent::Output { data } => {
-                    // NOTE: data is not processed and is sent as a bitfield
-                    // Send message containing LED events
-                    self.mailbox.send_command(
-                        mailbox::Address::DeviceHid { uid: self.uid },
-                        mailbox::Address::All,
-                        HidIoCommandId::HIDKeyboardLED,
-                        data.to_vec(),
-                    );
-                }
-                _ => {}
-            }
-        }
-
         // Default event handler
         default_output_event(output_event, self.params.clone())
     }
@@ -567,12 +601,90 @@ impl Drop for SysCtrlConsControl {
         self.mailbox.unregister_node(self.uid);
     }
 }
-*/
+
+/// Supported Ids by this module
+pub fn supported_ids() -> Vec<HidIoCommandId> {
+    vec![
+        HidIoCommandId::HidSystemCtrl,
+        HidIoCommandId::HidConsumerCtrl,
+    ]
+}
+
+/// Forwards System Control and Consumer Control events from HID-IO devices
+/// Injected through a virtual uhid device, useful when the device transport does not provide
+/// these reports itself (e.g. bridged devices).
+async fn forward_controls(mailbox: mailbox::Mailbox) {
+    // Setup receiver stream
+    let sender = mailbox.sender.clone();
+    let receiver = sender.clone().subscribe();
+    tokio::pin! {
+        let stream = receiver.into_stream()
+            .filter(Result::is_ok).map(Result::unwrap)
+            .take_while(|msg|
+                msg.src != mailbox::Address::DropSubscription &&
+                msg.dst != mailbox::Address::CancelAllSubscriptions
+            )
+            .filter(|msg| msg.dst == mailbox::Address::Module)
+            .filter(|msg| supported_ids().contains(&msg.data.id))
+            .filter(|msg| msg.data.ptype == HidIoPacketType::Data || msg.data.ptype == HidIoPacketType::NaData);
+    }
+
+    // Virtual device is only created once it's needed
+    let mut device: Option<SysCtrlConsControl> = None;
+
+    // Process filtered message stream
+    while let Some(msg) = stream.next().await {
+        let span = msg.span("module.vhid");
+        let _enter = span.enter();
+        if device.is_none() {
+            match SysCtrlConsControl::new(
+                mailbox.clone(),
+                "HID-IO System/Consumer Control".to_string(),
+                "".to_string(),
+                "hid-io-sysctrl-consctrl".to_string(),
+                uhid_virt::Bus::USB,
+                vhid::IC_VID as u32,
+                vhid::IC_PID_KEYBOARD as u32,
+                0,
+                0,
+            ) {
+                Ok(dev) => {
+                    device = Some(dev);
+                }
+                Err(e) => {
+                    error!("Could not create System/Consumer Control device: {}", e);
+                }
+            }
+        }
+
+        let data = &msg.data.data;
+        let result = match (device.as_mut(), msg.data.id) {
+            (Some(device), HidIoCommandId::HidSystemCtrl) if !data.is_empty() => {
+                device.send_system(data[0])
+            }
+            (Some(device), HidIoCommandId::HidConsumerCtrl) if data.len() >= 2 => {
+                device.send_consumer(u16::from_le_bytes([data[0], data[1]]))
+            }
+            (Some(_), _) => Err(Error::new(ErrorKind::InvalidData, "Payload too short")),
+            (None, _) => Err(Error::new(ErrorKind::NotFound, "No virtual device")),
+        };
+
+        if let Err(e) = &result {
+            warn!("Could not forward {:?} {:?}: {}", msg.data.id, data, e);
+        }
+        if msg.data.ptype == HidIoPacketType::Data {
+            match result {
+                Ok(_) => msg.send_ack(sender.clone(), vec![]),
+                Err(_) => msg.send_nak(sender.clone(), vec![]),
+            }
+        }
+    }
+}
 
 /// uhid initialization
 ///
 /// Sets up processing threads for uhid
-pub async fn initialize(_mailbox: mailbox::Mailbox) {
+pub async fn initialize(mailbox: mailbox::Mailbox) {
     info!("Initializing vhid/uhid...");
 
     // Spawn watcher thread (tokio)
@@ -581,6 +693,11 @@ pub async fn initialize(_mailbox: mailbox::Mailbox) {
     //        * Destroy hid device by uid
     //        * Lookup hid device information using uid
     // TODO - Can this functionality be moved up to vhid instead of uhid?
+
+    // Loopback devices for testing without hardware (HID_IO_LOOPBACK)
+    loopback::initialize(mailbox.clone());
+
+    forward_controls(mailbox).await;
 }
 
 #[allow(dead_code)]
@@ -0,0 +1,114 @@
+/* Copyright (C) 2021 by Jacob Alexander
+ *
+ * This file is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This file is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this file.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// ----- Crates -----
+
+use crate::mailbox;
+use heapless::consts::U0;
+use hid_io_protocol::commands::*;
+
+// ----- Enumerations -----
+
+#[derive(Debug)]
+pub enum WidgetError {
+    /// Device rejected the text
+    Rejected(h0029::Error),
+    /// Text does not fit in a single packet
+    TooLong(usize),
+    /// Device did not respond
+    NoResponse,
+    /// Command could not be sent
+    Command(CommandError),
+}
+
+impl std::fmt::Display for WidgetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WidgetError::Rejected(e) => write!(f, "Text rejected: {:?}", e),
+            WidgetError::TooLong(len) => write!(f, "Text too long: {} bytes", len),
+            WidgetError::NoResponse => write!(f, "No response from device"),
+            WidgetError::Command(e) => write!(f, "Command failed: {:?}", e),
+        }
+    }
+}
+
+// ----- Structs -----
+
+struct CommandInterface {
+    src: mailbox::Address,
+    dst: mailbox::Address,
+    mailbox: mailbox::Mailbox,
+    result: Option<Result<(), WidgetError>>,
+}
+
+impl Commands<mailbox::HidIoPacketBufferDataSize, U0> for CommandInterface {
+    fn tx_packetbuffer_send(
+        &mut self,
+        buf: &mut mailbox::HidIoPacketBuffer,
+    ) -> Result<(), CommandError> {
+        if let Some(rcvmsg) = self.mailbox.try_send_message(mailbox::Message {
+            src: self.src,
+            dst: self.dst,
+            data: buf.clone(),
+        })? {
+            // Handle ack/nak
+            self.rx_message_handling(rcvmsg.data)?;
+        }
+        Ok(())
+    }
+    fn h0029_displaytext_ack(&mut self, _data: h0029::Ack) -> Result<(), CommandError> {
+        self.result = Some(Ok(()));
+        Ok(())
+    }
+    fn h0029_displaytext_nak(&mut self, data: h0029::Nak) -> Result<(), CommandError> {
+        self.result = Some(Err(WidgetError::Rejected(data.error)));
+        Ok(())
+    }
+}
+
+// ----- Functions -----
+
+/// Set the text of a device display widget
+/// An empty string clears the widget.
+pub fn set_text(
+    mailbox: mailbox::Mailbox,
+    src: mailbox::Address,
+    dst: mailbox::Address,
+    widget: u16,
+    text: &str,
+) -> Result<(), WidgetError> {
+    let mut string = heapless::String::new();
+    if string.push_str(text).is_err() {
+        return Err(WidgetError::TooLong(text.len()));
+    }
+
+    let mut intf = CommandInterface {
+        src,
+        dst,
+        mailbox,
+        result: None,
+    };
+    if let Err(e) = intf.h0029_displaytext(h0029::Cmd {
+        widget,
+        text: string,
+    }) {
+        return Err(WidgetError::Command(e));
+    }
+    match intf.result.take() {
+        Some(result) => result,
+        None => Err(WidgetError::NoResponse),
+    }
+}